@@ -0,0 +1,7 @@
+fn main() {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let script = format!("{manifest_dir}/../../noli/app.ld");
+    println!("cargo:rerun-if-changed={script}");
+    println!("cargo:rustc-link-arg=-T{script}");
+    println!("cargo:rustc-link-arg=-no-pie");
+}
@@ -0,0 +1,36 @@
+//! An example of apps: greets, echoes stdin in upper case, and exercises
+//! the allocator.
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use noli::args::Args;
+use noli::println;
+use noli::sys;
+
+noli::entry_point!(main);
+
+fn main(args: Args) -> i64 {
+    let args: Vec<&str> = args.collect();
+    println!("Hello from {}!", args.first().copied().unwrap_or("hello"));
+    for (i, arg) in args.iter().enumerate().skip(1) {
+        println!("argv[{i}] = {arg}");
+    }
+    if args.iter().any(|a| *a == "--echo") {
+        let mut buf = [0u8; 256];
+        loop {
+            match sys::read(sys::STDIN, &mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    let line = String::from_utf8_lossy(&buf[..n]).to_uppercase();
+                    noli::print!("{line}");
+                }
+            }
+        }
+    }
+    0
+}
@@ -0,0 +1,181 @@
+//! Packs everything under assets/ into a single indexed blob that
+//! src/assets.rs embeds with include_bytes!, and exports the build
+//! information used by src/version.rs.
+//!
+//! Asset bundle
+//! Layout (little endian):
+//!   magic "WAB1", entry count: u32,
+//!   entries: path_len: u16, path, compression: u8, offset: u32, stored_len: u32, raw_len: u32
+//!   data (offsets are relative to here)
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::SystemTime;
+
+const ASSETS_DIR: &str = "assets";
+const MAGIC: &[u8; 4] = b"WAB1";
+const COMPRESSION_NONE: u8 = 0;
+const COMPRESSION_RLE: u8 = 1;
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for e in entries {
+        let path = e.expect("Failed to read the assets directory").path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else {
+            files.push(path);
+        }
+    }
+}
+
+/// PackBits形式: 0..=127はn+1バイトの生データ、129..=255は次の1バイトを257-n回繰り返す
+fn compress_rle(src: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < src.len() {
+        let run = src[i..]
+            .iter()
+            .take(128)
+            .take_while(|b| **b == src[i])
+            .count();
+        if run >= 2 {
+            out.push((257 - run) as u8);
+            out.push(src[i]);
+            i += run;
+            continue;
+        }
+        let start = i;
+        while i < src.len() && i - start < 128 && !(i + 1 < src.len() && src[i] == src[i + 1]) {
+            i += 1;
+        }
+        out.push((i - start - 1) as u8);
+        out.extend_from_slice(&src[start..i]);
+    }
+    out
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let out = Command::new(program).args(args).output().ok()?;
+    if !out.status.success() {
+        return None;
+    }
+    Some(String::from_utf8(out.stdout).ok()?.trim().to_string())
+}
+
+/// Converts days since 1970-01-01 to (year, month, day).
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // http://howardhinnant.github.io/date_algorithms.html の civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+fn export_build_info() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    let git_hash = command_output("git", &["rev-parse", "--short=12", "HEAD"])
+        .map(|hash| {
+            let dirty = command_output("git", &["status", "--porcelain", "--untracked-files=no"])
+                .is_some_and(|s| !s.is_empty());
+            if dirty {
+                hash + "-dirty"
+            } else {
+                hash
+            }
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    // 再現可能ビルドのため、SOURCE_DATE_EPOCHがあればそちらを使う
+    let epoch = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse::<i64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0)
+        });
+    let (y, m, d) = civil_from_days(epoch.div_euclid(86400));
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version =
+        command_output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_string());
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(k, _)| {
+            k.strip_prefix("CARGO_FEATURE_")
+                .map(|f| f.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=WASABI_GIT_HASH={git_hash}");
+    println!("cargo:rustc-env=WASABI_BUILD_DATE={y:04}-{m:02}-{d:02}");
+    println!("cargo:rustc-env=WASABI_RUSTC_VERSION={rustc_version}");
+    println!("cargo:rustc-env=WASABI_FEATURES={}", features.join(","));
+    println!(
+        "cargo:rustc-env=WASABI_PROFILE={}",
+        env::var("PROFILE").unwrap_or_default()
+    );
+}
+
+fn main() {
+    export_build_info();
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("none") {
+        println!("cargo:rerun-if-changed=linker/limine.ld");
+        println!("cargo:rustc-link-arg-bin=wasabi-limine=-Tlinker/limine.ld");
+        println!("cargo:rustc-link-arg-bin=wasabi-limine=-no-pie");
+        println!("cargo:rerun-if-changed=linker/kernel.ld");
+        println!("cargo:rustc-link-arg-bin=wasabi-kernel=-Tlinker/kernel.ld");
+        println!("cargo:rustc-link-arg-bin=wasabi-kernel=-no-pie");
+    }
+    println!("cargo:rerun-if-changed={ASSETS_DIR}");
+    let mut files = Vec::new();
+    collect_files(Path::new(ASSETS_DIR), &mut files);
+    files.sort();
+
+    let mut index = Vec::new();
+    let mut data = Vec::new();
+    for path in &files {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let raw = fs::read(path).expect("Failed to read an asset");
+        let name = path
+            .strip_prefix(ASSETS_DIR)
+            .unwrap()
+            .to_str()
+            .expect("Asset paths must be UTF-8")
+            .replace('\\', "/");
+        let rle = compress_rle(&raw);
+        let (compression, stored) = if rle.len() < raw.len() {
+            (COMPRESSION_RLE, rle)
+        } else {
+            (COMPRESSION_NONE, raw.clone())
+        };
+        index.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        index.extend_from_slice(name.as_bytes());
+        index.push(compression);
+        index.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        index.extend_from_slice(&(stored.len() as u32).to_le_bytes());
+        index.extend_from_slice(&(raw.len() as u32).to_le_bytes());
+        data.extend_from_slice(&stored);
+    }
+
+    let mut bundle = Vec::new();
+    bundle.extend_from_slice(MAGIC);
+    bundle.extend_from_slice(&(files.len() as u32).to_le_bytes());
+    bundle.extend_from_slice(&index);
+    bundle.extend_from_slice(&data);
+    let out = PathBuf::from(env::var("OUT_DIR").unwrap()).join("assets.bin");
+    fs::write(out, bundle).expect("Failed to write the asset bundle");
+}
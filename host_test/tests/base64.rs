@@ -0,0 +1,18 @@
+use wasabi::base64;
+
+#[test]
+fn rfc4648_test_vectors() {
+    let cases = [
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ];
+    for (data, expected) in cases {
+        assert_eq!(base64::encode(data.as_bytes()), expected);
+    }
+    assert_eq!(base64::encode(&[0xfb, 0xff]), "+/8=");
+}
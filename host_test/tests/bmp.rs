@@ -0,0 +1,88 @@
+use wasabi::bmp;
+use wasabi::graphics::OwnedBitmap;
+use wasabi::screenshot;
+
+/// A BMP file with a BITMAPINFOHEADER, of the rows as they are stored.
+fn bmp_file(width: i32, height: i32, bpp: u16, rows: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(b"BM");
+    data.extend_from_slice(&(54 + rows.len() as u32).to_le_bytes());
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(&54u32.to_le_bytes());
+    data.extend_from_slice(&40u32.to_le_bytes());
+    data.extend_from_slice(&width.to_le_bytes());
+    data.extend_from_slice(&height.to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&bpp.to_le_bytes());
+    data.extend_from_slice(&[0; 24]);
+    data.extend_from_slice(rows);
+    data
+}
+
+#[test]
+fn bottom_up_24_bits_with_padding() {
+    // 幅3の24ビットは1行9バイトで、4バイト境界まで3バイト詰める
+    #[rustfmt::skip]
+    let rows = [
+        // 下の行: 青、緑、赤
+        0xff, 0x00, 0x00, 0x00, 0xff, 0x00, 0x00, 0x00, 0xff, 0, 0, 0,
+        // 上の行: 白、黒、灰
+        0xff, 0xff, 0xff, 0x00, 0x00, 0x00, 0x80, 0x80, 0x80, 0, 0, 0,
+    ];
+    let bitmap = bmp::decode(&bmp_file(3, 2, 24, &rows)).unwrap();
+    assert_eq!(bitmap.rect().w, 3);
+    assert_eq!(bitmap.rect().h, 2);
+    assert_eq!(bitmap.pixel(0, 0), Some(0xffffff));
+    assert_eq!(bitmap.pixel(2, 0), Some(0x808080));
+    assert_eq!(bitmap.pixel(0, 1), Some(0x0000ff));
+    assert_eq!(bitmap.pixel(1, 1), Some(0x00ff00));
+    assert_eq!(bitmap.pixel(2, 1), Some(0xff0000));
+}
+
+#[test]
+fn top_down_32_bits() {
+    let rows = [0x01, 0x02, 0x03, 0xff, 0x04, 0x05, 0x06, 0xff];
+    let bitmap = bmp::decode(&bmp_file(1, -2, 32, &rows)).unwrap();
+    assert_eq!(bitmap.pixel(0, 0), Some(0x030201));
+    assert_eq!(bitmap.pixel(0, 1), Some(0x060504));
+}
+
+#[test]
+fn broken_files_are_rejected() {
+    let rows = [0; 8];
+    assert!(bmp::decode(b"").is_err());
+    assert!(bmp::decode(&bmp_file(1, 1, 32, &rows)[..40]).is_err());
+    let mut bad_signature = bmp_file(1, 1, 32, &rows);
+    bad_signature[0] = b'X';
+    assert!(bmp::decode(&bad_signature).is_err());
+    assert!(bmp::decode(&bmp_file(1, 1, 8, &rows)).is_err());
+    assert!(bmp::decode(&bmp_file(0, 1, 32, &rows)).is_err());
+    assert!(bmp::decode(&bmp_file(1, 3, 32, &rows)).is_err());
+    assert!(bmp::decode(&bmp_file(100_000, 100_000, 32, &rows)).is_err());
+}
+
+#[test]
+fn encoded_images_decode_to_the_same_pixels() {
+    let pixels: Vec<u32> = (0..15).map(|i| i * 0x010203).collect();
+    let bitmap = OwnedBitmap::from_pixels(5, 3, &pixels).unwrap();
+    let data = bmp::encode(&bitmap);
+    // 5 * 3バイトを4バイト境界に揃えて16バイト * 3行
+    assert_eq!(data.len(), 54 + 16 * 3);
+    assert_eq!(u32::from_le_bytes(data[2..6].try_into().unwrap()), 54 + 48);
+    let decoded = bmp::decode(&data).unwrap();
+    assert_eq!(decoded.pixels(), bitmap.pixels());
+    assert!(OwnedBitmap::from_pixels(5, 2, &pixels).is_err());
+}
+
+#[test]
+fn screenshots_are_dumped_in_base64_lines() {
+    let mut out = String::new();
+    screenshot::dump(&mut out, "a.bmp", &[0; 100]);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.first(), Some(&"-----BEGIN SCREENSHOT a.bmp-----"));
+    assert_eq!(lines.last(), Some(&"-----END SCREENSHOT-----"));
+    // 100バイトは136文字で、76文字ずつに折り返す
+    assert_eq!(lines[1].len(), 76);
+    assert_eq!(lines[2].len(), 60);
+    assert_eq!(lines.len(), 4);
+}
@@ -0,0 +1,76 @@
+use std::mem::size_of;
+use wasabi::boot_info::BootInfo;
+use wasabi::boot_info::Framebuffer;
+use wasabi::boot_info::MemoryKind;
+use wasabi::boot_info::MemoryRegion;
+use wasabi::boot_info::BOOT_INFO_VERSION;
+use wasabi::uefi::EfiMemoryDescriptor;
+use wasabi::uefi::EfiMemoryType;
+
+#[test]
+fn the_layout_is_fixed() {
+    // ローダとカーネルで別々にビルドしても読めるように大きさを固定する
+    assert_eq!(size_of::<Framebuffer>(), 24);
+    assert_eq!(size_of::<MemoryRegion>(), 24);
+    assert_eq!(size_of::<BootInfo>(), 96);
+}
+
+#[test]
+fn efi_memory_types_are_mapped_to_kinds() {
+    let region = MemoryRegion::from_efi(&EfiMemoryDescriptor {
+        memory_type: EfiMemoryType::CONVENTIONAL_MEMORY,
+        physical_start: 0x10_0000,
+        virtual_start: 0,
+        number_of_pages: 16,
+        attribute: 0,
+    });
+    assert_eq!(region, MemoryRegion::new(0x10_0000, 16, MemoryKind::Usable));
+    assert_eq!(region.size(), 0x1_0000);
+    let kind = MemoryKind::from_efi;
+    assert_eq!(kind(EfiMemoryType::LOADER_DATA), MemoryKind::Kernel);
+    assert_eq!(
+        kind(EfiMemoryType::BOOT_SERVICES_DATA),
+        MemoryKind::BootServices
+    );
+    assert_eq!(kind(EfiMemoryType::ACPI_MEMORY_NVS), MemoryKind::Acpi);
+    assert_eq!(kind(EfiMemoryType::MEMORY_MAPPED_IO), MemoryKind::Reserved);
+}
+
+#[test]
+fn everything_is_passed_along() {
+    let regions = [
+        MemoryRegion::new(0, 1, MemoryKind::Reserved),
+        MemoryRegion::new(0x1000, 255, MemoryKind::Usable),
+    ];
+    let initramfs = [1u8, 2, 3];
+    let fb = Framebuffer::new(0x8000_0000, 800, 600, 800);
+    let info = BootInfo::new(
+        fb,
+        &regions,
+        Some(0xe0000),
+        "loglevel=debug",
+        Some(&initramfs),
+    );
+    assert_eq!(info.check(), Ok(()));
+    assert_eq!(info.memory_map(), &regions);
+    assert_eq!(info.framebuffer.width, 800);
+    assert_eq!(info.rsdp(), Some(0xe0000 as *const u8));
+    assert_eq!(info.cmdline(), "loglevel=debug");
+    assert_eq!(info.initramfs(), Some(&initramfs[..]));
+
+    let info = BootInfo::new(fb, &regions, None, "", None);
+    assert_eq!(info.rsdp(), None);
+    assert_eq!(info.cmdline(), "");
+    assert_eq!(info.initramfs(), None);
+}
+
+#[test]
+fn older_versions_are_rejected() {
+    let mut info = BootInfo::new(Framebuffer::default(), &[], None, "", None);
+    assert_eq!(info.version, BOOT_INFO_VERSION);
+    info.size -= 8;
+    assert!(info.check().is_err());
+    info.size += 8;
+    info.version = 0;
+    assert!(info.check().is_err());
+}
@@ -0,0 +1,31 @@
+use wasabi::boot_menu::Action;
+use wasabi::boot_menu::BootMenu;
+use wasabi::keymap::Key;
+use wasabi::keymap::NamedKey;
+
+#[test]
+fn keys_select_and_change_options() {
+    let mut menu = BootMenu::new(&[(800, 600), (1024, 768), (800, 600)]);
+    let resolution = |menu: &BootMenu| menu.items()[0].value().to_string();
+    assert_eq!(menu.items()[0].key(), "resolution");
+    assert_eq!(resolution(&menu), "auto");
+    assert_eq!(
+        menu.handle_key(Key::Named(NamedKey::Right)),
+        Action::Continue
+    );
+    assert_eq!(resolution(&menu), "800x600");
+    menu.handle_key(Key::Named(NamedKey::Left));
+    menu.handle_key(Key::Named(NamedKey::Left));
+    // 重複したモードは1つにまとまり、端で折り返す
+    assert_eq!(resolution(&menu), "1024x768");
+    assert_eq!(menu.changes(), [("resolution", "1024x768")]);
+
+    menu.handle_key(Key::Named(NamedKey::Up));
+    assert_eq!(menu.cursor(), 0);
+    for _ in 0..menu.items().len() + 1 {
+        menu.handle_key(Key::Named(NamedKey::Down));
+    }
+    assert_eq!(menu.cursor(), menu.items().len() - 1);
+    assert_eq!(menu.handle_key(Key::Char('\n')), Action::SaveAndBoot);
+    assert_eq!(menu.handle_key(Key::Char('\x1b')), Action::Boot);
+}
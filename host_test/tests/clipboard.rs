@@ -0,0 +1,53 @@
+use wasabi::clipboard;
+use wasabi::keymap::Key;
+use wasabi::keymap::Keystroke;
+use wasabi::keymap::Modifiers;
+use wasabi::keymap::NamedKey;
+use wasabi::widget::Event;
+use wasabi::widget::TextBox;
+use wasabi::widget::Widget;
+
+fn press(text_box: &mut TextBox, key: Key) {
+    let ctrl = matches!(key, Key::Char(c) if c.is_control());
+    let modifiers = Modifiers {
+        ctrl,
+        ..Default::default()
+    };
+    assert!(text_box.handle_event(&Event::Key(Keystroke { key, modifiers })));
+}
+
+fn type_str(text_box: &mut TextBox, s: &str) {
+    for c in s.chars() {
+        press(text_box, Key::Char(c));
+    }
+}
+
+// クリップボードは1つしかないので、並行に走らないよう1つのテストにまとめる
+#[test]
+fn text_moves_between_text_boxes_through_the_clipboard() {
+    assert!(clipboard::copy(&"x".repeat(clipboard::MAX_LEN + 1)).is_err());
+    clipboard::copy("abc").unwrap();
+    assert_eq!(clipboard::paste(), "abc");
+
+    let mut from = TextBox::new(10);
+    type_str(&mut from, "hello");
+    press(&mut from, Key::Char('\x03'));
+    assert_eq!(clipboard::paste(), "hello");
+    assert_eq!(*from.text().borrow(), "hello");
+    press(&mut from, Key::Char('\x18'));
+    assert_eq!(*from.text().borrow(), "");
+    assert_eq!(from.cursor(), 0);
+
+    let mut to = TextBox::new(10);
+    type_str(&mut to, "<>");
+    press(&mut to, Key::Named(NamedKey::Left));
+    press(&mut to, Key::Char('\x16'));
+    assert_eq!(*to.text().borrow(), "<hello>");
+    assert_eq!(to.cursor(), 6);
+
+    // 改行などの制御文字は貼り付けない
+    clipboard::copy("a\nb\tc").unwrap();
+    press(&mut to, Key::Named(NamedKey::End));
+    press(&mut to, Key::Char('\x16'));
+    assert_eq!(*to.text().borrow(), "<hello>abc");
+}
@@ -0,0 +1,28 @@
+use wasabi::cmdline::Cmdline;
+
+#[test]
+fn options_and_flags_are_parsed() {
+    let c = Cmdline::parse("video=1024x768 loglevel=debug quiet  serial=on");
+    assert_eq!(c.get("video"), Some("1024x768"));
+    assert_eq!(c.get("loglevel"), Some("debug"));
+    assert_eq!(c.get("quiet"), None);
+    assert!(c.flag("quiet"));
+    assert!(c.flag("serial"));
+    assert!(!c.flag("video=1024x768"));
+    assert_eq!(c.options().len(), 4);
+}
+
+#[test]
+fn the_image_path_from_the_shell_is_skipped() {
+    let c = Cmdline::parse("fs0:\\EFI\\BOOT\\BOOTX64.EFI loglevel=trace");
+    assert_eq!(c.options().len(), 1);
+    assert_eq!(c.get("loglevel"), Some("trace"));
+    assert_eq!(Cmdline::parse("").options().len(), 0);
+}
+
+#[test]
+fn the_last_value_wins() {
+    let c = Cmdline::parse("keymap=us keymap=jp empty=");
+    assert_eq!(c.get("keymap"), Some("jp"));
+    assert_eq!(c.get("empty"), Some(""));
+}
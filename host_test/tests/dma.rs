@@ -0,0 +1,27 @@
+use wasabi::dma;
+
+#[test]
+fn buffers_are_zeroed_and_aligned() {
+    let b = dma::alloc_dma(100, 64).unwrap();
+    assert_eq!(b.phys() % 64, 0);
+    assert_eq!(b.phys(), b.as_ptr() as u64);
+    assert_eq!(b.len(), 100);
+    // SAFETY: the buffer has len bytes
+    let bytes = unsafe { core::slice::from_raw_parts(b.as_ptr(), b.len()) };
+    assert!(bytes.iter().all(|b| *b == 0));
+}
+
+#[test]
+fn page_aligned_buffers_are_whole_pages() {
+    let b = dma::alloc_dma(100, 4096).unwrap();
+    assert_eq!(b.phys() % 4096, 0);
+    assert_eq!(b.len(), 4096);
+    assert_eq!(dma::alloc_dma(0, 16).unwrap().len(), 1);
+}
+
+#[test]
+fn buffers_out_of_reach_need_the_firmware() {
+    // ホストのヒープが4KiB以下にあることはなく、ファームウェアもない
+    assert!(dma::alloc_dma_below(16, 16, 0x1000).is_err());
+    assert!(dma::alloc_dma(16, 3).is_err());
+}
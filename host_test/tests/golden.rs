@@ -0,0 +1,99 @@
+//! Golden image tests: every scenario of gui_test is rendered and compared
+//! with the PPM image in goldens/.
+//!
+//! When a change is intended to alter the output, run the tests with
+//! UPDATE_GOLDENS=1 to rewrite the images, look at them, and update the
+//! CRC32 in src/gui_test.rs too. On a mismatch the actual image is written
+//! next to the test binaries (see the failure message).
+
+use std::fs;
+use std::path::PathBuf;
+use wasabi::graphics::BackBuffer;
+use wasabi::gui_test::capture;
+use wasabi::gui_test::render;
+use wasabi::gui_test::Scenario;
+use wasabi::gui_test::SCENARIOS;
+
+/// A binary PPM (P6) of the frame.
+fn to_ppm(buf: &BackBuffer, (width, height): (i64, i64)) -> Vec<u8> {
+    let mut ppm = format!("P6\n{width} {height}\n255\n").into_bytes();
+    // 画素は 0x00RRGGBB のリトルエンディアン、つまり B G R 0 の順
+    for bgrx in buf.pixels().chunks_exact(4) {
+        ppm.extend_from_slice(&[bgrx[2], bgrx[1], bgrx[0]]);
+    }
+    ppm
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("goldens")
+        .join(format!("{name}.ppm"))
+}
+
+/// Returns a description of the differences, if any.
+fn compare(scenario: &Scenario, actual: &[u8], golden: &[u8]) -> Option<String> {
+    if actual == golden {
+        return None;
+    }
+    if actual.len() != golden.len() {
+        return Some(format!(
+            "size differs: {} bytes, expected {}",
+            actual.len(),
+            golden.len()
+        ));
+    }
+    let width = scenario.size.0 as usize;
+    let header = actual.len() - width * scenario.size.1 as usize * 3;
+    let diffs: Vec<usize> = (header..actual.len())
+        .step_by(3)
+        .filter(|&i| actual[i..i + 3] != golden[i..i + 3])
+        .map(|i| (i - header) / 3)
+        .collect();
+    let first = diffs.first()?;
+    Some(format!(
+        "{} pixels differ, first at ({}, {})",
+        diffs.len(),
+        first % width,
+        first / width
+    ))
+}
+
+#[test]
+fn scenarios_match_goldens() {
+    let update = std::env::var_os("UPDATE_GOLDENS").is_some();
+    let mut failures = Vec::new();
+    for scenario in SCENARIOS {
+        let buf = render(scenario).unwrap();
+        let actual = to_ppm(&buf, scenario.size);
+        let path = golden_path(scenario.name);
+        if update {
+            fs::write(&path, &actual).unwrap();
+            continue;
+        }
+        let golden = fs::read(&path).unwrap_or_default();
+        if let Some(diff) = compare(scenario, &actual, &golden) {
+            let actual_path =
+                PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.ppm", scenario.name));
+            fs::write(&actual_path, &actual).unwrap();
+            failures.push(format!(
+                "{}: {diff} (actual image: {})",
+                scenario.name,
+                actual_path.display()
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
+
+#[test]
+fn scenarios_match_the_crc32_of_gui_test() {
+    // QEMUで実行するgui_testと同じ検査をホストでもしておく
+    for scenario in SCENARIOS {
+        assert_eq!(
+            capture(scenario),
+            Ok(scenario.golden_crc32),
+            "{}",
+            scenario.name
+        );
+    }
+}
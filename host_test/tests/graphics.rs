@@ -0,0 +1,259 @@
+use wasabi::graphics::best_scale;
+use wasabi::graphics::blit;
+use wasabi::graphics::draw_font_fg;
+use wasabi::graphics::draw_line;
+use wasabi::graphics::draw_line_styled;
+use wasabi::graphics::draw_point;
+use wasabi::graphics::draw_str_fg;
+use wasabi::graphics::fill_rect;
+use wasabi::graphics::fill_triangle;
+use wasabi::graphics::parse_font;
+use wasabi::graphics::LineStyle;
+use wasabi::graphics::OwnedBitmap;
+use wasabi::graphics::Point;
+use wasabi::graphics::Rect;
+use wasabi::graphics::ScaledBuffer;
+use wasabi::graphics::TestBitmap;
+
+const RED: u32 = 0xff0000;
+const WHITE: u32 = 0xffffff;
+
+fn glyph_points(c: char) -> Vec<(i64, i64)> {
+    let source =
+        String::from_utf8(wasabi::assets::get("fonts/font.txt").unwrap().into_owned()).unwrap();
+    let glyph = parse_font(&source, c).unwrap();
+    let mut points = Vec::new();
+    for (y, row) in glyph.iter().enumerate() {
+        for (x, pixel) in row.iter().enumerate() {
+            if *pixel == '*' {
+                points.push((x as i64, y as i64));
+            }
+        }
+    }
+    points.sort_by_key(|&(x, y)| (y, x));
+    points
+}
+
+fn shifted(points: &[(i64, i64)], dx: i64, dy: i64) -> Vec<(i64, i64)> {
+    points.iter().map(|&(x, y)| (x + dx, y + dy)).collect()
+}
+
+#[test]
+fn fill_rect_fills_exactly_the_rect() {
+    let mut buf = TestBitmap::new(8, 6);
+    fill_rect(&mut buf, RED, 2, 1, 3, 2).unwrap();
+    assert_eq!(
+        buf.points_of(RED),
+        vec![(2, 1), (3, 1), (4, 1), (2, 2), (3, 2), (4, 2)]
+    );
+}
+
+#[test]
+fn fill_rect_accepts_the_whole_bitmap() {
+    let mut buf = TestBitmap::new(8, 6);
+    fill_rect(&mut buf, RED, 0, 0, 8, 6).unwrap();
+    assert_eq!(buf.points_of(RED).len(), 8 * 6);
+}
+
+#[test]
+fn fill_rect_rejects_rects_crossing_the_edges() {
+    let mut buf = TestBitmap::new(8, 6);
+    assert!(fill_rect(&mut buf, RED, 1, 0, 8, 1).is_err());
+    assert!(fill_rect(&mut buf, RED, 0, 1, 1, 6).is_err());
+    assert!(fill_rect(&mut buf, RED, -1, 0, 2, 2).is_err());
+    assert!(fill_rect(&mut buf, RED, 0, -1, 2, 2).is_err());
+    assert!(buf.points_of(RED).is_empty());
+}
+
+#[test]
+fn fill_rect_does_not_touch_the_padding() {
+    let mut buf = TestBitmap::with_stride(4, 3, 6);
+    fill_rect(&mut buf, RED, 0, 0, 4, 3).unwrap();
+    assert!(buf.points_of(RED).iter().all(|&(x, _)| x < 4));
+    assert!(fill_rect(&mut buf, WHITE, 0, 0, 5, 1).is_err());
+}
+
+#[test]
+fn draw_line_horizontal_excludes_the_end_point() {
+    let mut buf = TestBitmap::new(8, 4);
+    draw_line(&mut buf, RED, 1, 2, 5, 2).unwrap();
+    assert_eq!(buf.points_of(RED), vec![(1, 2), (2, 2), (3, 2), (4, 2)]);
+}
+
+#[test]
+fn draw_line_vertical_and_reversed() {
+    let mut buf = TestBitmap::new(4, 8);
+    draw_line(&mut buf, RED, 1, 6, 1, 3).unwrap();
+    assert_eq!(buf.points_of(RED), vec![(1, 4), (1, 5), (1, 6)]);
+}
+
+#[test]
+fn draw_line_diagonal() {
+    let mut buf = TestBitmap::new(8, 8);
+    draw_line(&mut buf, RED, 0, 0, 4, 4).unwrap();
+    assert_eq!(buf.points_of(RED), vec![(0, 0), (1, 1), (2, 2), (3, 3)]);
+}
+
+#[test]
+fn draw_line_shallow_slope_steps_once() {
+    let mut buf = TestBitmap::new(8, 4);
+    draw_line(&mut buf, RED, 0, 0, 6, 1).unwrap();
+    let points = buf.points_of(RED);
+    assert_eq!(points.len(), 6);
+    // 各列に1点ずつで、yは0から1へ一度だけ増える
+    let mut xs: Vec<i64> = points.iter().map(|p| p.0).collect();
+    xs.sort();
+    assert_eq!(xs, vec![0, 1, 2, 3, 4, 5]);
+    assert!(points.iter().all(|&(x, y)| y == i64::from(x >= 3)));
+}
+
+#[test]
+fn draw_line_rejects_end_points_outside() {
+    let mut buf = TestBitmap::new(8, 8);
+    assert!(draw_line(&mut buf, RED, 0, 0, 8, 0).is_err());
+    assert!(draw_line(&mut buf, RED, -1, 0, 3, 0).is_err());
+    assert!(draw_line(&mut buf, RED, 0, 0, 0, 8).is_err());
+    assert!(buf.points_of(RED).is_empty());
+}
+
+#[test]
+fn draw_line_styled_clips_thick_lines() {
+    let mut buf = TestBitmap::new(8, 8);
+    draw_line_styled(&mut buf, RED, 0, 0, 4, 0, LineStyle::SOLID.with_width(3)).unwrap();
+    // 幅3の線は y = -1..=1 に広がり、画面外の行は切り捨てられる
+    let points = buf.points_of(RED);
+    assert_eq!(points.len(), 8);
+    assert!(points.iter().all(|&(x, y)| x < 4 && (0..=1).contains(&y)));
+}
+
+#[test]
+fn draw_line_styled_dashes() {
+    let mut buf = TestBitmap::new(40, 1);
+    draw_line_styled(&mut buf, RED, 0, 0, 33, 0, LineStyle::DASHED).unwrap();
+    let xs: Vec<i64> = buf.points_of(RED).iter().map(|p| p.0).collect();
+    let expected: Vec<i64> = (0..33)
+        .filter(|i| LineStyle::DASHED.pattern & (1 << (i % 32)) != 0)
+        .collect();
+    assert_eq!(xs, expected);
+}
+
+#[test]
+fn draw_line_styled_rejects_zero_width() {
+    let mut buf = TestBitmap::new(8, 8);
+    let style = LineStyle::SOLID.with_width(0);
+    assert!(draw_line_styled(&mut buf, RED, 0, 0, 4, 0, style).is_err());
+}
+
+#[test]
+fn draw_font_fg_draws_the_glyph() {
+    let mut buf = TestBitmap::new(16, 20);
+    draw_font_fg(&mut buf, 3, 2, WHITE, 'A');
+    let expected = shifted(&glyph_points('A'), 3, 2);
+    assert!(!expected.is_empty());
+    assert_eq!(buf.points_of(WHITE), expected);
+}
+
+#[test]
+fn draw_font_fg_clips_at_the_edges() {
+    let glyph = glyph_points('W');
+    // 左上にはみ出す
+    let mut buf = TestBitmap::new(8, 16);
+    draw_font_fg(&mut buf, -3, -5, WHITE, 'W');
+    let expected: Vec<_> = shifted(&glyph, -3, -5)
+        .into_iter()
+        .filter(|&(x, y)| x >= 0 && y >= 0)
+        .collect();
+    assert_eq!(buf.points_of(WHITE), expected);
+    // 右端の外側 (パディング) には描かない
+    let mut buf = TestBitmap::with_stride(6, 16, 10);
+    draw_font_fg(&mut buf, 2, 0, WHITE, 'W');
+    let expected: Vec<_> = shifted(&glyph, 2, 0)
+        .into_iter()
+        .filter(|&(x, _)| x < 6)
+        .collect();
+    assert_eq!(buf.points_of(WHITE), expected);
+}
+
+#[test]
+fn draw_font_fg_ignores_missing_glyphs() {
+    let mut buf = TestBitmap::new(16, 16);
+    draw_font_fg(&mut buf, 0, 0, WHITE, 'あ');
+    assert!(buf.points_of(WHITE).is_empty());
+}
+
+#[test]
+fn draw_str_fg_advances_8_pixels() {
+    let mut buf = TestBitmap::new(24, 16);
+    draw_str_fg(&mut buf, 0, 0, WHITE, "AB");
+    let mut expected = glyph_points('A');
+    expected.extend(shifted(&glyph_points('B'), 8, 0));
+    expected.sort_by_key(|&(x, y)| (y, x));
+    assert_eq!(buf.points_of(WHITE), expected);
+}
+
+#[test]
+fn fill_triangle_clips_outside() {
+    let mut buf = TestBitmap::with_stride(8, 8, 12);
+    fill_triangle(
+        &mut buf,
+        RED,
+        Point::new(-8, -8),
+        Point::new(20, -8),
+        Point::new(-8, 20),
+    );
+    let points = buf.points_of(RED);
+    assert!(!points.is_empty());
+    assert!(points.iter().all(|&(x, y)| x < 8 && y < 8));
+    assert_eq!(buf.pixel(0, 0), RED);
+}
+
+#[test]
+fn blit_clips_to_both_bitmaps() {
+    let mut src = OwnedBitmap::new(4, 4, RED);
+    fill_rect(&mut src, WHITE, 0, 0, 1, 1).unwrap();
+    let mut dst = TestBitmap::with_stride(6, 6, 8);
+    blit(&mut src, Rect::new(0, 0, 4, 4), &mut dst, -1, 4).unwrap();
+    // (0, 0) の白は左にはみ出し、下の2行も切れる
+    let mut expected: Vec<(i64, i64)> = (4..6).flat_map(|y| (0..3).map(move |x| (x, y))).collect();
+    expected.sort_by_key(|&(x, y)| (y, x));
+    assert_eq!(dst.points_of(RED), expected);
+    assert!(dst.points_of(WHITE).is_empty());
+    // 転送元の範囲外は何も写さない
+    blit(&mut src, Rect::new(3, 3, 10, 10), &mut dst, 0, 0).unwrap();
+    assert_eq!(dst.pixel(0, 0), RED);
+    assert_eq!(dst.pixel(1, 1), 0);
+}
+
+#[test]
+fn owned_bitmap_pixels() {
+    let mut buf = OwnedBitmap::new(3, 2, RED);
+    draw_point(&mut buf, WHITE, 2, 1).unwrap();
+    assert_eq!(buf.pixel(0, 0), Some(RED));
+    assert_eq!(buf.pixel(2, 1), Some(WHITE));
+    assert_eq!(buf.pixel(3, 0), None);
+    assert_eq!(buf.pixels().len(), 3 * 2 * 4);
+}
+
+#[test]
+fn empty_virtual_screens_scale_by_one() {
+    assert_eq!(best_scale(320, 200, 1280, 800), 4);
+    assert_eq!(best_scale(0, 200, 1280, 800), 1);
+    assert_eq!(best_scale(320, 0, 1280, 800), 1);
+    let dst = TestBitmap::new(16, 16);
+    assert_eq!(ScaledBuffer::fit(0, 0, &dst).scale(), 1);
+}
+
+#[test]
+fn scaled_buffer_is_shown_in_its_area() {
+    let mut low = ScaledBuffer::new(2, 2, 3);
+    low.set_area(Rect::new(4, 8, 8, 6));
+    fill_rect(&mut low, RED, 0, 0, 2, 2).unwrap();
+    low.add_damage_all();
+    let mut dst = TestBitmap::new(16, 16);
+    low.present(&mut dst).unwrap();
+    // 8x6の領域の中央に6x6で置かれ、領域の外には描かない
+    assert_eq!(low.origin(&dst), (5, 8));
+    let expected: Vec<(i64, i64)> = (8..14).flat_map(|y| (5..11).map(move |x| (x, y))).collect();
+    assert_eq!(dst.points_of(RED), expected);
+    assert_eq!(low.to_virtual(&dst, 8, 11), (1, 1));
+}
@@ -0,0 +1,70 @@
+use core::alloc::GlobalAlloc;
+use core::alloc::Layout;
+use wasabi::allocator;
+use wasabi::allocator::Counters;
+use wasabi::allocator::SizeClassStats;
+use wasabi::allocator::ALLOCATOR;
+use wasabi::allocator::SIZE_CLASSES;
+
+#[test]
+fn sizes_fall_into_power_of_two_classes() {
+    assert_eq!(allocator::size_class(0), 0);
+    assert_eq!(allocator::size_class(16), 0);
+    assert_eq!(allocator::size_class(17), 1);
+    assert_eq!(allocator::size_class(4096), 8);
+    assert_eq!(allocator::size_class(64 * 1024), SIZE_CLASSES - 2);
+    assert_eq!(allocator::size_class(1 << 30), SIZE_CLASSES - 1);
+    assert_eq!(allocator::size_class_limit(0), Some(16));
+    assert_eq!(allocator::size_class_limit(SIZE_CLASSES - 1), None);
+}
+
+#[test]
+fn growth_since_the_mark_shows_the_leaks() {
+    let mut counters = Counters::default();
+    counters.record_alloc(10);
+    counters.record_alloc(100);
+    let mark = counters;
+    counters.record_alloc(12);
+    counters.record_alloc(5000);
+    counters.record_free(100);
+    assert_eq!(counters.live(), 3);
+    assert_eq!(counters.in_use, 10 + 12 + 5000);
+    assert_eq!(counters.peak_in_use, 10 + 100 + 12 + 5000);
+    assert_eq!(
+        counters.growth_since(&mark),
+        [
+            (0, SizeClassStats { live: 1, bytes: 12 }),
+            (
+                allocator::size_class(5000),
+                SizeClassStats {
+                    live: 1,
+                    bytes: 5000
+                }
+            ),
+        ]
+    );
+}
+
+#[test]
+fn the_allocator_counts_and_poisons() {
+    let region = Box::leak(vec![0u64; 64 * 1024].into_boxed_slice());
+    // SAFETY: the region is leaked, so nobody else uses it
+    unsafe { ALLOCATOR.add_region(region.as_mut_ptr() as usize, region.len() * 8) };
+    let before = ALLOCATOR.stats();
+    let layout = Layout::from_size_align(256, 8).unwrap();
+    // SAFETY: the layout has a non-zero size
+    let p = unsafe { ALLOCATOR.alloc(layout) };
+    assert!(!p.is_null());
+    let during = ALLOCATOR.stats();
+    assert_eq!(during.counters.allocs, before.counters.allocs + 1);
+    assert_eq!(during.counters.in_use, before.counters.in_use + 256);
+    // SAFETY: p is allocated with the layout
+    unsafe { ALLOCATOR.dealloc(p, layout) };
+    let after = ALLOCATOR.stats();
+    assert_eq!(after.counters.frees, before.counters.frees + 1);
+    assert_eq!(after.counters.live(), before.counters.live());
+    assert!(after.poison);
+    // SAFETY: the freed block is still in the region, and in the quarantine
+    assert_eq!(unsafe { *p.add(100) }, 0xdd);
+    assert!(after.fragmentation_percent() <= 100);
+}
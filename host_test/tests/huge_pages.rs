@@ -0,0 +1,51 @@
+use wasabi::paging;
+use wasabi::paging::ENTRIES;
+
+const PRESENT: u64 = 1;
+const WRITABLE: u64 = 1 << 1;
+const ACCESSED: u64 = 1 << 5;
+const DIRTY: u64 = 1 << 6;
+const HUGE: u64 = 1 << 7;
+const PAT: u64 = 1 << 7;
+const HUGE_PAT: u64 = 1 << 12;
+
+fn table(base: u64, flags: u64) -> Vec<u64> {
+    (0..ENTRIES as u64)
+        .map(|i| (base + i * 4096) | flags)
+        .collect()
+}
+
+#[test]
+fn contiguous_pages_merge_into_a_huge_page() {
+    let base = 0x4000_0000;
+    let mut entries = table(base, PRESENT | WRITABLE);
+    entries[3] |= ACCESSED;
+    entries[9] |= DIRTY;
+    assert_eq!(
+        paging::huge_entry_for(&entries),
+        Some(base | PRESENT | WRITABLE | ACCESSED | DIRTY | HUGE)
+    );
+    // 4KiBページのPATビットは大きいページでは12ビット目になる
+    let entries = table(base, PRESENT | PAT);
+    assert_eq!(
+        paging::huge_entry_for(&entries),
+        Some(base | PRESENT | HUGE | HUGE_PAT)
+    );
+}
+
+#[test]
+fn pages_that_can_not_merge_are_left_alone() {
+    let flags = PRESENT | WRITABLE;
+    assert_eq!(paging::huge_entry_for(&table(0x1000, flags)), None);
+    let mut hole = table(0x20_0000, flags);
+    hole[100] = 0;
+    assert_eq!(paging::huge_entry_for(&hole), None);
+    let mut mixed = table(0x20_0000, flags);
+    mixed[511] &= !WRITABLE;
+    assert_eq!(paging::huge_entry_for(&mixed), None);
+    let mut moved = table(0x20_0000, flags);
+    moved.swap(1, 2);
+    assert_eq!(paging::huge_entry_for(&moved), None);
+    assert_eq!(paging::huge_entry_for(&table(0x20_0000, 0)), None);
+    assert_eq!(paging::huge_entry_for(&[]), None);
+}
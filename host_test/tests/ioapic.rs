@@ -0,0 +1,33 @@
+use wasabi::apic::Gsi;
+use wasabi::apic::Polarity;
+use wasabi::apic::Trigger;
+use wasabi::ioapic;
+use wasabi::ioapic::ENTRY_MASKED;
+
+#[test]
+fn isa_irqs_are_edge_triggered_and_active_high() {
+    let gsi = Gsi {
+        gsi: 1,
+        polarity: Polarity::ActiveHigh,
+        trigger: Trigger::Edge,
+    };
+    assert_eq!(ioapic::redirection_entry(0x21, gsi, 0), 0x21 | ENTRY_MASKED);
+    assert_eq!(
+        ioapic::redirection_entry(0x21, gsi, 3),
+        0x21 | ENTRY_MASKED | 3 << 56
+    );
+}
+
+#[test]
+fn overridden_irqs_keep_their_polarity_and_trigger() {
+    // PCIの割り込みなどはオーバーライドでレベル・ローアクティブになる
+    let gsi = Gsi {
+        gsi: 11,
+        polarity: Polarity::ActiveLow,
+        trigger: Trigger::Level,
+    };
+    assert_eq!(
+        ioapic::redirection_entry(0x2b, gsi, 0),
+        0x2b | 1 << 13 | 1 << 15 | ENTRY_MASKED
+    );
+}
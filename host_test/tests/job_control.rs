@@ -0,0 +1,45 @@
+use wasabi::job::JobTable;
+use wasabi::process;
+use wasabi::process::ProcessState;
+use wasabi::process::Signal;
+
+#[test]
+fn ctrl_c_stops_the_foreground_job() {
+    let job = process::register("job");
+    let child = process::register("child");
+    process::set_group(child, job).unwrap();
+    let other = process::register("other");
+    let mut jobs = JobTable::new();
+    jobs.add(job, "run loop.elf");
+    let mut out = String::new();
+    assert_eq!(jobs.cmd_fg(None, &mut out).unwrap(), job);
+    assert_eq!(process::foreground(), Some(job));
+
+    // serial_console.rsがCtrl+Cで呼ぶのと同じ
+    assert_eq!(process::signal_foreground(Signal::Interrupt), Ok(job));
+    assert!(!process::is_runnable(job));
+    assert!(!process::is_runnable(child));
+    assert!(process::is_runnable(other));
+    // タスクの切り替えで届けられると、ジョブは終わる
+    assert_eq!(process::deliver_signals(job), Some(130));
+    assert_eq!(process::deliver_signals(child), Some(130));
+    assert_eq!(process::deliver_signals(other), None);
+    assert_eq!(process::info(job).unwrap().state, ProcessState::Exited(130));
+    assert_eq!(process::foreground(), None);
+}
+
+#[test]
+fn finished_background_jobs_are_reported() {
+    let pid = process::register("sleep");
+    let mut jobs = JobTable::new();
+    assert_eq!(jobs.add(pid, "sleep 1"), 1);
+    let mut out = String::new();
+    jobs.cmd_jobs(&mut out);
+    assert_eq!(out, format!("[1] {pid} Running\tsleep 1\n"));
+    process::send_signal(pid, Signal::Terminate).unwrap();
+    assert_eq!(process::deliver_signals(pid), Some(143));
+    out.clear();
+    jobs.cmd_jobs(&mut out);
+    assert_eq!(out, "[1] Done(143)\tsleep 1\n");
+    assert!(process::info(pid).is_none());
+}
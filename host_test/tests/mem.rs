@@ -0,0 +1,57 @@
+use wasabi::mem;
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 + 3) as u8).collect()
+}
+
+/// Sizes around the SSE chunks and the switch to the rep instructions.
+const SIZES: &[usize] = &[0, 1, 15, 16, 17, 31, 100, 255, 256, 257, 1000, 4096];
+
+#[test]
+fn copies_match_copy_from_slice() {
+    for &n in SIZES {
+        for offset in [0, 1, 7] {
+            let src = pattern(n + offset);
+            let mut dst = vec![0xaa; n + 2 * offset];
+            // SAFETY: both buffers have n bytes after offset
+            unsafe { mem::copy_forward(dst.as_mut_ptr().add(offset), src[offset..].as_ptr(), n) };
+            let mut expected = vec![0xaa; n + 2 * offset];
+            expected[offset..offset + n].copy_from_slice(&src[offset..]);
+            assert_eq!(dst, expected, "n = {n}, offset = {offset}");
+        }
+    }
+}
+
+#[test]
+fn overlapping_copies_match_copy_within() {
+    for &n in SIZES {
+        for shift in [1, 5, 16, 33] {
+            let buf = pattern(n + shift);
+            let mut up = buf.clone();
+            // SAFETY: both ranges are in the buffer
+            unsafe { mem::copy(up.as_mut_ptr().add(shift), up.as_ptr(), n) };
+            let mut expected = buf.clone();
+            expected.copy_within(0..n, shift);
+            assert_eq!(up, expected, "n = {n}, up by {shift}");
+
+            let mut down = buf.clone();
+            // SAFETY: both ranges are in the buffer
+            unsafe { mem::copy(down.as_mut_ptr(), down.as_ptr().add(shift), n) };
+            let mut expected = buf.clone();
+            expected.copy_within(shift..shift + n, 0);
+            assert_eq!(down, expected, "n = {n}, down by {shift}");
+        }
+    }
+}
+
+#[test]
+fn fills_only_the_range() {
+    for &n in SIZES {
+        let mut buf = vec![0u8; n + 2];
+        // SAFETY: the buffer has n bytes after the first
+        unsafe { mem::fill(buf.as_mut_ptr().add(1), 0x5a, n) };
+        assert_eq!(buf[0], 0);
+        assert!(buf[1..=n].iter().all(|b| *b == 0x5a), "n = {n}");
+        assert_eq!(buf[n + 1], 0);
+    }
+}
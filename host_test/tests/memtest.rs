@@ -0,0 +1,29 @@
+use wasabi::memtest;
+use wasabi::memtest::BadRegion;
+
+#[test]
+fn good_memory_has_no_bad_pages() {
+    let mut words = vec![0u64; 3 * 512];
+    let base = words.as_ptr() as u64;
+    assert!(memtest::test_words(&mut words, base).is_empty());
+    // 最後に書いたのはアドレスの補数
+    assert_eq!(words[5], !(base + 5 * 8));
+}
+
+#[test]
+fn bad_pages_merge_into_regions() {
+    assert_eq!(memtest::merge_pages(&[]), []);
+    assert_eq!(
+        memtest::merge_pages(&[0x1000, 0x2000, 0x3000, 0x8000]),
+        [
+            BadRegion {
+                start: 0x1000,
+                pages: 3
+            },
+            BadRegion {
+                start: 0x8000,
+                pages: 1
+            },
+        ]
+    );
+}
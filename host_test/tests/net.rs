@@ -0,0 +1,347 @@
+use core::time::Duration;
+use wasabi::net::arp;
+use wasabi::net::dhcp;
+use wasabi::net::dns;
+use wasabi::net::ethernet;
+use wasabi::net::ethernet::Frame;
+use wasabi::net::ethernet::MacAddr;
+use wasabi::net::http;
+use wasabi::net::icmp;
+use wasabi::net::ipv4;
+use wasabi::net::mdns;
+use wasabi::net::ntp;
+use wasabi::net::tcp;
+use wasabi::net::udp;
+use wasabi::net::IpConfig;
+use wasabi::net::Ipv4Addr;
+
+const HOST: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+const GUEST: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 15);
+const HOST_MAC: MacAddr = MacAddr::new([0x52, 0x55, 10, 0, 2, 2]);
+const GUEST_MAC: MacAddr = MacAddr::new([0x52, 0x54, 0, 0x12, 0x34, 0x56]);
+
+#[test]
+fn checksum_of_the_rfc1071_example() {
+    // RFC 1071 4.1 の例: 和は 0xddf2
+    let data = [0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7];
+    assert_eq!(ipv4::checksum(&data), !0xddf2);
+}
+
+#[test]
+fn checksum_pads_odd_lengths() {
+    assert_eq!(ipv4::checksum(&[0x12]), ipv4::checksum(&[0x12, 0x00]));
+}
+
+#[test]
+fn ethernet_round_trip() {
+    let frame = ethernet::build(HOST_MAC, GUEST_MAC, ethernet::ETHERTYPE_ARP, b"abc");
+    assert_eq!(
+        Frame::parse(&frame),
+        Some(Frame {
+            dst: HOST_MAC,
+            src: GUEST_MAC,
+            ethertype: ethernet::ETHERTYPE_ARP,
+            payload: b"abc",
+        })
+    );
+    assert_eq!(Frame::parse(&frame[..13]), None);
+    assert_eq!(GUEST_MAC.to_string(), "52:54:00:12:34:56");
+}
+
+#[test]
+fn arp_round_trip() {
+    let packet = arp::Packet {
+        op: arp::OP_REQUEST,
+        sender_mac: HOST_MAC,
+        sender_ip: HOST,
+        target_mac: MacAddr::ZERO,
+        target_ip: GUEST,
+    };
+    let bytes = packet.to_bytes();
+    assert_eq!(arp::Packet::parse(&bytes), Some(packet));
+    // Ethernetのパディングが付いていてもよい
+    let mut padded = bytes.to_vec();
+    padded.resize(46, 0);
+    assert_eq!(arp::Packet::parse(&padded), Some(packet));
+    assert_eq!(arp::Packet::parse(&bytes[..27]), None);
+}
+
+#[test]
+fn ipv4_round_trip() {
+    let packet = ipv4::build(GUEST, HOST, ipv4::PROTOCOL_UDP, 7, b"payload");
+    assert_eq!(packet.len(), ipv4::HEADER_SIZE + 7);
+    assert_eq!(ipv4::checksum(&packet[..ipv4::HEADER_SIZE]), 0);
+    let mut padded = packet.clone();
+    padded.resize(46, 0);
+    let parsed = ipv4::Packet::parse(&padded).unwrap();
+    assert_eq!(parsed.src, GUEST);
+    assert_eq!(parsed.dst, HOST);
+    assert_eq!(parsed.protocol, ipv4::PROTOCOL_UDP);
+    assert_eq!(parsed.payload, b"payload");
+}
+
+#[test]
+fn ipv4_rejects_broken_packets() {
+    let packet = ipv4::build(GUEST, HOST, ipv4::PROTOCOL_UDP, 7, b"payload");
+    let mut corrupted = packet.clone();
+    corrupted[15] ^= 1;
+    assert_eq!(ipv4::Packet::parse(&corrupted), None);
+    assert_eq!(ipv4::Packet::parse(&packet[..packet.len() - 1]), None);
+    // 断片は扱わない (More Fragments を立ててチェックサムを直す)
+    let mut fragment = packet.clone();
+    fragment[6] = 0x20;
+    fragment[10..12].copy_from_slice(&[0, 0]);
+    let sum = ipv4::checksum(&fragment[..ipv4::HEADER_SIZE]);
+    fragment[10..12].copy_from_slice(&sum.to_be_bytes());
+    assert_eq!(ipv4::Packet::parse(&fragment), None);
+}
+
+#[test]
+fn icmp_echo_request() {
+    let message = icmp::echo_request(0x1234, 5, b"ping");
+    assert_eq!(ipv4::checksum(&message), 0);
+    let parsed = icmp::Message::parse(&message).unwrap();
+    assert_eq!(parsed.icmp_type, icmp::TYPE_ECHO_REQUEST);
+    assert_eq!(parsed.echo(), (0x1234, 5));
+    assert_eq!(parsed.data, b"ping");
+    let mut corrupted = message.clone();
+    corrupted[9] ^= 1;
+    assert_eq!(icmp::Message::parse(&corrupted), None);
+}
+
+#[test]
+fn ip_config_accepts_its_address_and_broadcasts() {
+    let config = IpConfig {
+        addr: GUEST,
+        prefix_len: 24,
+        gateway: Some(HOST),
+        dns: None,
+    };
+    assert!(config.accepts(GUEST));
+    assert!(config.accepts(Ipv4Addr::BROADCAST));
+    assert!(config.accepts(Ipv4Addr::new(10, 0, 2, 255)));
+    assert!(!config.accepts(HOST));
+    assert!(!config.accepts(Ipv4Addr::new(10, 0, 3, 255)));
+    assert!(!IpConfig::UNCONFIGURED.accepts(GUEST));
+}
+
+#[test]
+fn multicast_groups_map_to_mac_addresses() {
+    let mac = MacAddr::for_multicast(mdns::MDNS_MULTICAST_ADDR);
+    assert_eq!(mac, MacAddr::new([0x01, 0x00, 0x5e, 0x00, 0x00, 0xfb]));
+    assert!(mac.is_multicast());
+    assert!(mdns::MDNS_MULTICAST_ADDR.is_multicast());
+    assert!(!GUEST.is_multicast());
+}
+
+#[test]
+fn mdns_answers_for_the_hostname() {
+    let query = dns::build_query(0, "myhost.local");
+    let response = mdns::handle_query(&query, "myhost", GUEST).unwrap();
+    // IDは0、応答1件で、末尾がAレコードのアドレス
+    assert_eq!(&response[..2], &[0, 0]);
+    assert_eq!(&response[6..8], &[0, 1]);
+    assert!(response.ends_with(&GUEST.octets()));
+    assert_eq!(mdns::handle_query(&query, "other", GUEST), None);
+}
+
+#[test]
+fn udp_round_trip() {
+    let datagram = udp::build(GUEST, 49152, HOST, 53, b"query");
+    let packet = ipv4::build(GUEST, HOST, ipv4::PROTOCOL_UDP, 1, &datagram);
+    let packet = ipv4::Packet::parse(&packet).unwrap();
+    assert_eq!(
+        udp::Header::parse(&packet),
+        Some(udp::Header {
+            src_port: 49152,
+            dst_port: 53,
+            data: b"query",
+        })
+    );
+    // 送信元が違えば疑似ヘッダのチェックサムが合わない
+    let spoofed = ipv4::Packet {
+        src: Ipv4Addr::new(10, 0, 2, 3),
+        ..packet
+    };
+    assert_eq!(udp::Header::parse(&spoofed), None);
+}
+
+#[test]
+fn udp_accepts_no_checksum() {
+    let mut datagram = udp::build(GUEST, 1, HOST, 2, b"x");
+    datagram[6..8].copy_from_slice(&[0, 0]);
+    let packet = ipv4::Packet {
+        src: GUEST,
+        dst: HOST,
+        protocol: ipv4::PROTOCOL_UDP,
+        ttl: 64,
+        payload: &datagram,
+    };
+    assert_eq!(udp::Header::parse(&packet).unwrap().data, b"x");
+}
+
+#[test]
+fn dhcp_round_trip() {
+    let message = dhcp::Message {
+        op: 2,
+        xid: 0x12345678,
+        yiaddr: GUEST,
+        chaddr: GUEST_MAC,
+        message_type: Some(dhcp::DHCPACK),
+        subnet_mask: Some(Ipv4Addr::new(255, 255, 255, 0)),
+        router: Some(HOST),
+        dns: Some(Ipv4Addr::new(10, 0, 2, 3)),
+        server_id: Some(HOST),
+        lease_secs: Some(86400),
+        hostname: Some("wasabi".into()),
+        ..Default::default()
+    };
+    let bytes = message.to_bytes();
+    assert!(bytes.len() >= 300);
+    assert_eq!(dhcp::Message::parse(&bytes), Some(message));
+}
+
+#[test]
+fn dhcp_skips_unknown_options() {
+    let mut bytes = dhcp::Message {
+        op: 2,
+        message_type: Some(dhcp::DHCPOFFER),
+        ..Default::default()
+    }
+    .to_bytes();
+    // 未知のオプション (code 250) を先頭に挟む
+    bytes.splice(240..240, [250, 2, 0xaa, 0xbb, 0]);
+    let parsed = dhcp::Message::parse(&bytes).unwrap();
+    assert_eq!(parsed.message_type, Some(dhcp::DHCPOFFER));
+    // 長さが足りないオプションは拒否する
+    assert_eq!(dhcp::Message::parse(&bytes[..243]), None);
+}
+
+#[test]
+fn tcp_round_trip() {
+    let segment = tcp::Segment {
+        src_port: 49152,
+        dst_port: 80,
+        seq: 0xfffffff0,
+        ack: 7,
+        flags: 0x12,
+        window: 65535,
+        mss: Some(1460),
+        data: b"",
+    };
+    let bytes = segment.to_bytes(GUEST, HOST);
+    assert_eq!(bytes.len(), tcp::HEADER_SIZE + 4);
+    let packet = ipv4::Packet {
+        src: GUEST,
+        dst: HOST,
+        protocol: ipv4::PROTOCOL_TCP,
+        ttl: 64,
+        payload: &bytes,
+    };
+    assert_eq!(tcp::Segment::parse(&packet), Some(segment));
+    let mut corrupted = bytes.clone();
+    corrupted[5] ^= 1;
+    let packet = ipv4::Packet {
+        payload: &corrupted,
+        ..packet
+    };
+    assert_eq!(tcp::Segment::parse(&packet), None);
+}
+
+#[test]
+fn dns_response_with_cname() {
+    let mut response = dns::build_query(0x4242, "www.example.com");
+    response[2] |= 0x80; // QR
+    response[7] = 2; // ancount
+
+    // www.example.com CNAME example.com (名前は圧縮ポインタで書く)
+    response.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 16]);
+    // example.com A 93.184.216.34
+    response.extend_from_slice(&[0xc0, 16, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 93, 184, 216, 34]);
+    assert_eq!(
+        dns::parse_response(&response, 0x4242),
+        Ok(vec![Ipv4Addr::new(93, 184, 216, 34)])
+    );
+    assert!(dns::parse_response(&response, 0x4243).is_err());
+    response[3] |= 3; // NXDOMAIN
+    assert_eq!(
+        dns::parse_response(&response, 0x4242),
+        Err("DNS: no such host")
+    );
+}
+
+#[test]
+fn http_url() {
+    let url = http::Url::parse("http://example.com:8080/a/b?q=1#top").unwrap();
+    assert_eq!(url.host, "example.com");
+    assert_eq!(url.port, 8080);
+    assert_eq!(url.path, "/a/b?q=1");
+    assert_eq!(url.to_string(), "http://example.com:8080/a/b?q=1");
+    assert_eq!(http::Url::parse("10.0.2.2").unwrap().path, "/");
+    assert!(http::Url::parse("https://example.com/").is_err());
+    assert_eq!(url.join("/c").unwrap().path, "/c");
+    assert_eq!(url.join("c").unwrap().path, "/a/c");
+    assert_eq!(url.join("http://other/").unwrap().host, "other");
+    let request = String::from_utf8(url.request()).unwrap();
+    assert!(request.starts_with("GET /a/b?q=1 HTTP/1.0\r\nHost: example.com:8080\r\n"));
+    assert!(request.ends_with("\r\n\r\n"));
+}
+
+#[test]
+fn http_response() {
+    let data =
+        b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\ncontent-length: 5\r\n\r\nhello, extra";
+    let response = http::Response::parse(data).unwrap();
+    assert_eq!(response.status, 200);
+    assert_eq!(response.reason, "OK");
+    assert_eq!(response.header("Content-Type"), Some("text/plain"));
+    assert_eq!(response.body, b"hello");
+    assert!(http::Response::parse(b"HTTP/1.0 200 OK\r\nContent-Length: 9\r\n\r\nshort").is_err());
+    assert!(http::Response::parse(b"HTTP/1.0 200 OK\r\n").is_err());
+    let redirect = http::Response::parse(b"HTTP/1.1 302 Found\r\nLocation: /x\r\n\r\n").unwrap();
+    assert!(redirect.is_redirect());
+    assert_eq!(redirect.header("location"), Some("/x"));
+}
+
+#[test]
+fn ntp_timestamps() {
+    // 2024-01-01 00:00:00 UTC と 0.5 秒
+    let t = ntp::Timestamp(((1_704_067_200 + 2_208_988_800) << 32) | 0x8000_0000);
+    assert_eq!(t.to_unix_time(), Duration::new(1_704_067_200, 500_000_000));
+    // 最上位ビットが0なら2036年以降 (era 1) とみなす
+    assert_eq!(
+        ntp::Timestamp(1 << 32).to_unix_time().as_secs(),
+        2_085_978_497
+    );
+}
+
+#[test]
+fn ntp_reply() {
+    let nonce = 0x1234_5678_9abc_def0;
+    let request = ntp::Packet::request(nonce).to_bytes();
+    assert_eq!(request.len(), ntp::PACKET_SIZE);
+    assert_eq!(request[0], 0x23);
+    let secs = (1_704_067_200 + 2_208_988_800) << 32;
+    let reply = ntp::Packet {
+        leap: 0,
+        version: 4,
+        mode: ntp::MODE_SERVER,
+        stratum: 2,
+        originate: ntp::Timestamp(nonce),
+        receive: ntp::Timestamp(secs),
+        transmit: ntp::Timestamp(secs + (1 << 32)),
+    };
+    let parsed = ntp::Packet::parse(&reply.to_bytes()).unwrap();
+    assert_eq!(parsed, reply);
+    assert!(parsed.is_reply_to(nonce));
+    assert!(!parsed.is_reply_to(nonce + 1));
+    let kiss_of_death = ntp::Packet {
+        stratum: 0,
+        ..reply
+    };
+    assert!(!kiss_of_death.is_reply_to(nonce));
+    // 往復3秒のうち1秒はサーバ内なので、片道1秒
+    let sample = ntp::Sample::new(&reply, 0, 3_000_000_000);
+    assert_eq!(sample.delay, Duration::from_secs(2));
+    assert_eq!(sample.unix_time, Duration::from_secs(1_704_067_202));
+}
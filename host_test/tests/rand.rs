@@ -0,0 +1,35 @@
+use wasabi::rand;
+use wasabi::rand::Rng;
+
+#[test]
+fn the_generator_matches_the_reference() {
+    // SplitMix64(0) で種を広げた xoshiro256** の参照実装の出力
+    let mut rng = Rng::from_seed(0);
+    assert_eq!(rng.next_u64(), 0x99ec5f36cb75f2b4);
+    assert_eq!(rng.next_u64(), 0xbf6e1f784956452a);
+    assert_eq!(rng.next_u64(), 0x1a5f849d4933e6e0);
+    assert_eq!(Rng::from_seed(42), Rng::from_seed(42));
+    assert_ne!(Rng::from_seed(1).next_u64(), Rng::from_seed(2).next_u64());
+}
+
+#[test]
+fn numbers_below_n_cover_the_range() {
+    let mut rng = Rng::from_seed(7);
+    let mut seen = [0u32; 6];
+    for _ in 0..6000 {
+        seen[rng.below(6) as usize] += 1;
+    }
+    assert!(seen.iter().all(|&n| (800..1200).contains(&n)), "{seen:?}");
+    assert_eq!(rng.below(1), 0);
+}
+
+#[test]
+fn bytes_are_filled_to_the_end() {
+    let mut rng = Rng::from_seed(3);
+    let mut buf = [0u8; 13];
+    rng.fill_bytes(&mut buf);
+    assert_eq!(&buf[..8], &Rng::from_seed(3).next_u64().to_le_bytes());
+    assert_ne!(buf[8..], [0; 5]);
+    // init() を呼ばなくても使える
+    assert_ne!(rand::random_u64(), rand::random_u64());
+}
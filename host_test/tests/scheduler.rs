@@ -0,0 +1,58 @@
+use wasabi::process;
+use wasabi::scheduler::Scheduler;
+use wasabi::scheduler::SchedulerConfig;
+
+const MS: u64 = 1_000_000;
+
+#[test]
+fn lower_nice_gets_more_cpu_time() {
+    let fast = process::register("fast");
+    let slow = process::register("slow");
+    process::set_nice(fast, -5).unwrap();
+    let mut s = Scheduler::new(SchedulerConfig::DEFAULT);
+    let mut now = 0;
+    s.add(fast, now);
+    s.add(slow, now);
+    // 2つのタスクが1msずつ走っては譲る
+    for _ in 0..1000 {
+        let pids = [fast, slow];
+        let i = s.pick(pids.iter().copied(), now).unwrap();
+        s.switch_to(pids[i], now);
+        now += MS;
+        s.account(now);
+    }
+    let fast_ns = s.stats(fast).unwrap().runtime_ns;
+    let slow_ns = s.stats(slow).unwrap().runtime_ns;
+    assert_eq!(fast_ns + slow_ns, 1000 * MS);
+    // 重みの比は3121:1024でおよそ3倍
+    assert!(fast_ns > 2 * slow_ns && fast_ns < 4 * slow_ns);
+    assert!(s.stats(slow).unwrap().latency.count() > 0);
+}
+
+#[test]
+fn the_slice_is_the_share_of_the_period() {
+    let a = process::register("a");
+    let b = process::register("b");
+    let mut s = Scheduler::new(SchedulerConfig::DEFAULT);
+    s.set_current(a, 0);
+    s.add(b, 0);
+    assert_eq!(s.time_slice(a), 10 * MS);
+    assert!(!s.slice_expired(9 * MS));
+    assert!(s.slice_expired(10 * MS));
+    s.remove(b);
+    assert_eq!(s.time_slice(a), 20 * MS);
+}
+
+#[test]
+fn running_over_the_cpu_limit_kills_the_process() {
+    let pid = process::register("busy");
+    process::set_cpu_limit(pid, Some(50 * MS)).unwrap();
+    let mut s = Scheduler::new(SchedulerConfig::DEFAULT);
+    s.set_current(pid, 0);
+    s.account(40 * MS);
+    assert_eq!(process::info(pid).unwrap().cpu_time, 40 * MS);
+    assert!(process::is_runnable(pid));
+    s.account(60 * MS);
+    assert!(!process::is_runnable(pid));
+    assert_eq!(process::deliver_signals(pid), Some(137));
+}
@@ -0,0 +1,49 @@
+use wasabi::settings;
+use wasabi::sha256::sha256;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[test]
+fn sha256_matches_the_test_vectors() {
+    assert_eq!(
+        hex(&sha256(b"")),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(
+        hex(&sha256(b"abc")),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    // 詰め物が2ブロックにまたがる長さ
+    assert_eq!(
+        hex(&sha256(
+            b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+        )),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+    assert_eq!(
+        hex(&sha256(&[b'a'; 1000])),
+        "41edece42d63e8d9bf515a9ba6932e1c20cbc9f5a5d134645adb5db1b9737ea3"
+    );
+}
+
+#[test]
+fn secrets_are_hashed_and_hidden() {
+    let show = |args: &[&str]| {
+        let mut out = String::new();
+        settings::cmd_config(args, &mut out).unwrap();
+        out
+    };
+    assert!(settings::matches_secret("serial_password", ""));
+    assert_eq!(show(&["get", "serial_password"]), "(empty)\n");
+
+    settings::set_for_boot("serial_password", "hunter2").unwrap();
+    assert!(!settings::get("serial_password").contains("hunter2"));
+    assert!(settings::matches_secret("serial_password", "hunter2"));
+    assert!(!settings::matches_secret("serial_password", "hunter3"));
+    assert!(!settings::matches_secret("serial_password", ""));
+    assert_eq!(show(&["get", "serial_password"]), "(set)\n");
+    assert!(!show(&[]).contains("hunter2"));
+    assert!(show(&[]).contains("serial_password (set) *"));
+}
@@ -0,0 +1,47 @@
+use core::alloc::GlobalAlloc;
+use core::alloc::Layout;
+use wasabi::allocator::ALLOCATOR;
+
+#[test]
+fn small_blocks_come_from_the_slabs_and_go_back() {
+    let region = Box::leak(vec![0u64; 128 * 1024].into_boxed_slice());
+    // SAFETY: the region is leaked, so nobody else uses it
+    unsafe { ALLOCATOR.add_region(region.as_mut_ptr() as usize, region.len() * 8) };
+    let live = |stats: &wasabi::allocator::HeapStats| -> usize {
+        stats.slabs.iter().map(|s| s.live).sum()
+    };
+    let before = ALLOCATOR.stats();
+    let free_before = before.free_bytes;
+    let layout = Layout::from_size_align(40, 8).unwrap();
+    // SAFETY: the layout has a non-zero size
+    let blocks: Vec<*mut u8> = (0..500)
+        .map(|_| unsafe { ALLOCATOR.alloc(layout) })
+        .collect();
+    assert!(blocks.iter().all(|p| !p.is_null()));
+    let during = ALLOCATOR.stats();
+    assert_eq!(live(&during), live(&before) + 500);
+    assert!(during.slabs.iter().all(|s| s.live <= s.capacity()));
+    for p in &blocks {
+        // SAFETY: each block is allocated with the layout
+        unsafe { ALLOCATOR.dealloc(*p, layout) };
+    }
+    ALLOCATOR.set_quarantine_enabled(false);
+    let after = ALLOCATOR.stats();
+    // 隔離中だったブロックも戻るので、前より増えてはいない
+    assert!(live(&after) <= live(&before));
+    // 空いたスラブは各キャッシュで1つを残して空き領域に戻る
+    let kept: usize = after.slabs.iter().map(|s| s.slabs).sum();
+    assert!(kept <= after.slabs.len());
+    assert!(after.free_bytes + kept * 16 * 1024 >= free_before);
+
+    // 同じALLOCATORを使うので、並行して走らないよう同じテストで確かめる
+    for align in [16, 64, 256, 1024] {
+        let layout = Layout::from_size_align(24, align).unwrap();
+        // SAFETY: the layout has a non-zero size
+        let p = unsafe { ALLOCATOR.alloc(layout) };
+        assert!(!p.is_null());
+        assert_eq!(p as usize % align, 0);
+        // SAFETY: p is allocated with the layout
+        unsafe { ALLOCATOR.dealloc(p, layout) };
+    }
+}
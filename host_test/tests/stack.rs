@@ -0,0 +1,12 @@
+use wasabi::stack;
+use wasabi::stack::Stack;
+
+#[test]
+fn stacks_are_page_sized_and_aligned() {
+    let s = Stack::new(10_000);
+    assert_eq!(s.size(), 12_288);
+    assert_eq!(s.top() % 4096, 0);
+    // ホストではページテーブルを持っていないので、ガードページは作られない
+    assert_eq!(s.guard_page(), None);
+    assert!(!stack::is_guard_page(s.top() - s.size() as u64 - 1));
+}
@@ -0,0 +1,56 @@
+use wasabi::graphics::Rect;
+use wasabi::graphics::TestBitmap;
+use wasabi::taskbar;
+use wasabi::taskbar::CpuMeter;
+use wasabi::taskbar::Status;
+use wasabi::wm::WindowId;
+
+fn status(windows: usize) -> Status {
+    Status {
+        windows: (1..=windows)
+            .map(|i| (WindowId(i as u32), format!("w{i}"), i == 1))
+            .collect(),
+        cpu_percent: 50,
+        free_bytes: 1 << 20,
+        total_bytes: 4 << 20,
+        clock: "2024-01-01 00:00:00".to_string(),
+    }
+}
+
+#[test]
+fn cpu_usage_is_the_time_not_idle() {
+    let mut meter = CpuMeter::new();
+    meter.sample(1_000, 0);
+    assert_eq!(meter.sample(2_000, 250), 75);
+    assert_eq!(meter.sample(3_000, 1_250), 0);
+    // 時間が進んでいなければ0、アイドル時間が経過時間を超えても0
+    assert_eq!(meter.sample(3_000, 1_250), 0);
+    assert_eq!(meter.sample(4_000, 5_000), 0);
+}
+
+#[test]
+fn window_buttons_are_laid_out_from_the_left() {
+    let mut bar = TestBitmap::new(640, taskbar::HEIGHT);
+    let buttons = taskbar::draw(&mut bar, &status(2));
+    assert_eq!(buttons.len(), 2);
+    assert_eq!(buttons[0].0, WindowId(1));
+    assert_eq!(buttons[0].1.x, 8);
+    assert!(buttons[0].1.right() < buttons[1].1.x);
+    assert_eq!(buttons[0].1.y, 3);
+    // フォーカスのあるウィンドウのボタンは色が違う
+    let (a, b) = (buttons[0].1, buttons[1].1);
+    assert_ne!(
+        bar.pixel(a.x + a.w - 2, a.y + 2),
+        bar.pixel(b.x + b.w - 2, b.y + 2)
+    );
+    assert!(taskbar::draw(&mut bar, &status(0)).is_empty());
+}
+
+#[test]
+fn buttons_that_do_not_fit_are_left_out() {
+    let mut bar = TestBitmap::new(640, taskbar::HEIGHT);
+    let buttons = taskbar::draw(&mut bar, &status(20));
+    assert!(!buttons.is_empty() && buttons.len() < 20);
+    let last: Rect = buttons.last().unwrap().1;
+    assert!(last.right() < 640 / 2);
+}
@@ -0,0 +1,130 @@
+use core::time::Duration;
+use wasabi::rtc;
+use wasabi::rtc::Registers;
+use wasabi::time::DateTime;
+use wasabi::uefi::EfiTime;
+use wasabi::uefi::EFI_UNSPECIFIED_TIMEZONE;
+
+const fn date_time(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+    DateTime {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    }
+}
+
+#[test]
+fn unix_time_round_trip() {
+    let cases = [
+        (0, date_time(1970, 1, 1, 0, 0, 0)),
+        (951_782_400, date_time(2000, 2, 29, 0, 0, 0)),
+        (1_704_067_199, date_time(2023, 12, 31, 23, 59, 59)),
+        (4_107_542_400, date_time(2100, 3, 1, 0, 0, 0)),
+    ];
+    for (secs, expected) in cases {
+        let t = DateTime::from_unix_time(Duration::from_secs(secs));
+        assert_eq!(t, expected);
+        assert_eq!(t.to_unix_time(), Some(Duration::from_secs(secs)));
+    }
+}
+
+#[test]
+fn invalid_dates() {
+    assert!(date_time(2024, 2, 29, 0, 0, 0).is_valid());
+    assert!(!date_time(2023, 2, 29, 0, 0, 0).is_valid());
+    assert!(!date_time(2100, 2, 29, 0, 0, 0).is_valid());
+    assert!(!date_time(2024, 4, 31, 0, 0, 0).is_valid());
+    assert!(!date_time(2024, 13, 1, 0, 0, 0).is_valid());
+    assert!(!date_time(2024, 1, 1, 24, 0, 0).is_valid());
+    assert_eq!(date_time(1969, 12, 31, 0, 0, 0).to_unix_time(), None);
+}
+
+#[test]
+fn formatting() {
+    let t = date_time(2024, 1, 1, 9, 5, 3);
+    assert_eq!(t.to_string(), "2024-01-01 09:05:03");
+    assert_eq!(format!("{t:#}"), "2024-01-01T09:05:03Z");
+    assert_eq!(t.weekday_name(), "Mon");
+    assert_eq!(t.month_name(), "Jan");
+    assert_eq!(date_time(1970, 1, 1, 0, 0, 0).weekday_name(), "Thu");
+    assert_eq!(date_time(2000, 2, 29, 0, 0, 0).weekday_name(), "Tue");
+}
+
+#[test]
+fn rtc_bcd_12_hour() {
+    // 2024-03-15 午後11時59分58秒 (BCD, 12時間制)
+    let registers = Registers {
+        seconds: 0x58,
+        minutes: 0x59,
+        hours: rtc::HOURS_PM | 0x11,
+        day: 0x15,
+        month: 0x03,
+        year: 0x24,
+        century: Some(0x20),
+        status_b: 0,
+    };
+    assert_eq!(
+        registers.to_date_time(),
+        Some(date_time(2024, 3, 15, 23, 59, 58))
+    );
+    // 午前12時は0時
+    let midnight = Registers {
+        hours: 0x12,
+        ..registers
+    };
+    assert_eq!(midnight.to_date_time().unwrap().hour, 0);
+    let broken = Registers {
+        minutes: 0x5a,
+        ..registers
+    };
+    assert_eq!(broken.to_date_time(), None);
+}
+
+#[test]
+fn rtc_binary_24_hour() {
+    let registers = Registers {
+        seconds: 7,
+        minutes: 30,
+        hours: 13,
+        day: 31,
+        month: 12,
+        year: 99,
+        century: None,
+        status_b: rtc::STATUS_B_BINARY | rtc::STATUS_B_24_HOUR,
+    };
+    assert_eq!(
+        registers.to_date_time(),
+        Some(date_time(2099, 12, 31, 13, 30, 7))
+    );
+    let invalid = Registers {
+        day: 32,
+        ..registers
+    };
+    assert_eq!(invalid.to_date_time(), None);
+}
+
+#[test]
+fn efi_time_is_converted_to_utc() {
+    let t = date_time(2024, 1, 1, 0, 30, 0);
+    let efi = EfiTime::from_date_time(&t);
+    assert_eq!(efi.time_zone, EFI_UNSPECIFIED_TIMEZONE);
+    assert_eq!(efi.to_date_time(), Some(t));
+    // JST (+9:00) の 09:30 は UTC の 00:30
+    let mut jst = efi;
+    jst.hour = 9;
+    jst.time_zone = 9 * 60;
+    assert_eq!(jst.to_date_time(), Some(t));
+    // 日付をまたぐ
+    let mut west = EfiTime::from_date_time(&date_time(2023, 12, 31, 19, 30, 0));
+    west.time_zone = -5 * 60;
+    assert_eq!(west.to_date_time(), Some(t));
+    let mut invalid = efi;
+    invalid.month = 13;
+    assert_eq!(invalid.to_date_time(), None);
+    invalid = efi;
+    invalid.time_zone = 2000;
+    assert_eq!(invalid.to_date_time(), None);
+}
@@ -0,0 +1,50 @@
+use wasabi::tty::Tty;
+use wasabi::tty::BACKSPACE;
+use wasabi::tty::CTRL_D;
+use wasabi::tty::CTRL_W;
+
+fn feed(tty: &mut Tty, s: &str) -> String {
+    let mut out = String::new();
+    for c in s.chars() {
+        tty.input(c, &mut out);
+    }
+    out
+}
+
+#[test]
+fn erasing_echoes_backspace_space_backspace() {
+    let mut tty = Tty::new();
+    assert_eq!(feed(&mut tty, &format!("ab{BACKSPACE}")), "ab\x08 \x08");
+    assert_eq!(
+        feed(&mut tty, &format!(" cd{CTRL_W}")),
+        " cd\x08 \x08\x08 \x08"
+    );
+    feed(&mut tty, "e\n");
+    assert_eq!(tty.read_line().as_deref(), Some("a e"));
+}
+
+#[test]
+fn canonical_lines_are_limited_like_raw_input() {
+    let mut tty = Tty::new();
+    let line = "x".repeat(200);
+    for _ in 0..40 {
+        feed(&mut tty, &format!("{line}\n"));
+    }
+    let mut n = 0;
+    while tty.read_char().is_some() {
+        n += 1;
+    }
+    assert_eq!(n, 4096);
+}
+
+#[test]
+fn ctrl_d_makes_the_line_readable_without_a_newline() {
+    let mut tty = Tty::new();
+    feed(&mut tty, &format!("ab{CTRL_D}"));
+    assert_eq!(tty.read_line().as_deref(), Some("ab"));
+    assert_eq!(tty.read_line(), None);
+    feed(&mut tty, &format!("cd{CTRL_D}ef\n"));
+    assert_eq!(tty.read_line().as_deref(), Some("cd"));
+    assert_eq!(tty.read_line().as_deref(), Some("ef"));
+    assert!(!tty.take_eof());
+}
@@ -0,0 +1,28 @@
+use wasabi::keymap::Key;
+use wasabi::keymap::NamedKey;
+use wasabi::uefi::EfiInputKey;
+
+fn key(scan_code: u16, unicode_char: u16) -> Option<Key> {
+    EfiInputKey {
+        scan_code,
+        unicode_char,
+    }
+    .to_key()
+}
+
+#[test]
+fn firmware_keys_are_translated() {
+    assert_eq!(key(0, 'a' as u16), Some(Key::Char('a')));
+    assert_eq!(key(0, 0x3042), Some(Key::Char('あ')));
+    assert_eq!(key(0, '\r' as u16), Some(Key::Char('\n')));
+    assert_eq!(key(0, 0x08), Some(Key::Char('\x08')));
+    assert_eq!(key(0, 0), None);
+    // サロゲートは1文字にならない
+    assert_eq!(key(0, 0xd800), None);
+    assert_eq!(key(0x01, 0), Some(Key::Named(NamedKey::Up)));
+    assert_eq!(key(0x0a, 0), Some(Key::Named(NamedKey::PageDown)));
+    assert_eq!(key(0x0b, 0), Some(Key::Named(NamedKey::F(1))));
+    assert_eq!(key(0x16, 0), Some(Key::Named(NamedKey::F(12))));
+    assert_eq!(key(0x17, 0), Some(Key::Char('\x1b')));
+    assert_eq!(key(0x48, 0), None);
+}
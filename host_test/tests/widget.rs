@@ -0,0 +1,134 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use wasabi::graphics::OwnedBitmap;
+use wasabi::graphics::Rect;
+use wasabi::input::KeyEvent;
+use wasabi::mouse::MouseButtons;
+use wasabi::widget::Button;
+use wasabi::widget::Label;
+use wasabi::widget::TextBox;
+use wasabi::widget::Ui;
+use wasabi::widget::VStack;
+use wasabi::widget::Widget;
+use wasabi::wm::WindowEvent;
+
+const LEFT: MouseButtons = MouseButtons::from_bits(1);
+const NONE: MouseButtons = MouseButtons::from_bits(0);
+
+fn click(ui: &mut Ui, x: i64, y: i64) -> bool {
+    let down = ui.handle(&WindowEvent::MouseDown {
+        x,
+        y,
+        buttons: LEFT,
+    });
+    let up = ui.handle(&WindowEvent::MouseUp {
+        x,
+        y,
+        buttons: NONE,
+    });
+    down || up
+}
+
+fn key(ui: &mut Ui, usage: u8) -> bool {
+    let pressed = ui.handle(&WindowEvent::Key(KeyEvent {
+        usage,
+        pressed: true,
+    }));
+    ui.handle(&WindowEvent::Key(KeyEvent {
+        usage,
+        pressed: false,
+    }));
+    pressed
+}
+
+#[test]
+fn vstack_lays_out_from_the_top() {
+    let mut stack = VStack::new()
+        .with(Label::new("abc"))
+        .with(TextBox::new(10))
+        .with(Button::new("ok", || {}));
+    let s = VStack::SPACING;
+    assert_eq!(stack.preferred_size(), (88 + s * 2, 16 + 24 + 24 + s * 4));
+    stack.layout(Rect::new(0, 0, 200, 100));
+    assert_eq!(stack.rect(), Rect::new(0, 0, 200, 100));
+    let mut bitmap = OwnedBitmap::new(200, 100, 0);
+    stack.draw(&mut bitmap);
+    // テキストボックスの枠は2番目、ラベルの下
+    assert_ne!(bitmap.pixel(s, s + 16 + s), Some(0));
+    assert_eq!(bitmap.pixel(s, s + 16 + s - 1), Some(0));
+}
+
+#[test]
+fn button_clicks_only_when_released_on_it() {
+    let clicks = Rc::new(Cell::new(0));
+    let counter = clicks.clone();
+    let mut ui =
+        Ui::new(VStack::new().with(Button::new("ok", move || counter.set(counter.get() + 1))));
+    ui.layout(100, 100);
+    assert!(click(&mut ui, 20, 12));
+    assert_eq!(clicks.get(), 1);
+    // 押してから外へ出て離すと押されない
+    ui.handle(&WindowEvent::MouseDown {
+        x: 20,
+        y: 12,
+        buttons: LEFT,
+    });
+    ui.handle(&WindowEvent::MouseUp {
+        x: 20,
+        y: 80,
+        buttons: NONE,
+    });
+    assert_eq!(clicks.get(), 1);
+    assert!(!click(&mut ui, 20, 80));
+    assert_eq!(clicks.get(), 1);
+}
+
+#[test]
+fn text_box_edits_at_the_cursor() {
+    let text_box = TextBox::new(10);
+    let text = text_box.text();
+    let mut ui = Ui::new(VStack::new().with(text_box));
+    ui.layout(200, 100);
+    // フォーカスがないうちはキーを受け取らない
+    assert!(!key(&mut ui, 0x04));
+    assert!(click(&mut ui, 20, 12));
+    for usage in [0x04, 0x05, 0x06] {
+        assert!(key(&mut ui, usage));
+    }
+    assert_eq!(*text.borrow(), "abc");
+    // Left, Backspace, Home, Delete
+    key(&mut ui, 0x50);
+    key(&mut ui, 0x2a);
+    assert_eq!(*text.borrow(), "ac");
+    key(&mut ui, 0x4a);
+    key(&mut ui, 0x4c);
+    assert_eq!(*text.borrow(), "c");
+    // Shift+D は大文字
+    ui.handle(&WindowEvent::Key(KeyEvent {
+        usage: 0xe1,
+        pressed: true,
+    }));
+    key(&mut ui, 0x07);
+    ui.handle(&WindowEvent::Key(KeyEvent {
+        usage: 0xe1,
+        pressed: false,
+    }));
+    assert_eq!(*text.borrow(), "Dc");
+    // 何もない所をクリックするとフォーカスが外れる
+    assert!(click(&mut ui, 20, 90));
+    assert!(!key(&mut ui, 0x04));
+    assert_eq!(*text.borrow(), "Dc");
+}
+
+#[test]
+fn clicking_a_text_box_moves_the_cursor() {
+    let text_box = TextBox::new(10);
+    let text = text_box.text();
+    text.borrow_mut().push_str("hello");
+    let mut ui = Ui::new(VStack::new().with(text_box));
+    ui.layout(200, 100);
+    // 左端(8) + 余白(4) + 2文字の位置
+    click(&mut ui, 8 + 4 + 16, 12);
+    key(&mut ui, 0x2a);
+    assert_eq!(*text.borrow(), "hllo");
+}
@@ -0,0 +1,100 @@
+use wasabi::graphics::fill_rect;
+use wasabi::graphics::OwnedBitmap;
+use wasabi::graphics::Rect;
+use wasabi::input::KeyEvent;
+use wasabi::window_protocol;
+use wasabi::window_protocol::Channel;
+use wasabi::window_protocol::Event;
+use wasabi::window_protocol::Request;
+use wasabi::window_protocol::Server;
+use wasabi::wm::WindowId;
+use wasabi::wm::WindowManager;
+
+fn desktop() -> WindowManager {
+    WindowManager::new(OwnedBitmap::new(128, 96, 0x336699))
+}
+
+#[test]
+fn surfaces_are_windows_drawn_through_the_shared_buffer() {
+    let mut wm = desktop();
+    let (client, server) = Channel::pair();
+    let mut server = Server::new(server);
+    client
+        .send_request(&Request::CreateSurface {
+            width: 40,
+            height: 20,
+        })
+        .unwrap();
+    assert!(server.handle(&mut wm));
+    let Some(Ok(Event::SurfaceCreated {
+        surface,
+        shm,
+        width: 40,
+        height: 20,
+    })) = client.recv_event()
+    else {
+        panic!("no SurfaceCreated");
+    };
+    let id = WindowId(surface.0);
+    assert_eq!(
+        wm.window(id).unwrap().contents().rect(),
+        Rect::new(0, 0, 40, 20)
+    );
+
+    let mut bitmap = window_protocol::map_shm(shm).unwrap().bitmap();
+    fill_rect(&mut bitmap, 0xff0000, 0, 0, 40, 20).unwrap();
+    client
+        .send_request(&Request::Commit {
+            surface,
+            damage: vec![Rect::new(4, 4, 2, 2)],
+        })
+        .unwrap();
+    client
+        .send_request(&Request::SetTitle {
+            surface,
+            title: "app".into(),
+        })
+        .unwrap();
+    assert!(server.handle(&mut wm));
+    let window = wm.window(id).unwrap();
+    assert_eq!(window.title(), "app");
+    // 損傷した範囲だけが写される
+    assert_eq!(window.contents().pixel(4, 4), Some(0xff0000));
+    assert_eq!(window.contents().pixel(6, 6), Some(0x000000));
+
+    let key = KeyEvent {
+        usage: 0x04,
+        pressed: true,
+    };
+    assert!(wm.handle_key(key));
+    assert!(server.handle(&mut wm));
+    assert_eq!(client.recv_event(), Some(Ok(Event::Key { surface, key })));
+
+    wm.request_close(id).unwrap();
+    assert!(server.handle(&mut wm));
+    assert_eq!(client.recv_event(), Some(Ok(Event::Close { surface })));
+    client
+        .send_request(&Request::DestroySurface { surface })
+        .unwrap();
+    assert!(server.handle(&mut wm));
+    assert!(wm.window(id).is_none());
+    assert!(window_protocol::map_shm(shm).is_none());
+}
+
+#[test]
+fn hanging_up_closes_the_windows() {
+    let mut wm = desktop();
+    let (client, server) = Channel::pair();
+    let mut server = Server::new(server);
+    client
+        .send_request(&Request::CreateSurface {
+            width: 40,
+            height: 20,
+        })
+        .unwrap();
+    assert!(server.handle(&mut wm));
+    assert_eq!(wm.windows().len(), 1);
+    drop(client);
+    assert!(!server.handle(&mut wm));
+    assert!(wm.windows().is_empty());
+}
@@ -0,0 +1,270 @@
+use wasabi::graphics::OwnedBitmap;
+use wasabi::graphics::Rect;
+use wasabi::graphics::TestBitmap;
+use wasabi::input::KeyEvent;
+use wasabi::mouse::MouseButtons;
+use wasabi::wm;
+use wasabi::wm::Wallpaper;
+use wasabi::wm::WindowEvent;
+use wasabi::wm::WindowId;
+use wasabi::wm::WindowManager;
+
+const BACKGROUND: u32 = 0x336699;
+const LEFT: MouseButtons = MouseButtons::from_bits(1);
+const NONE: MouseButtons = MouseButtons::from_bits(0);
+
+fn desktop() -> WindowManager {
+    WindowManager::new(OwnedBitmap::new(128, 96, BACKGROUND))
+}
+
+/// A window of 48x48 at (x, y), whose contents are 44x24 at (x + 2, y + 22).
+fn window(x: i64, y: i64) -> Rect {
+    Rect::new(x, y, 48, 48)
+}
+
+fn click(wm: &mut WindowManager, x: i64, y: i64) {
+    wm.handle_mouse(x, y, NONE);
+    wm.handle_mouse(x, y, LEFT);
+    wm.handle_mouse(x, y, NONE);
+}
+
+#[test]
+fn windows_are_stacked_in_the_order_of_creation() {
+    let mut wm = desktop();
+    let a = wm.create_window("a", window(0, 0)).unwrap();
+    let b = wm.create_window("b", window(32, 32)).unwrap();
+    assert_eq!(wm.window_at(40, 40), Some(b));
+    assert_eq!(wm.window_at(4, 4), Some(a));
+    assert_eq!(wm.window_at(120, 4), None);
+    assert_eq!(wm.focused(), Some(b));
+    assert_eq!(
+        wm.window(a).unwrap().content_rect(),
+        Rect::new(wm::BORDER, wm::BORDER + wm::TITLE_HEIGHT, 44, 24)
+    );
+    wm.draw(a, |bitmap| *bitmap = OwnedBitmap::new(44, 24, 0xff0000))
+        .unwrap();
+    wm.draw(b, |bitmap| *bitmap = OwnedBitmap::new(44, 24, 0x00ff00))
+        .unwrap();
+    let mut screen = TestBitmap::new(128, 96);
+    wm.present(&mut screen, 0, 0).unwrap();
+    assert_eq!(screen.pixel(4, 24), 0xff0000);
+    assert_eq!(screen.pixel(40, 60), 0x00ff00);
+    assert_eq!(screen.pixel(120, 4), BACKGROUND);
+    // フォーカスのある窓だけ枠の色が変わる
+    assert_ne!(screen.pixel(0, 0), screen.pixel(32, 32));
+    assert_eq!(wm.damage_bounds(), None);
+}
+
+#[test]
+fn too_small_windows_are_rejected() {
+    let mut wm = desktop();
+    assert!(wm.create_window("a", Rect::new(0, 0, 48, 24)).is_err());
+    assert!(wm.create_window("a", Rect::new(0, 0, 16, 48)).is_err());
+    assert!(wm.windows().is_empty());
+}
+
+#[test]
+fn click_focuses_and_raises() {
+    let mut wm = desktop();
+    let a = wm.create_window("a", window(0, 0)).unwrap();
+    let b = wm.create_window("b", window(32, 32)).unwrap();
+    wm.handle_mouse(4, 30, LEFT);
+    assert_eq!(wm.focused(), Some(a));
+    assert_eq!(wm.window_at(40, 40), Some(a));
+    // 押しっぱなしで動かしても、押した瞬間しか反応しない
+    wm.handle_mouse(70, 70, LEFT);
+    assert_eq!(wm.focused(), Some(a));
+    wm.handle_mouse(70, 70, NONE);
+    wm.handle_mouse(70, 70, LEFT);
+    assert_eq!(wm.focused(), Some(b));
+    click(&mut wm, 120, 2);
+    assert_eq!(wm.focused(), None);
+}
+
+#[test]
+fn dragging_the_title_bar_moves_the_window() {
+    let mut wm = desktop();
+    let a = wm.create_window("a", window(0, 0)).unwrap();
+    wm.handle_mouse(10, 10, LEFT);
+    wm.handle_mouse(30, 20, LEFT);
+    assert_eq!(wm.window(a).unwrap().rect(), window(20, 10));
+    wm.handle_mouse(40, 20, NONE);
+    wm.handle_mouse(50, 50, NONE);
+    assert_eq!(wm.window(a).unwrap().rect(), window(20, 10));
+    // 中身を掴んでも動かない
+    wm.handle_mouse(30, 40, LEFT);
+    wm.handle_mouse(60, 60, LEFT);
+    wm.handle_mouse(60, 60, NONE);
+    assert_eq!(wm.window(a).unwrap().rect(), window(20, 10));
+    // タイトルバーはデスクトップの外へ出ていかない
+    wm.handle_mouse(30, 15, LEFT);
+    wm.handle_mouse(-100, -100, LEFT);
+    wm.handle_mouse(-100, -100, NONE);
+    assert_eq!(wm.window(a).unwrap().rect(), window(32 - 48, 0));
+}
+
+#[test]
+fn close_button_closes_the_window() {
+    let mut wm = desktop();
+    let a = wm.create_window("a", window(0, 0)).unwrap();
+    let b = wm.create_window("b", window(64, 0)).unwrap();
+    // 閉じるボタンは右上、枠とタイトルバーの余白の内側
+    click(&mut wm, 64 + 36, 12);
+    assert!(wm.window(b).is_none());
+    assert_eq!(wm.focused(), Some(a));
+    click(&mut wm, 20, 12);
+    assert!(wm.window(a).is_some());
+}
+
+#[test]
+fn close_button_asks_the_app_to_close() {
+    let mut wm = desktop();
+    let a = wm.create_window("a", window(0, 0)).unwrap();
+    let events = wm.events(a).unwrap();
+    click(&mut wm, 36, 12);
+    assert_eq!(events.try_next(), Some(WindowEvent::Close));
+    assert!(wm.window(a).is_some());
+    wm.close_window(a).unwrap();
+    assert_eq!(events.try_next(), None);
+}
+
+#[test]
+fn keys_go_to_the_focused_window() {
+    let mut wm = desktop();
+    let a = wm.create_window("a", window(0, 0)).unwrap();
+    let events = wm.events(a).unwrap();
+    let key = KeyEvent {
+        usage: 0x04,
+        pressed: true,
+    };
+    assert!(wm.handle_key(key));
+    assert_eq!(events.try_next(), Some(WindowEvent::Key(key)));
+    wm.focus(None).unwrap();
+    assert!(!wm.handle_key(key));
+    assert_eq!(events.try_next(), None);
+}
+
+#[test]
+fn pointer_events_are_relative_to_the_contents() {
+    let mut wm = desktop();
+    let a = wm.create_window("a", window(0, 0)).unwrap();
+    let events = wm.events(a).unwrap();
+    // タイトルバーの上では何も届かない
+    wm.handle_mouse(10, 10, NONE);
+    assert_eq!(events.try_next(), None);
+    wm.handle_mouse(10, 30, NONE);
+    assert_eq!(
+        events.try_next(),
+        Some(WindowEvent::MouseMove {
+            x: 8,
+            y: 8,
+            buttons: NONE
+        })
+    );
+    wm.handle_mouse(10, 30, LEFT);
+    assert_eq!(
+        events.try_next(),
+        Some(WindowEvent::MouseDown {
+            x: 8,
+            y: 8,
+            buttons: LEFT
+        })
+    );
+    // 押している間は外へ出ても届く
+    wm.handle_mouse(100, 90, LEFT);
+    wm.handle_mouse(100, 90, NONE);
+    assert_eq!(
+        events.try_next(),
+        Some(WindowEvent::MouseMove {
+            x: 98,
+            y: 68,
+            buttons: LEFT
+        })
+    );
+    assert_eq!(
+        events.try_next(),
+        Some(WindowEvent::MouseUp {
+            x: 98,
+            y: 68,
+            buttons: NONE
+        })
+    );
+    wm.handle_mouse(110, 90, NONE);
+    assert_eq!(events.try_next(), None);
+}
+
+#[test]
+fn resizing_keeps_the_contents() {
+    let mut wm = desktop();
+    let a = wm.create_window("a", window(0, 0)).unwrap();
+    let events = wm.events(a).unwrap();
+    wm.draw(a, |bitmap| *bitmap = OwnedBitmap::new(44, 24, 0xffffff))
+        .unwrap();
+    assert!(wm.resize_window(a, 16, 16).is_err());
+    wm.resize_window(a, 64, 64).unwrap();
+    assert_eq!(
+        events.try_next(),
+        Some(WindowEvent::Resize {
+            width: 60,
+            height: 40
+        })
+    );
+    let contents = wm.window(a).unwrap().contents();
+    assert_eq!(contents.pixel(43, 23), Some(0xffffff));
+    assert_eq!(contents.pixel(44, 24), Some(0x000000));
+    assert_eq!(contents.pixel(60, 0), None);
+}
+
+#[test]
+fn moving_and_closing_redraw_what_was_below() {
+    let mut wm = desktop();
+    let a = wm.create_window("a", window(0, 0)).unwrap();
+    wm.draw(a, |bitmap| *bitmap = OwnedBitmap::new(44, 24, 0xffffff))
+        .unwrap();
+    let mut screen = TestBitmap::new(128, 96);
+    wm.present(&mut screen, 0, 0).unwrap();
+    wm.move_window(a, 40, 30).unwrap();
+    assert_eq!(wm.damage_bounds(), Some(Rect::new(0, 0, 88, 78)));
+    wm.present(&mut screen, 0, 0).unwrap();
+    assert_eq!(screen.pixel(0, 0), BACKGROUND);
+    assert_eq!(screen.pixel(42, 52), 0xffffff);
+    wm.close_window(a).unwrap();
+    assert_eq!(wm.close_window(a), Err("No such window"));
+    assert_eq!(wm.focused(), None);
+    wm.present(&mut screen, 0, 0).unwrap();
+    assert_eq!(screen.pixel(42, 52), BACKGROUND);
+    assert_eq!(wm.window(WindowId(1)).map(|w| w.title()), None);
+}
+
+#[test]
+fn present_places_the_desktop() {
+    let mut wm = desktop();
+    let mut screen = TestBitmap::with_stride(128, 128, 136);
+    wm.present(&mut screen, 8, 16).unwrap();
+    assert_eq!(screen.pixel(7, 16), 0);
+    assert_eq!(screen.pixel(8, 16), BACKGROUND);
+    assert_eq!(screen.pixel(127, 111), BACKGROUND);
+    assert_eq!(screen.pixel(127, 112), 0);
+}
+
+#[test]
+fn wallpaper_is_drawn_beneath_the_windows() {
+    let mut wm = desktop();
+    let a = wm.create_window("a", window(0, 0)).unwrap();
+    let mut screen = TestBitmap::new(128, 96);
+    wm.present(&mut screen, 0, 0).unwrap();
+    wm.set_wallpaper(Wallpaper::Gradient(0x000000, 0x00ff80));
+    wm.present(&mut screen, 0, 0).unwrap();
+    assert_eq!(screen.pixel(100, 0), 0x000000);
+    assert_eq!(screen.pixel(100, 95), 0x00ff80);
+    assert_eq!(screen.pixel(100, 19), 0x003319);
+    assert!(wm.window(a).unwrap().rect().contains(4, 4));
+    assert_ne!(screen.pixel(4, 4), 0x000000);
+    // 画像は中央に置き、はみ出た分は切る
+    wm.set_wallpaper(Wallpaper::Image(OwnedBitmap::new(200, 10, 0xff0000)));
+    wm.present(&mut screen, 0, 0).unwrap();
+    assert_eq!(screen.pixel(100, 42), 0x000000);
+    assert_eq!(screen.pixel(100, 43), 0xff0000);
+    assert_eq!(screen.pixel(127, 52), 0xff0000);
+    assert_eq!(screen.pixel(100, 53), 0x000000);
+}
@@ -0,0 +1,56 @@
+use wasabi::paging::Protection;
+use wasabi::wx;
+
+/// A PE image with the given (name, rva, size, characteristics) sections.
+fn pe(sections: &[(&[u8], u32, u32, u32)]) -> Vec<u8> {
+    const PE: usize = 0x80;
+    const OPTIONAL_HEADER_SIZE: u16 = 240;
+    let mut image = vec![0u8; 0x400];
+    image[0..2].copy_from_slice(b"MZ");
+    image[0x3c..0x40].copy_from_slice(&(PE as u32).to_le_bytes());
+    image[PE..PE + 4].copy_from_slice(b"PE\0\0");
+    let coff = PE + 4;
+    image[coff..coff + 2].copy_from_slice(&0x8664u16.to_le_bytes());
+    image[coff + 2..coff + 4].copy_from_slice(&(sections.len() as u16).to_le_bytes());
+    image[coff + 16..coff + 18].copy_from_slice(&OPTIONAL_HEADER_SIZE.to_le_bytes());
+    let mut h = coff + 20 + OPTIONAL_HEADER_SIZE as usize;
+    for (name, rva, size, characteristics) in sections {
+        image[h..h + name.len()].copy_from_slice(name);
+        image[h + 8..h + 12].copy_from_slice(&size.to_le_bytes());
+        image[h + 12..h + 16].copy_from_slice(&rva.to_le_bytes());
+        image[h + 36..h + 40].copy_from_slice(&characteristics.to_le_bytes());
+        h += 40;
+    }
+    image
+}
+
+#[test]
+fn sections_are_protected_by_characteristics() {
+    let image = pe(&[
+        (b".text", 0x1000, 0x2345, 0x6000_0020),
+        (b".rdata", 0x4000, 0x800, 0x4000_0040),
+        (b".data", 0x5000, 0x3000, 0xc000_0040),
+    ]);
+    let sections = wx::sections(&image).unwrap();
+    let summary: Vec<(&str, u64, u64, Protection)> = sections
+        .iter()
+        .map(|s| (s.name.as_str(), s.rva, s.size, s.protection))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            (".text", 0x1000, 0x2345, Protection::READ_EXECUTE),
+            (".rdata", 0x4000, 0x800, Protection::READ_ONLY),
+            (".data", 0x5000, 0x3000, Protection::READ_WRITE),
+        ]
+    );
+}
+
+#[test]
+fn broken_images_are_rejected() {
+    assert!(wx::sections(b"MZ").is_err());
+    assert!(wx::sections(&[0u8; 0x400]).is_err());
+    let mut image = pe(&[(b".text", 0x1000, 0x1000, 0x6000_0020)]);
+    image.truncate(0x100);
+    assert!(wx::sections(&image).is_err());
+}
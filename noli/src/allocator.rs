@@ -0,0 +1,75 @@
+//! The global allocator of apps.
+//!
+//! There is no syscall to grow the memory of a process, so the heap is a
+//! fixed array in .bss, which the kernel maps when loading the program.
+//! Blocks are rounded up to a power of two, and freed blocks are kept in a
+//! list per size to be reused. Apps are single-threaded.
+
+use core::alloc::GlobalAlloc;
+use core::alloc::Layout;
+use core::cell::UnsafeCell;
+use core::ptr::null_mut;
+
+pub const HEAP_SIZE: usize = 1024 * 1024;
+const MIN_SHIFT: usize = 4;
+const CLASSES: usize = HEAP_SIZE.trailing_zeros() as usize + 1;
+
+#[repr(C, align(4096))]
+struct Heap([u8; HEAP_SIZE]);
+
+struct State {
+    heap: Heap,
+    /// The offset of the memory not handed out yet.
+    used: usize,
+    /// Freed blocks of 1 << class bytes, linked through their first word.
+    free: [*mut u8; CLASSES],
+}
+
+pub struct Allocator {
+    state: UnsafeCell<State>,
+}
+// SAFETY: apps have only one thread
+unsafe impl Sync for Allocator {}
+
+#[global_allocator]
+static ALLOCATOR: Allocator = Allocator {
+    state: UnsafeCell::new(State {
+        heap: Heap([0; HEAP_SIZE]),
+        used: 0,
+        free: [null_mut(); CLASSES],
+    }),
+};
+
+/// The class of blocks for the layout. Blocks are aligned to their size.
+fn class_of(layout: Layout) -> usize {
+    let size = layout.size().max(layout.align()).max(1 << MIN_SHIFT);
+    size.next_power_of_two().trailing_zeros() as usize
+}
+
+unsafe impl GlobalAlloc for Allocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let class = class_of(layout);
+        if class >= CLASSES {
+            return null_mut();
+        }
+        let state = &mut *self.state.get();
+        let block = state.free[class];
+        if !block.is_null() {
+            state.free[class] = *(block as *mut *mut u8);
+            return block;
+        }
+        let size = 1 << class;
+        let start = (state.used + size - 1) & !(size - 1);
+        if start + size > HEAP_SIZE {
+            return null_mut();
+        }
+        state.used = start + size;
+        state.heap.0.as_mut_ptr().add(start)
+    }
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let state = &mut *self.state.get();
+        let class = class_of(layout);
+        *(ptr as *mut *mut u8) = state.free[class];
+        state.free[class] = ptr;
+    }
+}
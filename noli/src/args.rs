@@ -0,0 +1,71 @@
+//! The entry point and the arguments of the program.
+//!
+//! The kernel starts the program with rsp pointing to argc, followed by the
+//! pointers to the arguments (NUL-terminated), a NULL, the environment
+//! (always empty) and the auxiliary vector, as on System V.
+
+use crate::sys;
+use core::arch::global_asm;
+use core::slice;
+use core::str;
+
+global_asm!(
+    ".global _start",
+    "_start:",
+    "mov rdi, rsp",
+    "and rsp, -16",
+    "call noli_start",
+    "ud2",
+);
+
+extern "Rust" {
+    /// Defined by entry_point!().
+    fn noli_main(args: Args) -> i64;
+}
+
+#[no_mangle]
+extern "sysv64" fn noli_start(sp: *const u64) -> ! {
+    // SAFETY: the kernel has put argc and argv at the initial stack pointer
+    let args = unsafe {
+        Args {
+            argv: sp.add(1) as *const *const u8,
+            remaining: *sp as usize,
+        }
+    };
+    // SAFETY: the app defines noli_main with entry_point!()
+    let code = unsafe { noli_main(args) };
+    sys::exit(code)
+}
+
+/// The arguments of the program. The first one is the path of the program
+/// by convention. Arguments that are not valid UTF-8 are given as "".
+#[derive(Clone)]
+pub struct Args {
+    argv: *const *const u8,
+    remaining: usize,
+}
+impl Iterator for Args {
+    type Item = &'static str;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        // SAFETY: argv has `remaining` more pointers to NUL-terminated
+        // strings, which stay on the stack until the program exits
+        let arg = unsafe {
+            let p = *self.argv;
+            let mut len = 0;
+            while *p.add(len) != 0 {
+                len += 1;
+            }
+            slice::from_raw_parts(p, len)
+        };
+        self.argv = self.argv.wrapping_add(1);
+        self.remaining -= 1;
+        Some(str::from_utf8(arg).unwrap_or(""))
+    }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+impl ExactSizeIterator for Args {}
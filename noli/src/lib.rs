@@ -0,0 +1,52 @@
+//! The support library of WasabiOS user programs.
+//!
+//! An app is a `no_std`, `no_main` binary for x86_64-unknown-none that
+//! depends on this crate and names its main function with entry_point!():
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! use noli::println;
+//!
+//! noli::entry_point!(main);
+//!
+//! fn main(args: noli::args::Args) -> i64 {
+//!     println!("Hello, {}!", args.skip(1).next().unwrap_or("world"));
+//!     0
+//! }
+//! ```
+//!
+//! The crate provides the entry point (_start), the syscalls, print!() and
+//! friends, a global allocator and the panic handler. The app links with
+//! `app.ld` and `-no-pie`; see apps/hello for the build settings.
+
+#![no_std]
+
+pub mod allocator;
+pub mod args;
+pub mod print;
+pub mod sys;
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+
+/// Defines the function called by _start with the arguments of the program.
+/// Its return value is the exit code.
+#[macro_export]
+macro_rules! entry_point {
+    ($main:path) => {
+        #[no_mangle]
+        fn noli_main(args: $crate::args::Args) -> i64 {
+            let main: fn($crate::args::Args) -> i64 = $main;
+            main(args)
+        }
+    };
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    eprintln!("{info}");
+    sys::exit(-1)
+}
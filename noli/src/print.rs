@@ -0,0 +1,41 @@
+use crate::sys;
+use core::fmt;
+
+/// Writes to a file descriptor with fmt::Write.
+pub struct FdWriter(pub sys::Fd);
+impl fmt::Write for FdWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        sys::write_all(self.0, s.as_bytes()).or(Err(fmt::Error))
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(fd: sys::Fd, args: fmt::Arguments) {
+    let _ = fmt::Write::write_fmt(&mut FdWriter(fd), args);
+}
+
+/// Prints to stdout.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::print::_print($crate::sys::STDOUT, format_args!($($arg)*)));
+}
+
+/// Prints to stdout, with a newline.
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
+
+/// Prints to stderr.
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::print::_print($crate::sys::STDERR, format_args!($($arg)*)));
+}
+
+/// Prints to stderr, with a newline.
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($($arg:tt)*) => ($crate::eprint!("{}\n", format_args!($($arg)*)));
+}
@@ -0,0 +1,196 @@
+//! The syscalls of WasabiOS. The numbers must match src/syscall.rs of the
+//! kernel.
+
+use core::arch::asm;
+
+pub type Result<T> = core::result::Result<T, &'static str>;
+pub type Fd = u64;
+
+pub const STDIN: Fd = 0;
+pub const STDOUT: Fd = 1;
+pub const STDERR: Fd = 2;
+
+const SYS_EXIT: u64 = 0;
+const SYS_WRITE: u64 = 1;
+const SYS_GETCHAR: u64 = 2;
+const SYS_DRAW_RECT: u64 = 3;
+const SYS_READ: u64 = 4;
+const SYS_SPAWN: u64 = 5;
+const SYS_WAIT: u64 = 6;
+const SYS_CLOSE: u64 = 7;
+const SYS_PIPE: u64 = 8;
+const SYS_DUP2: u64 = 9;
+const SYS_MQ_OPEN: u64 = 10;
+const SYS_CLIPBOARD_COPY: u64 = 11;
+const SYS_CLIPBOARD_PASTE: u64 = 12;
+
+const ERROR: u64 = u64::MAX;
+
+/// Calls the syscall with up to five arguments.
+fn syscall(num: u64, a0: u64, a1: u64, a2: u64, a3: u64, a4: u64) -> u64 {
+    let result;
+    // SAFETY: the kernel only touches the memory passed to it, and
+    // preserves the registers other than rcx and r11, including the x87
+    // and SSE state (it saves them with FXSAVE on entry), so no other
+    // register needs to be marked as clobbered
+    unsafe {
+        asm!(
+            "syscall",
+            inout("rax") num => result,
+            in("rdi") a0,
+            in("rsi") a1,
+            in("rdx") a2,
+            in("r10") a3,
+            in("r8") a4,
+            out("rcx") _,
+            out("r11") _,
+            options(nostack),
+        );
+    }
+    result
+}
+
+fn check(result: u64, e: &'static str) -> Result<u64> {
+    if result == ERROR {
+        Err(e)
+    } else {
+        Ok(result)
+    }
+}
+
+pub fn exit(code: i64) -> ! {
+    syscall(SYS_EXIT, code as u64, 0, 0, 0, 0);
+    unreachable!("exit returned")
+}
+
+/// Returns the number of bytes written, which may be less than buf.len().
+pub fn write(fd: Fd, buf: &[u8]) -> Result<usize> {
+    let n = syscall(SYS_WRITE, fd, buf.as_ptr() as u64, buf.len() as u64, 0, 0);
+    check(n, "write failed").map(|n| n as usize)
+}
+
+/// Writes all of buf.
+pub fn write_all(fd: Fd, mut buf: &[u8]) -> Result<()> {
+    while !buf.is_empty() {
+        let n = write(fd, buf)?;
+        buf = &buf[n..];
+    }
+    Ok(())
+}
+
+/// Waits for at least one byte, and returns the number of bytes read. 0
+/// means the end of the file.
+pub fn read(fd: Fd, buf: &mut [u8]) -> Result<usize> {
+    let n = syscall(
+        SYS_READ,
+        fd,
+        buf.as_mut_ptr() as u64,
+        buf.len() as u64,
+        0,
+        0,
+    );
+    check(n, "read failed").map(|n| n as usize)
+}
+
+/// The next byte of stdin, or None at the end.
+pub fn getchar() -> Option<u8> {
+    let c = syscall(SYS_GETCHAR, 0, 0, 0, 0, 0);
+    (c != ERROR).then_some(c as u8)
+}
+
+pub fn draw_rect(x: i64, y: i64, w: i64, h: i64, color: u32) -> Result<()> {
+    let result = syscall(
+        SYS_DRAW_RECT,
+        x as u64,
+        y as u64,
+        w as u64,
+        h as u64,
+        color as u64,
+    );
+    check(result, "draw_rect failed").map(|_| ())
+}
+
+/// Starts the program at path on the ESP with the arguments separated by
+/// spaces, and returns its pid.
+pub fn spawn(path: &str, args: &str) -> Result<u64> {
+    let pid = syscall(
+        SYS_SPAWN,
+        path.as_ptr() as u64,
+        path.len() as u64,
+        args.as_ptr() as u64,
+        args.len() as u64,
+        0,
+    );
+    check(pid, "spawn failed")
+}
+
+/// Waits for the process to exit, and returns its exit code.
+pub fn wait(pid: u64) -> Result<i64> {
+    check(syscall(SYS_WAIT, pid, 0, 0, 0, 0), "wait failed").map(|c| c as i64)
+}
+
+pub fn close(fd: Fd) -> Result<()> {
+    check(syscall(SYS_CLOSE, fd, 0, 0, 0, 0), "close failed").map(|_| ())
+}
+
+/// Returns the read end and the write end of a new pipe.
+pub fn pipe() -> Result<(Fd, Fd)> {
+    let mut fds = [0u64; 2];
+    check(
+        syscall(SYS_PIPE, fds.as_mut_ptr() as u64, 0, 0, 0, 0),
+        "pipe failed",
+    )?;
+    Ok((fds[0], fds[1]))
+}
+
+/// Makes new_fd refer to the file of fd, closing the one it referred to.
+pub fn dup2(fd: Fd, new_fd: Fd) -> Result<Fd> {
+    check(syscall(SYS_DUP2, fd, new_fd, 0, 0, 0), "dup2 failed")
+}
+
+/// Opens the message queue with the name, creating it if needed. Each
+/// write() sends a message and each read() receives one.
+pub fn mq_open(name: &str) -> Result<Fd> {
+    check(
+        syscall(
+            SYS_MQ_OPEN,
+            name.as_ptr() as u64,
+            name.len() as u64,
+            0,
+            0,
+            0,
+        ),
+        "mq_open failed",
+    )
+}
+
+/// Puts text on the clipboard shared with the console and the GUI apps.
+pub fn clipboard_copy(text: &str) -> Result<()> {
+    check(
+        syscall(
+            SYS_CLIPBOARD_COPY,
+            text.as_ptr() as u64,
+            text.len() as u64,
+            0,
+            0,
+            0,
+        ),
+        "clipboard_copy failed",
+    )
+    .map(|_| ())
+}
+
+/// Copies the text on the clipboard into buf, and returns its whole length,
+/// which may be more than buf.len(). The copy may end in the middle of a
+/// UTF-8 character.
+pub fn clipboard_paste(buf: &mut [u8]) -> Result<usize> {
+    let n = syscall(
+        SYS_CLIPBOARD_PASTE,
+        buf.as_mut_ptr() as u64,
+        buf.len() as u64,
+        0,
+        0,
+        0,
+    );
+    check(n, "clipboard_paste failed").map(|n| n as usize)
+}
@@ -0,0 +1,13 @@
+#!/bin/bash -e
+# Builds the user programs in apps/ and puts them in target/apps, which
+# launch_qemu.sh copies to \apps on the ESP.
+PROJ_ROOT="$(dirname $(dirname ${BASH_SOURCE:-$0}))"
+cd "${PROJ_ROOT}"
+
+mkdir -p target/apps
+for app in apps/*/; do
+    name=$(basename "${app}")
+    (cd "${app}" && cargo build --release)
+    cp "${app}target/x86_64-unknown-none/release/${name}" "target/apps/${name}"
+done
+ls -l target/apps
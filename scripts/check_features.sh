@@ -0,0 +1,21 @@
+#!/bin/bash -e
+# Builds the kernel with every combination of the subsystem features, and
+# shows the size of the EFI binary for each of them.
+PROJ_ROOT="$(dirname $(dirname ${BASH_SOURCE:-$0}))"
+cd "${PROJ_ROOT}"
+
+FEATURES=(net usb gui storage)
+for ((mask = 0; mask < 1 << ${#FEATURES[@]}; mask++)); do
+    enabled=()
+    for i in "${!FEATURES[@]}"; do
+        if (( mask & (1 << i) )); then
+            enabled+=("${FEATURES[$i]}")
+        fi
+    done
+    list=$(IFS=,; echo "${enabled[*]}")
+    echo "--features \"${list}\""
+    cargo build --release --no-default-features --features "${list}"
+    cargo clippy --release --no-default-features --features "${list}" -- -D warnings
+    ls -l target/x86_64-unknown-uefi/release/wasabi.efi | awk '{print "size:", $5}'
+done
+echo "All feature combinations passed"
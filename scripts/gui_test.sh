@@ -0,0 +1,16 @@
+#!/bin/bash -e
+# Runs the frame capture tests (src/gui_test.rs) in QEMU.
+PROJ_ROOT="$(dirname $(dirname ${BASH_SOURCE:-$0}))"
+cd "${PROJ_ROOT}"
+
+cargo build --features gui_test
+set +e
+timeout 120 scripts/launch_qemu.sh target/x86_64-unknown-uefi/debug/wasabi.efi < /dev/null
+STATUS=$?
+set -e
+# isa-debug-exit: (QemuExitCode::Success << 1) | 1
+if [ ${STATUS} -ne 33 ]; then
+    echo "gui_test failed (QEMU exit status ${STATUS})"
+    exit 1
+fi
+echo "gui_test passed"
@@ -7,8 +7,32 @@ rm -rf mnt
 mkdir -p mnt/EFI/BOOT/
 # cp target/x86_64-unknown-uefi/debug/wasabi.efi mnt/EFI/BOOT/BOOTX64.EFI
 cp ${PATH_TO_EFI} mnt/EFI/BOOT/BOOTX64.EFI
+# WASABI_KERNEL=カーネルELF を指定すると、wasabi-loaderが読み込む \EFI\wasabi\kernel.elf に置く
+if [ -n "${WASABI_KERNEL}" ]; then
+    mkdir -p mnt/EFI/wasabi
+    cp "${WASABI_KERNEL}" mnt/EFI/wasabi/kernel.elf
+fi
+# scripts/build_apps.sh でビルドしたユーザープログラムは \apps に置く
+if [ -d target/apps ]; then
+    mkdir -p mnt/apps
+    cp target/apps/* mnt/apps/
+fi
+# WASABI_DISK=disk.img を指定すると virtio-blk (vda) として接続する
+DISK_ARGS=()
+if [ -n "${WASABI_DISK}" ]; then
+    DISK_ARGS=(-drive "if=none,id=vda,format=raw,file=${WASABI_DISK}" -device "virtio-blk-pci,drive=vda,disable-legacy=on")
+fi
+# WASABI_NET=user (または tap,ifname=tap0,script=no,downscript=no など -netdev の指定)
+# を指定すると virtio-net (eth0) として接続する
+NET_ARGS=()
+if [ -n "${WASABI_NET}" ]; then
+    NET_ARGS=(-netdev "${WASABI_NET},id=net0" -device "virtio-net-pci,netdev=net0,disable-legacy=on")
+fi
 qemu-system-x86_64 \
     -m 4G \
     -bios third_party/ovmf/RELEASEX64_OVMF.fd \
     -drive format=raw,file=fat:rw:mnt \
     -device isa-debug-exit,iobase=0xf4,iosize=0x01 \
+    "${DISK_ARGS[@]}" \
+    "${NET_ARGS[@]}" \
+    -serial stdio
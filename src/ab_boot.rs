@@ -0,0 +1,239 @@
+//! A/B kernel image selection.
+//!
+//! The image started by the firmware (BOOTX64.EFI) acts as a loader: it picks
+//! one of two kernel images on the ESP and starts it with the load option
+//! `wasabi-slot=A` (or B). The slot kernel calls mark_boot_successful() once
+//! it is up. If it never does (e.g. it hangs or crashes and the machine is
+//! reset), the next boot falls back to the last known good slot.
+//!
+//! The state is kept in a non-volatile UEFI variable.
+
+use crate::info;
+use crate::result::Result;
+use crate::uefi;
+use crate::uefi::EfiSystemTable;
+use crate::uefi::EFI_VARIABLE_BOOTSERVICE_ACCESS;
+use crate::uefi::EFI_VARIABLE_NON_VOLATILE;
+use crate::uefi::EFI_VARIABLE_RUNTIME_ACCESS;
+use crate::uefi::WASABI_VENDOR_GUID;
+use crate::warn;
+use alloc::format;
+use core::fmt;
+
+const STATE_VARIABLE: &str = "WasabiAbState";
+const STATE_VERSION: u8 = 1;
+const LOAD_OPTION_PREFIX: &str = "wasabi-slot=";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Slot {
+    A,
+    B,
+}
+impl Slot {
+    pub fn path(self) -> &'static str {
+        match self {
+            Slot::A => "\\EFI\\wasabi\\kernel_a.efi",
+            Slot::B => "\\EFI\\wasabi\\kernel_b.efi",
+        }
+    }
+    fn from_byte(b: u8) -> Option<Self> {
+        match b {
+            b'A' => Some(Slot::A),
+            b'B' => Some(Slot::B),
+            _ => None,
+        }
+    }
+    fn to_byte(self) -> u8 {
+        match self {
+            Slot::A => b'A',
+            Slot::B => b'B',
+        }
+    }
+}
+impl fmt::Display for Slot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_byte() as char)
+    }
+}
+impl core::str::FromStr for Slot {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "A" | "a" => Ok(Slot::A),
+            "B" | "b" => Ok(Slot::B),
+            _ => Err("Slot must be A or B"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AbState {
+    /// The slot to boot next.
+    pub active: Slot,
+    /// The last slot that called mark_boot_successful().
+    pub last_good: Slot,
+    /// Set while the active slot is booting and not confirmed yet.
+    pub pending: bool,
+}
+impl Default for AbState {
+    fn default() -> Self {
+        Self {
+            active: Slot::A,
+            last_good: Slot::A,
+            pending: false,
+        }
+    }
+}
+
+pub fn read_state(efi_system_table: &EfiSystemTable) -> Result<AbState> {
+    let mut buf = [0u8; 4];
+    let size = efi_system_table.runtime_services.get_variable(
+        STATE_VARIABLE,
+        &WASABI_VENDOR_GUID,
+        &mut buf,
+    )?;
+    if size != Some(buf.len()) || buf[0] != STATE_VERSION {
+        return Ok(AbState::default());
+    }
+    Ok(AbState {
+        active: Slot::from_byte(buf[1]).unwrap_or(Slot::A),
+        last_good: Slot::from_byte(buf[2]).unwrap_or(Slot::A),
+        pending: buf[3] != 0,
+    })
+}
+
+pub fn write_state(efi_system_table: &EfiSystemTable, state: &AbState) -> Result<()> {
+    let buf = [
+        STATE_VERSION,
+        state.active.to_byte(),
+        state.last_good.to_byte(),
+        state.pending as u8,
+    ];
+    efi_system_table.runtime_services.set_variable(
+        STATE_VARIABLE,
+        &WASABI_VENDOR_GUID,
+        EFI_VARIABLE_NON_VOLATILE | EFI_VARIABLE_BOOTSERVICE_ACCESS | EFI_VARIABLE_RUNTIME_ACCESS,
+        &buf,
+    )
+}
+
+/// The slot this kernel was started from, or None if it was started by the
+/// firmware directly.
+pub fn current_slot(efi_system_table: &EfiSystemTable) -> Option<Slot> {
+    let image = uefi::image_handle()?;
+    let options = uefi::load_options(efi_system_table, image).ok()?;
+    options
+        .split_whitespace()
+        .find_map(|o| o.strip_prefix(LOAD_OPTION_PREFIX))
+        .and_then(|s| s.parse().ok())
+}
+
+/// Starts the selected slot image if this kernel is the loader.
+///
+/// Must be called before the kernel takes over the interrupt setup, since
+/// the firmware needs it to load and start the image. Returns if this is a
+/// slot kernel, if no slot image could be started, or if it exited; the
+/// caller should then continue booting itself.
+pub fn boot_slot(efi_system_table: &EfiSystemTable) -> Result<()> {
+    if current_slot(efi_system_table).is_some() {
+        return Ok(());
+    }
+    let parent = uefi::image_handle().ok_or("EFI context is not initialized")?;
+    let boot_services = efi_system_table.boot_services;
+    let mut state = read_state(efi_system_table)?;
+    if state.pending {
+        warn!(
+            "slot {} did not finish booting, falling back to slot {}",
+            state.active, state.last_good
+        );
+        state.active = state.last_good;
+        state.pending = false;
+        write_state(efi_system_table, &state)?;
+    }
+    let candidates = [state.active, state.last_good];
+    for (i, slot) in candidates.iter().enumerate() {
+        if candidates[..i].contains(slot) {
+            continue;
+        }
+        let image = uefi::file_device_path(efi_system_table, slot.path())
+            .and_then(|path| boot_services.load_image(parent, &path));
+        let image = match image {
+            Ok(image) => image,
+            Err(e) => {
+                warn!("cannot load slot {slot} ({}): {e}", slot.path());
+                continue;
+            }
+        };
+        uefi::set_load_options(
+            efi_system_table,
+            image,
+            &format!("{LOAD_OPTION_PREFIX}{slot}"),
+        )?;
+        state.active = *slot;
+        state.pending = true;
+        write_state(efi_system_table, &state)?;
+        info!("starting slot {slot}");
+        let status = boot_services.start_image(image);
+        warn!("slot {slot} exited with status {status:#x}");
+        return Ok(());
+    }
+    Ok(())
+}
+
+/// Records that this slot kernel has booted fine, making it the last known
+/// good one.
+pub fn mark_boot_successful(efi_system_table: &EfiSystemTable) -> Result<()> {
+    let Some(slot) = current_slot(efi_system_table) else {
+        return Ok(());
+    };
+    let state = read_state(efi_system_table)?;
+    let confirmed = AbState {
+        active: slot,
+        last_good: slot,
+        pending: false,
+    };
+    if state.active == slot && state != confirmed {
+        write_state(efi_system_table, &confirmed)?;
+    }
+    Ok(())
+}
+
+/// The `abboot` command.
+pub fn cmd_abboot(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let efi_system_table = uefi::system_table().ok_or("EFI context is not initialized")?;
+    match args {
+        [] => {
+            let state = read_state(efi_system_table)?;
+            match current_slot(efi_system_table) {
+                Some(slot) => {
+                    let _ = writeln!(out, "running: slot {slot}");
+                }
+                None => {
+                    let _ = writeln!(out, "running: not started from a slot");
+                }
+            }
+            let _ = writeln!(
+                out,
+                "active: {}{}\nlast good: {}",
+                state.active,
+                if state.pending { " (pending)" } else { "" },
+                state.last_good
+            );
+            Ok(())
+        }
+        ["set", slot] => {
+            let slot: Slot = slot.parse()?;
+            let mut state = read_state(efi_system_table)?;
+            state.active = slot;
+            state.pending = false;
+            write_state(efi_system_table, &state)?;
+            let _ = writeln!(
+                out,
+                "slot {slot} will be tried on the next boot (falls back to {})",
+                state.last_good
+            );
+            Ok(())
+        }
+        _ => Err("usage: abboot [set A|B]"),
+    }
+}
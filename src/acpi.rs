@@ -0,0 +1,633 @@
+//! ACPI table discovery.
+//!
+//! The RSDP is found through the EFI configuration table, and the tables
+//! listed in the XSDT are checked with their checksums before use. The
+//! tables that the kernel understands are available as typed views:
+//! Fadt, Madt, HpetTable and Mcfg.
+
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::uefi::EfiSystemTable;
+use crate::uefi::EFI_ACPI_20_TABLE_GUID;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem::size_of;
+
+fn checksum_ok(p: *const u8, len: usize) -> bool {
+    let bytes = unsafe { core::slice::from_raw_parts(p, len) };
+    bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+#[repr(C, packed)]
+struct Rsdp {
+    signature: [u8; 8],
+    checksum: u8,
+    oem_id: [u8; 6],
+    revision: u8,
+    rsdt_address: u32,
+    length: u32,
+    xsdt_address: u64,
+    extended_checksum: u8,
+    reserved: [u8; 3],
+}
+const _: () = assert!(size_of::<Rsdp>() == 36);
+
+/// The header common to all the ACPI system description tables.
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct SdtHeader {
+    signature: [u8; 4],
+    length: u32,
+    revision: u8,
+    checksum: u8,
+    oem_id: [u8; 6],
+    oem_table_id: [u8; 8],
+    oem_revision: u32,
+    creator_id: u32,
+    creator_revision: u32,
+}
+const _: () = assert!(size_of::<SdtHeader>() == 36);
+impl SdtHeader {
+    pub fn signature(&self) -> &[u8; 4] {
+        &self.signature
+    }
+    pub fn length(&self) -> usize {
+        self.length as usize
+    }
+    pub fn revision(&self) -> u8 {
+        self.revision
+    }
+    pub fn oem_id(&self) -> &[u8; 6] {
+        &self.oem_id
+    }
+    pub fn oem_table_id(&self) -> &[u8; 8] {
+        &self.oem_table_id
+    }
+    fn is_valid(&self) -> bool {
+        checksum_ok(self as *const Self as *const u8, self.length())
+    }
+}
+
+/// A table that can be looked up with Acpi::table().
+///
+/// # Safety
+/// The type must be repr(C, packed), start with an SdtHeader, and match the
+/// layout of the table with the signature.
+pub unsafe trait AcpiTable {
+    const SIGNATURE: &'static [u8; 4];
+}
+
+/// A register location in the FADT and other tables.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GenericAddress {
+    /// 0: system memory, 1: system I/O
+    pub address_space_id: u8,
+    pub register_bit_width: u8,
+    pub register_bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+const _: () = assert!(size_of::<GenericAddress>() == 12);
+impl GenericAddress {
+    pub const SYSTEM_MEMORY: u8 = 0;
+    pub const SYSTEM_IO: u8 = 1;
+    pub fn is_null(&self) -> bool {
+        self.address == 0
+    }
+}
+
+/// Fixed ACPI Description Table, up to the ACPI 2.0 fields.
+#[repr(C, packed)]
+#[derive(Clone, Copy)]
+pub struct Fadt {
+    header: SdtHeader,
+    firmware_ctrl: u32,
+    dsdt: u32,
+    _reserved0: u8,
+    preferred_pm_profile: u8,
+    sci_int: u16,
+    smi_cmd: u32,
+    acpi_enable: u8,
+    acpi_disable: u8,
+    s4bios_req: u8,
+    pstate_cnt: u8,
+    pm1a_evt_blk: u32,
+    pm1b_evt_blk: u32,
+    pm1a_cnt_blk: u32,
+    pm1b_cnt_blk: u32,
+    pm2_cnt_blk: u32,
+    pm_tmr_blk: u32,
+    gpe0_blk: u32,
+    gpe1_blk: u32,
+    pm1_evt_len: u8,
+    pm1_cnt_len: u8,
+    pm2_cnt_len: u8,
+    pm_tmr_len: u8,
+    gpe0_blk_len: u8,
+    gpe1_blk_len: u8,
+    gpe1_base: u8,
+    cst_cnt: u8,
+    p_lvl2_lat: u16,
+    p_lvl3_lat: u16,
+    flush_size: u16,
+    flush_stride: u16,
+    duty_offset: u8,
+    duty_width: u8,
+    day_alrm: u8,
+    mon_alrm: u8,
+    century: u8,
+    iapc_boot_arch: u16,
+    _reserved1: u8,
+    flags: u32,
+    reset_reg: GenericAddress,
+    reset_value: u8,
+    arm_boot_arch: u16,
+    fadt_minor_version: u8,
+    x_firmware_ctrl: u64,
+    x_dsdt: u64,
+    x_pm1a_evt_blk: GenericAddress,
+    x_pm1b_evt_blk: GenericAddress,
+    x_pm1a_cnt_blk: GenericAddress,
+    x_pm1b_cnt_blk: GenericAddress,
+    x_pm2_cnt_blk: GenericAddress,
+    x_pm_tmr_blk: GenericAddress,
+    x_gpe0_blk: GenericAddress,
+    x_gpe1_blk: GenericAddress,
+}
+const _: () = assert!(size_of::<Fadt>() == 244);
+impl Fadt {
+    /// FLAGS: the reset register is supported.
+    pub const FLAG_RESET_REG_SUP: u32 = 1 << 10;
+    /// IAPC_BOOT_ARCH: there is an 8042 keyboard controller.
+    pub const BOOT_ARCH_8042: u16 = 1 << 1;
+
+    /// The address of the DSDT, preferring the 64-bit field.
+    pub fn dsdt(&self) -> u64 {
+        match self.x_dsdt {
+            0 => self.dsdt as u64,
+            x => x,
+        }
+    }
+    pub fn sci_int(&self) -> u16 {
+        self.sci_int
+    }
+    pub fn smi_cmd(&self) -> u32 {
+        self.smi_cmd
+    }
+    pub fn acpi_enable(&self) -> u8 {
+        self.acpi_enable
+    }
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+    pub fn iapc_boot_arch(&self) -> u16 {
+        self.iapc_boot_arch
+    }
+    /// The RTC register index of the century, or 0 if there is none.
+    pub fn century(&self) -> u8 {
+        self.century
+    }
+    /// The I/O port of PM1a_CNT. 0 if there is none.
+    pub fn pm1a_cnt_blk(&self) -> u16 {
+        Self::io_port(self.x_pm1a_cnt_blk, self.pm1a_cnt_blk)
+    }
+    /// The I/O port of PM1b_CNT. 0 if there is none.
+    pub fn pm1b_cnt_blk(&self) -> u16 {
+        Self::io_port(self.x_pm1b_cnt_blk, self.pm1b_cnt_blk)
+    }
+    /// The I/O port of the PM timer. 0 if there is none.
+    pub fn pm_tmr_blk(&self) -> u16 {
+        Self::io_port(self.x_pm_tmr_blk, self.pm_tmr_blk)
+    }
+    /// The reset register and the value to write to it, if supported.
+    pub fn reset_reg(&self) -> Option<(GenericAddress, u8)> {
+        let reg = self.reset_reg;
+        (self.flags & Self::FLAG_RESET_REG_SUP != 0 && !reg.is_null())
+            .then_some((reg, self.reset_value))
+    }
+    fn io_port(x: GenericAddress, legacy: u32) -> u16 {
+        if !x.is_null() && x.address_space_id == GenericAddress::SYSTEM_IO {
+            x.address as u16
+        } else {
+            legacy as u16
+        }
+    }
+}
+unsafe impl AcpiTable for Fadt {
+    const SIGNATURE: &'static [u8; 4] = b"FACP";
+}
+
+/// Multiple APIC Description Table. The interrupt controller structures
+/// follow the fixed part.
+#[repr(C, packed)]
+pub struct Madt {
+    header: SdtHeader,
+    local_apic_address: u32,
+    flags: u32,
+}
+const _: () = assert!(size_of::<Madt>() == 44);
+impl Madt {
+    /// FLAGS: the machine also has the dual 8259 PICs.
+    pub const FLAG_PCAT_COMPAT: u32 = 1;
+
+    pub fn local_apic_address(&self) -> u64 {
+        self.local_apic_address as u64
+    }
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+    /// The raw interrupt controller structures.
+    pub fn structures(&self) -> &[u8] {
+        let len = self.header.length().saturating_sub(size_of::<Self>());
+        // SAFETY: the bytes are within the table, whose length was checked
+        unsafe { core::slice::from_raw_parts((self as *const Self).add(1) as *const u8, len) }
+    }
+    /// Iterates over the interrupt controller structures.
+    pub fn entries(&self) -> MadtEntries<'_> {
+        MadtEntries {
+            bytes: self.structures(),
+        }
+    }
+}
+
+/// An interrupt controller structure in the MADT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MadtEntry {
+    LocalApic {
+        processor_uid: u32,
+        apic_id: u32,
+        flags: u32,
+    },
+    IoApic {
+        id: u8,
+        address: u64,
+        gsi_base: u32,
+    },
+    InterruptSourceOverride {
+        bus: u8,
+        source: u8,
+        gsi: u32,
+        flags: u16,
+    },
+    NmiSource {
+        flags: u16,
+        gsi: u32,
+    },
+    LocalApicNmi {
+        /// 0xffff_ffff for all the processors.
+        processor_uid: u32,
+        flags: u16,
+        lint: u8,
+    },
+    LocalApicAddressOverride {
+        address: u64,
+    },
+    /// A structure that is not parsed, with its type.
+    Other(u8),
+}
+impl MadtEntry {
+    /// LocalApic flags: the processor is usable.
+    pub const LAPIC_ENABLED: u32 = 1;
+    /// LocalApic flags: the processor can be enabled by the OS.
+    pub const LAPIC_ONLINE_CAPABLE: u32 = 1 << 1;
+
+    fn parse(t: u8, b: &[u8]) -> Option<Self> {
+        let u16_at = |i: usize| Some(u16::from_le_bytes(b.get(i..i + 2)?.try_into().ok()?));
+        let u32_at = |i: usize| Some(u32::from_le_bytes(b.get(i..i + 4)?.try_into().ok()?));
+        let u64_at = |i: usize| Some(u64::from_le_bytes(b.get(i..i + 8)?.try_into().ok()?));
+        // オフセットは2バイトのヘッダ (type, length) を含む
+        Some(match t {
+            0 => Self::LocalApic {
+                processor_uid: *b.get(2)? as u32,
+                apic_id: *b.get(3)? as u32,
+                flags: u32_at(4)?,
+            },
+            1 => Self::IoApic {
+                id: *b.get(2)?,
+                address: u32_at(4)? as u64,
+                gsi_base: u32_at(8)?,
+            },
+            2 => Self::InterruptSourceOverride {
+                bus: *b.get(2)?,
+                source: *b.get(3)?,
+                gsi: u32_at(4)?,
+                flags: u16_at(8)?,
+            },
+            3 => Self::NmiSource {
+                flags: u16_at(2)?,
+                gsi: u32_at(4)?,
+            },
+            4 => Self::LocalApicNmi {
+                processor_uid: match *b.get(2)? {
+                    0xff => 0xffff_ffff,
+                    uid => uid as u32,
+                },
+                flags: u16_at(3)?,
+                lint: *b.get(5)?,
+            },
+            5 => Self::LocalApicAddressOverride {
+                address: u64_at(4)?,
+            },
+            9 => Self::LocalApic {
+                processor_uid: u32_at(12)?,
+                apic_id: u32_at(4)?,
+                flags: u32_at(8)?,
+            },
+            0xa => Self::LocalApicNmi {
+                processor_uid: u32_at(4)?,
+                flags: u16_at(2)?,
+                lint: *b.get(8)?,
+            },
+            t => Self::Other(t),
+        })
+    }
+}
+
+pub struct MadtEntries<'a> {
+    bytes: &'a [u8],
+}
+impl Iterator for MadtEntries<'_> {
+    type Item = MadtEntry;
+    fn next(&mut self) -> Option<MadtEntry> {
+        let (&t, &len) = (self.bytes.first()?, self.bytes.get(1)?);
+        let len = len as usize;
+        if len < 2 || len > self.bytes.len() {
+            // 壊れた構造体の後ろは読まない
+            self.bytes = &[];
+            return None;
+        }
+        let (entry, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Some(MadtEntry::parse(t, entry).unwrap_or(MadtEntry::Other(t)))
+    }
+}
+unsafe impl AcpiTable for Madt {
+    const SIGNATURE: &'static [u8; 4] = b"APIC";
+}
+
+/// High Precision Event Timer Description Table.
+#[repr(C, packed)]
+pub struct HpetTable {
+    header: SdtHeader,
+    event_timer_block_id: u32,
+    address: GenericAddress,
+    hpet_number: u8,
+    min_tick: u16,
+    page_protection: u8,
+}
+const _: () = assert!(size_of::<HpetTable>() == 56);
+impl HpetTable {
+    /// The base address of the registers, or None if they are not memory mapped.
+    pub fn base_address(&self) -> Option<u64> {
+        let address = self.address;
+        (address.address_space_id == GenericAddress::SYSTEM_MEMORY).then_some(address.address)
+    }
+    pub fn hpet_number(&self) -> u8 {
+        self.hpet_number
+    }
+    /// The minimum tick in periodic mode, in counter ticks.
+    pub fn min_tick(&self) -> u16 {
+        self.min_tick
+    }
+}
+unsafe impl AcpiTable for HpetTable {
+    const SIGNATURE: &'static [u8; 4] = b"HPET";
+}
+
+/// The memory-mapped configuration space (ECAM) of a PCI segment, listed in
+/// the MCFG table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct McfgEntry {
+    pub base: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+#[repr(C, packed)]
+struct McfgAllocation {
+    base: u64,
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8,
+    _reserved: u32,
+}
+const _: () = assert!(size_of::<McfgAllocation>() == 16);
+
+/// PCI Express memory mapped configuration table.
+#[repr(C, packed)]
+pub struct Mcfg {
+    header: SdtHeader,
+    _reserved: u64,
+}
+const _: () = assert!(size_of::<Mcfg>() == 44);
+impl Mcfg {
+    pub fn entries(&self) -> Vec<McfgEntry> {
+        let base = (self as *const Self).wrapping_add(1) as *const McfgAllocation;
+        let n =
+            self.header.length().saturating_sub(size_of::<Self>()) / size_of::<McfgAllocation>();
+        (0..n)
+            .map(|i| {
+                // SAFETY: the entries are within the table, whose length was checked
+                let a = unsafe { base.add(i).read_unaligned() };
+                McfgEntry {
+                    base: a.base,
+                    segment: a.segment,
+                    start_bus: a.start_bus,
+                    end_bus: a.end_bus,
+                }
+            })
+            .collect()
+    }
+}
+unsafe impl AcpiTable for Mcfg {
+    const SIGNATURE: &'static [u8; 4] = b"MCFG";
+}
+
+#[derive(Clone, Copy)]
+pub struct Acpi {
+    xsdt: &'static SdtHeader,
+}
+impl Acpi {
+    /// Finds the XSDT through the RSDP that the firmware put in the
+    /// configuration table. ACPI 1.0 (RSDT only) is not supported.
+    pub fn new(efi_system_table: &EfiSystemTable) -> Result<Self> {
+        let rsdp = efi_system_table
+            .lookup_configuration_table(&EFI_ACPI_20_TABLE_GUID)
+            .ok_or("ACPI 2.0 RSDP not found")?;
+        // SAFETY: the firmware provides a valid RSDP address
+        unsafe { Self::from_rsdp(rsdp) }
+    }
+    /// Same as new(), but with an RSDP found by other means (e.g. a bootloader).
+    ///
+    /// # Safety
+    /// rsdp must point to readable memory, and the tables must be identity mapped.
+    pub unsafe fn from_rsdp(rsdp: *const u8) -> Result<Self> {
+        let rsdp = &*(rsdp as *const Rsdp);
+        if &rsdp.signature != b"RSD PTR " {
+            return Err("Invalid RSDP signature");
+        }
+        if rsdp.revision < 2 || !checksum_ok(rsdp as *const Rsdp as *const u8, size_of::<Rsdp>()) {
+            return Err("Invalid RSDP");
+        }
+        let xsdt = &*(rsdp.xsdt_address as *const SdtHeader);
+        if xsdt.signature() != b"XSDT" || !xsdt.is_valid() {
+            return Err("Invalid XSDT");
+        }
+        Ok(Self { xsdt })
+    }
+    /// Iterates over the tables listed in the XSDT.
+    pub fn tables(&self) -> impl Iterator<Item = &'static SdtHeader> {
+        let entries =
+            (self.xsdt as *const SdtHeader as usize + size_of::<SdtHeader>()) as *const u64;
+        let n = (self.xsdt.length() - size_of::<SdtHeader>()) / size_of::<u64>();
+        // XSDTのエントリは4バイト境界にしか揃っていない
+        (0..n).map(move |i| unsafe { &*(entries.add(i).read_unaligned() as *const SdtHeader) })
+    }
+    pub fn find_table(&self, signature: &[u8; 4]) -> Option<&'static SdtHeader> {
+        self.tables()
+            .find(|t| t.signature() == signature && t.is_valid())
+    }
+    /// Finds a table by its type. Tables shorter than the type are ignored.
+    pub fn table<T: AcpiTable>(&self) -> Option<&'static T> {
+        let t = self.find_table(T::SIGNATURE)?;
+        // SAFETY: T matches the layout of the table (see AcpiTable)
+        (t.length() >= size_of::<T>()).then(|| unsafe { &*(t as *const SdtHeader as *const T) })
+    }
+    /// The FADT. Fields that an older (shorter) FADT lacks read as zero.
+    pub fn fadt(&self) -> Option<Fadt> {
+        let t = self.find_table(Fadt::SIGNATURE)?;
+        let len = t.length().min(size_of::<Fadt>());
+        // SAFETY: Fadt is plain data, and len bytes are readable in both
+        unsafe {
+            let mut fadt: Fadt = core::mem::zeroed();
+            core::ptr::copy_nonoverlapping(
+                t as *const SdtHeader as *const u8,
+                &mut fadt as *mut Fadt as *mut u8,
+                len,
+            );
+            Some(fadt)
+        }
+    }
+    /// The DSDT, if its checksum is valid.
+    pub fn dsdt(&self) -> Option<&'static SdtHeader> {
+        let addr = self.fadt()?.dsdt();
+        // SAFETY: the FADT points to the DSDT, which is identity mapped
+        let dsdt = unsafe { &*(addr as *const SdtHeader) };
+        (addr != 0 && dsdt.signature() == b"DSDT" && dsdt.is_valid()).then_some(dsdt)
+    }
+    /// SLP_TYPa and SLP_TYPb of the S5 (soft off) state, from the \_S5_
+    /// object in the DSDT.
+    pub fn s5_sleep_types(&self) -> Option<(u8, u8)> {
+        let dsdt = self.dsdt()?;
+        // SAFETY: the whole table is readable, as its checksum was computed
+        let aml = unsafe {
+            core::slice::from_raw_parts(dsdt as *const SdtHeader as *const u8, dsdt.length())
+        };
+        parse_s5(&aml[size_of::<SdtHeader>()..])
+    }
+    pub fn madt(&self) -> Option<&'static Madt> {
+        self.table()
+    }
+    pub fn hpet(&self) -> Option<&'static HpetTable> {
+        self.table()
+    }
+    pub fn mcfg(&self) -> Option<&'static Mcfg> {
+        self.table()
+    }
+}
+
+/// Finds `Name (_S5_, Package () { SLP_TYPa, SLP_TYPb, ... })` in the AML.
+///
+/// This is not an AML interpreter: it only looks for the byte pattern that
+/// compilers emit for the object, which is enough for the machines we run on.
+fn parse_s5(aml: &[u8]) -> Option<(u8, u8)> {
+    const NAME_OP: u8 = 0x08;
+    const PACKAGE_OP: u8 = 0x12;
+    const ZERO_OP: u8 = 0x00;
+    const ONE_OP: u8 = 0x01;
+    const BYTE_PREFIX: u8 = 0x0a;
+    let pos = aml.windows(4).position(|w| w == b"_S5_")?;
+    let named = match pos {
+        0 => false,
+        1 => aml[0] == NAME_OP,
+        _ => aml[pos - 1] == NAME_OP || (aml[pos - 1] == b'\\' && aml[pos - 2] == NAME_OP),
+    };
+    if !named {
+        return None;
+    }
+    let mut rest = aml.get(pos + 4..)?;
+    if *rest.first()? != PACKAGE_OP {
+        return None;
+    }
+    // PkgLengthの上位2ビットは後続のバイト数
+    let pkg_length_bytes = 1 + (*rest.get(1)? >> 6) as usize;
+    rest = rest.get(1 + pkg_length_bytes + 1..)?; // PkgLength, NumElements
+    let mut values = [0u8; 2];
+    for v in &mut values {
+        *v = match *rest.first()? {
+            ZERO_OP => 0,
+            ONE_OP => 1,
+            BYTE_PREFIX => {
+                let v = *rest.get(1)?;
+                rest = &rest[1..];
+                v
+            }
+            _ => return None,
+        };
+        rest = &rest[1..];
+    }
+    Some((values[0], values[1]))
+}
+
+static ACPI: Mutex<Option<Acpi>> = Mutex::new(None);
+
+/// Finds the ACPI tables and keeps them for get().
+pub fn init(efi_system_table: &EfiSystemTable) -> Result<Acpi> {
+    let acpi = Acpi::new(efi_system_table)?;
+    *ACPI.lock() = Some(acpi);
+    Ok(acpi)
+}
+
+/// Returns the tables if init() has succeeded.
+pub fn get() -> Option<Acpi> {
+    *ACPI.lock()
+}
+
+struct Ascii<'a>(&'a [u8]);
+impl fmt::Display for Ascii<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for &c in self.0 {
+            let c = if c.is_ascii_graphic() || c == b' ' {
+                c as char
+            } else {
+                '.'
+            };
+            fmt::Write::write_char(f, c)?;
+        }
+        Ok(())
+    }
+}
+
+/// The `acpi` command.
+pub fn cmd_acpi(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    if !args.is_empty() {
+        return Err("usage: acpi");
+    }
+    let acpi = get().ok_or("ACPI is not available")?;
+    for t in acpi.tables() {
+        let _ = writeln!(
+            out,
+            "{} {:#012x} {:6} rev {} {} {}{}",
+            Ascii(t.signature()),
+            t as *const SdtHeader as usize,
+            t.length(),
+            t.revision(),
+            Ascii(t.oem_id()),
+            Ascii(t.oem_table_id()),
+            if t.is_valid() { "" } else { " (bad checksum)" }
+        );
+    }
+    Ok(())
+}
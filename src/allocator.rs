@@ -0,0 +1,1005 @@
+//! The kernel heap: a first-fit allocator over an address-ordered free list,
+//! with slab caches in front of it for the small blocks.
+//!
+//! Blocks of up to 2 KiB (tasks, timers, network buffers, damage rects and
+//! the like) come from the slab cache of their power-of-two size: 16 KiB
+//! slabs taken from the free list and cut into objects of that size, so
+//! that allocating and freeing them is O(1) and they do not fragment the
+//! free list. A slab goes back to the free list when its last object is
+//! freed, unless it is the only one of its cache with free objects.
+//!
+//! Debug builds surround each allocation with redzones and poison freed
+//! blocks, keeping them in a quarantine for a while to catch use-after-free;
+//! poisoning can be turned on in release builds too. Every build counts the
+//! allocations, the bytes in use and their peak, and the live allocations
+//! per size class, which the `heap` command shows along with the
+//! fragmentation of the free list. `heap mark` and `heap leaks` compare the
+//! live allocations with an earlier point to find what a task leaks.
+
+use crate::memory_map::Size;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::uefi::AllocateType;
+use crate::uefi::EfiBootServicesTable;
+use crate::uefi::EfiMemoryType;
+use crate::uefi::MemoryMapHolder;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::alloc::GlobalAlloc;
+use core::alloc::Layout;
+use core::fmt;
+use core::mem::size_of;
+use core::ptr::null_mut;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+// 空き領域は先頭に FreeBlock を埋め込んだアドレス順の単方向リストで管理する
+#[repr(C)]
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+const BLOCK_UNIT: usize = size_of::<FreeBlock>();
+const _: () = assert!(BLOCK_UNIT.is_power_of_two());
+
+fn round_up(v: usize, align: usize) -> usize {
+    (v + align - 1) & !(align - 1)
+}
+
+struct FreeList {
+    head: *mut FreeBlock,
+}
+// SAFETY: the list is only touched while holding the allocator lock.
+unsafe impl Send for FreeList {}
+
+impl FreeList {
+    /// # Safety
+    ///
+    /// [start, start + size) must be unused memory that stays valid forever.
+    unsafe fn add_region(&mut self, start: usize, size: usize) {
+        let begin = round_up(start, BLOCK_UNIT);
+        let end = (start + size) & !(BLOCK_UNIT - 1);
+        if end <= begin {
+            return;
+        }
+        self.insert(begin, end - begin);
+    }
+    /// Inserts a free block keeping the list sorted by address, merging it with its neighbors.
+    unsafe fn insert(&mut self, addr: usize, size: usize) {
+        let mut prev: *mut FreeBlock = null_mut();
+        let mut cur = self.head;
+        while !cur.is_null() && (cur as usize) < addr {
+            prev = cur;
+            cur = (*cur).next;
+        }
+        let block = addr as *mut FreeBlock;
+        block.write(FreeBlock { size, next: cur });
+        if !cur.is_null() && addr + size == cur as usize {
+            (*block).size += (*cur).size;
+            (*block).next = (*cur).next;
+        }
+        if prev.is_null() {
+            self.head = block;
+        } else if prev as usize + (*prev).size == addr {
+            (*prev).size += (*block).size;
+            (*prev).next = (*block).next;
+        } else {
+            (*prev).next = block;
+        }
+    }
+    unsafe fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        let mut prev: *mut FreeBlock = null_mut();
+        let mut cur = self.head;
+        while !cur.is_null() {
+            let begin = cur as usize;
+            let end = begin + (*cur).size;
+            let next = (*cur).next;
+            let start = round_up(begin, align);
+            if start + size <= end {
+                // 余った前後の領域は空きブロックとして残す
+                if prev.is_null() {
+                    self.head = next;
+                } else {
+                    (*prev).next = next;
+                }
+                if start > begin {
+                    self.insert(begin, start - begin);
+                }
+                if start + size < end {
+                    self.insert(start + size, end - (start + size));
+                }
+                return start as *mut u8;
+            }
+            prev = cur;
+            cur = next;
+        }
+        null_mut()
+    }
+    /// The free bytes, the number of free blocks and the largest of them.
+    fn free_stats(&self) -> (usize, usize, usize) {
+        let (mut total, mut blocks, mut largest) = (0, 0, 0);
+        let mut cur = self.head;
+        while !cur.is_null() {
+            // SAFETY: every entry in the list is a valid FreeBlock.
+            unsafe {
+                total += (*cur).size;
+                blocks += 1;
+                largest = largest.max((*cur).size);
+                cur = (*cur).next;
+            }
+        }
+        (total, blocks, largest)
+    }
+}
+
+/// Blocks up to this size come from the slab caches.
+const MAX_SLAB_OBJECT: usize = 2048;
+const SLAB_SIZE: usize = 16 * 1024;
+/// One cache per size class up to MAX_SLAB_OBJECT.
+pub const SLAB_CACHES: usize = 8;
+const _: () = assert!(16 << (SLAB_CACHES - 1) == MAX_SLAB_OBJECT);
+
+/// The header at the start of each slab.
+#[repr(C)]
+struct Slab {
+    /// The neighbors in the list of the slabs with free objects.
+    prev: *mut Slab,
+    next: *mut Slab,
+    free: *mut FreeObject,
+    live: usize,
+}
+
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// The slabs of one object size.
+struct SlabCache {
+    object_size: usize,
+    /// The slabs that have free objects.
+    partial: *mut Slab,
+    slabs: usize,
+    live: usize,
+}
+// SAFETY: the caches are only touched while holding the allocator lock.
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+    const fn new(object_size: usize) -> Self {
+        Self {
+            object_size,
+            partial: null_mut(),
+            slabs: 0,
+            live: 0,
+        }
+    }
+    /// The offset of the first object, aligned to the object size.
+    fn first_object(&self) -> usize {
+        round_up(size_of::<Slab>(), self.object_size)
+    }
+    unsafe fn push_partial(&mut self, slab: *mut Slab) {
+        (*slab).prev = null_mut();
+        (*slab).next = self.partial;
+        if !self.partial.is_null() {
+            (*self.partial).prev = slab;
+        }
+        self.partial = slab;
+    }
+    unsafe fn remove_partial(&mut self, slab: *mut Slab) {
+        let (prev, next) = ((*slab).prev, (*slab).next);
+        if prev.is_null() {
+            self.partial = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+    }
+    /// Makes a slab of free objects from a block of SLAB_SIZE bytes aligned
+    /// to SLAB_SIZE.
+    unsafe fn add_slab(&mut self, addr: usize) {
+        let slab = addr as *mut Slab;
+        let mut free: *mut FreeObject = null_mut();
+        // 後ろから積むと先頭のオブジェクトから使われる
+        let mut obj = addr + SLAB_SIZE - self.object_size;
+        while obj >= addr + self.first_object() {
+            let o = obj as *mut FreeObject;
+            o.write(FreeObject { next: free });
+            free = o;
+            obj -= self.object_size;
+        }
+        slab.write(Slab {
+            prev: null_mut(),
+            next: null_mut(),
+            free,
+            live: 0,
+        });
+        self.push_partial(slab);
+        self.slabs += 1;
+    }
+    /// Takes an object, or returns null if a new slab is needed.
+    unsafe fn alloc(&mut self) -> *mut u8 {
+        let slab = self.partial;
+        if slab.is_null() {
+            return null_mut();
+        }
+        let obj = (*slab).free;
+        (*slab).free = (*obj).next;
+        (*slab).live += 1;
+        if (*slab).free.is_null() {
+            self.remove_partial(slab);
+        }
+        self.live += 1;
+        obj as *mut u8
+    }
+    /// Puts an object back, returning its slab if that is to be freed.
+    unsafe fn free(&mut self, addr: usize) -> Option<usize> {
+        let slab = (addr & !(SLAB_SIZE - 1)) as *mut Slab;
+        let obj = addr as *mut FreeObject;
+        let was_full = (*slab).free.is_null();
+        obj.write(FreeObject { next: (*slab).free });
+        (*slab).free = obj;
+        (*slab).live -= 1;
+        self.live -= 1;
+        if was_full {
+            self.push_partial(slab);
+        }
+        // 空きのある最後のスラブは次の確保のために残しておく
+        let only = self.partial == slab && (*slab).next.is_null();
+        if (*slab).live > 0 || only {
+            return None;
+        }
+        self.remove_partial(slab);
+        self.slabs -= 1;
+        Some(slab as usize)
+    }
+}
+
+const fn slab_caches() -> [SlabCache; SLAB_CACHES] {
+    const EMPTY: SlabCache = SlabCache::new(0);
+    let mut caches = [EMPTY; SLAB_CACHES];
+    let mut i = 0;
+    while i < SLAB_CACHES {
+        caches[i].object_size = 16 << i;
+        i += 1;
+    }
+    caches
+}
+
+/// The slab cache for blocks of size bytes, if they are small enough.
+fn slab_cache_index(size: usize) -> Option<usize> {
+    (size <= MAX_SLAB_OBJECT).then(|| size_class(size))
+}
+
+mod poison {
+    pub const FREED_BYTE: u8 = 0xdd;
+    #[cfg(debug_assertions)]
+    const QUARANTINE_SIZE: usize = 64;
+
+    /// Details of a freed block whose poison pattern was overwritten.
+    #[cfg(debug_assertions)]
+    pub struct Corruption {
+        pub addr: usize,
+        pub block: usize,
+        pub size: usize,
+    }
+
+    /// # Safety
+    ///
+    /// [addr, addr + size) must be a block owned by the allocator.
+    pub unsafe fn fill(addr: usize, size: usize) {
+        core::ptr::write_bytes(addr as *mut u8, FREED_BYTE, size);
+    }
+
+    /// # Safety
+    ///
+    /// [addr, addr + size) must be a block owned by the allocator.
+    #[cfg(debug_assertions)]
+    pub unsafe fn check(addr: usize, size: usize) -> Result<(), Corruption> {
+        let bytes = core::slice::from_raw_parts(addr as *const u8, size);
+        match bytes.iter().position(|b| *b != FREED_BYTE) {
+            Some(ofs) => Err(Corruption {
+                addr: addr + ofs,
+                block: addr,
+                size,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Freed blocks are kept here for a while before being reused, so that
+    /// writes through dangling pointers hit the poison pattern rather than
+    /// a live allocation.
+    #[cfg(debug_assertions)]
+    pub struct Quarantine {
+        entries: [(usize, usize); QUARANTINE_SIZE],
+        head: usize,
+        len: usize,
+        pub enabled: bool,
+    }
+    #[cfg(debug_assertions)]
+    impl Quarantine {
+        pub const fn new() -> Self {
+            Self {
+                entries: [(0, 0); QUARANTINE_SIZE],
+                head: 0,
+                len: 0,
+                enabled: true,
+            }
+        }
+        /// Returns the block that has been evicted to make room, if any.
+        pub fn push(&mut self, addr: usize, size: usize) -> Option<(usize, usize)> {
+            if !self.enabled {
+                return Some((addr, size));
+            }
+            let evicted = if self.len == QUARANTINE_SIZE {
+                self.pop()
+            } else {
+                None
+            };
+            self.entries[(self.head + self.len) % QUARANTINE_SIZE] = (addr, size);
+            self.len += 1;
+            evicted
+        }
+        pub fn pop(&mut self) -> Option<(usize, usize)> {
+            if self.len == 0 {
+                return None;
+            }
+            let e = self.entries[self.head];
+            self.head = (self.head + 1) % QUARANTINE_SIZE;
+            self.len -= 1;
+            Some(e)
+        }
+    }
+}
+
+/// The number of size classes: the powers of two from 16 bytes to 64 KiB,
+/// and one for anything larger.
+pub const SIZE_CLASSES: usize = 14;
+const MIN_CLASS_SHIFT: u32 = 4;
+
+/// The size class of an allocation of size bytes.
+pub fn size_class(size: usize) -> usize {
+    let shift = size.max(1).next_power_of_two().trailing_zeros();
+    (shift.saturating_sub(MIN_CLASS_SHIFT) as usize).min(SIZE_CLASSES - 1)
+}
+
+/// The largest size in the class, or None for the last one.
+pub fn size_class_limit(class: usize) -> Option<usize> {
+    (class < SIZE_CLASSES - 1).then(|| 1 << (class as u32 + MIN_CLASS_SHIFT))
+}
+
+/// The live allocations of a size class, in the sizes asked for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeClassStats {
+    pub live: usize,
+    pub bytes: usize,
+}
+
+/// The counters kept by the allocator, in the sizes asked for (without
+/// the redzones and the rounding).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    pub allocs: u64,
+    pub frees: u64,
+    /// Allocations that failed for the lack of memory.
+    pub failures: u64,
+    pub in_use: usize,
+    pub peak_in_use: usize,
+    pub classes: [SizeClassStats; SIZE_CLASSES],
+}
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            allocs: 0,
+            frees: 0,
+            failures: 0,
+            in_use: 0,
+            peak_in_use: 0,
+            classes: [SizeClassStats { live: 0, bytes: 0 }; SIZE_CLASSES],
+        }
+    }
+    pub fn record_alloc(&mut self, size: usize) {
+        self.allocs += 1;
+        self.in_use += size;
+        self.peak_in_use = self.peak_in_use.max(self.in_use);
+        let class = &mut self.classes[size_class(size)];
+        class.live += 1;
+        class.bytes += size;
+    }
+    pub fn record_free(&mut self, size: usize) {
+        self.frees += 1;
+        self.in_use = self.in_use.saturating_sub(size);
+        let class = &mut self.classes[size_class(size)];
+        class.live = class.live.saturating_sub(1);
+        class.bytes = class.bytes.saturating_sub(size);
+    }
+    /// The live allocations of all the classes.
+    pub fn live(&self) -> usize {
+        self.classes.iter().map(|c| c.live).sum()
+    }
+    /// The classes that have more live allocations than at mark, with how
+    /// many more and how many more bytes.
+    pub fn growth_since(&self, mark: &Counters) -> Vec<(usize, SizeClassStats)> {
+        self.classes
+            .iter()
+            .zip(mark.classes.iter())
+            .enumerate()
+            .filter(|(_, (now, then))| now.live > then.live)
+            .map(|(class, (now, then))| {
+                let growth = SizeClassStats {
+                    live: now.live - then.live,
+                    bytes: now.bytes.saturating_sub(then.bytes),
+                };
+                (class, growth)
+            })
+            .collect()
+    }
+}
+
+/// The objects of a slab cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SlabStats {
+    pub object_size: usize,
+    pub slabs: usize,
+    pub live: usize,
+}
+impl SlabStats {
+    /// The number of objects that the slabs can hold.
+    pub fn capacity(&self) -> usize {
+        let first = round_up(size_of::<Slab>(), self.object_size.max(1));
+        self.slabs * ((SLAB_SIZE - first) / self.object_size.max(1))
+    }
+}
+
+/// A snapshot of the heap for the `heap` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    pub total_bytes: usize,
+    pub free_bytes: usize,
+    pub free_blocks: usize,
+    pub largest_free_block: usize,
+    pub poison: bool,
+    pub counters: Counters,
+    pub slabs: [SlabStats; SLAB_CACHES],
+}
+impl HeapStats {
+    /// How much of the free memory is not in the largest free block, in
+    /// percent: 0 if all of it could be allocated at once.
+    pub fn fragmentation_percent(&self) -> usize {
+        if self.free_bytes == 0 {
+            return 0;
+        }
+        (self.free_bytes - self.largest_free_block) * 100 / self.free_bytes
+    }
+}
+
+struct Heap {
+    free_list: FreeList,
+    slabs: [SlabCache; SLAB_CACHES],
+    counters: Counters,
+    poison: bool,
+    #[cfg(debug_assertions)]
+    quarantine: poison::Quarantine,
+    #[cfg(debug_assertions)]
+    redzone_size: usize,
+}
+impl Heap {
+    /// Takes a block from its slab cache or the free list. If size is small
+    /// enough for a slab, align must not be larger than its size class, so
+    /// that give() finds the block from the size alone.
+    unsafe fn take(&mut self, size: usize, align: usize) -> *mut u8 {
+        let Some(i) = slab_cache_index(size) else {
+            return self.free_list.alloc(size, align);
+        };
+        let cache = &mut self.slabs[i];
+        debug_assert!(align <= cache.object_size);
+        if cache.partial.is_null() {
+            let slab = self.free_list.alloc(SLAB_SIZE, SLAB_SIZE);
+            if slab.is_null() {
+                return slab;
+            }
+            cache.add_slab(slab as usize);
+        }
+        cache.alloc()
+    }
+    /// Returns a block from take().
+    unsafe fn give(&mut self, addr: usize, size: usize) {
+        let Some(i) = slab_cache_index(size) else {
+            self.free_list.insert(addr, size);
+            return;
+        };
+        if let Some(slab) = self.slabs[i].free(addr) {
+            self.free_list.insert(slab, SLAB_SIZE);
+        }
+    }
+    #[cfg(debug_assertions)]
+    unsafe fn release(
+        &mut self,
+        addr: usize,
+        size: usize,
+    ) -> core::result::Result<(), poison::Corruption> {
+        poison::check(addr, size)?;
+        self.give(addr, size);
+        Ok(())
+    }
+    #[cfg(debug_assertions)]
+    unsafe fn alloc(
+        &mut self,
+        size: usize,
+        align: usize,
+    ) -> core::result::Result<*mut u8, poison::Corruption> {
+        let p = self.take(size, align);
+        if !p.is_null() {
+            return Ok(p);
+        }
+        // 隔離中のブロックを全て戻してから再挑戦する
+        self.drain_quarantine()?;
+        Ok(self.take(size, align))
+    }
+    #[cfg(debug_assertions)]
+    unsafe fn dealloc(
+        &mut self,
+        addr: usize,
+        size: usize,
+    ) -> core::result::Result<(), poison::Corruption> {
+        if !self.poison {
+            self.give(addr, size);
+            return Ok(());
+        }
+        poison::fill(addr, size);
+        match self.quarantine.push(addr, size) {
+            Some((addr, size)) => self.release(addr, size),
+            None => Ok(()),
+        }
+    }
+    /// Returns the quarantined blocks to the free list, checking them.
+    #[cfg(debug_assertions)]
+    unsafe fn drain_quarantine(&mut self) -> core::result::Result<(), poison::Corruption> {
+        while let Some((addr, size)) = self.quarantine.pop() {
+            self.release(addr, size)?;
+        }
+        Ok(())
+    }
+    #[cfg(not(debug_assertions))]
+    unsafe fn alloc(&mut self, size: usize, align: usize) -> *mut u8 {
+        self.take(size, align)
+    }
+    #[cfg(not(debug_assertions))]
+    unsafe fn dealloc(&mut self, addr: usize, size: usize) {
+        if self.poison {
+            poison::fill(addr, size);
+        }
+        self.give(addr, size);
+    }
+}
+
+pub struct FirstFitAllocator {
+    heap: Mutex<Heap>,
+    total_bytes: AtomicUsize,
+}
+
+#[cfg_attr(any(target_os = "uefi", target_os = "none"), global_allocator)]
+pub static ALLOCATOR: FirstFitAllocator = FirstFitAllocator {
+    heap: Mutex::new(Heap {
+        free_list: FreeList { head: null_mut() },
+        slabs: slab_caches(),
+        counters: Counters::new(),
+        // デバッグビルドでは解放後の書き込みの検出に使うので既定で有効
+        poison: cfg!(debug_assertions),
+        #[cfg(debug_assertions)]
+        quarantine: poison::Quarantine::new(),
+        #[cfg(debug_assertions)]
+        redzone_size: redzone::DEFAULT_SIZE,
+    }),
+    total_bytes: AtomicUsize::new(0),
+};
+
+impl FirstFitAllocator {
+    /// Allocates the largest conventional memory region from the firmware
+    /// with AllocatePages and makes it the kernel heap, so that the
+    /// firmware does not hand it out while the boot services are alive.
+    pub fn init_with_boot_services(
+        &self,
+        boot_services: &EfiBootServicesTable,
+        memory_map: &MemoryMapHolder,
+    ) -> Result<()> {
+        let e = memory_map
+            .iter()
+            .filter(|e| e.memory_type == EfiMemoryType::CONVENTIONAL_MEMORY)
+            .max_by_key(|e| e.number_of_pages)
+            .ok_or("No conventional memory")?;
+        let pages = e.number_of_pages as usize;
+        let start = boot_services.allocate_pages(
+            AllocateType::Address(e.physical_start),
+            EfiMemoryType::LOADER_DATA,
+            pages,
+        )?;
+        // SAFETY: the pages are allocated for the heap and not used by the firmware
+        unsafe {
+            self.add_region(start as usize, pages * 4096);
+        }
+        Ok(())
+    }
+    /// Makes the largest conventional memory region the kernel heap,
+    /// without telling the firmware.
+    pub fn init_with_mmap(&self, memory_map: &MemoryMapHolder) {
+        let region = memory_map
+            .iter()
+            .filter(|e| e.memory_type == EfiMemoryType::CONVENTIONAL_MEMORY)
+            .max_by_key(|e| e.number_of_pages);
+        if let Some(e) = region {
+            // SAFETY: conventional memory is not used by anyone else at this point.
+            unsafe {
+                self.add_region(e.physical_start as usize, e.number_of_pages as usize * 4096);
+            }
+        }
+    }
+    /// Adds a memory region to the heap.
+    ///
+    /// # Safety
+    /// The region must be mapped, writable and not used by anyone else.
+    pub unsafe fn add_region(&self, start: usize, size: usize) {
+        self.heap.lock().free_list.add_region(start, size);
+        self.total_bytes.fetch_add(size, Ordering::Relaxed);
+    }
+    pub fn free_bytes(&self) -> usize {
+        self.heap.lock().free_list.free_stats().0
+    }
+    /// The size of all the regions given to the heap.
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes.load(Ordering::Relaxed)
+    }
+    /// Enables or disables delaying the reuse of freed blocks (debug builds only).
+    ///
+    /// Freed memory is poisoned and verified before reuse either way, but
+    /// the quarantine widens the window in which a use-after-free is caught.
+    #[cfg(debug_assertions)]
+    pub fn set_quarantine_enabled(&self, enabled: bool) {
+        let mut heap = self.heap.lock();
+        heap.quarantine.enabled = enabled;
+        if !enabled {
+            // SAFETY: quarantined blocks are owned by the allocator.
+            if let Err(e) = unsafe { heap.drain_quarantine() } {
+                drop(heap);
+                report_corruption(e);
+            }
+        }
+    }
+    /// Enables or disables filling freed blocks with a pattern, so that
+    /// reads through dangling pointers stand out. In debug builds it is on
+    /// by default, and the pattern is checked before a block is reused.
+    pub fn set_poison_enabled(&self, enabled: bool) {
+        let mut heap = self.heap.lock();
+        // 隔離中のブロックは毒が入っている前提で検査されるので先に戻す
+        #[cfg(debug_assertions)]
+        if !enabled {
+            // SAFETY: quarantined blocks are owned by the allocator.
+            if let Err(e) = unsafe { heap.drain_quarantine() } {
+                drop(heap);
+                report_corruption(e);
+            }
+        }
+        heap.poison = enabled;
+    }
+    pub fn stats(&self) -> HeapStats {
+        let heap = self.heap.lock();
+        let (free_bytes, free_blocks, largest_free_block) = heap.free_list.free_stats();
+        HeapStats {
+            total_bytes: self.total_bytes(),
+            free_bytes,
+            free_blocks,
+            largest_free_block,
+            poison: heap.poison,
+            counters: heap.counters,
+            slabs: core::array::from_fn(|i| SlabStats {
+                object_size: heap.slabs[i].object_size,
+                slabs: heap.slabs[i].slabs,
+                live: heap.slabs[i].live,
+            }),
+        }
+    }
+    /// Sets the number of guard bytes placed after each new allocation
+    /// (debug builds only). Allocations that are already live keep theirs.
+    #[cfg(debug_assertions)]
+    pub fn set_redzone_size(&self, size: usize) {
+        self.heap.lock().redzone_size = round_up(size, BLOCK_UNIT).min(redzone::MAX_SIZE);
+    }
+}
+
+#[cfg(debug_assertions)]
+fn report_corruption(e: poison::Corruption) -> ! {
+    panic!(
+        "heap: use-after-free detected: {:#X} (offset {} of freed block {:#X}, {} bytes) was modified after free",
+        e.addr,
+        e.addr - e.block,
+        e.block,
+        e.size
+    );
+}
+
+#[cfg(debug_assertions)]
+fn report_violation(v: redzone::Violation) -> ! {
+    match v {
+        redzone::Violation::Header { ptr } => {
+            panic!("heap: redzone header of {ptr:#X} is broken (underflow or invalid free)")
+        }
+        redzone::Violation::SizeMismatch { ptr, size, expected } => panic!(
+            "heap: {ptr:#X} was allocated with {expected} bytes but freed with {size} bytes"
+        ),
+        redzone::Violation::Underflow { ptr, addr } => panic!(
+            "heap: buffer underflow detected: {addr:#X} ({} bytes before {ptr:#X}) was modified",
+            ptr - addr
+        ),
+        redzone::Violation::Overflow { ptr, size, addr } => panic!(
+            "heap: buffer overflow detected: {addr:#X} ({} bytes past the end of {ptr:#X}, {size} bytes) was modified",
+            addr - (ptr + size)
+        ),
+    }
+}
+
+/// Guard bytes around each allocation, checked when it is freed.
+///
+/// An allocation is laid out as follows:
+///
+/// [front redzone][header: 16 bytes][user data][back redzone]
+///
+/// The header just before the user data records the layout so that the
+/// block can be found again even if the redzone size has changed since.
+#[cfg(debug_assertions)]
+mod redzone {
+    use super::round_up;
+    use super::BLOCK_UNIT;
+
+    pub const DEFAULT_SIZE: usize = 16;
+    pub const MAX_SIZE: usize = 4096;
+    const BYTE: u8 = 0xfd;
+    const MAGIC: u64 = 0x5a5a_a5a5_c3c3_3c3c;
+    const HEADER_SIZE: usize = 16;
+
+    pub enum Violation {
+        Header {
+            ptr: usize,
+        },
+        SizeMismatch {
+            ptr: usize,
+            size: usize,
+            expected: usize,
+        },
+        Underflow {
+            ptr: usize,
+            addr: usize,
+        },
+        Overflow {
+            ptr: usize,
+            size: usize,
+            addr: usize,
+        },
+    }
+
+    /// Returns (offset of user data, total block size).
+    pub fn frame(size: usize, align: usize, redzone: usize) -> (usize, usize) {
+        let front = round_up(redzone + HEADER_SIZE, align);
+        let total = front + round_up(size + redzone, BLOCK_UNIT);
+        (front, total)
+    }
+
+    unsafe fn fill(begin: usize, end: usize) {
+        core::ptr::write_bytes(begin as *mut u8, BYTE, end - begin);
+    }
+
+    fn find_broken(begin: usize, end: usize) -> Option<usize> {
+        // SAFETY: callers only pass ranges inside a block that they own.
+        let bytes = unsafe { core::slice::from_raw_parts(begin as *const u8, end - begin) };
+        bytes.iter().position(|b| *b != BYTE).map(|ofs| begin + ofs)
+    }
+
+    /// Fills the redzones of a newly allocated block and returns the pointer for the user.
+    ///
+    /// # Safety
+    ///
+    /// [block, block + total) must be owned by the caller and (front, total) must come from frame().
+    pub unsafe fn arm(block: usize, size: usize, front: usize, total: usize) -> *mut u8 {
+        let ptr = block + front;
+        let header = (ptr - HEADER_SIZE) as *mut u64;
+        fill(block, ptr - HEADER_SIZE);
+        fill(ptr + size, block + total);
+        header.write((front as u64 | (total as u64) << 32) ^ MAGIC);
+        header.add(1).write(size as u64 ^ MAGIC);
+        ptr as *mut u8
+    }
+
+    /// Verifies the redzones of an allocation being freed and returns (block, total).
+    ///
+    /// # Safety
+    ///
+    /// ptr must be a pointer returned by arm().
+    pub unsafe fn disarm(ptr: usize, size: usize) -> Result<(usize, usize), Violation> {
+        let header = (ptr - HEADER_SIZE) as *const u64;
+        let layout = header.read() ^ MAGIC;
+        let expected = (header.add(1).read() ^ MAGIC) as usize;
+        let front = (layout & 0xffff_ffff) as usize;
+        let total = (layout >> 32) as usize;
+        if front < HEADER_SIZE
+            || front % BLOCK_UNIT != 0
+            || total % BLOCK_UNIT != 0
+            || total < front + expected
+        {
+            return Err(Violation::Header { ptr });
+        }
+        if expected != size {
+            return Err(Violation::SizeMismatch {
+                ptr,
+                size,
+                expected,
+            });
+        }
+        let block = ptr - front;
+        if let Some(addr) = find_broken(block, ptr - HEADER_SIZE) {
+            return Err(Violation::Underflow { ptr, addr });
+        }
+        if let Some(addr) = find_broken(ptr + size, block + total) {
+            return Err(Violation::Overflow { ptr, size, addr });
+        }
+        Ok((block, total))
+    }
+}
+
+#[cfg(not(debug_assertions))]
+fn block_layout(layout: Layout) -> (usize, usize) {
+    let align = layout.align().max(BLOCK_UNIT);
+    // 揃えより小さいブロックは揃えの大きさにして、スラブの大きさの区分と揃えを合わせる
+    let size = round_up(layout.size().max(1), BLOCK_UNIT).max(align);
+    (size, align)
+}
+
+unsafe impl GlobalAlloc for FirstFitAllocator {
+    #[cfg(debug_assertions)]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let align = layout.align().max(BLOCK_UNIT);
+        let mut heap = self.heap.lock();
+        let (front, total) = redzone::frame(layout.size(), align, heap.redzone_size);
+        let result = heap.alloc(total, align);
+        match result {
+            Ok(p) if p.is_null() => heap.counters.failures += 1,
+            Ok(_) => heap.counters.record_alloc(layout.size()),
+            Err(_) => {}
+        }
+        drop(heap);
+        match result {
+            Ok(p) if p.is_null() => p,
+            Ok(p) => redzone::arm(p as usize, layout.size(), front, total),
+            Err(e) => report_corruption(e),
+        }
+    }
+    #[cfg(debug_assertions)]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (block, total) = match redzone::disarm(ptr as usize, layout.size()) {
+            Ok(v) => v,
+            Err(v) => report_violation(v),
+        };
+        let mut heap = self.heap.lock();
+        heap.counters.record_free(layout.size());
+        let result = heap.dealloc(block, total);
+        drop(heap);
+        if let Err(e) = result {
+            report_corruption(e);
+        }
+    }
+    #[cfg(not(debug_assertions))]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let (size, align) = block_layout(layout);
+        let mut heap = self.heap.lock();
+        let p = heap.alloc(size, align);
+        if p.is_null() {
+            heap.counters.failures += 1;
+        } else {
+            heap.counters.record_alloc(layout.size());
+        }
+        p
+    }
+    #[cfg(not(debug_assertions))]
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = block_layout(layout);
+        let mut heap = self.heap.lock();
+        heap.counters.record_free(layout.size());
+        heap.dealloc(ptr as usize, size);
+    }
+}
+
+/// The counters at the last `heap mark`.
+static MARK: Mutex<Option<Counters>> = Mutex::new(None);
+
+fn size_class_name(class: usize) -> String {
+    match size_class_limit(class) {
+        Some(limit) if limit < 1024 => format!("<= {limit} B"),
+        Some(limit) => format!("<= {} KiB", limit / 1024),
+        None => format!("> {} KiB", size_class_limit(class - 1).unwrap_or(0) / 1024),
+    }
+}
+
+fn write_stats(out: &mut dyn fmt::Write, stats: &HeapStats) {
+    let c = &stats.counters;
+    let _ = writeln!(
+        out,
+        "In use: {} (peak {}) in {} allocations",
+        Size(c.in_use as u64),
+        Size(c.peak_in_use as u64),
+        c.live()
+    );
+    let _ = writeln!(
+        out,
+        "Free: {} / {} total, {} blocks, largest {}, fragmentation {}%",
+        Size(stats.free_bytes as u64),
+        Size(stats.total_bytes as u64),
+        stats.free_blocks,
+        Size(stats.largest_free_block as u64),
+        stats.fragmentation_percent()
+    );
+    let _ = writeln!(
+        out,
+        "Allocations: {} made, {} freed, {} failed",
+        c.allocs, c.frees, c.failures
+    );
+    let _ = writeln!(
+        out,
+        "Poisoning: {}",
+        if stats.poison { "on" } else { "off" }
+    );
+    for slab in stats.slabs.iter().filter(|s| s.slabs > 0) {
+        let _ = writeln!(
+            out,
+            "Slab {:>5} B: {} slabs, {} / {} objects",
+            slab.object_size,
+            slab.slabs,
+            slab.live,
+            slab.capacity()
+        );
+    }
+    let _ = writeln!(out, "{:>12} {:>8} {:>10}", "size", "live", "bytes");
+    for (class, s) in c.classes.iter().enumerate() {
+        if s.live > 0 {
+            let _ = writeln!(
+                out,
+                "{:>12} {:>8} {:>10}",
+                size_class_name(class),
+                s.live,
+                s.bytes
+            );
+        }
+    }
+}
+
+/// The `heap` command.
+pub fn cmd_heap(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    match args {
+        [] => write_stats(out, &ALLOCATOR.stats()),
+        ["mark"] => {
+            let counters = ALLOCATOR.stats().counters;
+            let _ = writeln!(out, "Marked {} live allocations", counters.live());
+            *MARK.lock() = Some(counters);
+        }
+        ["leaks"] => {
+            let mark = (*MARK.lock()).ok_or("No mark: run `heap mark` first")?;
+            let growth = ALLOCATOR.stats().counters.growth_since(&mark);
+            if growth.is_empty() {
+                let _ = writeln!(out, "No more live allocations than at the mark");
+            }
+            for (class, g) in growth {
+                let _ = writeln!(
+                    out,
+                    "{:>12} +{} allocations, +{} bytes",
+                    size_class_name(class),
+                    g.live,
+                    g.bytes
+                );
+            }
+        }
+        ["poison", "on"] => ALLOCATOR.set_poison_enabled(true),
+        ["poison", "off"] => ALLOCATOR.set_poison_enabled(false),
+        _ => return Err("usage: heap [mark | leaks | poison on|off]"),
+    }
+    Ok(())
+}
@@ -0,0 +1,341 @@
+//! The APIC topology described by the ACPI MADT.
+//!
+//! init() collects the Local APICs (one per CPU), the IO APICs, and the
+//! interrupt source overrides that tell which GSI (global system interrupt)
+//! each ISA IRQ is wired to. SMP bring-up takes the application processors
+//! from here, and the interrupt routing uses irq_to_gsi().
+//!
+//! The Local APIC of the current CPU is accessed with the functions at the
+//! bottom, in either xAPIC (MMIO) or x2APIC (MSR) mode, whichever the
+//! firmware has chosen.
+
+use crate::acpi::Acpi;
+use crate::acpi::Madt;
+use crate::acpi::MadtEntry;
+use crate::arch::read_msr;
+use crate::arch::write_msr;
+use crate::interrupt::InterruptStackFrame;
+use crate::ioapic;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::smp;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ptr::read_volatile;
+use core::ptr::write_volatile;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cpu {
+    pub processor_uid: u32,
+    pub apic_id: u32,
+    /// False if the firmware reported the processor as disabled but online
+    /// capable; it can be started, but has not been tested.
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u64,
+    /// The first GSI that this IO APIC handles.
+    pub gsi_base: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trigger {
+    Edge,
+    Level,
+}
+
+/// Where an interrupt source is connected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gsi {
+    pub gsi: u32,
+    pub polarity: Polarity,
+    pub trigger: Trigger,
+}
+impl Gsi {
+    /// Decodes the MPS INTI flags, with the defaults of the ISA bus for
+    /// "conforms to the bus".
+    fn from_isa_flags(gsi: u32, flags: u16) -> Self {
+        let polarity = match flags & 0b11 {
+            0b11 => Polarity::ActiveLow,
+            _ => Polarity::ActiveHigh,
+        };
+        let trigger = match (flags >> 2) & 0b11 {
+            0b11 => Trigger::Level,
+            _ => Trigger::Edge,
+        };
+        Self {
+            gsi,
+            polarity,
+            trigger,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    pub irq: u8,
+    pub gsi: Gsi,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ApicInfo {
+    pub local_apic_address: u64,
+    /// The Local APIC ID of the CPU that runs init().
+    pub bsp_apic_id: u32,
+    pub cpus: Vec<Cpu>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptOverride>,
+    /// The machine also has the dual 8259 PICs.
+    pub has_8259: bool,
+}
+impl ApicInfo {
+    pub fn from_madt(madt: &Madt) -> Self {
+        let mut info = Self {
+            local_apic_address: madt.local_apic_address(),
+            bsp_apic_id: local_apic_id(),
+            has_8259: madt.flags() & Madt::FLAG_PCAT_COMPAT != 0,
+            ..Default::default()
+        };
+        for e in madt.entries() {
+            match e {
+                MadtEntry::LocalApic {
+                    processor_uid,
+                    apic_id,
+                    flags,
+                } => {
+                    if flags & (MadtEntry::LAPIC_ENABLED | MadtEntry::LAPIC_ONLINE_CAPABLE) == 0 {
+                        continue;
+                    }
+                    // x2APICの構造体が同じCPUを重ねて列挙することがある
+                    if info.cpus.iter().any(|c| c.apic_id == apic_id) {
+                        continue;
+                    }
+                    info.cpus.push(Cpu {
+                        processor_uid,
+                        apic_id,
+                        enabled: flags & MadtEntry::LAPIC_ENABLED != 0,
+                    });
+                }
+                MadtEntry::IoApic {
+                    id,
+                    address,
+                    gsi_base,
+                } => info.io_apics.push(IoApic {
+                    id,
+                    address,
+                    gsi_base,
+                }),
+                MadtEntry::InterruptSourceOverride {
+                    bus: 0,
+                    source,
+                    gsi,
+                    flags,
+                } => info.overrides.push(InterruptOverride {
+                    irq: source,
+                    gsi: Gsi::from_isa_flags(gsi, flags),
+                }),
+                MadtEntry::LocalApicAddressOverride { address } => {
+                    info.local_apic_address = address
+                }
+                _ => {}
+            }
+        }
+        info.io_apics.sort_by_key(|a| a.gsi_base);
+        info
+    }
+    /// The GSI that an ISA IRQ is connected to. Without an override, IRQ n
+    /// is GSI n, active high and edge triggered.
+    pub fn irq_to_gsi(&self, irq: u8) -> Gsi {
+        self.overrides
+            .iter()
+            .find(|o| o.irq == irq)
+            .map(|o| o.gsi)
+            .unwrap_or(Gsi::from_isa_flags(irq as u32, 0))
+    }
+    /// The IO APIC that handles the GSI.
+    pub fn io_apic_for(&self, gsi: u32) -> Option<&IoApic> {
+        self.io_apics.iter().rev().find(|a| a.gsi_base <= gsi)
+    }
+    /// The CPUs other than the one that runs init().
+    pub fn application_processors(&self) -> impl Iterator<Item = &Cpu> {
+        self.cpus.iter().filter(|c| c.apic_id != self.bsp_apic_id)
+    }
+}
+
+static INFO: Mutex<Option<ApicInfo>> = Mutex::new(None);
+
+/// Reads the MADT. Returns the number of CPUs.
+pub fn init(acpi: &Acpi) -> Result<usize> {
+    let madt = acpi.madt().ok_or("MADT not found")?;
+    let info = ApicInfo::from_madt(madt);
+    if info.cpus.is_empty() {
+        return Err("No Local APIC in the MADT");
+    }
+    let n = info.cpus.len();
+    *INFO.lock() = Some(info);
+    Ok(n)
+}
+
+/// Returns the topology if init() has succeeded.
+pub fn info() -> Option<ApicInfo> {
+    INFO.lock().clone()
+}
+
+/// The GSI of an ISA IRQ. Identity mapped if the MADT is not available.
+pub fn irq_to_gsi(irq: u8) -> Gsi {
+    match INFO.lock().as_ref() {
+        Some(info) => info.irq_to_gsi(irq),
+        None => Gsi::from_isa_flags(irq as u32, 0),
+    }
+}
+
+/// The `cpus` command.
+pub fn cmd_cpus(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    if !args.is_empty() {
+        return Err("usage: cpus");
+    }
+    let info = info().ok_or("The MADT is not available")?;
+    let _ = writeln!(out, "Local APIC at {:#x}", info.local_apic_address);
+    for c in &info.cpus {
+        let _ = writeln!(
+            out,
+            "CPU {:3}: APIC ID {:3}{}{}{}",
+            c.processor_uid,
+            c.apic_id,
+            if c.apic_id == info.bsp_apic_id {
+                " (BSP)"
+            } else {
+                ""
+            },
+            if c.enabled { "" } else { " (online capable)" },
+            if smp::is_online(c.apic_id) {
+                " online"
+            } else {
+                ""
+            }
+        );
+    }
+    for a in &info.io_apics {
+        let _ = writeln!(
+            out,
+            "IO APIC {:3}: {:#x}, GSI base {}",
+            a.id, a.address, a.gsi_base
+        );
+    }
+    let _ = writeln!(
+        out,
+        "IRQs through {}",
+        if ioapic::is_active() {
+            "the IO APIC"
+        } else {
+            "the 8259 PICs"
+        }
+    );
+    for o in &info.overrides {
+        let _ = writeln!(
+            out,
+            "IRQ {:2} -> GSI {:2} {:?} {:?}",
+            o.irq, o.gsi.gsi, o.gsi.polarity, o.gsi.trigger
+        );
+    }
+    Ok(())
+}
+
+const MSR_APIC_BASE: u32 = 0x1b;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+const REG_ID: usize = 0x20;
+const REG_EOI: usize = 0xb0;
+const REG_SVR: usize = 0xf0;
+const REG_ICR_LOW: usize = 0x300;
+const REG_ICR_HIGH: usize = 0x310;
+
+const SVR_ENABLE: u32 = 1 << 8;
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+pub const ICR_INIT: u32 = 0b101 << 8 | 1 << 14;
+pub const ICR_STARTUP: u32 = 0b110 << 8 | 1 << 14;
+
+/// The vector of the spurious interrupts of the Local APIC.
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+pub extern "x86-interrupt" fn spurious_handler(_frame: InterruptStackFrame) {
+    // スプリアス割り込みにはEOIを送らない
+}
+
+fn apic_base() -> u64 {
+    // SAFETY: IA32_APIC_BASE exists on every x86_64 CPU
+    unsafe { read_msr(MSR_APIC_BASE) }
+}
+
+fn is_x2apic() -> bool {
+    apic_base() & APIC_BASE_X2APIC != 0
+}
+
+fn read_local_apic(reg: usize) -> u32 {
+    if is_x2apic() {
+        // SAFETY: the register exists in x2APIC mode
+        unsafe { read_msr(X2APIC_MSR_BASE + (reg >> 4) as u32) as u32 }
+    } else {
+        let base = apic_base() & !0xfff;
+        // SAFETY: the registers are identity mapped at IA32_APIC_BASE
+        unsafe { read_volatile((base as usize + reg) as *const u32) }
+    }
+}
+
+fn write_local_apic(reg: usize, value: u32) {
+    if is_x2apic() {
+        // SAFETY: the register exists in x2APIC mode
+        unsafe { write_msr(X2APIC_MSR_BASE + (reg >> 4) as u32, value as u64) }
+    } else {
+        let base = apic_base() & !0xfff;
+        // SAFETY: the registers are identity mapped at IA32_APIC_BASE
+        unsafe { write_volatile((base as usize + reg) as *mut u32, value) }
+    }
+}
+
+/// The ID of the Local APIC of the current CPU, read from the APIC itself.
+pub fn local_apic_id() -> u32 {
+    match is_x2apic() {
+        true => read_local_apic(REG_ID),
+        false => read_local_apic(REG_ID) >> 24,
+    }
+}
+
+/// Software-enables the Local APIC of the current CPU.
+pub fn enable_local_apic() {
+    write_local_apic(REG_SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+}
+
+pub fn local_apic_eoi() {
+    write_local_apic(REG_EOI, 0);
+}
+
+/// Sends an inter-processor interrupt and waits until it is delivered.
+pub fn send_ipi(apic_id: u32, icr_low: u32) {
+    if is_x2apic() {
+        // x2APICではICRは1つの64ビットMSRで、配送完了を待つ必要もない
+        // SAFETY: the register exists in x2APIC mode
+        unsafe {
+            write_msr(
+                X2APIC_MSR_BASE + (REG_ICR_LOW >> 4) as u32,
+                (apic_id as u64) << 32 | icr_low as u64,
+            )
+        }
+        return;
+    }
+    write_local_apic(REG_ICR_HIGH, apic_id << 24);
+    write_local_apic(REG_ICR_LOW, icr_low);
+    while read_local_apic(REG_ICR_LOW) & ICR_DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,309 @@
+//! Thin wrappers around x86_64 instructions, so that other modules don't
+//! need to write asm! themselves.
+
+// インラインアセンブリを使うための宣言
+use core::arch::asm;
+use core::arch::x86_64::__cpuid;
+
+pub fn hlt() {
+    unsafe {
+        asm!("hlt");
+    }
+}
+
+pub fn cli() {
+    unsafe {
+        asm!("cli");
+    }
+}
+
+pub fn sti() {
+    unsafe {
+        asm!("sti");
+    }
+}
+
+/// Enables interrupts and halts until the next one.
+///
+/// sti takes effect after the next instruction, so an interrupt that is
+/// pending when this is called still wakes the hlt up.
+pub fn sti_and_hlt() {
+    unsafe {
+        asm!("sti; hlt");
+    }
+}
+
+const RFLAGS_IF: u64 = 1 << 9;
+
+pub fn read_rflags() -> u64 {
+    let mut rflags: u64;
+    unsafe {
+        asm!("pushfq; pop {}", out(reg) rflags);
+    }
+    rflags
+}
+
+pub fn interrupts_enabled() -> bool {
+    read_rflags() & RFLAGS_IF != 0
+}
+
+/// Disables interrupts until dropped, then restores the previous state.
+///
+/// Nesting is fine since only the outermost guard re-enables interrupts.
+pub struct InterruptGuard {
+    was_enabled: bool,
+}
+impl InterruptGuard {
+    pub fn new() -> Self {
+        let was_enabled = interrupts_enabled();
+        cli();
+        Self { was_enabled }
+    }
+}
+impl Default for InterruptGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        if self.was_enabled {
+            sti();
+        }
+    }
+}
+
+/// Runs f with interrupts disabled.
+pub fn without_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    let _guard = InterruptGuard::new();
+    f()
+}
+
+pub fn read_io_port_u8(port: u16) -> u8 {
+    let mut data: u8;
+    unsafe {
+        asm!("in al, dx",
+            out("al") data,
+            in("dx") port)
+    }
+    data
+}
+
+pub fn write_io_port_u8(port: u16, data: u8) {
+    unsafe {
+        asm!("out dx, al",
+            in("al") data,
+            in("dx") port)
+    }
+}
+
+/// Reads the time stamp counter.
+pub fn rdtsc() -> u64 {
+    let lo: u32;
+    let hi: u32;
+    unsafe {
+        asm!("rdtsc", out("eax") lo, out("edx") hi);
+    }
+    (hi as u64) << 32 | lo as u64
+}
+
+const CPUID1_ECX_RDRAND: u32 = 1 << 30;
+
+/// Returns a random number from RDRAND, or None if the CPU does not have
+/// it or it keeps failing.
+pub fn rdrand() -> Option<u64> {
+    // SAFETY: CPUID is always available on x86_64
+    if unsafe { __cpuid(1) }.ecx & CPUID1_ECX_RDRAND == 0 {
+        return None;
+    }
+    // Intelは10回続けて失敗したら諦めるよう勧めている
+    for _ in 0..10 {
+        let value: u64;
+        let ok: u8;
+        // SAFETY: RDRAND is supported and only writes the registers
+        unsafe {
+            asm!("rdrand {}", "setc {}", out(reg) value, out(reg_byte) ok, options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+pub fn read_io_port_u16(port: u16) -> u16 {
+    let mut data: u16;
+    unsafe {
+        asm!("in ax, dx",
+            out("ax") data,
+            in("dx") port)
+    }
+    data
+}
+
+pub fn write_io_port_u16(port: u16, data: u16) {
+    unsafe {
+        asm!("out dx, ax",
+            in("ax") data,
+            in("dx") port)
+    }
+}
+
+pub fn read_io_port_u32(port: u16) -> u32 {
+    let mut data: u32;
+    unsafe {
+        asm!("in eax, dx",
+            out("eax") data,
+            in("dx") port)
+    }
+    data
+}
+
+pub fn write_io_port_u32(port: u16, data: u32) {
+    unsafe {
+        asm!("out dx, eax",
+            in("eax") data,
+            in("dx") port)
+    }
+}
+
+/// # Safety
+/// Reading an MSR that the CPU does not implement raises #GP.
+pub unsafe fn read_msr(msr: u32) -> u64 {
+    let lo: u32;
+    let hi: u32;
+    asm!("rdmsr", in("ecx") msr, out("eax") lo, out("edx") hi);
+    (hi as u64) << 32 | lo as u64
+}
+
+/// # Safety
+/// MSRs control the CPU itself, and unimplemented ones raise #GP.
+pub unsafe fn write_msr(msr: u32, value: u64) {
+    asm!("wrmsr",
+        in("ecx") msr,
+        in("eax") value as u32,
+        in("edx") (value >> 32) as u32)
+}
+
+/// Operand of lgdt/lidt and friends.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DescriptorTablePointer {
+    pub limit: u16,
+    pub base: u64,
+}
+
+pub fn read_gdtr() -> DescriptorTablePointer {
+    let mut gdtr = DescriptorTablePointer::default();
+    unsafe {
+        asm!("sgdt [{}]", in(reg) &mut gdtr);
+    }
+    gdtr
+}
+
+/// # Safety
+/// gdtr must point to a valid GDT that lives forever.
+pub unsafe fn load_gdtr(gdtr: &DescriptorTablePointer) {
+    asm!("lgdt [{}]", in(reg) gdtr);
+}
+
+pub fn read_idtr() -> DescriptorTablePointer {
+    let mut idtr = DescriptorTablePointer::default();
+    unsafe {
+        asm!("sidt [{}]", in(reg) &mut idtr);
+    }
+    idtr
+}
+
+/// # Safety
+/// idtr must point to a valid IDT that lives forever.
+pub unsafe fn load_idtr(idtr: &DescriptorTablePointer) {
+    asm!("lidt [{}]", in(reg) idtr);
+}
+
+/// # Safety
+/// selector must refer to an available TSS descriptor in the current GDT.
+pub unsafe fn load_task_register(selector: u16) {
+    asm!("ltr {0:x}", in(reg) selector);
+}
+
+pub fn read_cs() -> u16 {
+    let mut cs: u16;
+    unsafe {
+        asm!("mov {0:x}, cs", out(reg) cs);
+    }
+    cs
+}
+
+pub fn read_cr0() -> u64 {
+    let mut cr0: u64;
+    unsafe {
+        asm!("mov {}, cr0", out(reg) cr0);
+    }
+    cr0
+}
+
+/// # Safety
+/// Changing CR0 can disable paging or protection.
+pub unsafe fn write_cr0(cr0: u64) {
+    asm!("mov cr0, {}", in(reg) cr0);
+}
+
+pub fn read_cr4() -> u64 {
+    let mut cr4: u64;
+    unsafe {
+        asm!("mov {}, cr4", out(reg) cr4);
+    }
+    cr4
+}
+
+/// # Safety
+/// Changing CR4 affects paging and which instructions are allowed.
+pub unsafe fn write_cr4(cr4: u64) {
+    asm!("mov cr4, {}", in(reg) cr4);
+}
+
+/// # Safety
+/// CR4.OSXSAVE must be set and value must be a valid XCR0 for this CPU.
+pub unsafe fn write_xcr0(value: u64) {
+    asm!("xsetbv",
+        in("ecx") 0,
+        in("eax") value as u32,
+        in("edx") (value >> 32) as u32)
+}
+
+pub fn read_cr3() -> u64 {
+    let mut cr3: u64;
+    unsafe {
+        asm!("mov {}, cr3", out(reg) cr3);
+    }
+    cr3
+}
+
+/// # Safety
+/// cr3 must point to a valid PML4 that maps the running code.
+pub unsafe fn write_cr3(cr3: u64) {
+    asm!("mov cr3, {}", in(reg) cr3);
+}
+
+/// Flushes the TLB entry of the page containing addr.
+pub fn invlpg(addr: u64) {
+    unsafe {
+        asm!("invlpg [{}]", in(reg) addr);
+    }
+}
+
+/// Writes back and evicts the cache line containing addr.
+pub fn clflush(addr: u64) {
+    unsafe {
+        asm!("clflush [{}]", in(reg) addr);
+    }
+}
+
+pub fn read_cr2() -> u64 {
+    let mut cr2: u64;
+    unsafe {
+        asm!("mov {}, cr2", out(reg) cr2);
+    }
+    cr2
+}
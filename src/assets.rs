@@ -0,0 +1,141 @@
+//! Files under assets/ packed by build.rs.
+
+use crate::result::Result;
+use alloc::borrow::Cow;
+use alloc::vec::Vec;
+
+static BUNDLE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/assets.bin"));
+
+const MAGIC: &[u8; 4] = b"WAB1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Rle,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Asset<'a> {
+    pub path: &'a str,
+    pub compression: Compression,
+    /// Size after decompression.
+    pub size: usize,
+    stored: &'a [u8],
+}
+impl<'a> Asset<'a> {
+    pub fn data(&self) -> Result<Cow<'a, [u8]>> {
+        match self.compression {
+            Compression::None => Ok(Cow::Borrowed(self.stored)),
+            Compression::Rle => decompress_rle(self.stored, self.size).map(Cow::Owned),
+        }
+    }
+}
+
+/// Expands PackBits-style RLE data that is expected to be size bytes long.
+pub fn decompress_rle(src: &[u8], size: usize) -> Result<Vec<u8>> {
+    // 1バイトの制御コードは最大128バイトにしか展開されない
+    let mut out = Vec::with_capacity(size.min(src.len().saturating_mul(128)));
+    let mut i = 0;
+    while i < src.len() {
+        let n = src[i] as usize;
+        i += 1;
+        match n {
+            0..=127 => {
+                let literal = src.get(i..i + n + 1).ok_or("Truncated RLE literal")?;
+                out.extend_from_slice(literal);
+                i += n + 1;
+            }
+            128 => return Err("Invalid RLE control byte"),
+            _ => {
+                let b = *src.get(i).ok_or("Truncated RLE run")?;
+                out.resize(out.len() + 257 - n, b);
+                i += 1;
+            }
+        }
+        if out.len() > size {
+            return Err("RLE data is longer than expected");
+        }
+    }
+    if out.len() != size {
+        return Err("RLE data is shorter than expected");
+    }
+    Ok(out)
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let b = self.buf.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(b)
+    }
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+}
+
+/// Parses the index of a bundle. Stops at the first malformed entry.
+pub fn parse_bundle(bundle: &[u8]) -> Vec<Asset<'_>> {
+    let mut r = Reader {
+        buf: bundle,
+        pos: 0,
+    };
+    let mut entries = Vec::new();
+    if r.bytes(4) != Some(MAGIC) {
+        return entries;
+    }
+    let Some(count) = r.u32() else {
+        return entries;
+    };
+    let mut index = Vec::new();
+    for _ in 0..count {
+        let Some(entry) = (|| {
+            let len = r.u16()? as usize;
+            let path = core::str::from_utf8(r.bytes(len)?).ok()?;
+            let compression = match r.bytes(1)?[0] {
+                0 => Compression::None,
+                1 => Compression::Rle,
+                _ => return None,
+            };
+            Some((path, compression, r.u32()?, r.u32()?, r.u32()?))
+        })() else {
+            break;
+        };
+        index.push(entry);
+    }
+    let data = &bundle[r.pos..];
+    for (path, compression, offset, stored_len, size) in index {
+        let Some(stored) =
+            data.get(offset as usize..(offset as usize).saturating_add(stored_len as usize))
+        else {
+            break;
+        };
+        entries.push(Asset {
+            path,
+            compression,
+            size: size as usize,
+            stored,
+        });
+    }
+    entries
+}
+
+/// Lists all the embedded assets.
+pub fn list() -> Vec<Asset<'static>> {
+    parse_bundle(BUNDLE)
+}
+
+pub fn find(path: &str) -> Option<Asset<'static>> {
+    list().into_iter().find(|a| a.path == path)
+}
+
+/// Returns the contents of the asset at path (e.g. "fonts/font.txt").
+pub fn get(path: &str) -> Option<Cow<'static, [u8]>> {
+    find(path)?.data().ok()
+}
@@ -0,0 +1,190 @@
+//! Benchmarks of the drawing code, measured in TSC cycles.
+//!
+//! The primitives draw into off-screen buffers of SIZE, so the screen is
+//! not disturbed, except for the console scroll, which scrolls the real
+//! console (and its VRAM) as many times as it has rows.
+
+use crate::arch::rdtsc;
+use crate::console;
+use crate::font;
+use crate::graphics::draw_font_fg;
+use crate::graphics::fill_rect;
+use crate::graphics::BackBuffer;
+use crate::perf::cycles_to_us;
+use crate::perf::RegionStats;
+use crate::result::Result;
+use alloc::vec::Vec;
+use core::fmt;
+use core::hint::black_box;
+
+/// The size of the off-screen buffers, a common screen size.
+const SIZE: (i64, i64) = (1024, 768);
+
+struct Bench {
+    name: &'static str,
+    run: fn() -> Result<RegionStats>,
+}
+
+const BENCHES: &[Bench] = &[
+    Bench {
+        name: "fill_rect_full",
+        run: bench_fill_rect_full,
+    },
+    Bench {
+        name: "fill_rect_16",
+        run: bench_fill_rect_small,
+    },
+    Bench {
+        name: "blit_full",
+        run: bench_blit,
+    },
+    Bench {
+        name: "glyph_font_txt",
+        run: bench_glyph_font_txt,
+    },
+    Bench {
+        name: "glyph",
+        run: bench_glyph,
+    },
+    Bench {
+        name: "console_scroll",
+        run: bench_console_scroll,
+    },
+];
+
+/// Runs f `iterations` times and returns the cycles spent in each run.
+fn measure(name: &'static str, iterations: u64, mut f: impl FnMut(u64)) -> RegionStats {
+    let mut stats = RegionStats {
+        name,
+        count: 0,
+        total_cycles: 0,
+        min_cycles: u64::MAX,
+        max_cycles: 0,
+    };
+    for i in 0..iterations {
+        let start = rdtsc();
+        f(i);
+        let cycles = rdtsc() - start;
+        stats.count += 1;
+        stats.total_cycles += cycles;
+        stats.min_cycles = stats.min_cycles.min(cycles);
+        stats.max_cycles = stats.max_cycles.max(cycles);
+    }
+    stats
+}
+
+// 最適化で描画が消えないよう、描き先はblack_box()に通す
+// 最初の1回は範囲の確認とキャッシュを温めるために計測の外で描く
+fn bench_fill_rect_full() -> Result<RegionStats> {
+    let (w, h) = SIZE;
+    let mut buf = BackBuffer::new(w, h);
+    fill_rect(&mut buf, 0, 0, 0, w, h)?;
+    Ok(measure("fill_rect_full", 32, |i| {
+        let _ = fill_rect(black_box(&mut buf), i as u32, 0, 0, w, h);
+    }))
+}
+
+fn bench_fill_rect_small() -> Result<RegionStats> {
+    let (w, h) = SIZE;
+    let (columns, rows) = (w / 16, h / 16);
+    let mut buf = BackBuffer::new(w, h);
+    fill_rect(&mut buf, 0, 0, 0, 16, 16)?;
+    Ok(measure("fill_rect_16", 4096, |i| {
+        let (x, y) = (i as i64 % columns, i as i64 / columns % rows);
+        let _ = fill_rect(black_box(&mut buf), 0xffffff, x * 16, y * 16, 16, 16);
+    }))
+}
+
+fn bench_blit() -> Result<RegionStats> {
+    let (w, h) = SIZE;
+    let mut src = BackBuffer::new(w, h);
+    let mut dst = BackBuffer::new(w, h);
+    src.add_damage_all();
+    src.flush(&mut dst)?;
+    Ok(measure("blit_full", 32, |_| {
+        src.add_damage_all();
+        let _ = src.flush(black_box(&mut dst));
+    }))
+}
+
+/// A glyph looked up in font.txt each time, as graphics::draw_font_fg().
+fn bench_glyph_font_txt() -> Result<RegionStats> {
+    let mut buf = BackBuffer::new(SIZE.0, SIZE.1);
+    Ok(measure("glyph_font_txt", 1024, |i| {
+        let c = (b'!' + (i % 94) as u8) as char;
+        draw_font_fg(black_box(&mut buf), (i as i64 % 128) * 8, 0, 0xffffff, c);
+    }))
+}
+
+/// A glyph of the console font, as drawn by the console.
+fn bench_glyph() -> Result<RegionStats> {
+    let mut buf = BackBuffer::new(SIZE.0, SIZE.1);
+    let id = console::font();
+    Ok(measure("glyph", 4096, |i| {
+        let c = (b'!' + (i % 94) as u8) as char;
+        font::draw_char(
+            black_box(&mut buf),
+            id,
+            (i as i64 % 64) * 16,
+            0,
+            0xffffff,
+            c,
+        );
+    }))
+}
+
+fn bench_console_scroll() -> Result<RegionStats> {
+    let (_, rows) = console::size().ok_or("The console is not shown")?;
+    let mut shown = true;
+    let stats = measure("console_scroll", rows as u64, |_| {
+        shown &= console::scroll_up();
+    });
+    if shown {
+        Ok(stats)
+    } else {
+        Err("The console is not shown")
+    }
+}
+
+/// The `bench` command: runs the benchmarks (or the named ones) and shows
+/// the cycles per iteration.
+pub fn cmd_bench(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let benches: Vec<&Bench> = BENCHES
+        .iter()
+        .filter(|b| args.is_empty() || args.contains(&b.name))
+        .collect();
+    if benches.is_empty() {
+        let names: Vec<&str> = BENCHES.iter().map(|b| b.name).collect();
+        let _ = writeln!(out, "benchmarks: {}", names.join(" "));
+        return Err("usage: bench [NAME...]");
+    }
+    // 結果の表示でコンソールがスクロールしないよう、全部測ってから表示する
+    let results: Vec<(&str, Result<RegionStats>)> =
+        benches.iter().map(|b| (b.name, (b.run)())).collect();
+    let _ = writeln!(
+        out,
+        "{:<16} {:>6} {:>12} {:>12} {:>12} {:>10}",
+        "BENCH", "ITERS", "MIN(cyc)", "AVG(cyc)", "MAX(cyc)", "AVG(us)"
+    );
+    for (name, result) in results {
+        match result {
+            Ok(s) => {
+                let avg = s.total_cycles / s.count.max(1);
+                let _ = writeln!(
+                    out,
+                    "{:<16} {:>6} {:>12} {:>12} {:>12} {:>10}",
+                    name,
+                    s.count,
+                    s.min_cycles,
+                    avg,
+                    s.max_cycles,
+                    cycles_to_us(avg)
+                );
+            }
+            Err(e) => {
+                let _ = writeln!(out, "{name:<16} {e}");
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! The second stage of the two-stage boot: the kernel as an ELF, started by
+//! wasabi-loader (src/bin/loader.rs) after it has exited the boot services.
+//! Build with:
+//!
+//! cargo build --target x86_64-unknown-none --features kernel_elf --bin wasabi-kernel
+
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use wasabi::acpi::Acpi;
+use wasabi::allocator::ALLOCATOR;
+use wasabi::arch::cli;
+use wasabi::arch::hlt;
+use wasabi::arch::sti;
+use wasabi::arch::sti_and_hlt;
+use wasabi::boot_info::BootInfo;
+use wasabi::boot_info::MemoryKind;
+use wasabi::cmdline;
+use wasabi::gdt;
+use wasabi::graphics::draw_str_fg;
+use wasabi::graphics::fill_rect;
+use wasabi::hpet;
+use wasabi::interrupt;
+use wasabi::pic;
+use wasabi::println;
+use wasabi::serial::SerialPort;
+use wasabi::serial_console::SerialConsole;
+use wasabi::time;
+use wasabi::uefi::VramBefferInfo;
+use wasabi::version;
+
+#[no_mangle]
+extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    SerialPort::default().init();
+    println!("{} (kernel ELF)", version::version());
+    boot_info.check().expect("Bad BootInfo");
+    let region = boot_info
+        .memory_map()
+        .iter()
+        .filter(|r| r.kind == MemoryKind::Usable)
+        .max_by_key(|r| r.pages)
+        .expect("No usable memory");
+    // SAFETY: usable memory is not used by anyone and is mapped 1:1
+    unsafe {
+        ALLOCATOR.add_region(region.base as usize, region.size() as usize);
+    }
+    gdt::init();
+    interrupt::init();
+    pic::init();
+    match boot_info
+        .rsdp()
+        .ok_or("No RSDP")
+        .and_then(|rsdp| unsafe { Acpi::from_rsdp(rsdp) })
+        .and_then(|acpi| hpet::init(&acpi))
+    {
+        Ok(hpet) => println!("HPET: {} MHz", hpet.frequency_hz() / 1_000_000),
+        Err(e) => println!("HPET unavailable: {e}"),
+    }
+    match time::init() {
+        Ok(reference) => println!("TSC: {} MHz ({reference})", time::tsc_hz() / 1_000_000),
+        Err(e) => println!("TSC calibration failed: {e}"),
+    }
+    cmdline::set(boot_info.cmdline());
+    println!("Command line: {}", boot_info.cmdline());
+    if let Some(initramfs) = boot_info.initramfs() {
+        println!("initramfs: {} bytes", initramfs.len());
+    }
+    let fb = boot_info.framebuffer;
+    if fb.base != 0 {
+        // SAFETY: the framebuffer is the one the firmware set up, mapped 1:1
+        let mut vram = unsafe {
+            VramBefferInfo::from_raw(
+                fb.base as *mut u8,
+                fb.width as i64,
+                fb.height as i64,
+                fb.pixels_per_line as i64,
+            )
+        };
+        let (w, h) = (vram.width, vram.height);
+        let _ = fill_rect(&mut vram, 0x000000, 0, 0, w, h);
+        draw_str_fg(&mut vram, 16, 16, 0xffffff, "Hello from wasabi-loader!");
+    }
+
+    let mut console = SerialConsole::new(SerialPort::default());
+    console
+        .enable_interrupt()
+        .expect("Failed to enable the serial interrupt");
+    console.start();
+    loop {
+        console.poll();
+        cli();
+        if SerialPort::default().has_data() {
+            sti();
+        } else {
+            sti_and_hlt();
+        }
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    println!("PANIC: {info}");
+    loop {
+        hlt()
+    }
+}
@@ -0,0 +1,99 @@
+//! Entry point for booting with a Limine-compatible bootloader instead of
+//! as a UEFI application. Build with:
+//!
+//! cargo build --target x86_64-unknown-none --features limine --bin wasabi-limine
+
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use wasabi::acpi::Acpi;
+use wasabi::allocator::ALLOCATOR;
+use wasabi::arch::cli;
+use wasabi::arch::hlt;
+use wasabi::arch::sti;
+use wasabi::arch::sti_and_hlt;
+use wasabi::gdt;
+use wasabi::graphics::draw_str_fg;
+use wasabi::graphics::fill_rect;
+use wasabi::hpet;
+use wasabi::interrupt;
+use wasabi::limine;
+use wasabi::limine::MemoryType;
+use wasabi::pic;
+use wasabi::println;
+use wasabi::serial::SerialPort;
+use wasabi::serial_console::SerialConsole;
+use wasabi::time;
+use wasabi::uefi::VramBefferInfo;
+use wasabi::version;
+
+#[no_mangle]
+extern "C" fn _start() -> ! {
+    SerialPort::default().init();
+    println!("{} (limine)", version::version());
+    let hhdm = limine::hhdm_offset().expect("HHDM response is missing");
+    let region = limine::memory_map()
+        .filter(|e| e.memory_type == MemoryType::Usable)
+        .max_by_key(|e| e.length)
+        .expect("No usable memory");
+    // SAFETY: usable memory is not used by anyone and is mapped at the HHDM
+    unsafe {
+        ALLOCATOR.add_region((region.base + hhdm) as usize, region.length as usize);
+    }
+    gdt::init();
+    interrupt::init();
+    pic::init();
+    match limine::rsdp()
+        .ok_or("RSDP response is missing")
+        .and_then(|rsdp| unsafe { Acpi::from_rsdp(rsdp) })
+        .and_then(|acpi| hpet::init(&acpi))
+    {
+        Ok(hpet) => println!("HPET: {} MHz", hpet.frequency_hz() / 1_000_000),
+        Err(e) => println!("HPET unavailable: {e}"),
+    }
+    match time::init() {
+        Ok(reference) => println!("TSC: {} MHz ({reference})", time::tsc_hz() / 1_000_000),
+        Err(e) => println!("TSC calibration failed: {e}"),
+    }
+    for m in limine::modules() {
+        println!("module: {} ({} bytes) {}", m.path, m.data.len(), m.cmdline);
+    }
+    if let Some(fb) = limine::framebuffer().filter(|fb| fb.bpp == 32) {
+        // SAFETY: the bootloader maps the framebuffer
+        let mut vram = unsafe {
+            VramBefferInfo::from_raw(
+                fb.address,
+                fb.width as i64,
+                fb.height as i64,
+                (fb.pitch / 4) as i64,
+            )
+        };
+        let (w, h) = (vram.width, vram.height);
+        let _ = fill_rect(&mut vram, 0x000000, 0, 0, w, h);
+        draw_str_fg(&mut vram, 16, 16, 0xffffff, "Hello from Limine!");
+    }
+
+    let mut console = SerialConsole::new(SerialPort::default());
+    console
+        .enable_interrupt()
+        .expect("Failed to enable the serial interrupt");
+    console.start();
+    loop {
+        console.poll();
+        cli();
+        if SerialPort::default().has_data() {
+            sti();
+        } else {
+            sti_and_hlt();
+        }
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    println!("PANIC: {info}");
+    loop {
+        hlt()
+    }
+}
@@ -0,0 +1,57 @@
+//! The first stage of the two-stage boot: a small UEFI application that
+//! loads the kernel ELF (src/bin/kernel.rs) from the ESP and jumps to it.
+//! Build with:
+//!
+//! cargo build --bin wasabi-loader
+
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use wasabi::allocator::ALLOCATOR;
+use wasabi::arch::hlt;
+use wasabi::loader;
+use wasabi::println;
+use wasabi::serial::SerialPort;
+use wasabi::uefi::init_efi_context;
+use wasabi::uefi::AllocateType;
+use wasabi::uefi::EfiHandle;
+use wasabi::uefi::EfiMemoryType;
+use wasabi::uefi::EfiSystemTable;
+use wasabi::version;
+
+/// The heap of the loader, enough to hold the kernel file.
+const HEAP_PAGES: usize = 64 * 1024 * 1024 / 4096;
+
+#[no_mangle]
+fn efi_main(image_handle: EfiHandle, efi_system_table: &'static EfiSystemTable) {
+    SerialPort::default().init();
+    init_efi_context(image_handle, efi_system_table);
+    println!("{} (loader)", version::version());
+    let heap = efi_system_table
+        .boot_services
+        .allocate_pages(
+            AllocateType::AnyPages,
+            EfiMemoryType::LOADER_DATA,
+            HEAP_PAGES,
+        )
+        .expect("Failed to allocate the heap");
+    // SAFETY: the pages are allocated for the heap and not used by the firmware
+    unsafe {
+        ALLOCATOR.add_region(heap as usize, HEAP_PAGES * 4096);
+    }
+    if let Err(e) = loader::boot(efi_system_table, image_handle, loader::KERNEL_PATH) {
+        println!("Failed to boot {}: {e}", loader::KERNEL_PATH);
+    }
+    loop {
+        hlt()
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    println!("PANIC: {info}");
+    loop {
+        hlt()
+    }
+}
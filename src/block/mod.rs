@@ -0,0 +1,143 @@
+//! Block devices.
+//!
+//! Disk drivers register their devices here, and the partitions found on
+//! them are registered as block devices of their own, so the filesystems
+//! find them by name (e.g. vda2 or nvme0p1).
+
+pub mod partition;
+
+use crate::mutex::Mutex;
+use crate::result::Result;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use partition::Partition;
+use partition::PartitionInfo;
+
+pub trait BlockDevice: Send + Sync {
+    fn name(&self) -> &str;
+    /// The size of a block in bytes, usually 512.
+    fn block_size(&self) -> usize;
+    fn block_count(&self) -> u64;
+    fn is_read_only(&self) -> bool {
+        false
+    }
+    /// Reads the blocks from lba. buf.len() must be a multiple of
+    /// block_size().
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()>;
+    /// Writes the blocks from lba. buf.len() must be a multiple of
+    /// block_size().
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()>;
+    fn size(&self) -> u64 {
+        self.block_count() * self.block_size() as u64
+    }
+    /// The entry in the partition table if this is a partition.
+    fn partition(&self) -> Option<&PartitionInfo> {
+        None
+    }
+}
+
+/// Checks that the blocks of buf starting at lba are within the device.
+pub fn check_range(dev: &dyn BlockDevice, lba: u64, len: usize) -> Result<()> {
+    if len % dev.block_size() != 0 {
+        return Err("Buffer is not a multiple of the block size");
+    }
+    let end = lba.checked_add((len / dev.block_size()) as u64);
+    if end.map_or(true, |end| end > dev.block_count()) {
+        return Err("Block is out of range");
+    }
+    Ok(())
+}
+
+static DEVICES: Mutex<Vec<Arc<dyn BlockDevice>>> = Mutex::new(Vec::new());
+
+/// Registers a disk and its partitions.
+pub fn register(dev: Arc<dyn BlockDevice>) {
+    let partitions = match partition::read_table(&*dev) {
+        Ok(partitions) => partitions,
+        Err(e) => {
+            crate::warn!("{}: {e}", dev.name());
+            Vec::new()
+        }
+    };
+    let mut devices = DEVICES.lock();
+    devices.push(dev.clone());
+    for info in partitions {
+        devices.push(Arc::new(Partition::new(dev.clone(), info)));
+    }
+}
+
+pub fn devices() -> Vec<Arc<dyn BlockDevice>> {
+    DEVICES.lock().clone()
+}
+
+pub fn find(name: &str) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES.lock().iter().find(|d| d.name() == name).cloned()
+}
+
+/// The first EFI System Partition found.
+pub fn find_esp() -> Option<Arc<dyn BlockDevice>> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|d| d.partition().map_or(false, |p| p.is_esp()))
+        .cloned()
+}
+
+/// The first name of the form {prefix}a, {prefix}b, ... that is not used.
+pub fn next_name(prefix: &str) -> String {
+    let devices = DEVICES.lock();
+    (b'a'..=b'z')
+        .map(|c| alloc::format!("{prefix}{}", c as char))
+        .find(|name| devices.iter().all(|d| d.name() != name))
+        .unwrap_or_else(|| alloc::format!("{prefix}?"))
+}
+
+pub fn hexdump(out: &mut dyn fmt::Write, data: &[u8]) {
+    for (i, line) in data.chunks(16).enumerate() {
+        let _ = write!(out, "{:04x}:", i * 16);
+        for b in line {
+            let _ = write!(out, " {b:02x}");
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&c| if c.is_ascii_graphic() { c as char } else { '.' })
+            .collect();
+        let _ = writeln!(out, "  {ascii}");
+    }
+}
+
+/// The `blk` command.
+pub fn cmd_blk(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    match args {
+        [] => {
+            for d in devices() {
+                let _ = write!(
+                    out,
+                    "{:8} {:>8} MiB  {} blocks of {} bytes{}",
+                    d.name(),
+                    d.size() >> 20,
+                    d.block_count(),
+                    d.block_size(),
+                    if d.is_read_only() { " (read-only)" } else { "" }
+                );
+                let _ = match d.partition() {
+                    Some(p) => writeln!(out, "  from {}, {}", p.first_lba, p.type_name()),
+                    None => writeln!(out),
+                };
+            }
+            Ok(())
+        }
+        ["read", name, lba] => {
+            let dev = find(name).ok_or("No such block device")?;
+            let lba = lba.parse().map_err(|_| "Invalid LBA")?;
+            let mut buf = vec![0u8; dev.block_size()];
+            dev.read_blocks(lba, &mut buf)?;
+            hexdump(out, &buf);
+            Ok(())
+        }
+        _ => Err("usage: blk [read <device> <lba>]"),
+    }
+}
@@ -0,0 +1,316 @@
+//! MBR and GPT partition tables.
+//!
+//! A disk with a protective MBR (type 0xee) is read as GPT, and otherwise
+//! the four primary MBR entries and the logical partitions in the extended
+//! partition are used. Logical partitions are numbered from 5 as in Linux.
+
+use super::BlockDevice;
+use crate::crc::crc32;
+use crate::result::Result;
+use crate::uefi::EfiGuid;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+const MBR_SIGNATURE: [u8; 2] = [0x55, 0xaa];
+const MBR_TABLE: usize = 446;
+const MBR_TYPE_EMPTY: u8 = 0x00;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+pub const MBR_TYPE_ESP: u8 = 0xef;
+/// Stops walking a broken (e.g. cyclic) chain of extended boot records.
+const MAX_LOGICAL_PARTITIONS: u32 = 128;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+const GPT_MAX_ENTRIES: usize = 1024;
+pub const GPT_TYPE_ESP: EfiGuid = EfiGuid {
+    data0: 0xc12a7328,
+    data1: 0xf81f,
+    data2: 0x11d2,
+    data3: [0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9, 0x3b],
+};
+const GPT_TYPE_BASIC_DATA: EfiGuid = EfiGuid {
+    data0: 0xebd0a0a2,
+    data1: 0xb9e5,
+    data2: 0x4433,
+    data3: [0x87, 0xc0, 0x68, 0xb6, 0xb7, 0x26, 0x99, 0xc7],
+};
+const GPT_TYPE_LINUX: EfiGuid = EfiGuid {
+    data0: 0x0fc63daf,
+    data1: 0x8483,
+    data2: 0x4772,
+    data3: [0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d, 0xe4],
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionType {
+    Mbr(u8),
+    Gpt {
+        type_guid: EfiGuid,
+        unique_guid: EfiGuid,
+        name: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionInfo {
+    /// 1-based, as in vda1.
+    pub number: u32,
+    pub first_lba: u64,
+    pub block_count: u64,
+    pub kind: PartitionType,
+}
+impl PartitionInfo {
+    /// Whether this is an EFI System Partition.
+    pub fn is_esp(&self) -> bool {
+        match &self.kind {
+            PartitionType::Mbr(t) => *t == MBR_TYPE_ESP,
+            PartitionType::Gpt { type_guid, .. } => *type_guid == GPT_TYPE_ESP,
+        }
+    }
+    pub fn type_name(&self) -> &'static str {
+        match &self.kind {
+            PartitionType::Mbr(0x01 | 0x04 | 0x06 | 0x0b | 0x0c | 0x0e) => "FAT",
+            PartitionType::Mbr(0x07) => "NTFS/exFAT",
+            PartitionType::Mbr(0x82) => "Linux swap",
+            PartitionType::Mbr(0x83) => "Linux",
+            PartitionType::Mbr(MBR_TYPE_ESP) => "EFI System",
+            PartitionType::Mbr(_) => "unknown",
+            PartitionType::Gpt { type_guid, .. } => match *type_guid {
+                GPT_TYPE_ESP => "EFI System",
+                GPT_TYPE_BASIC_DATA => "Basic data",
+                GPT_TYPE_LINUX => "Linux",
+                _ => "unknown",
+            },
+        }
+    }
+}
+
+fn u32_at(b: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+}
+fn u64_at(b: &[u8], i: usize) -> u64 {
+    u32_at(b, i) as u64 | (u32_at(b, i + 4) as u64) << 32
+}
+
+fn guid_at(b: &[u8], i: usize) -> EfiGuid {
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&b[i..i + 16]);
+    EfiGuid::from_bytes(&bytes)
+}
+
+fn read_block(dev: &dyn BlockDevice, lba: u64) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; dev.block_size()];
+    dev.read_blocks(lba, &mut buf)?;
+    Ok(buf)
+}
+
+/// (type, first LBA, number of blocks) of the 4 entries of an MBR or an EBR.
+fn mbr_entries(sector: &[u8]) -> [(u8, u64, u64); 4] {
+    core::array::from_fn(|i| {
+        let e = &sector[MBR_TABLE + i * 16..MBR_TABLE + (i + 1) * 16];
+        (e[4], u32_at(e, 8) as u64, u32_at(e, 12) as u64)
+    })
+}
+
+fn has_mbr_signature(sector: &[u8]) -> bool {
+    sector.len() >= 512 && sector[510..512] == MBR_SIGNATURE
+}
+
+/// Reads the partition table of the disk. A disk without one has no
+/// partitions.
+pub fn read_table(dev: &dyn BlockDevice) -> Result<Vec<PartitionInfo>> {
+    if dev.block_size() < 512 || dev.block_count() < 2 {
+        return Ok(Vec::new());
+    }
+    let mbr = read_block(dev, 0)?;
+    if !has_mbr_signature(&mbr) {
+        return Ok(Vec::new());
+    }
+    let entries = mbr_entries(&mbr);
+    let mut partitions = if entries.iter().any(|e| e.0 == MBR_TYPE_GPT_PROTECTIVE) {
+        read_gpt(dev)?
+    } else {
+        read_mbr(dev, &entries)?
+    };
+    // ディスクからはみ出すものは壊れているので無視する
+    partitions.retain(|p| {
+        p.first_lba > 0
+            && p.block_count > 0
+            && p.first_lba
+                .checked_add(p.block_count)
+                .map_or(false, |end| end <= dev.block_count())
+    });
+    Ok(partitions)
+}
+
+fn read_mbr(dev: &dyn BlockDevice, entries: &[(u8, u64, u64); 4]) -> Result<Vec<PartitionInfo>> {
+    let mut partitions = Vec::new();
+    for (i, &(kind, first_lba, block_count)) in entries.iter().enumerate() {
+        if kind == MBR_TYPE_EMPTY {
+            continue;
+        }
+        if MBR_TYPES_EXTENDED.contains(&kind) {
+            read_logical(dev, first_lba, &mut partitions)?;
+            continue;
+        }
+        partitions.push(PartitionInfo {
+            number: i as u32 + 1,
+            first_lba,
+            block_count,
+            kind: PartitionType::Mbr(kind),
+        });
+    }
+    Ok(partitions)
+}
+
+/// Walks the chain of EBRs in the extended partition at base. The first
+/// entry of an EBR is relative to the EBR, and the second one points to
+/// the next EBR relative to base.
+fn read_logical(dev: &dyn BlockDevice, base: u64, out: &mut Vec<PartitionInfo>) -> Result<()> {
+    let mut ebr_lba = base;
+    for number in 5..5 + MAX_LOGICAL_PARTITIONS {
+        if ebr_lba >= dev.block_count() {
+            break;
+        }
+        let ebr = read_block(dev, ebr_lba)?;
+        if !has_mbr_signature(&ebr) {
+            break;
+        }
+        let [(kind, first, count), (_, next, _), ..] = mbr_entries(&ebr);
+        if kind != MBR_TYPE_EMPTY {
+            out.push(PartitionInfo {
+                number,
+                first_lba: ebr_lba + first,
+                block_count: count,
+                kind: PartitionType::Mbr(kind),
+            });
+        }
+        if next == 0 {
+            break;
+        }
+        ebr_lba = base + next;
+    }
+    Ok(())
+}
+
+fn read_gpt(dev: &dyn BlockDevice) -> Result<Vec<PartitionInfo>> {
+    let header = read_block(dev, 1)?;
+    if &header[0..8] != GPT_SIGNATURE {
+        return Err("Invalid GPT signature");
+    }
+    let header_size = u32_at(&header, 12) as usize;
+    if !(92..=header.len()).contains(&header_size) {
+        return Err("Invalid GPT header size");
+    }
+    let mut check = header[..header_size].to_vec();
+    check[16..20].fill(0);
+    if crc32(&check) != u32_at(&header, 16) {
+        return Err("GPT header checksum mismatch");
+    }
+    let entries_lba = u64_at(&header, 72);
+    let num_entries = u32_at(&header, 80) as usize;
+    let entry_size = u32_at(&header, 84) as usize;
+    if num_entries > GPT_MAX_ENTRIES || entry_size < 128 || entry_size % 8 != 0 || entry_size > 4096
+    {
+        return Err("Unsupported GPT entry array");
+    }
+    let len = num_entries * entry_size;
+    let blocks = len.div_ceil(dev.block_size());
+    if entries_lba
+        .checked_add(blocks as u64)
+        .map_or(true, |end| end > dev.block_count())
+    {
+        return Err("GPT entry array is out of the disk");
+    }
+    let mut array = vec![0u8; blocks * dev.block_size()];
+    dev.read_blocks(entries_lba, &mut array)?;
+    if crc32(&array[..len]) != u32_at(&header, 88) {
+        return Err("GPT entry array checksum mismatch");
+    }
+    Ok(parse_gpt_entries(&array[..len], entry_size))
+}
+
+/// Parses the GPT partition entry array. Unused entries are skipped.
+pub fn parse_gpt_entries(array: &[u8], entry_size: usize) -> Vec<PartitionInfo> {
+    let mut partitions = Vec::new();
+    if entry_size < 128 {
+        return partitions;
+    }
+    for (i, e) in array.chunks_exact(entry_size).enumerate() {
+        if e[0..16].iter().all(|&b| b == 0) {
+            continue;
+        }
+        let first_lba = u64_at(e, 32);
+        let last_lba = u64_at(e, 40);
+        if last_lba < first_lba {
+            continue;
+        }
+        let name = char::decode_utf16(
+            e[56..128]
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .take_while(|&c| c != 0),
+        )
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect();
+        partitions.push(PartitionInfo {
+            number: i as u32 + 1,
+            first_lba,
+            block_count: last_lba - first_lba + 1,
+            kind: PartitionType::Gpt {
+                type_guid: guid_at(e, 0),
+                unique_guid: guid_at(e, 16),
+                name,
+            },
+        });
+    }
+    partitions
+}
+
+/// A partition as a block device of its own.
+pub struct Partition {
+    name: String,
+    disk: Arc<dyn BlockDevice>,
+    info: PartitionInfo,
+}
+impl Partition {
+    pub fn new(disk: Arc<dyn BlockDevice>, info: PartitionInfo) -> Self {
+        // nvme0 -> nvme0p1, vda -> vda1 (Linux と同じ)
+        let name = if disk.name().ends_with(|c: char| c.is_ascii_digit()) {
+            alloc::format!("{}p{}", disk.name(), info.number)
+        } else {
+            alloc::format!("{}{}", disk.name(), info.number)
+        };
+        Self { name, disk, info }
+    }
+    pub fn disk(&self) -> &Arc<dyn BlockDevice> {
+        &self.disk
+    }
+}
+impl BlockDevice for Partition {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn block_size(&self) -> usize {
+        self.disk.block_size()
+    }
+    fn block_count(&self) -> u64 {
+        self.info.block_count
+    }
+    fn is_read_only(&self) -> bool {
+        self.disk.is_read_only()
+    }
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        super::check_range(self, lba, buf.len())?;
+        self.disk.read_blocks(self.info.first_lba + lba, buf)
+    }
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        super::check_range(self, lba, buf.len())?;
+        self.disk.write_blocks(self.info.first_lba + lba, buf)
+    }
+    fn partition(&self) -> Option<&PartitionInfo> {
+        Some(&self.info)
+    }
+}
@@ -0,0 +1,122 @@
+//! Windows BMP images.
+//!
+//! Only the uncompressed 24 and 32 bits per pixel formats are decoded,
+//! which is what most tools write, both bottom-up and top-down. Images are
+//! encoded in 24 bits bottom-up, which every viewer reads.
+
+use crate::graphics::OwnedBitmap;
+use crate::graphics::Rect;
+use crate::result::Result;
+use alloc::vec::Vec;
+
+const FILE_HEADER_SIZE: usize = 14;
+const INFO_HEADER_SIZE: usize = 40;
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+/// The masks of BI_BITFIELDS that are the same as BI_RGB in 32 bits.
+const RGB_MASKS: [u32; 3] = [0x00ff_0000, 0x0000_ff00, 0x0000_00ff];
+/// Larger images are rejected rather than trying to allocate them.
+const MAX_PIXELS: u64 = 4096 * 4096;
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Decodes a BMP file.
+pub fn decode(data: &[u8]) -> Result<OwnedBitmap> {
+    const TRUNCATED: &str = "BMP: truncated";
+    if data.get(0..2) != Some(b"BM") {
+        return Err("BMP: bad signature");
+    }
+    let pixels_offset = u32_at(data, 10).ok_or(TRUNCATED)? as usize;
+    let header_size = u32_at(data, FILE_HEADER_SIZE).ok_or(TRUNCATED)? as usize;
+    if header_size < INFO_HEADER_SIZE {
+        return Err("BMP: unsupported header");
+    }
+    let width = u32_at(data, 18).ok_or(TRUNCATED)? as i32;
+    let height = u32_at(data, 22).ok_or(TRUNCATED)? as i32;
+    let bpp = u16_at(data, 28).ok_or(TRUNCATED)?;
+    let compression = u32_at(data, 30).ok_or(TRUNCATED)?;
+    if width <= 0 || height == 0 || height == i32::MIN {
+        return Err("BMP: bad size");
+    }
+    // 高さが負なら上の行から並んでいる
+    let top_down = height < 0;
+    let (width, height) = (width as u64, height.unsigned_abs() as u64);
+    if width * height > MAX_PIXELS {
+        return Err("BMP: too large");
+    }
+    match (bpp, compression) {
+        (24, BI_RGB) | (32, BI_RGB) => {}
+        (32, BI_BITFIELDS) => {
+            let offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+            let masks = [
+                u32_at(data, offset).ok_or(TRUNCATED)?,
+                u32_at(data, offset + 4).ok_or(TRUNCATED)?,
+                u32_at(data, offset + 8).ok_or(TRUNCATED)?,
+            ];
+            if masks != RGB_MASKS {
+                return Err("BMP: unsupported bit fields");
+            }
+        }
+        _ => return Err("BMP: unsupported format"),
+    }
+    let bytes_per_pixel = bpp as usize / 8;
+    let stride = (width as usize * bytes_per_pixel).div_ceil(4) * 4;
+    let rows = data.get(pixels_offset..).ok_or(TRUNCATED)?;
+    if rows.len() < stride * height as usize {
+        return Err(TRUNCATED);
+    }
+    let mut pixels = Vec::with_capacity((width * height) as usize);
+    for y in 0..height as usize {
+        let row = if top_down { y } else { height as usize - 1 - y };
+        let row = &rows[row * stride..][..width as usize * bytes_per_pixel];
+        pixels.extend(
+            row.chunks_exact(bytes_per_pixel)
+                .map(|p| u32::from_le_bytes([p[0], p[1], p[2], 0])),
+        );
+    }
+    OwnedBitmap::from_pixels(width as i64, height as i64, &pixels)
+}
+
+/// Encodes bitmap as a 24 bits per pixel BMP file.
+pub fn encode(bitmap: &OwnedBitmap) -> Vec<u8> {
+    let Rect { w, h, .. } = bitmap.rect();
+    let (width, height) = (w as usize, h as usize);
+    let stride = (width * 3).div_ceil(4) * 4;
+    let pixels_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE;
+    let file_size = pixels_offset + stride * height;
+    let mut data = Vec::with_capacity(file_size);
+    data.extend_from_slice(b"BM");
+    data.extend_from_slice(&(file_size as u32).to_le_bytes());
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(&(pixels_offset as u32).to_le_bytes());
+    data.extend_from_slice(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+    data.extend_from_slice(&(width as i32).to_le_bytes());
+    data.extend_from_slice(&(height as i32).to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&24u16.to_le_bytes());
+    data.extend_from_slice(&BI_RGB.to_le_bytes());
+    data.extend_from_slice(&((stride * height) as u32).to_le_bytes());
+    // 解像度(約72dpi)と色数
+    data.extend_from_slice(&2835u32.to_le_bytes());
+    data.extend_from_slice(&2835u32.to_le_bytes());
+    data.extend_from_slice(&[0; 8]);
+    let pixels = bitmap.pixels();
+    for y in (0..height).rev() {
+        let row = &pixels[y * width * 4..][..width * 4];
+        for p in row.chunks_exact(4) {
+            data.extend_from_slice(&p[..3]);
+        }
+        data.resize(data.len() + stride - width * 3, 0);
+    }
+    data
+}
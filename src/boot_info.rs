@@ -0,0 +1,177 @@
+//! What the loader (src/bin/loader.rs) tells the kernel ELF
+//! (src/bin/kernel.rs) about the machine.
+//!
+//! The loader fills a BootInfo while the boot services are alive, exits
+//! them and passes a pointer to it to the entry of the kernel, which gets
+//! everything from it instead of the EFI tables. Everything it points to is
+//! in memory of the Kernel kind, which the kernel must not hand out.
+//!
+//! The layout is fixed with repr(C) so that the two can be built
+//! separately. New fields are only ever appended, with a new VERSION, so a
+//! kernel can boot from a newer loader; check() rejects older ones.
+
+use crate::result::Result;
+use crate::uefi::EfiMemoryDescriptor;
+use crate::uefi::EfiMemoryType;
+use core::mem::size_of;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// A linear 32bpp framebuffer. base is 0 if there is no screen.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Framebuffer {
+    pub base: u64,
+    pub width: u32,
+    pub height: u32,
+    pub pixels_per_line: u32,
+    _reserved: u32,
+}
+impl Framebuffer {
+    pub fn new(base: u64, width: u32, height: u32, pixels_per_line: u32) -> Self {
+        Self {
+            base,
+            width,
+            height,
+            pixels_per_line,
+            _reserved: 0,
+        }
+    }
+}
+
+/// What a memory region can be used for once the boot services are gone.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// Free RAM.
+    Usable = 1,
+    /// The kernel image, the BootInfo and the stack from the loader.
+    Kernel,
+    /// Used by the firmware until now, including the page tables that are
+    /// still active. Free once the kernel has its own page tables.
+    BootServices,
+    /// The ACPI tables and the memory of the firmware for ACPI.
+    Acpi,
+    /// Runtime services, MMIO and anything else not to be touched.
+    Reserved,
+}
+impl MemoryKind {
+    pub fn from_efi(memory_type: EfiMemoryType) -> Self {
+        match memory_type {
+            EfiMemoryType::CONVENTIONAL_MEMORY => MemoryKind::Usable,
+            EfiMemoryType::LOADER_CODE | EfiMemoryType::LOADER_DATA => MemoryKind::Kernel,
+            EfiMemoryType::BOOT_SERVICES_CODE | EfiMemoryType::BOOT_SERVICES_DATA => {
+                MemoryKind::BootServices
+            }
+            EfiMemoryType::ACPI_RECLAIM_MEMORY | EfiMemoryType::ACPI_MEMORY_NVS => MemoryKind::Acpi,
+            _ => MemoryKind::Reserved,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub pages: u64,
+    pub kind: MemoryKind,
+    _reserved: u32,
+}
+impl MemoryRegion {
+    pub fn new(base: u64, pages: u64, kind: MemoryKind) -> Self {
+        Self {
+            base,
+            pages,
+            kind,
+            _reserved: 0,
+        }
+    }
+    pub fn from_efi(e: &EfiMemoryDescriptor) -> Self {
+        Self::new(
+            e.physical_start,
+            e.number_of_pages,
+            MemoryKind::from_efi(e.memory_type),
+        )
+    }
+    pub fn size(&self) -> u64 {
+        self.pages * PAGE_SIZE
+    }
+}
+
+/// "WASABIBI"
+pub const BOOT_INFO_MAGIC: u64 = u64::from_le_bytes(*b"WASABIBI");
+pub const BOOT_INFO_VERSION: u32 = 1;
+
+#[repr(C)]
+#[derive(Debug)]
+pub struct BootInfo {
+    magic: u64,
+    pub version: u32,
+    /// The size of the struct of the loader, for the fields of later versions.
+    pub size: u32,
+    pub framebuffer: Framebuffer,
+    memory_map: *const MemoryRegion,
+    memory_map_len: u64,
+    /// The physical address of the ACPI RSDP, or 0 if there is none.
+    pub rsdp: u64,
+    cmdline: *const u8,
+    cmdline_len: u64,
+    initramfs: *const u8,
+    initramfs_len: u64,
+}
+impl BootInfo {
+    /// Everything passed must stay alive as long as the BootInfo is used.
+    pub fn new(
+        framebuffer: Framebuffer,
+        memory_map: &[MemoryRegion],
+        rsdp: Option<u64>,
+        cmdline: &str,
+        initramfs: Option<&[u8]>,
+    ) -> Self {
+        let initramfs = initramfs.unwrap_or_default();
+        Self {
+            magic: BOOT_INFO_MAGIC,
+            version: BOOT_INFO_VERSION,
+            size: size_of::<Self>() as u32,
+            framebuffer,
+            memory_map: memory_map.as_ptr(),
+            memory_map_len: memory_map.len() as u64,
+            rsdp: rsdp.unwrap_or(0),
+            cmdline: cmdline.as_ptr(),
+            cmdline_len: cmdline.len() as u64,
+            initramfs: initramfs.as_ptr(),
+            initramfs_len: initramfs.len() as u64,
+        }
+    }
+    /// Checks that this is a BootInfo with all the fields of this version.
+    pub fn check(&self) -> Result<()> {
+        if self.magic != BOOT_INFO_MAGIC {
+            return Err("Not a BootInfo");
+        }
+        if self.version < BOOT_INFO_VERSION || (self.size as usize) < size_of::<Self>() {
+            return Err("BootInfo is from an older loader");
+        }
+        Ok(())
+    }
+    pub fn memory_map(&self) -> &[MemoryRegion] {
+        // SAFETY: the loader keeps the regions alive (see new())
+        unsafe { core::slice::from_raw_parts(self.memory_map, self.memory_map_len as usize) }
+    }
+    pub fn rsdp(&self) -> Option<*const u8> {
+        (self.rsdp != 0).then_some(self.rsdp as *const u8)
+    }
+    /// The kernel command line, empty if there is none or it is not UTF-8.
+    pub fn cmdline(&self) -> &str {
+        // SAFETY: the loader keeps the command line alive (see new())
+        let bytes = unsafe { core::slice::from_raw_parts(self.cmdline, self.cmdline_len as usize) };
+        core::str::from_utf8(bytes).unwrap_or_default()
+    }
+    /// The initramfs loaded with the kernel, if any.
+    pub fn initramfs(&self) -> Option<&[u8]> {
+        if self.initramfs_len == 0 {
+            return None;
+        }
+        // SAFETY: the loader keeps the initramfs alive (see new())
+        Some(unsafe { core::slice::from_raw_parts(self.initramfs, self.initramfs_len as usize) })
+    }
+}
@@ -0,0 +1,231 @@
+//! The boot menu, drawn on the GOP before the kernel takes over the screen.
+//!
+//! It shows the resolution, the serial log, demo or shell mode and a few
+//! other settings, and reads the keys with the text input protocol of the
+//! firmware. Up/Down select an option and Left/Right (or Space) change it.
+//! Enter stores the changes in the settings and boots, Esc boots with them
+//! for this boot only, and the menu boots unchanged when the countdown of
+//! `boot_menu_timeout` runs out before any key is pressed. With a timeout of
+//! 0 it is skipped unless `bootmenu` is on the command line.
+
+use crate::cmdline;
+use crate::graphics::draw_str_fg;
+use crate::graphics::fill_rect;
+use crate::graphics::Bitmap;
+use crate::keymap::Key;
+use crate::keymap::NamedKey;
+use crate::result::Result;
+use crate::settings;
+use crate::uefi;
+use crate::uefi::EfiSystemTable;
+use crate::version;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+const POLL_INTERVAL_MS: u64 = 100;
+const LINE_HEIGHT: i64 = 20;
+const MARGIN: i64 = 16;
+
+/// A setting and the values it can be switched to.
+pub struct MenuItem {
+    key: &'static str,
+    choices: Vec<String>,
+    selected: usize,
+    initial: usize,
+}
+impl MenuItem {
+    /// Starts at the current value, which is added if it is not a choice.
+    fn new(key: &'static str, choices: &[&str]) -> Self {
+        let mut choices: Vec<String> = choices.iter().map(|c| c.to_string()).collect();
+        let current = settings::get(key);
+        let selected = match choices.iter().position(|c| *c == current) {
+            Some(i) => i,
+            None => {
+                choices.push(current);
+                choices.len() - 1
+            }
+        };
+        Self {
+            key,
+            choices,
+            selected,
+            initial: selected,
+        }
+    }
+    pub fn key(&self) -> &'static str {
+        self.key
+    }
+    pub fn value(&self) -> &str {
+        &self.choices[self.selected]
+    }
+    fn next(&mut self) {
+        self.selected = (self.selected + 1) % self.choices.len();
+    }
+    fn prev(&mut self) {
+        self.selected = (self.selected + self.choices.len() - 1) % self.choices.len();
+    }
+}
+
+/// What to do after a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Continue,
+    /// Boot with the changes for this boot only.
+    Boot,
+    SaveAndBoot,
+}
+
+pub struct BootMenu {
+    items: Vec<MenuItem>,
+    cursor: usize,
+}
+impl BootMenu {
+    /// Builds the menu from the current settings. resolutions are the
+    /// (width, height) of the video modes.
+    pub fn new(resolutions: &[(u32, u32)]) -> Self {
+        let mut modes = vec!["auto".to_string()];
+        for (w, h) in resolutions {
+            let mode = format!("{w}x{h}");
+            if !modes.contains(&mode) {
+                modes.push(mode);
+            }
+        }
+        let modes: Vec<&str> = modes.iter().map(|m| m.as_str()).collect();
+        let items = vec![
+            MenuItem::new("resolution", &modes),
+            MenuItem::new("boot_mode", &["demo", "shell"]),
+            MenuItem::new("serial_log", &["on", "off"]),
+            MenuItem::new("log_level", &["error", "warn", "info", "debug", "trace"]),
+            MenuItem::new("keymap", &["us", "jp"]),
+            MenuItem::new("lang", &["en", "ja"]),
+            MenuItem::new("boot_menu_timeout", &["0", "3", "5", "10", "30"]),
+        ];
+        Self { items, cursor: 0 }
+    }
+    pub fn items(&self) -> &[MenuItem] {
+        &self.items
+    }
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+    pub fn handle_key(&mut self, key: Key) -> Action {
+        match key {
+            Key::Named(NamedKey::Up) => self.cursor = self.cursor.saturating_sub(1),
+            Key::Named(NamedKey::Down) => self.cursor = (self.cursor + 1).min(self.items.len() - 1),
+            Key::Named(NamedKey::Left) => self.items[self.cursor].prev(),
+            Key::Named(NamedKey::Right) | Key::Char(' ') => self.items[self.cursor].next(),
+            Key::Char('\n') => return Action::SaveAndBoot,
+            Key::Char('\x1b') => return Action::Boot,
+            _ => {}
+        }
+        Action::Continue
+    }
+    /// The settings changed in the menu.
+    pub fn changes(&self) -> Vec<(&'static str, &str)> {
+        self.items
+            .iter()
+            .filter(|item| item.selected != item.initial)
+            .map(|item| (item.key, item.value()))
+            .collect()
+    }
+    /// Applies the changes, storing them if save is true. Every change is
+    /// applied even if one fails, and the first error is returned.
+    pub fn apply(&self, save: bool) -> Result<()> {
+        let mut result = Ok(());
+        for (key, value) in self.changes() {
+            let applied = if save {
+                settings::set(key, value)
+            } else {
+                settings::set_for_boot(key, value)
+            };
+            result = result.and(applied);
+        }
+        result
+    }
+    /// Draws the menu, with the seconds left before booting if counting down.
+    pub fn draw<T: Bitmap>(&self, buf: &mut T, seconds_left: Option<u64>) {
+        let (w, h) = (buf.width(), buf.height());
+        let _ = fill_rect(buf, 0x000000, 0, 0, w, h);
+        let title = format!("{} boot menu", version::version());
+        draw_str_fg(buf, MARGIN, MARGIN, 0xffffff, &title);
+        for (i, item) in self.items.iter().enumerate() {
+            let (marker, color) = if i == self.cursor {
+                ('>', 0xffff00)
+            } else {
+                (' ', 0xc0c0c0)
+            };
+            let line = format!("{marker} {:20} < {} >", item.key, item.value());
+            let y = MARGIN + (i as i64 + 2) * LINE_HEIGHT;
+            draw_str_fg(buf, MARGIN, y, color, &line);
+        }
+        let y = MARGIN + (self.items.len() as i64 + 3) * LINE_HEIGHT;
+        draw_str_fg(
+            buf,
+            MARGIN,
+            y,
+            0x808080,
+            "Up/Down: select  Left/Right: change  Enter: save and boot  Esc: boot",
+        );
+        if let Some(seconds) = seconds_left {
+            let line = format!("Booting in {seconds} s");
+            draw_str_fg(buf, MARGIN, y + LINE_HEIGHT, 0xffffff, &line);
+        }
+    }
+}
+
+/// Shows the menu if it is enabled and applies what was chosen. Call after
+/// cmdline::apply(), while the boot services are available.
+pub fn run(efi_system_table: &EfiSystemTable) -> Result<()> {
+    let timeout: u64 = settings::get("boot_menu_timeout").parse().unwrap_or(0);
+    if timeout == 0 && !cmdline::flag("bootmenu") {
+        return Ok(());
+    }
+    let mut vram = uefi::init_vram(efi_system_table)?;
+    let resolutions: Vec<(u32, u32)> = uefi::video_modes(efi_system_table)
+        .unwrap_or_default()
+        .iter()
+        .map(|m| (m.width, m.height))
+        .collect();
+    let mut menu = BootMenu::new(&resolutions);
+    // 0秒ならbootmenuで開かれたので、キーが押されるまで待つ
+    let mut remaining_ms = (timeout > 0).then_some(timeout * 1000);
+    let mut shown = None;
+    let action = loop {
+        let seconds_left = remaining_ms.map(|ms| ms.div_ceil(1000));
+        if shown != Some(seconds_left) {
+            menu.draw(&mut vram, seconds_left);
+            shown = Some(seconds_left);
+        }
+        let key = match remaining_ms {
+            Some(0) => break Action::Boot,
+            Some(ms) => match efi_system_table.read_key_stroke()? {
+                Some(key) => {
+                    remaining_ms = None;
+                    key
+                }
+                None => {
+                    efi_system_table
+                        .boot_services
+                        .stall(Duration::from_millis(POLL_INTERVAL_MS))?;
+                    remaining_ms = Some(ms.saturating_sub(POLL_INTERVAL_MS));
+                    continue;
+                }
+            },
+            None => efi_system_table.wait_for_key()?,
+        };
+        let Some(key) = key.to_key() else {
+            continue;
+        };
+        match menu.handle_key(key) {
+            Action::Continue => shown = None,
+            action => break action,
+        }
+    };
+    let (w, h) = (vram.width(), vram.height());
+    let _ = fill_rect(&mut vram, 0x000000, 0, 0, w, h);
+    menu.apply(action == Action::SaveAndBoot)
+}
@@ -0,0 +1,63 @@
+//! Starting another EFI application (e.g. the UEFI shell or another OS
+//! loader) from the ESP, so that WasabiOS can act as a boot manager.
+
+use crate::arch::without_interrupts;
+use crate::hpet;
+use crate::interrupt;
+use crate::percpu;
+use crate::pic;
+use crate::result::Result;
+use crate::uefi;
+use core::fmt;
+
+/// Runs f with the firmware's interrupt setup, which boot services rely on
+/// (e.g. for timers), and restores the kernel's one afterwards.
+pub fn with_firmware_interrupts<T>(f: impl FnOnce() -> T) -> T {
+    // ファームウェアのタイマ割り込みが届く状態に戻さないと、ブートサービスが止まってしまう
+    let hpet = hpet::get();
+    let kernel_masks = without_interrupts(|| {
+        if let Some(hpet) = &hpet {
+            hpet.suspend();
+        }
+        interrupt::use_firmware_idt();
+        pic::restore_firmware()
+    });
+    let result = f();
+    without_interrupts(|| {
+        percpu::restore_bsp();
+        interrupt::use_kernel_idt();
+        pic::resume(kernel_masks);
+        if let Some(hpet) = &hpet {
+            hpet.resume();
+        }
+    });
+    result
+}
+
+/// Loads and starts the EFI application at path on the boot device.
+///
+/// Returns its exit status once it returns.
+pub fn chainload(path: &str) -> Result<u64> {
+    let efi_system_table = uefi::system_table().ok_or("EFI context is not initialized")?;
+    let parent = uefi::image_handle().ok_or("EFI context is not initialized")?;
+    let boot_services = efi_system_table.boot_services;
+    let device_path = uefi::file_device_path(efi_system_table, path)?;
+    with_firmware_interrupts(|| {
+        let image = boot_services.load_image(parent, &device_path)?;
+        let status = boot_services.start_image(image);
+        // 終了したイメージはファームウェアが解放済みのことが多いので、失敗は無視する
+        let _ = boot_services.unload_image(image);
+        Ok(status)
+    })
+}
+
+/// The `chainload` command.
+pub fn cmd_chainload(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let [path] = args else {
+        return Err("usage: chainload <path on the ESP, e.g. \\EFI\\Shell.efi>");
+    };
+    let _ = writeln!(out, "Starting {path}...");
+    let status = chainload(path)?;
+    let _ = writeln!(out, "{path} exited with status {status:#x}");
+    Ok(())
+}
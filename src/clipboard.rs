@@ -0,0 +1,30 @@
+//! The clipboard shared by the whole system.
+//!
+//! It holds a single text. The console copies and pastes with Ctrl+Shift+C
+//! and Ctrl+Shift+V, TextBox widgets with Ctrl+C, Ctrl+X and Ctrl+V, and
+//! user programs with the copy and paste syscalls.
+
+use crate::mutex::Mutex;
+use crate::result::Result;
+use alloc::string::String;
+
+/// Longer texts are rejected rather than truncated.
+pub const MAX_LEN: usize = 64 * 1024;
+
+static CLIPBOARD: Mutex<String> = Mutex::new(String::new());
+
+/// Replaces the text on the clipboard.
+pub fn copy(text: &str) -> Result<()> {
+    if text.len() > MAX_LEN {
+        return Err("Too long for the clipboard");
+    }
+    let mut clipboard = CLIPBOARD.lock();
+    clipboard.clear();
+    clipboard.push_str(text);
+    Ok(())
+}
+
+/// The text on the clipboard, empty if nothing has been copied.
+pub fn paste() -> String {
+    CLIPBOARD.lock().clone()
+}
@@ -0,0 +1,123 @@
+//! The kernel command line, from the LoadOptions of the image.
+//!
+//! It is a list of `key=value` options and `flag`s separated by spaces,
+//! e.g. `video=1024x768 loglevel=debug serial=off`. apply() overrides the
+//! settings of the same name (and `video` for `resolution`, `loglevel` for
+//! `log_level`, `serial` for `serial_log`) for this boot. Other options are
+//! left for get() and flag().
+
+use crate::info;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::settings;
+use crate::uefi;
+use crate::uefi::EfiSystemTable;
+use crate::warn;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+/// The options in the order given.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cmdline {
+    options: Vec<(String, Option<String>)>,
+}
+impl Cmdline {
+    /// Parses the options. The UEFI shell passes the path of the image as
+    /// the first word, which is skipped.
+    pub fn parse(s: &str) -> Self {
+        let mut words = s.split_whitespace().peekable();
+        if words
+            .peek()
+            .is_some_and(|w| w.to_ascii_lowercase().ends_with(".efi"))
+        {
+            words.next();
+        }
+        let options = words
+            .map(|w| match w.split_once('=') {
+                Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                None => (w.to_string(), None),
+            })
+            .collect();
+        Self { options }
+    }
+    /// The value of the last `key=value`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .and_then(|(_, v)| v.as_deref())
+    }
+    /// Whether `key` is given, with or without a value.
+    pub fn flag(&self, key: &str) -> bool {
+        self.options.iter().any(|(k, _)| k == key)
+    }
+    pub fn options(&self) -> &[(String, Option<String>)] {
+        &self.options
+    }
+}
+
+static CMDLINE: Mutex<Option<(String, Cmdline)>> = Mutex::new(None);
+
+/// Reads the command line from the LoadOptions of the running image.
+pub fn init(efi_system_table: &EfiSystemTable) -> Result<()> {
+    let image = uefi::image_handle().ok_or("EFI context is not initialized")?;
+    set(&uefi::load_options(efi_system_table, image)?);
+    Ok(())
+}
+
+/// Uses raw as the command line, e.g. the one from the BootInfo.
+pub fn set(raw: &str) {
+    *CMDLINE.lock() = Some((raw.to_string(), Cmdline::parse(raw)));
+}
+
+/// The command line as given, empty if there is none.
+pub fn cmdline() -> String {
+    CMDLINE
+        .lock()
+        .as_ref()
+        .map(|(raw, _)| raw.clone())
+        .unwrap_or_default()
+}
+
+/// The value of `key=value` on the command line.
+pub fn get(key: &str) -> Option<String> {
+    CMDLINE
+        .lock()
+        .as_ref()
+        .and_then(|(_, c)| c.get(key).map(|v| v.to_string()))
+}
+
+/// Whether `key` is on the command line.
+pub fn flag(key: &str) -> bool {
+    CMDLINE.lock().as_ref().is_some_and(|(_, c)| c.flag(key))
+}
+
+/// Configures the subsystems from the command line. Call after
+/// settings::load() and before the subsystems read the settings.
+pub fn apply() {
+    let Some((raw, cmdline)) = CMDLINE.lock().clone() else {
+        return;
+    };
+    if !raw.is_empty() {
+        info!("Command line: {raw}");
+    }
+    for (key, value) in cmdline.options() {
+        let Some(value) = value else {
+            continue;
+        };
+        let result = match key.as_str() {
+            "video" => settings::set_for_boot("resolution", value),
+            "loglevel" => settings::set_for_boot("log_level", value),
+            "serial" => settings::set_for_boot("serial_log", value),
+            key if settings::SETTINGS.iter().any(|s| s.key == key) => {
+                settings::set_for_boot(key, value)
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            warn!("Command line: {key}={value}: {e}");
+        }
+    }
+}
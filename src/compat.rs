@@ -0,0 +1,88 @@
+//! The APIs as of the checkpoints of the book, for the examples in
+//! `explained/`.
+//!
+//! Each module re-exports the items a stage of the book uses, under the
+//! names and signatures the book uses, e.g. `use wasabi::compat::p97::*;`.
+//! The signatures are pinned below, so a refactoring that breaks one of
+//! them fails to build instead of breaking the examples silently.
+
+/// p72: booting as an EFI application, GOP and hlt.
+pub mod p72 {
+    pub use crate::arch::hlt;
+    pub use crate::result::Result;
+    pub use crate::uefi::EfiHandle;
+    pub use crate::uefi::EfiStatus;
+    pub use crate::uefi::EfiSystemTable;
+}
+
+/// p80: the Bitmap trait and drawing into the VRAM.
+pub mod p80 {
+    pub use super::p72::*;
+    pub use crate::graphics::draw_point;
+    pub use crate::graphics::fill_rect;
+    pub use crate::graphics::Bitmap;
+    pub use crate::uefi::init_vram;
+    pub use crate::uefi::VramBefferInfo;
+}
+
+/// p83: lines.
+pub mod p83 {
+    pub use super::p80::*;
+    pub use crate::graphics::draw_line;
+}
+
+/// p91: the font.
+pub mod p91 {
+    pub use super::p83::*;
+    pub use crate::graphics::draw_font_fg;
+
+    /// Returns the glyph of c in the embedded font.
+    pub fn lookup_font(c: char) -> Option<[[char; 8]; 16]> {
+        crate::graphics::lookup_font(c)
+    }
+}
+
+/// p97: strings and the text writer.
+pub mod p97 {
+    pub use super::p91::*;
+    pub use crate::graphics::draw_str_fg;
+    pub use crate::uefi::VramTextWriter;
+}
+
+/// p105: the memory map.
+pub mod p105 {
+    pub use super::p97::*;
+    pub use crate::uefi::EfiBootServicesTable;
+    pub use crate::uefi::EfiMemoryDescriptor;
+    pub use crate::uefi::EfiMemoryType;
+    pub use crate::uefi::MemoryMapHolder;
+}
+
+// 本に載っているシグネチャを固定する
+mod signatures {
+    use super::p105::*;
+
+    const _: fn() = hlt;
+    const _: fn(&EfiSystemTable) -> Result<VramBefferInfo> = init_vram;
+    const _: fn(&mut VramBefferInfo, u32, i64, i64) -> Result<()> = draw_point;
+    const _: fn(&mut VramBefferInfo, u32, i64, i64, i64, i64) -> Result<()> = fill_rect;
+    const _: fn(&mut VramBefferInfo, u32, i64, i64, i64, i64) -> Result<()> = draw_line;
+    const _: fn(char) -> Option<[[char; 8]; 16]> = lookup_font;
+    const _: fn(&mut VramBefferInfo, i64, i64, u32, char) = draw_font_fg;
+    const _: fn(&mut VramBefferInfo, i64, i64, u32, &str) = draw_str_fg;
+    const _: fn(&mut VramBefferInfo) -> VramTextWriter = |vram| VramTextWriter::new(vram);
+    const _: fn() -> MemoryMapHolder = MemoryMapHolder::new;
+    const _: fn(&EfiBootServicesTable, &mut MemoryMapHolder) -> EfiStatus =
+        EfiBootServicesTable::get_memory_map;
+    const _: for<'a> fn(&'a MemoryMapHolder) -> crate::uefi::MemoryMapIterator<'a> =
+        MemoryMapHolder::iter;
+    const _: fn(&EfiMemoryDescriptor) -> (EfiMemoryType, u64, u64, u64, u64) = |e| {
+        (
+            e.memory_type,
+            e.physical_start,
+            e.virtual_start,
+            e.number_of_pages,
+            e.attribute,
+        )
+    };
+}
@@ -0,0 +1,367 @@
+//! The text console on the screen.
+//!
+//! Key events are routed here with handle_key(), translated with the
+//! current keymap, and edited through a Tty whose echo is drawn in the
+//! console area. read_line() waits for a completed line. Ctrl+Shift+C
+//! copies the line being edited to the clipboard and Ctrl+Shift+V types
+//! the clipboard into it.
+
+use crate::clipboard;
+use crate::cursor;
+use crate::font;
+use crate::font::FontId;
+use crate::graphics::fill_rect;
+use crate::graphics::Bitmap;
+use crate::graphics::Rect;
+use crate::input::KeyEvent;
+use crate::keymap::Key;
+use crate::keymap::KeyMapper;
+use crate::mutex::Mutex;
+use crate::tty::Tty;
+use crate::tty::BACKSPACE;
+use crate::tty::CTRL_C;
+use crate::tty::CTRL_V;
+use crate::uefi::VramBefferInfo;
+use alloc::string::String;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::ptr::copy;
+use core::task::Context;
+use core::task::Poll;
+use core::task::Waker;
+
+const FG_COLOR: u32 = 0xffffff;
+const BG_COLOR: u32 = 0x000000;
+
+/// A rectangular area of the screen that text is written into. It scrolls
+/// up when the last line is full.
+pub struct TextArea {
+    vram: VramBefferInfo,
+    area: Rect,
+    font: FontId,
+    /// The size of a character cell, taken from the font.
+    cell_w: i64,
+    cell_h: i64,
+    x: i64,
+    y: i64,
+    escape: Escape,
+}
+
+/// The state of parsing an escape sequence. Only `ESC [2J` (clear) and
+/// `ESC [H` (home) are interpreted; the others are dropped.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    Esc,
+    /// In `ESC [`, with the numeric parameter read so far.
+    Csi(u16),
+}
+impl TextArea {
+    pub fn new(vram: VramBefferInfo, area: Rect, font: FontId) -> Self {
+        let area = area
+            .intersection(&Rect::new(0, 0, vram.width(), vram.height()))
+            .unwrap_or_default();
+        let (cell_w, cell_h) = font::cell_size(font);
+        Self {
+            vram,
+            area,
+            font,
+            cell_w,
+            cell_h,
+            x: 0,
+            y: 0,
+            escape: Escape::None,
+        }
+    }
+    fn columns(&self) -> i64 {
+        self.area.w / self.cell_w
+    }
+    fn rows(&self) -> i64 {
+        self.area.h / self.cell_h
+    }
+    /// Switches the font and clears the area, since the cell size may change.
+    pub fn set_font(&mut self, font: FontId) {
+        self.font = font;
+        (self.cell_w, self.cell_h) = font::cell_size(font);
+        self.clear();
+    }
+    pub fn clear(&mut self) {
+        let a = self.area;
+        let _ = fill_rect(&mut self.vram, BG_COLOR, a.x, a.y, a.w, a.h);
+        self.x = 0;
+        self.y = 0;
+    }
+    fn scroll_up(&mut self) {
+        let a = self.area;
+        let h = self.cell_h;
+        for py in a.y..a.bottom() - h {
+            // SAFETY: area is clipped to the VRAM in new()
+            unsafe {
+                let src = self.vram.unchecked_pixel_at_mut(a.x, py + h);
+                let dst = self.vram.unchecked_pixel_at_mut(a.x, py);
+                copy(src, dst, a.w as usize);
+            }
+        }
+        let last = a.y + (self.rows() - 1) * h;
+        let _ = fill_rect(&mut self.vram, BG_COLOR, a.x, last, a.w, h);
+    }
+    fn new_line(&mut self) {
+        self.x = 0;
+        if self.y + 1 < self.rows() {
+            self.y += 1;
+        } else {
+            self.scroll_up();
+        }
+    }
+    /// Returns true if c is a part of an escape sequence.
+    fn handle_escape(&mut self, c: char) -> bool {
+        self.escape = match (self.escape, c) {
+            (Escape::None, '\x1b') => Escape::Esc,
+            (Escape::None, _) => return false,
+            (Escape::Esc, '[') => Escape::Csi(0),
+            (Escape::Csi(n), '0'..='9') => {
+                Escape::Csi(n.saturating_mul(10).saturating_add(c as u16 - '0' as u16))
+            }
+            (Escape::Csi(2), 'J') => {
+                let (x, y) = (self.x, self.y);
+                self.clear();
+                (self.x, self.y) = (x, y);
+                Escape::None
+            }
+            (Escape::Csi(_), 'H') => {
+                (self.x, self.y) = (0, 0);
+                Escape::None
+            }
+            _ => Escape::None,
+        };
+        true
+    }
+    fn put_char(&mut self, c: char) {
+        if self.rows() == 0 || self.columns() == 0 || self.handle_escape(c) {
+            return;
+        }
+        match c {
+            '\n' => self.new_line(),
+            BACKSPACE => {
+                if self.x > 0 {
+                    self.x -= 1;
+                    let (px, py) = self.pixel_pos();
+                    let _ = fill_rect(&mut self.vram, BG_COLOR, px, py, self.cell_w, self.cell_h);
+                }
+            }
+            c => {
+                // 代替フォントのグリフ (漢字など) はセルの幅より広いことがある
+                let cells = (font::char_width(self.font, c) + self.cell_w - 1) / self.cell_w;
+                if self.x + cells > self.columns() {
+                    self.new_line();
+                }
+                let (px, py) = self.pixel_pos();
+                let (w, h) = (cells * self.cell_w, self.cell_h);
+                let _ = fill_rect(&mut self.vram, BG_COLOR, px, py, w, h);
+                font::draw_char(&mut self.vram, self.font, px, py, FG_COLOR, c);
+                self.x += cells;
+            }
+        }
+    }
+    fn pixel_pos(&self) -> (i64, i64) {
+        (
+            self.area.x + self.x * self.cell_w,
+            self.area.y + self.y * self.cell_h,
+        )
+    }
+}
+impl fmt::Write for TextArea {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let area = self.area;
+        let mut vram = self.vram;
+        // マウスカーソルの下を書き換えるので、重なる場合は一旦隠す
+        cursor::draw_around(&mut vram, area, |_| {
+            for c in s.chars() {
+                self.put_char(c);
+            }
+        });
+        Ok(())
+    }
+}
+
+struct Console {
+    text: Option<TextArea>,
+    tty: Tty,
+    mapper: KeyMapper,
+    waker: Option<Waker>,
+    /// True while read_key() is waiting, so that keys bypass the Tty.
+    raw: bool,
+    key: Option<Key>,
+}
+// SAFETY: the VRAM pointer in TextArea is only accessed with the lock held
+unsafe impl Send for Console {}
+
+static CONSOLE: Mutex<Console> = Mutex::new(Console {
+    text: None,
+    tty: Tty::new(),
+    mapper: KeyMapper::new(),
+    waker: None,
+    raw: false,
+    key: None,
+});
+
+/// Shows the console in `area` of the screen.
+pub fn init(vram: VramBefferInfo, area: Rect) {
+    let mut text = TextArea::new(vram, area, FontId::DEFAULT);
+    text.clear();
+    CONSOLE.lock().text = Some(text);
+}
+
+/// The font of the console.
+pub fn font() -> FontId {
+    CONSOLE
+        .lock()
+        .text
+        .as_ref()
+        .map(|t| t.font)
+        .unwrap_or(FontId::DEFAULT)
+}
+
+pub fn set_font(font: FontId) {
+    if let Some(text) = CONSOLE.lock().text.as_mut() {
+        text.set_font(font);
+    }
+}
+
+/// The size of the console in characters (columns, rows).
+pub fn size() -> Option<(usize, usize)> {
+    let console = CONSOLE.lock();
+    let text = console.text.as_ref()?;
+    Some((text.columns() as usize, text.rows() as usize))
+}
+
+pub fn clear() {
+    if let Some(text) = CONSOLE.lock().text.as_mut() {
+        text.clear();
+    }
+}
+
+/// Scrolls the whole console up by a line, as when the last line is full.
+/// Returns false if the console is not shown. Used by the `bench` command.
+pub fn scroll_up() -> bool {
+    let mut console = CONSOLE.lock();
+    let Some(text) = console.text.as_mut() else {
+        return false;
+    };
+    let area = text.area;
+    let mut vram = text.vram;
+    cursor::draw_around(&mut vram, area, |_| text.scroll_up());
+    true
+}
+
+/// Writes to the console, e.g. `write!(console::ConsoleWriter, "...")`.
+pub struct ConsoleWriter;
+impl fmt::Write for ConsoleWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        match CONSOLE.lock().text.as_mut() {
+            Some(text) => text.write_str(s),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Feeds a key event from the input queue to the console.
+pub fn handle_key(e: &KeyEvent) {
+    let mut console = CONSOLE.lock();
+    let console = &mut *console;
+    let Some(stroke) = console.mapper.process(e) else {
+        return;
+    };
+    if console.raw {
+        console.key = Some(stroke.key);
+        if let Some(waker) = console.waker.take() {
+            waker.wake();
+        }
+        return;
+    }
+    let Key::Char(c) = stroke.key else {
+        return;
+    };
+    let mut sink = NullWriter;
+    let out: &mut dyn fmt::Write = match console.text.as_mut() {
+        Some(text) => text,
+        None => &mut sink,
+    };
+    let copy_paste = stroke.modifiers.ctrl && stroke.modifiers.shift;
+    match c {
+        CTRL_C if copy_paste => {
+            let _ = clipboard::copy(console.tty.line());
+        }
+        CTRL_V if copy_paste => {
+            // 改行は行の確定になり、それ以外の制御文字は捨てる
+            for c in clipboard::paste().chars() {
+                if c == '\n' || !c.is_control() {
+                    let _ = console.tty.input(c, out);
+                }
+            }
+        }
+        // Ctrl+Cは入力中の行を捨てるだけ (^Cの表示はTtyが行う)
+        c => {
+            let _ = console.tty.input(c, out);
+        }
+    }
+    if console.tty.has_input() {
+        if let Some(waker) = console.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+struct NullWriter;
+impl fmt::Write for NullWriter {
+    fn write_str(&mut self, _: &str) -> fmt::Result {
+        Ok(())
+    }
+}
+
+/// Waits until Enter is pressed and returns the line, without the newline.
+///
+/// Typed characters are echoed, and Backspace, Ctrl+U and Ctrl+W edit the line.
+pub fn read_line() -> ReadLine {
+    ReadLine
+}
+
+pub struct ReadLine;
+impl Future for ReadLine {
+    type Output = String;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<String> {
+        let mut console = CONSOLE.lock();
+        match console.tty.read_line() {
+            Some(line) => Poll::Ready(line),
+            None => {
+                console.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Waits for a key without echoing it, e.g. for the pager.
+pub fn read_key() -> ReadKey {
+    ReadKey
+}
+
+pub struct ReadKey;
+impl Future for ReadKey {
+    type Output = Key;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Key> {
+        let mut console = CONSOLE.lock();
+        match console.key.take() {
+            Some(key) => {
+                console.raw = false;
+                Poll::Ready(key)
+            }
+            None => {
+                console.raw = true;
+                console.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
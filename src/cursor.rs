@@ -0,0 +1,158 @@
+//! The mouse cursor drawn directly on the screen.
+//!
+//! The pixels under the sprite are saved when it is drawn and written back
+//! when it moves away, so the cursor does not leave a trail on the console.
+//! Anything else drawing on the same bitmap must not touch the area under
+//! the cursor while it is shown; use draw_around() for that.
+
+use crate::graphics::Bitmap;
+use crate::graphics::Rect;
+use crate::mouse::MouseEvent;
+use crate::mutex::Mutex;
+
+const SPRITE_W: usize = 12;
+const SPRITE_H: usize = 19;
+// '*': 縁取り, '.': 塗り, ' ': 透明
+const SPRITE: [&str; SPRITE_H] = [
+    "*           ",
+    "**          ",
+    "*.*         ",
+    "*..*        ",
+    "*...*       ",
+    "*....*      ",
+    "*.....*     ",
+    "*......*    ",
+    "*.......*   ",
+    "*........*  ",
+    "*.........* ",
+    "*..........*",
+    "*......*****",
+    "*...*..*    ",
+    "*..* *..*   ",
+    "*.*  *..*   ",
+    "**    *..*  ",
+    "      *..*  ",
+    "       **   ",
+];
+const BORDER_COLOR: u32 = 0x000000;
+const FILL_COLOR: u32 = 0xffffff;
+
+pub struct Cursor {
+    x: i64,
+    y: i64,
+    saved: [[u32; SPRITE_W]; SPRITE_H],
+    visible: bool,
+}
+impl Cursor {
+    pub const fn new(x: i64, y: i64) -> Self {
+        Self {
+            x,
+            y,
+            saved: [[0; SPRITE_W]; SPRITE_H],
+            visible: false,
+        }
+    }
+    pub fn position(&self) -> (i64, i64) {
+        (self.x, self.y)
+    }
+    /// The area covered by the sprite (the hot spot is at its top-left).
+    pub fn rect(&self) -> Rect {
+        Rect::new(self.x, self.y, SPRITE_W as i64, SPRITE_H as i64)
+    }
+    fn sprite_pixels(&self) -> impl Iterator<Item = (usize, usize, u32)> {
+        SPRITE.iter().enumerate().flat_map(|(dy, row)| {
+            row.bytes().enumerate().filter_map(move |(dx, c)| match c {
+                b'*' => Some((dx, dy, BORDER_COLOR)),
+                b'.' => Some((dx, dy, FILL_COLOR)),
+                _ => None,
+            })
+        })
+    }
+    pub fn show<T: Bitmap>(&mut self, buf: &mut T) {
+        if self.visible {
+            return;
+        }
+        for (dx, dy, color) in self.sprite_pixels() {
+            if let Some(p) = buf.pixel_at_mut(self.x + dx as i64, self.y + dy as i64) {
+                // SAFETY: p is checked by pixel_at_mut()
+                unsafe {
+                    self.saved[dy][dx] = *p;
+                    *p = color;
+                }
+            }
+        }
+        self.visible = true;
+    }
+    pub fn hide<T: Bitmap>(&mut self, buf: &mut T) {
+        if !self.visible {
+            return;
+        }
+        for (dx, dy, _) in self.sprite_pixels() {
+            if let Some(p) = buf.pixel_at_mut(self.x + dx as i64, self.y + dy as i64) {
+                // SAFETY: p is checked by pixel_at_mut()
+                unsafe {
+                    *p = self.saved[dy][dx];
+                }
+            }
+        }
+        self.visible = false;
+    }
+    /// Moves the cursor, keeping its hot spot within the bitmap.
+    pub fn move_to<T: Bitmap>(&mut self, buf: &mut T, x: i64, y: i64) {
+        let x = x.clamp(0, buf.width() - 1);
+        let y = y.clamp(0, buf.height() - 1);
+        if (x, y) == (self.x, self.y) {
+            return;
+        }
+        let visible = self.visible;
+        self.hide(buf);
+        self.x = x;
+        self.y = y;
+        if visible {
+            self.show(buf);
+        }
+    }
+    pub fn move_by<T: Bitmap>(&mut self, buf: &mut T, dx: i64, dy: i64) {
+        self.move_to(buf, self.x + dx, self.y + dy)
+    }
+    /// Runs f, which draws in `area`, with the cursor hidden if it overlaps.
+    pub fn draw_around<T: Bitmap, R>(
+        &mut self,
+        buf: &mut T,
+        area: Rect,
+        f: impl FnOnce(&mut T) -> R,
+    ) -> R {
+        if !self.visible || self.rect().intersection(&area).is_none() {
+            return f(buf);
+        }
+        self.hide(buf);
+        let result = f(buf);
+        self.show(buf);
+        result
+    }
+}
+
+/// The cursor on the screen.
+static CURSOR: Mutex<Cursor> = Mutex::new(Cursor::new(0, 0));
+
+/// Shows the cursor at the center of the screen.
+pub fn init<T: Bitmap>(screen: &mut T) {
+    let mut cursor = CURSOR.lock();
+    cursor.move_to(screen, screen.width() / 2, screen.height() / 2);
+    cursor.show(screen);
+}
+
+/// The position of the cursor on the screen.
+pub fn position() -> (i64, i64) {
+    CURSOR.lock().position()
+}
+
+/// Moves the cursor on the screen by a mouse event.
+pub fn handle_mouse_event<T: Bitmap>(screen: &mut T, e: &MouseEvent) {
+    CURSOR.lock().move_by(screen, e.dx as i64, e.dy as i64);
+}
+
+/// Runs f, which draws in `area` of the screen, without breaking the cursor.
+pub fn draw_around<T: Bitmap, R>(screen: &mut T, area: Rect, f: impl FnOnce(&mut T) -> R) -> R {
+    CURSOR.lock().draw_around(screen, area, f)
+}
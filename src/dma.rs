@@ -0,0 +1,151 @@
+//! Memory for devices to read and write with DMA.
+//!
+//! A DmaBuffer is zeroed, physically contiguous memory, along with the
+//! address to give to the device. The heap is identity mapped, so that is
+//! the address of the pointer; for a device that can not reach the heap
+//! (e.g. one with 32-bit addresses while the heap is above 4 GiB), pages
+//! below its limit are taken from the firmware instead.
+//!
+//! Buffers aligned to a page are padded to whole pages, so that
+//! map_uncached() can change the caching of their pages without touching
+//! anything else. DMA is coherent with the caches on x86, so this is only
+//! for devices that need it.
+
+use crate::paging;
+use crate::paging::PAGE_SIZE;
+use crate::result::Result;
+use crate::uefi;
+use crate::uefi::AllocateType;
+use crate::uefi::EfiMemoryType;
+use alloc::alloc::alloc_zeroed;
+use alloc::alloc::dealloc;
+use alloc::alloc::Layout;
+
+/// The limit of devices with 32-bit addresses.
+pub const LIMIT_32BIT: u64 = 1 << 32;
+
+enum Source {
+    Heap(Layout),
+    /// Pages from the firmware, as (base, pages).
+    Firmware(u64, usize),
+}
+
+pub struct DmaBuffer {
+    addr: u64,
+    len: usize,
+    source: Source,
+    uncached: bool,
+}
+impl DmaBuffer {
+    /// The address for the device.
+    pub fn phys(&self) -> u64 {
+        self.addr
+    }
+    /// The address for the CPU.
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.addr as *mut u8
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// Keeps the buffer forever, e.g. for the rings of a controller.
+    pub fn leak(self) -> *mut u8 {
+        let p = self.as_ptr();
+        core::mem::forget(self);
+        p
+    }
+    /// Maps the pages of the buffer uncached for the CPU, which needs a
+    /// buffer aligned to a page.
+    pub fn map_uncached(&mut self) -> Result<()> {
+        if self.addr % PAGE_SIZE != 0 || self.len as u64 % PAGE_SIZE != 0 {
+            return Err("The DMA buffer is not aligned to a page");
+        }
+        for page in (self.addr..self.addr + self.len as u64).step_by(PAGE_SIZE as usize) {
+            paging::set_kernel_page_uncached(page, true)?;
+        }
+        self.uncached = true;
+        Ok(())
+    }
+}
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        if self.uncached {
+            for page in (self.addr..self.addr + self.len as u64).step_by(PAGE_SIZE as usize) {
+                let _ = paging::set_kernel_page_uncached(page, false);
+            }
+        }
+        match self.source {
+            // SAFETY: the buffer is allocated with the layout
+            Source::Heap(layout) => unsafe { dealloc(self.as_ptr(), layout) },
+            Source::Firmware(base, pages) => {
+                if let Some(efi_system_table) = uefi::system_table() {
+                    // SAFETY: the pages are allocated for the buffer, which is gone
+                    let _ = unsafe { efi_system_table.boot_services.free_pages(base, pages) };
+                }
+            }
+        }
+    }
+}
+
+/// Allocates a buffer of len bytes aligned to align for a device that can
+/// reach any address.
+pub fn alloc_dma(len: usize, align: usize) -> Result<DmaBuffer> {
+    alloc_dma_below(len, align, u64::MAX)
+}
+
+/// Allocates a buffer of len bytes aligned to align that ends at or below
+/// limit.
+pub fn alloc_dma_below(len: usize, align: usize, limit: u64) -> Result<DmaBuffer> {
+    let len = len.max(1);
+    let len = if align >= PAGE_SIZE as usize {
+        len.div_ceil(PAGE_SIZE as usize) * PAGE_SIZE as usize
+    } else {
+        len
+    };
+    let layout = Layout::from_size_align(len, align).or(Err("Invalid DMA buffer layout"))?;
+    // SAFETY: the layout has a non-zero size
+    let p = unsafe { alloc_zeroed(layout) };
+    if p.is_null() {
+        return Err("Out of memory for DMA");
+    }
+    if (p as u64)
+        .checked_add(len as u64)
+        .is_some_and(|end| end <= limit)
+    {
+        return Ok(DmaBuffer {
+            addr: p as u64,
+            len,
+            source: Source::Heap(layout),
+            uncached: false,
+        });
+    }
+    // SAFETY: p is allocated with the layout
+    unsafe { dealloc(p, layout) };
+    alloc_from_firmware(len, align, limit)
+}
+
+/// Takes pages below limit from the firmware, with extra ones to align
+/// the buffer if align is larger than a page.
+fn alloc_from_firmware(len: usize, align: usize, limit: u64) -> Result<DmaBuffer> {
+    let efi_system_table =
+        uefi::system_table().ok_or("No memory for DMA below the limit of the device")?;
+    let page_size = PAGE_SIZE as usize;
+    let pages = len.div_ceil(page_size) + align.max(page_size) / page_size - 1;
+    let base = efi_system_table.boot_services.allocate_pages(
+        AllocateType::MaxAddress(limit.saturating_sub(1)),
+        EfiMemoryType::LOADER_DATA,
+        pages,
+    )?;
+    let addr = base.next_multiple_of(align as u64);
+    // SAFETY: the pages are allocated for the buffer
+    unsafe { core::ptr::write_bytes(addr as *mut u8, 0, len) };
+    Ok(DmaBuffer {
+        addr,
+        len,
+        source: Source::Firmware(base, pages),
+        uncached: false,
+    })
+}
@@ -0,0 +1,94 @@
+//! The kernel message buffer: the last SIZE bytes of the kernel output.
+//!
+//! Everything printed with print!() and every log record of the ring sink
+//! is kept here, regardless of what is on the screen. The buffer is a
+//! static array, so messages printed before the allocator is set up are
+//! kept too.
+
+use crate::mutex::Mutex;
+use crate::result::Result;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+pub const SIZE: usize = 64 * 1024;
+
+struct Ring {
+    buf: [u8; SIZE],
+    /// The total number of bytes written. The last SIZE of them are kept.
+    written: usize,
+}
+impl Ring {
+    fn write(&mut self, bytes: &[u8]) {
+        // 入りきらない分は先頭から捨てる
+        let bytes = &bytes[bytes.len().saturating_sub(SIZE)..];
+        let start = self.written % SIZE;
+        let first = bytes.len().min(SIZE - start);
+        self.buf[start..start + first].copy_from_slice(&bytes[..first]);
+        self.buf[..bytes.len() - first].copy_from_slice(&bytes[first..]);
+        self.written += bytes.len();
+    }
+    /// The bytes kept, oldest first.
+    fn bytes(&self) -> impl Iterator<Item = &u8> {
+        let (newer, older) = self.buf.split_at(self.written % SIZE);
+        let older = if self.written < SIZE { &[][..] } else { older };
+        older.iter().chain(newer.iter())
+    }
+}
+
+static RING: Mutex<Ring> = Mutex::new(Ring {
+    buf: [0; SIZE],
+    written: 0,
+});
+
+pub fn write(bytes: &[u8]) {
+    RING.lock().write(bytes);
+}
+
+/// Appends to the buffer with fmt::Write.
+pub struct DmesgWriter;
+impl fmt::Write for DmesgWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// The text kept. A line cut by the wrap-around is dropped.
+pub fn text() -> String {
+    let ring = RING.lock();
+    let mut bytes: Vec<u8> = ring.bytes().copied().collect();
+    if ring.written > SIZE {
+        let start = bytes.iter().position(|b| *b == b'\n').map_or(0, |i| i + 1);
+        bytes.drain(..start);
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+pub fn clear() {
+    let mut ring = RING.lock();
+    ring.written = 0;
+}
+
+/// The `dmesg` command. The output is paged on the screen console.
+pub fn cmd_dmesg(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let text = text();
+    let lines: Vec<&str> = text.lines().collect();
+    let shown = match args {
+        [] => &lines[..],
+        ["tail"] => &lines[lines.len().saturating_sub(20)..],
+        ["tail", n] => {
+            let n: usize = n.parse().or(Err("Invalid number of lines"))?;
+            &lines[lines.len().saturating_sub(n)..]
+        }
+        ["clear"] => {
+            clear();
+            return Ok(());
+        }
+        _ => return Err("usage: dmesg [tail [LINES]|clear]"),
+    };
+    for line in shown {
+        let _ = writeln!(out, "{line}");
+    }
+    Ok(())
+}
@@ -0,0 +1,101 @@
+//! A minimal parser of ELF64 executables for x86_64.
+//!
+//! Only statically linked executables (ET_EXEC) are supported, and only the
+//! PT_LOAD segments are looked at; there is no relocation nor interpreter.
+
+use crate::result::Result;
+use alloc::vec::Vec;
+
+const MAGIC: &[u8; 4] = b"\x7fELF";
+const CLASS_64: u8 = 2;
+const DATA_LITTLE_ENDIAN: u8 = 1;
+const TYPE_EXEC: u16 = 2;
+const MACHINE_X86_64: u16 = 0x3e;
+const HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+const PT_LOAD: u32 = 1;
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment<'a> {
+    pub vaddr: u64,
+    /// Bytes past data up to mem_size are zero (.bss).
+    pub mem_size: u64,
+    pub data: &'a [u8],
+    pub writable: bool,
+    pub executable: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Elf<'a> {
+    pub entry: u64,
+    pub segments: Vec<Segment<'a>>,
+}
+
+fn u16_at(b: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([b[i], b[i + 1]])
+}
+fn u32_at(b: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+}
+fn u64_at(b: &[u8], i: usize) -> u64 {
+    u32_at(b, i) as u64 | (u32_at(b, i + 4) as u64) << 32
+}
+
+/// A slice of len bytes at offset, if it is in the file.
+fn range(bytes: &[u8], offset: u64, len: u64) -> Result<&[u8]> {
+    let start = usize::try_from(offset).or(Err("ELF offset is too large"))?;
+    let len = usize::try_from(len).or(Err("ELF size is too large"))?;
+    let end = start.checked_add(len).ok_or("ELF size is too large")?;
+    bytes.get(start..end).ok_or("ELF is truncated")
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Elf> {
+    if bytes.len() < HEADER_SIZE || &bytes[0..4] != MAGIC {
+        return Err("Not an ELF file");
+    }
+    if bytes[4] != CLASS_64 || bytes[5] != DATA_LITTLE_ENDIAN {
+        return Err("Not a little-endian ELF64 file");
+    }
+    if u16_at(bytes, 16) != TYPE_EXEC {
+        return Err("Not a statically linked executable");
+    }
+    if u16_at(bytes, 18) != MACHINE_X86_64 {
+        return Err("Not an x86_64 executable");
+    }
+    let entry = u64_at(bytes, 24);
+    let phoff = u64_at(bytes, 32);
+    let phentsize = u16_at(bytes, 54) as u64;
+    let phnum = u16_at(bytes, 56) as u64;
+    if phentsize < PROGRAM_HEADER_SIZE as u64 {
+        return Err("Invalid ELF program header size");
+    }
+    let headers = range(bytes, phoff, phentsize * phnum)?;
+    let mut segments = Vec::new();
+    for ph in headers.chunks_exact(phentsize as usize) {
+        if u32_at(ph, 0) != PT_LOAD {
+            continue;
+        }
+        let flags = u32_at(ph, 4);
+        let offset = u64_at(ph, 8);
+        let vaddr = u64_at(ph, 16);
+        let file_size = u64_at(ph, 32);
+        let mem_size = u64_at(ph, 40);
+        if file_size > mem_size || vaddr.checked_add(mem_size).is_none() {
+            return Err("Invalid ELF segment size");
+        }
+        segments.push(Segment {
+            vaddr,
+            mem_size,
+            data: range(bytes, offset, file_size)?,
+            writable: flags & PF_W != 0,
+            executable: flags & PF_X != 0,
+        });
+    }
+    if segments.is_empty() {
+        return Err("ELF has no loadable segment");
+    }
+    Ok(Elf { entry, segments })
+}
@@ -0,0 +1,234 @@
+//! A small executor for Future-based kernel tasks.
+//!
+//! Wakers may be called from interrupt handlers (e.g. IrqQueue::push()), so
+//! everything they touch is locked with interrupts disabled on the executor
+//! side. A waker is just the id of its task, so cloning and dropping one
+//! never touches the heap, and waking one never allocates: that would
+//! deadlock on the allocator if the interrupt came in the middle of it. The
+//! executor itself runs inside a cooperative task (see task.rs).
+
+use crate::arch::without_interrupts;
+use crate::mutex::Mutex;
+use crate::mutex::MutexGuard;
+use crate::task;
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+use core::task::Context;
+use core::task::Poll;
+use core::task::RawWaker;
+use core::task::RawWakerVTable;
+use core::task::Waker;
+
+pub type AsyncTaskId = u64;
+
+struct AsyncTask {
+    id: AsyncTaskId,
+    future: Pin<Box<dyn Future<Output = ()>>>,
+}
+
+struct SpawnQueue(Vec<AsyncTask>);
+// SAFETY: futures are only touched by the executor on a single CPU, never
+// from interrupt handlers
+unsafe impl Send for SpawnQueue {}
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static SPAWNED: Mutex<SpawnQueue> = Mutex::new(SpawnQueue(Vec::new()));
+static READY: Mutex<VecDeque<AsyncTaskId>> = Mutex::new(VecDeque::new());
+/// Set when READY had no room for a task that was woken: all the tasks are
+/// polled instead.
+static WAKE_ALL: AtomicBool = AtomicBool::new(false);
+
+fn push_ready(id: AsyncTaskId) {
+    without_interrupts(|| {
+        let mut ready = READY.lock();
+        if ready.contains(&id) {
+            return;
+        }
+        // 割り込みハンドラから呼ばれることがあるので、ここではメモリを確保しない
+        if ready.len() < ready.capacity() {
+            ready.push_back(id);
+        } else {
+            WAKE_ALL.store(true, Ordering::SeqCst);
+        }
+    })
+}
+
+/// Makes room in READY for n more tasks. Call outside of interrupt handlers.
+fn reserve_ready(n: usize) {
+    without_interrupts(|| READY.lock().reserve(n))
+}
+
+/// Returns true if some task has been woken and waits to be polled.
+pub fn has_ready() -> bool {
+    without_interrupts(|| !READY.lock().is_empty())
+}
+
+const WAKER_VTABLE: RawWakerVTable =
+    RawWakerVTable::new(clone_waker, wake_waker, wake_waker, drop_waker);
+
+fn raw_waker(id: AsyncTaskId) -> RawWaker {
+    RawWaker::new(id as usize as *const (), &WAKER_VTABLE)
+}
+unsafe fn clone_waker(data: *const ()) -> RawWaker {
+    raw_waker(data as usize as AsyncTaskId)
+}
+unsafe fn wake_waker(data: *const ()) {
+    push_ready(data as usize as AsyncTaskId);
+}
+unsafe fn drop_waker(_data: *const ()) {}
+
+/// The waker of the task id.
+fn waker(id: AsyncTaskId) -> Waker {
+    // SAFETY: the functions of the vtable only use the data as the id
+    unsafe { Waker::from_raw(raw_waker(id)) }
+}
+
+/// Adds a future to be run by the executor.
+pub fn spawn(future: impl Future<Output = ()> + 'static) -> AsyncTaskId {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    SPAWNED.lock().0.push(AsyncTask {
+        id,
+        future: Box::pin(future),
+    });
+    reserve_ready(1);
+    push_ready(id);
+    id
+}
+
+#[derive(Default)]
+pub struct Executor {
+    tasks: BTreeMap<AsyncTaskId, AsyncTask>,
+}
+impl Executor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Polls the tasks that have been woken. Returns false if there was nothing to do.
+    pub fn run_ready(&mut self) -> bool {
+        crate::time::wake_expired_timers();
+        for t in core::mem::take(&mut SPAWNED.lock().0) {
+            self.tasks.insert(t.id, t);
+        }
+        // 割り込みハンドラが全部のタスクを起こせるよう、先に場所を空けておく
+        reserve_ready(self.tasks.len());
+        if WAKE_ALL.swap(false, Ordering::SeqCst) {
+            for id in self.tasks.keys() {
+                push_ready(*id);
+            }
+        }
+        let mut ran = false;
+        while let Some(id) = without_interrupts(|| READY.lock().pop_front()) {
+            let Some(task) = self.tasks.get_mut(&id) else {
+                // spawn()直後でまだ取り込んでいないタスクかもしれない
+                if SPAWNED.lock().0.iter().any(|t| t.id == id) {
+                    push_ready(id);
+                    break;
+                }
+                continue;
+            };
+            ran = true;
+            let waker = waker(id);
+            let mut cx = Context::from_waker(&waker);
+            if task.future.as_mut().poll(&mut cx).is_ready() {
+                self.tasks.remove(&id);
+            }
+            // 残りは次の番に回し、他のタスクを待たせすぎない
+            if task::should_yield() {
+                break;
+            }
+        }
+        ran
+    }
+    /// Runs the tasks forever, letting the other cooperative tasks run in between.
+    pub fn run(&mut self) -> ! {
+        loop {
+            self.run_ready();
+            task::yield_now();
+        }
+    }
+}
+
+struct IrqQueueInner<T> {
+    items: VecDeque<T>,
+    waker: Option<Waker>,
+}
+
+/// A bounded queue filled by an interrupt handler and drained by async code,
+/// e.g. `let ev = queue.pop().await;`.
+///
+/// The room for the items is allocated by the first pop, since push() must
+/// not allocate; the items pushed before that are dropped.
+pub struct IrqQueue<T> {
+    inner: Mutex<IrqQueueInner<T>>,
+    capacity: usize,
+}
+impl<T> IrqQueue<T> {
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(IrqQueueInner {
+                items: VecDeque::new(),
+                waker: None,
+            }),
+            capacity,
+        }
+    }
+    /// Adds an item and wakes the waiting task. Safe to call from an
+    /// interrupt handler. Returns false if the item was dropped.
+    pub fn push(&self, item: T) -> bool {
+        // 受け取る側は割り込みを止めてロックするので、割り込みハンドラ内では必ず取れる
+        let Some(mut inner) = self.inner.try_lock() else {
+            return false;
+        };
+        if inner.items.len() >= self.capacity.min(inner.items.capacity()) {
+            return false;
+        }
+        inner.items.push_back(item);
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+        true
+    }
+    pub fn try_pop(&self) -> Option<T> {
+        without_interrupts(|| self.lock_reserved().items.pop_front())
+    }
+    /// Locks the queue, with the room for the items allocated. Call with
+    /// interrupts disabled.
+    fn lock_reserved(&self) -> MutexGuard<IrqQueueInner<T>> {
+        let mut inner = self.inner.lock();
+        if inner.items.capacity() < self.capacity {
+            let additional = self.capacity - inner.items.len();
+            inner.items.reserve_exact(additional);
+        }
+        inner
+    }
+    /// Waits for the next item.
+    pub fn pop(&self) -> IrqQueuePop<'_, T> {
+        IrqQueuePop { queue: self }
+    }
+}
+
+pub struct IrqQueuePop<'a, T> {
+    queue: &'a IrqQueue<T>,
+}
+impl<T> Future for IrqQueuePop<'_, T> {
+    type Output = T;
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<T> {
+        without_interrupts(|| {
+            let mut inner = self.queue.lock_reserved();
+            match inner.items.pop_front() {
+                Some(item) => Poll::Ready(item),
+                None => {
+                    inner.waker = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+}
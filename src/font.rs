@@ -0,0 +1,362 @@
+//! The registry of bitmap fonts.
+//!
+//! Fonts are registered with a name and looked up by FontId. The embedded
+//! 8x16 font is always registered as "default", and PSF fonts (version 1 or
+//! 2, e.g. a kanji font) can be loaded from the ESP. When a font does not
+//! have a glyph, the other registered fonts are tried in the order of
+//! registration.
+
+use crate::assets;
+use crate::chainload;
+use crate::console;
+use crate::graphics::draw_point;
+use crate::graphics::parse_font;
+use crate::graphics::Bitmap;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::uefi;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Fonts that are loaded from the ESP at boot if they exist.
+const BOOT_FONTS: &[(&str, &str)] = &[
+    ("psf", "/EFI/wasabi/font.psf"),
+    ("kanji", "/EFI/wasabi/kanji.psf"),
+];
+const MAX_GLYPH_SIZE: usize = 64;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x02;
+const PSF1_MODE_SEQ: u8 = 0x04;
+const PSF1_SEPARATOR: u16 = 0xffff;
+const PSF1_START_SEQ: u16 = 0xfffe;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HAS_UNICODE_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xff;
+const PSF2_START_SEQ: u8 = 0xfe;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FontId(usize);
+impl FontId {
+    /// The embedded 8x16 font.
+    pub const DEFAULT: FontId = FontId(0);
+}
+
+pub struct Font {
+    name: String,
+    width: usize,
+    height: usize,
+    /// Glyph bitmaps, MSB first, with each row padded to a byte.
+    glyphs: Vec<u8>,
+    /// Characters to glyph indexes.
+    map: BTreeMap<char, usize>,
+}
+impl Font {
+    fn new(name: &str, width: usize, height: usize) -> Self {
+        Self {
+            name: name.into(),
+            width,
+            height,
+            glyphs: Vec::new(),
+            map: BTreeMap::new(),
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn width(&self) -> i64 {
+        self.width as i64
+    }
+    pub fn height(&self) -> i64 {
+        self.height as i64
+    }
+    pub fn num_glyphs(&self) -> usize {
+        self.map.len()
+    }
+    fn bytes_per_glyph(&self) -> usize {
+        self.width.div_ceil(8) * self.height
+    }
+    fn push_glyph(&mut self, chars: impl IntoIterator<Item = char>, bitmap: &[u8]) {
+        let index = self.glyphs.len() / self.bytes_per_glyph();
+        self.glyphs.extend_from_slice(bitmap);
+        for c in chars {
+            self.map.insert(c, index);
+        }
+    }
+    /// Builds a font from the font.txt format (8x16, `*` for set pixels).
+    pub fn from_text(name: &str, source: &str) -> Self {
+        let mut font = Self::new(name, 8, 16);
+        for c in 0..=u8::MAX {
+            let Some(glyph) = parse_font(source, c as char) else {
+                continue;
+            };
+            let mut bitmap = [0u8; 16];
+            for (row, line) in bitmap.iter_mut().zip(glyph.iter()) {
+                for (x, pixel) in line.iter().enumerate() {
+                    if *pixel == '*' {
+                        *row |= 0x80 >> x;
+                    }
+                }
+            }
+            font.push_glyph([c as char], &bitmap);
+        }
+        font
+    }
+    /// Parses a PC Screen Font (PSF1 or PSF2).
+    pub fn from_psf(name: &str, data: &[u8]) -> Result<Self> {
+        if data.starts_with(&PSF1_MAGIC) {
+            Self::from_psf1(name, data)
+        } else if data.starts_with(&PSF2_MAGIC) {
+            Self::from_psf2(name, data)
+        } else {
+            Err("Not a PSF font")
+        }
+    }
+    fn from_psf1(name: &str, data: &[u8]) -> Result<Self> {
+        let mode = *data.get(2).ok_or("PSF header is truncated")?;
+        let height = *data.get(3).ok_or("PSF header is truncated")? as usize;
+        let count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+        let has_table = mode & (PSF1_MODE_HAS_TABLE | PSF1_MODE_SEQ) != 0;
+        let bitmaps = data.get(4..4 + count * height).ok_or("PSF is truncated")?;
+        let mut table = Vec::new();
+        if has_table {
+            // グリフごとにUCS-2の文字の並びが0xffffで区切られている
+            let mut units = data[4 + count * height..]
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]));
+            for _ in 0..count {
+                let mut chars = Vec::new();
+                let mut in_seq = false;
+                for u in units.by_ref() {
+                    match u {
+                        PSF1_SEPARATOR => break,
+                        PSF1_START_SEQ => in_seq = true,
+                        _ if in_seq => {}
+                        _ => chars.extend(char::from_u32(u as u32)),
+                    }
+                }
+                table.push(chars);
+            }
+        }
+        Self::from_bitmaps(name, 8, height, height, bitmaps, has_table.then_some(table))
+    }
+    fn from_psf2(name: &str, data: &[u8]) -> Result<Self> {
+        let field = |i: usize| -> Result<usize> {
+            let b = data
+                .get(i * 4..i * 4 + 4)
+                .ok_or("PSF header is truncated")?;
+            Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        };
+        let header_size = field(2)?;
+        let flags = field(3)? as u32;
+        let count = field(4)?;
+        let bytes_per_glyph = field(5)?;
+        let height = field(6)?;
+        let width = field(7)?;
+        if bytes_per_glyph == 0 {
+            return Err("PSF glyphs are too small");
+        }
+        let end = count
+            .checked_mul(bytes_per_glyph)
+            .and_then(|n| n.checked_add(header_size))
+            .ok_or("PSF is too large")?;
+        let bitmaps = data.get(header_size..end).ok_or("PSF is truncated")?;
+        let mut table = None;
+        if flags & PSF2_HAS_UNICODE_TABLE != 0 {
+            // グリフごとにUTF-8の文字列が0xffで区切られている
+            let mut entries = data[end..].split(|b| *b == PSF2_SEPARATOR);
+            table = Some(
+                (0..count)
+                    .map(|_| {
+                        let entry = entries.next().unwrap_or(&[]);
+                        let single = match entry.iter().position(|b| *b == PSF2_START_SEQ) {
+                            Some(i) => &entry[..i],
+                            None => entry,
+                        };
+                        String::from_utf8_lossy(single).chars().collect()
+                    })
+                    .collect(),
+            );
+        }
+        Self::from_bitmaps(name, width, height, bytes_per_glyph, bitmaps, table)
+    }
+    fn from_bitmaps(
+        name: &str,
+        width: usize,
+        height: usize,
+        bytes_per_glyph: usize,
+        bitmaps: &[u8],
+        table: Option<Vec<Vec<char>>>,
+    ) -> Result<Self> {
+        if width == 0 || height == 0 || width > MAX_GLYPH_SIZE || height > MAX_GLYPH_SIZE {
+            return Err("Unsupported glyph size");
+        }
+        let mut font = Self::new(name, width, height);
+        if bytes_per_glyph < font.bytes_per_glyph() {
+            return Err("PSF glyphs are too small");
+        }
+        let glyph_size = font.bytes_per_glyph();
+        for (i, bitmap) in bitmaps.chunks_exact(bytes_per_glyph).enumerate() {
+            let bitmap = &bitmap[..glyph_size];
+            match &table {
+                Some(table) => font.push_glyph(table.get(i).into_iter().flatten().copied(), bitmap),
+                // 表が無ければグリフの番号がそのまま文字コード
+                None => font.push_glyph(char::from_u32(i as u32), bitmap),
+            }
+        }
+        Ok(font)
+    }
+    pub fn has_glyph(&self, c: char) -> bool {
+        self.map.contains_key(&c)
+    }
+    /// Draws c with its top-left corner at (x, y). Returns false if the font
+    /// does not have it.
+    pub fn draw_char<T: Bitmap>(&self, buf: &mut T, x: i64, y: i64, color: u32, c: char) -> bool {
+        let Some(index) = self.map.get(&c) else {
+            return false;
+        };
+        let size = self.bytes_per_glyph();
+        let stride = self.width.div_ceil(8);
+        let glyph = &self.glyphs[index * size..(index + 1) * size];
+        for (dy, row) in glyph.chunks_exact(stride).enumerate() {
+            for dx in 0..self.width {
+                if row[dx / 8] & (0x80 >> (dx % 8)) != 0 {
+                    let _ = draw_point(buf, color, x + dx as i64, y + dy as i64);
+                }
+            }
+        }
+        true
+    }
+}
+
+static FONTS: Mutex<Vec<Font>> = Mutex::new(Vec::new());
+
+fn with_fonts<T>(f: impl FnOnce(&mut Vec<Font>) -> T) -> T {
+    let mut fonts = FONTS.lock();
+    if fonts.is_empty() {
+        let source = assets::get("fonts/font.txt").expect("fonts/font.txt is not bundled");
+        let source = core::str::from_utf8(&source).expect("font.txt is not UTF-8");
+        fonts.push(Font::from_text("default", source));
+    }
+    f(&mut fonts)
+}
+
+/// Registers the embedded font and the fonts found on the ESP. Call before
+/// the kernel takes over the interrupts, since it uses boot services.
+pub fn init() {
+    with_fonts(|_| {});
+    let Some(efi_system_table) = uefi::system_table() else {
+        return;
+    };
+    for (name, path) in BOOT_FONTS {
+        let Ok(data) = uefi::read_file(efi_system_table, path) else {
+            continue;
+        };
+        match Font::from_psf(name, &data).and_then(register) {
+            Ok(_) => crate::info!("loaded {name} from {path}"),
+            Err(e) => crate::warn!("{path}: {e}"),
+        }
+    }
+}
+
+pub fn register(font: Font) -> Result<FontId> {
+    with_fonts(|fonts| {
+        if fonts.iter().any(|f| f.name == font.name) {
+            return Err("Font is already registered");
+        }
+        fonts.push(font);
+        Ok(FontId(fonts.len() - 1))
+    })
+}
+
+pub fn find(name: &str) -> Option<FontId> {
+    with_fonts(|fonts| fonts.iter().position(|f| f.name == name).map(FontId))
+}
+
+/// The size of the character cell of the font.
+pub fn cell_size(id: FontId) -> (i64, i64) {
+    with_fonts(|fonts| {
+        let font = fonts.get(id.0).unwrap_or(&fonts[0]);
+        (font.width(), font.height())
+    })
+}
+
+/// The font to draw c with: the font itself, or the first font that has c.
+fn font_for(fonts: &[Font], id: FontId, c: char) -> &Font {
+    let primary = fonts.get(id.0).unwrap_or(&fonts[0]);
+    core::iter::once(primary)
+        .chain(fonts.iter())
+        .find(|f| f.has_glyph(c))
+        .unwrap_or(primary)
+}
+
+/// The width of c, taking the fallback fonts into account.
+pub fn char_width(id: FontId, c: char) -> i64 {
+    with_fonts(|fonts| font_for(fonts, id, c).width())
+}
+
+/// Draws c with the font, or with a fallback font if it does not have c.
+/// Returns the width of the glyph drawn.
+pub fn draw_char<T: Bitmap>(buf: &mut T, id: FontId, x: i64, y: i64, color: u32, c: char) -> i64 {
+    with_fonts(|fonts| {
+        let font = font_for(fonts, id, c);
+        font.draw_char(buf, x, y, color, c);
+        font.width()
+    })
+}
+
+/// Draws s from (x, y) and returns the width drawn.
+pub fn draw_str<T: Bitmap>(buf: &mut T, id: FontId, x: i64, y: i64, color: u32, s: &str) -> i64 {
+    let mut w = 0;
+    for c in s.chars() {
+        w += draw_char(buf, id, x + w, y, color, c);
+    }
+    w
+}
+
+/// The `font` command.
+pub fn cmd_font(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    match args {
+        [] | ["list"] => {
+            let current = console::font();
+            // 出力先がコンソールだとフォントを使って描くので、ロックを外してから書く
+            let lines: Vec<String> = with_fonts(|fonts| {
+                fonts
+                    .iter()
+                    .enumerate()
+                    .map(|(i, f)| {
+                        let marker = if FontId(i) == current { " *" } else { "" };
+                        format!(
+                            "{:12} {}x{} {} glyphs{marker}",
+                            f.name,
+                            f.width,
+                            f.height,
+                            f.num_glyphs()
+                        )
+                    })
+                    .collect()
+            });
+            for line in lines {
+                let _ = writeln!(out, "{line}");
+            }
+            Ok(())
+        }
+        ["load", name, path] => {
+            let efi_system_table = uefi::system_table().ok_or("EFI context is not initialized")?;
+            let data =
+                chainload::with_firmware_interrupts(|| uefi::read_file(efi_system_table, path))?;
+            register(Font::from_psf(name, &data)?)?;
+            Ok(())
+        }
+        ["set", name] => {
+            let id = find(name).ok_or("Font not found")?;
+            console::set_font(id);
+            Ok(())
+        }
+        _ => Err("usage: font [list | load NAME PATH | set NAME]"),
+    }
+}
@@ -0,0 +1,79 @@
+//! The x87 FPU and SSE.
+//!
+//! The kernel itself uses the SSE registers (e.g. in mem.rs), so the state
+//! of a user program is saved with FXSAVE on each syscall (see syscall.rs),
+//! and the state of each task on each task switch (see task.rs). Interrupt
+//! handlers save the registers they use, as the x86-interrupt ABI treats
+//! all of them as callee-saved.
+
+use crate::arch::read_cr0;
+use crate::arch::read_cr4;
+use crate::arch::write_cr0;
+use crate::arch::write_cr4;
+use crate::arch::write_xcr0;
+use core::arch::asm;
+use core::arch::x86_64::__cpuid;
+
+const CR0_MP: u64 = 1 << 1;
+const CR0_EM: u64 = 1 << 2;
+const CR0_TS: u64 = 1 << 3;
+const CR0_NE: u64 = 1 << 5;
+const CR4_OSFXSR: u64 = 1 << 9;
+const CR4_OSXMMEXCPT: u64 = 1 << 10;
+const CR4_OSXSAVE: u64 = 1 << 18;
+
+const CPUID1_EDX_FXSR: u32 = 1 << 24;
+const CPUID1_EDX_SSE2: u32 = 1 << 26;
+const CPUID1_ECX_XSAVE: u32 = 1 << 26;
+
+const XCR0_X87: u64 = 1 << 0;
+const XCR0_SSE: u64 = 1 << 1;
+
+/// Enables the x87 FPU and SSE so that f32/f64 and SIMD instructions work.
+///
+/// Only the x87 and SSE states are enabled in XCR0, so FpuState (FXSAVE)
+/// covers the whole state.
+pub fn init() {
+    let cpuid = unsafe { __cpuid(1) };
+    if cpuid.edx & (CPUID1_EDX_FXSR | CPUID1_EDX_SSE2) != CPUID1_EDX_FXSR | CPUID1_EDX_SSE2 {
+        panic!("CPU does not support FXSAVE and SSE2");
+    }
+    unsafe {
+        write_cr0((read_cr0() & !(CR0_EM | CR0_TS)) | CR0_MP | CR0_NE);
+        let mut cr4 = read_cr4() | CR4_OSFXSR | CR4_OSXMMEXCPT;
+        if cpuid.ecx & CPUID1_ECX_XSAVE != 0 {
+            cr4 |= CR4_OSXSAVE;
+        }
+        write_cr4(cr4);
+        if cr4 & CR4_OSXSAVE != 0 {
+            write_xcr0(XCR0_X87 | XCR0_SSE);
+        }
+        // x87の制御ワードとMXCSRを既定値（全例外マスク）に戻す
+        asm!("fninit");
+        let mxcsr: u32 = 0x1f80;
+        asm!("ldmxcsr [{}]", in(reg) &mxcsr);
+    }
+}
+
+/// x87/SSE register state saved with FXSAVE, e.g. by the assembly of the
+/// task switch.
+#[repr(C, align(16))]
+#[derive(Clone)]
+pub struct FpuState {
+    area: [u8; 512],
+}
+impl FpuState {
+    /// The state right after init(), to start a new task with.
+    pub fn initial() -> Self {
+        let mut s = Self { area: [0; 512] };
+        // FCW = 0x037f, MXCSR = 0x1f80
+        s.area[0..2].copy_from_slice(&0x037fu16.to_le_bytes());
+        s.area[24..28].copy_from_slice(&0x1f80u32.to_le_bytes());
+        s
+    }
+}
+impl Default for FpuState {
+    fn default() -> Self {
+        Self::initial()
+    }
+}
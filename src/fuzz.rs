@@ -0,0 +1,206 @@
+//! Entry points for fuzzing the parsers of untrusted input on the host.
+//!
+//! Each function takes arbitrary bytes and must never panic nor touch
+//! hardware, so that it can be called from a cargo-fuzz target (see fuzz/).
+
+use crate::assets;
+#[cfg(feature = "storage")]
+use crate::block::partition;
+#[cfg(feature = "storage")]
+use crate::block::BlockDevice;
+use crate::bmp;
+use crate::elf;
+#[cfg(feature = "gui")]
+use crate::font::Font;
+use crate::graphics::parse_font;
+#[cfg(feature = "gui")]
+use crate::graphics::BackBuffer;
+use crate::input_replay;
+#[cfg(feature = "net")]
+use crate::net::arp;
+#[cfg(feature = "net")]
+use crate::net::dhcp;
+#[cfg(feature = "net")]
+use crate::net::dns;
+#[cfg(feature = "net")]
+use crate::net::ethernet::Frame;
+#[cfg(feature = "net")]
+use crate::net::http;
+#[cfg(feature = "net")]
+use crate::net::icmp;
+#[cfg(feature = "net")]
+use crate::net::ipv4;
+#[cfg(feature = "net")]
+use crate::net::mdns;
+#[cfg(feature = "net")]
+use crate::net::ntp;
+#[cfg(feature = "net")]
+use crate::net::tcp;
+#[cfg(feature = "net")]
+use crate::net::udp;
+#[cfg(feature = "net")]
+use crate::net::Ipv4Addr;
+#[cfg(feature = "storage")]
+use crate::result::Result;
+#[cfg(feature = "usb")]
+use crate::usb;
+#[cfg(feature = "gui")]
+use crate::window_protocol::Event;
+#[cfg(feature = "gui")]
+use crate::window_protocol::Request;
+
+pub fn font(data: &[u8]) {
+    let Ok(source) = core::str::from_utf8(data) else {
+        return;
+    };
+    for c in 0..=u8::MAX {
+        let _ = parse_font(source, c as char);
+    }
+}
+
+#[cfg(feature = "gui")]
+pub fn psf(data: &[u8]) {
+    if let Ok(font) = Font::from_psf("fuzz", data) {
+        let mut buf = BackBuffer::new(font.width(), font.height());
+        for c in ['A', 'あ', '\u{fffd}'] {
+            font.draw_char(&mut buf, 0, 0, 0xffffff, c);
+        }
+    }
+}
+
+pub fn bmp(data: &[u8]) {
+    if let Ok(bitmap) = bmp::decode(data) {
+        let r = bitmap.rect();
+        assert_eq!(bitmap.pixels().len() as i64, r.w * r.h * 4);
+    }
+}
+
+pub fn input_replay(data: &[u8]) {
+    let Ok(text) = core::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(events) = input_replay::parse(text) {
+        let _ = input_replay::parse(&input_replay::serialize(&events));
+    }
+}
+
+pub fn assets(data: &[u8]) {
+    for asset in assets::parse_bundle(data) {
+        let _ = asset.data();
+    }
+}
+
+#[cfg(feature = "net")]
+pub fn net_mdns(data: &[u8]) {
+    let _ = mdns::handle_query(data, "wasabi", Ipv4Addr::new(10, 0, 2, 15));
+}
+
+/// The receive path from the Ethernet frame down, without the replies.
+#[cfg(feature = "net")]
+pub fn net_packet(data: &[u8]) {
+    let Some(frame) = Frame::parse(data) else {
+        return;
+    };
+    if let Some(packet) = arp::Packet::parse(frame.payload) {
+        assert_eq!(arp::Packet::parse(&packet.to_bytes()), Some(packet));
+    }
+    if let Some(packet) = ipv4::Packet::parse(frame.payload) {
+        if let Some(message) = icmp::Message::parse(packet.payload) {
+            let _ = message.echo();
+            assert_eq!(ipv4::checksum(&message.to_bytes()), 0);
+        }
+        if let Some(header) = udp::Header::parse(&packet) {
+            if let Some(message) = dhcp::Message::parse(header.data) {
+                let _ = dhcp::Message::parse(&message.to_bytes());
+            }
+            if let [a, b, ..] = header.data {
+                let _ = dns::parse_response(header.data, u16::from_be_bytes([*a, *b]));
+            }
+            if let Some(reply) = ntp::Packet::parse(header.data) {
+                assert_eq!(ntp::Packet::parse(&reply.to_bytes()), Some(reply));
+                let _ = ntp::Sample::new(&reply, 0, u64::MAX);
+            }
+        }
+        if let Some(segment) = tcp::Segment::parse(&packet) {
+            let bytes = segment.to_bytes(packet.src, packet.dst);
+            let rebuilt = ipv4::Packet {
+                payload: &bytes,
+                ..packet
+            };
+            assert_eq!(tcp::Segment::parse(&rebuilt), Some(segment));
+            if let Ok(response) = http::Response::parse(segment.data) {
+                if let Some(location) = response.header("Location") {
+                    if let Ok(url) = http::Url::parse("http://example.com/a/b") {
+                        let _ = url.join(location);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "gui")]
+pub fn window_protocol(data: &[u8]) {
+    let mut rest = data;
+    while let Ok((_, n)) = Request::decode(rest) {
+        rest = &rest[n..];
+    }
+    let _ = Event::decode(data);
+}
+
+#[cfg(feature = "usb")]
+pub fn usb_descriptors(data: &[u8]) {
+    let _ = usb::DeviceDescriptor::parse(data);
+    if let Ok(config) = usb::ConfigurationDescriptor::parse(data) {
+        for iface in config.default_interfaces() {
+            let _ = usb::hid::is_boot_mouse(iface);
+            for ep in &iface.endpoints {
+                let _ = ep.xhci_interval(usb::xhci::SPEED_FULL);
+                let _ = ep.xhci_interval(usb::xhci::SPEED_HIGH);
+            }
+        }
+    }
+    let _ = usb::hid::parse_boot_mouse_report(data);
+}
+
+/// A disk whose contents are the fuzz input.
+#[cfg(feature = "storage")]
+struct MemoryDisk<'a>(&'a [u8]);
+#[cfg(feature = "storage")]
+impl BlockDevice for MemoryDisk<'_> {
+    fn name(&self) -> &str {
+        "fuzz"
+    }
+    fn block_size(&self) -> usize {
+        512
+    }
+    fn block_count(&self) -> u64 {
+        (self.0.len() / 512) as u64
+    }
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        crate::block::check_range(self, lba, buf.len())?;
+        let start = lba as usize * 512;
+        buf.copy_from_slice(&self.0[start..start + buf.len()]);
+        Ok(())
+    }
+    fn write_blocks(&self, _lba: u64, _buf: &[u8]) -> Result<()> {
+        Err("read-only")
+    }
+}
+
+#[cfg(feature = "storage")]
+pub fn partition_table(data: &[u8]) {
+    if let Ok(partitions) = partition::read_table(&MemoryDisk(data)) {
+        for p in partitions {
+            let _ = (p.is_esp(), p.type_name());
+        }
+    }
+}
+
+pub fn elf(data: &[u8]) {
+    if let Ok(elf) = elf::parse(data) {
+        for segment in &elf.segments {
+            assert!(segment.data.len() as u64 <= segment.mem_size);
+        }
+    }
+}
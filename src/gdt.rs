@@ -0,0 +1,110 @@
+use crate::arch::load_gdtr;
+use crate::arch::load_task_register;
+use crate::arch::read_cs;
+use crate::arch::read_gdtr;
+use crate::arch::DescriptorTablePointer;
+use crate::percpu;
+use alloc::boxed::Box;
+use alloc::vec;
+use core::mem::size_of;
+use core::mem::size_of_val;
+
+#[repr(C, packed)]
+pub struct TaskStateSegment {
+    _reserved0: u32,
+    pub rsp: [u64; 3],
+    _reserved1: u64,
+    pub ist: [u64; 7],
+    _reserved2: u64,
+    _reserved3: u16,
+    iomap_base: u16,
+}
+const _: () = assert!(size_of::<TaskStateSegment>() == 104);
+
+/// IST index used by the double fault handler.
+pub const IST_DOUBLE_FAULT: u8 = 1;
+const IST_STACK_SIZE: usize = 64 * 1024;
+
+/// The firmware's descriptors are kept in the first entries, and the ones
+/// added by the kernel follow at fixed positions, so that the selectors are
+/// the same on every CPU.
+const FIRMWARE_ENTRIES: usize = 16;
+/// Loaded by syscall. KERNEL_DS must follow KERNEL_CS.
+pub const KERNEL_CS: u16 = 0x80;
+pub const KERNEL_DS: u16 = 0x88;
+/// sysret loads USER_DS from this + 8 and USER_CS from this + 16.
+pub const SYSRET_BASE: u16 = 0x90 | 3;
+pub const USER_DS: u16 = 0x98 | 3;
+pub const USER_CS: u16 = 0xa0 | 3;
+const TSS_SELECTOR: u16 = 0xa8;
+
+// 64bit code / data segments (base and limit are ignored in 64bit mode)
+const DESC_KERNEL_CODE: u64 = 0x00af_9a00_0000_ffff;
+const DESC_KERNEL_DATA: u64 = 0x00cf_9200_0000_ffff;
+const DESC_USER_DATA: u64 = 0x00cf_f200_0000_ffff;
+const DESC_USER_CODE: u64 = 0x00af_fa00_0000_ffff;
+
+fn alloc_stack(size: usize) -> u64 {
+    let stack = Box::leak(vec![0u8; size].into_boxed_slice());
+    // スタックは下位アドレスに向かって伸びるので末尾を渡す (16バイト境界に揃える)
+    (stack.as_ptr() as u64 + size as u64) & !0xf
+}
+
+fn tss_descriptor(tss: &TaskStateSegment) -> [u64; 2] {
+    let base = tss as *const TaskStateSegment as u64;
+    let limit = (size_of::<TaskStateSegment>() - 1) as u64;
+    // Present, DPL=0, Type=0x9 (Available 64-bit TSS)
+    let low = (limit & 0xffff)
+        | (base & 0xff_ffff) << 16
+        | 0x89 << 40
+        | ((limit >> 16) & 0xf) << 48
+        | ((base >> 24) & 0xff) << 56;
+    [low, base >> 32]
+}
+
+/// Switches to a GDT that has a TSS so that interrupt stacks (IST) can be
+/// used, and the segments for user mode.
+///
+/// The descriptors set up by the firmware are kept as is, so the current
+/// CS/DS and the selectors in the IDT stay valid. Call after the per-CPU
+/// block is installed, as the TSS is registered there.
+pub fn init() {
+    let tss = Box::leak(Box::new(TaskStateSegment {
+        _reserved0: 0,
+        rsp: [0; 3],
+        _reserved1: 0,
+        ist: [alloc_stack(IST_STACK_SIZE), 0, 0, 0, 0, 0, 0],
+        _reserved2: 0,
+        _reserved3: 0,
+        iomap_base: size_of::<TaskStateSegment>() as u16,
+    }));
+    let current = read_gdtr();
+    // APはBSPのGDTを読み込んでから呼ぶので、先頭のファームウェア部分だけをコピーする
+    let n = ((current.limit as usize + 1) / size_of::<u64>()).min(FIRMWARE_ENTRIES);
+    assert!(
+        (read_cs() as usize) < n * size_of::<u64>(),
+        "CS is out of the firmware's GDT entries"
+    );
+    let mut gdt = vec![0u64; FIRMWARE_ENTRIES];
+    // SAFETY: GDTR points to the GDT which is in use by the CPU
+    gdt[..n].copy_from_slice(unsafe { core::slice::from_raw_parts(current.base as *const u64, n) });
+    gdt.extend_from_slice(&[
+        DESC_KERNEL_CODE,
+        DESC_KERNEL_DATA,
+        0,
+        DESC_USER_DATA,
+        DESC_USER_CODE,
+    ]);
+    debug_assert_eq!(gdt.len() * size_of::<u64>(), TSS_SELECTOR as usize);
+    gdt.extend_from_slice(&tss_descriptor(tss));
+    let gdt = Box::leak(gdt.into_boxed_slice());
+    let gdtr = DescriptorTablePointer {
+        limit: (size_of_val(gdt) - 1) as u16,
+        base: gdt.as_ptr() as u64,
+    };
+    unsafe {
+        load_gdtr(&gdtr);
+        load_task_register(TSS_SELECTOR);
+    }
+    percpu::this().set_tss(tss);
+}
@@ -0,0 +1,898 @@
+use crate::assets;
+use crate::result::Result;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cmp::max;
+use core::cmp::min;
+use core::ptr::copy_nonoverlapping;
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::Ordering;
+
+pub trait Bitmap {
+    fn bytes_per_pixel(&self) -> i64;
+    fn pixels_per_scan_line(&self) -> i64;
+    fn width(&self) -> i64;
+    fn height(&self) -> i64;
+    fn buf_mut(&mut self) -> *mut u8;
+
+    /// # Safety
+    ///
+    /// Returned pinter is valit as long as the given coordinates are valid.
+    /// whch means that passing is_in_*_range tests.
+    unsafe fn unchecked_pixel_at_mut(&mut self, x: i64, y: i64) -> *mut u32 {
+        self.buf_mut()
+            .add(((y * self.pixels_per_scan_line() + x) * self.bytes_per_pixel()) as usize)
+            as *mut u32
+    }
+    fn pixel_at_mut(&mut self, x: i64, y: i64) -> Option<*mut u32> {
+        if self.is_in_x_range(x) && self.is_in_y_range(y) {
+            // SAFETY: (x, y) is always validated by the cheks above.
+            unsafe { Some(&mut *self.unchecked_pixel_at_mut(x, y)) }
+        } else {
+            None
+        }
+    }
+    fn is_in_x_range(&self, px: i64) -> bool {
+        0 <= px && px < min(self.width(), self.pixels_per_scan_line())
+    }
+    fn is_in_y_range(&self, py: i64) -> bool {
+        0 <= py && py < self.height()
+    }
+}
+
+/// # Safety
+///
+/// (x, y) must be a valid point in the buf.
+pub unsafe fn unchecked_draw_point<T: Bitmap>(buf: &mut T, color: u32, x: i64, y: i64) {
+    *buf.unchecked_pixel_at_mut(x, y) = color;
+}
+
+pub fn draw_point<T: Bitmap>(buf: &mut T, color: u32, x: i64, y: i64) -> Result<()> {
+    unsafe {
+        *(buf.pixel_at_mut(x, y).ok_or("Out of Range")?) = color;
+    }
+    Ok(())
+}
+
+pub fn fill_rect<T: Bitmap>(
+    buf: &mut T,
+    color: u32,
+    px: i64,
+    py: i64,
+    w: i64,
+    h: i64,
+) -> Result<()> {
+    if !buf.is_in_x_range(px)
+        || !buf.is_in_y_range(py)
+        || !buf.is_in_x_range(px + w - 1)
+        || !buf.is_in_y_range(py + h - 1)
+    {
+        return Err("Out of Range");
+    }
+    for y in py..py + h {
+        for x in px..px + w {
+            unsafe {
+                unchecked_draw_point(buf, color, x, y);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn calc_slope_point(da: i64, db: i64, ia: i64) -> Option<i64> {
+    if da < db {
+        None
+    } else if da == 0 {
+        Some(0)
+    } else if (0..=da).contains(&ia) {
+        Some((2 * db * ia + da) / da / 2)
+    } else {
+        None
+    }
+}
+
+fn line_points(x0: i64, y0: i64, x1: i64, y1: i64) -> impl Iterator<Item = (i64, i64)> {
+    let dx = (x1 - x0).abs();
+    let dy = (y1 - y0).abs();
+    let sx = (x1 - x0).signum();
+    let sy = (y1 - y0).signum();
+    let (da, db) = if dx >= dy { (dx, dy) } else { (dy, dx) };
+    (0..da).flat_map(move |ia| {
+        calc_slope_point(da, db, ia).map(|ib| {
+            let (rx, ry) = if dx >= dy { (ia, ib) } else { (ib, ia) };
+            (x0 + rx * sx, y0 + ry * sy)
+        })
+    })
+}
+
+pub fn draw_line<T: Bitmap>(
+    buf: &mut T,
+    color: u32,
+    x0: i64,
+    y0: i64,
+    x1: i64,
+    y1: i64,
+) -> Result<()> {
+    if !buf.is_in_x_range(x0)
+        || !buf.is_in_y_range(y0)
+        || !buf.is_in_x_range(x1)
+        || !buf.is_in_y_range(y1)
+    {
+        return Err("Out of Range");
+    }
+    for (x, y) in line_points(x0, y0, x1, y1) {
+        draw_point(buf, color, x, y)?;
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineStyle {
+    /// Thickness in pixels.
+    pub width: i64,
+    /// Bit i (mod 32) tells whether the i-th pixel along the line is drawn.
+    pub pattern: u32,
+}
+impl LineStyle {
+    pub const SOLID: LineStyle = LineStyle::new(1, u32::MAX);
+    pub const DASHED: LineStyle = LineStyle::new(1, 0x00ff00ff);
+    pub const DOTTED: LineStyle = LineStyle::new(1, 0x33333333);
+    pub const fn new(width: i64, pattern: u32) -> Self {
+        Self { width, pattern }
+    }
+    pub const fn with_width(self, width: i64) -> Self {
+        Self::new(width, self.pattern)
+    }
+}
+impl Default for LineStyle {
+    fn default() -> Self {
+        Self::SOLID
+    }
+}
+
+/// Draws a line with the given thickness and dash pattern.
+///
+/// Thick lines are widened perpendicular to their major axis and centered on
+/// the line. Pixels that fall outside of buf are clipped.
+pub fn draw_line_styled<T: Bitmap>(
+    buf: &mut T,
+    color: u32,
+    x0: i64,
+    y0: i64,
+    x1: i64,
+    y1: i64,
+    style: LineStyle,
+) -> Result<()> {
+    if !buf.is_in_x_range(x0)
+        || !buf.is_in_y_range(y0)
+        || !buf.is_in_x_range(x1)
+        || !buf.is_in_y_range(y1)
+    {
+        return Err("Out of Range");
+    }
+    if style.width <= 0 {
+        return Err("Invalid line width");
+    }
+    let horizontal = (x1 - x0).abs() >= (y1 - y0).abs();
+    let begin = -(style.width - 1) / 2;
+    let end = begin + style.width;
+    for (i, (x, y)) in line_points(x0, y0, x1, y1).enumerate() {
+        if style.pattern & (1 << (i % 32)) == 0 {
+            continue;
+        }
+        for d in begin..end {
+            let (x, y) = if horizontal { (x, y + d) } else { (x + d, y) };
+            let _ = draw_point(buf, color, x, y);
+        }
+    }
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Point {
+    pub x: i64,
+    pub y: i64,
+}
+impl Point {
+    pub const fn new(x: i64, y: i64) -> Self {
+        Self { x, y }
+    }
+}
+
+/// Fills [x0, x1) on the row y, clipped to buf.
+fn fill_span<T: Bitmap>(buf: &mut T, color: u32, y: i64, x0: i64, x1: i64) {
+    if !buf.is_in_y_range(y) {
+        return;
+    }
+    let x0 = max(x0, 0);
+    let x1 = min(x1, min(buf.width(), buf.pixels_per_scan_line()));
+    for x in x0..x1 {
+        // SAFETY: x and y are clipped above
+        unsafe {
+            unchecked_draw_point(buf, color, x, y);
+        }
+    }
+}
+
+/// Fills a polygon using the even-odd rule. Parts outside of buf are clipped.
+///
+/// A pixel is filled when its center is inside the polygon, so polygons
+/// sharing an edge never overlap nor leave a gap between them.
+pub fn fill_polygon<T: Bitmap>(buf: &mut T, color: u32, points: &[Point]) {
+    if points.len() < 3 {
+        return;
+    }
+    let min_y = points.iter().map(|p| p.y).min().unwrap_or(0);
+    let max_y = points.iter().map(|p| p.y).max().unwrap_or(0);
+    let min_y = max(min_y, 0);
+    let max_y = min(max_y, buf.height());
+    let mut xs = Vec::new();
+    for y in min_y..max_y {
+        xs.clear();
+        for (i, p0) in points.iter().enumerate() {
+            let p1 = &points[(i + 1) % points.len()];
+            if p0.y == p1.y {
+                continue;
+            }
+            let (top, bottom) = if p0.y < p1.y { (p0, p1) } else { (p1, p0) };
+            if y < top.y || bottom.y <= y {
+                continue;
+            }
+            // 画素の中心 (y + 0.5) における交点のx座標を整数演算で求める
+            let dy2 = 2 * (bottom.y - top.y);
+            let num = (2 * (y - top.y) + 1) * (bottom.x - top.x);
+            xs.push(top.x + (num + dy2 / 2).div_euclid(dy2));
+        }
+        xs.sort_unstable();
+        for span in xs.chunks_exact(2) {
+            fill_span(buf, color, y, span[0], span[1]);
+        }
+    }
+}
+
+pub fn fill_triangle<T: Bitmap>(buf: &mut T, color: u32, p0: Point, p1: Point, p2: Point) {
+    fill_polygon(buf, color, &[p0, p1, p2]);
+}
+
+/// Fills the 4-connected region of the same color as (x, y) with color.
+///
+/// Pending seeds are kept on an explicit stack, one per horizontal span
+/// rather than one per pixel, so memory use stays small and no recursion
+/// happens even for large regions.
+pub fn flood_fill<T: Bitmap>(buf: &mut T, x: i64, y: i64, color: u32) -> Result<()> {
+    // SAFETY: pixel_at_mut checks the range
+    let target = unsafe { *buf.pixel_at_mut(x, y).ok_or("Out of Range")? };
+    if target == color {
+        return Ok(());
+    }
+    let is_target = |buf: &mut T, x: i64, y: i64| {
+        buf.is_in_x_range(x)
+            && buf.is_in_y_range(y)
+            // SAFETY: (x, y) is checked above
+            && unsafe { *buf.unchecked_pixel_at_mut(x, y) } == target
+    };
+    let mut stack = vec![(x, y)];
+    while let Some((x, y)) = stack.pop() {
+        if !is_target(buf, x, y) {
+            continue;
+        }
+        let mut left = x;
+        while is_target(buf, left - 1, y) {
+            left -= 1;
+        }
+        let mut right = x;
+        while is_target(buf, right + 1, y) {
+            right += 1;
+        }
+        for x in left..=right {
+            // SAFETY: every pixel in the span has been checked by is_target
+            unsafe { unchecked_draw_point(buf, color, x, y) };
+        }
+        // 上下の行では、連続する塗りつぶし対象ごとに1つだけ種を積む
+        for ny in [y - 1, y + 1] {
+            let mut in_span = false;
+            for nx in left..=right {
+                if is_target(buf, nx, ny) {
+                    if !in_span {
+                        stack.push((nx, ny));
+                    }
+                    in_span = true;
+                } else {
+                    in_span = false;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+static FONT_SOURCE: AtomicPtr<String> = AtomicPtr::new(core::ptr::null_mut());
+
+/// The default font, expanded from the asset bundle on the first use.
+fn font_source() -> &'static str {
+    let p = FONT_SOURCE.load(Ordering::Acquire);
+    if !p.is_null() {
+        return unsafe { &*p };
+    }
+    let data = assets::get("fonts/font.txt").expect("fonts/font.txt is not bundled");
+    let source = String::from_utf8(data.into_owned()).expect("font.txt is not UTF-8");
+    let p = Box::leak(Box::new(source));
+    FONT_SOURCE.store(p, Ordering::Release);
+    p
+}
+
+pub(crate) fn lookup_font(c: char) -> Option<[[char; 8]; 16]> {
+    parse_font(font_source(), c)
+}
+
+/// Finds the glyph of c in a font.txt-style source.
+pub fn parse_font(source: &str, c: char) -> Option<[[char; 8]; 16]> {
+    if let Ok(c) = u8::try_from(c) {
+        let mut fi = source.split('\n');
+        while let Some(line) = fi.next() {
+            if let Some(line) = line.strip_prefix("0x") {
+                if let Ok(idx) = u8::from_str_radix(line, 16) {
+                    if idx != c {
+                        continue;
+                    }
+                    let mut font = [['*'; 8]; 16];
+                    for (y, line) in fi.clone().take(16).enumerate() {
+                        for (x, c) in line.chars().enumerate() {
+                            if let Some(e) = font[y].get_mut(x) {
+                                *e = c;
+                            }
+                        }
+                    }
+                    return Some(font);
+                }
+            }
+        }
+    }
+    None
+}
+
+pub fn draw_font_fg<T: Bitmap>(buf: &mut T, x: i64, y: i64, color: u32, c: char) {
+    if let Some(font) = lookup_font(c) {
+        for (dy, row) in font.iter().enumerate() {
+            for (dx, pixel) in row.iter().enumerate() {
+                let color = match pixel {
+                    '*' => color,
+                    _ => continue,
+                };
+                let _ = draw_point(buf, color, x + dx as i64, y + dy as i64);
+            }
+        }
+    }
+}
+
+pub fn draw_str_fg<T: Bitmap>(buf: &mut T, x: i64, y: i64, color: u32, s: &str) {
+    for (i, c) in s.chars().enumerate() {
+        draw_font_fg(buf, x + i as i64 * 8, y, color, c);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: i64,
+    pub y: i64,
+    pub w: i64,
+    pub h: i64,
+}
+impl Rect {
+    pub const fn new(x: i64, y: i64, w: i64, h: i64) -> Self {
+        Self { x, y, w, h }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.w <= 0 || self.h <= 0
+    }
+    pub fn right(&self) -> i64 {
+        self.x + self.w
+    }
+    pub fn bottom(&self) -> i64 {
+        self.y + self.h
+    }
+    pub fn area(&self) -> i64 {
+        if self.is_empty() {
+            0
+        } else {
+            self.w * self.h
+        }
+    }
+    pub fn contains(&self, x: i64, y: i64) -> bool {
+        self.x <= x && x < self.right() && self.y <= y && y < self.bottom()
+    }
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let x = max(self.x, other.x);
+        let y = max(self.y, other.y);
+        let r = Rect::new(
+            x,
+            y,
+            min(self.right(), other.right()) - x,
+            min(self.bottom(), other.bottom()) - y,
+        );
+        (!r.is_empty()).then_some(r)
+    }
+    /// Returns the smallest rect that covers both rects.
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let x = min(self.x, other.x);
+        let y = min(self.y, other.y);
+        Rect::new(
+            x,
+            y,
+            max(self.right(), other.right()) - x,
+            max(self.bottom(), other.bottom()) - y,
+        )
+    }
+    fn touches(&self, other: &Rect) -> bool {
+        self.x <= other.right()
+            && other.x <= self.right()
+            && self.y <= other.bottom()
+            && other.y <= self.bottom()
+    }
+}
+
+const MAX_DAMAGE_RECTS: usize = 16;
+
+/// A set of rects that have been changed since the last flush.
+///
+/// Overlapping or adjacent rects are merged, and once the list is full
+/// everything collapses into a single bounding box, so the cost of a flush
+/// never exceeds that of a full-screen copy.
+pub struct Damage {
+    rects: [Rect; MAX_DAMAGE_RECTS],
+    len: usize,
+}
+impl Damage {
+    pub const fn new() -> Self {
+        Self {
+            rects: [Rect::new(0, 0, 0, 0); MAX_DAMAGE_RECTS],
+            len: 0,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    pub fn rects(&self) -> &[Rect] {
+        &self.rects[..self.len]
+    }
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+    pub fn add(&mut self, r: Rect) {
+        if r.is_empty() {
+            return;
+        }
+        let mut r = r;
+        // 併合した結果さらに他の矩形と接することがあるので、変化がなくなるまで繰り返す
+        let mut i = 0;
+        while i < self.len {
+            if self.rects[i].touches(&r) {
+                r = r.union(&self.rects[i]);
+                self.len -= 1;
+                self.rects[i] = self.rects[self.len];
+                i = 0;
+            } else {
+                i += 1;
+            }
+        }
+        if self.len == MAX_DAMAGE_RECTS {
+            r = self.rects().iter().fold(r, |acc, e| acc.union(e));
+            self.len = 0;
+        }
+        self.rects[self.len] = r;
+        self.len += 1;
+    }
+}
+impl Default for Damage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A bitmap in memory that owns its pixels, e.g. the contents of a window.
+pub struct OwnedBitmap {
+    buf: Vec<u8>,
+    width: i64,
+    height: i64,
+}
+impl Bitmap for OwnedBitmap {
+    fn bytes_per_pixel(&self) -> i64 {
+        4
+    }
+    fn pixels_per_scan_line(&self) -> i64 {
+        self.width
+    }
+    fn width(&self) -> i64 {
+        self.width
+    }
+    fn height(&self) -> i64 {
+        self.height
+    }
+    fn buf_mut(&mut self) -> *mut u8 {
+        self.buf.as_mut_ptr()
+    }
+}
+impl OwnedBitmap {
+    /// A bitmap filled with color.
+    pub fn new(width: i64, height: i64, color: u32) -> Self {
+        let (width, height) = (max(0, width), max(0, height));
+        Self {
+            buf: color.to_le_bytes().repeat((width * height) as usize),
+            width,
+            height,
+        }
+    }
+    /// A bitmap of the pixels (0x00RRGGBB), row by row.
+    pub fn from_pixels(width: i64, height: i64, pixels: &[u32]) -> Result<Self> {
+        if width < 0 || height < 0 || pixels.len() as i64 != width * height {
+            return Err("The number of the pixels does not match the size");
+        }
+        Ok(Self {
+            buf: pixels.iter().flat_map(|p| p.to_le_bytes()).collect(),
+            width,
+            height,
+        })
+    }
+    pub fn rect(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+    pub fn pixel(&self, x: i64, y: i64) -> Option<u32> {
+        if !self.rect().contains(x, y) {
+            return None;
+        }
+        let i = ((y * self.width + x) * 4) as usize;
+        Some(u32::from_le_bytes(self.buf[i..i + 4].try_into().ok()?))
+    }
+    /// The raw pixels (0x00RRGGBB, row by row).
+    pub fn pixels(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+/// Copies src_rect of src to (x, y) on dst. The parts outside of either
+/// bitmap are clipped.
+pub fn blit<S: Bitmap, D: Bitmap>(
+    src: &mut S,
+    src_rect: Rect,
+    dst: &mut D,
+    x: i64,
+    y: i64,
+) -> Result<()> {
+    if src.bytes_per_pixel() != dst.bytes_per_pixel() {
+        return Err("Pixel format mismatch");
+    }
+    let src_bounds = Rect::new(
+        0,
+        0,
+        min(src.width(), src.pixels_per_scan_line()),
+        src.height(),
+    );
+    let dst_bounds = Rect::new(
+        src_rect.x - x,
+        src_rect.y - y,
+        min(dst.width(), dst.pixels_per_scan_line()),
+        dst.height(),
+    );
+    // 転送元の座標系で、両方のビットマップに収まる部分を求める
+    let Some(r) = src_rect
+        .intersection(&src_bounds)
+        .and_then(|r| r.intersection(&dst_bounds))
+    else {
+        return Ok(());
+    };
+    let (dx, dy) = (x - src_rect.x, y - src_rect.y);
+    let len = (r.w * src.bytes_per_pixel()) as usize;
+    for sy in r.y..r.bottom() {
+        // SAFETY: r is clipped to both of the bitmaps, and they do not
+        // overlap since one of them is borrowed mutably for each
+        unsafe {
+            let from = src.unchecked_pixel_at_mut(r.x, sy) as *const u8;
+            let to = dst.unchecked_pixel_at_mut(r.x + dx, sy + dy) as *mut u8;
+            copy_nonoverlapping(from, to, len);
+        }
+    }
+    Ok(())
+}
+
+/// An off-screen bitmap that records which areas have been drawn since the
+/// last flush so that only those areas are copied to the VRAM.
+pub struct BackBuffer {
+    buf: Vec<u8>,
+    width: i64,
+    height: i64,
+    damage: Damage,
+}
+impl Bitmap for BackBuffer {
+    fn bytes_per_pixel(&self) -> i64 {
+        4
+    }
+    fn pixels_per_scan_line(&self) -> i64 {
+        self.width
+    }
+    fn width(&self) -> i64 {
+        self.width
+    }
+    fn height(&self) -> i64 {
+        self.height
+    }
+    fn buf_mut(&mut self) -> *mut u8 {
+        self.buf.as_mut_ptr()
+    }
+}
+impl BackBuffer {
+    pub fn new(width: i64, height: i64) -> Self {
+        Self {
+            buf: vec![0; (width * height * 4) as usize],
+            width,
+            height,
+            damage: Damage::new(),
+        }
+    }
+    pub fn rect(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+    /// Marks the given area as changed. Areas outside of the buffer are ignored.
+    pub fn add_damage(&mut self, r: Rect) {
+        if let Some(r) = r.intersection(&self.rect()) {
+            self.damage.add(r);
+        }
+    }
+    pub fn add_damage_all(&mut self) {
+        self.add_damage(self.rect());
+    }
+    pub fn damage(&self) -> &Damage {
+        &self.damage
+    }
+    /// The raw pixels (0x00RRGGBB, row by row).
+    pub fn pixels(&self) -> &[u8] {
+        &self.buf
+    }
+    /// Copies the damaged areas to dst and clears the damage.
+    pub fn flush<T: Bitmap>(&mut self, dst: &mut T) -> Result<()> {
+        self.flush_at(dst, 0, 0)
+    }
+    /// Same as flush(), but the buffer is placed at (x, y) on dst.
+    pub fn flush_at<T: Bitmap>(&mut self, dst: &mut T, x: i64, y: i64) -> Result<()> {
+        if dst.bytes_per_pixel() != self.bytes_per_pixel() {
+            return Err("Pixel format mismatch");
+        }
+        let dst_rect = Rect::new(
+            -x,
+            -y,
+            min(dst.width(), dst.pixels_per_scan_line()),
+            dst.height(),
+        );
+        let bpp = self.bytes_per_pixel();
+        for r in self.damage.rects() {
+            let Some(r) = r.intersection(&dst_rect) else {
+                continue;
+            };
+            for sy in r.y..r.bottom() {
+                // SAFETY: r is clipped to both of the bitmaps
+                unsafe {
+                    let src = self
+                        .buf
+                        .as_ptr()
+                        .add(((sy * self.width + r.x) * bpp) as usize);
+                    let dst = dst.unchecked_pixel_at_mut(r.x + x, sy + y) as *mut u8;
+                    copy_nonoverlapping(src, dst, (r.w * bpp) as usize);
+                }
+            }
+        }
+        self.damage.clear();
+        Ok(())
+    }
+}
+
+/// The largest integer scale at which a virtual screen fits in the real one,
+/// or 1 if the virtual screen is empty.
+pub fn best_scale(virtual_w: i64, virtual_h: i64, real_w: i64, real_h: i64) -> i64 {
+    if virtual_w <= 0 || virtual_h <= 0 {
+        return 1;
+    }
+    max(1, min(real_w / virtual_w, real_h / virtual_h))
+}
+
+/// A low resolution screen that is enlarged by an integer factor when
+/// presented, e.g. 640x360 shown at 3x on a 1920x1080 panel.
+///
+/// Draw on it like a BackBuffer (including add_damage()), then call present().
+/// The image is centered in dst, or in the area given by set_area(), and the
+/// margins are left as they are.
+pub struct ScaledBuffer {
+    back: BackBuffer,
+    scale: i64,
+    area: Option<Rect>,
+}
+impl Bitmap for ScaledBuffer {
+    fn bytes_per_pixel(&self) -> i64 {
+        self.back.bytes_per_pixel()
+    }
+    fn pixels_per_scan_line(&self) -> i64 {
+        self.back.pixels_per_scan_line()
+    }
+    fn width(&self) -> i64 {
+        self.back.width()
+    }
+    fn height(&self) -> i64 {
+        self.back.height()
+    }
+    fn buf_mut(&mut self) -> *mut u8 {
+        self.back.buf_mut()
+    }
+}
+impl ScaledBuffer {
+    pub fn new(width: i64, height: i64, scale: i64) -> Self {
+        Self {
+            back: BackBuffer::new(width, height),
+            scale: max(1, scale),
+            area: None,
+        }
+    }
+    /// Uses the largest scale that fits in dst.
+    pub fn fit<T: Bitmap>(width: i64, height: i64, dst: &T) -> Self {
+        Self::new(
+            width,
+            height,
+            best_scale(width, height, dst.width(), dst.height()),
+        )
+    }
+    pub fn scale(&self) -> i64 {
+        self.scale
+    }
+    /// Shows the image in area of dst instead of the whole of it.
+    pub fn set_area(&mut self, area: Rect) {
+        self.area = Some(area);
+    }
+    /// The area of dst that the image is shown in.
+    pub fn area<T: Bitmap>(&self, dst: &T) -> Rect {
+        self.area
+            .unwrap_or_else(|| Rect::new(0, 0, dst.width(), dst.height()))
+    }
+    pub fn add_damage(&mut self, r: Rect) {
+        self.back.add_damage(r)
+    }
+    pub fn add_damage_all(&mut self) {
+        self.back.add_damage_all()
+    }
+    /// Where the top-left pixel is placed in dst.
+    pub fn origin<T: Bitmap>(&self, dst: &T) -> (i64, i64) {
+        let area = self.area(dst);
+        (
+            area.x + max(0, (area.w - self.back.width * self.scale) / 2),
+            area.y + max(0, (area.h - self.back.height * self.scale) / 2),
+        )
+    }
+    /// Converts a point on dst (e.g. the mouse position) to the virtual screen.
+    pub fn to_virtual<T: Bitmap>(&self, dst: &T, x: i64, y: i64) -> (i64, i64) {
+        let (ox, oy) = self.origin(dst);
+        (
+            (x - ox).div_euclid(self.scale),
+            (y - oy).div_euclid(self.scale),
+        )
+    }
+    /// Enlarges the damaged areas into dst and clears the damage.
+    pub fn present<T: Bitmap>(&mut self, dst: &mut T) -> Result<()> {
+        if dst.bytes_per_pixel() != self.bytes_per_pixel() {
+            return Err("Pixel format mismatch");
+        }
+        let s = self.scale;
+        let (ox, oy) = self.origin(dst);
+        let Some(dst_rect) = Rect::new(
+            0,
+            0,
+            min(dst.width(), dst.pixels_per_scan_line()),
+            dst.height(),
+        )
+        .intersection(&self.area(dst)) else {
+            self.back.damage.clear();
+            return Ok(());
+        };
+        let src = self.back.buf.as_ptr() as *const u32;
+        let mut row: Vec<u32> = Vec::new();
+        for r in self.back.damage.rects() {
+            let scaled = Rect::new(ox + r.x * s, oy + r.y * s, r.w * s, r.h * s);
+            let Some(clipped) = scaled.intersection(&dst_rect) else {
+                continue;
+            };
+            for y in r.y..r.bottom() {
+                // 1行ぶん横に引き伸ばしてから、縦にscale回コピーする
+                row.clear();
+                for x in r.x..r.right() {
+                    // SAFETY: r is within the back buffer
+                    let pixel = unsafe { *src.add((y * self.back.width + x) as usize) };
+                    row.extend(core::iter::repeat(pixel).take(s as usize));
+                }
+                let skip = (clipped.x - scaled.x) as usize;
+                for dy in 0..s {
+                    let py = oy + y * s + dy;
+                    if py < clipped.y || py >= clipped.bottom() {
+                        continue;
+                    }
+                    // SAFETY: the row is clipped to dst
+                    unsafe {
+                        let dst = dst.unchecked_pixel_at_mut(clipped.x, py);
+                        copy_nonoverlapping(row.as_ptr().add(skip), dst, clipped.w as usize);
+                    }
+                }
+            }
+        }
+        self.back.damage.clear();
+        Ok(())
+    }
+}
+
+/// A bitmap in memory for the tests on the host (host_test/), with a stride
+/// that can be wider than the visible width to catch drawing past the
+/// right edge.
+#[cfg(feature = "test_bitmap")]
+pub struct TestBitmap {
+    buf: Vec<u8>,
+    width: i64,
+    height: i64,
+    stride: i64,
+}
+#[cfg(feature = "test_bitmap")]
+impl Bitmap for TestBitmap {
+    fn bytes_per_pixel(&self) -> i64 {
+        4
+    }
+    fn pixels_per_scan_line(&self) -> i64 {
+        self.stride
+    }
+    fn width(&self) -> i64 {
+        self.width
+    }
+    fn height(&self) -> i64 {
+        self.height
+    }
+    fn buf_mut(&mut self) -> *mut u8 {
+        self.buf.as_mut_ptr()
+    }
+}
+#[cfg(feature = "test_bitmap")]
+impl TestBitmap {
+    pub fn new(width: i64, height: i64) -> Self {
+        Self::with_stride(width, height, width)
+    }
+    /// A bitmap whose rows are `stride` pixels apart in the buffer.
+    pub fn with_stride(width: i64, height: i64, stride: i64) -> Self {
+        assert!(width <= stride);
+        Self {
+            buf: vec![0; (stride * height * 4) as usize],
+            width,
+            height,
+            stride,
+        }
+    }
+    /// The pixel at (x, y), which may be in the padding past the width.
+    pub fn pixel(&self, x: i64, y: i64) -> u32 {
+        assert!((0..self.stride).contains(&x) && (0..self.height).contains(&y));
+        let i = ((y * self.stride + x) * 4) as usize;
+        u32::from_le_bytes([
+            self.buf[i],
+            self.buf[i + 1],
+            self.buf[i + 2],
+            self.buf[i + 3],
+        ])
+    }
+    /// The points of the buffer (including the padding) with the color.
+    pub fn points_of(&self, color: u32) -> Vec<(i64, i64)> {
+        (0..self.height)
+            .flat_map(|y| (0..self.stride).map(move |x| (x, y)))
+            .filter(|&(x, y)| self.pixel(x, y) == color)
+            .collect()
+    }
+    /// The raw pixels (0x00RRGGBB, row by row, including the padding).
+    pub fn pixels(&self) -> &[u8] {
+        &self.buf
+    }
+}
@@ -0,0 +1,307 @@
+//! Frame capture tests for the drawing code.
+//!
+//! Each scenario draws into an off-screen buffer of a fixed size, and the
+//! CRC32 of the resulting pixels is compared with the golden value recorded
+//! in SCENARIOS. Run them with `scripts/gui_test.sh`, which boots the kernel
+//! built with the `gui_test` feature in QEMU and checks the exit status.
+//!
+//! The same scenarios are rendered on the host by host_test/tests/golden.rs
+//! and compared with the PPM images in host_test/goldens, which show what
+//! the frames look like.
+//!
+//! When a change is intended to alter the output, check the new image and
+//! update the golden value with the one printed by the failing test.
+
+use crate::crc::crc32;
+use crate::cursor::Cursor;
+use crate::graphics::blit;
+use crate::graphics::draw_line;
+use crate::graphics::draw_line_styled;
+use crate::graphics::draw_str_fg;
+use crate::graphics::fill_rect;
+use crate::graphics::fill_triangle;
+use crate::graphics::flood_fill;
+use crate::graphics::BackBuffer;
+use crate::graphics::LineStyle;
+use crate::graphics::OwnedBitmap;
+use crate::graphics::Point;
+use crate::graphics::Rect;
+use crate::graphics::ScaledBuffer;
+use crate::input::KeyEvent;
+use crate::mouse::MouseButtons;
+use crate::qemu::exit_qemu;
+use crate::qemu::QemuExitCode;
+use crate::result::Result;
+use crate::screen::draw_demo_shapes;
+use crate::screen::draw_demo_text;
+use crate::screen::DEMO_SIZE;
+use crate::serial::SerialPort;
+use crate::taskbar;
+use crate::taskbar::Status;
+use crate::widget;
+use crate::widget::Ui;
+use crate::wm::WindowEvent;
+use crate::wm::WindowId;
+use crate::wm::WindowManager;
+use alloc::string::ToString;
+use alloc::vec;
+use core::fmt;
+
+const WIDTH: i64 = 320;
+const HEIGHT: i64 = 200;
+
+pub struct Scenario {
+    pub name: &'static str,
+    /// The size of the frame, WIDTH x HEIGHT unless the scene is larger.
+    pub size: (i64, i64),
+    draw: fn(&mut BackBuffer) -> Result<()>,
+    pub golden_crc32: u32,
+}
+
+pub const SCENARIOS: &[Scenario] = &[
+    Scenario {
+        name: "font",
+        size: (WIDTH, HEIGHT),
+        draw: draw_font_scenario,
+        golden_crc32: 0x5cf7fdd3,
+    },
+    Scenario {
+        name: "primitives",
+        size: (WIDTH, HEIGHT),
+        draw: draw_primitives_scenario,
+        golden_crc32: 0x3e53ce00,
+    },
+    Scenario {
+        name: "compositor",
+        size: (WIDTH, HEIGHT),
+        draw: draw_compositor_scenario,
+        golden_crc32: 0x1071e329,
+    },
+    Scenario {
+        name: "cursor",
+        size: (WIDTH, HEIGHT),
+        draw: draw_cursor_scenario,
+        golden_crc32: 0xc1696264,
+    },
+    Scenario {
+        name: "wm",
+        size: (WIDTH, HEIGHT),
+        draw: draw_wm_scenario,
+        golden_crc32: 0x732fed40,
+    },
+    Scenario {
+        name: "widgets",
+        size: (WIDTH, HEIGHT),
+        draw: draw_widgets_scenario,
+        golden_crc32: 0x7408a50,
+    },
+    Scenario {
+        name: "taskbar",
+        size: (640, taskbar::HEIGHT),
+        draw: draw_taskbar_scenario,
+        golden_crc32: 0xe808c135,
+    },
+    Scenario {
+        name: "demo",
+        size: (DEMO_SIZE + 13 * 8, DEMO_SIZE + 16),
+        draw: draw_demo_scenario,
+        golden_crc32: 0xb3a3eff8,
+    },
+];
+
+fn draw_font_scenario(buf: &mut BackBuffer) -> Result<()> {
+    draw_str_fg(buf, 8, 8, 0xffffff, "Hello, WasabiOS!");
+    draw_str_fg(buf, 8, 32, 0x00ff00, "0123456789 !\"#$%&'()*+,-./");
+    draw_str_fg(buf, 8, 56, 0xff8000, ":;<=>?@[\\]^_`{|}~");
+    draw_str_fg(buf, 8, 80, 0x80c0ff, "ABCDEFGHIJKLMNOPQRSTUVWXYZ");
+    draw_str_fg(buf, 8, 104, 0xff00ff, "abcdefghijklmnopqrstuvwxyz");
+    Ok(())
+}
+
+fn draw_primitives_scenario(buf: &mut BackBuffer) -> Result<()> {
+    fill_rect(buf, 0x202040, 0, 0, WIDTH, HEIGHT)?;
+    fill_rect(buf, 0xff0000, 10, 10, 40, 30)?;
+    for i in (0..=100).step_by(20) {
+        draw_line(buf, 0xffff00, 60, 10, 60 + i, 110)?;
+        draw_line(buf, 0x00ffff, 60, 10, 160, 10 + i)?;
+    }
+    draw_line_styled(buf, 0xffffff, 10, 130, 300, 190, LineStyle::DASHED)?;
+    draw_line_styled(
+        buf,
+        0xff80ff,
+        10,
+        190,
+        300,
+        130,
+        LineStyle::SOLID.with_width(3),
+    )?;
+    fill_triangle(
+        buf,
+        0x00ff00,
+        Point::new(200, 20),
+        Point::new(300, 60),
+        Point::new(220, 110),
+    );
+    fill_rect(buf, 0x000000, 240, 130, 60, 40)?;
+    fill_rect(buf, 0x808080, 260, 140, 4, 20)?;
+    flood_fill(buf, 245, 135, 0x0000ff)
+}
+
+fn draw_compositor_scenario(buf: &mut BackBuffer) -> Result<()> {
+    let mut back = BackBuffer::new(WIDTH, HEIGHT);
+    fill_rect(&mut back, 0x336699, 0, 0, WIDTH, HEIGHT)?;
+    fill_rect(&mut back, 0xffcc00, 20, 20, 100, 60)?;
+    // 一部だけをダメージとして転送し、残りは転送されないことを確かめる
+    back.add_damage(Rect::new(0, 0, 80, 50));
+    back.add_damage(Rect::new(60, 40, 100, 100));
+    back.flush(buf)?;
+    let mut low = ScaledBuffer::new(32, 20, 3);
+    fill_rect(&mut low, 0xff0000, 0, 0, 32, 20)?;
+    fill_rect(&mut low, 0xffffff, 4, 4, 8, 8)?;
+    low.add_damage_all();
+    low.present(buf)
+}
+
+fn draw_cursor_scenario(buf: &mut BackBuffer) -> Result<()> {
+    fill_rect(buf, 0x008080, 0, 0, WIDTH, HEIGHT)?;
+    draw_str_fg(buf, 100, 100, 0xffffff, "under the cursor");
+    let mut cursor = Cursor::new(100, 95);
+    cursor.show(buf);
+    cursor.move_by(buf, 40, 10);
+    cursor.move_by(buf, 1000, 1000);
+    cursor.move_to(buf, 10, 10);
+    Ok(())
+}
+
+fn draw_wm_scenario(buf: &mut BackBuffer) -> Result<()> {
+    let mut wm = WindowManager::new(OwnedBitmap::new(WIDTH, HEIGHT, 0x336699));
+    let windows = [
+        ("window 1", 0xff0000),
+        ("window 2", 0x00ff00),
+        ("window 3", 0x0000ff),
+    ];
+    for (i, (title, color)) in windows.into_iter().enumerate() {
+        let i = i as i64;
+        let id = wm.create_window(title, Rect::new(20 + i * 50, 20 + i * 30, 120, 80))?;
+        wm.draw(id, |b| {
+            let _ = fill_rect(b, color, 0, 0, 116, 56);
+            draw_str_fg(b, 4, 4, 0xffffff, "contents");
+        })?;
+    }
+    wm.present(buf, 0, 0)?;
+    // 一番下の窓をクリックで最前面にし、真ん中の窓をタイトルバーで画面の端へ引きずる
+    wm.handle_mouse(30, 60, MouseButtons::from_bits(1));
+    wm.handle_mouse(30, 60, MouseButtons::from_bits(0));
+    wm.handle_mouse(150, 60, MouseButtons::from_bits(1));
+    wm.handle_mouse(330, 160, MouseButtons::from_bits(1));
+    wm.handle_mouse(330, 160, MouseButtons::from_bits(0));
+    wm.present(buf, 0, 0)
+}
+
+fn draw_widgets_scenario(buf: &mut BackBuffer) -> Result<()> {
+    fill_rect(buf, 0x336699, 0, 0, WIDTH, HEIGHT)?;
+    let mut ui = widget::demo();
+    let (w, h) = ui.preferred_size();
+    ui.layout(w, h);
+    let left = MouseButtons::from_bits(1);
+    let none = MouseButtons::from_bits(0);
+    let click = |ui: &mut Ui, x, y| {
+        ui.handle(&WindowEvent::MouseDown {
+            x,
+            y,
+            buttons: left,
+        });
+        ui.handle(&WindowEvent::MouseUp {
+            x,
+            y,
+            buttons: none,
+        });
+    };
+    // テキストボックスに名前を打ってGreetを押し、Clearは押したままにする
+    click(&mut ui, 20, 44);
+    for usage in [0x1a, 0x04, 0x16, 0x04, 0x05, 0x0c] {
+        ui.handle(&WindowEvent::Key(KeyEvent {
+            usage,
+            pressed: true,
+        }));
+    }
+    click(&mut ui, 20, 76);
+    ui.handle(&WindowEvent::MouseDown {
+        x: 20,
+        y: 108,
+        buttons: left,
+    });
+    let mut contents = OwnedBitmap::new(w, h, 0x000000);
+    ui.draw(&mut contents);
+    let rect = contents.rect();
+    blit(&mut contents, rect, buf, 16, 16)
+}
+
+fn draw_taskbar_scenario(buf: &mut BackBuffer) -> Result<()> {
+    let status = Status {
+        windows: vec![
+            (WindowId(1), "terminal".to_string(), false),
+            (WindowId(3), "a window with a long title".to_string(), true),
+        ],
+        cpu_percent: 25,
+        free_bytes: 96 << 20,
+        total_bytes: 128 << 20,
+        clock: "2024-01-01 12:34:56".to_string(),
+    };
+    taskbar::draw(buf, &status);
+    Ok(())
+}
+
+fn draw_demo_scenario(buf: &mut BackBuffer) -> Result<()> {
+    draw_demo_shapes(buf)?;
+    draw_demo_text(buf);
+    Ok(())
+}
+
+/// Draws a scenario into a new frame.
+pub fn render(scenario: &Scenario) -> Result<BackBuffer> {
+    let (width, height) = scenario.size;
+    let mut buf = BackBuffer::new(width, height);
+    (scenario.draw)(&mut buf)?;
+    Ok(buf)
+}
+
+/// Draws a scenario and returns the CRC32 of the frame.
+pub fn capture(scenario: &Scenario) -> Result<u32> {
+    Ok(crc32(render(scenario)?.pixels()))
+}
+
+/// Runs all the scenarios and returns true if all of them match.
+pub fn run_all(out: &mut dyn fmt::Write) -> bool {
+    let mut passed = 0;
+    for s in SCENARIOS {
+        match capture(s) {
+            Ok(crc) if crc == s.golden_crc32 => {
+                passed += 1;
+                let _ = writeln!(out, "[PASS] gui_test {}", s.name);
+            }
+            Ok(crc) => {
+                let _ = writeln!(
+                    out,
+                    "[FAIL] gui_test {}: crc32 {crc:#010x}, expected {:#010x}",
+                    s.name, s.golden_crc32
+                );
+            }
+            Err(e) => {
+                let _ = writeln!(out, "[FAIL] gui_test {}: {e}", s.name);
+            }
+        }
+    }
+    let _ = writeln!(out, "gui_test: {passed}/{} passed", SCENARIOS.len());
+    passed == SCENARIOS.len()
+}
+
+/// Runs all the scenarios, reporting to the serial port, and exits QEMU
+/// with the result.
+pub fn run_and_exit_qemu() -> ! {
+    if run_all(&mut SerialPort::default()) {
+        exit_qemu(QemuExitCode::Success)
+    } else {
+        exit_qemu(QemuExitCode::Failure)
+    }
+}
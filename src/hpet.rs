@@ -0,0 +1,157 @@
+use crate::acpi::Acpi;
+use crate::kexec;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use core::ptr::read_volatile;
+use core::ptr::write_volatile;
+use core::time::Duration;
+
+const REG_CAPABILITIES: usize = 0x000;
+const REG_CONFIG: usize = 0x010;
+const REG_MAIN_COUNTER: usize = 0x0f0;
+const fn reg_timer_config(n: usize) -> usize {
+    0x100 + 0x20 * n
+}
+const fn reg_timer_comparator(n: usize) -> usize {
+    0x108 + 0x20 * n
+}
+
+const CONFIG_ENABLE: u64 = 1 << 0;
+const CONFIG_LEGACY_ROUTE: u64 = 1 << 1;
+const CAP_LEGACY_ROUTE: u64 = 1 << 15;
+
+const TIMER_INT_ENABLE: u64 = 1 << 2;
+const TIMER_PERIODIC: u64 = 1 << 3;
+const TIMER_PERIODIC_CAP: u64 = 1 << 4;
+const TIMER_VAL_SET: u64 = 1 << 6;
+
+const FEMTOSECONDS_PER_SECOND: u64 = 1_000_000_000_000_000;
+
+/// High Precision Event Timer.
+///
+/// Timers 0 and 1 are used in the legacy replacement mode, so that they fire
+/// IRQ 0 and IRQ 8 on the PIC. Register a handler with
+/// pic::register_irq_handler() to receive them.
+#[derive(Clone, Copy)]
+pub struct Hpet {
+    // UEFIがMMIO領域も含めて恒等マッピングしているので、物理アドレスのまま使える
+    base: usize,
+    period_fs: u64,
+    num_timers: usize,
+}
+impl Hpet {
+    fn read(&self, ofs: usize) -> u64 {
+        unsafe { read_volatile((self.base + ofs) as *const u64) }
+    }
+    fn write(&self, ofs: usize, value: u64) {
+        unsafe { write_volatile((self.base + ofs) as *mut u64, value) }
+    }
+    pub fn frequency_hz(&self) -> u64 {
+        FEMTOSECONDS_PER_SECOND / self.period_fs
+    }
+    pub fn num_timers(&self) -> usize {
+        self.num_timers
+    }
+    pub fn main_counter(&self) -> u64 {
+        self.read(REG_MAIN_COUNTER)
+    }
+    pub fn ticks_to_ns(&self, ticks: u64) -> u64 {
+        (ticks as u128 * self.period_fs as u128 / 1_000_000) as u64
+    }
+    fn duration_to_ticks(&self, d: Duration) -> u64 {
+        ((d.as_nanos() * 1_000_000 / self.period_fs as u128) as u64).max(1)
+    }
+    /// The IRQ that the timer n fires in the legacy replacement mode.
+    pub fn irq_of(timer: usize) -> Result<u8> {
+        match timer {
+            0 => Ok(0),
+            1 => Ok(8),
+            _ => Err("Only the timers 0 and 1 are routed to the PIC"),
+        }
+    }
+    /// Fires the IRQ of the timer every period.
+    pub fn start_periodic(&self, timer: usize, period: Duration) -> Result<()> {
+        Self::irq_of(timer)?;
+        if self.read(reg_timer_config(timer)) & TIMER_PERIODIC_CAP == 0 {
+            return Err("The timer does not support the periodic mode");
+        }
+        let ticks = self.duration_to_ticks(period);
+        let config = self.read(reg_timer_config(timer));
+        self.write(
+            reg_timer_config(timer),
+            config | TIMER_INT_ENABLE | TIMER_PERIODIC | TIMER_VAL_SET,
+        );
+        // VAL_SETを立てた直後の書き込みがカウンタの初期値、次の書き込みが周期になる
+        self.write(reg_timer_comparator(timer), self.main_counter() + ticks);
+        self.write(reg_timer_comparator(timer), ticks);
+        Ok(())
+    }
+    /// Fires the IRQ of the timer once after delay.
+    pub fn start_oneshot(&self, timer: usize, delay: Duration) -> Result<()> {
+        Self::irq_of(timer)?;
+        let ticks = self.duration_to_ticks(delay);
+        let config = self.read(reg_timer_config(timer)) & !TIMER_PERIODIC;
+        self.write(reg_timer_config(timer), config | TIMER_INT_ENABLE);
+        self.write(reg_timer_comparator(timer), self.main_counter() + ticks);
+        Ok(())
+    }
+    /// Stops the main counter and gives IRQ 0 back to the PIT, for handing
+    /// the machine over to the firmware.
+    pub fn suspend(&self) {
+        self.write(REG_CONFIG, 0);
+    }
+    /// Undoes suspend().
+    pub fn resume(&self) {
+        self.write(REG_CONFIG, CONFIG_ENABLE | CONFIG_LEGACY_ROUTE);
+    }
+    pub fn stop(&self, timer: usize) -> Result<()> {
+        Self::irq_of(timer)?;
+        let config = self.read(reg_timer_config(timer));
+        self.write(
+            reg_timer_config(timer),
+            config & !(TIMER_INT_ENABLE | TIMER_PERIODIC),
+        );
+        Ok(())
+    }
+}
+
+static HPET: Mutex<Option<Hpet>> = Mutex::new(None);
+
+/// Finds the HPET through ACPI and starts its main counter.
+pub fn init(acpi: &Acpi) -> Result<Hpet> {
+    let table = acpi.hpet().ok_or("HPET table not found")?;
+    let base = table.base_address().ok_or("HPET is not memory mapped")? as usize;
+    let caps = unsafe { read_volatile((base + REG_CAPABILITIES) as *const u64) };
+    let period_fs = caps >> 32;
+    if period_fs == 0 || period_fs > 100_000_000 {
+        return Err("Invalid HPET counter period");
+    }
+    if caps & CAP_LEGACY_ROUTE == 0 {
+        return Err("HPET does not support the legacy replacement route");
+    }
+    let hpet = Hpet {
+        base,
+        period_fs,
+        num_timers: ((caps >> 8) & 0x1f) as usize + 1,
+    };
+    hpet.write(REG_CONFIG, 0);
+    for n in 0..hpet.num_timers {
+        hpet.write(reg_timer_config(n), 0);
+    }
+    hpet.write(REG_MAIN_COUNTER, 0);
+    hpet.resume();
+    *HPET.lock() = Some(hpet);
+    kexec::register_shutdown_hook("hpet", || {
+        if let Some(hpet) = get() {
+            for timer in 0..2 {
+                let _ = hpet.stop(timer);
+            }
+        }
+    });
+    Ok(hpet)
+}
+
+/// Returns the HPET if init() has succeeded.
+pub fn get() -> Option<Hpet> {
+    *HPET.lock()
+}
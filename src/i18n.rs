@@ -0,0 +1,261 @@
+//! The message catalog for the strings shown to users.
+//!
+//! Every message is a variant of Msg and has a text for each Lang, so a
+//! missing translation is a compile error. The language follows the `lang`
+//! setting.
+
+use crate::settings;
+use core::fmt;
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::Ordering;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Lang {
+    En = 0,
+    Ja = 1,
+}
+impl core::str::FromStr for Lang {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "en" => Ok(Lang::En),
+            "ja" => Ok(Lang::Ja),
+            _ => Err("lang must be en or ja"),
+        }
+    }
+}
+
+static LANG: AtomicU8 = AtomicU8::new(Lang::En as u8);
+
+pub fn lang() -> Lang {
+    match LANG.load(Ordering::Relaxed) {
+        1 => Lang::Ja,
+        _ => Lang::En,
+    }
+}
+
+pub fn set_lang(lang: Lang) {
+    LANG.store(lang as u8, Ordering::Relaxed);
+}
+
+/// Applies the `lang` setting and follows its changes. Call after settings::load().
+pub fn init() {
+    let apply = |value: &str| set_lang(value.parse().unwrap_or(Lang::En));
+    apply(&settings::get("lang"));
+    settings::on_change("lang", apply);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Msg {
+    LoginPrompt,
+    PasswordPrompt,
+    LoginIncorrect,
+    Welcome,
+    CommandNotFound,
+    NoBackgroundBuiltin,
+    HelpHelp,
+    HelpEcho,
+    HelpJobs,
+    HelpFg,
+    HelpFw,
+    HelpPerf,
+    HelpUname,
+    HelpChainload,
+    HelpKexec,
+    HelpAbboot,
+    HelpConfig,
+    HelpClear,
+    HelpMem,
+    HelpHeap,
+    HelpUptime,
+    HelpReboot,
+    HelpExit,
+    HelpFont,
+    HelpInput,
+    HelpLspci,
+    HelpAcpi,
+    HelpCpus,
+    HelpShutdown,
+    HelpLsusb,
+    HelpUsbdisk,
+    HelpBlk,
+    HelpRun,
+    HelpPs,
+    HelpSched,
+    HelpNice,
+    HelpUlimit,
+    HelpLog,
+    HelpDmesg,
+    HelpBench,
+    HelpIfconfig,
+    HelpArp,
+    HelpPing,
+    HelpDhcp,
+    HelpWget,
+    HelpNtp,
+    HelpDate,
+    HelpWin,
+    HelpWallpaper,
+    HelpShot,
+}
+impl Msg {
+    pub fn text(self, lang: Lang) -> &'static str {
+        // [English, 日本語]
+        let texts = match self {
+            Msg::LoginPrompt => ["login: ", "ログイン: "],
+            Msg::PasswordPrompt => ["Password: ", "パスワード: "],
+            Msg::LoginIncorrect => ["Login incorrect", "ログインできませんでした"],
+            Msg::Welcome => [
+                "Welcome to WasabiOS! Type `help` for commands.",
+                "WasabiOSへようこそ! `help` でコマンドの一覧を表示します。",
+            ],
+            Msg::CommandNotFound => ["command not found", "コマンドが見つかりません"],
+            Msg::NoBackgroundBuiltin => [
+                "built-in commands cannot run in the background",
+                "組み込みコマンドはバックグラウンドで実行できません",
+            ],
+            Msg::HelpHelp => ["show this message", "このメッセージを表示する"],
+            Msg::HelpEcho => ["print the arguments", "引数を表示する"],
+            Msg::HelpJobs => [
+                "list background jobs",
+                "バックグラウンドジョブの一覧を表示する",
+            ],
+            Msg::HelpFg => [
+                "bring a background job to the foreground",
+                "バックグラウンドジョブをフォアグラウンドに戻す",
+            ],
+            Msg::HelpFw => [
+                "show or edit the firewall rules",
+                "ファイアウォールのルールを表示・編集する",
+            ],
+            Msg::HelpPerf => [
+                "show the cycles spent in measured regions",
+                "計測区間で消費したサイクル数を表示する",
+            ],
+            Msg::HelpUname => ["show the kernel version", "カーネルのバージョンを表示する"],
+            Msg::HelpChainload => [
+                "start another EFI application on the boot disk",
+                "ブートディスク上の別のEFIアプリケーションを起動する",
+            ],
+            Msg::HelpKexec => [
+                "boot another kernel image without a firmware reset",
+                "ファームウェアをリセットせずに別のカーネルを起動する",
+            ],
+            Msg::HelpAbboot => [
+                "show or select the A/B kernel slot",
+                "A/Bカーネルスロットを表示・選択する",
+            ],
+            Msg::HelpConfig => ["show or change the settings", "設定を表示・変更する"],
+            Msg::HelpExit => ["exit the shell", "シェルを終了する"],
+            Msg::HelpClear => ["clear the screen", "画面を消去する"],
+            Msg::HelpMem => ["show the memory usage", "メモリの使用状況を表示する"],
+            Msg::HelpHeap => [
+                "show the heap statistics and leaks",
+                "ヒープの統計とリークを表示する",
+            ],
+            Msg::HelpUptime => [
+                "show the time since boot",
+                "起動してからの経過時間を表示する",
+            ],
+            Msg::HelpReboot => ["restart the machine", "マシンを再起動する"],
+            Msg::HelpShutdown => ["turn the machine off", "マシンの電源を切る"],
+            Msg::HelpFont => [
+                "list, load or switch the console fonts",
+                "コンソールのフォントを一覧・読み込み・切り替えする",
+            ],
+            Msg::HelpInput => [
+                "record or replay the input events",
+                "入力イベントを記録・再生する",
+            ],
+            Msg::HelpLspci => ["list the PCI devices", "PCIデバイスの一覧を表示する"],
+            Msg::HelpAcpi => ["list the ACPI tables", "ACPIテーブルの一覧を表示する"],
+            Msg::HelpCpus => ["show the CPUs and APICs", "CPUとAPICの構成を表示する"],
+            Msg::HelpLsusb => ["list the USB devices", "USBデバイスの一覧を表示する"],
+            Msg::HelpBlk => [
+                "list or read the block devices",
+                "ブロックデバイスの一覧表示・読み出しをする",
+            ],
+            Msg::HelpUsbdisk => [
+                "list or read the USB disks",
+                "USBディスクの一覧表示・読み出しをする",
+            ],
+            Msg::HelpRun => [
+                "run an ELF program on the ESP in user mode",
+                "ESP上のELFプログラムをユーザーモードで実行する",
+            ],
+            Msg::HelpPs => ["list the processes", "プロセスの一覧を表示する"],
+            Msg::HelpSched => [
+                "show the statistics of the scheduler",
+                "スケジューラの統計を表示する",
+            ],
+            Msg::HelpNice => [
+                "show or set the nice value of a process",
+                "プロセスのnice値を表示・設定する",
+            ],
+            Msg::HelpUlimit => [
+                "show or set the CPU time limit of a process",
+                "プロセスのCPU時間の上限を表示・設定する",
+            ],
+            Msg::HelpLog => [
+                "show or set the log levels and sinks",
+                "ログのレベルと出力先を表示・設定する",
+            ],
+            Msg::HelpBench => [
+                "measure the drawing code in CPU cycles",
+                "描画処理の速度をCPUサイクル数で計測する",
+            ],
+            Msg::HelpIfconfig => [
+                "show or set the addresses of the network interfaces",
+                "ネットワークインターフェースのアドレスを表示・設定する",
+            ],
+            Msg::HelpArp => [
+                "show or flush the ARP cache",
+                "ARPキャッシュを表示・消去する",
+            ],
+            Msg::HelpPing => [
+                "send ICMP echo requests to a host",
+                "ホストへICMPエコー要求を送る",
+            ],
+            Msg::HelpDhcp => [
+                "show the DHCP leases, or start or stop the client",
+                "DHCPのリースを表示し、クライアントを開始・停止する",
+            ],
+            Msg::HelpWget => [
+                "fetch a URL over HTTP and show or save it",
+                "URLをHTTPで取得し、表示またはファイルに保存する",
+            ],
+            Msg::HelpNtp => [
+                "set the clock from an NTP server",
+                "NTPサーバから時刻を合わせる",
+            ],
+            Msg::HelpDate => ["show the date and time in UTC", "現在の日時をUTCで表示する"],
+            Msg::HelpWin => [
+                "list, open, move, resize, raise or close windows",
+                "ウィンドウを一覧・作成・移動・リサイズ・最前面化・削除する",
+            ],
+            Msg::HelpWallpaper => [
+                "set the wallpaper to a color, a gradient or a BMP file",
+                "壁紙を単色・グラデーション・BMPファイルにする",
+            ],
+            Msg::HelpShot => [
+                "save a screenshot to the ESP, or send it over the serial port",
+                "スクリーンショットをESPに保存するか、シリアルポートに送る",
+            ],
+            Msg::HelpDmesg => [
+                "show the kernel log messages",
+                "カーネルのログメッセージを表示する",
+            ],
+        };
+        texts[lang as usize]
+    }
+    /// The text in the current language.
+    pub fn get(self) -> &'static str {
+        self.text(lang())
+    }
+}
+impl fmt::Display for Msg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.get())
+    }
+}
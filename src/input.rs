@@ -0,0 +1,60 @@
+//! The input event queue shared by all input drivers.
+//!
+//! Drivers push events from their interrupt handlers, and the consumer
+//! (the GUI or the console) awaits them with next_event().
+
+use crate::executor::IrqQueue;
+use crate::input_replay;
+use crate::mouse::MouseEvent;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+/// A key press or release. Keys are identified by USB HID usage IDs
+/// (keyboard page 0x07) regardless of the device, e.g. 0x04 is A.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub usage: u8,
+    pub pressed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputEvent {
+    Key(KeyEvent),
+    Mouse(MouseEvent),
+}
+
+static EVENTS: IrqQueue<InputEvent> = IrqQueue::new(512);
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Queues an event. Safe to call from an interrupt handler.
+///
+/// Events from the devices are ignored while a recording is replayed.
+pub fn push(event: InputEvent) {
+    if !input_replay::is_replaying() {
+        push_unfiltered(event);
+    }
+}
+
+pub(crate) fn push_unfiltered(event: InputEvent) {
+    if !EVENTS.push(event) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Waits for the next event, e.g. `input::next_event().await`.
+pub async fn next_event() -> InputEvent {
+    let event = EVENTS.pop().await;
+    input_replay::record(&event);
+    event
+}
+
+pub fn try_next_event() -> Option<InputEvent> {
+    let event = EVENTS.try_pop()?;
+    input_replay::record(&event);
+    Some(event)
+}
+
+/// The number of events lost because the queue was full.
+pub fn dropped_events() -> usize {
+    DROPPED.load(Ordering::Relaxed)
+}
@@ -0,0 +1,210 @@
+//! Recording and replaying of input events, to reproduce UI bugs exactly.
+//!
+//! While recording, every event taken out of the input queue is kept with
+//! the time since the recording started. A recording is saved as a text
+//! file with one event per line:
+//!
+//! ```text
+//! <ns> key <usage> down|up
+//! <ns> mouse <dx> <dy> <wheel> <buttons>
+//! ```
+//!
+//! Replaying feeds the events back into the input queue at the same
+//! relative times, and the events from the devices are ignored meanwhile.
+
+use crate::chainload;
+use crate::executor;
+use crate::input;
+use crate::input::InputEvent;
+use crate::input::KeyEvent;
+use crate::mouse::MouseButtons;
+use crate::mouse::MouseEvent;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::time;
+use crate::uefi;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Write;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+const HEADER: &str = "# wasabi input recording v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimedEvent {
+    /// The time since the recording started.
+    pub ns: u64,
+    pub event: InputEvent,
+}
+
+struct Recorder {
+    started_ns: u64,
+    events: Vec<TimedEvent>,
+}
+
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+static REPLAYING: AtomicBool = AtomicBool::new(false);
+
+pub fn is_recording() -> bool {
+    RECORDER.lock().is_some()
+}
+
+pub fn is_replaying() -> bool {
+    REPLAYING.load(Ordering::Relaxed)
+}
+
+/// Called by the input queue for each event taken out of it.
+pub(crate) fn record(event: &InputEvent) {
+    if is_replaying() {
+        return;
+    }
+    if let Some(recorder) = RECORDER.lock().as_mut() {
+        let ns = time::now_ns().saturating_sub(recorder.started_ns);
+        recorder.events.push(TimedEvent { ns, event: *event });
+    }
+}
+
+pub fn start_recording() -> Result<()> {
+    let mut recorder = RECORDER.lock();
+    if recorder.is_some() {
+        return Err("Already recording");
+    }
+    *recorder = Some(Recorder {
+        started_ns: time::now_ns(),
+        events: Vec::new(),
+    });
+    Ok(())
+}
+
+/// Stops the recording and returns the recorded events.
+pub fn stop_recording() -> Result<Vec<TimedEvent>> {
+    let recorder = RECORDER.lock().take().ok_or("Not recording")?;
+    Ok(recorder.events)
+}
+
+pub fn serialize(events: &[TimedEvent]) -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, "{HEADER}");
+    for e in events {
+        let _ = match e.event {
+            InputEvent::Key(k) => writeln!(
+                s,
+                "{} key {:#04x} {}",
+                e.ns,
+                k.usage,
+                if k.pressed { "down" } else { "up" }
+            ),
+            InputEvent::Mouse(m) => writeln!(
+                s,
+                "{} mouse {} {} {} {}",
+                e.ns,
+                m.dx,
+                m.dy,
+                m.wheel,
+                m.buttons.bits()
+            ),
+        };
+    }
+    s
+}
+
+fn parse_line(line: &str) -> Result<TimedEvent> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let num = |s: &str| s.parse::<i32>().or(Err("Invalid number"));
+    let (ns, event) = match fields.as_slice() {
+        [ns, "key", usage, state] => {
+            let usage = usage.strip_prefix("0x").unwrap_or(usage);
+            let usage = u8::from_str_radix(usage, 16).or(Err("Invalid usage"))?;
+            let pressed = match *state {
+                "down" => true,
+                "up" => false,
+                _ => return Err("Key state must be down or up"),
+            };
+            (ns, InputEvent::Key(KeyEvent { usage, pressed }))
+        }
+        [ns, "mouse", dx, dy, wheel, buttons] => {
+            let buttons = buttons.parse::<u8>().or(Err("Invalid buttons"))?;
+            let event = MouseEvent {
+                dx: num(dx)?,
+                dy: num(dy)?,
+                wheel: num(wheel)?,
+                buttons: MouseButtons::from_bits(buttons),
+            };
+            (ns, InputEvent::Mouse(event))
+        }
+        _ => return Err("Unknown event"),
+    };
+    let ns = ns.parse().or(Err("Invalid time"))?;
+    Ok(TimedEvent { ns, event })
+}
+
+/// Parses a recording. Empty lines and lines starting with `#` are skipped.
+pub fn parse(text: &str) -> Result<Vec<TimedEvent>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(parse_line)
+        .collect()
+}
+
+/// Feeds the events into the input queue in the background.
+pub fn start_replay(mut events: Vec<TimedEvent>) -> Result<()> {
+    if is_recording() {
+        return Err("Stop the recording first");
+    }
+    if REPLAYING.swap(true, Ordering::Relaxed) {
+        return Err("Already replaying");
+    }
+    events.sort_by_key(|e| e.ns);
+    executor::spawn(async move {
+        let started_ns = time::now_ns();
+        for e in events {
+            let elapsed = time::now_ns().saturating_sub(started_ns);
+            if e.ns > elapsed {
+                time::sleep(Duration::from_nanos(e.ns - elapsed)).await;
+            }
+            input::push_unfiltered(e.event);
+        }
+        REPLAYING.store(false, Ordering::Relaxed);
+    });
+    Ok(())
+}
+
+/// The `input` command.
+pub fn cmd_input(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    match args {
+        [] => {
+            let state = if is_replaying() {
+                "replaying"
+            } else if is_recording() {
+                "recording"
+            } else {
+                "idle"
+            };
+            let _ = writeln!(out, "{state} (dropped events: {})", input::dropped_events());
+            Ok(())
+        }
+        ["record"] => start_recording(),
+        ["stop", path] => {
+            let events = stop_recording()?;
+            let text = serialize(&events);
+            let efi_system_table = uefi::system_table().ok_or("EFI context is not initialized")?;
+            chainload::with_firmware_interrupts(|| {
+                uefi::write_file(efi_system_table, path, text.as_bytes())
+            })?;
+            let _ = writeln!(out, "saved {} events to {path}", events.len());
+            Ok(())
+        }
+        ["replay", path] => {
+            let efi_system_table = uefi::system_table().ok_or("EFI context is not initialized")?;
+            let data =
+                chainload::with_firmware_interrupts(|| uefi::read_file(efi_system_table, path))?;
+            let text = core::str::from_utf8(&data).or(Err("The recording is not UTF-8"))?;
+            start_replay(parse(text)?)
+        }
+        _ => Err("usage: input [record | stop PATH | replay PATH]"),
+    }
+}
@@ -0,0 +1,270 @@
+use crate::arch::cli;
+use crate::arch::hlt;
+use crate::arch::load_idtr;
+use crate::arch::read_cr2;
+use crate::arch::read_cs;
+use crate::arch::read_idtr;
+use crate::arch::sti;
+use crate::arch::DescriptorTablePointer;
+use crate::gdt::IST_DOUBLE_FAULT;
+use crate::mutex::Mutex;
+use crate::println;
+use crate::stack;
+use crate::user;
+use alloc::boxed::Box;
+use core::mem::size_of;
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::Ordering;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptStackFrame {
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+pub type InterruptHandler = extern "x86-interrupt" fn(InterruptStackFrame);
+pub type InterruptHandlerWithErrorCode = extern "x86-interrupt" fn(InterruptStackFrame, u64);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct IdtEntry {
+    offset_low: u16,
+    selector: u16,
+    options: u16,
+    offset_mid: u16,
+    offset_high: u32,
+    _reserved: u32,
+}
+const _: () = assert!(size_of::<IdtEntry>() == 16);
+
+// 64bit Interrupt Gate, Present, DPL=0
+const IDT_ATTR_INTERRUPT_GATE: u16 = 0x8e00;
+
+impl IdtEntry {
+    fn new(handler: u64, selector: u16, ist: u8) -> Self {
+        Self {
+            offset_low: handler as u16,
+            selector,
+            options: IDT_ATTR_INTERRUPT_GATE | (ist & 0b111) as u16,
+            offset_mid: (handler >> 16) as u16,
+            offset_high: (handler >> 32) as u32,
+            _reserved: 0,
+        }
+    }
+}
+
+#[repr(C, align(16))]
+struct Idt {
+    entries: [IdtEntry; 256],
+}
+
+static IDT: AtomicPtr<Idt> = AtomicPtr::new(core::ptr::null_mut());
+static FIRMWARE_IDTR: Mutex<Option<DescriptorTablePointer>> = Mutex::new(None);
+
+pub const VECTOR_INVALID_OPCODE: u8 = 6;
+pub const VECTOR_DOUBLE_FAULT: u8 = 8;
+pub const VECTOR_GENERAL_PROTECTION: u8 = 13;
+pub const VECTOR_PAGE_FAULT: u8 = 14;
+
+/// Switches to a kernel-owned IDT.
+///
+/// Entries installed by the firmware are carried over so that vectors the
+/// kernel does not handle yet keep working as before.
+/// gdt::init() must be called before this, as some handlers run on IST stacks.
+pub fn init() {
+    let mut idt = Box::new(Idt {
+        entries: [IdtEntry::default(); 256],
+    });
+    let current = read_idtr();
+    *FIRMWARE_IDTR.lock() = Some(current);
+    let n = ((current.limit as usize + 1) / size_of::<IdtEntry>()).min(256);
+    if current.base != 0 {
+        // SAFETY: the IDTR points to the IDT which is in use by the CPU
+        let entries = unsafe { core::slice::from_raw_parts(current.base as *const IdtEntry, n) };
+        idt.entries[..n].copy_from_slice(entries);
+    }
+    let idt = Box::leak(idt);
+    let idtr = DescriptorTablePointer {
+        limit: (size_of::<Idt>() - 1) as u16,
+        base: idt as *mut Idt as u64,
+    };
+    cli();
+    unsafe {
+        load_idtr(&idtr);
+    }
+    IDT.store(idt, Ordering::SeqCst);
+    set_entry(
+        VECTOR_DOUBLE_FAULT,
+        double_fault_handler as usize as u64,
+        IST_DOUBLE_FAULT,
+    );
+    set_handler_with_error_code(VECTOR_PAGE_FAULT, page_fault_handler);
+    set_handler(VECTOR_INVALID_OPCODE, invalid_opcode_handler);
+    set_handler_with_error_code(VECTOR_GENERAL_PROTECTION, general_protection_handler);
+    sti();
+}
+
+/// Gives an application processor its own copy of the kernel IDT.
+pub fn init_ap() {
+    let idt = IDT.load(Ordering::SeqCst);
+    assert!(!idt.is_null(), "interrupt::init() is not called yet");
+    // SAFETY: IDT is initialized and leaked in init()
+    let copy = Box::leak(Box::new(Idt {
+        entries: unsafe { (*idt).entries },
+    }));
+    let idtr = DescriptorTablePointer {
+        limit: (size_of::<Idt>() - 1) as u16,
+        base: copy as *mut Idt as u64,
+    };
+    // SAFETY: the copy is leaked and lives forever
+    unsafe { load_idtr(&idtr) }
+}
+
+/// Switches back to the firmware's IDT, e.g. before starting another EFI application.
+pub fn use_firmware_idt() {
+    if let Some(idtr) = *FIRMWARE_IDTR.lock() {
+        // SAFETY: the firmware's IDT is still alive as boot services are not exited
+        unsafe { load_idtr(&idtr) }
+    }
+}
+
+/// Undoes use_firmware_idt().
+pub fn use_kernel_idt() {
+    let idt = IDT.load(Ordering::SeqCst);
+    if idt.is_null() {
+        return;
+    }
+    let idtr = DescriptorTablePointer {
+        limit: (size_of::<Idt>() - 1) as u16,
+        base: idt as u64,
+    };
+    // SAFETY: IDT is initialized and leaked in init()
+    unsafe { load_idtr(&idtr) }
+}
+
+fn set_entry(vector: u8, handler: u64, ist: u8) {
+    let idt = IDT.load(Ordering::SeqCst);
+    assert!(!idt.is_null(), "interrupt::init() is not called yet");
+    let entry = IdtEntry::new(handler, read_cs(), ist);
+    // SAFETY: IDT is initialized and leaked in init()
+    unsafe {
+        (*idt).entries[vector as usize] = entry;
+    }
+}
+
+pub fn set_handler(vector: u8, handler: InterruptHandler) {
+    set_entry(vector, handler as usize as u64, 0);
+}
+
+pub fn set_handler_with_error_code(vector: u8, handler: InterruptHandlerWithErrorCode) {
+    set_entry(vector, handler as usize as u64, 0);
+}
+
+// スタックオーバーフローでも確実に動けるように、専用のスタック(IST)上で実行される
+extern "x86-interrupt" fn double_fault_handler(frame: InterruptStackFrame, error_code: u64) {
+    println!("");
+    println!("!!!! DOUBLE FAULT (error_code = {error_code:#X}) !!!!");
+    println!("{frame:#X?}");
+    let addr = read_cr2();
+    if stack::is_guard_page(addr) {
+        println!("Kernel stack overflow: the guard page at {addr:#018X} was hit.");
+    } else {
+        println!("An exception occurred while handling another exception.");
+        println!("This is likely a kernel stack overflow, or a bug in an exception handler.");
+    }
+    loop {
+        hlt();
+    }
+}
+
+extern "x86-interrupt" fn invalid_opcode_handler(frame: InterruptStackFrame) {
+    if user::is_user_frame(&frame) {
+        user::kill_on_fault("invalid opcode", &frame);
+    }
+    println!("");
+    println!("!!!! INVALID OPCODE !!!!");
+    println!("{frame:#X?}");
+    loop {
+        hlt();
+    }
+}
+
+extern "x86-interrupt" fn general_protection_handler(frame: InterruptStackFrame, error_code: u64) {
+    if user::is_user_frame(&frame) {
+        user::kill_on_fault("general protection fault", &frame);
+    }
+    println!("");
+    println!("!!!! GENERAL PROTECTION FAULT (error_code = {error_code:#X}) !!!!");
+    println!("{frame:#X?}");
+    loop {
+        hlt();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageFaultErrorCode(pub u64);
+impl PageFaultErrorCode {
+    /// The page was present, i.e. this is a protection violation.
+    pub fn is_protection_violation(&self) -> bool {
+        self.0 & (1 << 0) != 0
+    }
+    pub fn is_write(&self) -> bool {
+        self.0 & (1 << 1) != 0
+    }
+    pub fn is_user(&self) -> bool {
+        self.0 & (1 << 2) != 0
+    }
+    pub fn is_reserved_bit_violation(&self) -> bool {
+        self.0 & (1 << 3) != 0
+    }
+    pub fn is_instruction_fetch(&self) -> bool {
+        self.0 & (1 << 4) != 0
+    }
+    pub fn access_type(&self) -> &'static str {
+        if self.is_instruction_fetch() {
+            "execute"
+        } else if self.is_write() {
+            "write"
+        } else {
+            "read"
+        }
+    }
+}
+
+extern "x86-interrupt" fn page_fault_handler(frame: InterruptStackFrame, error_code: u64) {
+    let addr = read_cr2();
+    let error_code = PageFaultErrorCode(error_code);
+    if user::is_user_frame(&frame) {
+        user::kill_on_fault("page fault", &frame);
+    }
+    println!("");
+    println!("!!!! PAGE FAULT (error_code = {:#X}) !!!!", error_code.0);
+    println!("  address: {addr:#018X}");
+    println!(
+        "  access:  {} in {} mode",
+        error_code.access_type(),
+        if error_code.is_user() {
+            "user"
+        } else {
+            "kernel"
+        }
+    );
+    println!(
+        "  reason:  {}",
+        if error_code.is_reserved_bit_violation() {
+            "reserved bit set in a page table entry"
+        } else if error_code.is_protection_violation() {
+            "protection violation"
+        } else {
+            "page not present"
+        }
+    );
+    println!("{frame:#X?}");
+    loop {
+        hlt();
+    }
+}
@@ -0,0 +1,176 @@
+//! The IO APIC, which delivers the ISA IRQs through the Local APIC instead
+//! of the 8259 PICs.
+//!
+//! init() programs a redirection entry for each ISA IRQ at the GSI that the
+//! MADT routes it to, with the polarity and the trigger mode of the
+//! interrupt source override, and with the vector that the PIC would use,
+//! so that the handlers of pic::register_irq_handler() keep working. Once
+//! it is active, pic.rs masks, unmasks and acknowledges the IRQs here.
+
+use crate::apic;
+use crate::apic::Gsi;
+use crate::apic::Polarity;
+use crate::apic::Trigger;
+use crate::interrupt;
+use crate::mutex::Mutex;
+use crate::pic::IRQ_VECTOR_BASE;
+use crate::pic::NUM_IRQS;
+use crate::result::Result;
+use core::ptr::read_volatile;
+use core::ptr::write_volatile;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicU16;
+use core::sync::atomic::Ordering;
+
+const REG_SELECT: usize = 0x00;
+const REG_WINDOW: usize = 0x10;
+const REG_VERSION: u32 = 0x01;
+const REG_REDIRECTION_BASE: u32 = 0x10;
+
+const ENTRY_ACTIVE_LOW: u64 = 1 << 13;
+const ENTRY_LEVEL: u64 = 1 << 15;
+pub const ENTRY_MASKED: u64 = 1 << 16;
+
+/// The pin of an IO APIC that an IRQ is connected to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Route {
+    address: u64,
+    pin: u8,
+    entry: u64,
+}
+
+static ROUTES: Mutex<[Option<Route>; NUM_IRQS as usize]> = Mutex::new([None; NUM_IRQS as usize]);
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// Bit n set = IRQ n masked, as in pic::masks().
+static MASKS: AtomicU16 = AtomicU16::new(0xffff);
+
+fn read(address: u64, reg: u32) -> u32 {
+    // SAFETY: the registers of the IO APIC from the MADT are identity mapped
+    unsafe {
+        write_volatile((address as usize + REG_SELECT) as *mut u32, reg);
+        read_volatile((address as usize + REG_WINDOW) as *const u32)
+    }
+}
+
+fn write(address: u64, reg: u32, value: u32) {
+    // SAFETY: the registers of the IO APIC from the MADT are identity mapped
+    unsafe {
+        write_volatile((address as usize + REG_SELECT) as *mut u32, reg);
+        write_volatile((address as usize + REG_WINDOW) as *mut u32, value);
+    }
+}
+
+fn write_entry(address: u64, pin: u8, entry: u64) {
+    let reg = REG_REDIRECTION_BASE + pin as u32 * 2;
+    // マスクのビットがある下位を後に書き、途中の状態で割り込みが届かないようにする
+    write(address, reg + 1, (entry >> 32) as u32);
+    write(address, reg, entry as u32);
+}
+
+/// The number of pins of the IO APIC.
+fn pins(address: u64) -> u32 {
+    ((read(address, REG_VERSION) >> 16) & 0xff) + 1
+}
+
+/// The redirection entry that delivers the GSI as the vector to the Local
+/// APIC with apic_id, masked.
+pub fn redirection_entry(vector: u8, gsi: Gsi, apic_id: u8) -> u64 {
+    // 固定配送・物理宛先なので、それらのビットは0のまま
+    let mut entry = vector as u64 | ENTRY_MASKED | (apic_id as u64) << 56;
+    if gsi.polarity == Polarity::ActiveLow {
+        entry |= ENTRY_ACTIVE_LOW;
+    }
+    if gsi.trigger == Trigger::Level {
+        entry |= ENTRY_LEVEL;
+    }
+    entry
+}
+
+/// Routes the ISA IRQs to the BSP through the IO APICs, with the IRQs
+/// masked as in masks. Returns the number of IRQs routed. Call after
+/// apic::init(), with the 8259 PICs masked afterwards.
+pub fn init(masks: u16) -> Result<usize> {
+    let info = apic::info().ok_or("The MADT is not available")?;
+    if info.io_apics.is_empty() {
+        return Err("No IO APIC in the MADT");
+    }
+    let apic_id = u8::try_from(info.bsp_apic_id).or(Err("The APIC ID of the BSP is too large"))?;
+    for a in &info.io_apics {
+        for pin in 0..pins(a.address) {
+            write_entry(a.address, pin as u8, ENTRY_MASKED);
+        }
+    }
+    let mut routes = ROUTES.lock();
+    for irq in 0..NUM_IRQS {
+        let gsi = info.irq_to_gsi(irq);
+        let Some(a) = info.io_apic_for(gsi.gsi) else {
+            continue;
+        };
+        let pin = gsi.gsi - a.gsi_base;
+        // IRQ0がGSI2に繋がっているとき、カスケード用のIRQ2は使われていない
+        if pin >= pins(a.address)
+            || routes
+                .iter()
+                .flatten()
+                .any(|r| r.address == a.address && r.pin == pin as u8)
+        {
+            continue;
+        }
+        let route = Route {
+            address: a.address,
+            pin: pin as u8,
+            entry: redirection_entry(IRQ_VECTOR_BASE + irq, gsi, apic_id),
+        };
+        write_entry(route.address, route.pin, route.entry);
+        routes[irq as usize] = Some(route);
+    }
+    let routed = routes.iter().flatten().count();
+    drop(routes);
+    interrupt::set_handler(apic::SPURIOUS_VECTOR, apic::spurious_handler);
+    apic::enable_local_apic();
+    ACTIVE.store(true, Ordering::SeqCst);
+    set_masks(masks);
+    Ok(routed)
+}
+
+/// Whether the IRQs are delivered through the IO APIC.
+pub fn is_active() -> bool {
+    ACTIVE.load(Ordering::SeqCst)
+}
+
+/// Whether the IRQ has a pin on an IO APIC.
+pub fn is_routed(irq: u8) -> bool {
+    ROUTES.lock().get(irq as usize).is_some_and(|r| r.is_some())
+}
+
+/// The masks of the IRQs, as in pic::masks().
+pub fn masks() -> u16 {
+    MASKS.load(Ordering::SeqCst)
+}
+
+/// Masks (bit n set) or unmasks the IRQs.
+pub fn set_masks(masks: u16) {
+    MASKS.store(masks, Ordering::SeqCst);
+    for (irq, route) in ROUTES.lock().iter().enumerate() {
+        if let Some(r) = route {
+            let masked = masks & (1 << irq) != 0;
+            write_entry(
+                r.address,
+                r.pin,
+                if masked {
+                    r.entry | ENTRY_MASKED
+                } else {
+                    r.entry & !ENTRY_MASKED
+                },
+            );
+        }
+    }
+}
+
+pub fn mask(irq: u8) {
+    set_masks(masks() | 1 << irq);
+}
+
+pub fn unmask(irq: u8) {
+    set_masks(masks() & !(1 << irq));
+}
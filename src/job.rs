@@ -0,0 +1,92 @@
+use crate::process;
+use crate::process::Pid;
+use crate::process::ProcessState;
+use crate::result::Result;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Splits a trailing `&` off a command line.
+///
+/// Returns the command without `&` and whether it should run in the background.
+pub fn parse_background(line: &str) -> (&str, bool) {
+    let line = line.trim();
+    match line.strip_suffix('&') {
+        Some(cmd) => (cmd.trim_end(), true),
+        None => (line, false),
+    }
+}
+
+pub struct Job {
+    pub id: usize,
+    pub pid: Pid,
+    pub command: String,
+}
+
+/// Background jobs started from a shell.
+#[derive(Default)]
+pub struct JobTable {
+    jobs: Vec<Job>,
+}
+impl JobTable {
+    pub const fn new() -> Self {
+        Self { jobs: Vec::new() }
+    }
+    fn next_id(&self) -> usize {
+        self.jobs.iter().map(|j| j.id).max().unwrap_or(0) + 1
+    }
+    /// Starts tracking pid as a background job and returns its job id.
+    pub fn add(&mut self, pid: Pid, command: &str) -> usize {
+        let id = self.next_id();
+        self.jobs.push(Job {
+            id,
+            pid,
+            command: command.to_string(),
+        });
+        id
+    }
+    /// Reaps finished jobs, reporting them to out.
+    pub fn reap_finished(&mut self, out: &mut dyn fmt::Write) {
+        self.jobs
+            .retain(|j| match process::info(j.pid).map(|p| p.state) {
+                Some(ProcessState::Running) => true,
+                Some(ProcessState::Exited(code)) => {
+                    let _ = process::reap(j.pid);
+                    let _ = writeln!(out, "[{}] Done({code})\t{}", j.id, j.command);
+                    false
+                }
+                None => false,
+            });
+    }
+    /// The `jobs` built-in.
+    pub fn cmd_jobs(&mut self, out: &mut dyn fmt::Write) {
+        self.reap_finished(out);
+        for j in &self.jobs {
+            let _ = writeln!(out, "[{}] {} Running\t{}", j.id, j.pid, j.command);
+        }
+    }
+    /// The `fg` built-in. Moves the given job (or the latest one) to the foreground.
+    ///
+    /// Returns the pid that the shell should wait for.
+    pub fn cmd_fg(&mut self, arg: Option<&str>, out: &mut dyn fmt::Write) -> Result<Pid> {
+        self.reap_finished(out);
+        let i = match arg {
+            Some(arg) => {
+                let id: usize = arg
+                    .trim_start_matches('%')
+                    .parse()
+                    .map_err(|_| "fg: invalid job id")?;
+                self.jobs
+                    .iter()
+                    .position(|j| j.id == id)
+                    .ok_or("fg: no such job")?
+            }
+            None => self.jobs.len().checked_sub(1).ok_or("fg: no current job")?,
+        };
+        let job = self.jobs.remove(i);
+        process::set_foreground(Some(job.pid))?;
+        let _ = writeln!(out, "{}", job.command);
+        Ok(job.pid)
+    }
+}
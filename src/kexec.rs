@@ -0,0 +1,97 @@
+//! Warm reboot into another kernel image without going through a firmware
+//! reset.
+//!
+//! The new image is loaded by the firmware's LoadImage (so it must be an EFI
+//! application, like wasabi.efi itself), the drivers are asked to quiesce
+//! their devices, and then the control is handed over with StartImage.
+
+use crate::chainload::with_firmware_interrupts;
+use crate::mutex::Mutex;
+use crate::println;
+use crate::result::Result;
+use crate::uefi;
+use crate::uefi::EfiHandle;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Called before jumping to the new kernel. Drivers should stop DMA and
+/// interrupts of their devices here.
+pub type ShutdownHook = fn();
+
+static SHUTDOWN_HOOKS: Mutex<Vec<(&'static str, ShutdownHook)>> = Mutex::new(Vec::new());
+
+pub fn register_shutdown_hook(name: &'static str, hook: ShutdownHook) {
+    SHUTDOWN_HOOKS.lock().push((name, hook));
+}
+
+/// A kernel image loaded and ready to be executed.
+pub struct LoadedKernel {
+    image: EfiHandle,
+}
+
+fn parent() -> Result<(EfiHandle, &'static uefi::EfiSystemTable)> {
+    Ok((
+        uefi::image_handle().ok_or("EFI context is not initialized")?,
+        uefi::system_table().ok_or("EFI context is not initialized")?,
+    ))
+}
+
+/// Loads a kernel image from memory (e.g. read from a file system or received
+/// over the network).
+pub fn load(image: &[u8]) -> Result<LoadedKernel> {
+    let (parent, efi_system_table) = parent()?;
+    let image = with_firmware_interrupts(|| {
+        efi_system_table
+            .boot_services
+            .load_image_from_buffer(parent, image)
+    })?;
+    Ok(LoadedKernel { image })
+}
+
+/// Loads a kernel image from a path on the boot device.
+pub fn load_from_esp(path: &str) -> Result<LoadedKernel> {
+    let (parent, efi_system_table) = parent()?;
+    let device_path = uefi::file_device_path(efi_system_table, path)?;
+    let image = with_firmware_interrupts(|| {
+        efi_system_table
+            .boot_services
+            .load_image(parent, &device_path)
+    })?;
+    Ok(LoadedKernel { image })
+}
+
+impl LoadedKernel {
+    /// Shuts the devices down and starts the new kernel.
+    ///
+    /// This returns only if the new kernel exits; the devices stay shut
+    /// down in that case.
+    pub fn execute(self) -> Result<u64> {
+        let (_, efi_system_table) = parent()?;
+        // 後から登録されたドライバほど上位の層なので、逆順に止める
+        let hooks = SHUTDOWN_HOOKS.lock().clone();
+        for (name, hook) in hooks.iter().rev() {
+            println!("kexec: shutting down {name}");
+            hook();
+        }
+        Ok(with_firmware_interrupts(|| {
+            efi_system_table.boot_services.start_image(self.image)
+        }))
+    }
+    /// Frees the loaded image without executing it.
+    pub fn unload(self) -> Result<()> {
+        let (_, efi_system_table) = parent()?;
+        with_firmware_interrupts(|| efi_system_table.boot_services.unload_image(self.image))
+    }
+}
+
+/// The `kexec` command.
+pub fn cmd_kexec(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let [path] = args else {
+        return Err("usage: kexec <path of the new kernel on the ESP>");
+    };
+    let kernel = load_from_esp(path)?;
+    let _ = writeln!(out, "kexec: starting {path}");
+    let status = kernel.execute()?;
+    let _ = writeln!(out, "kexec: {path} exited with status {status:#x}");
+    Ok(())
+}
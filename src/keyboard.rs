@@ -0,0 +1,146 @@
+//! PS/2 keyboard driver.
+//!
+//! The controller translates the keyboard's scancodes to set 1, which are
+//! converted to HID usage IDs here and pushed to the input queue. Use
+//! keymap::KeyMapper to turn them into characters.
+
+use crate::input;
+use crate::input::InputEvent;
+use crate::input::KeyEvent;
+use crate::kexec;
+use crate::mutex::Mutex;
+use crate::pic;
+use crate::ps2;
+use crate::result::Result;
+
+pub const KEYBOARD_IRQ: u8 = 1;
+
+/// Set 1 make codes (0x00-0x7f) to HID usage IDs. 0 means unmapped.
+#[rustfmt::skip]
+const SET1_TO_USAGE: [u8; 0x80] = [
+    // 0x00
+    0x00, 0x29, 0x1e, 0x1f, 0x20, 0x21, 0x22, 0x23,
+    0x24, 0x25, 0x26, 0x27, 0x2d, 0x2e, 0x2a, 0x2b,
+    // 0x10
+    0x14, 0x1a, 0x08, 0x15, 0x17, 0x1c, 0x18, 0x0c,
+    0x12, 0x13, 0x2f, 0x30, 0x28, 0xe0, 0x04, 0x16,
+    // 0x20
+    0x07, 0x09, 0x0a, 0x0b, 0x0d, 0x0e, 0x0f, 0x33,
+    0x34, 0x35, 0xe1, 0x31, 0x1d, 0x1b, 0x06, 0x19,
+    // 0x30
+    0x05, 0x11, 0x10, 0x36, 0x37, 0x38, 0xe5, 0x55,
+    0xe2, 0x2c, 0x39, 0x3a, 0x3b, 0x3c, 0x3d, 0x3e,
+    // 0x40
+    0x3f, 0x40, 0x41, 0x42, 0x43, 0x53, 0x47, 0x5f,
+    0x60, 0x61, 0x56, 0x5c, 0x5d, 0x5e, 0x57, 0x59,
+    // 0x50
+    0x5a, 0x5b, 0x62, 0x63, 0x00, 0x00, 0x64, 0x44,
+    0x45, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // 0x60
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // 0x70: JIS (カタカナ/ひらがな, ろ, 変換, 無変換, ¥)
+    0x88, 0x00, 0x00, 0x87, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x8a, 0x00, 0x8b, 0x00, 0x89, 0x00, 0x00,
+];
+
+/// Set 1 codes after the 0xE0 prefix to HID usage IDs.
+fn extended_to_usage(code: u8) -> u8 {
+    match code {
+        0x1c => 0x58, // Keypad Enter
+        0x1d => 0xe4, // Right Ctrl
+        0x35 => 0x54, // Keypad /
+        0x37 => 0x46, // Print Screen
+        0x38 => 0xe6, // Right Alt
+        0x47 => 0x4a, // Home
+        0x48 => 0x52, // Up
+        0x49 => 0x4b, // Page Up
+        0x4b => 0x50, // Left
+        0x4d => 0x4f, // Right
+        0x4f => 0x4d, // End
+        0x50 => 0x51, // Down
+        0x51 => 0x4e, // Page Down
+        0x52 => 0x49, // Insert
+        0x53 => 0x4c, // Delete
+        0x5b => 0xe3, // Left GUI
+        0x5c => 0xe7, // Right GUI
+        0x5d => 0x65, // Menu
+        // E0 2A などの偽のShiftは無視する
+        _ => 0,
+    }
+}
+
+/// Turns a stream of set 1 scancodes into key events.
+#[derive(Default)]
+pub struct ScancodeDecoder {
+    extended: bool,
+    /// Bytes left to skip in a Pause sequence (E1 1D 45 E1 9D C5).
+    skip: u8,
+}
+impl ScancodeDecoder {
+    pub const fn new() -> Self {
+        Self {
+            extended: false,
+            skip: 0,
+        }
+    }
+    pub fn feed(&mut self, byte: u8) -> Option<KeyEvent> {
+        if self.skip > 0 {
+            self.skip -= 1;
+            return None;
+        }
+        match byte {
+            0xe0 => {
+                self.extended = true;
+                return None;
+            }
+            0xe1 => {
+                self.skip = 5;
+                return None;
+            }
+            _ => {}
+        }
+        let extended = core::mem::take(&mut self.extended);
+        let code = byte & 0x7f;
+        let usage = if extended {
+            extended_to_usage(code)
+        } else {
+            SET1_TO_USAGE[code as usize]
+        };
+        if usage == 0 {
+            return None;
+        }
+        Some(KeyEvent {
+            usage,
+            pressed: byte & 0x80 == 0,
+        })
+    }
+}
+
+static DECODER: Mutex<ScancodeDecoder> = Mutex::new(ScancodeDecoder::new());
+
+fn irq_handler(_irq: u8) {
+    let byte = ps2::read_data_nowait();
+    let Some(mut decoder) = DECODER.try_lock() else {
+        return;
+    };
+    if let Some(key) = decoder.feed(byte) {
+        input::push(InputEvent::Key(key));
+    }
+}
+
+/// Enables the keyboard interrupt and starts delivering key events to the input queue.
+pub fn init() -> Result<()> {
+    ps2::flush();
+    let config = ps2::read_config()?;
+    ps2::write_config(
+        (config | ps2::CONFIG_KEYBOARD_IRQ | ps2::CONFIG_TRANSLATION)
+            & !ps2::CONFIG_KEYBOARD_CLOCK_DISABLED,
+    )?;
+    pic::register_irq_handler(KEYBOARD_IRQ, irq_handler)?;
+    kexec::register_shutdown_hook("keyboard", || {
+        pic::unregister_irq_handler(KEYBOARD_IRQ);
+        ps2::flush();
+    });
+    Ok(())
+}
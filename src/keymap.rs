@@ -0,0 +1,272 @@
+//! Translation of key events (HID usage IDs) into characters.
+//!
+//! The layout follows the `keymap` setting (us or jp) and can be changed
+//! at runtime.
+
+use crate::input::KeyEvent;
+use crate::settings;
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::Ordering;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Layout {
+    Us = 0,
+    /// The Japanese (JIS) layout.
+    Jis = 1,
+}
+impl core::str::FromStr for Layout {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "us" => Ok(Layout::Us),
+            "jp" => Ok(Layout::Jis),
+            _ => Err("keymap must be us or jp"),
+        }
+    }
+}
+
+static LAYOUT: AtomicU8 = AtomicU8::new(Layout::Us as u8);
+
+pub fn layout() -> Layout {
+    match LAYOUT.load(Ordering::Relaxed) {
+        1 => Layout::Jis,
+        _ => Layout::Us,
+    }
+}
+
+pub fn set_layout(layout: Layout) {
+    LAYOUT.store(layout as u8, Ordering::Relaxed);
+}
+
+/// Applies the `keymap` setting and follows its changes. Call after settings::load().
+pub fn init() {
+    let apply = |value: &str| set_layout(value.parse().unwrap_or(Layout::Us));
+    apply(&settings::get("keymap"));
+    settings::on_change("keymap", apply);
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub gui: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
+
+/// Keys that do not produce a character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NamedKey {
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Insert,
+    Delete,
+    F(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    /// Enter, Backspace, Tab, Esc and Ctrl+letter are control characters.
+    Char(char),
+    Named(NamedKey),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Keystroke {
+    pub key: Key,
+    pub modifiers: Modifiers,
+}
+
+/// (without Shift, with Shift) for the keys that differ between the layouts.
+fn symbol(layout: Layout, usage: u8) -> Option<(char, Option<char>)> {
+    let us = match usage {
+        0x1e..=0x26 => {
+            let i = (usage - 0x1e) as usize;
+            let digit = (b'1' + i as u8) as char;
+            let shifted = match layout {
+                Layout::Us => b"!@#$%^&*("[i],
+                Layout::Jis => b"!\"#$%&'()"[i],
+            };
+            return Some((digit, Some(shifted as char)));
+        }
+        0x27 => ('0', ')'),
+        0x2c => (' ', ' '),
+        0x2d => ('-', '_'),
+        0x2e => ('=', '+'),
+        0x2f => ('[', '{'),
+        0x30 => (']', '}'),
+        0x31 | 0x32 | 0x64 => ('\\', '|'),
+        0x33 => (';', ':'),
+        0x34 => ('\'', '"'),
+        0x35 => ('`', '~'),
+        0x36 => (',', '<'),
+        0x37 => ('.', '>'),
+        0x38 => ('/', '?'),
+        0x87 | 0x89 if layout == Layout::Jis => {
+            return match usage {
+                // ろ
+                0x87 => Some(('\\', Some('_'))),
+                // ¥ (ASCIIでは\として扱う)
+                _ => Some(('\\', Some('|'))),
+            };
+        }
+        _ => return None,
+    };
+    if layout == Layout::Us {
+        return Some((us.0, Some(us.1)));
+    }
+    // JISでは記号の位置がUSと異なる
+    let jis = match usage {
+        0x27 => ('0', None),
+        0x2d => ('-', Some('=')),
+        0x2e => ('^', Some('~')),
+        0x2f => ('@', Some('`')),
+        0x30 => ('[', Some('{')),
+        0x31 | 0x32 => (']', Some('}')),
+        0x33 => (';', Some('+')),
+        0x34 => (':', Some('*')),
+        // 半角/全角
+        0x35 => return None,
+        _ => (us.0, Some(us.1)),
+    };
+    Some(jis)
+}
+
+/// Characters of the keypad keys with NumLock on.
+fn keypad(usage: u8, num_lock: bool) -> Option<Key> {
+    let c = match usage {
+        0x54 => '/',
+        0x55 => '*',
+        0x56 => '-',
+        0x57 => '+',
+        0x58 => '\n',
+        0x59..=0x63 if !num_lock => {
+            return match usage {
+                0x59 => Some(Key::Named(NamedKey::End)),
+                0x5a => Some(Key::Named(NamedKey::Down)),
+                0x5b => Some(Key::Named(NamedKey::PageDown)),
+                0x5c => Some(Key::Named(NamedKey::Left)),
+                0x5e => Some(Key::Named(NamedKey::Right)),
+                0x5f => Some(Key::Named(NamedKey::Home)),
+                0x60 => Some(Key::Named(NamedKey::Up)),
+                0x61 => Some(Key::Named(NamedKey::PageUp)),
+                0x62 => Some(Key::Named(NamedKey::Insert)),
+                0x63 => Some(Key::Named(NamedKey::Delete)),
+                _ => None,
+            };
+        }
+        0x59..=0x61 => (b'1' + (usage - 0x59)) as char,
+        0x62 => '0',
+        0x63 => '.',
+        _ => return None,
+    };
+    Some(Key::Char(c))
+}
+
+/// Tracks the modifier keys and translates key events into keystrokes.
+#[derive(Default)]
+pub struct KeyMapper {
+    left_shift: bool,
+    right_shift: bool,
+    left_ctrl: bool,
+    right_ctrl: bool,
+    left_alt: bool,
+    right_alt: bool,
+    gui: bool,
+    caps_lock: bool,
+    num_lock: bool,
+}
+impl KeyMapper {
+    pub const fn new() -> Self {
+        Self {
+            left_shift: false,
+            right_shift: false,
+            left_ctrl: false,
+            right_ctrl: false,
+            left_alt: false,
+            right_alt: false,
+            gui: false,
+            caps_lock: false,
+            num_lock: true,
+        }
+    }
+    pub fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.left_shift || self.right_shift,
+            ctrl: self.left_ctrl || self.right_ctrl,
+            alt: self.left_alt || self.right_alt,
+            gui: self.gui,
+            caps_lock: self.caps_lock,
+            num_lock: self.num_lock,
+        }
+    }
+    /// Translates with the current layout. Returns None for releases and
+    /// for keys that only change the modifiers.
+    pub fn process(&mut self, e: &KeyEvent) -> Option<Keystroke> {
+        self.process_with(layout(), e)
+    }
+    pub fn process_with(&mut self, layout: Layout, e: &KeyEvent) -> Option<Keystroke> {
+        match e.usage {
+            0xe0 => self.left_ctrl = e.pressed,
+            0xe1 => self.left_shift = e.pressed,
+            0xe2 => self.left_alt = e.pressed,
+            0xe3 | 0xe7 => self.gui = e.pressed,
+            0xe4 => self.right_ctrl = e.pressed,
+            0xe5 => self.right_shift = e.pressed,
+            0xe6 => self.right_alt = e.pressed,
+            0x39 if e.pressed => self.caps_lock = !self.caps_lock,
+            0x53 if e.pressed => self.num_lock = !self.num_lock,
+            _ if e.pressed => {
+                let modifiers = self.modifiers();
+                let key = self.translate(layout, e.usage, &modifiers)?;
+                return Some(Keystroke { key, modifiers });
+            }
+            _ => {}
+        }
+        None
+    }
+    fn translate(&self, layout: Layout, usage: u8, m: &Modifiers) -> Option<Key> {
+        let named = |k| Some(Key::Named(k));
+        match usage {
+            0x04..=0x1d => {
+                let c = (b'a' + (usage - 0x04)) as char;
+                if m.ctrl {
+                    // Ctrl+C => 0x03 (ETX)
+                    return Some(Key::Char(((c as u8) & 0x1f) as char));
+                }
+                // CapsLockはアルファベットにだけ効き、Shiftと打ち消し合う
+                let upper = m.shift != m.caps_lock;
+                Some(Key::Char(if upper { c.to_ascii_uppercase() } else { c }))
+            }
+            0x28 => Some(Key::Char('\n')),
+            0x29 => Some(Key::Char('\x1b')),
+            0x2a => Some(Key::Char('\x08')),
+            0x2b => Some(Key::Char('\t')),
+            0x3a..=0x45 => named(NamedKey::F(usage - 0x3a + 1)),
+            0x49 => named(NamedKey::Insert),
+            0x4a => named(NamedKey::Home),
+            0x4b => named(NamedKey::PageUp),
+            0x4c => named(NamedKey::Delete),
+            0x4d => named(NamedKey::End),
+            0x4e => named(NamedKey::PageDown),
+            0x4f => named(NamedKey::Right),
+            0x50 => named(NamedKey::Left),
+            0x51 => named(NamedKey::Down),
+            0x52 => named(NamedKey::Up),
+            0x54..=0x63 => keypad(usage, m.num_lock),
+            _ => {
+                let (normal, shifted) = symbol(layout, usage)?;
+                let c = if m.shift { shifted? } else { normal };
+                Some(Key::Char(c))
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+#![no_std]
+#![feature(offset_of)]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+pub mod ab_boot;
+pub mod acpi;
+pub mod allocator;
+pub mod apic;
+pub mod arch;
+pub mod assets;
+pub mod base64;
+#[cfg(feature = "gui")]
+pub mod bench;
+#[cfg(feature = "storage")]
+pub mod block;
+pub mod bmp;
+pub mod boot_info;
+pub mod boot_menu;
+pub mod chainload;
+pub mod clipboard;
+pub mod cmdline;
+pub mod compat;
+#[cfg(feature = "gui")]
+pub mod console;
+pub mod crc;
+#[cfg(feature = "gui")]
+pub mod cursor;
+pub mod dma;
+pub mod dmesg;
+pub mod elf;
+pub mod executor;
+#[cfg(feature = "gui")]
+pub mod font;
+pub mod fpu;
+pub mod fuzz;
+pub mod gdt;
+pub mod graphics;
+#[cfg(feature = "gui")]
+pub mod gui_test;
+pub mod hpet;
+pub mod i18n;
+pub mod input;
+pub mod input_replay;
+pub mod interrupt;
+pub mod ioapic;
+pub mod job;
+pub mod kexec;
+pub mod keyboard;
+pub mod keymap;
+pub mod limine;
+pub mod loader;
+pub mod log;
+pub mod mem;
+pub mod memory_map;
+pub mod memtest;
+pub mod mouse;
+pub mod mutex;
+#[cfg(feature = "net")]
+pub mod net;
+#[cfg(feature = "gui")]
+pub mod pager;
+pub mod paging;
+pub mod pci;
+pub mod percpu;
+pub mod perf;
+pub mod pic;
+pub mod power;
+pub mod print;
+pub mod process;
+pub mod ps2;
+pub mod qemu;
+pub mod rand;
+pub mod result;
+pub mod rtc;
+pub mod scheduler;
+#[cfg(feature = "gui")]
+pub mod screen;
+#[cfg(feature = "gui")]
+pub mod screenshot;
+pub mod serial;
+pub mod serial_console;
+pub mod settings;
+pub mod sha256;
+pub mod shell;
+pub mod smp;
+pub mod stack;
+pub mod syscall;
+pub mod task;
+#[cfg(feature = "gui")]
+pub mod taskbar;
+pub mod time;
+pub mod tty;
+pub mod uefi;
+#[cfg(feature = "usb")]
+pub mod usb;
+pub mod user;
+pub mod version;
+#[cfg(any(feature = "storage", feature = "net"))]
+pub mod virtio;
+#[cfg(feature = "gui")]
+pub mod widget;
+#[cfg(feature = "gui")]
+pub mod window_protocol;
+#[cfg(feature = "gui")]
+pub mod wm;
+pub mod wx;
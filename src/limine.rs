@@ -0,0 +1,200 @@
+//! Requests of the Limine boot protocol (base revision 0).
+//!
+//! The bootloader finds the requests in the kernel ELF, fills in their
+//! response pointers and jumps to `_start` in 64-bit mode. In base revision 0
+//! the first 4 GiB are identity mapped in addition to the HHDM, so physical
+//! addresses of ACPI tables and MMIO below 4 GiB can be used as is, like
+//! in the UEFI path.
+//!
+//! See src/bin/limine.rs for the entry point.
+
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::ffi::c_char;
+use core::ffi::CStr;
+use core::ptr::read_volatile;
+
+const COMMON_MAGIC: [u64; 2] = [0xc7b1dd30df4c8b88, 0x0a82e883a194f07b];
+
+#[repr(C)]
+pub struct Request<T> {
+    id: [u64; 4],
+    revision: u64,
+    response: UnsafeCell<*const T>,
+}
+// SAFETY: the response is written only by the bootloader before the kernel starts.
+unsafe impl<T> Sync for Request<T> {}
+impl<T: 'static> Request<T> {
+    const fn new(id: [u64; 2]) -> Self {
+        Self {
+            id: [COMMON_MAGIC[0], COMMON_MAGIC[1], id[0], id[1]],
+            revision: 0,
+            response: UnsafeCell::new(core::ptr::null()),
+        }
+    }
+    fn response(&self) -> Option<&'static T> {
+        // ブートローダが書き換えるので、コンパイラにnullと決めつけさせない
+        let p = unsafe { read_volatile(self.response.get()) };
+        unsafe { p.as_ref() }
+    }
+}
+
+unsafe fn slice_of_ptrs<T: 'static>(
+    ptr: *const *const T,
+    count: u64,
+) -> impl Iterator<Item = &'static T> {
+    let ptrs: &[*const T] = if ptr.is_null() {
+        &[]
+    } else {
+        core::slice::from_raw_parts(ptr, count as usize)
+    };
+    ptrs.iter().filter_map(|p| p.as_ref())
+}
+
+#[repr(C)]
+pub struct Framebuffer {
+    pub address: *mut u8,
+    pub width: u64,
+    pub height: u64,
+    /// Bytes per line.
+    pub pitch: u64,
+    pub bpp: u16,
+    pub memory_model: u8,
+    pub red_mask_size: u8,
+    pub red_mask_shift: u8,
+    pub green_mask_size: u8,
+    pub green_mask_shift: u8,
+    pub blue_mask_size: u8,
+    pub blue_mask_shift: u8,
+    _unused: [u8; 7],
+    pub edid_size: u64,
+    pub edid: *const u8,
+}
+#[repr(C)]
+pub struct FramebufferResponse {
+    revision: u64,
+    framebuffer_count: u64,
+    framebuffers: *const *const Framebuffer,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum MemoryType {
+    Usable = 0,
+    Reserved,
+    AcpiReclaimable,
+    AcpiNvs,
+    BadMemory,
+    BootloaderReclaimable,
+    KernelAndModules,
+    Framebuffer,
+}
+#[repr(C)]
+#[derive(Debug)]
+pub struct MemoryMapEntry {
+    pub base: u64,
+    pub length: u64,
+    pub memory_type: MemoryType,
+}
+#[repr(C)]
+pub struct MemoryMapResponse {
+    revision: u64,
+    entry_count: u64,
+    entries: *const *const MemoryMapEntry,
+}
+
+#[repr(C)]
+pub struct HhdmResponse {
+    revision: u64,
+    offset: u64,
+}
+
+#[repr(C)]
+struct File {
+    revision: u64,
+    address: *mut u8,
+    size: u64,
+    path: *const c_char,
+    cmdline: *const c_char,
+}
+#[repr(C)]
+pub struct ModuleResponse {
+    revision: u64,
+    module_count: u64,
+    modules: *const *const File,
+}
+
+#[repr(C)]
+pub struct RsdpResponse {
+    revision: u64,
+    address: *const u8,
+}
+
+#[used]
+#[link_section = ".requests"]
+static FRAMEBUFFER_REQUEST: Request<FramebufferResponse> =
+    Request::new([0x9d5827dcd881dd75, 0xa3148604f6fab11b]);
+#[used]
+#[link_section = ".requests"]
+static MEMORY_MAP_REQUEST: Request<MemoryMapResponse> =
+    Request::new([0x67cf3d9d378a806f, 0xe304acdfc50c3c62]);
+#[used]
+#[link_section = ".requests"]
+static HHDM_REQUEST: Request<HhdmResponse> = Request::new([0x48dcf1cb8ad2b852, 0x63984e959a98244b]);
+#[used]
+#[link_section = ".requests"]
+static MODULE_REQUEST: Request<ModuleResponse> =
+    Request::new([0x3e7e279702be32af, 0xca1c4f3bd1280cee]);
+#[used]
+#[link_section = ".requests"]
+static RSDP_REQUEST: Request<RsdpResponse> = Request::new([0xc5e77b6b397e7b43, 0x27637845accdcf3c]);
+
+/// The first framebuffer, if the bootloader set one up.
+pub fn framebuffer() -> Option<&'static Framebuffer> {
+    let r = FRAMEBUFFER_REQUEST.response()?;
+    unsafe { slice_of_ptrs(r.framebuffers, r.framebuffer_count) }.next()
+}
+
+pub fn memory_map() -> impl Iterator<Item = &'static MemoryMapEntry> {
+    let (entries, count) = MEMORY_MAP_REQUEST
+        .response()
+        .map_or((core::ptr::null(), 0), |r| (r.entries, r.entry_count));
+    unsafe { slice_of_ptrs(entries, count) }
+}
+
+/// Offset of the higher half direct map, where all physical memory is mapped.
+pub fn hhdm_offset() -> Option<u64> {
+    HHDM_REQUEST.response().map(|r| r.offset)
+}
+
+/// The RSDP address, usable with acpi::Acpi::from_rsdp().
+pub fn rsdp() -> Option<*const u8> {
+    RSDP_REQUEST.response().map(|r| r.address)
+}
+
+/// A file loaded by the bootloader along with the kernel.
+pub struct Module {
+    pub path: &'static str,
+    pub cmdline: &'static str,
+    pub data: &'static [u8],
+}
+
+pub fn modules() -> Vec<Module> {
+    let Some(r) = MODULE_REQUEST.response() else {
+        return Vec::new();
+    };
+    let cstr = |p: *const c_char| {
+        if p.is_null() {
+            ""
+        } else {
+            unsafe { CStr::from_ptr(p) }.to_str().unwrap_or("")
+        }
+    };
+    unsafe { slice_of_ptrs(r.modules, r.module_count) }
+        .map(|f| Module {
+            path: cstr(f.path),
+            cmdline: cstr(f.cmdline),
+            data: unsafe { core::slice::from_raw_parts(f.address, f.size as usize) },
+        })
+        .collect()
+}
@@ -0,0 +1,172 @@
+//! The first stage of the two-stage boot: loading the kernel ELF from the
+//! ESP and jumping to it.
+//!
+//! The segments are put at the physical addresses they are linked at (see
+//! linker/kernel.ld), since the kernel starts with the identity mapping of
+//! the firmware. The RSDP, the load options of the loader as the command
+//! line and the initramfs (if there is one next to the kernel) go into the
+//! BootInfo with the memory map, the boot services are exited and the entry
+//! is called with the BootInfo in rdi on a stack of its own.
+
+use crate::arch::cli;
+use crate::boot_info::BootInfo;
+use crate::boot_info::Framebuffer;
+use crate::boot_info::MemoryRegion;
+use crate::elf;
+use crate::elf::Elf;
+use crate::graphics::Bitmap;
+use crate::result::Result;
+use crate::uefi;
+use crate::uefi::AllocateType;
+use crate::uefi::EfiHandle;
+use crate::uefi::EfiMemoryType;
+use crate::uefi::EfiSystemTable;
+use crate::uefi::MemoryMapHolder;
+use crate::uefi::EFI_ACPI_20_TABLE_GUID;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::convert::Infallible;
+
+/// Where the loader looks for the kernel on the ESP.
+pub const KERNEL_PATH: &str = "\\EFI\\wasabi\\kernel.elf";
+/// Passed to the kernel if it exists.
+pub const INITRAMFS_PATH: &str = "\\EFI\\wasabi\\initramfs";
+const PAGE_SIZE: u64 = 4096;
+const STACK_PAGES: usize = 16;
+/// Extra regions to reserve, as the map can grow between two GetMemoryMap().
+const MEMORY_MAP_SLACK: usize = 8;
+
+/// Copies the segments of the kernel to where they are linked at, with
+/// the pages allocated from the firmware so that nothing else is there.
+pub fn load_segments(efi_system_table: &EfiSystemTable, kernel: &Elf) -> Result<()> {
+    for segment in &kernel.segments {
+        let start = segment.vaddr & !(PAGE_SIZE - 1);
+        let end = (segment.vaddr + segment.mem_size).div_ceil(PAGE_SIZE) * PAGE_SIZE;
+        let memory_type = if segment.executable {
+            EfiMemoryType::LOADER_CODE
+        } else {
+            EfiMemoryType::LOADER_DATA
+        };
+        efi_system_table
+            .boot_services
+            .allocate_pages(
+                AllocateType::Address(start),
+                memory_type,
+                ((end - start) / PAGE_SIZE) as usize,
+            )
+            .map_err(|_| "The kernel is linked at memory that is in use")?;
+        // SAFETY: the pages were just allocated for the segment, and the
+        // firmware maps all the memory 1:1
+        unsafe {
+            let dst = segment.vaddr as *mut u8;
+            core::ptr::copy_nonoverlapping(segment.data.as_ptr(), dst, segment.data.len());
+            core::ptr::write_bytes(
+                dst.add(segment.data.len()),
+                0,
+                segment.mem_size as usize - segment.data.len(),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// The screen as set up by the firmware, or none if there is no GOP.
+pub fn framebuffer(efi_system_table: &EfiSystemTable) -> Framebuffer {
+    match uefi::init_vram(efi_system_table) {
+        Ok(mut vram) => Framebuffer::new(
+            vram.buf_mut() as u64,
+            vram.width() as u32,
+            vram.height() as u32,
+            vram.pixels_per_scan_line() as u32,
+        ),
+        Err(_) => Framebuffer::default(),
+    }
+}
+
+/// Gets the final memory map into regions and exits the boot services.
+fn exit_boot_services(
+    efi_system_table: &EfiSystemTable,
+    image_handle: EfiHandle,
+    regions: &mut Vec<MemoryRegion>,
+) -> Result<()> {
+    const MAX_TRIES: usize = 4;
+    let mut map = Box::new(MemoryMapHolder::new());
+    for _ in 0..MAX_TRIES {
+        efi_system_table.boot_services.fetch_memory_map(&mut map)?;
+        // 確保はカーネル側のヒープからなので、ファームウェアのメモリマップは変わらない
+        regions.clear();
+        regions.reserve(map.iter().count() + MEMORY_MAP_SLACK);
+        regions.extend(map.iter().map(MemoryRegion::from_efi));
+        if efi_system_table
+            .boot_services
+            .exit_boot_services(image_handle, map.map_key())
+            .is_ok()
+        {
+            return Ok(());
+        }
+    }
+    Err("ExitBootServices kept failing")
+}
+
+/// Calls the entry of the kernel on a new stack. The System V ABI wants
+/// rsp to be 16-byte aligned at the call, which the page-aligned top is.
+///
+/// # Safety
+/// entry must be the entry of a kernel that takes the BootInfo, and
+/// stack_top the end of memory that nobody else uses.
+unsafe fn jump(entry: u64, boot_info: *const BootInfo, stack_top: u64) -> ! {
+    asm!(
+        "mov rsp, {stack_top}",
+        "xor ebp, ebp",
+        "call {entry}",
+        "ud2",
+        stack_top = in(reg) stack_top,
+        entry = in(reg) entry,
+        in("rdi") boot_info,
+        options(noreturn)
+    )
+}
+
+/// Loads the kernel ELF at path on the ESP and boots it. Returns only if
+/// that failed, with the boot services still available if it failed before
+/// exiting them.
+pub fn boot(
+    efi_system_table: &EfiSystemTable,
+    image_handle: EfiHandle,
+    path: &str,
+) -> Result<Infallible> {
+    let image = uefi::read_file(efi_system_table, path)?;
+    let kernel = elf::parse(&image)?;
+    load_segments(efi_system_table, &kernel)?;
+    let stack = efi_system_table.boot_services.allocate_pages(
+        AllocateType::AnyPages,
+        EfiMemoryType::LOADER_DATA,
+        STACK_PAGES,
+    )?;
+    let framebuffer = framebuffer(efi_system_table);
+    let rsdp = efi_system_table
+        .lookup_configuration_table(&EFI_ACPI_20_TABLE_GUID)
+        .map(|rsdp| rsdp as u64);
+    let cmdline = uefi::load_options(efi_system_table, image_handle).unwrap_or_default();
+    let initramfs = uefi::read_file(efi_system_table, INITRAMFS_PATH).ok();
+    let mut regions = Vec::new();
+    exit_boot_services(efi_system_table, image_handle, &mut regions)?;
+    cli();
+    // カーネルはBootInfoを返さないので、リークさせて生かしておく
+    let boot_info = Box::leak(Box::new(BootInfo::new(
+        framebuffer,
+        regions.leak(),
+        rsdp,
+        cmdline.leak(),
+        initramfs.map(|data| &*data.leak()),
+    )));
+    // SAFETY: the kernel is loaded and the stack is allocated for it
+    unsafe {
+        jump(
+            kernel.entry,
+            boot_info,
+            stack + (STACK_PAGES as u64) * PAGE_SIZE,
+        )
+    }
+}
@@ -0,0 +1,328 @@
+//! A logging facade: error!(), warn!(), info!(), debug!() and trace!().
+//!
+//! A message is logged with the module path of the caller as its target.
+//! Whether it is logged is decided by the level of the longest matching
+//! module prefix set with set_level(), or the default level (Info). Logged
+//! messages are passed to every enabled sink: the serial port, the screen
+//! console and the kernel message buffer shown by `dmesg` (see dmesg.rs).
+//!
+//! Do not log from interrupt handlers: the sinks take locks.
+
+use crate::dmesg::DmesgWriter;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::serial::SerialPort;
+use crate::settings;
+use crate::time;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+impl Level {
+    pub const ALL: [Level; 5] = [
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|l| l.name() == s)
+    }
+}
+
+/// The most verbose level logged, or None to log nothing.
+pub type LevelFilter = Option<Level>;
+
+fn filter_name(filter: LevelFilter) -> &'static str {
+    filter.map_or("off", Level::name)
+}
+
+fn parse_filter(s: &str) -> Result<LevelFilter> {
+    match s {
+        "off" => Ok(None),
+        _ => Level::parse(s).map(Some).ok_or("Unknown log level"),
+    }
+}
+
+pub struct Record<'a> {
+    pub level: Level,
+    /// The module path of the caller.
+    pub target: &'a str,
+    /// Nanoseconds since boot (0 before the TSC is calibrated).
+    pub time_ns: u64,
+    pub args: fmt::Arguments<'a>,
+}
+impl fmt::Display for Record<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let us = self.time_ns / 1000;
+        write!(
+            f,
+            "[{:5}.{:06}] {:<5} {}: {}",
+            us / 1_000_000,
+            us % 1_000_000,
+            self.level.name(),
+            self.target,
+            self.args
+        )
+    }
+}
+
+pub trait Sink: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn log(&self, record: &Record);
+}
+
+/// Prints to the serial port.
+pub struct SerialSink;
+impl Sink for SerialSink {
+    fn name(&self) -> &'static str {
+        "serial"
+    }
+    fn log(&self, record: &Record) {
+        let _ = writeln!(SerialPort::default(), "{record}");
+    }
+}
+
+/// Prints to the screen console, once it is started.
+#[cfg(feature = "gui")]
+pub struct ScreenSink;
+#[cfg(feature = "gui")]
+impl Sink for ScreenSink {
+    fn name(&self) -> &'static str {
+        "screen"
+    }
+    fn log(&self, record: &Record) {
+        let _ = writeln!(crate::console::ConsoleWriter, "{record}");
+    }
+}
+
+/// Appends to the kernel message buffer shown by `dmesg`.
+pub struct RingSink;
+impl Sink for RingSink {
+    fn name(&self) -> &'static str {
+        "ring"
+    }
+    fn log(&self, record: &Record) {
+        let _ = writeln!(DmesgWriter, "{record}");
+    }
+}
+
+struct SinkEntry {
+    sink: &'static dyn Sink,
+    enabled: bool,
+}
+
+struct Config {
+    default: LevelFilter,
+    /// Levels of module path prefixes, e.g. "wasabi::net".
+    modules: Vec<(String, LevelFilter)>,
+    sinks: Vec<SinkEntry>,
+}
+impl Config {
+    fn enabled(&self, level: Level, target: &str) -> bool {
+        self.filter(target).is_some_and(|max| level <= max)
+    }
+    fn filter(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .filter(|(prefix, _)| is_in_module(target, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, filter)| *filter)
+    }
+}
+
+static CONFIG: Mutex<Config> = Mutex::new(Config {
+    default: Some(Level::Info),
+    modules: Vec::new(),
+    sinks: Vec::new(),
+});
+
+/// Whether target is the module or one of its submodules.
+fn is_in_module(target: &str, module: &str) -> bool {
+    target
+        .strip_prefix(module)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+/// Registers the built-in sinks. The screen is not enabled, as the boot
+/// messages would clutter the shell.
+pub fn init() {
+    register_sink(&SerialSink, true);
+    #[cfg(feature = "gui")]
+    register_sink(&ScreenSink, false);
+    register_sink(&RingSink, true);
+}
+
+/// Applies the `log_level` and `serial_log` settings and follows their
+/// changes. Call after settings::load().
+pub fn init_settings() {
+    let apply_level = |value: &str| set_level(None, Level::parse(value));
+    let apply_serial = |value: &str| {
+        let _ = set_sink_enabled("serial", value == "on");
+    };
+    apply_level(&settings::get("log_level"));
+    apply_serial(&settings::get("serial_log"));
+    settings::on_change("log_level", apply_level);
+    settings::on_change("serial_log", apply_serial);
+}
+
+/// Adds a sink, replacing the one with the same name.
+pub fn register_sink(sink: &'static dyn Sink, enabled: bool) {
+    let mut config = CONFIG.lock();
+    config.sinks.retain(|s| s.sink.name() != sink.name());
+    config.sinks.push(SinkEntry { sink, enabled });
+}
+
+pub fn set_sink_enabled(name: &str, enabled: bool) -> Result<()> {
+    let mut config = CONFIG.lock();
+    let entry = config
+        .sinks
+        .iter_mut()
+        .find(|s| s.sink.name() == name)
+        .ok_or("No such log sink")?;
+    entry.enabled = enabled;
+    Ok(())
+}
+
+/// Sets the level of the module and its submodules, or the default level
+/// if module is None.
+pub fn set_level(module: Option<&str>, filter: LevelFilter) {
+    let mut config = CONFIG.lock();
+    match module {
+        None => config.default = filter,
+        Some(module) => {
+            config.modules.retain(|(m, _)| m != module);
+            config.modules.push((module.to_string(), filter));
+        }
+    }
+}
+
+/// Makes the module follow the default level again.
+pub fn reset_level(module: &str) {
+    CONFIG.lock().modules.retain(|(m, _)| m != module);
+}
+
+pub fn enabled(level: Level, target: &str) -> bool {
+    CONFIG.lock().enabled(level, target)
+}
+
+#[doc(hidden)]
+pub fn _log(level: Level, target: &str, args: fmt::Arguments) {
+    let sinks: Vec<&'static dyn Sink> = {
+        let config = CONFIG.lock();
+        if !config.enabled(level, target) {
+            return;
+        }
+        config
+            .sinks
+            .iter()
+            .filter(|s| s.enabled)
+            .map(|s| s.sink)
+            .collect()
+    };
+    let record = Record {
+        level,
+        target,
+        time_ns: time::now_ns(),
+        args,
+    };
+    for sink in sinks {
+        sink.log(&record);
+    }
+}
+
+/// Logs a message at the level, e.g. `log!(Level::Info, "{n} devices")`.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)*) => (
+        $crate::log::_log($level, module_path!(), format_args!($($arg)*))
+    );
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Error, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Warn, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Info, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Debug, $($arg)*));
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)*) => ($crate::log!($crate::log::Level::Trace, $($arg)*));
+}
+
+/// The `log` command.
+pub fn cmd_log(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    const USAGE: &str = "usage: log [level [MODULE] LEVEL|default] [sink NAME on|off]";
+    match args {
+        [] => {
+            let config = CONFIG.lock();
+            let _ = writeln!(out, "default: {}", filter_name(config.default));
+            for (module, filter) in &config.modules {
+                let _ = writeln!(out, "{module}: {}", filter_name(*filter));
+            }
+            let sinks: Vec<String> = config
+                .sinks
+                .iter()
+                .map(|s| {
+                    format!(
+                        "{}({})",
+                        s.sink.name(),
+                        if s.enabled { "on" } else { "off" }
+                    )
+                })
+                .collect();
+            let _ = writeln!(out, "sinks: {}", sinks.join(" "));
+            Ok(())
+        }
+        ["level", level] => {
+            set_level(None, parse_filter(level)?);
+            Ok(())
+        }
+        ["level", module, "default"] => {
+            reset_level(module);
+            Ok(())
+        }
+        ["level", module, level] => {
+            set_level(Some(module), parse_filter(level)?);
+            Ok(())
+        }
+        ["sink", name, "on"] => set_sink_enabled(name, true),
+        ["sink", name, "off"] => set_sink_enabled(name, false),
+        _ => Err(USAGE),
+    }
+}
@@ -1,475 +1,266 @@
 #![no_std]
 #![no_main]
-#![feature(offset_of)]
 
-// インラインアセンブリを使うための宣言
-use core::arch::asm;
-use core::cmp::min;
-use core::fmt;
-use core::fmt::Write;
-use core::mem::offset_of;
-use core::mem::size_of;
-use core::panic::PanicInfo;
-use core::ptr::null_mut;
-use core::writeln;
-
-type EfiVoid = u8;
-type EfiHandle = u64;
-type Result<T> = core::result::Result<T, &'static str>;
-
-#[repr(C)]
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
-struct EfiGuid {
-    data0: u32,
-    data1: u16,
-    data2: u16,
-    data3: [u8; 8],
-}
-
-const EFI_GRAPHICS_OUTPUT_PROTOCOL_GUID: EfiGuid = EfiGuid {
-    data0: 0x9042a9de,
-    data1: 0x23dc,
-    data2: 0x4a38,
-    data3: [0x96, 0xfb, 0x7a, 0xde, 0xd0, 0x80, 0x51, 0x6a],
-};
-
-#[derive(Debug, PartialEq, Eq, Copy, Clone)]
-#[must_use]
-#[repr(u64)]
-enum EfiStatus {
-    Success = 0,
-}
-
-#[repr(i64)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[allow(non_camel_case_types)]
-pub enum EfiMemoryType {
-    RESERVED = 0,
-    LOADER_CODE,
-    LOADER_DATA,
-    BOOT_SERVICES_CODE,
-    BOOT_SERVICES_DATA,
-    RUNTIME_SERVICES_CODE,
-    RUNTIME_SERVICES_DATA,
-    CONVENTIONAL_MEMORY,
-    UNUSABLE_MEMORY,
-    ACPI_RECLAIM_MEMORY,
-    ACPI_MEMORY_NVS,
-    MEMORY_MAPPED_IO,
-    MEMORY_MAPPED_IO_PORT_SPACE,
-    PAL_CODE,
-    PERSISTENT_MEMORY,
-}
-
-#[repr(C)]
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
-struct EfiMemoryDescriptor {
-    memory_type: EfiMemoryType,
-    physical_start: u64,
-    virtual_start: u64,
-    number_of_pages: u64,
-    attribute: u64,
-}
-
-const MEMORY_MAP_BUFFER_SIZE: usize = 0x8000;
-
-struct MemoryMapHolder {
-    memory_map_buffer: [u8; MEMORY_MAP_BUFFER_SIZE],
-    memory_map_size: usize,
-    map_key: usize,
-    descriptor_size: usize,
-    descriptor_version: u32,
-}
-struct MemoryMapIterator<'a> {
-    map: &'a MemoryMapHolder,
-    ofs: usize,
-}
-impl<'a> Iterator for MemoryMapIterator<'a> {
-    type Item = &'a EfiMemoryDescriptor;
-    fn next(&mut self) -> Option<&'a EfiMemoryDescriptor> {
-        if self.ofs >= self.map.memory_map_size {
-            None
-        } else {
-            let e: &EfiMemoryDescriptor = unsafe {
-                &*(self.map.memory_map_buffer.as_ptr().add(self.ofs) as *const EfiMemoryDescriptor)
-            };
-            self.ofs += self.map.descriptor_size;
-            Some(e)
-        }
-    }
-}
-
-impl MemoryMapHolder {
-    pub const fn new() -> MemoryMapHolder {
-        MemoryMapHolder {
-            memory_map_buffer: [0; MEMORY_MAP_BUFFER_SIZE],
-            memory_map_size: MEMORY_MAP_BUFFER_SIZE,
-            map_key: 0,
-            descriptor_size: 0,
-            descriptor_version: 0,
-        }
-    }
-    pub fn iter(&self) -> MemoryMapIterator {
-        MemoryMapIterator { map: self, ofs: 0 }
-    }
-}
-
-#[repr(C)]
-struct EfiBootServicesTable {
-    _reserved0: [u64; 7],
-    get_memory_map: extern "win64" fn(
-        memory_map_size: *mut usize,
-        memory_map: *mut u8,
-        map_key: *mut usize,
-        descriptor_size: *mut usize,
-        descriptor_version: *mut u32,
-    ) -> EfiStatus,
-    _reserved1: [u64; 32],
-    locate_protocol: extern "win64" fn(
-        protocol: *const EfiGuid,
-        registration: *mut EfiVoid,
-        interface: *mut *mut EfiVoid,
-    ) -> EfiStatus,
-}
-impl EfiBootServicesTable {
-    fn get_memory_map(&self, map: &mut MemoryMapHolder) -> EfiStatus {
-        (self.get_memory_map)(
-            &mut map.memory_map_size,
-            map.memory_map_buffer.as_mut_ptr(),
-            &mut map.map_key,
-            &mut map.descriptor_size,
-            &mut map.descriptor_version,
-        )
-    }
-}
-const _: () = assert!(offset_of!(EfiBootServicesTable, get_memory_map) == 56);
-const _: () = assert!(offset_of!(EfiBootServicesTable, locate_protocol) == 320);
-
-#[repr(C)]
-struct EfiSystemTable {
-    _reserved0: [u64; 12],
-    pub boot_services: &'static EfiBootServicesTable,
-}
-const _: () = assert!(offset_of!(EfiSystemTable, boot_services) == 96);
-
-#[repr(C)]
-#[derive(Debug)]
-struct EfiGraphicsOutputProtocolPixelInfo {
-    pub version: u32,
-    pub horizontal_resolution: u32,
-    pub vertical_resolution: u32,
-    _padding0: [u32; 5],
-    pub pixels_per_scan_line: u32,
-}
-const _: () = assert!(size_of::<EfiGraphicsOutputProtocolPixelInfo>() == 36);
-
-#[repr(C)]
-#[derive(Debug)]
-struct EfiGraphicsOutputProtocolMode<'a> {
-    pub max_mode: u32,
-    pub mode: u32,
-    pub info: &'a EfiGraphicsOutputProtocolPixelInfo,
-    pub size_of_info: u32,
-    pub frame_buffer_base: usize,
-    pub frame_buffer_size: usize,
-}
+extern crate alloc;
 
-#[repr(C)]
-#[derive(Debug)]
-struct EfiGraphicsOutputProtocol<'a> {
-    reserved: [u64; 3],
-    pub mode: &'a EfiGraphicsOutputProtocolMode<'a>,
-}
-fn locate_graphic_protocol<'a>(
-    efi_system_table: &'a EfiSystemTable,
-) -> Result<&'a EfiGraphicsOutputProtocol<'a>> {
-    let mut efi_graphics_output_protocol = null_mut::<EfiGraphicsOutputProtocol>();
-    let status = (efi_system_table.boot_services.locate_protocol)(
-        &EFI_GRAPHICS_OUTPUT_PROTOCOL_GUID,
-        null_mut::<EfiVoid>(),
-        &mut efi_graphics_output_protocol as *mut *mut EfiGraphicsOutputProtocol
-            as *mut *mut EfiVoid,
-    );
-    if status != EfiStatus::Success {
-        return Err("Failed to locate graphics outptut protocol");
-    }
-    Ok(unsafe { &*efi_graphics_output_protocol })
-}
-
-pub fn hlt() {
-    unsafe {
-        asm!("hlt");
-    }
-}
+use alloc::boxed::Box;
+use core::panic::PanicInfo;
+use core::time::Duration;
+use wasabi::ab_boot;
+use wasabi::acpi;
+use wasabi::allocator::ALLOCATOR;
+use wasabi::apic;
+use wasabi::arch::cli;
+use wasabi::arch::hlt;
+use wasabi::arch::sti;
+use wasabi::arch::sti_and_hlt;
+use wasabi::boot_menu;
+use wasabi::cmdline;
+use wasabi::executor;
+use wasabi::executor::Executor;
+#[cfg(feature = "gui")]
+use wasabi::font;
+use wasabi::fpu;
+use wasabi::gdt;
+#[cfg(feature = "gui")]
+use wasabi::gui_test;
+use wasabi::hpet;
+use wasabi::hpet::Hpet;
+use wasabi::i18n;
+use wasabi::info;
+use wasabi::interrupt;
+use wasabi::keyboard;
+use wasabi::keymap;
+use wasabi::log;
+use wasabi::memory_map;
+use wasabi::memtest;
+use wasabi::mouse;
+#[cfg(feature = "net")]
+use wasabi::net;
+use wasabi::paging;
+use wasabi::pci;
+use wasabi::percpu;
+use wasabi::pic;
+use wasabi::println;
+use wasabi::rand;
+use wasabi::rtc;
+#[cfg(feature = "gui")]
+use wasabi::screen;
+use wasabi::serial::SerialPort;
+use wasabi::serial_console::SerialConsole;
+use wasabi::settings;
+use wasabi::shell;
+use wasabi::smp;
+use wasabi::stack;
+use wasabi::stack::Stack;
+use wasabi::syscall;
+use wasabi::task;
+use wasabi::time;
+use wasabi::uefi::init_efi_context;
+use wasabi::uefi::EfiHandle;
+use wasabi::uefi::EfiSystemTable;
+use wasabi::uefi::MemoryMapHolder;
+#[cfg(feature = "usb")]
+use wasabi::usb;
+use wasabi::version;
+#[cfg(any(feature = "storage", feature = "net"))]
+use wasabi::virtio;
+use wasabi::warn;
+use wasabi::wx;
 
 #[no_mangle]
 // The entry point for the EFI application(仕様でEFIアプリケーションのエントリポイントはefi_mainとなっている)
-fn efi_main(_image_handle: EfiHandle, efi_system_table: &EfiSystemTable) {
-    let mut vram = init_vram(efi_system_table).expect("init_vram failed");
-    let vw = vram.width;
-    let vh = vram.height;
-    fill_rect(&mut vram, 0x000000, 0, 0, vw, vh).expect("fill_rect failed");
-    fill_rect(&mut vram, 0xff0000, 32, 32, 32, 32).expect("fill_rect failed");
-    fill_rect(&mut vram, 0x00ff00, 64, 64, 64, 64).expect("fill_rect failed");
-    fill_rect(&mut vram, 0x0000ff, 128, 128, 128, 128).expect("fill_rect failed");
-    for i in 0..256 {
-        let _ = draw_point(&mut vram, 0x010101 * i as u32, i, i);
-    }
-    let grid_size: i64 = 32;
-    let rect_size: i64 = grid_size * 8;
-    for i in (0..=rect_size).step_by(grid_size as usize) {
-        let _ = draw_line(&mut vram, 0xff0000, 0, i, rect_size, i);
-        let _ = draw_line(&mut vram, 0xff0000, i, 0, i, rect_size);
-    }
-    let cx = rect_size / 2;
-    let cy = rect_size / 2;
-    for i in (0..=rect_size).step_by(grid_size as usize) {
-        let _ = draw_line(&mut vram, 0xffff00, cx, cy, 0, i);
-        let _ = draw_line(&mut vram, 0x00ffff, cx, cy, i, 0);
-        let _ = draw_line(&mut vram, 0xff00ff, cx, cy, rect_size, i);
-        let _ = draw_line(&mut vram, 0xffffff, cx, cy, i, rect_size);
-    }
-    for (i, c) in "ABCDEF".chars().enumerate() {
-        draw_font_fg(&mut vram, i as i64 * 16 + 256, i as i64 * 16, 0xffffff, c)
-    }
-    draw_str_fg(&mut vram, 256, 256, 0xffffff, "Hello, world!");
-    let mut w = VramTextWriter::new(&mut vram);
-    for i in 0..4 {
-        writeln!(w, "i = {i}").unwrap();
-    }
+fn efi_main(image_handle: EfiHandle, efi_system_table: &'static EfiSystemTable) {
+    SerialPort::default().init();
+    init_efi_context(image_handle, efi_system_table);
+    println!("{}", version::version());
     let mut memory_map = MemoryMapHolder::new();
-    let status = efi_system_table
+    efi_system_table
         .boot_services
-        .get_memory_map(&mut memory_map);
-    writeln!(w, "{status:?}").unwrap();
-    for e in memory_map.iter() {
-        writeln!(w, "{e:?}").unwrap();
-    }
-    // println!("Hello, world!");
-
-    // 画面を保つために無限ループ
-    loop {
-        hlt()
-    }
-}
-
-// panic!()が呼ばれたときの処理
-#[panic_handler]
-fn panic(_info: &PanicInfo) -> ! {
-    loop {
-        hlt()
+        .fetch_memory_map(&mut memory_map)
+        .expect("Failed to get the memory map");
+    if let Err(e) = ALLOCATOR.init_with_boot_services(efi_system_table.boot_services, &memory_map) {
+        // ファームウェアに断らずに使うしかない
+        println!("{e}: taking the heap from the memory map");
+        ALLOCATOR.init_with_mmap(&memory_map);
+    }
+    log::init();
+    // ExitBootServicesを呼ばないので、5分でリセットされないように止める
+    if let Err(e) = efi_system_table.boot_services.set_watchdog_timer(0) {
+        warn!("{e}");
+    }
+    percpu::init_bsp(apic::local_apic_id());
+    memory_map::init(&memory_map);
+    if let Err(e) = ab_boot::boot_slot(efi_system_table) {
+        warn!("{e}");
+    }
+    if let Err(e) = settings::load() {
+        warn!("Failed to load the settings: {e}");
+    }
+    log::init_settings();
+    match cmdline::init(efi_system_table) {
+        Ok(()) => cmdline::apply(),
+        Err(e) => warn!("No command line: {e}"),
+    }
+    if cmdline::flag("memtest") {
+        if let Err(e) = memtest::run(efi_system_table) {
+            warn!("Memory test: {e}");
+        }
     }
-}
-
-trait Bitmap {
-    fn bytes_per_pixel(&self) -> i64;
-    fn pixels_per_scan_line(&self) -> i64;
-    fn width(&self) -> i64;
-    fn height(&self) -> i64;
-    fn buf_mut(&mut self) -> *mut u8;
-
-    /// # Safety
-    ///
-    /// Returned pinter is valit as long as the given coordinates are valid.
-    /// whch means that passing is_in_*_range tests.
-    unsafe fn unchecked_pixel_at_mut(&mut self, x: i64, y: i64) -> *mut u32 {
-        self.buf_mut()
-            .add(((y * self.pixels_per_scan_line() + x) * self.bytes_per_pixel()) as usize)
-            as *mut u32
+    info!("Random seed: {}", rand::init(efi_system_table));
+    if !cfg!(feature = "gui_test") {
+        if let Err(e) = boot_menu::run(efi_system_table) {
+            warn!("Boot menu: {e}");
+        }
     }
-    fn pixel_at_mut(&mut self, x: i64, y: i64) -> Option<*mut u32> {
-        if self.is_in_x_range(x) && self.is_in_y_range(y) {
-            // SAFETY: (x, y) is always validated by the cheks above.
-            unsafe { Some(&mut *self.unchecked_pixel_at_mut(x, y)) }
-        } else {
-            None
+    #[cfg(feature = "gui")]
+    if let Err(e) = screen::apply_resolution(efi_system_table) {
+        warn!("Resolution: {e}");
+    }
+    i18n::init();
+    keymap::init();
+    #[cfg(feature = "gui")]
+    font::init();
+    fpu::init();
+    gdt::init();
+    syscall::init();
+    paging::init();
+    match wx::init(efi_system_table).and_then(|()| wx::self_test(efi_system_table)) {
+        Ok(()) => info!("W^X: self-test passed"),
+        Err(e) => warn!("W^X: {e}"),
+    }
+    info!(
+        "Paging: {} tables merged into 2 MiB pages",
+        paging::promote_kernel_pages()
+    );
+    // ファームウェアのスタックは大きさが分からず、溢れても気づけないので乗り換える
+    let stack = Box::leak(Box::new(Stack::new(stack::BOOT_STACK_SIZE)));
+    stack::run_on(stack, || kernel_main(efi_system_table, &memory_map))
+}
+
+// memory_mapはデモの描画にしか使わない
+#[cfg_attr(not(feature = "gui"), allow(unused_variables))]
+fn kernel_main(efi_system_table: &'static EfiSystemTable, memory_map: &MemoryMapHolder) -> ! {
+    interrupt::init();
+    pic::init();
+    let acpi = acpi::init(efi_system_table);
+    match acpi.as_ref().map_err(|e| *e).and_then(hpet::init) {
+        Ok(hpet) => info!("HPET: {} MHz", hpet.frequency_hz() / 1_000_000),
+        Err(e) => warn!("HPET unavailable: {e}"),
+    }
+    match acpi.as_ref().map_err(|e| *e).and_then(apic::init) {
+        Ok(cpus) => info!("MADT: {cpus} CPUs"),
+        Err(e) => warn!("MADT unavailable: {e}"),
+    }
+    if !cmdline::flag("noapic") {
+        match pic::use_io_apic() {
+            Ok(n) => info!("IO APIC: {n} IRQs routed"),
+            Err(e) => info!("IRQs through the 8259 PICs ({e})"),
         }
     }
-    fn is_in_x_range(&self, px: i64) -> bool {
-        0 <= px && px < min(self.width(), self.pixels_per_scan_line())
+    match acpi.as_ref().map_err(|e| *e).and_then(pci::use_ecam) {
+        Ok(base) => info!("PCI: ECAM at {base:#x}"),
+        Err(e) => info!("PCI: using port I/O ({e})"),
     }
-    fn is_in_y_range(&self, py: i64) -> bool {
-        0 <= py && py < self.height()
+    info!("PCI: {} devices", pci::init());
+    match mouse::init() {
+        Ok(has_wheel) => info!("PS/2 mouse enabled (wheel: {has_wheel})"),
+        Err(e) => warn!("PS/2 mouse unavailable: {e}"),
     }
-}
-
-#[derive(Clone, Copy)]
-struct VramBefferInfo {
-    buf: *mut u8,
-    width: i64,
-    height: i64,
-    pixels_per_line: i64,
-}
-
-impl Bitmap for VramBefferInfo {
-    fn bytes_per_pixel(&self) -> i64 {
-        4
+    if let Err(e) = keyboard::init() {
+        warn!("PS/2 keyboard unavailable: {e}");
     }
-    fn pixels_per_scan_line(&self) -> i64 {
-        self.pixels_per_line
+    match time::init() {
+        Ok(reference) => info!("TSC: {} MHz ({reference})", time::tsc_hz() / 1_000_000),
+        Err(e) => warn!("TSC calibration failed: {e}"),
     }
-    fn width(&self) -> i64 {
-        self.width
+    match rtc::init() {
+        Ok(now) => info!("RTC: {now} UTC"),
+        Err(e) => warn!("RTC unavailable: {e}"),
     }
-    fn height(&self) -> i64 {
-        self.height
+    match smp::init() {
+        Ok(n) => info!("SMP: {n} application processors online"),
+        Err(e) => warn!("SMP unavailable: {e}"),
     }
-    fn buf_mut(&mut self) -> *mut u8 {
-        self.buf
+    #[cfg(feature = "storage")]
+    match virtio::blk::init() {
+        Ok(n) => info!("virtio-blk: {n} disks"),
+        Err(e) => warn!("virtio-blk unavailable: {e}"),
     }
-}
-
-fn init_vram(efi_system_table: &EfiSystemTable) -> Result<VramBefferInfo> {
-    let gp = locate_graphic_protocol(efi_system_table)?;
-
-    Ok(VramBefferInfo {
-        buf: gp.mode.frame_buffer_base as *mut u8,
-        width: gp.mode.info.horizontal_resolution as i64,
-        height: gp.mode.info.vertical_resolution as i64,
-        pixels_per_line: gp.mode.info.pixels_per_scan_line as i64,
-    })
-}
-
-/// # Safety
-///
-/// (x, y) must be a valid point in the buf.
-unsafe fn unchecked_draw_point<T: Bitmap>(buf: &mut T, color: u32, x: i64, y: i64) {
-    *buf.unchecked_pixel_at_mut(x, y) = color;
-}
-
-fn draw_point<T: Bitmap>(buf: &mut T, color: u32, x: i64, y: i64) -> Result<()> {
-    unsafe {
-        *(buf.pixel_at_mut(x, y).ok_or("Out of Range")?) = color;
-    }
-    Ok(())
-}
-
-fn fill_rect<T: Bitmap>(buf: &mut T, color: u32, px: i64, py: i64, w: i64, h: i64) -> Result<()> {
-    if !buf.is_in_x_range(px)
-        || !buf.is_in_y_range(py)
-        || !buf.is_in_x_range(px + w - 1)
-        || !buf.is_in_y_range(py + h - 1)
-    {
-        return Err("Out of Range");
+    #[cfg(feature = "net")]
+    match virtio::net::init() {
+        Ok(n) => info!("virtio-net: {n} interfaces"),
+        Err(e) => warn!("virtio-net unavailable: {e}"),
     }
-    for y in py..py + h {
-        for x in px..px + w {
-            unsafe {
-                unchecked_draw_point(buf, color, x, y);
-            }
+    #[cfg(feature = "net")]
+    if let Ok(n) = net::init() {
+        for iface in net::interfaces() {
+            info!("{}: {}", iface.name(), iface.mac_addr());
         }
-    }
-    Ok(())
-}
-
-fn calc_slope_point(da: i64, db: i64, ia: i64) -> Option<i64> {
-    if da < db {
-        None
-    } else if da == 0 {
-        Some(0)
-    } else if (0..=da).contains(&ia) {
-        Some((2 * db * ia + da) / da / 2)
-    } else {
-        None
-    }
-}
-
-fn draw_line<T: Bitmap>(buf: &mut T, color: u32, x0: i64, y0: i64, x1: i64, y1: i64) -> Result<()> {
-    if !buf.is_in_x_range(x0)
-        || !buf.is_in_y_range(y0)
-        || !buf.is_in_x_range(x1)
-        || !buf.is_in_y_range(y1)
-    {
-        return Err("Out of Range");
-    }
-    let dx = (x1 - x0).abs();
-    let dy = (y1 - y0).abs();
-    let sx = (x1 - x0).signum();
-    let sy = (y1 - y0).signum();
-    if dx >= dy {
-        for (rx, ry) in (0..dx).flat_map(|rx| calc_slope_point(dx, dy, rx).map(|ry| (rx, ry))) {
-            draw_point(buf, color, x0 + rx * sx, y0 + ry * sy)?;
-        }
-    } else {
-        for (rx, ry) in (0..dy).flat_map(|ry| calc_slope_point(dy, dx, ry).map(|rx| (rx, ry))) {
-            draw_point(buf, color, x0 + rx * sx, y0 + ry * sy)?;
+        info!("Network: {n} interfaces");
+    }
+    #[cfg(feature = "usb")]
+    match usb::init() {
+        Ok(n) => info!("USB: {n} devices"),
+        Err(e) => warn!("USB unavailable: {e}"),
+    }
+    #[cfg(feature = "gui")]
+    if cfg!(feature = "gui_test") {
+        gui_test::run_and_exit_qemu();
+    }
+    #[cfg(feature = "gui")]
+    let vram = match settings::get("boot_mode").as_str() {
+        "shell" => screen::draw_blank(efi_system_table),
+        _ => screen::draw_demo(efi_system_table, memory_map),
+    };
+
+    if let Err(e) = ab_boot::mark_boot_successful(efi_system_table) {
+        warn!("{e}");
+    }
+    task::init("idle");
+    if let Some(hpet) = hpet::get() {
+        // sleep()しているタスクを起こすための定期割り込み
+        let started = Hpet::irq_of(0)
+            .and_then(|irq| pic::register_irq_handler(irq, |_| time::wake_expired_timers()))
+            .and_then(|_| hpet.start_periodic(0, Duration::from_millis(10)));
+        if let Err(e) = started {
+            warn!("Failed to start the periodic timer: {e}");
         }
     }
-    Ok(())
-}
-
-fn lookup_font(c: char) -> Option<[[char; 8]; 16]> {
-    const FONT_SOURCE: &str = include_str!("font.txt");
-    if let Ok(c) = u8::try_from(c) {
-        let mut fi = FONT_SOURCE.split('\n');
-        while let Some(line) = fi.next() {
-            if let Some(line) = line.strip_prefix("0x") {
-                if let Ok(idx) = u8::from_str_radix(line, 16) {
-                    if idx != c {
-                        continue;
-                    }
-                    let mut font = [['*'; 8]; 16];
-                    for (y, line) in fi.clone().take(16).enumerate() {
-                        for (x, c) in line.chars().enumerate() {
-                            if let Some(e) = font[y].get_mut(x) {
-                                *e = c;
-                            }
-                        }
-                    }
-                    return Some(font);
-                }
-            }
-        }
-    }
-    None
-}
-
-fn draw_font_fg<T: Bitmap>(buf: &mut T, x: i64, y: i64, color: u32, c: char) {
-    if let Some(font) = lookup_font(c) {
-        for (dy, row) in font.iter().enumerate() {
-            for (dx, pixel) in row.iter().enumerate() {
-                let color = match pixel {
-                    '*' => color,
-                    _ => continue,
-                };
-                let _ = draw_point(buf, color, x + dx as i64, y + dy as i64);
-            }
+    shell::init();
+    #[cfg(feature = "gui")]
+    screen::start_console(vram);
+    task::spawn("executor", || Executor::new().run());
+    task::spawn("serial-console", || {
+        let mut console = SerialConsole::new(SerialPort::default());
+        console
+            .enable_interrupt()
+            .expect("Failed to enable the serial interrupt");
+        console.start();
+        loop {
+            console.poll();
+            task::yield_now();
         }
-    }
-}
-
-fn draw_str_fg<T: Bitmap>(buf: &mut T, x: i64, y: i64, color: u32, s: &str) {
-    for (i, c) in s.chars().enumerate() {
-        draw_font_fg(buf, x + i as i64 * 8, y, color, c);
-    }
-}
-
-struct VramTextWriter<'a> {
-    vram: &'a mut VramBefferInfo,
-    cursor_x: i64,
-    cursor_y: i64,
-}
-impl<'a> VramTextWriter<'a> {
-    fn new(vram: &'a mut VramBefferInfo) -> Self {
-        Self {
-            vram,
-            cursor_x: 0,
-            cursor_y: 0,
+    });
+    loop {
+        task::yield_now();
+        // 割り込みを禁止してから確認しないと、その間に届いた入力を取りこぼして眠ってしまう
+        cli();
+        if SerialPort::default().has_data() || executor::has_ready() {
+            sti();
+        } else {
+            let halted_at = time::now_ns();
+            sti_and_hlt();
+            time::add_idle_time(time::now_ns() - halted_at);
         }
     }
 }
 
-impl fmt::Write for VramTextWriter<'_> {
-    fn write_str(&mut self, s: &str) -> fmt::Result {
-        for c in s.chars() {
-            if c == '\n' {
-                self.cursor_x = 0;
-                self.cursor_y += 16;
-                continue;
-            }
-            draw_font_fg(self.vram, self.cursor_x, self.cursor_y, 0xffffff, c);
-            self.cursor_x += 8;
-        }
-        Ok(())
+// panic!()が呼ばれたときの処理
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    println!("PANIC: {info}");
+    loop {
+        hlt()
     }
 }
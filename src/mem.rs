@@ -0,0 +1,215 @@
+//! memcpy, memmove and memset for the kernel.
+//!
+//! The compiler turns copies and fills of slices and large values (blits,
+//! scrolling of the console, buffers of the drivers) into calls to these.
+//! compiler_builtins has weak generic versions of them; the ones here take
+//! their place on the targets of the kernel. Short runs are moved 16 bytes
+//! at a time with SSE, and long ones with `rep movsb` / `rep stosb`, which
+//! CPUs with ERMSB run at the speed of their cache lines.
+//!
+//! Everything is in asm, since the compiler would turn a plain loop here
+//! back into a call to the function itself.
+
+use core::arch::asm;
+
+/// From this size on, `rep movsb` / `rep stosb` beat the SSE loops.
+const REP_THRESHOLD: usize = 256;
+
+/// Copies n bytes from src to dst, front to back. The ranges may overlap
+/// if dst is below src.
+///
+/// # Safety
+/// Both ranges must be valid for n bytes.
+pub unsafe fn copy_forward(dst: *mut u8, src: *const u8, n: usize) {
+    if n < REP_THRESHOLD {
+        copy_forward_short(dst, src, n);
+        return;
+    }
+    asm!(
+        "rep movsb",
+        inout("rcx") n => _,
+        inout("rdi") dst => _,
+        inout("rsi") src => _,
+        options(nostack, preserves_flags)
+    );
+}
+
+#[cfg(target_feature = "sse2")]
+unsafe fn copy_forward_short(dst: *mut u8, src: *const u8, n: usize) {
+    asm!(
+        "2:",
+        "cmp rcx, 16",
+        "jb 3f",
+        "movdqu xmm0, [rsi]",
+        "movdqu [rdi], xmm0",
+        "add rsi, 16",
+        "add rdi, 16",
+        "sub rcx, 16",
+        "jmp 2b",
+        "3:",
+        "rep movsb",
+        inout("rcx") n => _,
+        inout("rdi") dst => _,
+        inout("rsi") src => _,
+        out("xmm0") _,
+        options(nostack)
+    );
+}
+
+#[cfg(not(target_feature = "sse2"))]
+unsafe fn copy_forward_short(dst: *mut u8, src: *const u8, n: usize) {
+    asm!(
+        "rep movsb",
+        inout("rcx") n => _,
+        inout("rdi") dst => _,
+        inout("rsi") src => _,
+        options(nostack, preserves_flags)
+    );
+}
+
+/// Copies n bytes from src to dst, back to front. The ranges may overlap
+/// if dst is above src.
+///
+/// # Safety
+/// Both ranges must be valid for n bytes.
+#[cfg(target_feature = "sse2")]
+pub unsafe fn copy_backward(dst: *mut u8, src: *const u8, n: usize) {
+    // 後ろ向きのrep movsbは速くないので、長さによらずSSEで16バイトずつ
+    asm!(
+        "2:",
+        "cmp rcx, 16",
+        "jb 3f",
+        "sub rcx, 16",
+        "movdqu xmm0, [rsi + rcx]",
+        "movdqu [rdi + rcx], xmm0",
+        "jmp 2b",
+        "3:",
+        "test rcx, rcx",
+        "jz 4f",
+        "dec rcx",
+        "mov al, [rsi + rcx]",
+        "mov [rdi + rcx], al",
+        "jmp 3b",
+        "4:",
+        inout("rcx") n => _,
+        in("rdi") dst,
+        in("rsi") src,
+        out("al") _,
+        out("xmm0") _,
+        options(nostack)
+    );
+}
+
+/// Copies n bytes from src to dst, back to front. The ranges may overlap
+/// if dst is above src.
+///
+/// # Safety
+/// Both ranges must be valid for n bytes.
+#[cfg(not(target_feature = "sse2"))]
+pub unsafe fn copy_backward(dst: *mut u8, src: *const u8, n: usize) {
+    if n == 0 {
+        return;
+    }
+    asm!(
+        "std",
+        "rep movsb",
+        "cld",
+        inout("rcx") n => _,
+        inout("rdi") dst.add(n - 1) => _,
+        inout("rsi") src.add(n - 1) => _,
+        options(nostack)
+    );
+}
+
+/// Copies n bytes from src to dst, which may overlap in any way.
+///
+/// # Safety
+/// Both ranges must be valid for n bytes.
+pub unsafe fn copy(dst: *mut u8, src: *const u8, n: usize) {
+    // dstがsrcより下か、重ならなければ前から写してよい
+    if (dst as usize).wrapping_sub(src as usize) >= n {
+        copy_forward(dst, src, n);
+    } else {
+        copy_backward(dst, src, n);
+    }
+}
+
+/// Sets n bytes at dst to c.
+///
+/// # Safety
+/// The range must be valid for n bytes.
+pub unsafe fn fill(dst: *mut u8, c: u8, n: usize) {
+    if n < REP_THRESHOLD {
+        fill_short(dst, c, n);
+        return;
+    }
+    asm!(
+        "rep stosb",
+        inout("rcx") n => _,
+        inout("rdi") dst => _,
+        in("al") c,
+        options(nostack, preserves_flags)
+    );
+}
+
+#[cfg(target_feature = "sse2")]
+unsafe fn fill_short(dst: *mut u8, c: u8, n: usize) {
+    asm!(
+        "movq xmm0, {pattern}",
+        "punpcklqdq xmm0, xmm0",
+        "2:",
+        "cmp rcx, 16",
+        "jb 3f",
+        "movdqu [rdi], xmm0",
+        "add rdi, 16",
+        "sub rcx, 16",
+        "jmp 2b",
+        "3:",
+        "rep stosb",
+        pattern = in(reg) c as u64 * 0x0101_0101_0101_0101,
+        inout("rcx") n => _,
+        inout("rdi") dst => _,
+        in("al") c,
+        out("xmm0") _,
+        options(nostack)
+    );
+}
+
+#[cfg(not(target_feature = "sse2"))]
+unsafe fn fill_short(dst: *mut u8, c: u8, n: usize) {
+    asm!(
+        "rep stosb",
+        inout("rcx") n => _,
+        inout("rdi") dst => _,
+        in("al") c,
+        options(nostack, preserves_flags)
+    );
+}
+
+// ホストのテストではlibcのものを使う
+#[cfg(any(target_os = "uefi", target_os = "none"))]
+mod intrinsics {
+    /// # Safety
+    /// See copy_forward().
+    #[no_mangle]
+    pub unsafe extern "C" fn memcpy(dst: *mut u8, src: *const u8, n: usize) -> *mut u8 {
+        super::copy_forward(dst, src, n);
+        dst
+    }
+
+    /// # Safety
+    /// See copy().
+    #[no_mangle]
+    pub unsafe extern "C" fn memmove(dst: *mut u8, src: *const u8, n: usize) -> *mut u8 {
+        super::copy(dst, src, n);
+        dst
+    }
+
+    /// # Safety
+    /// See fill().
+    #[no_mangle]
+    pub unsafe extern "C" fn memset(dst: *mut u8, c: i32, n: usize) -> *mut u8 {
+        super::fill(dst, c as u8, n);
+        dst
+    }
+}
@@ -0,0 +1,216 @@
+//! A human-readable summary of the UEFI memory map.
+//!
+//! Adjacent descriptors of the same type are merged into regions, and the
+//! pages are totaled per EfiMemoryType. The summary and the descriptors of
+//! the map at boot are kept for the `mem` command, whose output is paged on
+//! the screen console since the raw map is often longer than the screen.
+
+use crate::allocator::ALLOCATOR;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::uefi::EfiMemoryDescriptor;
+use crate::uefi::EfiMemoryType;
+use crate::uefi::MemoryMapHolder;
+use crate::uefi::EFI_MEMORY_DESCRIPTOR_VERSION;
+use crate::warn;
+use alloc::format;
+use alloc::vec::Vec;
+use core::fmt;
+
+const PAGE_SIZE: u64 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRegion {
+    pub memory_type: EfiMemoryType,
+    pub start: u64,
+    pub pages: u64,
+}
+impl MemoryRegion {
+    pub fn end(&self) -> u64 {
+        self.start + self.pages * PAGE_SIZE
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypeTotal {
+    pub memory_type: EfiMemoryType,
+    pub pages: u64,
+    /// The number of regions after merging.
+    pub regions: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MemoryMapSummary {
+    /// Regions sorted by address.
+    pub regions: Vec<MemoryRegion>,
+    /// Totals in the order of EfiMemoryType.
+    pub totals: Vec<TypeTotal>,
+}
+impl MemoryMapSummary {
+    pub fn new<'a>(descriptors: impl Iterator<Item = &'a EfiMemoryDescriptor>) -> Self {
+        let mut sorted: Vec<&EfiMemoryDescriptor> = descriptors.collect();
+        sorted.sort_by_key(|e| e.physical_start);
+        let mut regions: Vec<MemoryRegion> = Vec::new();
+        for e in sorted {
+            match regions.last_mut() {
+                Some(last)
+                    if last.memory_type == e.memory_type && last.end() == e.physical_start =>
+                {
+                    last.pages += e.number_of_pages;
+                }
+                _ => regions.push(MemoryRegion {
+                    memory_type: e.memory_type,
+                    start: e.physical_start,
+                    pages: e.number_of_pages,
+                }),
+            }
+        }
+        let mut totals: Vec<TypeTotal> = Vec::new();
+        for r in &regions {
+            match totals.iter_mut().find(|t| t.memory_type == r.memory_type) {
+                Some(t) => {
+                    t.pages += r.pages;
+                    t.regions += 1;
+                }
+                None => totals.push(TypeTotal {
+                    memory_type: r.memory_type,
+                    pages: r.pages,
+                    regions: 1,
+                }),
+            }
+        }
+        totals.sort_by_key(|t| t.memory_type as i64);
+        Self { regions, totals }
+    }
+    pub fn from_map(map: &MemoryMapHolder) -> Self {
+        Self::new(map.iter())
+    }
+    pub fn pages_of(&self, memory_type: EfiMemoryType) -> u64 {
+        self.totals
+            .iter()
+            .filter(|t| t.memory_type == memory_type)
+            .map(|t| t.pages)
+            .sum()
+    }
+    /// The size of all the memory backed by RAM.
+    pub fn total_bytes(&self) -> u64 {
+        self.totals
+            .iter()
+            .filter(|t| t.memory_type.is_ram())
+            .map(|t| t.pages * PAGE_SIZE)
+            .sum()
+    }
+    /// The size of the memory that is free from the firmware's point of view.
+    pub fn free_bytes(&self) -> u64 {
+        self.pages_of(EfiMemoryType::CONVENTIONAL_MEMORY) * PAGE_SIZE
+    }
+}
+impl fmt::Display for MemoryMapSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for t in &self.totals {
+            writeln!(
+                f,
+                "{:20} {:>10} in {} regions",
+                t.memory_type.name(),
+                Size(t.pages * PAGE_SIZE),
+                t.regions
+            )?;
+        }
+        writeln!(
+            f,
+            "Conventional: {} free / {} total",
+            Size(self.free_bytes()),
+            Size(self.total_bytes())
+        )
+    }
+}
+
+/// A byte count shown in MiB, or in KiB if it is smaller than 1 MiB.
+pub struct Size(pub u64);
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = if self.0 >= 1024 * 1024 {
+            format!("{} MiB", self.0 / (1024 * 1024))
+        } else {
+            format!("{} KiB", self.0 / 1024)
+        };
+        f.pad(&s)
+    }
+}
+
+static BOOT_SUMMARY: Mutex<Option<MemoryMapSummary>> = Mutex::new(None);
+static BOOT_DESCRIPTORS: Mutex<Vec<EfiMemoryDescriptor>> = Mutex::new(Vec::new());
+
+/// Keeps the summary and the descriptors of the memory map at boot for
+/// summary() and descriptors().
+pub fn init(map: &MemoryMapHolder) {
+    if map.descriptor_version() != EFI_MEMORY_DESCRIPTOR_VERSION {
+        warn!(
+            "Memory map: descriptor version {} (expected {})",
+            map.descriptor_version(),
+            EFI_MEMORY_DESCRIPTOR_VERSION
+        );
+    }
+    *BOOT_SUMMARY.lock() = Some(MemoryMapSummary::from_map(map));
+    *BOOT_DESCRIPTORS.lock() = map.iter().copied().collect();
+}
+
+/// The descriptors of the memory map at boot, in the order of the firmware.
+pub fn descriptors() -> Vec<EfiMemoryDescriptor> {
+    BOOT_DESCRIPTORS.lock().clone()
+}
+
+/// The summary of the memory map at boot.
+pub fn summary() -> Option<MemoryMapSummary> {
+    BOOT_SUMMARY.lock().clone()
+}
+
+/// The `mem` command.
+pub fn cmd_mem(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let summary = summary().ok_or("The memory map is not available")?;
+    match args {
+        [] => {
+            let _ = write!(out, "{summary}");
+            let total = ALLOCATOR.total_bytes() as u64;
+            let free = ALLOCATOR.free_bytes() as u64;
+            let _ = writeln!(
+                out,
+                "Heap: {} used / {} total ({} free)",
+                Size(total.saturating_sub(free)),
+                Size(total),
+                Size(free)
+            );
+            Ok(())
+        }
+        ["regions"] => {
+            for r in &summary.regions {
+                let _ = writeln!(
+                    out,
+                    "{:#012x}-{:#012x} {:20} {:>10}",
+                    r.start,
+                    r.end(),
+                    r.memory_type.name(),
+                    Size(r.pages * PAGE_SIZE)
+                );
+            }
+            Ok(())
+        }
+        ["descriptors"] => {
+            let descriptors = descriptors();
+            let _ = writeln!(out, "{} descriptors", descriptors.len());
+            for e in &descriptors {
+                let _ = writeln!(
+                    out,
+                    "{:#012x}-{:#012x} {:20} {:>10} attr {:#x}",
+                    e.physical_start,
+                    e.physical_start + e.number_of_pages * PAGE_SIZE,
+                    e.memory_type.name(),
+                    Size(e.number_of_pages * PAGE_SIZE),
+                    e.attribute
+                );
+            }
+            Ok(())
+        }
+        _ => Err("usage: mem [regions | descriptors]"),
+    }
+}
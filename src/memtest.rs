@@ -0,0 +1,161 @@
+//! A memory test for dodgy hardware, run at boot with `memtest` on the
+//! command line.
+//!
+//! Each conventional memory region that the firmware still has free is
+//! taken with AllocatePages, written with walking ones (and zeros) and
+//! with the address of each word (and its complement), and read back. The
+//! pages that did not read back are allocated again as Unusable memory and
+//! kept, so that neither the firmware nor the kernel hands them out.
+
+use crate::info;
+use crate::memory_map::Size;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::uefi::AllocateType;
+use crate::uefi::EfiMemoryType;
+use crate::uefi::EfiSystemTable;
+use crate::uefi::MemoryMapHolder;
+use crate::warn;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ptr::read_volatile;
+use core::ptr::write_volatile;
+
+const PAGE_SIZE: u64 = 4096;
+const WORDS_PER_PAGE: usize = PAGE_SIZE as usize / 8;
+
+/// The patterns as functions of the address and the index of a word.
+const PATTERNS: [fn(u64, usize) -> u64; 4] = [
+    |_, i| 1 << (i % 64),
+    |_, i| !(1 << (i % 64)),
+    |addr, _| addr,
+    |addr, _| !addr,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BadRegion {
+    pub start: u64,
+    pub pages: u64,
+}
+impl BadRegion {
+    pub fn end(&self) -> u64 {
+        self.start + self.pages * PAGE_SIZE
+    }
+}
+
+static BAD_REGIONS: Mutex<Vec<BadRegion>> = Mutex::new(Vec::new());
+
+/// Tests the words at base with all the patterns and returns the addresses
+/// of the pages with a word that did not read back, in order.
+pub fn test_words(words: &mut [u64], base: u64) -> Vec<u64> {
+    let mut bad = Vec::new();
+    for pattern in PATTERNS {
+        // 書いてから読むまでにキャッシュを追い出せるよう、全体を書いてから確かめる
+        for (i, w) in words.iter_mut().enumerate() {
+            // SAFETY: w is a valid reference
+            unsafe { write_volatile(w, pattern(base + i as u64 * 8, i)) };
+        }
+        for (i, w) in words.iter().enumerate() {
+            // SAFETY: w is a valid reference
+            if unsafe { read_volatile(w) } != pattern(base + i as u64 * 8, i) {
+                bad.push(base + (i / WORDS_PER_PAGE) as u64 * PAGE_SIZE);
+            }
+        }
+    }
+    bad.sort_unstable();
+    bad.dedup();
+    bad
+}
+
+/// Merges the addresses of bad pages, in order, into regions.
+pub fn merge_pages(pages: &[u64]) -> Vec<BadRegion> {
+    let mut regions: Vec<BadRegion> = Vec::new();
+    for &page in pages {
+        match regions.last_mut() {
+            Some(last) if last.end() == page => last.pages += 1,
+            _ => regions.push(BadRegion {
+                start: page,
+                pages: 1,
+            }),
+        }
+    }
+    regions
+}
+
+/// Tests the free conventional memory and keeps the bad regions away from
+/// everyone. Call while the boot services are available.
+pub fn run(efi_system_table: &EfiSystemTable) -> Result<Vec<BadRegion>> {
+    let boot_services = &efi_system_table.boot_services;
+    let mut map = Box::new(MemoryMapHolder::new());
+    boot_services.fetch_memory_map(&mut map)?;
+    // init_with_mmap()のヒープはファームウェアから見ると空いているので避ける
+    let probe = Box::new(0u8);
+    let heap = &*probe as *const u8 as u64;
+    let free: Vec<(u64, u64)> = map
+        .iter()
+        .filter(|e| e.memory_type == EfiMemoryType::CONVENTIONAL_MEMORY)
+        .map(|e| (e.physical_start, e.number_of_pages))
+        .filter(|(start, pages)| !(*start..start + pages * PAGE_SIZE).contains(&heap))
+        .collect();
+    let mut bad_pages = Vec::new();
+    let mut tested = 0;
+    for (start, pages) in free {
+        // 0番地は参照できないので飛ばす
+        let (start, pages) = if start == 0 {
+            (PAGE_SIZE, pages.saturating_sub(1))
+        } else {
+            (start, pages)
+        };
+        if pages == 0
+            || boot_services
+                .allocate_pages(
+                    AllocateType::Address(start),
+                    EfiMemoryType::LOADER_DATA,
+                    pages as usize,
+                )
+                .is_err()
+        {
+            continue;
+        }
+        // SAFETY: the pages are allocated for the test and identity mapped
+        let words = unsafe {
+            core::slice::from_raw_parts_mut(start as *mut u64, pages as usize * WORDS_PER_PAGE)
+        };
+        bad_pages.extend(test_words(words, start));
+        // SAFETY: the test is done with the pages
+        unsafe { boot_services.free_pages(start, pages as usize)? };
+        tested += pages;
+    }
+    let bad = merge_pages(&bad_pages);
+    for r in &bad {
+        warn!("Memory test: bad memory at {:#x}-{:#x}", r.start, r.end());
+        // Unusableで確保できないファームウェアでも、確保しておけば配られない
+        let kept = [EfiMemoryType::UNUSABLE_MEMORY, EfiMemoryType::LOADER_DATA]
+            .into_iter()
+            .any(|memory_type| {
+                boot_services
+                    .allocate_pages(
+                        AllocateType::Address(r.start),
+                        memory_type,
+                        r.pages as usize,
+                    )
+                    .is_ok()
+            });
+        if !kept {
+            warn!("Memory test: failed to keep {:#x} away", r.start);
+        }
+    }
+    info!(
+        "Memory test: {} tested, {} bad pages in {} regions",
+        Size(tested * PAGE_SIZE),
+        bad_pages.len(),
+        bad.len()
+    );
+    BAD_REGIONS.lock().clone_from(&bad);
+    Ok(bad)
+}
+
+/// The bad regions found by run().
+pub fn bad_regions() -> Vec<BadRegion> {
+    BAD_REGIONS.lock().clone()
+}
@@ -0,0 +1,137 @@
+use crate::input;
+use crate::input::InputEvent;
+use crate::kexec;
+use crate::mutex::Mutex;
+use crate::pic;
+use crate::ps2;
+use crate::result::Result;
+
+pub const MOUSE_IRQ: u8 = 12;
+
+const CMD_SET_DEFAULTS: u8 = 0xf6;
+const CMD_ENABLE_REPORTING: u8 = 0xf4;
+const CMD_DISABLE_REPORTING: u8 = 0xf5;
+const CMD_SET_SAMPLE_RATE: u8 = 0xf3;
+const CMD_GET_ID: u8 = 0xf2;
+/// Device id reported after the IntelliMouse sample rate sequence.
+const ID_WHEEL_MOUSE: u8 = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MouseButtons(u8);
+impl MouseButtons {
+    /// Bit 0 is the left button, bit 1 the right and bit 2 the middle.
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits & 0x07)
+    }
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+    pub fn left(&self) -> bool {
+        self.0 & 1 != 0
+    }
+    pub fn right(&self) -> bool {
+        self.0 & 2 != 0
+    }
+    pub fn middle(&self) -> bool {
+        self.0 & 4 != 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// Movement to the right.
+    pub dx: i32,
+    /// Movement downwards (the screen direction, unlike PS/2 itself).
+    pub dy: i32,
+    /// Positive when scrolled down.
+    pub wheel: i32,
+    pub buttons: MouseButtons,
+}
+
+/// Assembles PS/2 mouse packets from the bytes received one by one.
+pub struct PacketDecoder {
+    buf: [u8; 4],
+    len: usize,
+    packet_size: usize,
+}
+impl PacketDecoder {
+    pub const fn new(has_wheel: bool) -> Self {
+        Self {
+            buf: [0; 4],
+            len: 0,
+            packet_size: if has_wheel { 4 } else { 3 },
+        }
+    }
+    pub fn feed(&mut self, byte: u8) -> Option<MouseEvent> {
+        // 1バイト目はbit3が常に1なので、取りこぼしてずれたらここで同期し直す
+        if self.len == 0 && byte & 0x08 == 0 {
+            return None;
+        }
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if self.len < self.packet_size {
+            return None;
+        }
+        self.len = 0;
+        let [flags, x, y, z] = self.buf;
+        if flags & 0xc0 != 0 {
+            // オーバーフローした移動量は信用できない
+            return None;
+        }
+        let dx = x as i32 - (((flags as i32) << 4) & 0x100);
+        let dy = y as i32 - (((flags as i32) << 3) & 0x100);
+        let wheel = if self.packet_size == 4 {
+            ((z << 4) as i8 >> 4) as i32
+        } else {
+            0
+        };
+        Some(MouseEvent {
+            dx,
+            dy: -dy,
+            wheel,
+            buttons: MouseButtons(flags & 0x07),
+        })
+    }
+}
+
+static DECODER: Mutex<PacketDecoder> = Mutex::new(PacketDecoder::new(false));
+
+fn irq_handler(_irq: u8) {
+    let byte = ps2::read_data_nowait();
+    let Some(mut decoder) = DECODER.try_lock() else {
+        return;
+    };
+    if let Some(event) = decoder.feed(byte) {
+        input::push(InputEvent::Mouse(event));
+    }
+}
+
+fn set_sample_rate(rate: u8) -> Result<()> {
+    ps2::send_aux(CMD_SET_SAMPLE_RATE)?;
+    ps2::send_aux(rate)
+}
+
+/// Enables the PS/2 mouse and starts delivering events to the input queue. Returns true if
+/// it has a scroll wheel.
+pub fn init() -> Result<bool> {
+    ps2::write_command(ps2::CMD_ENABLE_AUX)?;
+    ps2::flush();
+    let config = ps2::read_config()?;
+    ps2::write_config((config | ps2::CONFIG_AUX_IRQ) & !ps2::CONFIG_AUX_CLOCK_DISABLED)?;
+    ps2::send_aux(CMD_SET_DEFAULTS)?;
+    // 200, 100, 80の順にサンプルレートを設定するとホイール付きのモードになる
+    set_sample_rate(200)?;
+    set_sample_rate(100)?;
+    set_sample_rate(80)?;
+    ps2::send_aux(CMD_GET_ID)?;
+    let has_wheel = ps2::read_data()? == ID_WHEEL_MOUSE;
+    ps2::send_aux(CMD_ENABLE_REPORTING)?;
+    *DECODER.lock() = PacketDecoder::new(has_wheel);
+    pic::register_irq_handler(MOUSE_IRQ, irq_handler)?;
+    kexec::register_shutdown_hook("mouse", || {
+        pic::unregister_irq_handler(MOUSE_IRQ);
+        let _ = ps2::send_aux(CMD_DISABLE_REPORTING);
+        ps2::flush();
+    });
+    Ok(has_wheel)
+}
@@ -0,0 +1,195 @@
+//! ARP (RFC 826) for IPv4 over Ethernet, and the cache of the answers.
+
+use crate::mutex::Mutex;
+use crate::net::ethernet;
+use crate::net::ethernet::MacAddr;
+use crate::net::Interface;
+use crate::net::Ipv4Addr;
+use crate::result::Result;
+use crate::time;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+
+const HTYPE_ETHERNET: u16 = 1;
+pub const OP_REQUEST: u16 = 1;
+pub const OP_REPLY: u16 = 2;
+pub const PACKET_SIZE: usize = 28;
+
+/// Entries are asked again after this.
+const ENTRY_LIFETIME_NS: u64 = 300_000_000_000;
+const MAX_ENTRIES: usize = 64;
+const REQUEST_TIMEOUT_NS: u64 = 1_000_000_000;
+const REQUEST_RETRIES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub op: u16,
+    pub sender_mac: MacAddr,
+    pub sender_ip: Ipv4Addr,
+    pub target_mac: MacAddr,
+    pub target_ip: Ipv4Addr,
+}
+impl Packet {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let data = data.get(..PACKET_SIZE)?;
+        let htype = u16::from_be_bytes([data[0], data[1]]);
+        let ptype = u16::from_be_bytes([data[2], data[3]]);
+        if htype != HTYPE_ETHERNET
+            || ptype != ethernet::ETHERTYPE_IPV4
+            || data[4] != 6
+            || data[5] != 4
+        {
+            return None;
+        }
+        Some(Self {
+            op: u16::from_be_bytes([data[6], data[7]]),
+            sender_mac: MacAddr::new(data[8..14].try_into().ok()?),
+            sender_ip: Ipv4Addr::from_bytes(data[14..18].try_into().ok()?),
+            target_mac: MacAddr::new(data[18..24].try_into().ok()?),
+            target_ip: Ipv4Addr::from_bytes(data[24..28].try_into().ok()?),
+        })
+    }
+    pub fn to_bytes(&self) -> [u8; PACKET_SIZE] {
+        let mut data = [0u8; PACKET_SIZE];
+        data[0..2].copy_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+        data[2..4].copy_from_slice(&ethernet::ETHERTYPE_IPV4.to_be_bytes());
+        data[4] = 6;
+        data[5] = 4;
+        data[6..8].copy_from_slice(&self.op.to_be_bytes());
+        data[8..14].copy_from_slice(&self.sender_mac.octets());
+        data[14..18].copy_from_slice(&self.sender_ip.octets());
+        data[18..24].copy_from_slice(&self.target_mac.octets());
+        data[24..28].copy_from_slice(&self.target_ip.octets());
+        data
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    ip: Ipv4Addr,
+    mac: MacAddr,
+    updated_ns: u64,
+}
+
+static CACHE: Mutex<Vec<Entry>> = Mutex::new(Vec::new());
+
+pub fn lookup(ip: Ipv4Addr) -> Option<MacAddr> {
+    let now = time::now_ns();
+    CACHE
+        .lock()
+        .iter()
+        .find(|e| e.ip == ip && now - e.updated_ns < ENTRY_LIFETIME_NS)
+        .map(|e| e.mac)
+}
+
+fn insert(ip: Ipv4Addr, mac: MacAddr) {
+    let now = time::now_ns();
+    let mut cache = CACHE.lock();
+    if let Some(e) = cache.iter_mut().find(|e| e.ip == ip) {
+        e.mac = mac;
+        e.updated_ns = now;
+        return;
+    }
+    if cache.len() >= MAX_ENTRIES {
+        // 一番古いものを捨てる
+        if let Some(oldest) = (0..cache.len()).min_by_key(|&i| cache[i].updated_ns) {
+            cache.swap_remove(oldest);
+        }
+    }
+    cache.push(Entry {
+        ip,
+        mac,
+        updated_ns: now,
+    });
+}
+
+/// Handles a received ARP packet: learns the sender and answers the
+/// requests for the address of iface.
+pub fn handle(iface: &Interface, data: &[u8]) {
+    let Some(packet) = Packet::parse(data) else {
+        return;
+    };
+    let addr = iface.config().addr;
+    let for_us = iface.config().is_configured() && packet.target_ip == addr;
+    // RFC 826 の通り、既知の相手は更新し、自分宛てなら新たに覚える
+    if for_us || lookup(packet.sender_ip).is_some() {
+        insert(packet.sender_ip, packet.sender_mac);
+    }
+    if for_us && packet.op == OP_REQUEST {
+        let reply = Packet {
+            op: OP_REPLY,
+            sender_mac: iface.mac_addr(),
+            sender_ip: addr,
+            target_mac: packet.sender_mac,
+            target_ip: packet.sender_ip,
+        };
+        let _ = iface.send_frame(
+            packet.sender_mac,
+            ethernet::ETHERTYPE_ARP,
+            &reply.to_bytes(),
+        );
+    }
+}
+
+fn request(iface: &Interface, ip: Ipv4Addr) -> Result<()> {
+    let request = Packet {
+        op: OP_REQUEST,
+        sender_mac: iface.mac_addr(),
+        sender_ip: iface.config().addr,
+        target_mac: MacAddr::ZERO,
+        target_ip: ip,
+    };
+    iface.send_frame(
+        MacAddr::BROADCAST,
+        ethernet::ETHERTYPE_ARP,
+        &request.to_bytes(),
+    )
+}
+
+/// Returns the MAC address of ip on the link of iface. If it is not cached,
+/// asks the link and waits for the reply, handling the received frames.
+pub fn resolve(iface: &Interface, ip: Ipv4Addr) -> Result<MacAddr> {
+    if ip == Ipv4Addr::BROADCAST {
+        return Ok(MacAddr::BROADCAST);
+    }
+    if ip.is_multicast() {
+        return Ok(MacAddr::for_multicast(ip));
+    }
+    if let Some(mac) = lookup(ip) {
+        return Ok(mac);
+    }
+    for _ in 0..REQUEST_RETRIES {
+        request(iface, ip)?;
+        let deadline = time::now_ns() + REQUEST_TIMEOUT_NS;
+        while time::now_ns() < deadline {
+            super::poll();
+            if let Some(mac) = lookup(ip) {
+                return Ok(mac);
+            }
+            core::hint::spin_loop();
+        }
+    }
+    Err("ARP: no reply")
+}
+
+/// The `arp` command: shows or flushes the cache.
+pub fn cmd_arp(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    match args {
+        [] => {
+            let now = time::now_ns();
+            let _ = writeln!(out, "{:<16} {:<18} {:>6}", "ADDRESS", "MAC", "AGE(s)");
+            for e in CACHE.lock().iter() {
+                let age = (now - e.updated_ns) / 1_000_000_000;
+                let (ip, mac) = (e.ip.to_string(), e.mac.to_string());
+                let _ = writeln!(out, "{ip:<16} {mac:<18} {age:>6}");
+            }
+            Ok(())
+        }
+        ["flush"] => {
+            CACHE.lock().clear();
+            Ok(())
+        }
+        _ => Err("usage: arp [flush]"),
+    }
+}
@@ -0,0 +1,589 @@
+//! The DHCP client (RFC 2131).
+//!
+//! Each interface runs the client as an async task, which configures the
+//! interface with the lease and renews it. Replies are asked to be
+//! broadcast, since the interface has no address while it asks.
+
+use crate::executor;
+use crate::info;
+use crate::mutex::Mutex;
+use crate::net;
+use crate::net::ethernet::MacAddr;
+use crate::net::udp::UdpSocket;
+use crate::net::Interface;
+use crate::net::IpConfig;
+use crate::net::Ipv4Addr;
+use crate::rand;
+use crate::result::Result;
+use crate::settings;
+use crate::time;
+use crate::warn;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// The fixed part before the magic cookie.
+const FIXED_SIZE: usize = 236;
+/// The smallest message BOOTP relays accept.
+const MIN_MESSAGE_SIZE: usize = 300;
+
+const OPT_PAD: u8 = 0;
+const OPT_SUBNET_MASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_HOSTNAME: u8 = 12;
+const OPT_REQUESTED_IP: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETER_LIST: u8 = 55;
+const OPT_RENEWAL_TIME: u8 = 58;
+const OPT_REBINDING_TIME: u8 = 59;
+const OPT_END: u8 = 255;
+
+pub const DHCPDISCOVER: u8 = 1;
+pub const DHCPOFFER: u8 = 2;
+pub const DHCPREQUEST: u8 = 3;
+pub const DHCPACK: u8 = 5;
+pub const DHCPNAK: u8 = 6;
+
+/// The first retransmission timeout, doubled up to MAX_RETRANSMIT_SECS.
+const FIRST_RETRANSMIT_SECS: u64 = 4;
+const MAX_RETRANSMIT_SECS: u64 = 64;
+/// The shortest wait between the requests while renewing.
+const MIN_RENEW_WAIT_SECS: u64 = 60;
+const NS_PER_SEC: u64 = 1_000_000_000;
+
+/// A DHCP message, with the options the client uses.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Message {
+    pub op: u8,
+    pub xid: u32,
+    pub flags: u16,
+    pub ciaddr: Ipv4Addr,
+    pub yiaddr: Ipv4Addr,
+    pub chaddr: MacAddr,
+    pub message_type: Option<u8>,
+    pub subnet_mask: Option<Ipv4Addr>,
+    pub router: Option<Ipv4Addr>,
+    pub dns: Option<Ipv4Addr>,
+    pub requested_ip: Option<Ipv4Addr>,
+    pub server_id: Option<Ipv4Addr>,
+    pub lease_secs: Option<u32>,
+    pub renewal_secs: Option<u32>,
+    pub rebinding_secs: Option<u32>,
+    pub hostname: Option<String>,
+}
+impl Message {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < FIXED_SIZE + MAGIC_COOKIE.len()
+            || data[1] != HTYPE_ETHERNET
+            || data[2] != 6
+            || data[FIXED_SIZE..FIXED_SIZE + 4] != MAGIC_COOKIE
+        {
+            return None;
+        }
+        let addr =
+            |i: usize| Ipv4Addr::from_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        let mut message = Self {
+            op: data[0],
+            xid: u32::from_be_bytes(data[4..8].try_into().ok()?),
+            flags: u16::from_be_bytes([data[10], data[11]]),
+            ciaddr: addr(12),
+            yiaddr: addr(16),
+            chaddr: MacAddr::new(data[28..34].try_into().ok()?),
+            ..Default::default()
+        };
+        let mut options = &data[FIXED_SIZE + 4..];
+        while let [code, rest @ ..] = options {
+            match *code {
+                OPT_PAD => {
+                    options = rest;
+                    continue;
+                }
+                OPT_END => break,
+                _ => {}
+            }
+            let (&len, rest) = rest.split_first()?;
+            let value = rest.get(..len as usize)?;
+            options = &rest[len as usize..];
+            let addr = value
+                .get(..4)
+                .map(|v| Ipv4Addr::from_bytes([v[0], v[1], v[2], v[3]]));
+            let secs = value
+                .get(..4)
+                .map(|v| u32::from_be_bytes([v[0], v[1], v[2], v[3]]));
+            match *code {
+                OPT_MESSAGE_TYPE => message.message_type = value.first().copied(),
+                OPT_SUBNET_MASK => message.subnet_mask = addr,
+                // 複数あるときは最初のものを使う
+                OPT_ROUTER => message.router = addr,
+                OPT_DNS => message.dns = addr,
+                OPT_REQUESTED_IP => message.requested_ip = addr,
+                OPT_SERVER_ID => message.server_id = addr,
+                OPT_LEASE_TIME => message.lease_secs = secs,
+                OPT_RENEWAL_TIME => message.renewal_secs = secs,
+                OPT_REBINDING_TIME => message.rebinding_secs = secs,
+                OPT_HOSTNAME => {
+                    message.hostname = core::str::from_utf8(value).ok().map(String::from)
+                }
+                _ => {}
+            }
+        }
+        Some(message)
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = alloc::vec![0u8; FIXED_SIZE];
+        data[0] = self.op;
+        data[1] = HTYPE_ETHERNET;
+        data[2] = 6;
+        data[4..8].copy_from_slice(&self.xid.to_be_bytes());
+        data[10..12].copy_from_slice(&self.flags.to_be_bytes());
+        data[12..16].copy_from_slice(&self.ciaddr.octets());
+        data[16..20].copy_from_slice(&self.yiaddr.octets());
+        data[28..34].copy_from_slice(&self.chaddr.octets());
+        data.extend_from_slice(&MAGIC_COOKIE);
+        let mut option = |code: u8, value: &[u8]| {
+            data.push(code);
+            data.push(value.len() as u8);
+            data.extend_from_slice(value);
+        };
+        if let Some(t) = self.message_type {
+            option(OPT_MESSAGE_TYPE, &[t]);
+        }
+        let addrs = [
+            (OPT_SUBNET_MASK, self.subnet_mask),
+            (OPT_ROUTER, self.router),
+            (OPT_DNS, self.dns),
+            (OPT_REQUESTED_IP, self.requested_ip),
+            (OPT_SERVER_ID, self.server_id),
+        ];
+        for (code, addr) in addrs {
+            if let Some(addr) = addr {
+                option(code, &addr.octets());
+            }
+        }
+        let times = [
+            (OPT_LEASE_TIME, self.lease_secs),
+            (OPT_RENEWAL_TIME, self.renewal_secs),
+            (OPT_REBINDING_TIME, self.rebinding_secs),
+        ];
+        for (code, secs) in times {
+            if let Some(secs) = secs {
+                option(code, &secs.to_be_bytes());
+            }
+        }
+        if let Some(hostname) = &self.hostname {
+            option(
+                OPT_HOSTNAME,
+                &hostname.as_bytes()[..hostname.len().min(255)],
+            );
+        }
+        if self.op == OP_REQUEST {
+            option(
+                OPT_PARAMETER_LIST,
+                &[
+                    OPT_SUBNET_MASK,
+                    OPT_ROUTER,
+                    OPT_DNS,
+                    OPT_LEASE_TIME,
+                    OPT_RENEWAL_TIME,
+                    OPT_REBINDING_TIME,
+                ],
+            );
+        }
+        data.push(OPT_END);
+        data.resize(data.len().max(MIN_MESSAGE_SIZE), OPT_PAD);
+        data
+    }
+}
+
+/// The prefix length of a netmask, which is assumed to be contiguous.
+fn prefix_len(mask: Ipv4Addr) -> u8 {
+    mask.to_u32().count_ones() as u8
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lease {
+    pub config: IpConfig,
+    pub server: Ipv4Addr,
+    pub lease_secs: u32,
+    pub renewal_secs: u32,
+    pub rebinding_secs: u32,
+    /// When the request that got the lease was sent.
+    pub acquired_ns: u64,
+}
+impl Lease {
+    fn from_ack(ack: &Message, acquired_ns: u64) -> Option<Self> {
+        let lease_secs = ack.lease_secs.unwrap_or(u32::MAX);
+        Some(Self {
+            config: IpConfig {
+                addr: ack.yiaddr,
+                // マスクがなければアドレスのクラスから決めるのが本来だが、/24 で済ませる
+                prefix_len: ack.subnet_mask.map_or(24, prefix_len),
+                gateway: ack.router,
+                dns: ack.dns,
+            },
+            server: ack.server_id?,
+            lease_secs,
+            // RFC 2131 4.4.5 の既定値
+            renewal_secs: ack.renewal_secs.unwrap_or(lease_secs / 2),
+            rebinding_secs: ack
+                .rebinding_secs
+                .unwrap_or((lease_secs as u64 * 7 / 8) as u32),
+            acquired_ns,
+        })
+    }
+    fn deadline_ns(&self, secs: u32) -> u64 {
+        self.acquired_ns.saturating_add(secs as u64 * NS_PER_SEC)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Init,
+    Selecting,
+    Requesting,
+    Bound,
+    Renewing,
+    Rebinding,
+    Stopped,
+}
+impl State {
+    pub fn name(self) -> &'static str {
+        match self {
+            State::Init => "init",
+            State::Selecting => "selecting",
+            State::Requesting => "requesting",
+            State::Bound => "bound",
+            State::Renewing => "renewing",
+            State::Rebinding => "rebinding",
+            State::Stopped => "stopped",
+        }
+    }
+}
+
+struct Client {
+    iface: Arc<Interface>,
+    /// The task running the client. 0 if stopped.
+    generation: u64,
+    state: State,
+    lease: Option<Lease>,
+}
+
+static CLIENTS: Mutex<Vec<Client>> = Mutex::new(Vec::new());
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+/// Starts the client on iface, replacing the running one.
+pub fn start(iface: Arc<Interface>) {
+    let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+    {
+        let mut clients = CLIENTS.lock();
+        clients.retain(|c| !Arc::ptr_eq(&c.iface, &iface));
+        clients.push(Client {
+            iface: iface.clone(),
+            generation,
+            state: State::Init,
+            lease: None,
+        });
+    }
+    executor::spawn(async move {
+        let mut task = Task {
+            iface,
+            generation,
+            xid: 0,
+        };
+        if let Err(e) = task.run().await {
+            warn!("{}: DHCP: {e}", task.iface.name());
+            task.set_state(State::Stopped);
+        }
+    });
+}
+
+/// Stops the client on iface. The address is kept.
+pub fn stop(iface: &Interface) {
+    for c in CLIENTS.lock().iter_mut() {
+        if core::ptr::eq(Arc::as_ptr(&c.iface), iface) {
+            c.generation = 0;
+            c.state = State::Stopped;
+        }
+    }
+}
+
+/// The lease of iface, if bound.
+pub fn lease(iface: &Interface) -> Option<Lease> {
+    CLIENTS
+        .lock()
+        .iter()
+        .find(|c| core::ptr::eq(Arc::as_ptr(&c.iface), iface))
+        .and_then(|c| c.lease)
+}
+
+struct Task {
+    iface: Arc<Interface>,
+    generation: u64,
+    xid: u32,
+}
+impl Task {
+    fn is_current(&self) -> bool {
+        CLIENTS
+            .lock()
+            .iter()
+            .any(|c| Arc::ptr_eq(&c.iface, &self.iface) && c.generation == self.generation)
+    }
+    fn set_state(&self, state: State) {
+        if let Some(c) = CLIENTS
+            .lock()
+            .iter_mut()
+            .find(|c| c.generation == self.generation)
+        {
+            c.state = state;
+        }
+    }
+    fn set_lease(&self, lease: Option<Lease>) {
+        if let Some(c) = CLIENTS
+            .lock()
+            .iter_mut()
+            .find(|c| c.generation == self.generation)
+        {
+            c.lease = lease;
+        }
+        self.iface
+            .set_config(lease.map_or(IpConfig::UNCONFIGURED, |l| l.config));
+    }
+    fn new_xid(&mut self) {
+        self.xid = rand::random_u32();
+    }
+    fn message(&self, message_type: u8) -> Message {
+        let hostname = settings::get("hostname");
+        Message {
+            op: OP_REQUEST,
+            xid: self.xid,
+            flags: FLAG_BROADCAST,
+            chaddr: self.iface.mac_addr(),
+            message_type: Some(message_type),
+            hostname: Some(hostname),
+            ..Default::default()
+        }
+    }
+    /// Sleeps until deadline. Returns false if the client is stopped or
+    /// replaced meanwhile.
+    async fn sleep_until(&self, deadline_ns: u64) -> bool {
+        while time::now_ns() < deadline_ns {
+            if !self.is_current() {
+                return false;
+            }
+            let left = deadline_ns - time::now_ns();
+            time::sleep(Duration::from_nanos(left.min(NS_PER_SEC))).await;
+        }
+        self.is_current()
+    }
+    /// Sends message to dst and waits up to timeout for a reply of the
+    /// types.
+    async fn exchange(
+        &self,
+        socket: &UdpSocket,
+        message: &Message,
+        dst: Ipv4Addr,
+        types: &[u8],
+        timeout_ns: u64,
+    ) -> Result<Option<Message>> {
+        socket.send_to(dst, SERVER_PORT, &message.to_bytes())?;
+        let deadline = time::now_ns() + timeout_ns;
+        while time::now_ns() < deadline && self.is_current() {
+            let Some(datagram) = socket.recv(Duration::from_millis(100)).await else {
+                continue;
+            };
+            let Some(reply) = Message::parse(&datagram.data) else {
+                continue;
+            };
+            if reply.op == OP_REPLY
+                && reply.xid == self.xid
+                && reply.chaddr == self.iface.mac_addr()
+                && reply.message_type.is_some_and(|t| types.contains(&t))
+            {
+                return Ok(Some(reply));
+            }
+        }
+        Ok(None)
+    }
+    async fn bind_socket(&self) -> Result<UdpSocket> {
+        // 置き換えられた前のタスクがポートを手放すまで少し待つ
+        for _ in 0..20 {
+            if let Ok(socket) = UdpSocket::bind_to_interface(self.iface.clone(), CLIENT_PORT) {
+                return Ok(socket);
+            }
+            time::sleep(Duration::from_millis(100)).await;
+        }
+        Err("port 68 is in use")
+    }
+    async fn run(&mut self) -> Result<()> {
+        let socket = self.bind_socket().await?;
+        while self.is_current() {
+            let Some(lease) = self.acquire(&socket).await? else {
+                return Ok(());
+            };
+            info!(
+                "{}: DHCP lease {} from {} for {}s",
+                self.iface.name(),
+                lease.config,
+                lease.server,
+                lease.lease_secs
+            );
+            self.set_lease(Some(lease));
+            self.set_state(State::Bound);
+            if !self.keep(&socket, lease).await? {
+                return Ok(());
+            }
+            warn!("{}: DHCP lease lost", self.iface.name());
+            self.set_lease(None);
+        }
+        Ok(())
+    }
+    /// INIT, SELECTING and REQUESTING: retries until a lease is acquired.
+    /// Returns None if the client is stopped.
+    async fn acquire(&mut self, socket: &UdpSocket) -> Result<Option<Lease>> {
+        let mut timeout = FIRST_RETRANSMIT_SECS;
+        loop {
+            if !self.is_current() {
+                return Ok(None);
+            }
+            self.set_state(State::Selecting);
+            self.new_xid();
+            let discover = self.message(DHCPDISCOVER);
+            let offer = self
+                .exchange(
+                    socket,
+                    &discover,
+                    Ipv4Addr::BROADCAST,
+                    &[DHCPOFFER],
+                    timeout * NS_PER_SEC,
+                )
+                .await?;
+            timeout = (timeout * 2).min(MAX_RETRANSMIT_SECS);
+            let Some(offer) = offer else {
+                continue;
+            };
+            self.set_state(State::Requesting);
+            let request = Message {
+                requested_ip: Some(offer.yiaddr),
+                server_id: offer.server_id,
+                ..self.message(DHCPREQUEST)
+            };
+            let sent_ns = time::now_ns();
+            let ack = self
+                .exchange(
+                    socket,
+                    &request,
+                    Ipv4Addr::BROADCAST,
+                    &[DHCPACK, DHCPNAK],
+                    FIRST_RETRANSMIT_SECS * NS_PER_SEC,
+                )
+                .await?;
+            match ack {
+                Some(ack) if ack.message_type == Some(DHCPACK) => {
+                    if let Some(lease) = Lease::from_ack(&ack, sent_ns) {
+                        return Ok(Some(lease));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    /// BOUND, RENEWING and REBINDING: extends the lease until it expires
+    /// (returns true) or the client is stopped (returns false).
+    async fn keep(&mut self, socket: &UdpSocket, mut lease: Lease) -> Result<bool> {
+        loop {
+            if !self
+                .sleep_until(lease.deadline_ns(lease.renewal_secs))
+                .await
+            {
+                return Ok(false);
+            }
+            self.set_state(State::Renewing);
+            self.new_xid();
+            let mut renewed = None;
+            let rebinding_ns = lease.deadline_ns(lease.rebinding_secs);
+            let expiry_ns = lease.deadline_ns(lease.lease_secs);
+            while renewed.is_none() && time::now_ns() < expiry_ns {
+                if !self.is_current() {
+                    return Ok(false);
+                }
+                let now = time::now_ns();
+                let (dst, until) = if now < rebinding_ns {
+                    (lease.server, rebinding_ns)
+                } else {
+                    self.set_state(State::Rebinding);
+                    (Ipv4Addr::BROADCAST, expiry_ns)
+                };
+                // RFC 2131 4.4.5: 残り時間の半分 (最短60秒) 待って再送する
+                let wait = ((until - now) / 2).max(MIN_RENEW_WAIT_SECS * NS_PER_SEC);
+                let request = Message {
+                    ciaddr: lease.config.addr,
+                    ..self.message(DHCPREQUEST)
+                };
+                let sent_ns = time::now_ns();
+                // 送れなくても (ARPの失敗など) 次の再送を待つ
+                let ack = self
+                    .exchange(socket, &request, dst, &[DHCPACK, DHCPNAK], wait)
+                    .await
+                    .unwrap_or(None);
+                match ack {
+                    Some(ack) if ack.message_type == Some(DHCPNAK) => return Ok(true),
+                    Some(ack) => renewed = Lease::from_ack(&ack, sent_ns),
+                    None => {}
+                }
+            }
+            let Some(new_lease) = renewed else {
+                return Ok(true);
+            };
+            lease = new_lease;
+            self.set_lease(Some(lease));
+            self.set_state(State::Bound);
+        }
+    }
+}
+
+/// The `dhcp` command: shows the clients, or starts or stops one.
+pub fn cmd_dhcp(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    match args {
+        [] => {
+            let now = time::now_ns();
+            for c in CLIENTS.lock().iter() {
+                let _ = write!(out, "{}: {}", c.iface.name(), c.state.name());
+                if let Some(lease) = c.lease {
+                    let left = lease.deadline_ns(lease.lease_secs).saturating_sub(now) / NS_PER_SEC;
+                    let _ = write!(
+                        out,
+                        " {} server {} expires in {left}s",
+                        lease.config, lease.server
+                    );
+                }
+                let _ = writeln!(out);
+            }
+            Ok(())
+        }
+        ["start", name] => {
+            start(net::find(name).ok_or("dhcp: no such interface")?);
+            Ok(())
+        }
+        ["stop", name] => {
+            let iface = net::find(name).ok_or("dhcp: no such interface")?;
+            stop(&iface);
+            Ok(())
+        }
+        _ => Err("usage: dhcp [start|stop IFACE]"),
+    }
+}
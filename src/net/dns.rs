@@ -0,0 +1,156 @@
+//! A DNS stub resolver for A records (RFC 1035), which asks the server
+//! given by DHCP. The wire format helpers are shared with mDNS.
+
+use crate::net;
+use crate::net::udp::UdpSocket;
+use crate::net::Ipv4Addr;
+use crate::result::Result;
+use crate::time;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+pub const DNS_PORT: u16 = 53;
+
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+const FLAGS_QR: u16 = 0x8000;
+const FLAGS_RD: u16 = 0x0100;
+const RCODE_MASK: u16 = 0x000f;
+const RCODE_NXDOMAIN: u16 = 3;
+const HEADER_SIZE: usize = 12;
+const MAX_NAME_LEN: usize = 255;
+const MAX_POINTER_HOPS: usize = 16;
+const TIMEOUT: Duration = Duration::from_secs(2);
+const RETRIES: usize = 3;
+
+pub(super) fn read_u16(data: &[u8], ofs: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(ofs..ofs + 2)?.try_into().ok()?))
+}
+
+/// Reads a (possibly compressed) domain name at ofs.
+///
+/// Returns the name and the offset right after it.
+pub(super) fn read_name(data: &[u8], mut ofs: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    let mut hops = 0;
+    loop {
+        let len = *data.get(ofs)? as usize;
+        match len {
+            0 => {
+                return Some((name, end.unwrap_or(ofs + 1)));
+            }
+            // 圧縮されたラベル (先頭2ビットが11) は、パケット内の別の位置を指す
+            l if l & 0xc0 == 0xc0 => {
+                hops += 1;
+                if hops > MAX_POINTER_HOPS {
+                    return None;
+                }
+                end.get_or_insert(ofs + 2);
+                ofs = (read_u16(data, ofs)? & 0x3fff) as usize;
+            }
+            l if l & 0xc0 != 0 => return None,
+            l => {
+                let label = data.get(ofs + 1..ofs + 1 + l)?;
+                if name.len() + l + 1 > MAX_NAME_LEN {
+                    return None;
+                }
+                if !name.is_empty() {
+                    name.push('.');
+                }
+                name.push_str(core::str::from_utf8(label).ok()?);
+                ofs += 1 + l;
+            }
+        }
+    }
+}
+
+pub(super) fn write_name(out: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|l| !l.is_empty()) {
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+}
+
+/// A recursive query for the A records of name.
+pub fn build_query(id: u16, name: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&id.to_be_bytes());
+    out.extend_from_slice(&FLAGS_RD.to_be_bytes());
+    out.extend_from_slice(&1u16.to_be_bytes()); // qdcount
+    out.extend_from_slice(&[0; 6]); // ancount, nscount, arcount
+    write_name(&mut out, name);
+    out.extend_from_slice(&TYPE_A.to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    out
+}
+
+/// Returns the addresses in the response to the query id. The CNAME
+/// records are skipped, as the servers answer with the A records of the
+/// canonical name too.
+pub fn parse_response(data: &[u8], id: u16) -> Result<Vec<Ipv4Addr>> {
+    let invalid = "DNS: invalid response";
+    let header = |i: usize| read_u16(data, i * 2).ok_or(invalid);
+    if header(0)? != id || header(1)? & FLAGS_QR == 0 {
+        return Err(invalid);
+    }
+    match header(1)? & RCODE_MASK {
+        0 => {}
+        RCODE_NXDOMAIN => return Err("DNS: no such host"),
+        _ => return Err("DNS: server failure"),
+    }
+    let mut ofs = HEADER_SIZE;
+    for _ in 0..header(2)? {
+        ofs = read_name(data, ofs).ok_or(invalid)?.1 + 4;
+    }
+    let mut addrs = Vec::new();
+    for _ in 0..header(3)? {
+        ofs = read_name(data, ofs).ok_or(invalid)?.1;
+        let rtype = read_u16(data, ofs).ok_or(invalid)?;
+        let class = read_u16(data, ofs + 2).ok_or(invalid)?;
+        let len = read_u16(data, ofs + 8).ok_or(invalid)? as usize;
+        let rdata = data.get(ofs + 10..ofs + 10 + len).ok_or(invalid)?;
+        if let (TYPE_A, CLASS_IN, [a, b, c, d]) = (rtype, class, rdata) {
+            addrs.push(Ipv4Addr::new(*a, *b, *c, *d));
+        }
+        ofs += 10 + len;
+    }
+    Ok(addrs)
+}
+
+/// Returns the address of host, which can also be a dotted address.
+pub fn resolve(host: &str) -> Result<Ipv4Addr> {
+    if let Ok(addr) = host.parse() {
+        return Ok(addr);
+    }
+    if host.is_empty() || host.len() > MAX_NAME_LEN || host.split('.').any(|l| l.len() > 63) {
+        return Err("DNS: invalid host name");
+    }
+    let server = net::interfaces()
+        .iter()
+        .find_map(|i| i.config().dns)
+        .ok_or("DNS: no server is configured")?;
+    let socket = UdpSocket::bind(0)?;
+    let id = time::now_ns() as u16;
+    let query = build_query(id, host);
+    for _ in 0..RETRIES {
+        socket.send_to(server, DNS_PORT, &query)?;
+        let deadline = time::now_ns() + TIMEOUT.as_nanos() as u64;
+        while let Some(datagram) = socket.recv_timeout(Duration::from_nanos(
+            deadline.saturating_sub(time::now_ns()),
+        )) {
+            if datagram.src != server || datagram.src_port != DNS_PORT {
+                continue;
+            }
+            // 別の問い合わせへの応答 (IDが違う) は読み捨てる
+            match parse_response(&datagram.data, id) {
+                Ok(addrs) => return addrs.first().copied().ok_or("DNS: no address"),
+                Err(e) if read_u16(&datagram.data, 0) == Some(id) => return Err(e),
+                Err(_) => {}
+            }
+        }
+    }
+    Err("DNS: no response")
+}
@@ -0,0 +1,69 @@
+//! Ethernet II frames.
+
+use crate::net::Ipv4Addr;
+use alloc::vec::Vec;
+use core::fmt;
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+pub const HEADER_SIZE: usize = 14;
+/// The largest payload, without VLAN tags.
+pub const MTU: usize = 1500;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct MacAddr([u8; 6]);
+impl MacAddr {
+    pub const BROADCAST: MacAddr = MacAddr([0xff; 6]);
+    pub const ZERO: MacAddr = MacAddr([0; 6]);
+    pub const fn new(bytes: [u8; 6]) -> Self {
+        Self(bytes)
+    }
+    pub const fn octets(&self) -> [u8; 6] {
+        self.0
+    }
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 1 != 0
+    }
+    /// The address that the frames to an IPv4 multicast group are sent to:
+    /// 01:00:5e followed by the lower 23 bits of the group (RFC 1112).
+    pub const fn for_multicast(group: Ipv4Addr) -> Self {
+        let [_, b, c, d] = group.octets();
+        Self([0x01, 0x00, 0x5e, b & 0x7f, c, d])
+    }
+}
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame<'a> {
+    pub dst: MacAddr,
+    pub src: MacAddr,
+    pub ethertype: u16,
+    pub payload: &'a [u8],
+}
+impl<'a> Frame<'a> {
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < HEADER_SIZE {
+            return None;
+        }
+        Some(Self {
+            dst: MacAddr(data[0..6].try_into().ok()?),
+            src: MacAddr(data[6..12].try_into().ok()?),
+            ethertype: u16::from_be_bytes([data[12], data[13]]),
+            payload: &data[HEADER_SIZE..],
+        })
+    }
+}
+
+pub fn build(dst: MacAddr, src: MacAddr, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&dst.0);
+    frame.extend_from_slice(&src.0);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
@@ -0,0 +1,199 @@
+use crate::mutex::Mutex;
+use crate::net::Ipv4Addr;
+use crate::result::Result;
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Icmp,
+    Udp,
+    Tcp,
+}
+impl Protocol {
+    fn parse(s: &str) -> Result<Option<Protocol>> {
+        match s {
+            "any" => Ok(None),
+            "icmp" => Ok(Some(Protocol::Icmp)),
+            "udp" => Ok(Some(Protocol::Udp)),
+            "tcp" => Ok(Some(Protocol::Tcp)),
+            _ => Err("fw: unknown protocol"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Allow,
+    Deny,
+}
+impl Action {
+    fn parse(s: &str) -> Result<Action> {
+        match s {
+            "allow" => Ok(Action::Allow),
+            "deny" => Ok(Action::Deny),
+            _ => Err("fw: action must be allow or deny"),
+        }
+    }
+}
+
+/// What the receive path knows about an incoming packet.
+#[derive(Debug, Clone, Copy)]
+pub struct PacketInfo {
+    pub protocol: Protocol,
+    pub src: Ipv4Addr,
+    /// Destination port for UDP and TCP.
+    pub dst_port: Option<u16>,
+}
+
+/// A filter rule. Fields set to None match anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    pub action: Action,
+    pub protocol: Option<Protocol>,
+    pub src: Option<(Ipv4Addr, u8)>,
+    pub dst_port: Option<u16>,
+}
+impl Rule {
+    pub fn matches(&self, pkt: &PacketInfo) -> bool {
+        self.protocol.map_or(true, |p| p == pkt.protocol)
+            && self
+                .src
+                .map_or(true, |(addr, len)| addr.is_in_same_network(&pkt.src, len))
+            && self
+                .dst_port
+                .map_or(true, |port| pkt.dst_port == Some(port))
+    }
+    /// Parses `allow|deny [proto <p>] [from <addr>[/<len>]] [port <n>]`.
+    pub fn parse(args: &[&str]) -> Result<Rule> {
+        let (action, mut args) = args.split_first().ok_or("fw: missing action")?;
+        let mut rule = Rule {
+            action: Action::parse(action)?,
+            protocol: None,
+            src: None,
+            dst_port: None,
+        };
+        while let [key, value, rest @ ..] = args {
+            match *key {
+                "proto" => rule.protocol = Protocol::parse(value)?,
+                "from" => {
+                    let (addr, len) = value.split_once('/').unwrap_or((value, "32"));
+                    let len: u8 = len.parse().map_err(|_| "fw: invalid prefix length")?;
+                    if len > 32 {
+                        return Err("fw: invalid prefix length");
+                    }
+                    rule.src = Some((addr.parse()?, len));
+                }
+                "port" => rule.dst_port = Some(value.parse().map_err(|_| "fw: invalid port")?),
+                _ => return Err("fw: unknown rule option"),
+            }
+            args = rest;
+        }
+        if !args.is_empty() {
+            return Err("fw: missing value for rule option");
+        }
+        Ok(rule)
+    }
+}
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.action {
+            Action::Allow => write!(f, "allow")?,
+            Action::Deny => write!(f, "deny")?,
+        }
+        match self.protocol {
+            Some(Protocol::Icmp) => write!(f, " proto icmp")?,
+            Some(Protocol::Udp) => write!(f, " proto udp")?,
+            Some(Protocol::Tcp) => write!(f, " proto tcp")?,
+            None => {}
+        }
+        if let Some((addr, len)) = self.src {
+            write!(f, " from {addr}/{len}")?;
+        }
+        if let Some(port) = self.dst_port {
+            write!(f, " port {port}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Rules are evaluated in order and the first matching one decides.
+pub struct Firewall {
+    rules: Vec<Rule>,
+    default_action: Action,
+}
+impl Firewall {
+    pub const fn new() -> Self {
+        Self {
+            rules: Vec::new(),
+            default_action: Action::Allow,
+        }
+    }
+    pub fn evaluate(&self, pkt: &PacketInfo) -> Action {
+        self.rules
+            .iter()
+            .find(|r| r.matches(pkt))
+            .map_or(self.default_action, |r| r.action)
+    }
+}
+impl Default for Firewall {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+static FIREWALL: Mutex<Firewall> = Mutex::new(Firewall::new());
+
+/// Called from the receive paths. Returns false if the packet should be dropped.
+pub fn check(pkt: &PacketInfo) -> bool {
+    crate::measure!("net.firewall", { FIREWALL.lock().evaluate(pkt) }) == Action::Allow
+}
+
+pub fn add_rule(rule: Rule) {
+    FIREWALL.lock().rules.push(rule);
+}
+
+/// The `fw` command.
+pub fn cmd_fw(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let mut fw = FIREWALL.lock();
+    match args {
+        [] | ["list"] => {
+            for (i, r) in fw.rules.iter().enumerate() {
+                let _ = writeln!(out, "{i:3}: {r}");
+            }
+            let default = match fw.default_action {
+                Action::Allow => "allow",
+                Action::Deny => "deny",
+            };
+            let _ = writeln!(out, "default: {default}");
+        }
+        ["add", rule @ ..] => fw.rules.push(Rule::parse(rule)?),
+        ["insert", index, rule @ ..] => {
+            let index: usize = index.parse().map_err(|_| "fw: invalid index")?;
+            if index > fw.rules.len() {
+                return Err("fw: index out of range");
+            }
+            fw.rules.insert(index, Rule::parse(rule)?);
+        }
+        ["del", index] => {
+            let index: usize = index.parse().map_err(|_| "fw: invalid index")?;
+            if index >= fw.rules.len() {
+                return Err("fw: index out of range");
+            }
+            fw.rules.remove(index);
+        }
+        ["flush"] => fw.rules.clear(),
+        ["default", action] => fw.default_action = Action::parse(action)?,
+        _ => {
+            let _ = writeln!(
+                out,
+                "usage: fw [list | add <rule> | insert <index> <rule> | del <index> | flush | default allow|deny]"
+            );
+            let _ = writeln!(
+                out,
+                "  <rule>: allow|deny [proto any|icmp|udp|tcp] [from <addr>[/<len>]] [port <n>]"
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,221 @@
+//! An HTTP/1.0 client over TCP, and the `wget` command.
+//!
+//! Only GET of `http://` URLs: the whole response is read until the server
+//! closes the connection, which HTTP/1.0 servers do.
+
+use crate::chainload;
+use crate::net::dns;
+use crate::net::tcp::TcpStream;
+use crate::result::Result;
+use crate::uefi;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+use core::time::Duration;
+
+const DEFAULT_PORT: u16 = 80;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for more data before giving up.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_RESPONSE_SIZE: usize = 16 << 20;
+const MAX_REDIRECTS: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    /// The path and the query, starting with '/'.
+    pub path: String,
+}
+impl Url {
+    pub fn parse(url: &str) -> Result<Self> {
+        let rest = match url.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => rest,
+            Some((scheme, _)) if scheme.eq_ignore_ascii_case("https") => {
+                return Err("HTTPS is not supported")
+            }
+            Some(_) => return Err("Unsupported URL scheme"),
+            None => url,
+        };
+        // フラグメントはサーバに送らない
+        let rest = rest.split('#').next().unwrap_or_default();
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().or(Err("Invalid port in URL"))?),
+            None => (authority, DEFAULT_PORT),
+        };
+        if host.is_empty() || host.contains('@') {
+            return Err("Invalid host in URL");
+        }
+        let path = if path.starts_with('?') {
+            format!("/{path}")
+        } else {
+            path.to_string()
+        };
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+    /// The URL of location, which can be relative to self.
+    pub fn join(&self, location: &str) -> Result<Self> {
+        if location.contains("://") {
+            Self::parse(location)
+        } else if location.starts_with('/') {
+            Ok(Self {
+                path: location.to_string(),
+                ..self.clone()
+            })
+        } else {
+            let dir = &self.path[..self.path.rfind('/').map_or(0, |i| i + 1)];
+            Ok(Self {
+                path: format!("{dir}{location}"),
+                ..self.clone()
+            })
+        }
+    }
+    pub fn request(&self) -> Vec<u8> {
+        let host = if self.port == DEFAULT_PORT {
+            self.host.clone()
+        } else {
+            format!("{}:{}", self.host, self.port)
+        };
+        format!(
+            "GET {} HTTP/1.0\r\nHost: {host}\r\nUser-Agent: wasabi\r\nAccept: */*\r\n\r\n",
+            self.path
+        )
+        .into_bytes()
+    }
+}
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "http://{}", self.host)?;
+        if self.port != DEFAULT_PORT {
+            write!(f, ":{}", self.port)?;
+        }
+        write!(f, "{}", self.path)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+impl Response {
+    /// Parses a whole response, up to the end of the connection.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let end = data
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .ok_or("HTTP: no end of the header")?;
+        let head = core::str::from_utf8(&data[..end]).or(Err("HTTP: header is not UTF-8"))?;
+        let mut lines = head.split("\r\n");
+        let status_line = lines.next().unwrap_or_default();
+        let mut parts = status_line.splitn(3, ' ');
+        if !parts.next().is_some_and(|v| v.starts_with("HTTP/")) {
+            return Err("HTTP: invalid status line");
+        }
+        let status = parts
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or("HTTP: invalid status code")?;
+        let reason = parts.next().unwrap_or_default().to_string();
+        let headers = lines
+            .filter_map(|l| l.split_once(':'))
+            .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+            .collect();
+        let mut response = Self {
+            status,
+            reason,
+            headers,
+            body: data[end + 4..].to_vec(),
+        };
+        if let Some(len) = response.header("Content-Length") {
+            let len: usize = len.parse().or(Err("HTTP: invalid Content-Length"))?;
+            if response.body.len() < len {
+                return Err("HTTP: response is truncated");
+            }
+            response.body.truncate(len);
+        }
+        Ok(response)
+    }
+    /// The value of the header, whose name is case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+    pub fn is_redirect(&self) -> bool {
+        matches!(self.status, 301 | 302 | 303 | 307 | 308)
+    }
+}
+
+fn get_once(url: &Url) -> Result<Response> {
+    let addr = dns::resolve(&url.host)?;
+    let stream = TcpStream::connect(addr, url.port, CONNECT_TIMEOUT)?;
+    stream.write_all(&url.request())?;
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut buf, READ_TIMEOUT)?;
+        if n == 0 {
+            break;
+        }
+        if data.len() + n > MAX_RESPONSE_SIZE {
+            return Err("HTTP: response is too large");
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    let _ = stream.close();
+    Response::parse(&data)
+}
+
+/// Fetches url, following the redirects.
+pub fn get(url: &str) -> Result<Response> {
+    let mut url = Url::parse(url)?;
+    for _ in 0..MAX_REDIRECTS {
+        let response = get_once(&url)?;
+        match response.header("Location") {
+            Some(location) if response.is_redirect() => url = url.join(location)?,
+            _ => return Ok(response),
+        }
+    }
+    Err("HTTP: too many redirects")
+}
+
+/// The `wget` command: shows the body, or saves it to a file on the ESP.
+pub fn cmd_wget(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let (url, path) = match args {
+        [url] => (url, None),
+        [url, path] => (url, Some(path)),
+        _ => return Err("usage: wget URL [PATH]"),
+    };
+    let response = get(url)?;
+    if !(200..300).contains(&response.status) {
+        let _ = writeln!(out, "HTTP {} {}", response.status, response.reason);
+        return Err("wget: the server returned an error");
+    }
+    match path {
+        Some(path) => {
+            let efi_system_table = uefi::system_table().ok_or("EFI context is not initialized")?;
+            chainload::with_firmware_interrupts(|| {
+                uefi::write_file(efi_system_table, path, &response.body)
+            })?;
+            let _ = writeln!(out, "saved {} bytes to {path}", response.body.len());
+        }
+        None => {
+            let _ = write!(out, "{}", String::from_utf8_lossy(&response.body));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,194 @@
+//! ICMP (RFC 792): answers echo requests, and `ping`.
+
+use crate::mutex::Mutex;
+use crate::net;
+use crate::net::ethernet::MacAddr;
+use crate::net::ipv4;
+use crate::net::Interface;
+use crate::net::Ipv4Addr;
+use crate::result::Result;
+use crate::time;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::AtomicU16;
+use core::sync::atomic::Ordering;
+
+pub const TYPE_ECHO_REPLY: u8 = 0;
+pub const TYPE_DEST_UNREACHABLE: u8 = 3;
+pub const TYPE_ECHO_REQUEST: u8 = 8;
+pub const HEADER_SIZE: usize = 8;
+
+const PING_DATA_SIZE: usize = 56;
+const PING_TIMEOUT_NS: u64 = 1_000_000_000;
+/// Echo replies kept for ping, the older ones are dropped.
+const MAX_REPLIES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Message<'a> {
+    pub icmp_type: u8,
+    pub code: u8,
+    /// The 4 bytes after the checksum: the identifier and the sequence
+    /// number for echo messages.
+    pub rest_of_header: [u8; 4],
+    pub data: &'a [u8],
+}
+impl<'a> Message<'a> {
+    /// Parses a message, checking the checksum.
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < HEADER_SIZE || ipv4::checksum(data) != 0 {
+            return None;
+        }
+        Some(Self {
+            icmp_type: data[0],
+            code: data[1],
+            rest_of_header: data[4..8].try_into().ok()?,
+            data: &data[HEADER_SIZE..],
+        })
+    }
+    /// The identifier and the sequence number of an echo message.
+    pub fn echo(&self) -> (u16, u16) {
+        let [a, b, c, d] = self.rest_of_header;
+        (u16::from_be_bytes([a, b]), u16::from_be_bytes([c, d]))
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut message = Vec::with_capacity(HEADER_SIZE + self.data.len());
+        message.extend_from_slice(&[self.icmp_type, self.code, 0, 0]);
+        message.extend_from_slice(&self.rest_of_header);
+        message.extend_from_slice(self.data);
+        let sum = ipv4::checksum(&message);
+        message[2..4].copy_from_slice(&sum.to_be_bytes());
+        message
+    }
+}
+
+pub fn echo_request(id: u16, seq: u16, data: &[u8]) -> Vec<u8> {
+    let [a, b] = id.to_be_bytes();
+    let [c, d] = seq.to_be_bytes();
+    Message {
+        icmp_type: TYPE_ECHO_REQUEST,
+        code: 0,
+        rest_of_header: [a, b, c, d],
+        data,
+    }
+    .to_bytes()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct EchoReply {
+    src: Ipv4Addr,
+    id: u16,
+    seq: u16,
+    ttl: u8,
+    size: usize,
+}
+
+static REPLIES: Mutex<Vec<EchoReply>> = Mutex::new(Vec::new());
+static NEXT_PING_ID: AtomicU16 = AtomicU16::new(1);
+
+/// Handles a received message: answers the echo requests to this host, and
+/// keeps the echo replies for ping.
+pub fn handle(iface: &Interface, src_mac: MacAddr, packet: &ipv4::Packet) {
+    let Some(message) = Message::parse(packet.payload) else {
+        return;
+    };
+    match message.icmp_type {
+        // ブロードキャスト宛てのpingには答えない (Linuxの既定と同じ)
+        TYPE_ECHO_REQUEST if packet.dst == iface.config().addr => {
+            let reply = Message {
+                icmp_type: TYPE_ECHO_REPLY,
+                ..message
+            };
+            let _ = ipv4::send_to(
+                iface,
+                src_mac,
+                packet.src,
+                ipv4::PROTOCOL_ICMP,
+                &reply.to_bytes(),
+            );
+        }
+        TYPE_ECHO_REPLY => {
+            let (id, seq) = message.echo();
+            let mut replies = REPLIES.lock();
+            if replies.len() >= MAX_REPLIES {
+                replies.remove(0);
+            }
+            replies.push(EchoReply {
+                src: packet.src,
+                id,
+                seq,
+                ttl: packet.ttl,
+                size: packet.payload.len(),
+            });
+        }
+        _ => {}
+    }
+}
+
+fn take_reply(dst: Ipv4Addr, id: u16, seq: u16) -> Option<EchoReply> {
+    let mut replies = REPLIES.lock();
+    let i = replies
+        .iter()
+        .position(|r| r.src == dst && r.id == id && r.seq == seq)?;
+    Some(replies.remove(i))
+}
+
+/// Sends an echo request and waits for the reply, handling the received
+/// frames meanwhile. Returns the reply and the round trip time in ns.
+fn ping_once(dst: Ipv4Addr, id: u16, seq: u16) -> Result<Option<(EchoReply, u64)>> {
+    let data: Vec<u8> = (0..PING_DATA_SIZE).map(|i| i as u8).collect();
+    let start = time::now_ns();
+    ipv4::send(dst, ipv4::PROTOCOL_ICMP, &echo_request(id, seq, &data))?;
+    let deadline = start + PING_TIMEOUT_NS;
+    while time::now_ns() < deadline {
+        net::poll();
+        if let Some(reply) = take_reply(dst, id, seq) {
+            return Ok(Some((reply, time::now_ns() - start)));
+        }
+        core::hint::spin_loop();
+    }
+    Ok(None)
+}
+
+/// The `ping` command. The next request is sent as soon as the reply to the
+/// previous one arrives (or times out).
+pub fn cmd_ping(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let (dst, count) = match args {
+        [dst] => (dst, 4),
+        [dst, count] => (dst, count.parse().map_err(|_| "ping: invalid count")?),
+        _ => return Err("usage: ping ADDR [COUNT]"),
+    };
+    let dst: Ipv4Addr = dst.parse()?;
+    let id = NEXT_PING_ID.fetch_add(1, Ordering::Relaxed);
+    let _ = writeln!(out, "PING {dst}: {PING_DATA_SIZE} data bytes");
+    let mut received = 0;
+    for seq in 1..=count {
+        match ping_once(dst, id, seq)? {
+            Some((reply, rtt_ns)) => {
+                received += 1;
+                let _ = writeln!(
+                    out,
+                    "{} bytes from {}: icmp_seq={} ttl={} time={}.{:03} ms",
+                    reply.size,
+                    reply.src,
+                    reply.seq,
+                    reply.ttl,
+                    rtt_ns / 1_000_000,
+                    rtt_ns / 1_000 % 1_000
+                );
+            }
+            None => {
+                let _ = writeln!(out, "Request timeout for icmp_seq {seq}");
+            }
+        }
+    }
+    let loss = if count == 0 {
+        0
+    } else {
+        (count - received) as u32 * 100 / count as u32
+    };
+    let _ = writeln!(
+        out,
+        "{count} packets transmitted, {received} received, {loss}% packet loss"
+    );
+    Ok(())
+}
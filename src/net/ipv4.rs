@@ -0,0 +1,184 @@
+//! IPv4 packets. Fragments and options are not supported: fragmented
+//! packets are dropped, and the options of received packets are skipped.
+
+use crate::net::arp;
+use crate::net::ethernet;
+use crate::net::ethernet::MacAddr;
+use crate::net::firewall;
+use crate::net::firewall::PacketInfo;
+use crate::net::firewall::Protocol;
+use crate::net::icmp;
+use crate::net::tcp;
+use crate::net::udp;
+use crate::net::Interface;
+use crate::net::Ipv4Addr;
+use crate::result::Result;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU16;
+use core::sync::atomic::Ordering;
+
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+pub const HEADER_SIZE: usize = 20;
+const DEFAULT_TTL: u8 = 64;
+const FLAG_MORE_FRAGMENTS: u16 = 0x2000;
+const FLAG_DONT_FRAGMENT: u16 = 0x4000;
+const FRAGMENT_OFFSET_MASK: u16 = 0x1fff;
+
+static NEXT_ID: AtomicU16 = AtomicU16::new(1);
+
+/// The Internet checksum (RFC 1071) of data: the one's complement of the
+/// one's complement sum of the 16-bit words. A packet including its
+/// checksum sums to 0.
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data
+        .chunks(2)
+        .map(|w| u16::from_be_bytes([w[0], *w.get(1).unwrap_or(&0)]) as u32)
+        .sum::<u32>();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// The checksum of a UDP datagram or a TCP segment, with the pseudo header.
+pub fn pseudo_header_checksum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, data: &[u8]) -> u16 {
+    let mut buf = Vec::with_capacity(12 + data.len());
+    buf.extend_from_slice(&src.octets());
+    buf.extend_from_slice(&dst.octets());
+    buf.extend_from_slice(&[0, protocol]);
+    buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    buf.extend_from_slice(data);
+    checksum(&buf)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet<'a> {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+    pub payload: &'a [u8],
+}
+impl<'a> Packet<'a> {
+    /// Parses a packet, checking the header checksum. The Ethernet padding
+    /// after the packet is dropped.
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        let header = data.get(..HEADER_SIZE)?;
+        if header[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = (header[0] & 0xf) as usize * 4;
+        let total_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        if header_len < HEADER_SIZE || total_len < header_len || data.len() < total_len {
+            return None;
+        }
+        if checksum(&data[..header_len]) != 0 {
+            return None;
+        }
+        let flags = u16::from_be_bytes([header[6], header[7]]);
+        if flags & (FLAG_MORE_FRAGMENTS | FRAGMENT_OFFSET_MASK) != 0 {
+            return None;
+        }
+        Some(Self {
+            src: Ipv4Addr::from_bytes(header[12..16].try_into().ok()?),
+            dst: Ipv4Addr::from_bytes(header[16..20].try_into().ok()?),
+            protocol: header[9],
+            ttl: header[8],
+            payload: &data[header_len..total_len],
+        })
+    }
+}
+
+/// Builds a packet with the header checksum. payload must fit in the MTU
+/// with the header, as packets are not fragmented.
+pub fn build(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, id: u16, payload: &[u8]) -> Vec<u8> {
+    let total_len = (HEADER_SIZE + payload.len()) as u16;
+    let mut packet = Vec::with_capacity(total_len as usize);
+    packet.push(0x45); // version 4, 5 words
+    packet.push(0); // DSCP, ECN
+    packet.extend_from_slice(&total_len.to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&FLAG_DONT_FRAGMENT.to_be_bytes());
+    packet.push(DEFAULT_TTL);
+    packet.push(protocol);
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+    let sum = checksum(&packet);
+    packet[10..12].copy_from_slice(&sum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Handles a received packet. src_mac is the sender on the link, which the
+/// replies are sent back to.
+pub fn handle(iface: &Interface, src_mac: MacAddr, data: &[u8]) {
+    let Some(packet) = Packet::parse(data) else {
+        return;
+    };
+    if !iface.config().accepts(packet.dst) && !iface.is_member_of(packet.dst) {
+        return;
+    }
+    match packet.protocol {
+        PROTOCOL_ICMP => {
+            let info = PacketInfo {
+                protocol: Protocol::Icmp,
+                src: packet.src,
+                dst_port: None,
+            };
+            if firewall::check(&info) {
+                icmp::handle(iface, src_mac, &packet);
+            }
+        }
+        PROTOCOL_UDP => {
+            let Some(header) = udp::Header::parse(&packet) else {
+                return;
+            };
+            let info = PacketInfo {
+                protocol: Protocol::Udp,
+                src: packet.src,
+                dst_port: Some(header.dst_port),
+            };
+            if firewall::check(&info) {
+                udp::handle(iface, &packet, &header);
+            }
+        }
+        PROTOCOL_TCP => {
+            let Some(segment) = tcp::Segment::parse(&packet) else {
+                return;
+            };
+            let info = PacketInfo {
+                protocol: Protocol::Tcp,
+                src: packet.src,
+                dst_port: Some(segment.dst_port),
+            };
+            if firewall::check(&info) {
+                tcp::handle(iface, src_mac, &packet, &segment);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Sends a packet to dst_mac on the link of iface.
+pub fn send_to(
+    iface: &Interface,
+    dst_mac: MacAddr,
+    dst: Ipv4Addr,
+    protocol: u8,
+    payload: &[u8],
+) -> Result<()> {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let packet = build(iface.config().addr, dst, protocol, id, payload);
+    iface.send_frame(dst_mac, ethernet::ETHERTYPE_IPV4, &packet)
+}
+
+/// Sends a packet to dst through the route to it, resolving the next hop
+/// with ARP.
+pub fn send(dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<()> {
+    let (iface, next_hop) = super::route(dst).ok_or("Network is unreachable")?;
+    let dst_mac = arp::resolve(&iface, next_hop)?;
+    send_to(&iface, dst_mac, dst, protocol, payload)
+}
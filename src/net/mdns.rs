@@ -0,0 +1,116 @@
+//! The mDNS responder (RFC 6762), which answers the queries for
+//! `<hostname>.local` with the address of the interface, so that the machine
+//! can be reached by the name in the `hostname` setting.
+
+use crate::executor;
+use crate::net::dns::read_name;
+use crate::net::dns::read_u16;
+use crate::net::dns::write_name;
+use crate::net::udp::UdpSocket;
+use crate::net::Interface;
+use crate::net::Ipv4Addr;
+use crate::settings;
+use crate::warn;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+pub const MDNS_PORT: u16 = 5353;
+pub const MDNS_MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+
+const TYPE_A: u16 = 1;
+const TYPE_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const CLASS_MASK: u16 = 0x7fff;
+const CACHE_FLUSH: u16 = 0x8000;
+const FLAGS_QR: u16 = 0x8000;
+const FLAGS_OPCODE: u16 = 0x7800;
+const FLAGS_AA: u16 = 0x0400;
+const TTL_SECONDS: u32 = 120;
+const HEADER_SIZE: usize = 12;
+
+/// Returns true if the mDNS query in packet asks for the A record of `<hostname>.local`.
+pub fn is_query_for(packet: &[u8], hostname: &str) -> bool {
+    let (Some(flags), Some(qdcount)) = (read_u16(packet, 2), read_u16(packet, 4)) else {
+        return false;
+    };
+    if flags & (FLAGS_QR | FLAGS_OPCODE) != 0 {
+        return false;
+    }
+    let mut ofs = HEADER_SIZE;
+    for _ in 0..qdcount {
+        let Some((name, next)) = read_name(packet, ofs) else {
+            return false;
+        };
+        let (Some(qtype), Some(qclass)) = (read_u16(packet, next), read_u16(packet, next + 2))
+        else {
+            return false;
+        };
+        ofs = next + 4;
+        let matches_name = name
+            .strip_suffix(".local")
+            .is_some_and(|h| h.eq_ignore_ascii_case(hostname));
+        if matches_name && (qtype == TYPE_A || qtype == TYPE_ANY) && qclass & CLASS_MASK == CLASS_IN
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// Builds the response to an mDNS query if it asks for `<hostname>.local`.
+///
+/// The returned packet should be sent to MDNS_MULTICAST_ADDR:MDNS_PORT.
+pub fn handle_query(packet: &[u8], hostname: &str, addr: Ipv4Addr) -> Option<Vec<u8>> {
+    if !is_query_for(packet, hostname) {
+        return None;
+    }
+    let mut out = Vec::new();
+    // マルチキャストの応答ではIDは0にする (RFC 6762 18.1)
+    out.extend_from_slice(&0u16.to_be_bytes());
+    out.extend_from_slice(&(FLAGS_QR | FLAGS_AA).to_be_bytes());
+    out.extend_from_slice(&0u16.to_be_bytes()); // qdcount
+    out.extend_from_slice(&1u16.to_be_bytes()); // ancount
+    out.extend_from_slice(&0u16.to_be_bytes()); // nscount
+    out.extend_from_slice(&0u16.to_be_bytes()); // arcount
+    write_name(&mut out, hostname);
+    out.pop();
+    write_name(&mut out, "local");
+    out.extend_from_slice(&TYPE_A.to_be_bytes());
+    out.extend_from_slice(&(CLASS_IN | CACHE_FLUSH).to_be_bytes());
+    out.extend_from_slice(&TTL_SECONDS.to_be_bytes());
+    out.extend_from_slice(&4u16.to_be_bytes());
+    out.extend_from_slice(&addr.octets());
+    Some(out)
+}
+
+/// Joins the mDNS group on iface and answers the queries in the background,
+/// once DHCP has given iface an address.
+pub fn start(iface: Arc<Interface>) {
+    iface.join_multicast(MDNS_MULTICAST_ADDR);
+    executor::spawn(async move {
+        let socket = match UdpSocket::bind_to_interface(iface.clone(), MDNS_PORT) {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("mDNS: {e}");
+                return;
+            }
+        };
+        loop {
+            let Some(query) = socket.recv(Duration::from_secs(60)).await else {
+                continue;
+            };
+            let config = iface.config();
+            if !config.is_configured() {
+                continue;
+            }
+            // 設定が変わっても再起動せずに済むよう、毎回読む
+            let hostname = settings::get("hostname");
+            if let Some(response) = handle_query(&query.data, &hostname, config.addr) {
+                if let Err(e) = socket.send_to(MDNS_MULTICAST_ADDR, MDNS_PORT, &response) {
+                    warn!("mDNS: {e}");
+                }
+            }
+        }
+    });
+}
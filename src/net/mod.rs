@@ -0,0 +1,317 @@
+//! The network stack: Ethernet, ARP, IPv4, ICMP, UDP and TCP on top of the
+//! NetworkDevice drivers. The interfaces are configured by DHCP.
+//!
+//! The received frames are handled by a task that polls the devices, and
+//! also by anyone waiting for a reply (e.g. arp::resolve()), so that the
+//! replies are not stuck behind the waiter.
+
+pub mod arp;
+pub mod dhcp;
+pub mod dns;
+pub mod ethernet;
+pub mod firewall;
+pub mod http;
+pub mod icmp;
+pub mod ipv4;
+pub mod mdns;
+pub mod ntp;
+pub mod tcp;
+pub mod udp;
+
+use crate::executor;
+use crate::mutex::Mutex;
+use crate::time;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+use core::time::Duration;
+use ethernet::Frame;
+use ethernet::MacAddr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, PartialOrd, Ord, Hash)]
+pub struct Ipv4Addr([u8; 4]);
+impl Ipv4Addr {
+    pub const UNSPECIFIED: Ipv4Addr = Ipv4Addr([0, 0, 0, 0]);
+    pub const BROADCAST: Ipv4Addr = Ipv4Addr([255, 255, 255, 255]);
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Self([a, b, c, d])
+    }
+    pub const fn from_bytes(bytes: [u8; 4]) -> Self {
+        Self(bytes)
+    }
+    pub const fn octets(&self) -> [u8; 4] {
+        self.0
+    }
+    pub const fn to_u32(&self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+    /// Returns true if self is a multicast group, i.e. in 224.0.0.0/4.
+    pub const fn is_multicast(&self) -> bool {
+        self.0[0] & 0xf0 == 224
+    }
+    /// Returns true if self and other are in the same network of the given prefix length.
+    pub fn is_in_same_network(&self, other: &Ipv4Addr, prefix_len: u8) -> bool {
+        let mask = u32::MAX
+            .checked_shl(32 - prefix_len.min(32) as u32)
+            .unwrap_or(0);
+        self.to_u32() & mask == other.to_u32() & mask
+    }
+}
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{a}.{b}.{c}.{d}")
+    }
+}
+impl FromStr for Ipv4Addr {
+    type Err = &'static str;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut octets = [0u8; 4];
+        let mut it = s.split('.');
+        for e in octets.iter_mut() {
+            *e = it
+                .next()
+                .and_then(|v| v.parse().ok())
+                .ok_or("Invalid IPv4 address")?;
+        }
+        if it.next().is_some() {
+            return Err("Invalid IPv4 address");
+        }
+        Ok(Self(octets))
+    }
+}
+
+pub trait NetworkDevice: Send + Sync {
+    fn name(&self) -> &str;
+    fn mac_addr(&self) -> MacAddr;
+    /// Sends an Ethernet frame, without the FCS.
+    fn send(&self, frame: &[u8]) -> crate::result::Result<()>;
+    /// Returns a received frame, without the FCS, if any. Does not wait.
+    fn receive(&self) -> Option<Vec<u8>>;
+}
+
+/// The IPv4 configuration of an interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpConfig {
+    pub addr: Ipv4Addr,
+    pub prefix_len: u8,
+    pub gateway: Option<Ipv4Addr>,
+    pub dns: Option<Ipv4Addr>,
+}
+impl IpConfig {
+    pub const UNCONFIGURED: IpConfig = IpConfig {
+        addr: Ipv4Addr::UNSPECIFIED,
+        prefix_len: 0,
+        gateway: None,
+        dns: None,
+    };
+    pub fn is_configured(&self) -> bool {
+        self.addr != Ipv4Addr::UNSPECIFIED
+    }
+    /// Returns true if packets to dst are for this host.
+    pub fn accepts(&self, dst: Ipv4Addr) -> bool {
+        let host_bits = u32::MAX.checked_shr(self.prefix_len as u32).unwrap_or(0);
+        dst == self.addr
+            || dst == Ipv4Addr::BROADCAST
+            || (self.is_configured()
+                && self.prefix_len < 31
+                && dst.is_in_same_network(&self.addr, self.prefix_len)
+                && dst.to_u32() & host_bits == host_bits)
+    }
+}
+impl fmt::Display for IpConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)?;
+        if let Some(gateway) = self.gateway {
+            write!(f, " gateway {gateway}")?;
+        }
+        if let Some(dns) = self.dns {
+            write!(f, " dns {dns}")?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Interface {
+    device: Arc<dyn NetworkDevice>,
+    config: Mutex<IpConfig>,
+    multicast_groups: Mutex<Vec<Ipv4Addr>>,
+}
+impl Interface {
+    pub fn name(&self) -> &str {
+        self.device.name()
+    }
+    pub fn mac_addr(&self) -> MacAddr {
+        self.device.mac_addr()
+    }
+    pub fn config(&self) -> IpConfig {
+        *self.config.lock()
+    }
+    pub fn set_config(&self, config: IpConfig) {
+        *self.config.lock() = config;
+    }
+    /// Starts receiving the packets sent to the multicast group.
+    pub fn join_multicast(&self, group: Ipv4Addr) {
+        let mut groups = self.multicast_groups.lock();
+        if group.is_multicast() && !groups.contains(&group) {
+            groups.push(group);
+        }
+    }
+    pub fn is_member_of(&self, group: Ipv4Addr) -> bool {
+        self.multicast_groups.lock().contains(&group)
+    }
+    /// Returns true if frames to mac are for this interface.
+    pub fn accepts_mac(&self, mac: MacAddr) -> bool {
+        mac == self.mac_addr()
+            || mac == MacAddr::BROADCAST
+            || self
+                .multicast_groups
+                .lock()
+                .iter()
+                .any(|g| MacAddr::for_multicast(*g) == mac)
+    }
+    pub fn send_frame(
+        &self,
+        dst: MacAddr,
+        ethertype: u16,
+        payload: &[u8],
+    ) -> crate::result::Result<()> {
+        if payload.len() > ethernet::MTU {
+            return Err("Packet is larger than the MTU");
+        }
+        self.device
+            .send(&ethernet::build(dst, self.mac_addr(), ethertype, payload))
+    }
+}
+
+static INTERFACES: Mutex<Vec<Arc<Interface>>> = Mutex::new(Vec::new());
+
+/// Registers a device as an interface, without an address until init().
+pub fn register(device: Arc<dyn NetworkDevice>) -> Arc<Interface> {
+    let iface = Arc::new(Interface {
+        device,
+        config: Mutex::new(IpConfig::UNCONFIGURED),
+        multicast_groups: Mutex::new(Vec::new()),
+    });
+    INTERFACES.lock().push(iface.clone());
+    iface
+}
+
+/// The name for the next interface: eth0, eth1, ...
+pub fn next_name() -> String {
+    format!("eth{}", INTERFACES.lock().len())
+}
+
+pub fn interfaces() -> Vec<Arc<Interface>> {
+    INTERFACES.lock().clone()
+}
+
+pub fn find(name: &str) -> Option<Arc<Interface>> {
+    INTERFACES.lock().iter().find(|i| i.name() == name).cloned()
+}
+
+/// The interface to send packets to dst through, and the next hop: dst
+/// itself if it is on the link, the gateway otherwise.
+pub fn route(dst: Ipv4Addr) -> Option<(Arc<Interface>, Ipv4Addr)> {
+    let interfaces = interfaces();
+    let on_link = interfaces.iter().find(|i| {
+        let config = i.config();
+        config.is_configured()
+            && (dst == Ipv4Addr::BROADCAST
+                || dst.is_in_same_network(&config.addr, config.prefix_len))
+    });
+    if let Some(iface) = on_link {
+        return Some((iface.clone(), dst));
+    }
+    interfaces
+        .into_iter()
+        .find_map(|i| i.config().gateway.map(|gateway| (i.clone(), gateway)))
+}
+
+/// Handles the frames received so far on all the interfaces, and runs the
+/// TCP timers.
+pub fn poll() {
+    for iface in interfaces() {
+        while let Some(frame) = iface.device.receive() {
+            handle_frame(&iface, &frame);
+        }
+    }
+    tcp::on_tick();
+}
+
+fn handle_frame(iface: &Interface, data: &[u8]) {
+    let Some(frame) = Frame::parse(data) else {
+        return;
+    };
+    if !iface.accepts_mac(frame.dst) {
+        return;
+    }
+    match frame.ethertype {
+        ethernet::ETHERTYPE_ARP => arp::handle(iface, frame.payload),
+        ethernet::ETHERTYPE_IPV4 => ipv4::handle(iface, frame.src, frame.payload),
+        _ => {}
+    }
+}
+
+/// Starts handling the received frames in the background, and DHCP and the
+/// mDNS responder on each interface. Returns the number of interfaces.
+pub fn init() -> crate::result::Result<usize> {
+    let n = INTERFACES.lock().len();
+    if n == 0 {
+        return Err("No network interface");
+    }
+    executor::spawn(async {
+        loop {
+            time::sleep(Duration::from_millis(10)).await;
+            poll();
+        }
+    });
+    for iface in interfaces() {
+        dhcp::start(iface.clone());
+        mdns::start(iface);
+    }
+    ntp::start();
+    Ok(n)
+}
+
+/// The `ifconfig` command.
+pub fn cmd_ifconfig(args: &[&str], out: &mut dyn fmt::Write) -> crate::result::Result<()> {
+    match args {
+        [] => {
+            for iface in interfaces() {
+                let _ = writeln!(out, "{}: ether {}", iface.name(), iface.mac_addr());
+                let config = iface.config();
+                if config.is_configured() {
+                    let _ = writeln!(out, "    inet {config}");
+                }
+            }
+            Ok(())
+        }
+        [name, addr, rest @ ..] if rest.len() <= 2 => {
+            let iface = find(name).ok_or("ifconfig: no such interface")?;
+            let (addr, prefix_len) = addr
+                .split_once('/')
+                .ok_or("ifconfig: address must be ADDR/PREFIX")?;
+            let prefix_len: u8 = prefix_len
+                .parse()
+                .ok()
+                .filter(|&n| n <= 32)
+                .ok_or("ifconfig: invalid prefix length")?;
+            let gateway = rest.first().map(|a| a.parse()).transpose()?;
+            let dns = rest.get(1).map(|a| a.parse()).transpose()?;
+            // 手で設定したアドレスをDHCPが上書きしないよう止める
+            dhcp::stop(&iface);
+            iface.set_config(IpConfig {
+                addr: addr.parse()?,
+                prefix_len,
+                gateway,
+                dns,
+            });
+            Ok(())
+        }
+        _ => Err("usage: ifconfig [NAME ADDR/PREFIX [GATEWAY [DNS]]]"),
+    }
+}
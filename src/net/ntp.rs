@@ -0,0 +1,268 @@
+//! An SNTP client (RFC 4330) that sets the wall clock.
+//!
+//! The server is the `ntp_server` setting. At boot, a task waits for an
+//! interface to get an address, sets the clock and then resyncs it every
+//! hour, since the TSC calibration is only accurate to a few hundred ppm.
+
+use crate::executor;
+use crate::info;
+use crate::net;
+use crate::net::dns;
+use crate::net::udp::Datagram;
+use crate::net::udp::UdpSocket;
+use crate::net::Ipv4Addr;
+use crate::result::Result;
+use crate::settings;
+use crate::time;
+use crate::time::DateTime;
+use crate::warn;
+use core::fmt;
+use core::time::Duration;
+
+pub const NTP_PORT: u16 = 123;
+pub const PACKET_SIZE: usize = 48;
+const VERSION: u8 = 4;
+pub const MODE_CLIENT: u8 = 3;
+pub const MODE_SERVER: u8 = 4;
+/// The leap indicator of a server whose clock is not synchronized.
+const LEAP_ALARM: u8 = 3;
+/// Seconds from 1900-01-01 (the NTP epoch) to 1970-01-01.
+const UNIX_EPOCH: u64 = 2_208_988_800;
+const TIMEOUT: Duration = Duration::from_secs(2);
+const RETRIES: usize = 3;
+const RETRY_INTERVAL: Duration = Duration::from_secs(16);
+const RESYNC_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// A 32.32 fixed point number of seconds since the NTP epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Timestamp(pub u64);
+impl Timestamp {
+    /// The time since the Unix epoch. The timestamps with the top bit clear
+    /// are taken as in the era after 2036 (RFC 4330 section 3).
+    pub fn to_unix_time(self) -> Duration {
+        let mut secs = self.0 >> 32;
+        if secs & 0x8000_0000 == 0 {
+            secs += 1 << 32;
+        }
+        let nanos = ((self.0 & 0xffff_ffff) * 1_000_000_000) >> 32;
+        Duration::new(secs.saturating_sub(UNIX_EPOCH), nanos as u32)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Packet {
+    pub leap: u8,
+    pub version: u8,
+    pub mode: u8,
+    pub stratum: u8,
+    /// The transmit timestamp of the request, echoed by the server.
+    pub originate: Timestamp,
+    /// When the server received the request.
+    pub receive: Timestamp,
+    /// When the server sent the reply.
+    pub transmit: Timestamp,
+}
+impl Packet {
+    /// A request, whose transmit timestamp is only used as a nonce since
+    /// the client does not know the time yet.
+    pub fn request(nonce: u64) -> Self {
+        Self {
+            version: VERSION,
+            mode: MODE_CLIENT,
+            transmit: Timestamp(nonce),
+            ..Default::default()
+        }
+    }
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < PACKET_SIZE {
+            return None;
+        }
+        let timestamp = |i: usize| {
+            Some(Timestamp(u64::from_be_bytes(
+                data[i..i + 8].try_into().ok()?,
+            )))
+        };
+        Some(Self {
+            leap: data[0] >> 6,
+            version: (data[0] >> 3) & 7,
+            mode: data[0] & 7,
+            stratum: data[1],
+            originate: timestamp(24)?,
+            receive: timestamp(32)?,
+            transmit: timestamp(40)?,
+        })
+    }
+    pub fn to_bytes(&self) -> [u8; PACKET_SIZE] {
+        let mut data = [0u8; PACKET_SIZE];
+        data[0] = (self.leap << 6) | ((self.version & 7) << 3) | (self.mode & 7);
+        data[1] = self.stratum;
+        data[24..32].copy_from_slice(&self.originate.0.to_be_bytes());
+        data[32..40].copy_from_slice(&self.receive.0.to_be_bytes());
+        data[40..48].copy_from_slice(&self.transmit.0.to_be_bytes());
+        data
+    }
+    /// Checks that self is a usable reply to the request with nonce.
+    pub fn is_reply_to(&self, nonce: u64) -> bool {
+        self.mode == MODE_SERVER
+            && self.leap != LEAP_ALARM
+            // 0 は Kiss-o'-Death
+            && (1..=15).contains(&self.stratum)
+            && self.originate.0 == nonce
+            && self.transmit.0 != 0
+    }
+}
+
+/// The result of an exchange with a server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// The Unix time when the reply was received.
+    pub unix_time: Duration,
+    /// The round trip delay, excluding the time spent in the server.
+    pub delay: Duration,
+}
+impl Sample {
+    /// Computes the time from a reply sent at sent_ns and received at
+    /// received_ns, assuming that the network delay is symmetric.
+    pub fn new(reply: &Packet, sent_ns: u64, received_ns: u64) -> Self {
+        let round_trip = Duration::from_nanos(received_ns.saturating_sub(sent_ns));
+        let in_server = reply
+            .transmit
+            .to_unix_time()
+            .saturating_sub(reply.receive.to_unix_time());
+        let delay = round_trip.saturating_sub(in_server);
+        Self {
+            unix_time: reply.transmit.to_unix_time() + delay / 2,
+            delay,
+        }
+    }
+}
+
+fn nonce() -> u64 {
+    // 値は何でもよいが、古い応答と区別できるように毎回変える
+    time::now_ns().rotate_left(32) ^ 0x5741_5341_4249
+}
+
+/// The address of the server to use, or None if disabled.
+fn server() -> Result<Option<Ipv4Addr>> {
+    match settings::get("ntp_server").as_str() {
+        "off" => Ok(None),
+        host => dns::resolve(host).map(Some),
+    }
+}
+
+/// The sample from datagram if it is the reply to the request with nonce.
+fn sample_from(datagram: &Datagram, server: Ipv4Addr, nonce: u64, sent_ns: u64) -> Option<Sample> {
+    let reply = Packet::parse(&datagram.data)?;
+    (datagram.src == server && datagram.src_port == NTP_PORT && reply.is_reply_to(nonce))
+        .then(|| Sample::new(&reply, sent_ns, time::now_ns()))
+}
+
+/// Asks server for the time. Blocks, for the shell commands.
+pub fn query(server: Ipv4Addr) -> Result<Sample> {
+    let socket = UdpSocket::bind(0)?;
+    for _ in 0..RETRIES {
+        let nonce = nonce();
+        let sent_ns = time::now_ns();
+        socket.send_to(server, NTP_PORT, &Packet::request(nonce).to_bytes())?;
+        let deadline = sent_ns + TIMEOUT.as_nanos() as u64;
+        while let Some(datagram) = socket.recv_timeout(Duration::from_nanos(
+            deadline.saturating_sub(time::now_ns()),
+        )) {
+            if let Some(sample) = sample_from(&datagram, server, nonce, sent_ns) {
+                return Ok(sample);
+            }
+        }
+    }
+    Err("NTP: no response")
+}
+
+/// Same as query() but lets the other tasks run while waiting.
+async fn query_async(server: Ipv4Addr) -> Result<Sample> {
+    let socket = UdpSocket::bind(0)?;
+    for _ in 0..RETRIES {
+        let nonce = nonce();
+        let sent_ns = time::now_ns();
+        socket.send_to(server, NTP_PORT, &Packet::request(nonce).to_bytes())?;
+        let deadline = sent_ns + TIMEOUT.as_nanos() as u64;
+        while let Some(datagram) = socket
+            .recv(Duration::from_nanos(
+                deadline.saturating_sub(time::now_ns()),
+            ))
+            .await
+        {
+            if let Some(sample) = sample_from(&datagram, server, nonce, sent_ns) {
+                return Ok(sample);
+            }
+        }
+    }
+    Err("NTP: no response")
+}
+
+/// Sets the wall clock from sample. Returns how far the clock stepped in
+/// milliseconds, or None if it was not set before.
+fn apply(sample: &Sample) -> Option<i64> {
+    let old = time::unix_time();
+    time::set_unix_time(sample.unix_time);
+    old.map(|old| sample.unix_time.as_millis() as i64 - old.as_millis() as i64)
+}
+
+/// Syncs the clock once. Returns false if disabled by the setting.
+async fn sync() -> Result<bool> {
+    let Some(server) = server()? else {
+        return Ok(false);
+    };
+    let sample = query_async(server).await?;
+    if apply(&sample).is_none() {
+        let now = DateTime::from_unix_time(sample.unix_time);
+        info!("NTP: clock set to {now} UTC by {server}");
+    }
+    Ok(true)
+}
+
+/// Starts the task that keeps the wall clock in sync. Call after the
+/// interfaces are up.
+pub fn start() {
+    executor::spawn(async {
+        loop {
+            let configured = net::interfaces().iter().any(|i| i.config().is_configured());
+            let interval = if !configured {
+                Duration::from_secs(1)
+            } else {
+                match sync().await {
+                    Ok(true) => RESYNC_INTERVAL,
+                    Ok(false) => return,
+                    Err(e) => {
+                        warn!("NTP: {e}");
+                        RETRY_INTERVAL
+                    }
+                }
+            };
+            time::sleep(interval).await;
+        }
+    });
+}
+
+/// The `ntp` command: sets the clock from the server, or the configured one.
+pub fn cmd_ntp(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let server = match args {
+        [] => server()?.ok_or("ntp: ntp_server is off")?,
+        [host] => dns::resolve(host)?,
+        _ => return Err("usage: ntp [SERVER]"),
+    };
+    let sample = query(server)?;
+    let _ = write!(
+        out,
+        "{} UTC from {server}, delay {} ms",
+        DateTime::from_unix_time(sample.unix_time),
+        sample.delay.as_millis()
+    );
+    match apply(&sample) {
+        Some(step) => {
+            let _ = writeln!(out, ", stepped {step:+} ms");
+        }
+        None => {
+            let _ = writeln!(out);
+        }
+    }
+    Ok(())
+}
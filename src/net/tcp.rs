@@ -0,0 +1,573 @@
+//! A minimal TCP (RFC 793) client: active open, in-order receive and
+//! go-back-N retransmission. There are no listening sockets, options other
+//! than MSS, zero window probes nor congestion control; enough to talk to
+//! a server on the LAN or through QEMU's user networking.
+//!
+//! The segments are handled by net::poll(), which also runs the
+//! retransmission timers, and TcpStream waits by calling it.
+
+use crate::mutex::Mutex;
+use crate::net;
+use crate::net::arp;
+use crate::net::ethernet;
+use crate::net::ethernet::MacAddr;
+use crate::net::ipv4;
+use crate::net::Interface;
+use crate::net::Ipv4Addr;
+use crate::rand;
+use crate::result::Result;
+use crate::time;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+pub const HEADER_SIZE: usize = 20;
+
+const FLAG_FIN: u8 = 0x01;
+const FLAG_SYN: u8 = 0x02;
+const FLAG_RST: u8 = 0x04;
+const FLAG_PSH: u8 = 0x08;
+const FLAG_ACK: u8 = 0x10;
+
+const OPT_END: u8 = 0;
+const OPT_NOP: u8 = 1;
+const OPT_MSS: u8 = 2;
+
+/// The MSS assumed when the peer does not tell (RFC 1122).
+const DEFAULT_MSS: usize = 536;
+const OUR_MSS: usize = ethernet::MTU - ipv4::HEADER_SIZE - HEADER_SIZE;
+const RECV_BUFFER_SIZE: usize = 65535;
+const FIRST_RTO_NS: u64 = 1_000_000_000;
+const MAX_RTO_NS: u64 = 16_000_000_000;
+const MAX_RETRANSMITS: u32 = 6;
+/// How long to wait for the peer to acknowledge our FIN on close.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+const EPHEMERAL_PORTS: core::ops::Range<u16> = 49152..65535;
+
+/// Sequence numbers wrap around, so they are compared by the difference.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Segment<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    pub flags: u8,
+    pub window: u16,
+    /// The MSS option, if any.
+    pub mss: Option<u16>,
+    pub data: &'a [u8],
+}
+impl<'a> Segment<'a> {
+    /// Parses the segment in packet, checking the checksum.
+    pub fn parse(packet: &ipv4::Packet<'a>) -> Option<Self> {
+        let data = packet.payload;
+        let header_len = (*data.get(12)? >> 4) as usize * 4;
+        if header_len < HEADER_SIZE || data.len() < header_len {
+            return None;
+        }
+        if ipv4::pseudo_header_checksum(packet.src, packet.dst, ipv4::PROTOCOL_TCP, data) != 0 {
+            return None;
+        }
+        let mut mss = None;
+        let mut options = &data[HEADER_SIZE..header_len];
+        while let [kind, rest @ ..] = options {
+            match *kind {
+                OPT_END => break,
+                OPT_NOP => options = rest,
+                _ => {
+                    let len = *rest.first()? as usize;
+                    let value = options.get(2..len)?;
+                    if *kind == OPT_MSS && value.len() == 2 {
+                        mss = Some(u16::from_be_bytes([value[0], value[1]]));
+                    }
+                    options = &options[len..];
+                }
+            }
+        }
+        let u32_at =
+            |i: usize| u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]);
+        Some(Self {
+            src_port: u16::from_be_bytes([data[0], data[1]]),
+            dst_port: u16::from_be_bytes([data[2], data[3]]),
+            seq: u32_at(4),
+            ack: u32_at(8),
+            flags: data[13],
+            window: u16::from_be_bytes([data[14], data[15]]),
+            mss,
+            data: &data[header_len..],
+        })
+    }
+    pub fn to_bytes(&self, src: Ipv4Addr, dst: Ipv4Addr) -> Vec<u8> {
+        let header_len = if self.mss.is_some() {
+            HEADER_SIZE + 4
+        } else {
+            HEADER_SIZE
+        };
+        let mut out = Vec::with_capacity(header_len + self.data.len());
+        out.extend_from_slice(&self.src_port.to_be_bytes());
+        out.extend_from_slice(&self.dst_port.to_be_bytes());
+        out.extend_from_slice(&self.seq.to_be_bytes());
+        out.extend_from_slice(&self.ack.to_be_bytes());
+        out.push(((header_len / 4) as u8) << 4);
+        out.push(self.flags);
+        out.extend_from_slice(&self.window.to_be_bytes());
+        out.extend_from_slice(&[0, 0, 0, 0]); // checksum, urgent pointer
+        if let Some(mss) = self.mss {
+            out.extend_from_slice(&[OPT_MSS, 4]);
+            out.extend_from_slice(&mss.to_be_bytes());
+        }
+        out.extend_from_slice(self.data);
+        let sum = ipv4::pseudo_header_checksum(src, dst, ipv4::PROTOCOL_TCP, &out);
+        out[16..18].copy_from_slice(&sum.to_be_bytes());
+        out
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    SynSent,
+    Established,
+    /// We have sent FIN.
+    FinWait1,
+    /// Our FIN is acknowledged, waiting for the peer's.
+    FinWait2,
+    /// The peer has sent FIN.
+    CloseWait,
+    /// Both have sent FIN, ours is not acknowledged yet.
+    Closing,
+    LastAck,
+    /// Both FINs are acknowledged. TIME-WAIT is not kept.
+    Closed,
+}
+
+struct Connection {
+    id: u64,
+    iface: Arc<Interface>,
+    dst_mac: MacAddr,
+    local: (Ipv4Addr, u16),
+    remote: (Ipv4Addr, u16),
+    state: State,
+    iss: u32,
+    /// The oldest unacknowledged sequence number.
+    snd_una: u32,
+    /// The next sequence number to send.
+    snd_nxt: u32,
+    snd_wnd: u32,
+    mss: usize,
+    rcv_nxt: u32,
+    /// The data from snd_una: sent but unacknowledged, then unsent.
+    send_buf: VecDeque<u8>,
+    /// close() has been called: FIN follows send_buf.
+    fin_queued: bool,
+    recv_buf: VecDeque<u8>,
+    /// The window advertised last, to send an update when it reopens.
+    advertised_window: u16,
+    rto_ns: u64,
+    retransmit_at: Option<u64>,
+    retransmits: u32,
+    error: Option<&'static str>,
+}
+impl Connection {
+    fn window(&self) -> u16 {
+        (RECV_BUFFER_SIZE - self.recv_buf.len()) as u16
+    }
+    fn send_segment(&mut self, seq: u32, flags: u8, data: &[u8]) {
+        let window = self.window();
+        self.advertised_window = window;
+        let segment = Segment {
+            src_port: self.local.1,
+            dst_port: self.remote.1,
+            seq,
+            ack: if flags & FLAG_ACK != 0 {
+                self.rcv_nxt
+            } else {
+                0
+            },
+            flags,
+            window,
+            mss: (flags & FLAG_SYN != 0).then_some(OUR_MSS as u16),
+            data,
+        };
+        let bytes = segment.to_bytes(self.local.0, self.remote.0);
+        let _ = ipv4::send_to(
+            &self.iface,
+            self.dst_mac,
+            self.remote.0,
+            ipv4::PROTOCOL_TCP,
+            &bytes,
+        );
+    }
+    fn send_ack(&mut self) {
+        self.send_segment(self.snd_nxt, FLAG_ACK, &[]);
+    }
+    /// The sequence number of our FIN, once queued.
+    fn fin_seq(&self) -> Option<u32> {
+        self.fin_queued
+            .then(|| self.snd_una.wrapping_add(self.send_buf.len() as u32))
+    }
+    /// Sends what the window allows from snd_nxt.
+    fn output(&mut self) {
+        if self.state == State::SynSent {
+            self.send_segment(self.iss, FLAG_SYN, &[]);
+            self.snd_nxt = self.iss.wrapping_add(1);
+        } else {
+            loop {
+                let sent = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+                let window_left = (self.snd_wnd as usize).saturating_sub(sent);
+                let len = self
+                    .send_buf
+                    .len()
+                    .saturating_sub(sent)
+                    .min(self.mss)
+                    .min(window_left);
+                if len > 0 {
+                    let data: Vec<u8> = self.send_buf.range(sent..sent + len).copied().collect();
+                    self.send_segment(self.snd_nxt, FLAG_ACK | FLAG_PSH, &data);
+                    self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+                    continue;
+                }
+                if self.fin_seq() == Some(self.snd_nxt) && sent == self.send_buf.len() {
+                    self.send_segment(self.snd_nxt, FLAG_FIN | FLAG_ACK, &[]);
+                    self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                }
+                break;
+            }
+        }
+        if self.snd_nxt != self.snd_una && self.retransmit_at.is_none() {
+            self.retransmit_at = Some(time::now_ns() + self.rto_ns);
+        }
+    }
+    fn fail(&mut self, error: &'static str) {
+        self.state = State::Closed;
+        self.error = Some(error);
+        self.retransmit_at = None;
+    }
+    fn on_timer(&mut self, now: u64) {
+        let Some(at) = self.retransmit_at else {
+            return;
+        };
+        if now < at {
+            return;
+        }
+        self.retransmits += 1;
+        if self.retransmits > MAX_RETRANSMITS {
+            self.fail("TCP: connection timed out");
+            return;
+        }
+        // 未確認のところから全部送り直す
+        self.rto_ns = (self.rto_ns * 2).min(MAX_RTO_NS);
+        self.retransmit_at = None;
+        self.snd_nxt = self.snd_una;
+        self.output();
+    }
+    fn on_segment(&mut self, segment: &Segment) {
+        if segment.flags & FLAG_RST != 0 {
+            let acceptable = if self.state == State::SynSent {
+                segment.flags & FLAG_ACK != 0 && segment.ack == self.snd_nxt
+            } else {
+                segment.seq == self.rcv_nxt
+            };
+            if acceptable && self.state == State::SynSent {
+                self.fail("TCP: connection refused");
+            } else if acceptable {
+                self.fail("TCP: connection reset");
+            }
+            return;
+        }
+        if self.state == State::SynSent {
+            if segment.flags & (FLAG_SYN | FLAG_ACK) != FLAG_SYN | FLAG_ACK
+                || segment.ack != self.snd_nxt
+            {
+                return;
+            }
+            self.rcv_nxt = segment.seq.wrapping_add(1);
+            self.snd_una = segment.ack;
+            self.snd_wnd = segment.window as u32;
+            self.mss = segment.mss.map_or(DEFAULT_MSS, |m| m as usize).min(OUR_MSS);
+            self.state = State::Established;
+            self.retransmit_at = None;
+            self.retransmits = 0;
+            self.rto_ns = FIRST_RTO_NS;
+            self.send_ack();
+            self.output();
+            return;
+        }
+        if segment.flags & FLAG_ACK != 0 {
+            self.on_ack(segment.ack, segment.window);
+        }
+        // 順番通りのものだけ受け取り、それ以外はACKを返して送り直してもらう
+        let mut data = segment.data;
+        let mut seq = segment.seq;
+        if seq_lt(seq, self.rcv_nxt) {
+            let dup = self.rcv_nxt.wrapping_sub(seq) as usize;
+            if dup > data.len() || (dup == data.len() && segment.flags & FLAG_FIN == 0) {
+                if !data.is_empty() || segment.flags & FLAG_FIN != 0 {
+                    self.send_ack();
+                }
+                return;
+            }
+            data = &data[dup.min(data.len())..];
+            seq = self.rcv_nxt;
+        }
+        if seq != self.rcv_nxt {
+            self.send_ack();
+            return;
+        }
+        let receiving = matches!(
+            self.state,
+            State::Established | State::FinWait1 | State::FinWait2
+        );
+        let mut ack = false;
+        if receiving && !data.is_empty() {
+            let len = data.len().min(self.window() as usize);
+            self.recv_buf.extend(&data[..len]);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(len as u32);
+            data = &data[len..];
+            ack = true;
+        }
+        if receiving && segment.flags & FLAG_FIN != 0 && data.is_empty() {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.state = match self.state {
+                State::Established => State::CloseWait,
+                State::FinWait1 => State::Closing,
+                _ => State::Closed,
+            };
+            ack = true;
+        }
+        if ack {
+            self.send_ack();
+        }
+    }
+    fn on_ack(&mut self, ack: u32, window: u16) {
+        if !seq_le(self.snd_una, ack) || !seq_le(ack, self.snd_nxt) {
+            return;
+        }
+        self.snd_wnd = window as u32;
+        let acked = ack.wrapping_sub(self.snd_una) as usize;
+        if acked == 0 {
+            // 窓が開いただけかもしれない
+            self.output();
+            return;
+        }
+        let fin_acked = self.fin_seq().is_some_and(|fin| seq_lt(fin, ack));
+        self.send_buf.drain(..acked.min(self.send_buf.len()));
+        self.snd_una = ack;
+        self.retransmits = 0;
+        self.rto_ns = FIRST_RTO_NS;
+        self.retransmit_at = (self.snd_una != self.snd_nxt).then(|| time::now_ns() + self.rto_ns);
+        if fin_acked {
+            // FINの分は send_buf に入っていないので、ここで閉じる
+            self.fin_queued = false;
+            self.state = match self.state {
+                State::FinWait1 => State::FinWait2,
+                State::Closing | State::LastAck => State::Closed,
+                s => s,
+            };
+        }
+        self.output();
+    }
+}
+
+static CONNECTIONS: Mutex<Vec<Connection>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Handles a received segment. Segments of no connection are answered with
+/// RST.
+pub fn handle(iface: &Interface, src_mac: MacAddr, packet: &ipv4::Packet, segment: &Segment) {
+    let mut connections = CONNECTIONS.lock();
+    let conn = connections.iter_mut().find(|c| {
+        c.local == (packet.dst, segment.dst_port) && c.remote == (packet.src, segment.src_port)
+    });
+    match conn {
+        Some(conn) => conn.on_segment(segment),
+        None if segment.flags & FLAG_RST == 0 => {
+            drop(connections);
+            let (seq, ack, flags) = if segment.flags & FLAG_ACK != 0 {
+                (segment.ack, 0, FLAG_RST)
+            } else {
+                let len = segment.data.len() as u32
+                    + u32::from(segment.flags & FLAG_SYN != 0)
+                    + u32::from(segment.flags & FLAG_FIN != 0);
+                (0, segment.seq.wrapping_add(len), FLAG_RST | FLAG_ACK)
+            };
+            let reset = Segment {
+                src_port: segment.dst_port,
+                dst_port: segment.src_port,
+                seq,
+                ack,
+                flags,
+                window: 0,
+                mss: None,
+                data: &[],
+            };
+            let bytes = reset.to_bytes(packet.dst, packet.src);
+            let _ = ipv4::send_to(iface, src_mac, packet.src, ipv4::PROTOCOL_TCP, &bytes);
+        }
+        None => {}
+    }
+}
+
+/// Runs the retransmission timers. Called from net::poll().
+pub fn on_tick() {
+    let now = time::now_ns();
+    for conn in CONNECTIONS.lock().iter_mut() {
+        conn.on_timer(now);
+    }
+}
+
+pub struct TcpStream {
+    id: u64,
+}
+impl TcpStream {
+    /// Connects to port of dst, waiting up to timeout.
+    pub fn connect(dst: Ipv4Addr, port: u16, timeout: Duration) -> Result<Self> {
+        let (iface, next_hop) = net::route(dst).ok_or("Network is unreachable")?;
+        let dst_mac = arp::resolve(&iface, next_hop)?;
+        let local_addr = iface.config().addr;
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        {
+            let mut connections = CONNECTIONS.lock();
+            let n = EPHEMERAL_PORTS.len() as u64;
+            let start = rand::random_below(n);
+            let local_port = (0..n)
+                .map(|i| EPHEMERAL_PORTS.start + ((start + i) % n) as u16)
+                .find(|&p| !connections.iter().any(|c| c.local.1 == p))
+                .ok_or("TCP: no free port")?;
+            // ISSを推測されないように乱数にする (RFC 6528)
+            let iss = rand::random_u32();
+            let mut conn = Connection {
+                id,
+                iface,
+                dst_mac,
+                local: (local_addr, local_port),
+                remote: (dst, port),
+                state: State::SynSent,
+                iss,
+                snd_una: iss,
+                snd_nxt: iss,
+                snd_wnd: 0,
+                mss: DEFAULT_MSS,
+                rcv_nxt: 0,
+                send_buf: VecDeque::new(),
+                fin_queued: false,
+                recv_buf: VecDeque::new(),
+                advertised_window: 0,
+                rto_ns: FIRST_RTO_NS,
+                retransmit_at: None,
+                retransmits: 0,
+                error: None,
+            };
+            conn.output();
+            connections.push(conn);
+        }
+        let stream = Self { id };
+        stream.wait(timeout, |c| c.state != State::SynSent)?;
+        match stream.with(|c| c.error)? {
+            Some(e) => Err(e),
+            None => Ok(stream),
+        }
+    }
+    fn with<T>(&self, f: impl FnOnce(&mut Connection) -> T) -> Result<T> {
+        let mut connections = CONNECTIONS.lock();
+        let conn = connections
+            .iter_mut()
+            .find(|c| c.id == self.id)
+            .ok_or("TCP: connection is closed")?;
+        Ok(f(conn))
+    }
+    /// Waits until done(), handling the received frames meanwhile.
+    fn wait(&self, timeout: Duration, done: impl Fn(&Connection) -> bool) -> Result<()> {
+        let deadline = time::now_ns() + timeout.as_nanos() as u64;
+        loop {
+            net::poll();
+            if self.with(|c| done(c) || c.error.is_some())? {
+                return Ok(());
+            }
+            if time::now_ns() > deadline {
+                return Err("TCP: timed out");
+            }
+            core::hint::spin_loop();
+        }
+    }
+    /// Queues data and sends what the window allows. Does not wait for the
+    /// acknowledgement.
+    pub fn write_all(&self, data: &[u8]) -> Result<()> {
+        self.with(|c| {
+            if let Some(e) = c.error {
+                return Err(e);
+            }
+            if c.fin_queued || !matches!(c.state, State::Established | State::CloseWait) {
+                return Err("TCP: connection is closing");
+            }
+            c.send_buf.extend(data);
+            c.output();
+            Ok(())
+        })?
+    }
+    /// Reads the received data, waiting up to timeout for some. Returns 0
+    /// once the peer has closed its side.
+    pub fn read(&self, buf: &mut [u8], timeout: Duration) -> Result<usize> {
+        self.wait(timeout, |c| {
+            !c.recv_buf.is_empty()
+                || !matches!(
+                    c.state,
+                    State::Established | State::FinWait1 | State::FinWait2
+                )
+        })?;
+        self.with(|c| {
+            if c.recv_buf.is_empty() {
+                return c.error.map_or(Ok(0), Err);
+            }
+            let len = buf.len().min(c.recv_buf.len());
+            for (dst, src) in buf.iter_mut().zip(c.recv_buf.drain(..len)) {
+                *dst = src;
+            }
+            // 窓が閉じかけていたら、開いたことを知らせる
+            if (c.advertised_window as usize) < c.mss && c.window() as usize >= c.mss {
+                c.send_ack();
+            }
+            Ok(len)
+        })?
+    }
+    /// Sends FIN after the queued data and waits a little for the peer to
+    /// acknowledge it.
+    pub fn close(&self) -> Result<()> {
+        self.with(|c| {
+            c.state = match c.state {
+                State::Established => State::FinWait1,
+                State::CloseWait => State::LastAck,
+                _ => return,
+            };
+            c.fin_queued = true;
+            c.output();
+        })?;
+        self.wait(CLOSE_TIMEOUT, |c| {
+            matches!(c.state, State::FinWait2 | State::Closed)
+        })
+    }
+    pub fn state(&self) -> Option<State> {
+        self.with(|c| c.state).ok()
+    }
+}
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        let mut connections = CONNECTIONS.lock();
+        if let Some(i) = connections.iter().position(|c| c.id == self.id) {
+            let mut conn = connections.swap_remove(i);
+            // 閉じ終わっていなければ、相手が待ち続けないようRSTを送る
+            if !matches!(conn.state, State::Closed | State::FinWait2 | State::SynSent) {
+                conn.send_segment(conn.snd_nxt, FLAG_RST | FLAG_ACK, &[]);
+            }
+        }
+    }
+}
@@ -0,0 +1,242 @@
+//! UDP (RFC 768) and the sockets to send and receive datagrams.
+//!
+//! A socket is bound to a local port, and optionally to an interface so
+//! that it can be used before the interface has an address (DHCP).
+//! Received datagrams are queued on the socket until read.
+
+use crate::mutex::Mutex;
+use crate::net;
+use crate::net::arp;
+use crate::net::ethernet::MacAddr;
+use crate::net::ipv4;
+use crate::net::Interface;
+use crate::net::Ipv4Addr;
+use crate::rand;
+use crate::result::Result;
+use crate::time;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+pub const HEADER_SIZE: usize = 8;
+const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+/// Datagrams queued on a socket, the newer ones are dropped.
+const MAX_QUEUED: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header<'a> {
+    pub src_port: u16,
+    pub dst_port: u16,
+    pub data: &'a [u8],
+}
+impl<'a> Header<'a> {
+    /// Parses the datagram in packet, checking the checksum if it is set.
+    pub fn parse(packet: &ipv4::Packet<'a>) -> Option<Self> {
+        let data = packet.payload;
+        if data.len() < HEADER_SIZE {
+            return None;
+        }
+        let len = u16::from_be_bytes([data[4], data[5]]) as usize;
+        if len < HEADER_SIZE || data.len() < len {
+            return None;
+        }
+        let sum = u16::from_be_bytes([data[6], data[7]]);
+        if sum != 0 && checksum(packet.src, packet.dst, &data[..len]) != 0 {
+            return None;
+        }
+        Some(Self {
+            src_port: u16::from_be_bytes([data[0], data[1]]),
+            dst_port: u16::from_be_bytes([data[2], data[3]]),
+            data: &data[HEADER_SIZE..len],
+        })
+    }
+}
+
+fn checksum(src: Ipv4Addr, dst: Ipv4Addr, datagram: &[u8]) -> u16 {
+    ipv4::pseudo_header_checksum(src, dst, ipv4::PROTOCOL_UDP, datagram)
+}
+
+pub fn build(src: Ipv4Addr, src_port: u16, dst: Ipv4Addr, dst_port: u16, data: &[u8]) -> Vec<u8> {
+    let len = (HEADER_SIZE + data.len()) as u16;
+    let mut datagram = Vec::with_capacity(len as usize);
+    datagram.extend_from_slice(&src_port.to_be_bytes());
+    datagram.extend_from_slice(&dst_port.to_be_bytes());
+    datagram.extend_from_slice(&len.to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(data);
+    // 0 は「チェックサムなし」なので 0xffff として送る
+    let sum = match checksum(src, dst, &datagram) {
+        0 => 0xffff,
+        sum => sum,
+    };
+    datagram[6..8].copy_from_slice(&sum.to_be_bytes());
+    datagram
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datagram {
+    pub src: Ipv4Addr,
+    pub src_port: u16,
+    /// The destination address, which can be a broadcast address.
+    pub dst: Ipv4Addr,
+    pub data: Vec<u8>,
+}
+
+struct Binding {
+    id: u64,
+    port: u16,
+    iface: Option<Arc<Interface>>,
+    queue: VecDeque<Datagram>,
+}
+
+static BINDINGS: Mutex<Vec<Binding>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+fn same_iface(a: &Option<Arc<Interface>>, b: &Option<Arc<Interface>>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+        _ => true,
+    }
+}
+
+/// Handles a received datagram: queues it on the socket bound to the port.
+/// Returns false if no socket is bound to it.
+pub fn handle(iface: &Interface, packet: &ipv4::Packet, header: &Header) -> bool {
+    let mut bindings = BINDINGS.lock();
+    let Some(binding) = bindings.iter_mut().find(|b| {
+        b.port == header.dst_port
+            && b.iface
+                .as_ref()
+                .map_or(true, |i| core::ptr::eq(Arc::as_ptr(i), iface))
+    }) else {
+        return false;
+    };
+    if binding.queue.len() < MAX_QUEUED {
+        binding.queue.push_back(Datagram {
+            src: packet.src,
+            src_port: header.src_port,
+            dst: packet.dst,
+            data: header.data.to_vec(),
+        });
+    }
+    true
+}
+
+pub struct UdpSocket {
+    id: u64,
+    port: u16,
+    iface: Option<Arc<Interface>>,
+}
+impl UdpSocket {
+    /// Binds a socket to port on all the interfaces. Port 0 picks a free
+    /// ephemeral port.
+    pub fn bind(port: u16) -> Result<Self> {
+        Self::bind_with(None, port)
+    }
+    /// Binds a socket to port on iface only. The datagrams are sent from
+    /// iface even while it has no address.
+    pub fn bind_to_interface(iface: Arc<Interface>, port: u16) -> Result<Self> {
+        Self::bind_with(Some(iface), port)
+    }
+    fn bind_with(iface: Option<Arc<Interface>>, port: u16) -> Result<Self> {
+        let mut bindings = BINDINGS.lock();
+        let in_use = |port: u16| {
+            bindings
+                .iter()
+                .any(|b| b.port == port && same_iface(&b.iface, &iface))
+        };
+        let port = if port == 0 {
+            // 乱数から始めて空いているポートを探す
+            let n = EPHEMERAL_PORTS.len() as u64;
+            let start = rand::random_below(n);
+            (0..n)
+                .map(|i| *EPHEMERAL_PORTS.start() + ((start + i) % n) as u16)
+                .find(|&port| !in_use(port))
+                .ok_or("UDP: no free port")?
+        } else if in_use(port) {
+            return Err("UDP: port is in use");
+        } else {
+            port
+        };
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        bindings.push(Binding {
+            id,
+            port,
+            iface: iface.clone(),
+            queue: VecDeque::new(),
+        });
+        Ok(Self { id, port, iface })
+    }
+    pub fn local_port(&self) -> u16 {
+        self.port
+    }
+    pub fn send_to(&self, dst: Ipv4Addr, dst_port: u16, data: &[u8]) -> Result<()> {
+        let (iface, next_hop) = match &self.iface {
+            Some(iface) => {
+                let config = iface.config();
+                let next_hop = match config.gateway {
+                    Some(gateway)
+                        if dst != Ipv4Addr::BROADCAST
+                            && !dst.is_multicast()
+                            && !dst.is_in_same_network(&config.addr, config.prefix_len) =>
+                    {
+                        gateway
+                    }
+                    _ => dst,
+                };
+                (iface.clone(), next_hop)
+            }
+            None => net::route(dst).ok_or("Network is unreachable")?,
+        };
+        let dst_mac: MacAddr = arp::resolve(&iface, next_hop)?;
+        let src = iface.config().addr;
+        let datagram = build(src, self.port, dst, dst_port, data);
+        ipv4::send_to(&iface, dst_mac, dst, ipv4::PROTOCOL_UDP, &datagram)
+    }
+    /// Returns a received datagram, if any. Does not wait.
+    pub fn try_recv(&self) -> Option<Datagram> {
+        BINDINGS
+            .lock()
+            .iter_mut()
+            .find(|b| b.id == self.id)?
+            .queue
+            .pop_front()
+    }
+    /// Waits for a datagram up to timeout, handling the received frames
+    /// meanwhile. For the shell commands, which block the executor.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Datagram> {
+        let deadline = time::now_ns() + timeout.as_nanos() as u64;
+        loop {
+            net::poll();
+            if let Some(datagram) = self.try_recv() {
+                return Some(datagram);
+            }
+            if time::now_ns() > deadline {
+                return None;
+            }
+            core::hint::spin_loop();
+        }
+    }
+    /// Waits for a datagram up to timeout, letting the other async tasks
+    /// (including the one handling the received frames) run meanwhile.
+    pub async fn recv(&self, timeout: Duration) -> Option<Datagram> {
+        let deadline = time::now_ns() + timeout.as_nanos() as u64;
+        loop {
+            if let Some(datagram) = self.try_recv() {
+                return Some(datagram);
+            }
+            if time::now_ns() > deadline {
+                return None;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+}
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        BINDINGS.lock().retain(|b| b.id != self.id);
+    }
+}
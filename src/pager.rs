@@ -0,0 +1,119 @@
+//! A pager for the output of the shell commands on the screen console.
+//!
+//! Output is passed through until the screen is full, and the rest is held
+//! until the command returns. finish() then shows it page by page: Space
+//! shows the next page, Enter the next line, and q (or Ctrl+C) drops the rest.
+
+use crate::console;
+use crate::keymap::Key;
+use crate::tty::BACKSPACE;
+use alloc::string::String;
+use core::fmt;
+
+const MORE_PROMPT: &str = "-- More --";
+
+pub struct Pager<'a> {
+    out: &'a mut dyn fmt::Write,
+    /// None if the output is not paged.
+    size: Option<(usize, usize)>,
+    rows_left: usize,
+    column: usize,
+    at_line_start: bool,
+    in_escape: bool,
+    held: String,
+}
+impl<'a> Pager<'a> {
+    /// Pages the output to `out`, a screen of (columns, rows) characters.
+    /// Output is passed through as is if `size` is None.
+    pub fn new(out: &'a mut dyn fmt::Write, size: Option<(usize, usize)>) -> Self {
+        let mut pager = Self {
+            out,
+            size,
+            rows_left: 0,
+            column: 0,
+            at_line_start: true,
+            in_escape: false,
+            held: String::new(),
+        };
+        pager.next_page();
+        pager
+    }
+    fn next_page(&mut self) {
+        // 最後の行はプロンプト用に空けておく
+        self.rows_left = self
+            .size
+            .map_or(0, |(_, rows)| rows.saturating_sub(1).max(1));
+    }
+    /// Accounts for c and returns false if it does not fit in the page.
+    fn fits(&mut self, c: char) -> bool {
+        let Some((columns, _)) = self.size else {
+            return true;
+        };
+        if self.in_escape {
+            self.in_escape = !c.is_ascii_alphabetic();
+            if !self.in_escape && (c == 'J' || c == 'H') {
+                // 画面が消去されたら数え直す
+                self.next_page();
+                self.at_line_start = true;
+            }
+            return true;
+        }
+        match c {
+            '\x1b' => self.in_escape = true,
+            BACKSPACE => self.column = self.column.saturating_sub(1),
+            '\n' if !self.at_line_start => self.at_line_start = true,
+            c => {
+                if self.at_line_start || self.column >= columns {
+                    if self.rows_left == 0 {
+                        return false;
+                    }
+                    self.rows_left -= 1;
+                    self.column = 0;
+                    self.at_line_start = false;
+                }
+                if c == '\n' {
+                    self.at_line_start = true;
+                } else {
+                    self.column += 1;
+                }
+            }
+        }
+        true
+    }
+    /// Shows the held output page by page. Call after the command returns.
+    pub async fn finish(mut self) {
+        while !self.held.is_empty() {
+            let _ = self.out.write_str(MORE_PROMPT);
+            let key = loop {
+                if let Key::Char(c @ (' ' | '\n' | 'q' | '\x03')) = console::read_key().await {
+                    break c;
+                }
+            };
+            for _ in MORE_PROMPT.chars() {
+                let _ = self.out.write_char(BACKSPACE);
+            }
+            match key {
+                ' ' => self.next_page(),
+                '\n' => self.rows_left = 1,
+                _ => return,
+            }
+            let held = core::mem::take(&mut self.held);
+            let _ = fmt::Write::write_str(&mut self, &held);
+        }
+    }
+}
+impl fmt::Write for Pager<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if !self.held.is_empty() {
+            self.held.push_str(s);
+            return Ok(());
+        }
+        for (i, c) in s.char_indices() {
+            if !self.fits(c) {
+                self.held.push_str(&s[i..]);
+                return self.out.write_str(&s[..i]);
+            }
+        }
+        self.out.write_str(s)
+    }
+}
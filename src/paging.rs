@@ -0,0 +1,645 @@
+//! Page tables for user mode.
+//!
+//! The kernel keeps running on the identity map built by the firmware, in
+//! a copy of its PML4 so that single pages can be unmapped (e.g. the guard
+//! pages of the stacks): the tables on the way to such a page are copied,
+//! and huge pages split, leaving the ones of the firmware untouched. The
+//! same way, ranges of the kernel can be made read-only or non-executable
+//! (see wx.rs). promote_kernel_pages() goes the other way and turns the
+//! tables of 4 KiB pages that map 2 MiB of contiguous memory alike (RAM,
+//! the framebuffer, the sections of the kernel) back into 2 MiB pages, to
+//! save TLB entries and tables; they are split again when a single page
+//! has to change.
+//! An AddressSpace is a copy of the kernel's PML4 with one more slot for
+//! the user pages, so the kernel stays mapped (as supervisor only) while a
+//! user program runs. The page tables and the frames are taken from the
+//! heap, whose addresses are also physical ones thanks to the identity map.
+
+use crate::arch::clflush;
+use crate::arch::invlpg;
+use crate::arch::read_cr3;
+use crate::arch::read_msr;
+use crate::arch::write_cr3;
+use crate::arch::write_msr;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::uefi;
+use crate::uefi::AllocateType;
+use crate::uefi::EfiMemoryType;
+use alloc::alloc::alloc_zeroed;
+use alloc::alloc::dealloc;
+use alloc::alloc::Layout;
+use alloc::vec::Vec;
+use core::arch::x86_64::__cpuid;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+pub const PAGE_SIZE: u64 = 4096;
+
+/// The PML4 slot for the user pages. 0 is where the physical memory is
+/// mapped, and MMIO may be mapped in the slots right above it.
+const USER_PML4_INDEX: usize = 0xfe;
+/// The user pages are in [USER_START, USER_END).
+pub const USER_START: u64 = (USER_PML4_INDEX as u64) << 39;
+pub const USER_END: u64 = USER_START + (1 << 39);
+
+const PTE_PRESENT: u64 = 1 << 0;
+const PTE_WRITABLE: u64 = 1 << 1;
+const PTE_USER: u64 = 1 << 2;
+const PTE_WRITE_THROUGH: u64 = 1 << 3;
+const PTE_CACHE_DISABLE: u64 = 1 << 4;
+const PTE_ACCESSED: u64 = 1 << 5;
+const PTE_DIRTY: u64 = 1 << 6;
+const PTE_HUGE: u64 = 1 << 7;
+/// The PAT bit of a 4 KiB page, which is bit 12 for huge pages.
+const PTE_PAT: u64 = 1 << 7;
+const PTE_HUGE_PAT: u64 = 1 << 12;
+const PTE_NO_EXECUTE: u64 = 1 << 63;
+const PTE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+pub const ENTRIES: usize = 512;
+const HUGE_PAGE_SIZE: u64 = 1 << 21;
+
+const MSR_EFER: u32 = 0xc000_0080;
+const EFER_NXE: u64 = 1 << 11;
+const CPUID_EXT_FEATURES: u32 = 0x8000_0001;
+const CPUID_EXT_EDX_NX: u32 = 1 << 20;
+
+static KERNEL_CR3: AtomicU64 = AtomicU64::new(0);
+/// The kernel's page tables that are copies, and can be changed.
+static OWNED_TABLES: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+/// A page for the kernel's PML4, below 4 GiB since the AP trampoline loads
+/// CR3 in 32-bit mode (see smp.rs).
+fn alloc_low_page() -> Result<u64> {
+    let Some(efi_system_table) = uefi::system_table() else {
+        return alloc_page();
+    };
+    let page = efi_system_table.boot_services.allocate_pages(
+        AllocateType::MaxAddress(0xffff_ffff),
+        EfiMemoryType::LOADER_DATA,
+        1,
+    )?;
+    // SAFETY: the page is allocated for the table
+    unsafe { core::ptr::write_bytes(page as *mut u8, 0, PAGE_SIZE as usize) };
+    Ok(page)
+}
+
+/// Switches to a copy of the page table of the firmware, which becomes the
+/// one for the kernel. Call before the interrupts are taken over, as it may
+/// ask the firmware for a page.
+pub fn init() {
+    let firmware = read_cr3();
+    let Ok(pml4) = alloc_low_page() else {
+        // コピーできなければファームウェアの表をそのまま使う (ページ単位の変更はできない)
+        KERNEL_CR3.store(firmware, Ordering::SeqCst);
+        return;
+    };
+    // SAFETY: the tables are identity mapped, and pml4 is a fresh page
+    unsafe {
+        *table(pml4) = *table(firmware);
+        write_cr3(pml4 | (firmware & !PTE_ADDR_MASK));
+    }
+    OWNED_TABLES.lock().push(pml4);
+    KERNEL_CR3.store(read_cr3(), Ordering::SeqCst);
+}
+
+/// The CR3 value for kernel tasks, or 0 if init() is not called yet.
+pub fn kernel_cr3() -> u64 {
+    KERNEL_CR3.load(Ordering::SeqCst)
+}
+
+fn page_layout() -> Layout {
+    Layout::from_size_align(PAGE_SIZE as usize, PAGE_SIZE as usize).unwrap()
+}
+
+fn alloc_page() -> Result<u64> {
+    // SAFETY: the layout has a non-zero size
+    let p = unsafe { alloc_zeroed(page_layout()) };
+    if p.is_null() {
+        return Err("Out of memory for a page");
+    }
+    Ok(p as u64)
+}
+
+fn table(addr: u64) -> *mut [u64; ENTRIES] {
+    (addr & PTE_ADDR_MASK) as *mut [u64; ENTRIES]
+}
+
+fn indices(va: u64) -> [usize; 4] {
+    [39, 30, 21, 12].map(|shift| ((va >> shift) & 0x1ff) as usize)
+}
+
+/// Makes the table that the entry at slot (of level 0 for the PML4 to 3)
+/// points to one of the kernel's, copying it, or splitting the huge page
+/// of the entry into a table of smaller pages with the same attributes.
+fn own_table(slot: &mut u64, level: usize, owned: &mut Vec<u64>) -> Result<()> {
+    let entry = *slot;
+    if entry & PTE_PRESENT == 0 {
+        return Err("Address is not mapped");
+    }
+    let mut entries = [0u64; ENTRIES];
+    if level > 0 && entry & PTE_HUGE != 0 {
+        // 1GiBのページは2MiBの、2MiBのページは4KiBのページに分ける
+        let (base, size) = match level {
+            1 => (entry & PTE_ADDR_MASK & !((1 << 30) - 1), 1 << 21),
+            _ => (entry & PTE_ADDR_MASK & !((1 << 21) - 1), PAGE_SIZE),
+        };
+        let mut flags = entry & !PTE_ADDR_MASK;
+        if level == 2 {
+            flags &= !PTE_HUGE;
+            if entry & PTE_HUGE_PAT != 0 {
+                flags |= PTE_PAT;
+            }
+        } else {
+            flags |= entry & PTE_HUGE_PAT;
+        }
+        for (i, e) in entries.iter_mut().enumerate() {
+            *e = (base + i as u64 * size) | flags;
+        }
+    } else if owned.contains(&(entry & PTE_ADDR_MASK)) {
+        return Ok(());
+    } else {
+        // SAFETY: the tables are identity mapped, and entry is present
+        entries = unsafe { *table(entry) };
+    }
+    let page = alloc_page()?;
+    // SAFETY: page is a fresh page
+    unsafe { *table(page) = entries };
+    owned.push(page);
+    *slot = page | (entry & (0xfff | PTE_NO_EXECUTE) & !PTE_HUGE);
+    Ok(())
+}
+
+/// Changes the entry of the 4 KiB page at va in the kernel's page table.
+fn update_kernel_page(va: u64, f: impl FnOnce(&mut u64)) -> Result<()> {
+    let mut owned = OWNED_TABLES.lock();
+    let mut entry = kernel_cr3();
+    if !owned.contains(&(entry & PTE_ADDR_MASK)) {
+        return Err("The kernel is on the page table of the firmware");
+    }
+    for (level, i) in indices(va).into_iter().enumerate() {
+        // SAFETY: the tables on the way are the kernel's (made so below)
+        let slot = unsafe { &mut (*table(entry))[i] };
+        if level == 3 {
+            f(slot);
+            break;
+        }
+        own_table(slot, level, &mut owned)?;
+        entry = *slot;
+    }
+    invlpg(va);
+    Ok(())
+}
+
+/// Unmaps the page at va from the kernel, e.g. as a guard page, so that
+/// any access to it faults.
+pub fn unmap_kernel_page(va: u64) -> Result<()> {
+    update_kernel_page(va, |e| *e &= !PTE_PRESENT)
+}
+
+/// Maps the page at va again after unmap_kernel_page().
+pub fn remap_kernel_page(va: u64) -> Result<()> {
+    update_kernel_page(va, |e| *e |= PTE_PRESENT)
+}
+
+/// What the kernel may do with its pages, besides reading them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Protection {
+    pub writable: bool,
+    pub executable: bool,
+}
+impl Protection {
+    pub const READ_ONLY: Self = Self {
+        writable: false,
+        executable: false,
+    };
+    pub const READ_WRITE: Self = Self {
+        writable: true,
+        executable: false,
+    };
+    pub const READ_EXECUTE: Self = Self {
+        writable: false,
+        executable: true,
+    };
+}
+
+/// Turns on the NX bit (EFER.NXE), without which bit 63 of the entries is
+/// reserved. Fails if the CPU does not have it.
+pub fn enable_no_execute() -> Result<()> {
+    // SAFETY: CPUID is always available on x86_64, and the extended leaves
+    // on every 64-bit CPU
+    if unsafe { __cpuid(CPUID_EXT_FEATURES) }.edx & CPUID_EXT_EDX_NX == 0 {
+        return Err("The CPU does not have the NX bit");
+    }
+    // SAFETY: EFER exists on every 64-bit CPU, and NXE only makes bit 63 of
+    // the entries meaningful, which is 0 in all of them until now
+    unsafe { write_msr(MSR_EFER, read_msr(MSR_EFER) | EFER_NXE) };
+    Ok(())
+}
+
+fn no_execute_enabled() -> bool {
+    // SAFETY: EFER exists on every 64-bit CPU
+    let efer = unsafe { read_msr(MSR_EFER) };
+    efer & EFER_NXE != 0
+}
+
+fn set_protection(slot: &mut u64, protection: Protection) {
+    if protection.writable {
+        *slot |= PTE_WRITABLE;
+    } else {
+        *slot &= !PTE_WRITABLE;
+    }
+    if protection.executable || !no_execute_enabled() {
+        *slot &= !PTE_NO_EXECUTE;
+    } else {
+        *slot |= PTE_NO_EXECUTE;
+    }
+}
+
+/// Moves the restrictions of the entry at slot, which points to a table of
+/// the kernel, down to the entries of that table, so that they can be
+/// lifted for some of them.
+fn push_down_protection(slot: &mut u64) {
+    let entry = *slot;
+    // SAFETY: the table is the kernel's, and identity mapped
+    for e in unsafe { (*table(entry)).iter_mut() } {
+        if *e & PTE_PRESENT == 0 {
+            continue;
+        }
+        if entry & PTE_WRITABLE == 0 {
+            *e &= !PTE_WRITABLE;
+        }
+        *e |= entry & PTE_NO_EXECUTE;
+    }
+    *slot = (entry | PTE_WRITABLE) & !PTE_NO_EXECUTE;
+}
+
+/// Sets the protection of [start, end) in the table at table_addr of level
+/// (0 for the PML4), which must be one of the kernel's.
+fn protect_in(
+    table_addr: u64,
+    level: usize,
+    start: u64,
+    end: u64,
+    protection: Protection,
+    owned: &mut Vec<u64>,
+) -> Result<()> {
+    let shift = 39 - 9 * level as u64;
+    let span = 1u64 << shift;
+    let mut va = start;
+    while va < end {
+        let entry_start = va & !(span - 1);
+        let entry_end = entry_start.saturating_add(span);
+        // SAFETY: the table is the kernel's, and identity mapped
+        let slot = unsafe { &mut (*table(table_addr))[((va >> shift) & 0x1ff) as usize] };
+        if *slot & PTE_PRESENT != 0 {
+            let leaf = level == 3 || (level > 0 && *slot & PTE_HUGE != 0);
+            let covered = start <= entry_start && entry_end <= end;
+            // 上位のエントリで書き込みや実行を禁止すれば下の全ページに効くが、
+            // 許可するにはページ自身のエントリまで降りる必要がある
+            if covered && (leaf || !protection.executable) {
+                set_protection(slot, protection);
+            } else {
+                own_table(slot, level, owned)?;
+                push_down_protection(slot);
+                protect_in(*slot, level + 1, va, end.min(entry_end), protection, owned)?;
+            }
+        }
+        va = entry_end;
+    }
+    Ok(())
+}
+
+/// Sets the protection of the kernel's pages over [start, end). Pages that
+/// are not mapped are skipped. Without the NX bit (see enable_no_execute())
+/// everything stays executable.
+pub fn protect_kernel_range(start: u64, end: u64, protection: Protection) -> Result<()> {
+    let mut owned = OWNED_TABLES.lock();
+    let pml4 = kernel_cr3();
+    if !owned.contains(&(pml4 & PTE_ADDR_MASK)) {
+        return Err("The kernel is on the page table of the firmware");
+    }
+    let start = start & !(PAGE_SIZE - 1);
+    let end = end.div_ceil(PAGE_SIZE) * PAGE_SIZE;
+    protect_in(pml4, 0, start, end, protection, &mut owned)?;
+    // 範囲が広いとinvlpgでは追いつかないので、TLBを丸ごと捨てる
+    // SAFETY: CR3 stays the same, which only flushes the TLB
+    unsafe { write_cr3(read_cr3()) };
+    Ok(())
+}
+
+/// The protection of the page at va for the kernel, combining the entries
+/// on the way, or None if it is not mapped.
+pub fn kernel_page_protection(va: u64) -> Option<Protection> {
+    let mut entry = kernel_cr3();
+    if entry == 0 {
+        return None;
+    }
+    let no_execute = no_execute_enabled();
+    let mut protection = Protection {
+        writable: true,
+        executable: true,
+    };
+    for (level, i) in indices(va).into_iter().enumerate() {
+        // SAFETY: the tables are identity mapped, and entry is present
+        entry = unsafe { (*table(entry))[i] };
+        if entry & PTE_PRESENT == 0 {
+            return None;
+        }
+        protection.writable &= entry & PTE_WRITABLE != 0;
+        protection.executable &= !no_execute || entry & PTE_NO_EXECUTE == 0;
+        if level > 0 && entry & PTE_HUGE != 0 {
+            break;
+        }
+    }
+    Some(protection)
+}
+
+/// The entry of a 2 MiB page that maps the same as the entries of a table
+/// of 4 KiB pages, if they map 2 MiB of aligned, contiguous memory with the
+/// same attributes.
+pub fn huge_entry_for(entries: &[u64]) -> Option<u64> {
+    // AとDはCPUが勝手に立てるので、比べずにまとめる
+    let attributes = |e: u64| e & !PTE_ADDR_MASK & !(PTE_ACCESSED | PTE_DIRTY);
+    let first = *entries.first()?;
+    let base = first & PTE_ADDR_MASK;
+    if entries.len() != ENTRIES || first & PTE_PRESENT == 0 || base % HUGE_PAGE_SIZE != 0 {
+        return None;
+    }
+    let mut used = 0;
+    for (i, e) in entries.iter().enumerate() {
+        if e & PTE_ADDR_MASK != base + i as u64 * PAGE_SIZE || attributes(*e) != attributes(first) {
+            return None;
+        }
+        used |= e & (PTE_ACCESSED | PTE_DIRTY);
+    }
+    let pat = if first & PTE_PAT != 0 {
+        PTE_HUGE_PAT
+    } else {
+        0
+    };
+    Some(base | (attributes(first) & !PTE_PAT) | used | PTE_HUGE | pat)
+}
+
+/// The 2 MiB-aligned addresses below the user range whose tables of 4 KiB
+/// pages could be 2 MiB pages.
+fn huge_page_candidates(pml4: u64) -> Vec<u64> {
+    let present = |e: u64| e & PTE_PRESENT != 0 && e & PTE_HUGE == 0;
+    let mut candidates = Vec::new();
+    // SAFETY: the tables are identity mapped, and only present ones are read
+    unsafe {
+        for (i4, e4) in (*table(pml4)).iter().enumerate().take(USER_PML4_INDEX) {
+            if *e4 & PTE_PRESENT == 0 {
+                continue;
+            }
+            for (i3, e3) in (*table(*e4)).iter().enumerate() {
+                if !present(*e3) {
+                    continue;
+                }
+                for (i2, e2) in (*table(*e3)).iter().enumerate() {
+                    if present(*e2) && huge_entry_for(&*table(*e2)).is_some() {
+                        candidates.push((i4 << 39 | i3 << 30 | i2 << 21) as u64);
+                    }
+                }
+            }
+        }
+    }
+    candidates
+}
+
+/// Replaces the table of 4 KiB pages at va with a 2 MiB page, making the
+/// tables on the way the kernel's.
+fn promote(pml4: u64, va: u64, owned: &mut Vec<u64>) -> Result<bool> {
+    let [i4, i3, i2, _] = indices(va);
+    // SAFETY: the tables on the way are the kernel's (made so below)
+    unsafe {
+        let slot = &mut (*table(pml4))[i4];
+        own_table(slot, 0, owned)?;
+        let slot = &mut (*table(*slot))[i3];
+        own_table(slot, 1, owned)?;
+        let slot = &mut (*table(*slot))[i2];
+        let pt = *slot & PTE_ADDR_MASK;
+        let Some(mut huge) = huge_entry_for(&*table(pt)) else {
+            return Ok(false);
+        };
+        // 中間のエントリの制限は大きいページに引き継ぐ
+        huge &= !((PTE_WRITABLE | PTE_USER) & !*slot);
+        huge |= *slot & PTE_NO_EXECUTE;
+        *slot = huge;
+        if let Some(i) = owned.iter().position(|t| *t == pt) {
+            owned.swap_remove(i);
+            dealloc(pt as *mut u8, page_layout());
+        }
+    }
+    Ok(true)
+}
+
+/// Turns the tables of 4 KiB pages in the kernel's page table that could
+/// be 2 MiB pages into them, and returns how many. Call before the APs are
+/// started, as only the TLB of this CPU is flushed.
+pub fn promote_kernel_pages() -> usize {
+    let mut owned = OWNED_TABLES.lock();
+    let pml4 = kernel_cr3();
+    if !owned.contains(&(pml4 & PTE_ADDR_MASK)) {
+        return 0;
+    }
+    let promoted = huge_page_candidates(pml4)
+        .into_iter()
+        .filter(|va| promote(pml4, *va, &mut owned).unwrap_or(false))
+        .count();
+    if promoted > 0 {
+        // SAFETY: CR3 stays the same, which only flushes the TLB
+        unsafe { write_cr3(read_cr3()) };
+    }
+    promoted
+}
+
+/// Makes the kernel's page at va uncached (or cached again), e.g. for a
+/// DMA buffer. The lines of the page are written back first, so that no
+/// stale copy is left in the caches.
+pub fn set_kernel_page_uncached(va: u64, uncached: bool) -> Result<()> {
+    const CACHE_LINE: u64 = 64;
+    let va = va & !(PAGE_SIZE - 1);
+    for line in (va..va + PAGE_SIZE).step_by(CACHE_LINE as usize) {
+        clflush(line);
+    }
+    update_kernel_page(va, |e| {
+        if uncached {
+            *e |= PTE_CACHE_DISABLE | PTE_WRITE_THROUGH;
+        } else {
+            *e &= !(PTE_CACHE_DISABLE | PTE_WRITE_THROUGH);
+        }
+    })
+}
+
+/// Finds the page that maps va in the table at cr3, if user mode can access
+/// it (and write to it if write is true).
+fn translate_user(cr3: u64, va: u64, write: bool) -> Option<u64> {
+    if !(USER_START..USER_END).contains(&va) {
+        return None;
+    }
+    let mut entry = cr3;
+    for (level, i) in indices(va).into_iter().enumerate() {
+        // SAFETY: the tables are identity mapped, and entry is present
+        entry = unsafe { (*table(entry))[i] };
+        if entry & PTE_PRESENT == 0 || entry & PTE_USER == 0 {
+            return None;
+        }
+        if level < 3 && entry & PTE_HUGE != 0 {
+            return None;
+        }
+        if write && entry & PTE_WRITABLE == 0 {
+            return None;
+        }
+    }
+    Some(entry & PTE_ADDR_MASK)
+}
+
+/// Copies len bytes at va in the current address space, checking that user
+/// mode can read all of them.
+pub fn copy_from_user(va: u64, len: usize) -> Result<Vec<u8>> {
+    let cr3 = read_cr3();
+    let mut data = Vec::with_capacity(len);
+    let end = va.checked_add(len as u64).ok_or("Bad user address")?;
+    let mut addr = va;
+    while addr < end {
+        let page = translate_user(cr3, addr, false).ok_or("Bad user address")?;
+        let offset = addr % PAGE_SIZE;
+        let n = (PAGE_SIZE - offset).min(end - addr);
+        // SAFETY: the page is mapped and identity mapped for the kernel
+        let src = unsafe { core::slice::from_raw_parts((page + offset) as *const u8, n as usize) };
+        data.extend_from_slice(src);
+        addr += n;
+    }
+    Ok(data)
+}
+
+/// Copies data to va in the current address space, checking that user mode
+/// can write all of it.
+pub fn copy_to_user(va: u64, data: &[u8]) -> Result<()> {
+    let cr3 = read_cr3();
+    va.checked_add(data.len() as u64)
+        .ok_or("Bad user address")?;
+    let mut addr = va;
+    let mut data = data;
+    while !data.is_empty() {
+        let page = translate_user(cr3, addr, true).ok_or("Bad user address")?;
+        let offset = addr % PAGE_SIZE;
+        let n = ((PAGE_SIZE - offset) as usize).min(data.len());
+        // SAFETY: the page is mapped as writable for user mode
+        unsafe {
+            core::ptr::copy_nonoverlapping(data.as_ptr(), (page + offset) as *mut u8, n);
+        }
+        data = &data[n..];
+        addr += n as u64;
+    }
+    Ok(())
+}
+
+/// The page tables of a user program.
+pub struct AddressSpace {
+    pml4: u64,
+    /// The page tables and the frames below pml4, freed on drop.
+    pages: Vec<u64>,
+}
+impl AddressSpace {
+    pub fn new() -> Result<Self> {
+        let kernel = kernel_cr3();
+        if kernel == 0 {
+            return Err("paging::init() is not called yet");
+        }
+        // SAFETY: the kernel's PML4 is identity mapped and alive forever
+        let mut entries = unsafe { *table(kernel) };
+        if entries[USER_PML4_INDEX] & PTE_PRESENT != 0 {
+            return Err("The user address range is used by the kernel");
+        }
+        // カーネルの領域はユーザーモードから触れないようにする
+        for e in entries.iter_mut() {
+            *e &= !PTE_USER;
+        }
+        let pml4 = alloc_page()?;
+        // SAFETY: pml4 is a fresh page
+        unsafe { *table(pml4) = entries };
+        Ok(Self {
+            pml4,
+            pages: Vec::new(),
+        })
+    }
+    pub fn cr3(&self) -> u64 {
+        self.pml4
+    }
+    /// The memory used by the user pages and their page tables, in bytes.
+    pub fn size(&self) -> u64 {
+        (self.pages.len() as u64 + 1) * PAGE_SIZE
+    }
+    fn alloc(&mut self) -> Result<u64> {
+        let page = alloc_page()?;
+        self.pages.push(page);
+        Ok(page)
+    }
+    /// Maps a zeroed page at va if there is none.
+    fn map_page(&mut self, va: u64, writable: bool) -> Result<()> {
+        if !(USER_START..USER_END).contains(&va) {
+            return Err("Address is out of the user range");
+        }
+        let mut entry = self.pml4;
+        for (level, i) in indices(va).into_iter().enumerate() {
+            // SAFETY: the tables below pml4 are allocated by this address space
+            let slot = unsafe { &mut (*table(entry))[i] };
+            if *slot & PTE_PRESENT == 0 {
+                // 中間のテーブルは書き込み可能にしておき、最下位のエントリで制限する
+                *slot = self.alloc()? | PTE_PRESENT | PTE_USER | PTE_WRITABLE;
+                if level == 3 && !writable {
+                    *slot &= !PTE_WRITABLE;
+                }
+            } else if level == 3 && writable {
+                *slot |= PTE_WRITABLE;
+            }
+            entry = *slot;
+        }
+        Ok(())
+    }
+    /// Maps zeroed pages over [va, va + len).
+    pub fn map(&mut self, va: u64, len: u64, writable: bool) -> Result<()> {
+        let end = va
+            .checked_add(len)
+            .ok_or("Address is out of the user range")?;
+        let mut page = va & !(PAGE_SIZE - 1);
+        while page < end {
+            self.map_page(page, writable)?;
+            page += PAGE_SIZE;
+        }
+        Ok(())
+    }
+    /// Copies data to va, which must have been mapped by map().
+    pub fn write(&mut self, va: u64, data: &[u8]) -> Result<()> {
+        let mut addr = va;
+        let mut data = data;
+        while !data.is_empty() {
+            let page = translate_user(self.pml4, addr, false).ok_or("Address is not mapped")?;
+            let offset = addr % PAGE_SIZE;
+            let n = ((PAGE_SIZE - offset) as usize).min(data.len());
+            // SAFETY: the page is a frame of this address space
+            unsafe {
+                core::ptr::copy_nonoverlapping(data.as_ptr(), (page + offset) as *mut u8, n);
+            }
+            data = &data[n..];
+            addr += n as u64;
+        }
+        Ok(())
+    }
+}
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        assert_ne!(
+            read_cr3() & PTE_ADDR_MASK,
+            self.pml4,
+            "An address space in use is dropped"
+        );
+        for page in self.pages.drain(..).chain(core::iter::once(self.pml4)) {
+            // SAFETY: the pages are allocated by alloc_page() with the layout
+            unsafe { dealloc(page as *mut u8, page_layout()) }
+        }
+    }
+}
@@ -0,0 +1,506 @@
+//! PCI bus enumeration.
+//!
+//! init() scans every bus/device/function and records the devices found,
+//! with their class codes and BARs. Drivers look their devices up with
+//! find() or find_by_class().
+//!
+//! The configuration space is accessed through the memory-mapped ECAM area
+//! if use_ecam() has found one in the ACPI MCFG table, and through the
+//! configuration mechanism #1 (ports 0xCF8/0xCFC) otherwise. Only ECAM can
+//! reach the extended configuration space (offsets 0x100-0xfff).
+
+use crate::acpi::Acpi;
+use crate::arch::read_io_port_u32;
+use crate::arch::without_interrupts;
+use crate::arch::write_io_port_u32;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ptr::read_volatile;
+use core::ptr::write_volatile;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::Ordering;
+
+const CONFIG_ADDRESS: u16 = 0xcf8;
+const CONFIG_DATA: u16 = 0xcfc;
+const LEGACY_CONFIG_SIZE: u16 = 0x100;
+const ECAM_CONFIG_SIZE: u16 = 0x1000;
+
+const REG_VENDOR_ID: u16 = 0x00;
+const REG_COMMAND: u16 = 0x04;
+const REG_STATUS: u16 = 0x06;
+const REG_CLASS: u16 = 0x08;
+const REG_HEADER_TYPE: u16 = 0x0e;
+const REG_BAR0: u16 = 0x10;
+const REG_CAPABILITIES: u16 = 0x34;
+const REG_INTERRUPT_LINE: u16 = 0x3c;
+
+const COMMAND_IO_SPACE: u16 = 1 << 0;
+const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+const COMMAND_BUS_MASTER: u16 = 1 << 2;
+
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+const HEADER_TYPE_MULTI_FUNCTION: u8 = 0x80;
+const HEADER_TYPE_MASK: u8 = 0x7f;
+const HEADER_TYPE_NORMAL: u8 = 0x00;
+const HEADER_TYPE_BRIDGE: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct BusDeviceFunction {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+impl BusDeviceFunction {
+    pub fn new(bus: u8, device: u8, function: u8) -> Result<Self> {
+        if device >= 32 || function >= 8 {
+            return Err("Invalid PCI device or function number");
+        }
+        Ok(Self {
+            bus,
+            device,
+            function,
+        })
+    }
+}
+impl fmt::Display for BusDeviceFunction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+/// The ECAM area of segment 0 (0 if not found) and the buses it covers.
+static ECAM_BASE: AtomicU64 = AtomicU64::new(0);
+static ECAM_START_BUS: AtomicU8 = AtomicU8::new(0);
+static ECAM_END_BUS: AtomicU8 = AtomicU8::new(0);
+
+/// Switches to ECAM if the MCFG table has an area for segment 0. Returns
+/// the base address of the area.
+pub fn use_ecam(acpi: &Acpi) -> Result<u64> {
+    let entry = acpi
+        .mcfg()
+        .ok_or("MCFG table not found")?
+        .entries()
+        .into_iter()
+        .find(|e| e.segment == 0 && e.base != 0 && e.start_bus <= e.end_bus)
+        .ok_or("No ECAM area for PCI segment 0")?;
+    ECAM_START_BUS.store(entry.start_bus, Ordering::Relaxed);
+    ECAM_END_BUS.store(entry.end_bus, Ordering::Relaxed);
+    ECAM_BASE.store(entry.base, Ordering::Release);
+    Ok(entry.base)
+}
+
+/// The address of the register in the ECAM area, if it covers the bus.
+fn ecam_address(bdf: BusDeviceFunction, offset: u16) -> Option<*mut u32> {
+    let base = ECAM_BASE.load(Ordering::Acquire);
+    let start = ECAM_START_BUS.load(Ordering::Relaxed);
+    if base == 0 || bdf.bus < start || bdf.bus > ECAM_END_BUS.load(Ordering::Relaxed) {
+        return None;
+    }
+    let ofs = ((bdf.bus - start) as u64) << 20
+        | (bdf.device as u64) << 15
+        | (bdf.function as u64) << 12
+        | (offset & 0xffc) as u64;
+    Some((base + ofs) as *mut u32)
+}
+
+/// True if the extended configuration space of the device is reachable.
+pub fn has_extended_config(bdf: BusDeviceFunction) -> bool {
+    ecam_address(bdf, 0).is_some()
+}
+
+fn config_address(bdf: BusDeviceFunction, offset: u16) -> u32 {
+    (1 << 31)
+        | (bdf.bus as u32) << 16
+        | (bdf.device as u32) << 11
+        | (bdf.function as u32) << 8
+        | (offset & 0xfc) as u32
+}
+
+/// Reads a register. Registers that are not reachable read as all ones.
+pub fn read_config_u32(bdf: BusDeviceFunction, offset: u16) -> u32 {
+    if offset >= ECAM_CONFIG_SIZE {
+        return !0;
+    }
+    if let Some(p) = ecam_address(bdf, offset) {
+        // SAFETY: the ECAM area is identity mapped, and p is in it
+        return unsafe { read_volatile(p) };
+    }
+    if offset >= LEGACY_CONFIG_SIZE {
+        return !0;
+    }
+    // アドレスとデータの書き込みの間に割り込まれると別のレジスタを読んでしまう
+    without_interrupts(|| {
+        write_io_port_u32(CONFIG_ADDRESS, config_address(bdf, offset));
+        read_io_port_u32(CONFIG_DATA)
+    })
+}
+
+pub fn write_config_u32(bdf: BusDeviceFunction, offset: u16, value: u32) {
+    if offset >= ECAM_CONFIG_SIZE {
+        return;
+    }
+    if let Some(p) = ecam_address(bdf, offset) {
+        // SAFETY: the ECAM area is identity mapped, and p is in it
+        unsafe { write_volatile(p, value) };
+        return;
+    }
+    if offset >= LEGACY_CONFIG_SIZE {
+        return;
+    }
+    without_interrupts(|| {
+        write_io_port_u32(CONFIG_ADDRESS, config_address(bdf, offset));
+        write_io_port_u32(CONFIG_DATA, value);
+    })
+}
+
+pub fn read_config_u16(bdf: BusDeviceFunction, offset: u16) -> u16 {
+    (read_config_u32(bdf, offset) >> ((offset & 2) * 8)) as u16
+}
+
+pub fn read_config_u8(bdf: BusDeviceFunction, offset: u16) -> u8 {
+    (read_config_u32(bdf, offset) >> ((offset & 3) * 8)) as u8
+}
+
+pub fn write_config_u16(bdf: BusDeviceFunction, offset: u16, value: u16) {
+    let shift = (offset & 2) * 8;
+    let old = read_config_u32(bdf, offset) & !(0xffff << shift);
+    write_config_u32(bdf, offset, old | (value as u32) << shift);
+}
+
+/// Lets the device access the memory (DMA), and enables its I/O and memory
+/// spaces.
+pub fn enable_bus_master(bdf: BusDeviceFunction) {
+    let command = read_config_u16(bdf, REG_COMMAND);
+    write_config_u16(
+        bdf,
+        REG_COMMAND,
+        command | COMMAND_BUS_MASTER | COMMAND_MEMORY_SPACE | COMMAND_IO_SPACE,
+    );
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        addr: u64,
+        size: u64,
+        is_64bit: bool,
+        prefetchable: bool,
+    },
+    Io {
+        port: u16,
+        size: u16,
+    },
+}
+impl Bar {
+    /// The physical address of a memory BAR.
+    pub fn memory_addr(&self) -> Option<u64> {
+        match self {
+            Bar::Memory { addr, .. } => Some(*addr),
+            Bar::Io { .. } => None,
+        }
+    }
+    pub fn io_port(&self) -> Option<u16> {
+        match self {
+            Bar::Io { port, .. } => Some(*port),
+            Bar::Memory { .. } => None,
+        }
+    }
+}
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bar::Memory {
+                addr,
+                size,
+                is_64bit,
+                prefetchable,
+            } => write!(
+                f,
+                "Memory at {addr:#x} ({}-bit, {}) [size={size:#x}]",
+                if *is_64bit { 64 } else { 32 },
+                if *prefetchable {
+                    "prefetchable"
+                } else {
+                    "non-prefetchable"
+                }
+            ),
+            Bar::Io { port, size } => write!(f, "I/O ports at {port:#x} [size={size:#x}]"),
+        }
+    }
+}
+
+/// Reads the BARs, probing their sizes by writing all ones. Decoding is
+/// disabled meanwhile so that the device does not respond at the probed
+/// addresses.
+fn read_bars(bdf: BusDeviceFunction, count: u16) -> Vec<(usize, Bar)> {
+    let command = read_config_u16(bdf, REG_COMMAND);
+    write_config_u16(
+        bdf,
+        REG_COMMAND,
+        command & !(COMMAND_IO_SPACE | COMMAND_MEMORY_SPACE),
+    );
+    let probe = |offset: u16| {
+        let original = read_config_u32(bdf, offset);
+        write_config_u32(bdf, offset, 0xffff_ffff);
+        let mask = read_config_u32(bdf, offset);
+        write_config_u32(bdf, offset, original);
+        (original, mask)
+    };
+    let mut bars = Vec::new();
+    let mut i = 0;
+    while i < count {
+        let offset = REG_BAR0 + i * 4;
+        let index = i as usize;
+        let (low, low_mask) = probe(offset);
+        i += 1;
+        if low & 1 != 0 {
+            let mask = (low_mask & 0xffff_fffc) as u16;
+            if mask != 0 {
+                bars.push((
+                    index,
+                    Bar::Io {
+                        port: (low & 0xffff_fffc) as u16,
+                        size: (!mask).wrapping_add(1),
+                    },
+                ));
+            }
+            continue;
+        }
+        let is_64bit = (low >> 1) & 3 == 2;
+        let (addr, mask) = if is_64bit && i < count {
+            let (high, high_mask) = probe(offset + 4);
+            i += 1;
+            (
+                (high as u64) << 32 | (low & 0xffff_fff0) as u64,
+                (high_mask as u64) << 32 | (low_mask & 0xffff_fff0) as u64,
+            )
+        } else {
+            // 32ビットのBARは上位をすべて1とみなして大きさを求める
+            let mask = low_mask & 0xffff_fff0;
+            if mask == 0 {
+                continue;
+            }
+            (
+                (low & 0xffff_fff0) as u64,
+                0xffff_ffff_0000_0000 | mask as u64,
+            )
+        };
+        if mask == 0 {
+            continue;
+        }
+        bars.push((
+            index,
+            Bar::Memory {
+                addr,
+                size: (!mask).wrapping_add(1),
+                is_64bit,
+                prefetchable: low & 8 != 0,
+            },
+        ));
+    }
+    write_config_u16(bdf, REG_COMMAND, command);
+    bars
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PciDevice {
+    pub bdf: BusDeviceFunction,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: u8,
+    /// (index, BAR) of the implemented BARs. A 64-bit BAR takes two indexes.
+    pub bars: Vec<(usize, Bar)>,
+    pub interrupt_line: u8,
+    pub interrupt_pin: u8,
+}
+impl PciDevice {
+    fn read(bdf: BusDeviceFunction) -> Option<Self> {
+        let id = read_config_u32(bdf, REG_VENDOR_ID);
+        let vendor_id = id as u16;
+        if vendor_id == 0xffff {
+            return None;
+        }
+        let class = read_config_u32(bdf, REG_CLASS);
+        let header_type = read_config_u8(bdf, REG_HEADER_TYPE);
+        let bars = match header_type & HEADER_TYPE_MASK {
+            HEADER_TYPE_NORMAL => read_bars(bdf, 6),
+            HEADER_TYPE_BRIDGE => read_bars(bdf, 2),
+            _ => Vec::new(),
+        };
+        let interrupt = read_config_u16(bdf, REG_INTERRUPT_LINE);
+        Some(Self {
+            bdf,
+            vendor_id,
+            device_id: (id >> 16) as u16,
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            header_type,
+            bars,
+            interrupt_line: interrupt as u8,
+            interrupt_pin: (interrupt >> 8) as u8,
+        })
+    }
+    pub fn bar(&self, index: usize) -> Option<Bar> {
+        self.bars.iter().find(|(i, _)| *i == index).map(|(_, b)| *b)
+    }
+    pub fn class_name(&self) -> &'static str {
+        class_name(self.class, self.subclass)
+    }
+}
+
+fn class_name(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x01, 0x00) => "SCSI storage controller",
+        (0x01, 0x01) => "IDE interface",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "Non-Volatile memory controller",
+        (0x01, _) => "Mass storage controller",
+        (0x02, 0x00) => "Ethernet controller",
+        (0x02, _) => "Network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "Display controller",
+        (0x04, 0x01) => "Audio device",
+        (0x04, 0x03) => "Audio device",
+        (0x04, _) => "Multimedia controller",
+        (0x05, _) => "Memory controller",
+        (0x06, 0x00) => "Host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "Bridge",
+        (0x07, _) => "Communication controller",
+        (0x08, _) => "System peripheral",
+        (0x0c, 0x03) => "USB controller",
+        (0x0c, 0x05) => "SMBus",
+        (0x0c, _) => "Serial bus controller",
+        _ => "Unclassified device",
+    }
+}
+
+static DEVICES: Mutex<Vec<PciDevice>> = Mutex::new(Vec::new());
+
+fn scan() -> Vec<PciDevice> {
+    let mut devices = Vec::new();
+    for bus in 0..=255 {
+        for device in 0..32 {
+            let Ok(bdf) = BusDeviceFunction::new(bus, device, 0) else {
+                continue;
+            };
+            let Some(d) = PciDevice::read(bdf) else {
+                continue;
+            };
+            let multi_function = d.header_type & HEADER_TYPE_MULTI_FUNCTION != 0;
+            devices.push(d);
+            if !multi_function {
+                continue;
+            }
+            for function in 1..8 {
+                if let Some(d) = BusDeviceFunction::new(bus, device, function)
+                    .ok()
+                    .and_then(PciDevice::read)
+                {
+                    devices.push(d);
+                }
+            }
+        }
+    }
+    devices
+}
+
+/// Scans all the buses and returns the number of devices found.
+pub fn init() -> usize {
+    let devices = scan();
+    let n = devices.len();
+    *DEVICES.lock() = devices;
+    n
+}
+
+pub fn devices() -> Vec<PciDevice> {
+    DEVICES.lock().clone()
+}
+
+pub fn find(vendor_id: u16, device_id: u16) -> Option<PciDevice> {
+    DEVICES
+        .lock()
+        .iter()
+        .find(|d| d.vendor_id == vendor_id && d.device_id == device_id)
+        .cloned()
+}
+
+/// The (ID, offset) of the capabilities in the configuration space of the
+/// device, e.g. ID 0x09 is vendor specific.
+pub fn capabilities(bdf: BusDeviceFunction) -> Vec<(u8, u16)> {
+    let mut caps = Vec::new();
+    if read_config_u16(bdf, REG_STATUS) & STATUS_CAPABILITIES == 0 {
+        return caps;
+    }
+    let mut offset = (read_config_u8(bdf, REG_CAPABILITIES) & !3) as u16;
+    // 壊れたリストで無限ループしないように数を制限する
+    while offset >= 0x40 && caps.len() < 48 {
+        caps.push((read_config_u8(bdf, offset), offset));
+        offset = (read_config_u8(bdf, offset + 1) & !3) as u16;
+    }
+    caps
+}
+
+/// Returns the devices of the class, e.g. (0x0c, 0x03, 0x30) for xHCI.
+/// prog_if is not checked if it is None.
+pub fn find_by_class(class: u8, subclass: u8, prog_if: Option<u8>) -> Vec<PciDevice> {
+    DEVICES
+        .lock()
+        .iter()
+        .filter(|d| d.class == class && d.subclass == subclass)
+        .filter(|d| prog_if.map_or(true, |p| d.prog_if == p))
+        .cloned()
+        .collect()
+}
+
+/// The `lspci` command.
+pub fn cmd_lspci(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let verbose = match args {
+        [] => false,
+        ["-v"] => true,
+        _ => return Err("usage: lspci [-v]"),
+    };
+    for d in devices() {
+        let _ = writeln!(
+            out,
+            "{} {}: {:04x}:{:04x} [{:02x}{:02x}{:02x}] (rev {:02x})",
+            d.bdf,
+            d.class_name(),
+            d.vendor_id,
+            d.device_id,
+            d.class,
+            d.subclass,
+            d.prog_if,
+            d.revision
+        );
+        if !verbose {
+            continue;
+        }
+        if has_extended_config(d.bdf) {
+            let _ = writeln!(out, "    Extended config space: ECAM");
+        }
+        if (1..=4).contains(&d.interrupt_pin) {
+            let pin = (b'A' + d.interrupt_pin - 1) as char;
+            let _ = writeln!(
+                out,
+                "    Interrupt: pin {pin} routed to IRQ {}",
+                d.interrupt_line
+            );
+        }
+        for (i, bar) in &d.bars {
+            let _ = writeln!(out, "    BAR{i}: {bar}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! Per-CPU data, reached through the GS base.
+//!
+//! Each CPU has a PerCpu block whose address is in its IA32_GS_BASE MSR,
+//! and the block starts with a pointer to itself, so this() is a single
+//! `mov rax, gs:[0]` without any lock. The BSP installs its block in
+//! init_bsp() early in the boot, and each AP in smp::ap_entry().
+//!
+//! IA32_KERNEL_GS_BASE also points to the block, so that the syscall entry
+//! can get it back with `swapgs` whatever user mode did to GS.
+
+use crate::arch::read_msr;
+use crate::arch::write_msr;
+use crate::gdt::TaskStateSegment;
+use crate::mutex::Mutex;
+use crate::process::Pid;
+use crate::task::TaskManager;
+use alloc::boxed::Box;
+use core::arch::asm;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+
+const MSR_GS_BASE: u32 = 0xc000_0101;
+const MSR_KERNEL_GS_BASE: u32 = 0xc000_0102;
+
+/// Offsets used by the syscall entry in assembly (see syscall.rs).
+pub const OFFSET_USER_RSP: usize = 8;
+pub const OFFSET_KERNEL_STACK: usize = 16;
+
+#[repr(C)]
+pub struct PerCpu {
+    /// Points to the block itself. Must be the first field (see this()).
+    this: *const PerCpu,
+    /// The user stack pointer, saved by the syscall entry until it is pushed
+    /// to the kernel stack.
+    user_rsp: AtomicU64,
+    /// The stack the syscall entry switches to. Also in the TSS as rsp0.
+    kernel_stack: AtomicU64,
+    /// 0 for the BSP, and 1.. for the APs in the order they were started.
+    pub index: usize,
+    pub apic_id: u32,
+    online: AtomicBool,
+    /// The pid of the running task, or 0 if no task is running yet.
+    current_task: AtomicU64,
+    /// The tasks that run on this CPU.
+    pub(crate) tasks: Mutex<TaskManager>,
+    /// Set by gdt::init().
+    tss: AtomicPtr<TaskStateSegment>,
+}
+const _: () = assert!(core::mem::offset_of!(PerCpu, user_rsp) == OFFSET_USER_RSP);
+const _: () = assert!(core::mem::offset_of!(PerCpu, kernel_stack) == OFFSET_KERNEL_STACK);
+// SAFETY: `this` only points to the block itself, which is never freed
+unsafe impl Sync for PerCpu {}
+unsafe impl Send for PerCpu {}
+impl PerCpu {
+    /// Allocates a block that lives forever.
+    pub fn new(index: usize, apic_id: u32) -> &'static PerCpu {
+        let cpu = Box::leak(Box::new(PerCpu {
+            this: core::ptr::null(),
+            user_rsp: AtomicU64::new(0),
+            kernel_stack: AtomicU64::new(0),
+            index,
+            apic_id,
+            online: AtomicBool::new(false),
+            current_task: AtomicU64::new(0),
+            tasks: Mutex::new(TaskManager::new()),
+            tss: AtomicPtr::new(core::ptr::null_mut()),
+        }));
+        cpu.this = cpu;
+        cpu
+    }
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::Acquire)
+    }
+    pub fn set_online(&self, online: bool) {
+        self.online.store(online, Ordering::Release);
+    }
+    pub fn current_task(&self) -> Option<Pid> {
+        match self.current_task.load(Ordering::Relaxed) {
+            0 => None,
+            pid => Some(pid),
+        }
+    }
+    pub(crate) fn set_current_task(&self, pid: Option<Pid>) {
+        self.current_task.store(pid.unwrap_or(0), Ordering::Relaxed);
+    }
+    pub(crate) fn set_tss(&self, tss: &'static mut TaskStateSegment) {
+        self.tss.store(tss, Ordering::Relaxed);
+    }
+    pub fn kernel_stack(&self) -> u64 {
+        self.kernel_stack.load(Ordering::Relaxed)
+    }
+    /// Sets the stack used on entering the kernel from user mode, by both
+    /// syscall and interrupts.
+    pub fn set_kernel_stack(&self, rsp: u64) {
+        self.kernel_stack.store(rsp, Ordering::Relaxed);
+        let tss = self.tss.load(Ordering::Relaxed);
+        if !tss.is_null() {
+            // SAFETY: the TSS is leaked in gdt::init(), and only this CPU
+            // uses it
+            unsafe { core::ptr::addr_of_mut!((*tss).rsp[0]).write_unaligned(rsp) }
+        }
+    }
+}
+
+static BSP: AtomicPtr<PerCpu> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Makes cpu the per-CPU block of the running CPU.
+pub fn install(cpu: &'static PerCpu) {
+    // SAFETY: IA32_GS_BASE exists on every x86_64 CPU, and the kernel does
+    // not use GS for anything else
+    unsafe {
+        write_msr(MSR_GS_BASE, cpu as *const PerCpu as u64);
+        write_msr(MSR_KERNEL_GS_BASE, cpu as *const PerCpu as u64);
+    }
+}
+
+/// Makes GS point to the block again after an exception in user mode, which
+/// enters the kernel without `swapgs`.
+pub fn restore_from_kernel_gs_base() {
+    // SAFETY: install() has put the block of this CPU in IA32_KERNEL_GS_BASE
+    unsafe { write_msr(MSR_GS_BASE, read_msr(MSR_KERNEL_GS_BASE)) }
+}
+
+/// Sets up the block of the BSP. Call right after the heap is ready.
+pub fn init_bsp(apic_id: u32) -> &'static PerCpu {
+    let cpu = PerCpu::new(0, apic_id);
+    cpu.set_online(true);
+    install(cpu);
+    BSP.store(cpu as *const PerCpu as *mut PerCpu, Ordering::SeqCst);
+    cpu
+}
+
+/// Installs the block of the BSP again, e.g. after an EFI application that
+/// might have changed the GS base returned.
+pub fn restore_bsp() {
+    let bsp = BSP.load(Ordering::SeqCst);
+    if !bsp.is_null() {
+        // SAFETY: the block is leaked in init_bsp()
+        install(unsafe { &*bsp });
+    }
+}
+
+/// The block of the running CPU. init_bsp() (or install() on an AP) must
+/// have been called.
+pub fn this() -> &'static PerCpu {
+    let cpu: *const PerCpu;
+    // SAFETY: GS base points to a PerCpu, whose first field points to itself
+    unsafe { asm!("mov {}, gs:[0]", out(reg) cpu, options(nostack, readonly, preserves_flags)) };
+    // SAFETY: the blocks are leaked in PerCpu::new()
+    unsafe { &*cpu }
+}
+
+/// The index of the running CPU (0 for the BSP).
+pub fn cpu_id() -> usize {
+    this().index
+}
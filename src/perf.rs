@@ -0,0 +1,103 @@
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::time;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Cycles spent in one region named by measure!().
+#[derive(Debug, Clone)]
+pub struct RegionStats {
+    pub name: &'static str,
+    pub count: u64,
+    pub total_cycles: u64,
+    pub min_cycles: u64,
+    pub max_cycles: u64,
+}
+
+static REGIONS: Mutex<Vec<RegionStats>> = Mutex::new(Vec::new());
+
+#[doc(hidden)]
+pub fn record(name: &'static str, cycles: u64) {
+    // 割り込みハンドラ内で計測されることもあるので、取れなければ諦める
+    let Some(mut regions) = REGIONS.try_lock() else {
+        return;
+    };
+    match regions.iter_mut().find(|r| r.name == name) {
+        Some(r) => {
+            r.count += 1;
+            r.total_cycles += cycles;
+            r.min_cycles = r.min_cycles.min(cycles);
+            r.max_cycles = r.max_cycles.max(cycles);
+        }
+        None => regions.push(RegionStats {
+            name,
+            count: 1,
+            total_cycles: cycles,
+            min_cycles: cycles,
+            max_cycles: cycles,
+        }),
+    }
+}
+
+/// Measures the TSC cycles spent in the block and adds them to the named region.
+///
+/// ```ignore
+/// let r = measure!("compositor", { back.flush(&mut vram) });
+/// ```
+#[macro_export]
+macro_rules! measure {
+    ($name:expr, $body:block) => {{
+        let start = $crate::arch::rdtsc();
+        let result = $body;
+        $crate::perf::record($name, $crate::arch::rdtsc() - start);
+        result
+    }};
+}
+
+pub fn regions() -> Vec<RegionStats> {
+    REGIONS.lock().clone()
+}
+
+pub fn reset() {
+    REGIONS.lock().clear();
+}
+
+pub fn cycles_to_us(cycles: u64) -> u64 {
+    match time::tsc_hz() {
+        0 => 0,
+        hz => (cycles as u128 * 1_000_000 / hz as u128) as u64,
+    }
+}
+
+/// The `perf` command.
+pub fn cmd_perf(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    match args {
+        [] | ["show"] => {
+            let mut regions = regions();
+            regions.sort_by_key(|r| core::cmp::Reverse(r.total_cycles));
+            let _ = writeln!(
+                out,
+                "{:<16} {:>8} {:>14} {:>10} {:>10} {:>10}",
+                "REGION", "COUNT", "TOTAL(cyc)", "AVG(cyc)", "MAX(cyc)", "TOTAL(us)"
+            );
+            for r in regions {
+                let _ = writeln!(
+                    out,
+                    "{:<16} {:>8} {:>14} {:>10} {:>10} {:>10}",
+                    r.name,
+                    r.count,
+                    r.total_cycles,
+                    r.total_cycles / r.count,
+                    r.max_cycles,
+                    cycles_to_us(r.total_cycles)
+                );
+            }
+            Ok(())
+        }
+        ["reset"] => {
+            reset();
+            Ok(())
+        }
+        _ => Err("usage: perf [show|reset]"),
+    }
+}
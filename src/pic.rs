@@ -0,0 +1,225 @@
+//! The dual 8259 PICs and the handlers of the ISA IRQs.
+//!
+//! The IRQs start out on the PICs. After use_io_apic() they are delivered
+//! through the IO APIC, with the same vectors and handlers, and the PICs
+//! stay masked; the functions here take care of either.
+
+use crate::apic;
+use crate::arch::read_io_port_u8;
+use crate::arch::without_interrupts;
+use crate::arch::write_io_port_u8;
+use crate::interrupt;
+use crate::interrupt::InterruptStackFrame;
+use crate::ioapic;
+use crate::result::Result;
+use crate::user;
+use core::sync::atomic::AtomicU16;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+
+const PIC1_CMD: u16 = 0x20;
+const PIC1_DATA: u16 = 0x21;
+const PIC2_CMD: u16 = 0xa0;
+const PIC2_DATA: u16 = 0xa1;
+
+const ICW1_INIT: u8 = 0x11;
+const ICW4_8086: u8 = 0x01;
+const OCW3_READ_ISR: u8 = 0x0b;
+const EOI: u8 = 0x20;
+
+pub const NUM_IRQS: u8 = 16;
+/// IRQ n is delivered as the vector IRQ_VECTOR_BASE + n, right after the CPU exceptions.
+pub const IRQ_VECTOR_BASE: u8 = 0x20;
+const IRQ_CASCADE: u8 = 2;
+
+pub type IrqHandler = fn(irq: u8);
+
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_HANDLER: AtomicUsize = AtomicUsize::new(0);
+static IRQ_HANDLERS: [AtomicUsize; NUM_IRQS as usize] = [NO_HANDLER; NUM_IRQS as usize];
+
+fn io_wait() {
+    // 未使用のポートへの書き込みで、PICが処理を終えるのを少し待つ
+    write_io_port_u8(0x80, 0);
+}
+
+/// Vector bases that EDK2-based firmware (e.g. OVMF) programs the PICs with.
+const FIRMWARE_VECTOR_BASE: u8 = 0x68;
+const FIRMWARE_VECTOR_BASE_SLAVE: u8 = 0x70;
+
+static FIRMWARE_MASKS: AtomicU16 = AtomicU16::new(0xffff);
+
+fn program(master_base: u8, slave_base: u8, masks: u16) {
+    write_io_port_u8(PIC1_DATA, 0xff);
+    write_io_port_u8(PIC2_DATA, 0xff);
+    write_io_port_u8(PIC1_CMD, ICW1_INIT);
+    io_wait();
+    write_io_port_u8(PIC2_CMD, ICW1_INIT);
+    io_wait();
+    write_io_port_u8(PIC1_DATA, master_base);
+    io_wait();
+    write_io_port_u8(PIC2_DATA, slave_base);
+    io_wait();
+    write_io_port_u8(PIC1_DATA, 1 << IRQ_CASCADE);
+    io_wait();
+    write_io_port_u8(PIC2_DATA, IRQ_CASCADE);
+    io_wait();
+    write_io_port_u8(PIC1_DATA, ICW4_8086);
+    io_wait();
+    write_io_port_u8(PIC2_DATA, ICW4_8086);
+    io_wait();
+    write_io_port_u8(PIC1_DATA, masks as u8);
+    write_io_port_u8(PIC2_DATA, (masks >> 8) as u8);
+}
+
+/// Returns the interrupt mask registers of the PICs (bit n set = IRQ n masked).
+pub fn masks() -> u16 {
+    read_io_port_u8(PIC1_DATA) as u16 | (read_io_port_u8(PIC2_DATA) as u16) << 8
+}
+
+/// Remaps the 8259 PICs to IRQ_VECTOR_BASE and masks all the IRQs.
+pub fn init() {
+    FIRMWARE_MASKS.store(masks(), Ordering::SeqCst);
+    program(IRQ_VECTOR_BASE, IRQ_VECTOR_BASE + 8, !(1u16 << IRQ_CASCADE));
+    for (irq, handler) in IRQ_STUBS.iter().enumerate() {
+        interrupt::set_handler(IRQ_VECTOR_BASE + irq as u8, *handler);
+    }
+}
+
+/// Delivers the IRQs through the IO APIC from now on, keeping the handlers
+/// and the masks, and masks the PICs. Returns the number of IRQs routed.
+/// Call after apic::init().
+pub fn use_io_apic() -> Result<usize> {
+    without_interrupts(|| {
+        let routed = ioapic::init(masks())?;
+        write_io_port_u8(PIC1_DATA, 0xff);
+        write_io_port_u8(PIC2_DATA, 0xff);
+        Ok(routed)
+    })
+}
+
+/// Puts the PICs back into the state the firmware left them in, so that
+/// boot services work again (e.g. before starting another EFI application).
+///
+/// Returns the kernel's masks to be passed to resume().
+pub fn restore_firmware() -> u16 {
+    let kernel_masks = if ioapic::is_active() {
+        // ファームウェアはPICしか使わないので、IO APICからは何も届かないようにする
+        let masks = ioapic::masks();
+        ioapic::set_masks(0xffff);
+        masks
+    } else {
+        masks()
+    };
+    program(
+        FIRMWARE_VECTOR_BASE,
+        FIRMWARE_VECTOR_BASE_SLAVE,
+        FIRMWARE_MASKS.load(Ordering::SeqCst),
+    );
+    kernel_masks
+}
+
+/// Undoes restore_firmware().
+pub fn resume(masks: u16) {
+    if ioapic::is_active() {
+        program(IRQ_VECTOR_BASE, IRQ_VECTOR_BASE + 8, 0xffff);
+        ioapic::set_masks(masks);
+    } else {
+        program(IRQ_VECTOR_BASE, IRQ_VECTOR_BASE + 8, masks);
+    }
+}
+
+fn data_port(irq: u8) -> (u16, u8) {
+    if irq < 8 {
+        (PIC1_DATA, irq)
+    } else {
+        (PIC2_DATA, irq - 8)
+    }
+}
+
+pub fn mask(irq: u8) {
+    if ioapic::is_active() {
+        return ioapic::mask(irq);
+    }
+    let (port, bit) = data_port(irq);
+    write_io_port_u8(port, read_io_port_u8(port) | (1 << bit));
+}
+
+pub fn unmask(irq: u8) {
+    if ioapic::is_active() {
+        return ioapic::unmask(irq);
+    }
+    let (port, bit) = data_port(irq);
+    write_io_port_u8(port, read_io_port_u8(port) & !(1 << bit));
+}
+
+pub fn eoi(irq: u8) {
+    if ioapic::is_active() {
+        return apic::local_apic_eoi();
+    }
+    if irq >= 8 {
+        write_io_port_u8(PIC2_CMD, EOI);
+    }
+    write_io_port_u8(PIC1_CMD, EOI);
+}
+
+fn read_isr() -> u16 {
+    write_io_port_u8(PIC1_CMD, OCW3_READ_ISR);
+    write_io_port_u8(PIC2_CMD, OCW3_READ_ISR);
+    (read_io_port_u8(PIC2_CMD) as u16) << 8 | read_io_port_u8(PIC1_CMD) as u16
+}
+
+/// Registers the handler for the irq and unmasks it.
+///
+/// The handler runs with interrupts disabled and EOI is sent after it returns.
+pub fn register_irq_handler(irq: u8, handler: IrqHandler) -> Result<()> {
+    if irq >= NUM_IRQS || irq == IRQ_CASCADE {
+        return Err("Invalid IRQ number");
+    }
+    if ioapic::is_active() && !ioapic::is_routed(irq) {
+        return Err("The IRQ is not connected to the IO APIC");
+    }
+    IRQ_HANDLERS[irq as usize]
+        .compare_exchange(0, handler as usize, Ordering::SeqCst, Ordering::SeqCst)
+        .map_err(|_| "IRQ handler is already registered")?;
+    unmask(irq);
+    Ok(())
+}
+
+pub fn unregister_irq_handler(irq: u8) {
+    if irq < NUM_IRQS {
+        mask(irq);
+        IRQ_HANDLERS[irq as usize].store(0, Ordering::SeqCst);
+    }
+}
+
+fn dispatch(irq: u8) {
+    // IRQ7/15はノイズなどで発生する偽の割り込みの場合があり、その時はEOIを送ってはいけない
+    if (irq == 7 || irq == 15) && !ioapic::is_active() && read_isr() & (1 << irq) == 0 {
+        if irq == 15 {
+            eoi(IRQ_CASCADE);
+        }
+        return;
+    }
+    let handler = IRQ_HANDLERS[irq as usize].load(Ordering::SeqCst);
+    if handler != 0 {
+        // SAFETY: only IrqHandlers are stored in IRQ_HANDLERS
+        let handler: IrqHandler = unsafe { core::mem::transmute(handler) };
+        handler(irq);
+    }
+    eoi(irq);
+}
+
+macro_rules! irq_stubs {
+    ($($irq:literal),*) => {
+        [$({
+            extern "x86-interrupt" fn stub(frame: InterruptStackFrame) {
+                dispatch($irq);
+                user::preempt(&frame);
+            }
+            stub as interrupt::InterruptHandler
+        }),*]
+    };
+}
+static IRQ_STUBS: [interrupt::InterruptHandler; NUM_IRQS as usize] =
+    irq_stubs!(0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15);
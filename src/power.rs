@@ -0,0 +1,146 @@
+//! Turning the machine off and rebooting it.
+
+use crate::acpi;
+use crate::acpi::GenericAddress;
+use crate::arch::cli;
+use crate::arch::hlt;
+use crate::arch::load_idtr;
+use crate::arch::read_io_port_u16;
+use crate::arch::write_io_port_u16;
+use crate::arch::write_io_port_u8;
+use crate::arch::DescriptorTablePointer;
+use crate::ps2;
+use crate::result::Result;
+use crate::uefi;
+use crate::uefi::EfiResetType;
+use core::fmt;
+
+const CMD_PULSE_RESET: u8 = 0xfe;
+
+const PM1_CNT_SCI_EN: u16 = 1 << 0;
+const PM1_CNT_SLP_TYP_SHIFT: u16 = 10;
+const PM1_CNT_SLP_EN: u16 = 1 << 13;
+
+/// Ports and values that power off emulators without ACPI: QEMU (q35 and
+/// piix4 with OVMF), Bochs and old QEMU, and VirtualBox.
+const EMULATOR_POWER_OFF: [(u16, u16); 3] = [(0x604, 0x2000), (0xb004, 0x2000), (0x4004, 0x3400)];
+
+fn spin(n: usize) {
+    for _ in 0..n {
+        core::hint::spin_loop();
+    }
+}
+
+/// Enters the S5 state through PM1a_CNT (and PM1b_CNT).
+fn acpi_power_off() -> Result<()> {
+    let acpi = acpi::get().ok_or("ACPI is not available")?;
+    let fadt = acpi.fadt().ok_or("FADT not found")?;
+    let (slp_typ_a, slp_typ_b) = acpi.s5_sleep_types().ok_or("\\_S5_ not found")?;
+    let pm1a = fadt.pm1a_cnt_blk();
+    if pm1a == 0 {
+        return Err("PM1a_CNT not found");
+    }
+    if read_io_port_u16(pm1a) & PM1_CNT_SCI_EN == 0 && fadt.smi_cmd() != 0 {
+        // ファームウェアがACPIモードにしていなければ切り替えてもらう
+        write_io_port_u8(fadt.smi_cmd() as u16, fadt.acpi_enable());
+        for _ in 0..1000 {
+            if read_io_port_u16(pm1a) & PM1_CNT_SCI_EN != 0 {
+                break;
+            }
+            spin(10_000);
+        }
+    }
+    let sleep = |port: u16, slp_typ: u8| {
+        let cnt = read_io_port_u16(port) & !(0b111 << PM1_CNT_SLP_TYP_SHIFT);
+        write_io_port_u16(
+            port,
+            cnt | (slp_typ as u16) << PM1_CNT_SLP_TYP_SHIFT | PM1_CNT_SLP_EN,
+        );
+    };
+    let pm1b = fadt.pm1b_cnt_blk();
+    if pm1b != 0 {
+        sleep(pm1b, slp_typ_b);
+    }
+    sleep(pm1a, slp_typ_a);
+    spin(1_000_000);
+    Err("The machine did not power off")
+}
+
+/// Writes the reset value to the FADT reset register.
+fn acpi_reset() -> Result<()> {
+    let acpi = acpi::get().ok_or("ACPI is not available")?;
+    let (reg, value) = acpi
+        .fadt()
+        .and_then(|fadt| fadt.reset_reg())
+        .ok_or("No reset register")?;
+    let address = reg.address;
+    match reg.address_space_id {
+        GenericAddress::SYSTEM_IO => write_io_port_u8(address as u16, value),
+        // SAFETY: the firmware tells that writing the value resets the machine
+        GenericAddress::SYSTEM_MEMORY => unsafe {
+            core::ptr::write_volatile(address as *mut u8, value)
+        },
+        _ => return Err("Unsupported reset register"),
+    }
+    spin(1_000_000);
+    Err("The machine did not reset")
+}
+
+/// Asks the firmware to reset or turn off the machine with ResetSystem.
+fn firmware_reset(reset_type: EfiResetType) {
+    if let Some(efi_system_table) = uefi::system_table() {
+        efi_system_table.runtime_services.reset_system(reset_type);
+    }
+}
+
+/// Turns the machine off.
+///
+/// Enters the ACPI S5 state, and if that does not work, asks the firmware
+/// and then tries the ports that emulators use. Halts forever if nothing
+/// works.
+pub fn shutdown() -> ! {
+    cli();
+    let _ = acpi_power_off();
+    firmware_reset(EfiResetType::Shutdown);
+    for (port, value) in EMULATOR_POWER_OFF {
+        write_io_port_u16(port, value);
+        spin(100_000);
+    }
+    loop {
+        hlt();
+    }
+}
+
+/// Resets the machine.
+///
+/// Writes to the ACPI reset register, then asks the firmware, then pulses
+/// the reset line of the keyboard controller, and if none works, causes a
+/// triple fault by raising an exception with an empty IDT.
+pub fn reboot() -> ! {
+    cli();
+    let _ = acpi_reset();
+    firmware_reset(EfiResetType::Cold);
+    let _ = ps2::write_command(CMD_PULSE_RESET);
+    spin(1_000_000);
+    let empty = DescriptorTablePointer::default();
+    // SAFETY: this never returns; the CPU resets on the triple fault
+    unsafe {
+        load_idtr(&empty);
+        core::arch::asm!("int3");
+    }
+    loop {
+        hlt();
+    }
+}
+
+/// The `reboot` command.
+pub fn cmd_reboot(_args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let _ = writeln!(out, "Rebooting...");
+    reboot()
+}
+
+/// The `shutdown` command.
+pub fn cmd_shutdown(_args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let _ = writeln!(out, "Powering off...");
+    shutdown()
+}
@@ -0,0 +1,23 @@
+use crate::dmesg::DmesgWriter;
+use crate::serial::SerialPort;
+use core::fmt;
+use core::fmt::Write;
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    let _ = SerialPort::default().write_fmt(args);
+    let _ = DmesgWriter.write_fmt(args);
+}
+
+/// Prints to the serial console, and keeps it in the kernel message buffer.
+#[macro_export]
+macro_rules! print {
+    ($($arg:tt)*) => ($crate::print::_print(format_args!($($arg)*)));
+}
+
+/// Prints to the serial console, with a newline.
+#[macro_export]
+macro_rules! println {
+    () => ($crate::print!("\n"));
+    ($($arg:tt)*) => ($crate::print!("{}\n", format_args!($($arg)*)));
+}
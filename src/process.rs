@@ -0,0 +1,359 @@
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::tty::TtySignal;
+use crate::user;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+
+pub type Pid = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Signal {
+    /// Sent by Ctrl+C on the terminal.
+    Interrupt = 0,
+    /// Asks the process to exit.
+    Terminate = 1,
+    /// Stops the process immediately. Cannot be ignored.
+    Kill = 2,
+}
+impl Signal {
+    const ALL: [Signal; 3] = [Signal::Interrupt, Signal::Terminate, Signal::Kill];
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+    /// The exit code of a process ended by the signal, 128 + the number of
+    /// the signal on Unix as in shells.
+    pub fn exit_code(self) -> i64 {
+        128 + match self {
+            Signal::Interrupt => 2,
+            Signal::Terminate => 15,
+            Signal::Kill => 9,
+        }
+    }
+}
+impl From<TtySignal> for Signal {
+    fn from(sig: TtySignal) -> Self {
+        match sig {
+            TtySignal::Interrupt => Signal::Interrupt,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessState {
+    Running,
+    Exited(i64),
+}
+
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: Pid,
+    pub name: String,
+    pub state: ProcessState,
+    /// The process group, i.e. the pid of the job that the process belongs
+    /// to. Ctrl+C is sent to all the processes of the foreground group.
+    pub group: Pid,
+    pub nice: i8,
+    /// CPU time consumed so far, in nanoseconds.
+    pub cpu_time: u64,
+    /// The process is killed once cpu_time exceeds this, in nanoseconds.
+    pub cpu_limit: Option<u64>,
+}
+
+pub const NICE_MIN: i8 = -20;
+pub const NICE_MAX: i8 = 19;
+
+struct Process {
+    info: ProcessInfo,
+    pending_signals: u32,
+}
+
+struct ProcessTable {
+    processes: Vec<Process>,
+    next_pid: Pid,
+    foreground: Option<Pid>,
+}
+impl ProcessTable {
+    fn get_mut(&mut self, pid: Pid) -> Result<&mut Process> {
+        self.processes
+            .iter_mut()
+            .find(|p| p.info.pid == pid)
+            .ok_or("No such process")
+    }
+}
+
+static PROCESS_TABLE: Mutex<ProcessTable> = Mutex::new(ProcessTable {
+    processes: Vec::new(),
+    next_pid: 1,
+    foreground: None,
+});
+
+/// Registers a new running process and returns its pid.
+pub fn register(name: &str) -> Pid {
+    let mut table = PROCESS_TABLE.lock();
+    let pid = table.next_pid;
+    table.next_pid += 1;
+    table.processes.push(Process {
+        info: ProcessInfo {
+            pid,
+            name: name.to_string(),
+            state: ProcessState::Running,
+            group: pid,
+            nice: 0,
+            cpu_time: 0,
+            cpu_limit: None,
+        },
+        pending_signals: 0,
+    });
+    pid
+}
+
+pub fn exit(pid: Pid, code: i64) -> Result<()> {
+    let mut table = PROCESS_TABLE.lock();
+    let p = table.get_mut(pid)?;
+    p.info.state = ProcessState::Exited(code);
+    p.pending_signals = 0;
+    if table.foreground == Some(pid) {
+        table.foreground = None;
+    }
+    Ok(())
+}
+
+/// Removes an exited process from the table and returns its exit code.
+pub fn reap(pid: Pid) -> Option<i64> {
+    let mut table = PROCESS_TABLE.lock();
+    let i = table.processes.iter().position(|p| p.info.pid == pid)?;
+    if let ProcessState::Exited(code) = table.processes[i].info.state {
+        table.processes.remove(i);
+        Some(code)
+    } else {
+        None
+    }
+}
+
+pub fn info(pid: Pid) -> Option<ProcessInfo> {
+    let table = PROCESS_TABLE.lock();
+    table
+        .processes
+        .iter()
+        .find(|p| p.info.pid == pid)
+        .map(|p| p.info.clone())
+}
+
+/// The nice value of the process, without copying the rest of its info.
+pub fn nice(pid: Pid) -> Option<i8> {
+    PROCESS_TABLE
+        .lock()
+        .processes
+        .iter()
+        .find(|p| p.info.pid == pid)
+        .map(|p| p.info.nice)
+}
+
+pub fn list() -> Vec<ProcessInfo> {
+    PROCESS_TABLE
+        .lock()
+        .processes
+        .iter()
+        .map(|p| p.info.clone())
+        .collect()
+}
+
+pub fn send_signal(pid: Pid, sig: Signal) -> Result<()> {
+    let mut table = PROCESS_TABLE.lock();
+    let p = table.get_mut(pid)?;
+    if p.info.state != ProcessState::Running {
+        return Err("Process already exited");
+    }
+    p.pending_signals |= sig.bit();
+    Ok(())
+}
+
+/// Pops one pending signal of the process. Kill is always returned first.
+pub fn take_signal(pid: Pid) -> Option<Signal> {
+    let mut table = PROCESS_TABLE.lock();
+    let p = table.get_mut(pid).ok()?;
+    let sig = Signal::ALL
+        .iter()
+        .rev()
+        .find(|s| p.pending_signals & s.bit() != 0)
+        .copied()?;
+    p.pending_signals &= !sig.bit();
+    Some(sig)
+}
+
+/// Ends the process if it has a pending signal, as no signal can be handled
+/// for now. Returns the exit code if it has been ended.
+pub fn deliver_signals(pid: Pid) -> Option<i64> {
+    let code = take_signal(pid)?.exit_code();
+    exit(pid, code).ok()?;
+    Some(code)
+}
+
+/// Moves the process to the group, e.g. to make a job its own group.
+pub fn set_group(pid: Pid, group: Pid) -> Result<()> {
+    PROCESS_TABLE.lock().get_mut(pid)?.info.group = group;
+    Ok(())
+}
+
+/// Sends the signal to all the running processes of the group, and
+/// returns how many got it.
+pub fn signal_group(group: Pid, sig: Signal) -> usize {
+    let mut table = PROCESS_TABLE.lock();
+    let mut n = 0;
+    for p in table
+        .processes
+        .iter_mut()
+        .filter(|p| p.info.group == group && p.info.state == ProcessState::Running)
+    {
+        p.pending_signals |= sig.bit();
+        n += 1;
+    }
+    n
+}
+
+/// The group of the foreground job.
+pub fn foreground() -> Option<Pid> {
+    PROCESS_TABLE.lock().foreground
+}
+
+pub fn set_foreground(pid: Option<Pid>) -> Result<()> {
+    let mut table = PROCESS_TABLE.lock();
+    if let Some(pid) = pid {
+        if table.get_mut(pid)?.info.state != ProcessState::Running {
+            return Err("Process already exited");
+        }
+    }
+    table.foreground = pid;
+    Ok(())
+}
+
+/// Delivers a signal raised by the terminal (e.g. Ctrl+C) to the processes
+/// of the foreground group.
+pub fn signal_foreground(sig: Signal) -> Result<Pid> {
+    let group = foreground().ok_or("No foreground process")?;
+    if signal_group(group, sig) == 0 {
+        return Err("Process already exited");
+    }
+    Ok(group)
+}
+
+/// Returns true if the process can be picked by the scheduler, i.e. it is
+/// running and has no pending signal to be ended by.
+pub fn is_runnable(pid: Pid) -> bool {
+    let mut table = PROCESS_TABLE.lock();
+    table
+        .get_mut(pid)
+        .is_ok_and(|p| p.info.state == ProcessState::Running && p.pending_signals == 0)
+}
+
+pub fn set_nice(pid: Pid, nice: i8) -> Result<()> {
+    if !(NICE_MIN..=NICE_MAX).contains(&nice) {
+        return Err("nice value out of range");
+    }
+    PROCESS_TABLE.lock().get_mut(pid)?.info.nice = nice;
+    Ok(())
+}
+
+pub fn set_cpu_limit(pid: Pid, limit: Option<u64>) -> Result<()> {
+    PROCESS_TABLE.lock().get_mut(pid)?.info.cpu_limit = limit;
+    Ok(())
+}
+
+/// Charges CPU time to the process and kills it if it runs over its limit.
+///
+/// Returns true if the limit has been exceeded.
+pub fn charge_cpu_time(pid: Pid, ns: u64) -> Result<bool> {
+    let mut table = PROCESS_TABLE.lock();
+    let p = table.get_mut(pid)?;
+    p.info.cpu_time += ns;
+    let exceeded = p
+        .info
+        .cpu_limit
+        .is_some_and(|limit| p.info.cpu_time > limit);
+    if exceeded && p.info.state == ProcessState::Running {
+        p.pending_signals |= Signal::Kill.bit();
+    }
+    Ok(exceeded)
+}
+
+/// The `ps` command. MEM is the memory of the address space of user
+/// processes.
+pub fn cmd_ps(_args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let _ = writeln!(
+        out,
+        "{:>5} {:>4} {:>4} {:>8} {:>8}  NAME",
+        "PID", "STAT", "NI", "TIME", "MEM"
+    );
+    for p in list() {
+        let state = match p.state {
+            ProcessState::Running => "R",
+            ProcessState::Exited(_) => "Z",
+        };
+        let mem = user::process::find(p.pid)
+            .map_or("-".to_string(), |u| format!("{}K", u.memory_size() / 1024));
+        let _ = writeln!(
+            out,
+            "{:>5} {:>4} {:>4} {:>8} {:>8}  {}",
+            p.pid,
+            state,
+            p.nice,
+            format_cpu_time(p.cpu_time),
+            mem,
+            p.name
+        );
+    }
+    Ok(())
+}
+
+/// Formats nanoseconds of CPU time as seconds, e.g. "1.25".
+fn format_cpu_time(ns: u64) -> String {
+    format!("{}.{:02}", ns / 1_000_000_000, ns / 10_000_000 % 100)
+}
+
+fn parse_pid(s: &str) -> Result<Pid> {
+    s.parse().or(Err("Invalid pid"))
+}
+
+/// The `nice` command: shows or sets the nice value of a process. A lower
+/// value gets more of the CPU.
+pub fn cmd_nice(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    match args {
+        [pid] => {
+            let nice = nice(parse_pid(pid)?).ok_or("No such process")?;
+            let _ = writeln!(out, "{nice}");
+        }
+        [pid, value] => {
+            let value = value.parse().or(Err("Invalid nice value"))?;
+            set_nice(parse_pid(pid)?, value)?;
+        }
+        _ => return Err("usage: nice <pid> [<nice>]"),
+    }
+    Ok(())
+}
+
+/// The `ulimit` command: shows or sets the CPU time limit of a process in
+/// seconds. The process is killed once it has run for longer.
+pub fn cmd_ulimit(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    match args {
+        [pid] => {
+            let p = info(parse_pid(pid)?).ok_or("No such process")?;
+            let limit = p.cpu_limit.map_or("none".to_string(), format_cpu_time);
+            let _ = writeln!(out, "cpu time: {} / {limit}", format_cpu_time(p.cpu_time));
+        }
+        [pid, "none"] => set_cpu_limit(parse_pid(pid)?, None)?,
+        [pid, seconds] => {
+            let seconds: u64 = seconds.parse().or(Err("Invalid number of seconds"))?;
+            let ns = seconds
+                .checked_mul(1_000_000_000)
+                .ok_or("Too many seconds")?;
+            set_cpu_limit(parse_pid(pid)?, Some(ns))?;
+        }
+        _ => return Err("usage: ulimit <pid> [<cpu seconds>|none]"),
+    }
+    Ok(())
+}
@@ -0,0 +1,92 @@
+//! The 8042 PS/2 controller shared by the keyboard and the mouse.
+
+use crate::arch::read_io_port_u8;
+use crate::arch::write_io_port_u8;
+use crate::result::Result;
+
+const DATA: u16 = 0x60;
+const STATUS: u16 = 0x64;
+const COMMAND: u16 = 0x64;
+
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+const STATUS_INPUT_FULL: u8 = 1 << 1;
+
+pub const CMD_READ_CONFIG: u8 = 0x20;
+pub const CMD_WRITE_CONFIG: u8 = 0x60;
+pub const CMD_ENABLE_AUX: u8 = 0xa8;
+pub const CMD_WRITE_AUX: u8 = 0xd4;
+
+pub const CONFIG_KEYBOARD_IRQ: u8 = 1 << 0;
+pub const CONFIG_AUX_IRQ: u8 = 1 << 1;
+pub const CONFIG_KEYBOARD_CLOCK_DISABLED: u8 = 1 << 4;
+pub const CONFIG_AUX_CLOCK_DISABLED: u8 = 1 << 5;
+/// Translate the keyboard's scancodes to set 1.
+pub const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+const DEVICE_ACK: u8 = 0xfa;
+const TIMEOUT_SPINS: usize = 100_000;
+
+fn wait_input_empty() -> Result<()> {
+    for _ in 0..TIMEOUT_SPINS {
+        if read_io_port_u8(STATUS) & STATUS_INPUT_FULL == 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+    Err("PS/2 controller timed out")
+}
+
+/// Waits for a byte from the controller or a device.
+pub fn read_data() -> Result<u8> {
+    for _ in 0..TIMEOUT_SPINS {
+        if read_io_port_u8(STATUS) & STATUS_OUTPUT_FULL != 0 {
+            return Ok(read_io_port_u8(DATA));
+        }
+        core::hint::spin_loop();
+    }
+    Err("PS/2 device did not respond")
+}
+
+/// Reads the data port without waiting. For interrupt handlers.
+pub fn read_data_nowait() -> u8 {
+    read_io_port_u8(DATA)
+}
+
+pub fn write_command(cmd: u8) -> Result<()> {
+    wait_input_empty()?;
+    write_io_port_u8(COMMAND, cmd);
+    Ok(())
+}
+
+pub fn write_data(data: u8) -> Result<()> {
+    wait_input_empty()?;
+    write_io_port_u8(DATA, data);
+    Ok(())
+}
+
+/// Drops bytes left in the output buffer.
+pub fn flush() {
+    while read_io_port_u8(STATUS) & STATUS_OUTPUT_FULL != 0 {
+        read_io_port_u8(DATA);
+    }
+}
+
+pub fn read_config() -> Result<u8> {
+    write_command(CMD_READ_CONFIG)?;
+    read_data()
+}
+
+pub fn write_config(config: u8) -> Result<()> {
+    write_command(CMD_WRITE_CONFIG)?;
+    write_data(config)
+}
+
+/// Sends a byte to the device on the second (auxiliary) port and waits for ACK.
+pub fn send_aux(data: u8) -> Result<()> {
+    write_command(CMD_WRITE_AUX)?;
+    write_data(data)?;
+    match read_data()? {
+        DEVICE_ACK => Ok(()),
+        _ => Err("PS/2 aux device did not ACK"),
+    }
+}
@@ -0,0 +1,23 @@
+//! Helpers for running under QEMU (see scripts/launch_qemu.sh).
+
+use crate::arch::hlt;
+use crate::arch::write_io_port_u8;
+
+/// The port of `-device isa-debug-exit,iobase=0xf4,iosize=0x01`.
+const DEBUG_EXIT_PORT: u16 = 0xf4;
+
+/// QEMU exits with status `(code << 1) | 1`, i.e. 33 for Success and 35 for Failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failure = 0x11,
+}
+
+/// Terminates QEMU. Halts if the debug exit device is not present.
+pub fn exit_qemu(code: QemuExitCode) -> ! {
+    write_io_port_u8(DEBUG_EXIT_PORT, code as u8);
+    loop {
+        hlt();
+    }
+}
@@ -0,0 +1,142 @@
+//! Random numbers.
+//!
+//! A xoshiro256** generator is seeded at boot from EFI_RNG_PROTOCOL if the
+//! firmware has it, RDRAND if the CPU has it, or the jitter of the TSC, and
+//! random_u64() and friends take numbers from it. Rng can also be used on
+//! its own with a fixed seed, e.g. for games and tests. Neither is
+//! suitable for cryptography.
+
+use crate::arch::rdrand;
+use crate::arch::rdtsc;
+use crate::mutex::Mutex;
+use crate::uefi;
+use crate::uefi::EfiSystemTable;
+use core::fmt;
+
+/// The xoshiro256** generator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    s: [u64; 4],
+}
+impl Rng {
+    /// Expands seed into the state with SplitMix64, as the authors of
+    /// xoshiro recommend, so that any seed (even 0) works.
+    pub const fn from_seed(seed: u64) -> Self {
+        let mut x = seed;
+        let mut s = [0; 4];
+        let mut i = 0;
+        while i < 4 {
+            x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            s[i] = z ^ (z >> 31);
+            i += 1;
+        }
+        Self { s }
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.s;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+    /// A number in 0..n, without the bias of `% n`. Panics if n is 0.
+    pub fn below(&mut self, n: u64) -> u64 {
+        assert!(n > 0, "Rng::below(0)");
+        // 2^64 を n で割った余りの分だけ先頭を捨てる
+        let threshold = n.wrapping_neg() % n;
+        loop {
+            let x = self.next_u64();
+            if x >= threshold {
+                return x % n;
+            }
+        }
+    }
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+/// Where the seed came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Firmware,
+    Rdrand,
+    TscJitter,
+}
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Source::Firmware => "EFI_RNG_PROTOCOL",
+            Source::Rdrand => "RDRAND",
+            Source::TscJitter => "TSC jitter",
+        })
+    }
+}
+
+static RNG: Mutex<Option<Rng>> = Mutex::new(None);
+
+/// A seed from the timing noise of the TSC. Weak, but available everywhere.
+fn tsc_jitter() -> u64 {
+    let mut seed = rdtsc();
+    for _ in 0..64 {
+        let start = rdtsc();
+        for _ in 0..(start & 0xff) {
+            core::hint::spin_loop();
+        }
+        seed = (seed ^ rdtsc().wrapping_sub(start)).rotate_left(7);
+        seed = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+    seed
+}
+
+/// Seeds the generator. Call while the boot services are available.
+pub fn init(efi_system_table: &EfiSystemTable) -> Source {
+    let mut buf = [0u8; 8];
+    let (seed, source) = if uefi::get_rng(efi_system_table, &mut buf).is_ok() {
+        (u64::from_le_bytes(buf), Source::Firmware)
+    } else if let Some(seed) = rdrand() {
+        (seed, Source::Rdrand)
+    } else {
+        (tsc_jitter(), Source::TscJitter)
+    };
+    *RNG.lock() = Some(Rng::from_seed(seed));
+    source
+}
+
+/// Calls f with the generator, seeding it from the TSC if init() has not
+/// been called (e.g. in the host tests).
+fn with_rng<T>(f: impl FnOnce(&mut Rng) -> T) -> T {
+    let mut rng = RNG.lock();
+    f(rng.get_or_insert_with(|| Rng::from_seed(tsc_jitter())))
+}
+
+pub fn random_u64() -> u64 {
+    with_rng(|rng| rng.next_u64())
+}
+
+pub fn random_u32() -> u32 {
+    with_rng(|rng| rng.next_u32())
+}
+
+/// A random number in 0..n. Panics if n is 0.
+pub fn random_below(n: u64) -> u64 {
+    with_rng(|rng| rng.below(n))
+}
+
+pub fn fill_bytes(buf: &mut [u8]) {
+    with_rng(|rng| rng.fill_bytes(buf))
+}
@@ -0,0 +1 @@
+pub type Result<T> = core::result::Result<T, &'static str>;
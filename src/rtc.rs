@@ -0,0 +1,160 @@
+//! The MC146818 compatible real-time clock in the CMOS.
+//!
+//! The RTC is assumed to keep UTC, as QEMU does by default. It is read once
+//! at boot to set the wall clock, which NTP corrects later if available.
+//! The clock of the firmware (GetTime) is used when the RTC cannot be read.
+
+use crate::acpi;
+use crate::arch::read_io_port_u8;
+use crate::arch::without_interrupts;
+use crate::arch::write_io_port_u8;
+use crate::result::Result;
+use crate::time;
+use crate::time::DateTime;
+use crate::uefi;
+use crate::uefi::EfiTime;
+
+const PORT_INDEX: u16 = 0x70;
+const PORT_DATA: u16 = 0x71;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_MINUTES: u8 = 0x02;
+const REG_HOURS: u8 = 0x04;
+const REG_DAY: u8 = 0x07;
+const REG_MONTH: u8 = 0x08;
+const REG_YEAR: u8 = 0x09;
+const REG_STATUS_A: u8 = 0x0a;
+const REG_STATUS_B: u8 = 0x0b;
+
+const STATUS_A_UPDATE_IN_PROGRESS: u8 = 0x80;
+pub const STATUS_B_24_HOUR: u8 = 0x02;
+pub const STATUS_B_BINARY: u8 = 0x04;
+/// Set in the hours register for PM in the 12-hour mode.
+pub const HOURS_PM: u8 = 0x80;
+
+/// Reads until two reads in a row agree, since an update can start in
+/// between the wait and the reads.
+const MAX_READS: usize = 8;
+
+/// The raw values of the time registers, in the format of status B.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Registers {
+    pub seconds: u8,
+    pub minutes: u8,
+    pub hours: u8,
+    pub day: u8,
+    pub month: u8,
+    pub year: u8,
+    /// The century register given by the FADT, if any.
+    pub century: Option<u8>,
+    pub status_b: u8,
+}
+impl Registers {
+    /// Decodes the registers, or returns None if they make no valid date.
+    pub fn to_date_time(&self) -> Option<DateTime> {
+        let binary = self.status_b & STATUS_B_BINARY != 0;
+        let decode = |v: u8| {
+            if binary {
+                Some(v)
+            } else if v & 0x0f < 10 && v >> 4 < 10 {
+                Some((v >> 4) * 10 + (v & 0x0f))
+            } else {
+                None
+            }
+        };
+        let mut hour = decode(self.hours & !HOURS_PM)?;
+        if self.status_b & STATUS_B_24_HOUR == 0 {
+            // 12時間制: 12時は0時として数え、午後なら12を足す
+            if !(1..=12).contains(&hour) {
+                return None;
+            }
+            hour %= 12;
+            if self.hours & HOURS_PM != 0 {
+                hour += 12;
+            }
+        }
+        // 世紀のレジスタがなければ2000年代とみなす
+        let century = match self.century {
+            Some(century) => decode(century)? as u16,
+            None => 20,
+        };
+        let date_time = DateTime {
+            year: century * 100 + decode(self.year)? as u16,
+            month: decode(self.month)?,
+            day: decode(self.day)?,
+            hour,
+            minute: decode(self.minutes)?,
+            second: decode(self.seconds)?,
+        };
+        date_time.is_valid().then_some(date_time)
+    }
+}
+
+fn read_register(reg: u8) -> u8 {
+    write_io_port_u8(PORT_INDEX, reg);
+    read_io_port_u8(PORT_DATA)
+}
+
+fn read_registers(century_reg: Option<u8>) -> Registers {
+    while read_register(REG_STATUS_A) & STATUS_A_UPDATE_IN_PROGRESS != 0 {
+        core::hint::spin_loop();
+    }
+    Registers {
+        seconds: read_register(REG_SECONDS),
+        minutes: read_register(REG_MINUTES),
+        hours: read_register(REG_HOURS),
+        day: read_register(REG_DAY),
+        month: read_register(REG_MONTH),
+        year: read_register(REG_YEAR),
+        century: century_reg.map(read_register),
+        status_b: read_register(REG_STATUS_B),
+    }
+}
+
+/// The century register from the FADT. 0 there means none.
+fn century_register() -> Option<u8> {
+    acpi::get()?
+        .fadt()
+        .map(|fadt| fadt.century())
+        .filter(|&reg| reg != 0)
+}
+
+/// Reads the current date and time from the RTC.
+pub fn read() -> Result<DateTime> {
+    let century_reg = century_register();
+    let registers = without_interrupts(|| {
+        let mut last = read_registers(century_reg);
+        for _ in 1..MAX_READS {
+            let registers = read_registers(century_reg);
+            if registers == last {
+                return Ok(registers);
+            }
+            last = registers;
+        }
+        Err("RTC: the time keeps changing")
+    })?;
+    registers.to_date_time().ok_or("RTC: the time is not valid")
+}
+
+/// Reads the clock of the firmware with GetTime.
+pub fn read_firmware() -> Result<DateTime> {
+    let efi_system_table = uefi::system_table().ok_or("EFI context is not initialized")?;
+    let time = efi_system_table.runtime_services.get_time()?;
+    time.to_date_time().ok_or("GetTime: the time is not valid")
+}
+
+/// Sets the clock of the firmware with SetTime.
+pub fn write_firmware(t: &DateTime) -> Result<()> {
+    let efi_system_table = uefi::system_table().ok_or("EFI context is not initialized")?;
+    efi_system_table
+        .runtime_services
+        .set_time(&EfiTime::from_date_time(t))
+}
+
+/// Sets the wall clock from the RTC, or from the firmware if the RTC cannot
+/// be read. Call after time::init().
+pub fn init() -> Result<DateTime> {
+    let now = read().or_else(|e| read_firmware().map_err(|_| e))?;
+    time::set_unix_time(now.to_unix_time().ok_or("RTC: the time is before 1970")?);
+    Ok(now)
+}
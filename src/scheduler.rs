@@ -0,0 +1,252 @@
+//! The fair scheduler of the tasks of a CPU.
+//!
+//! Like Linux's CFS, the ready task with the smallest virtual runtime runs
+//! next. The virtual runtime is the time a task has run, weighted by its
+//! nice value, so tasks with a lower nice value get more of the CPU. The
+//! kernel is cooperative, so the time slice does not preempt a task in the
+//! kernel; a task that runs for long checks task::should_yield() instead.
+//! Only user programs are preempted, by the IRQs (see user::preempt()).
+
+use crate::process;
+use crate::process::Pid;
+use crate::process::NICE_MIN;
+use alloc::vec::Vec;
+use core::fmt;
+
+// Linuxの sched_prio_to_weight と同じく、nice値が1違うとCPU時間がおよそ1.25倍変わる
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+const NICE_0_WEIGHT: u64 = 1024;
+
+pub fn nice_to_weight(nice: i8) -> u64 {
+    NICE_TO_WEIGHT[(nice - NICE_MIN) as usize]
+}
+
+fn weight_of(pid: Pid) -> u64 {
+    process::nice(pid).map_or(NICE_0_WEIGHT, nice_to_weight)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedulerConfig {
+    /// Every ready task gets to run once within this time.
+    pub period_ns: u64,
+    /// A time slice is never shorter than this, however many tasks are ready.
+    pub min_slice_ns: u64,
+}
+impl SchedulerConfig {
+    pub const DEFAULT: Self = Self {
+        period_ns: 20_000_000,
+        min_slice_ns: 2_000_000,
+    };
+}
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+pub const LATENCY_BUCKETS: usize = 16;
+
+/// Histogram of scheduling latencies in power-of-two microsecond buckets.
+///
+/// Bucket 0 counts latencies below 1us, bucket i those in [2^(i-1), 2^i) us,
+/// and the last bucket everything above.
+#[derive(Debug, Clone, Default)]
+pub struct LatencyHistogram {
+    pub buckets: [u64; LATENCY_BUCKETS],
+    pub max_ns: u64,
+    pub total_ns: u64,
+}
+impl LatencyHistogram {
+    pub fn record(&mut self, ns: u64) {
+        let us = ns / 1000;
+        let i = (u64::BITS - us.leading_zeros()) as usize;
+        self.buckets[i.min(LATENCY_BUCKETS - 1)] += 1;
+        self.max_ns = self.max_ns.max(ns);
+        self.total_ns += ns;
+    }
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+    pub fn mean_ns(&self) -> u64 {
+        self.total_ns.checked_div(self.count()).unwrap_or(0)
+    }
+    /// Upper bound in microseconds of the bucket i (None for the last one).
+    pub fn bucket_limit_us(i: usize) -> Option<u64> {
+        (i < LATENCY_BUCKETS - 1).then_some(1 << i)
+    }
+}
+
+/// Scheduling statistics of one task.
+#[derive(Debug, Clone, Default)]
+pub struct TaskStats {
+    pub pid: Pid,
+    /// Virtual runtime weighted by nice (nice 0 advances as the clock).
+    pub vruntime_ns: u64,
+    pub runtime_ns: u64,
+    pub switches: u64,
+    /// Time from becoming ready to being picked.
+    pub latency: LatencyHistogram,
+}
+
+struct Entity {
+    ready_since_ns: Option<u64>,
+    stats: TaskStats,
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+    config: SchedulerConfig,
+    entities: Vec<Entity>,
+    current: Option<Pid>,
+    /// When the current task started running.
+    switched_in_ns: u64,
+    min_vruntime: u64,
+}
+impl Scheduler {
+    pub const fn new(config: SchedulerConfig) -> Self {
+        Self {
+            config,
+            entities: Vec::new(),
+            current: None,
+            switched_in_ns: 0,
+            min_vruntime: 0,
+        }
+    }
+    pub fn config(&self) -> SchedulerConfig {
+        self.config
+    }
+    pub fn set_config(&mut self, config: SchedulerConfig) {
+        self.config = config;
+    }
+    pub fn current(&self) -> Option<Pid> {
+        self.current
+    }
+    fn entity_mut(&mut self, pid: Pid) -> Option<&mut Entity> {
+        self.entities.iter_mut().find(|e| e.stats.pid == pid)
+    }
+    /// Starts tracking a ready task, from min_vruntime so that it can
+    /// neither starve the others nor be starved.
+    pub fn add(&mut self, pid: Pid, now: u64) {
+        if self.entity_mut(pid).is_none() {
+            self.entities.push(Entity {
+                ready_since_ns: Some(now),
+                stats: TaskStats {
+                    pid,
+                    vruntime_ns: self.min_vruntime,
+                    ..Default::default()
+                },
+            });
+        }
+    }
+    /// Stops tracking an ended task.
+    pub fn remove(&mut self, pid: Pid) {
+        self.entities.retain(|e| e.stats.pid != pid);
+        if self.current == Some(pid) {
+            self.current = None;
+        }
+    }
+    /// Makes pid the running task, e.g. the one that runs at boot.
+    pub fn set_current(&mut self, pid: Pid, now: u64) {
+        self.add(pid, now);
+        self.current = Some(pid);
+        self.switched_in_ns = now;
+    }
+    /// Charges the time since the current task was switched in to it, and
+    /// to its process (which is killed if it runs over its CPU limit).
+    pub fn account(&mut self, now: u64) {
+        let Some(pid) = self.current else {
+            return;
+        };
+        let ns = now.saturating_sub(self.switched_in_ns);
+        self.switched_in_ns = now;
+        let _ = process::charge_cpu_time(pid, ns);
+        let weight = weight_of(pid);
+        if let Some(e) = self.entity_mut(pid) {
+            e.stats.vruntime_ns += ns * NICE_0_WEIGHT / weight;
+            e.stats.runtime_ns += ns;
+        }
+    }
+    /// The index of the candidate with the smallest virtual runtime.
+    pub fn pick(&mut self, candidates: impl Iterator<Item = Pid>, now: u64) -> Option<usize> {
+        let mut best: Option<(usize, u64)> = None;
+        for (i, pid) in candidates.enumerate() {
+            self.add(pid, now);
+            let vruntime = self.entity_mut(pid).map_or(0, |e| e.stats.vruntime_ns);
+            if best.map_or(true, |(_, v)| vruntime < v) {
+                best = Some((i, vruntime));
+            }
+        }
+        best.map(|(i, _)| i)
+    }
+    /// Switches from the current task, which becomes ready, to pid.
+    pub fn switch_to(&mut self, pid: Pid, now: u64) {
+        if let Some(e) = self.current.and_then(|prev| self.entity_mut(prev)) {
+            e.ready_since_ns = Some(now);
+        }
+        self.add(pid, now);
+        let Some(next) = self.entity_mut(pid) else {
+            return;
+        };
+        if let Some(since) = next.ready_since_ns.take() {
+            next.stats.latency.record(now.saturating_sub(since));
+        }
+        next.stats.switches += 1;
+        let vruntime = next.stats.vruntime_ns;
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+        self.current = Some(pid);
+        self.switched_in_ns = now;
+    }
+    /// The share of the scheduling period that pid gets, by its weight.
+    pub fn time_slice(&self, pid: Pid) -> u64 {
+        let total: u64 = self.entities.iter().map(|e| weight_of(e.stats.pid)).sum();
+        (self.config.period_ns * weight_of(pid) / total.max(1)).max(self.config.min_slice_ns)
+    }
+    /// Whether the current task has used up its time slice.
+    pub fn slice_expired(&self, now: u64) -> bool {
+        self.current
+            .is_some_and(|pid| now.saturating_sub(self.switched_in_ns) >= self.time_slice(pid))
+    }
+    pub fn stats(&self, pid: Pid) -> Option<TaskStats> {
+        self.entities
+            .iter()
+            .find(|e| e.stats.pid == pid)
+            .map(|e| e.stats.clone())
+    }
+    pub fn all_stats(&self) -> Vec<TaskStats> {
+        self.entities.iter().map(|e| e.stats.clone()).collect()
+    }
+}
+
+/// Writes the statistics in a human readable form.
+pub fn write_stats(stats: &[TaskStats], out: &mut dyn fmt::Write) -> fmt::Result {
+    writeln!(
+        out,
+        "  PID  VRUNTIME   RUNTIME  SWITCHES  LAT(avg)  LAT(max)"
+    )?;
+    for s in stats {
+        writeln!(
+            out,
+            "{:>5} {:>7}ms {:>7}ms {:>9} {:>7}us {:>7}us",
+            s.pid,
+            s.vruntime_ns / 1_000_000,
+            s.runtime_ns / 1_000_000,
+            s.switches,
+            s.latency.mean_ns() / 1000,
+            s.latency.max_ns / 1000
+        )?;
+        for (i, n) in s.latency.buckets.iter().enumerate() {
+            if *n == 0 {
+                continue;
+            }
+            match LatencyHistogram::bucket_limit_us(i) {
+                Some(limit) => writeln!(out, "      <{limit:>6}us {n}")?,
+                None => writeln!(out, "      >={:>5}us {n}", 1 << (LATENCY_BUCKETS - 2))?,
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,207 @@
+//! The screen: the resolution, the demo drawing, and wsh on the screen console with the
+//! clock and the mouse cursor. Built with the `gui` feature.
+//!
+//! With the `retro` setting (e.g. `retro=3` on the command line), the window
+//! manager draws the desktop on a ScaledBuffer of 1/N of its size, which is
+//! enlarged N times into the desktop area like the screens of the old days.
+
+use crate::console;
+use crate::cursor;
+use crate::executor;
+use crate::graphics::draw_font_fg;
+use crate::graphics::draw_line;
+use crate::graphics::draw_point;
+use crate::graphics::draw_str_fg;
+use crate::graphics::fill_rect;
+use crate::graphics::BackBuffer;
+use crate::graphics::Bitmap;
+use crate::graphics::Rect;
+use crate::graphics::ScaledBuffer;
+use crate::input;
+use crate::input::InputEvent;
+use crate::measure;
+use crate::memory_map::MemoryMapSummary;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::screenshot;
+use crate::settings;
+use crate::shell;
+use crate::taskbar;
+use crate::time;
+use crate::uefi;
+use crate::uefi::init_vram;
+use crate::uefi::EfiSystemTable;
+use crate::uefi::MemoryMapHolder;
+use crate::uefi::VramBefferInfo;
+use crate::uefi::VramTextWriter;
+use crate::wm;
+use core::fmt::Write;
+use core::time::Duration;
+
+/// Switches the screen to the `resolution` setting, unless it is auto.
+pub fn apply_resolution(efi_system_table: &EfiSystemTable) -> Result<()> {
+    let value = settings::get("resolution");
+    let Some((w, h)) = value.split_once('x') else {
+        return Ok(());
+    };
+    let w = w.parse().map_err(|_| "Bad resolution")?;
+    let h = h.parse().map_err(|_| "Bad resolution")?;
+    uefi::set_resolution(efi_system_table, w, h)
+}
+
+/// Clears the screen instead of drawing the demo, for the shell mode, and
+/// returns the VRAM for the console.
+pub fn draw_blank(efi_system_table: &EfiSystemTable) -> VramBefferInfo {
+    let mut vram = init_vram(efi_system_table).expect("init_vram failed");
+    let (vw, vh) = (vram.width, vram.height);
+    fill_rect(&mut vram, 0x000000, 0, 0, vw, vh).expect("fill_rect failed");
+    vram
+}
+
+/// Draws the demo on the screen and returns the VRAM for the console.
+pub fn draw_demo(
+    efi_system_table: &EfiSystemTable,
+    memory_map: &MemoryMapHolder,
+) -> VramBefferInfo {
+    let mut vram = init_vram(efi_system_table).expect("init_vram failed");
+    let vw = vram.width;
+    let vh = vram.height;
+    fill_rect(&mut vram, 0x000000, 0, 0, vw, vh).expect("fill_rect failed");
+    // 図形はバックバッファに描いて、変更のあった部分だけをVRAMへ転送する
+    let mut back = BackBuffer::new(vw, vh);
+    draw_demo_shapes(&mut back).expect("draw_demo_shapes failed");
+    back.add_damage(Rect::new(0, 0, DEMO_SIZE + 1, DEMO_SIZE + 1));
+    measure!("compositor", { back.flush(&mut vram) }).expect("flush failed");
+    draw_demo_text(&mut vram);
+    let mut w = VramTextWriter::new(&mut vram);
+    for i in 0..4 {
+        writeln!(w, "i = {i}").unwrap();
+    }
+    write!(w, "{}", MemoryMapSummary::from_map(memory_map)).unwrap();
+    vram
+}
+
+/// The size of the grid drawn by draw_demo_shapes().
+pub const DEMO_SIZE: i64 = 256;
+
+/// Draws the squares, the gradient and the grid with the radial lines of
+/// the demo, in [0, DEMO_SIZE] x [0, DEMO_SIZE].
+pub fn draw_demo_shapes<T: Bitmap>(buf: &mut T) -> Result<()> {
+    fill_rect(buf, 0xff0000, 32, 32, 32, 32)?;
+    fill_rect(buf, 0x00ff00, 64, 64, 64, 64)?;
+    fill_rect(buf, 0x0000ff, 128, 128, 128, 128)?;
+    for i in 0..256 {
+        draw_point(buf, 0x010101 * i as u32, i, i)?;
+    }
+    let grid_size: i64 = 32;
+    for i in (0..=DEMO_SIZE).step_by(grid_size as usize) {
+        draw_line(buf, 0xff0000, 0, i, DEMO_SIZE, i)?;
+        draw_line(buf, 0xff0000, i, 0, i, DEMO_SIZE)?;
+    }
+    let cx = DEMO_SIZE / 2;
+    let cy = DEMO_SIZE / 2;
+    for i in (0..=DEMO_SIZE).step_by(grid_size as usize) {
+        draw_line(buf, 0xffff00, cx, cy, 0, i)?;
+        draw_line(buf, 0x00ffff, cx, cy, i, 0)?;
+        draw_line(buf, 0xff00ff, cx, cy, DEMO_SIZE, i)?;
+        draw_line(buf, 0xffffff, cx, cy, i, DEMO_SIZE)?;
+    }
+    Ok(())
+}
+
+/// Draws the text of the demo, right of and below the shapes.
+pub fn draw_demo_text<T: Bitmap>(buf: &mut T) {
+    for (i, c) in "ABCDEF".chars().enumerate() {
+        draw_font_fg(buf, i as i64 * 16 + DEMO_SIZE, i as i64 * 16, 0xffffff, c)
+    }
+    draw_str_fg(buf, DEMO_SIZE, DEMO_SIZE, 0xffffff, "Hello, world!");
+}
+
+/// The low resolution desktop in the retro mode.
+static RETRO: Mutex<Option<ScaledBuffer>> = Mutex::new(None);
+
+/// The scale of the `retro` setting, or None if it is off.
+fn retro_scale() -> Option<i64> {
+    settings::get("retro").parse().ok()
+}
+
+/// Copies the changes of the window manager to the low resolution desktop,
+/// and enlarges them onto the screen.
+fn present_retro(low: &mut ScaledBuffer, vram: &mut VramBefferInfo) -> Result<()> {
+    let Some(bounds) = wm::with(|wm| {
+        let bounds = wm.damage_bounds()?;
+        wm.present(low, 0, 0).ok()?;
+        Some(bounds)
+    })?
+    else {
+        return Ok(());
+    };
+    low.add_damage(bounds);
+    let area = low.area(vram);
+    cursor::draw_around(vram, area, |vram| low.present(vram))
+}
+
+/// Starts wsh on the lower half of the screen above the taskbar, with the
+/// mouse cursor, and the window manager on the upper half. Call after
+/// shell::init().
+pub fn start_console(mut vram: VramBefferInfo) {
+    let vw = vram.width;
+    let vh = vram.height;
+    let console_h = vh - vh / 2 - taskbar::HEIGHT;
+    console::init(vram, Rect::new(0, vh / 2, vw, console_h));
+    let desktop = Rect::new(0, 0, vw, vh / 2);
+    match retro_scale() {
+        Some(scale) => {
+            let mut low = ScaledBuffer::new(desktop.w / scale, desktop.h / scale, scale);
+            low.set_area(desktop);
+            let (w, h) = (low.width(), low.height());
+            wm::init(&mut low, Rect::new(0, 0, w, h));
+            *RETRO.lock() = Some(low);
+        }
+        // デモの描画がそのままデスクトップの背景になる
+        None => wm::init(&mut vram, desktop),
+    }
+    cursor::init(&mut vram);
+    screenshot::init(vram);
+    taskbar::start(
+        vram,
+        Rect::new(0, vh - taskbar::HEIGHT, vw, taskbar::HEIGHT),
+    );
+    executor::spawn(shell::run_on_console());
+    executor::spawn(async move {
+        loop {
+            let _ = match RETRO.lock().as_mut() {
+                Some(low) => present_retro(low, &mut vram),
+                None => wm::present(&mut vram),
+            };
+            time::sleep(Duration::from_millis(16)).await;
+        }
+    });
+    executor::spawn(async move {
+        loop {
+            match input::next_event().await {
+                InputEvent::Mouse(e) => {
+                    cursor::handle_mouse_event(&mut vram, &e);
+                    let (x, y) = cursor::position();
+                    let (wx, wy) = match RETRO.lock().as_ref() {
+                        Some(low) => low.to_virtual(&vram, x, y),
+                        None => (x, y),
+                    };
+                    wm::handle_mouse(wx, wy, e.buttons);
+                    taskbar::handle_mouse(x, y, e.buttons);
+                }
+                // フォーカスのあるウィンドウがなければコンソールへ
+                InputEvent::Key(e) if e.usage == screenshot::PRINT_SCREEN => {
+                    if e.pressed {
+                        screenshot::take();
+                    }
+                }
+                InputEvent::Key(e) => {
+                    if !wm::handle_key(&e) {
+                        console::handle_key(&e);
+                    }
+                }
+            }
+        }
+    });
+}
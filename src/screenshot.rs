@@ -0,0 +1,140 @@
+//! Screenshots of the whole screen as BMP files.
+//!
+//! They are saved to the ESP, or dumped over the serial port in base64
+//! between BEGIN and END lines when the ESP cannot be written. The `shot`
+//! command and the PrintScreen key take one. Built with the `gui` feature.
+
+use crate::base64;
+use crate::bmp;
+use crate::chainload;
+use crate::cursor;
+use crate::graphics::blit;
+use crate::graphics::Bitmap;
+use crate::graphics::OwnedBitmap;
+use crate::graphics::Rect;
+use crate::info;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::serial::SerialPort;
+use crate::time;
+use crate::time::DateTime;
+use crate::uefi;
+use crate::uefi::VramBefferInfo;
+use crate::warn;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// The HID usage of PrintScreen.
+pub const PRINT_SCREEN: u8 = 0x46;
+/// The length of the base64 lines on the serial port.
+const LINE_LENGTH: usize = 76;
+
+struct Screen(VramBefferInfo);
+// SAFETY: the VRAM pointer is only accessed with the lock held
+unsafe impl Send for Screen {}
+
+static SCREEN: Mutex<Option<Screen>> = Mutex::new(None);
+
+/// Makes the screen capturable. Call once the screen is set up.
+pub fn init(screen: VramBefferInfo) {
+    *SCREEN.lock() = Some(Screen(screen));
+}
+
+/// Copies what is on the screen now, without the mouse cursor.
+pub fn capture() -> Result<OwnedBitmap> {
+    let mut screen = SCREEN.lock();
+    let Screen(vram) = screen.as_mut().ok_or("The screen is not available")?;
+    let rect = Rect::new(0, 0, vram.width(), vram.height());
+    let mut bitmap = OwnedBitmap::new(rect.w, rect.h, 0x000000);
+    cursor::draw_around(vram, rect, |vram| blit(vram, rect, &mut bitmap, 0, 0))?;
+    Ok(bitmap)
+}
+
+/// Takes a screenshot as a BMP file.
+pub fn screenshot() -> Result<Vec<u8>> {
+    Ok(bmp::encode(&capture()?))
+}
+
+/// A file name from the date and time, or the uptime until the clock is set.
+pub fn default_path() -> String {
+    match DateTime::now() {
+        Some(t) => format!(
+            "\\shot-{:04}{:02}{:02}-{:02}{:02}{:02}.bmp",
+            t.year, t.month, t.day, t.hour, t.minute, t.second
+        ),
+        None => format!("\\shot-{}.bmp", time::now_ns() / 1_000_000),
+    }
+}
+
+/// Writes data in base64 lines between the BEGIN and END lines.
+pub fn dump(out: &mut dyn fmt::Write, name: &str, data: &[u8]) {
+    let _ = writeln!(out, "-----BEGIN SCREENSHOT {name}-----");
+    let encoded = base64::encode(data);
+    for line in encoded.as_bytes().chunks(LINE_LENGTH) {
+        let _ = writeln!(out, "{}", core::str::from_utf8(line).unwrap_or_default());
+    }
+    let _ = writeln!(out, "-----END SCREENSHOT-----");
+}
+
+/// Where a screenshot went.
+pub enum Saved {
+    File(String),
+    Serial,
+}
+
+/// Saves a screenshot to path on the ESP, or dumps it over the serial port
+/// if that fails.
+pub fn save(path: &str) -> Result<Saved> {
+    let data = screenshot()?;
+    let written = uefi::system_table()
+        .ok_or("EFI context is not initialized")
+        .and_then(|efi_system_table| {
+            chainload::with_firmware_interrupts(|| uefi::write_file(efi_system_table, path, &data))
+        });
+    match written {
+        Ok(()) => Ok(Saved::File(path.into())),
+        Err(e) => {
+            warn!("screenshot: {e}, sending it over the serial port");
+            let name = path.rsplit(['\\', '/']).next().unwrap_or(path);
+            dump(&mut SerialPort::default(), name, &data);
+            Ok(Saved::Serial)
+        }
+    }
+}
+
+/// Takes a screenshot with the default name, for the PrintScreen key.
+pub fn take() {
+    match save(&default_path()) {
+        Ok(Saved::File(path)) => info!("screenshot: saved to {path}"),
+        Ok(Saved::Serial) => {}
+        Err(e) => warn!("screenshot: {e}"),
+    }
+}
+
+/// The `shot` command: takes a screenshot to PATH or a new file, or over
+/// the serial port.
+pub fn cmd_shot(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let path = match args {
+        ["serial"] => {
+            let data = screenshot()?;
+            dump(&mut SerialPort::default(), "screenshot.bmp", &data);
+            let _ = writeln!(out, "sent {} bytes over the serial port", data.len());
+            return Ok(());
+        }
+        [] => default_path(),
+        [path] => path.to_string(),
+        _ => return Err("usage: shot [PATH | serial]"),
+    };
+    match save(&path)? {
+        Saved::File(path) => {
+            let _ = writeln!(out, "saved to {path}");
+        }
+        Saved::Serial => {
+            let _ = writeln!(out, "could not save it, sent it over the serial port");
+        }
+    }
+    Ok(())
+}
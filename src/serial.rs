@@ -0,0 +1,77 @@
+use crate::arch::read_io_port_u8;
+use crate::arch::write_io_port_u8;
+use core::fmt;
+
+pub const COM1: u16 = 0x3f8;
+
+const THR: u16 = 0; // Transmitter Holding Register
+const RBR: u16 = 0; // Receiver Buffer Register
+const DLL: u16 = 0; // Divisor Latch (LSB)
+const IER: u16 = 1; // Interrupt Enable Register
+const DLM: u16 = 1; // Divisor Latch (MSB)
+const FCR: u16 = 2; // FIFO Control Register
+const LCR: u16 = 3; // Line Control Register
+const MCR: u16 = 4; // Modem Control Register
+const LSR: u16 = 5; // Line Status Register
+
+const LSR_DATA_READY: u8 = 0x01;
+const LSR_THR_EMPTY: u8 = 0x20;
+
+#[derive(Clone, Copy)]
+pub struct SerialPort {
+    base: u16,
+}
+impl SerialPort {
+    pub const fn new(base: u16) -> Self {
+        Self { base }
+    }
+    /// Configures the port for 115200 baud, 8N1 with FIFOs enabled.
+    pub fn init(&self) {
+        write_io_port_u8(self.base + IER, 0x00);
+        write_io_port_u8(self.base + LCR, 0x80);
+        const BAUD_DIVISOR: u16 = 1; // 115200 / 1
+        write_io_port_u8(self.base + DLL, (BAUD_DIVISOR & 0xff) as u8);
+        write_io_port_u8(self.base + DLM, (BAUD_DIVISOR >> 8) as u8);
+        write_io_port_u8(self.base + LCR, 0x03);
+        write_io_port_u8(self.base + FCR, 0xc7);
+        write_io_port_u8(self.base + MCR, 0x0b);
+    }
+    pub fn enable_rx_interrupt(&self) {
+        write_io_port_u8(self.base + IER, 0x01);
+    }
+    pub fn disable_interrupts(&self) {
+        write_io_port_u8(self.base + IER, 0x00);
+    }
+    pub fn send_char(&self, c: u8) {
+        while read_io_port_u8(self.base + LSR) & LSR_THR_EMPTY == 0 {
+            core::hint::spin_loop();
+        }
+        write_io_port_u8(self.base + THR, c)
+    }
+    pub fn has_data(&self) -> bool {
+        read_io_port_u8(self.base + LSR) & LSR_DATA_READY != 0
+    }
+    pub fn try_read(&self) -> Option<u8> {
+        if self.has_data() {
+            Some(read_io_port_u8(self.base + RBR))
+        } else {
+            None
+        }
+    }
+}
+impl Default for SerialPort {
+    fn default() -> Self {
+        Self::new(COM1)
+    }
+}
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.bytes() {
+            if c == b'\n' {
+                self.send_char(b'\r');
+            }
+            self.send_char(c);
+        }
+        Ok(())
+    }
+}
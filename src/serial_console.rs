@@ -0,0 +1,151 @@
+use crate::i18n::Msg;
+use crate::kexec;
+use crate::pic;
+use crate::process;
+use crate::process::Pid;
+use crate::process::ProcessState;
+use crate::result::Result;
+use crate::serial::SerialPort;
+use crate::settings;
+use crate::shell;
+use crate::shell::Shell;
+use crate::shell::ShellAction;
+use crate::tty::Tty;
+use crate::tty::TtyMode;
+use crate::version;
+use alloc::boxed::Box;
+use alloc::string::String;
+use core::fmt::Write;
+
+pub const COM1_IRQ: u8 = 4;
+
+enum State {
+    Login,
+    Password(String),
+    Shell,
+    Foreground(Pid),
+}
+
+/// Runs a login prompt and a shell on a serial port, so that the OS can be
+/// operated over a UART cable (or QEMU's `-serial stdio`).
+///
+/// The credentials are the `serial_user` and `serial_password` settings,
+/// which can also be given on the command line for a boot. The password is
+/// empty by default, so set one before connecting the port to anything
+/// shared.
+pub struct SerialConsole {
+    port: SerialPort,
+    tty: Tty,
+    state: State,
+    shell: Shell,
+}
+fn new_shell() -> Shell {
+    Shell::new(|| Box::<SerialPort>::default())
+}
+
+impl SerialConsole {
+    pub fn new(port: SerialPort) -> Self {
+        Self {
+            port,
+            tty: Tty::new(),
+            state: State::Login,
+            shell: new_shell(),
+        }
+    }
+    /// Enables the receive interrupt so that hlt() wakes up on incoming bytes.
+    pub fn enable_interrupt(&self) -> Result<()> {
+        // 実際の処理はpoll()で行うので、ハンドラはhltから起こすだけでよい
+        pic::register_irq_handler(COM1_IRQ, |_| {})?;
+        self.port.enable_rx_interrupt();
+        kexec::register_shutdown_hook("serial", || SerialPort::default().disable_interrupts());
+        Ok(())
+    }
+    pub fn start(&mut self) {
+        let _ = write!(
+            self.port,
+            "\n{}\n\n{}",
+            version::version(),
+            Msg::LoginPrompt
+        );
+    }
+    /// Processes all the bytes received so far.
+    pub fn poll(&mut self) {
+        while let Some(c) = self.port.try_read() {
+            if let Some(sig) = self.tty.input(c as char, &mut self.port) {
+                if let State::Foreground(_) = self.state {
+                    let _ = process::signal_foreground(sig.into());
+                } else {
+                    if let State::Password(_) = self.state {
+                        // エコーが止まっているので改行を補ってログインからやり直す
+                        self.tty.set_mode(TtyMode::cooked());
+                        self.state = State::Login;
+                        let _ = writeln!(self.port);
+                    }
+                    let _ = write!(self.port, "{}", self.prompt());
+                }
+            }
+        }
+        if let State::Foreground(pid) = self.state {
+            match process::info(pid).map(|p| p.state) {
+                Some(ProcessState::Running) => return,
+                Some(ProcessState::Exited(_)) => {
+                    let _ = process::reap(pid);
+                }
+                None => {}
+            }
+            self.state = State::Shell;
+            let _ = write!(self.port, "{}", self.prompt());
+        }
+        while let Some(line) = self.tty.read_line() {
+            self.handle_line(&line);
+            if let State::Foreground(_) = self.state {
+                break;
+            }
+        }
+    }
+    fn prompt(&self) -> &'static str {
+        match self.state {
+            State::Login => Msg::LoginPrompt.get(),
+            State::Password(_) => Msg::PasswordPrompt.get(),
+            State::Shell => shell::PROMPT,
+            State::Foreground(_) => "",
+        }
+    }
+    fn handle_line(&mut self, line: &str) {
+        self.state = match core::mem::replace(&mut self.state, State::Login) {
+            State::Login if line.is_empty() => State::Login,
+            State::Login => {
+                self.tty.set_mode(TtyMode {
+                    echo: false,
+                    ..TtyMode::cooked()
+                });
+                State::Password(String::from(line))
+            }
+            State::Password(user) => {
+                self.tty.set_mode(TtyMode::cooked());
+                let _ = writeln!(self.port);
+                // 設定の変更がすぐ効くよう、ログインのたびに読む
+                if user == settings::get("serial_user")
+                    && settings::matches_secret("serial_password", line)
+                {
+                    let _ = writeln!(self.port, "{}", Msg::Welcome);
+                    State::Shell
+                } else {
+                    let _ = writeln!(self.port, "{}", Msg::LoginIncorrect);
+                    State::Login
+                }
+            }
+            State::Shell => match self.shell.execute(line, &mut self.port) {
+                ShellAction::Continue => State::Shell,
+                ShellAction::WaitForeground(pid) => State::Foreground(pid),
+                ShellAction::Exit => {
+                    // 次にログインする人に前のセッションのジョブを引き継がない
+                    self.shell = new_shell();
+                    State::Login
+                }
+            },
+            State::Foreground(pid) => State::Foreground(pid),
+        };
+        let _ = write!(self.port, "{}", self.prompt());
+    }
+}
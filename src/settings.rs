@@ -0,0 +1,455 @@
+//! Runtime-changeable options shared by the subsystems.
+//!
+//! Every option has a default and a validator in SETTINGS. Only the values
+//! changed from their defaults are stored, as `key=value` lines in a
+//! non-volatile UEFI variable, and they are written back on every change.
+//! Subsystems read the current value with get() and can register a hook
+//! with on_change() to apply a new value immediately. The kernel command
+//! line can override values for one boot with set_for_boot(). Secret
+//! settings are kept only as a salted SHA-256 hash, which `config` does not
+//! show; check a value against one with matches_secret().
+
+use crate::mutex::Mutex;
+use crate::rand;
+use crate::result::Result;
+use crate::sha256::sha256;
+use crate::uefi;
+use crate::uefi::EFI_VARIABLE_BOOTSERVICE_ACCESS;
+use crate::uefi::EFI_VARIABLE_NON_VOLATILE;
+use crate::uefi::EFI_VARIABLE_RUNTIME_ACCESS;
+use crate::uefi::WASABI_VENDOR_GUID;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+
+const SETTINGS_VARIABLE: &str = "WasabiSettings";
+const MAX_STORED_SIZE: usize = 1024;
+const SECRET_PREFIX: &str = "sha256$";
+
+pub struct Setting {
+    pub key: &'static str,
+    pub default: &'static str,
+    pub help: &'static str,
+    /// Kept as a salted hash and never shown, e.g. a password.
+    pub secret: bool,
+    validate: fn(&str) -> Result<()>,
+}
+
+fn one_of(value: &str, candidates: &[&str], err: &'static str) -> Result<()> {
+    if candidates.contains(&value) {
+        Ok(())
+    } else {
+        Err(err)
+    }
+}
+
+fn validate_resolution(value: &str) -> Result<()> {
+    if value == "auto" {
+        return Ok(());
+    }
+    let (w, h) = value
+        .split_once('x')
+        .ok_or("resolution must be auto or WIDTHxHEIGHT")?;
+    match (w.parse::<u32>(), h.parse::<u32>()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok(()),
+        _ => Err("resolution must be auto or WIDTHxHEIGHT"),
+    }
+}
+
+fn validate_hostname(value: &str) -> Result<()> {
+    let valid = !value.is_empty()
+        && value.len() <= 63
+        && !value.starts_with('-')
+        && value
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'-');
+    if valid {
+        Ok(())
+    } else {
+        Err("hostname must be 1-63 letters, digits or '-'")
+    }
+}
+
+fn validate_ntp_server(value: &str) -> Result<()> {
+    let valid = !value.is_empty()
+        && value.len() <= 253
+        && value
+            .split('.')
+            .all(|label| validate_hostname(label).is_ok());
+    if valid {
+        Ok(())
+    } else {
+        Err("ntp_server must be a host name, an address or off")
+    }
+}
+
+fn validate_serial_user(value: &str) -> Result<()> {
+    let valid = !value.is_empty()
+        && value.len() <= 32
+        && value
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_');
+    if valid {
+        Ok(())
+    } else {
+        Err("serial_user must be 1-32 letters, digits, '-' or '_'")
+    }
+}
+
+fn validate_serial_password(value: &str) -> Result<()> {
+    if value.len() <= 64 && value.bytes().all(|c| c.is_ascii_graphic()) {
+        Ok(())
+    } else {
+        Err("serial_password must be up to 64 printable ASCII characters without spaces")
+    }
+}
+
+pub const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "theme",
+        default: "dark",
+        help: "color theme (dark, light)",
+        secret: false,
+        validate: |v| one_of(v, &["dark", "light"], "theme must be dark or light"),
+    },
+    Setting {
+        key: "keymap",
+        default: "us",
+        help: "keyboard layout (us, jp)",
+        secret: false,
+        validate: |v| one_of(v, &["us", "jp"], "keymap must be us or jp"),
+    },
+    Setting {
+        key: "resolution",
+        default: "auto",
+        help: "screen resolution (auto, WIDTHxHEIGHT)",
+        secret: false,
+        validate: validate_resolution,
+    },
+    Setting {
+        key: "log_level",
+        default: "info",
+        help: "log level (error, warn, info, debug, trace)",
+        secret: false,
+        validate: |v| {
+            one_of(
+                v,
+                &["error", "warn", "info", "debug", "trace"],
+                "log_level must be error, warn, info, debug or trace",
+            )
+        },
+    },
+    Setting {
+        key: "lang",
+        default: "en",
+        help: "language of the messages (en, ja)",
+        secret: false,
+        validate: |v| one_of(v, &["en", "ja"], "lang must be en or ja"),
+    },
+    Setting {
+        key: "hostname",
+        default: "wasabi",
+        help: "host name",
+        secret: false,
+        validate: validate_hostname,
+    },
+    Setting {
+        key: "ntp_server",
+        default: "pool.ntp.org",
+        help: "NTP server to set the clock from (HOST, ADDR or off)",
+        secret: false,
+        validate: validate_ntp_server,
+    },
+    Setting {
+        key: "serial_log",
+        default: "on",
+        help: "log to the serial port (on, off)",
+        secret: false,
+        validate: |v| one_of(v, &["on", "off"], "serial_log must be on or off"),
+    },
+    Setting {
+        key: "serial_user",
+        default: "root",
+        help: "user name to log in on the serial console",
+        secret: false,
+        validate: validate_serial_user,
+    },
+    Setting {
+        key: "serial_password",
+        default: "",
+        help: "password of the serial console (empty: none)",
+        secret: true,
+        validate: validate_serial_password,
+    },
+    Setting {
+        key: "boot_mode",
+        default: "demo",
+        help: "what the screen shows at boot (demo, shell)",
+        secret: false,
+        validate: |v| one_of(v, &["demo", "shell"], "boot_mode must be demo or shell"),
+    },
+    Setting {
+        key: "retro",
+        default: "off",
+        help: "draw the desktop at 1/N resolution, N times larger (off, 2-8)",
+        secret: false,
+        validate: |v| match v {
+            "off" => Ok(()),
+            _ if matches!(v.parse::<u32>(), Ok(2..=8)) => Ok(()),
+            _ => Err("retro must be off or 2-8"),
+        },
+    },
+    Setting {
+        key: "boot_menu_timeout",
+        default: "3",
+        help: "seconds the boot menu waits (0-60, 0 skips it)",
+        secret: false,
+        validate: |v| match v.parse::<u32>() {
+            Ok(0..=60) => Ok(()),
+            _ => Err("boot_menu_timeout must be 0-60"),
+        },
+    },
+];
+
+fn find_setting(key: &str) -> Result<&'static Setting> {
+    SETTINGS
+        .iter()
+        .find(|s| s.key == key)
+        .ok_or("Unknown setting")
+}
+
+fn hash_secret_with(salt: u64, value: &str) -> String {
+    let mut data = salt.to_le_bytes().to_vec();
+    data.extend_from_slice(value.as_bytes());
+    let mut hash = format!("{SECRET_PREFIX}{salt:016x}$");
+    for b in sha256(&data) {
+        hash.push_str(&format!("{b:02x}"));
+    }
+    hash
+}
+
+/// `sha256$SALT$HASH` for a secret, or the empty string for no secret.
+fn hash_secret(value: &str) -> String {
+    if value.is_empty() {
+        String::new()
+    } else {
+        hash_secret_with(rand::random_u64(), value)
+    }
+}
+
+fn secret_salt(hash: &str) -> Option<u64> {
+    let (salt, digest) = hash.strip_prefix(SECRET_PREFIX)?.split_once('$')?;
+    let valid =
+        salt.len() == 16 && digest.len() == 64 && digest.bytes().all(|c| c.is_ascii_hexdigit());
+    if !valid {
+        return None;
+    }
+    u64::from_str_radix(salt, 16).ok()
+}
+
+/// The value to keep for a setting: the value itself, or its hash if the
+/// setting is secret.
+fn value_to_keep(setting: &Setting, value: &str) -> Result<String> {
+    (setting.validate)(value)?;
+    Ok(if setting.secret {
+        hash_secret(value)
+    } else {
+        value.to_string()
+    })
+}
+
+pub type ChangeHook = fn(value: &str);
+
+/// Values changed from their defaults.
+static VALUES: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+/// Values for this boot only, which take precedence over VALUES.
+static OVERRIDES: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+static HOOKS: Mutex<Vec<(&'static str, ChangeHook)>> = Mutex::new(Vec::new());
+
+fn parse(text: &str) -> BTreeMap<String, String> {
+    let mut values = BTreeMap::new();
+    for line in text.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        // 古いバージョンで保存された不正な値や知らないキーは読み飛ばす
+        let Ok(setting) = find_setting(key) else {
+            continue;
+        };
+        let value = if setting.secret && secret_salt(value).is_some() {
+            value.to_string()
+        } else {
+            // ハッシュにする前のバージョンが平文で保存した値も読めるようにする
+            match value_to_keep(setting, value) {
+                Ok(value) => value,
+                Err(_) => continue,
+            }
+        };
+        if value != setting.default {
+            values.insert(key.to_string(), value);
+        }
+    }
+    values
+}
+
+fn serialize(values: &BTreeMap<String, String>) -> String {
+    let mut text = String::new();
+    for (key, value) in values {
+        text.push_str(key);
+        text.push('=');
+        text.push_str(value);
+        text.push('\n');
+    }
+    text
+}
+
+/// Loads the stored values. Call once the EFI context is initialized.
+pub fn load() -> Result<()> {
+    let efi_system_table = uefi::system_table().ok_or("EFI context is not initialized")?;
+    let mut buf = [0u8; MAX_STORED_SIZE];
+    let Some(size) = efi_system_table.runtime_services.get_variable(
+        SETTINGS_VARIABLE,
+        &WASABI_VENDOR_GUID,
+        &mut buf,
+    )?
+    else {
+        return Ok(());
+    };
+    let text = core::str::from_utf8(&buf[..size]).map_err(|_| "Settings are corrupted")?;
+    *VALUES.lock() = parse(text);
+    Ok(())
+}
+
+fn store(values: &BTreeMap<String, String>) -> Result<()> {
+    let efi_system_table = uefi::system_table().ok_or("EFI context is not initialized")?;
+    let text = serialize(values);
+    if text.len() > MAX_STORED_SIZE {
+        return Err("Settings are too large to store");
+    }
+    efi_system_table.runtime_services.set_variable(
+        SETTINGS_VARIABLE,
+        &WASABI_VENDOR_GUID,
+        EFI_VARIABLE_NON_VOLATILE | EFI_VARIABLE_BOOTSERVICE_ACCESS | EFI_VARIABLE_RUNTIME_ACCESS,
+        text.as_bytes(),
+    )
+}
+
+/// Returns the current value of a setting (the hash for a secret one).
+/// Panics if the key is not in SETTINGS.
+pub fn get(key: &str) -> String {
+    let setting = find_setting(key).expect("Unknown setting");
+    if let Some(value) = OVERRIDES.lock().get(key) {
+        return value.clone();
+    }
+    VALUES
+        .lock()
+        .get(key)
+        .cloned()
+        .unwrap_or_else(|| setting.default.to_string())
+}
+
+fn notify(key: &str, value: &str) {
+    let hooks: Vec<ChangeHook> = HOOKS
+        .lock()
+        .iter()
+        .filter(|(k, _)| *k == key)
+        .map(|(_, hook)| *hook)
+        .collect();
+    for hook in hooks {
+        hook(value);
+    }
+}
+
+/// Changes a setting until the next reboot, without storing it.
+pub fn set_for_boot(key: &str, value: &str) -> Result<()> {
+    let value = value_to_keep(find_setting(key)?, value)?;
+    OVERRIDES.lock().insert(key.to_string(), value.clone());
+    notify(key, &value);
+    Ok(())
+}
+
+/// Changes a setting, notifies the hooks and stores the result.
+///
+/// If storing fails, the new value is still in effect until the next reboot.
+pub fn set(key: &str, value: &str) -> Result<()> {
+    let setting = find_setting(key)?;
+    let value = value_to_keep(setting, value)?;
+    OVERRIDES.lock().remove(key);
+    let result = {
+        let mut values = VALUES.lock();
+        if value == setting.default {
+            values.remove(key);
+        } else {
+            values.insert(key.to_string(), value.clone());
+        }
+        store(&values)
+    };
+    notify(key, &value);
+    result
+}
+
+/// Whether value is the current value of a secret setting.
+pub fn matches_secret(key: &str, value: &str) -> bool {
+    let hash = get(key);
+    if hash.is_empty() {
+        return value.is_empty();
+    }
+    let Some(salt) = secret_salt(&hash) else {
+        return false;
+    };
+    // 一致した長さから推測されないよう、最後まで比べる
+    hash_secret_with(salt, value)
+        .bytes()
+        .zip(hash.bytes())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+/// The value of a setting as `config` shows it.
+fn shown_value(setting: &Setting) -> String {
+    let value = get(setting.key);
+    match (setting.secret, value.is_empty()) {
+        (false, _) => value,
+        (true, true) => "(empty)".to_string(),
+        (true, false) => "(set)".to_string(),
+    }
+}
+
+/// Restores the default value of a setting.
+pub fn reset(key: &str) -> Result<()> {
+    set(key, find_setting(key)?.default)
+}
+
+/// Registers a hook called with the new value whenever `key` is changed.
+pub fn on_change(key: &'static str, hook: ChangeHook) {
+    HOOKS.lock().push((key, hook));
+}
+
+/// The `config` command.
+pub fn cmd_config(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    match args {
+        [] => {
+            for s in SETTINGS {
+                let marker = if get(s.key) == s.default { "" } else { " *" };
+                let _ = writeln!(out, "{:12} {}{marker}", s.key, shown_value(s));
+            }
+            Ok(())
+        }
+        ["help"] => {
+            for s in SETTINGS {
+                let _ = writeln!(out, "{:12} {}", s.key, s.help);
+            }
+            Ok(())
+        }
+        ["get", key] => {
+            let _ = writeln!(out, "{}", shown_value(find_setting(key)?));
+            Ok(())
+        }
+        ["set", key, value] => set(key, value),
+        ["reset", key] => reset(key),
+        _ => Err("usage: config [help | get KEY | set KEY VALUE | reset KEY]"),
+    }
+}
@@ -0,0 +1,300 @@
+use crate::ab_boot;
+use crate::acpi;
+use crate::allocator;
+use crate::apic;
+#[cfg(feature = "gui")]
+use crate::bench;
+#[cfg(feature = "storage")]
+use crate::block;
+use crate::chainload;
+#[cfg(feature = "gui")]
+use crate::console;
+#[cfg(feature = "gui")]
+use crate::console::ConsoleWriter;
+use crate::dmesg;
+#[cfg(feature = "gui")]
+use crate::font;
+use crate::i18n::Msg;
+use crate::input_replay;
+use crate::job;
+use crate::job::JobTable;
+use crate::kexec;
+use crate::log;
+use crate::memory_map;
+use crate::mutex::Mutex;
+#[cfg(feature = "net")]
+use crate::net;
+#[cfg(feature = "net")]
+use crate::net::arp;
+#[cfg(feature = "net")]
+use crate::net::dhcp;
+#[cfg(feature = "net")]
+use crate::net::firewall;
+#[cfg(feature = "net")]
+use crate::net::http;
+#[cfg(feature = "net")]
+use crate::net::icmp;
+#[cfg(feature = "net")]
+use crate::net::ntp;
+#[cfg(feature = "gui")]
+use crate::pager::Pager;
+use crate::pci;
+use crate::perf;
+use crate::power;
+use crate::process;
+use crate::process::Pid;
+#[cfg(feature = "gui")]
+use crate::process::ProcessState;
+use crate::result::Result;
+#[cfg(feature = "gui")]
+use crate::screenshot;
+use crate::settings;
+use crate::task;
+use crate::time;
+#[cfg(feature = "usb")]
+use crate::usb;
+use crate::user;
+use crate::version;
+#[cfg(feature = "gui")]
+use crate::wm;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "gui")]
+use core::fmt::Write;
+#[cfg(feature = "gui")]
+use core::time::Duration;
+
+pub const PROMPT: &str = "wsh$ ";
+
+pub enum ShellAction {
+    /// Ready for the next command.
+    Continue,
+    /// Wait until the process exits before showing the next prompt.
+    WaitForeground(Pid),
+    Exit,
+}
+
+pub type CommandFn = fn(args: &[&str], out: &mut dyn fmt::Write) -> Result<()>;
+
+/// Makes a writer to the terminal of the shell, for the background jobs.
+pub type OutputFn = fn() -> Box<dyn fmt::Write>;
+
+#[derive(Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    pub help: Msg,
+    pub run: CommandFn,
+}
+
+/// Commands that work on the state of the shell itself.
+const SHELL_COMMANDS: &[(&str, Msg)] = &[
+    ("help", Msg::HelpHelp),
+    ("jobs", Msg::HelpJobs),
+    ("fg", Msg::HelpFg),
+    ("exit", Msg::HelpExit),
+];
+
+static COMMANDS: Mutex<Vec<Command>> = Mutex::new(Vec::new());
+
+/// Adds a command to all the shells.
+pub fn register_command(name: &'static str, help: Msg, run: CommandFn) -> Result<()> {
+    let mut commands = COMMANDS.lock();
+    if SHELL_COMMANDS.iter().any(|(n, _)| *n == name) || commands.iter().any(|c| c.name == name) {
+        return Err("Command is already registered");
+    }
+    commands.push(Command { name, help, run });
+    Ok(())
+}
+
+fn find_command(name: &str) -> Option<Command> {
+    COMMANDS.lock().iter().find(|c| c.name == name).copied()
+}
+
+/// Registers the commands provided by the kernel itself.
+pub fn init() {
+    let commands: [(&'static str, Msg, CommandFn); 25] = [
+        ("echo", Msg::HelpEcho, cmd_echo),
+        ("clear", Msg::HelpClear, cmd_clear),
+        ("mem", Msg::HelpMem, memory_map::cmd_mem),
+        ("heap", Msg::HelpHeap, allocator::cmd_heap),
+        ("uptime", Msg::HelpUptime, time::cmd_uptime),
+        ("date", Msg::HelpDate, time::cmd_date),
+        ("input", Msg::HelpInput, input_replay::cmd_input),
+        ("lspci", Msg::HelpLspci, pci::cmd_lspci),
+        ("acpi", Msg::HelpAcpi, acpi::cmd_acpi),
+        ("cpus", Msg::HelpCpus, apic::cmd_cpus),
+        ("reboot", Msg::HelpReboot, power::cmd_reboot),
+        ("shutdown", Msg::HelpShutdown, power::cmd_shutdown),
+        ("uname", Msg::HelpUname, version::cmd_uname),
+        ("config", Msg::HelpConfig, settings::cmd_config),
+        ("perf", Msg::HelpPerf, perf::cmd_perf),
+        ("chainload", Msg::HelpChainload, chainload::cmd_chainload),
+        ("kexec", Msg::HelpKexec, kexec::cmd_kexec),
+        ("abboot", Msg::HelpAbboot, ab_boot::cmd_abboot),
+        ("run", Msg::HelpRun, user::cmd_run),
+        ("ps", Msg::HelpPs, process::cmd_ps),
+        ("sched", Msg::HelpSched, task::cmd_sched),
+        ("nice", Msg::HelpNice, process::cmd_nice),
+        ("ulimit", Msg::HelpUlimit, process::cmd_ulimit),
+        ("log", Msg::HelpLog, log::cmd_log),
+        ("dmesg", Msg::HelpDmesg, dmesg::cmd_dmesg),
+    ];
+    for (name, help, run) in commands {
+        let _ = register_command(name, help, run);
+    }
+    #[cfg(feature = "gui")]
+    let _ = register_command("font", Msg::HelpFont, font::cmd_font);
+    #[cfg(feature = "gui")]
+    let _ = register_command("bench", Msg::HelpBench, bench::cmd_bench);
+    #[cfg(feature = "gui")]
+    let _ = register_command("win", Msg::HelpWin, wm::cmd_win);
+    #[cfg(feature = "gui")]
+    let _ = register_command("wallpaper", Msg::HelpWallpaper, wm::cmd_wallpaper);
+    #[cfg(feature = "gui")]
+    let _ = register_command("shot", Msg::HelpShot, screenshot::cmd_shot);
+    #[cfg(feature = "net")]
+    let _ = register_command("fw", Msg::HelpFw, firewall::cmd_fw);
+    #[cfg(feature = "net")]
+    let _ = register_command("ifconfig", Msg::HelpIfconfig, net::cmd_ifconfig);
+    #[cfg(feature = "net")]
+    let _ = register_command("arp", Msg::HelpArp, arp::cmd_arp);
+    #[cfg(feature = "net")]
+    let _ = register_command("dhcp", Msg::HelpDhcp, dhcp::cmd_dhcp);
+    #[cfg(feature = "net")]
+    let _ = register_command("wget", Msg::HelpWget, http::cmd_wget);
+    #[cfg(feature = "net")]
+    let _ = register_command("ntp", Msg::HelpNtp, ntp::cmd_ntp);
+    #[cfg(feature = "net")]
+    let _ = register_command("ping", Msg::HelpPing, icmp::cmd_ping);
+    #[cfg(feature = "storage")]
+    let _ = register_command("blk", Msg::HelpBlk, block::cmd_blk);
+    #[cfg(feature = "usb")]
+    let _ = register_command("lsusb", Msg::HelpLsusb, usb::cmd_lsusb);
+    #[cfg(feature = "usb")]
+    let _ = register_command("usbdisk", Msg::HelpUsbdisk, usb::msc::cmd_usbdisk);
+}
+
+fn cmd_echo(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let _ = writeln!(out, "{}", args.join(" "));
+    Ok(())
+}
+
+fn cmd_clear(_args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    // ESC [2J (画面消去) と ESC [H (カーソルを左上へ)
+    let _ = write!(out, "\x1b[2J\x1b[H");
+    Ok(())
+}
+
+pub struct Shell {
+    jobs: JobTable,
+    output: OutputFn,
+}
+impl Shell {
+    pub const fn new(output: OutputFn) -> Self {
+        Self {
+            jobs: JobTable::new(),
+            output,
+        }
+    }
+    /// Runs the command as a task in its own process group, and tracks it
+    /// as a job.
+    fn spawn_job(&mut self, line: &str, out: &mut dyn fmt::Write) {
+        let args: Vec<String> = line.split_whitespace().map(|a| a.to_string()).collect();
+        let name = &args[0];
+        if SHELL_COMMANDS.iter().any(|(n, _)| n == name) {
+            let _ = writeln!(out, "wsh: {name}: {}", Msg::NoBackgroundBuiltin);
+            return;
+        }
+        let Some(c) = find_command(name) else {
+            let _ = writeln!(out, "wsh: {name}: {}", Msg::CommandNotFound);
+            return;
+        };
+        let output = self.output;
+        let pid = task::spawn(c.name, move || {
+            let args: Vec<&str> = args[1..].iter().map(|a| a.as_str()).collect();
+            let mut out = output();
+            if let Err(e) = (c.run)(&args, &mut *out) {
+                let _ = writeln!(out, "{e}");
+            }
+        });
+        let _ = process::set_group(pid, pid);
+        let id = self.jobs.add(pid, line);
+        let _ = writeln!(out, "[{id}] {pid}");
+    }
+    pub fn execute(&mut self, line: &str, out: &mut dyn fmt::Write) -> ShellAction {
+        self.jobs.reap_finished(out);
+        let (line, background) = job::parse_background(line);
+        let args: Vec<&str> = line.split_whitespace().collect();
+        if args.is_empty() {
+            return ShellAction::Continue;
+        }
+        if background {
+            self.spawn_job(line, out);
+            return ShellAction::Continue;
+        }
+        match args.as_slice() {
+            ["help"] => {
+                for (name, help) in SHELL_COMMANDS {
+                    let _ = writeln!(out, "  {name:10} {help}");
+                }
+                for c in COMMANDS.lock().iter() {
+                    let _ = writeln!(out, "  {:10} {}", c.name, c.help);
+                }
+            }
+            ["jobs"] => self.jobs.cmd_jobs(out),
+            ["fg", rest @ ..] => match self.jobs.cmd_fg(rest.first().copied(), out) {
+                Ok(pid) => return ShellAction::WaitForeground(pid),
+                Err(e) => {
+                    let _ = writeln!(out, "{e}");
+                }
+            },
+            ["exit"] => return ShellAction::Exit,
+            [name, rest @ ..] => match find_command(name) {
+                Some(c) => {
+                    if let Err(e) = (c.run)(rest, out) {
+                        let _ = writeln!(out, "{e}");
+                    }
+                }
+                None => {
+                    let _ = writeln!(out, "wsh: {name}: {}", Msg::CommandNotFound);
+                }
+            },
+            [] => {}
+        }
+        ShellAction::Continue
+    }
+}
+
+/// Runs a shell on the screen console.
+#[cfg(feature = "gui")]
+pub async fn run_on_console() {
+    let mut shell = Shell::new(|| Box::new(ConsoleWriter));
+    let mut out = ConsoleWriter;
+    loop {
+        let _ = write!(out, "{PROMPT}");
+        let line = console::read_line().await;
+        let mut pager = Pager::new(&mut out, console::size());
+        let action = shell.execute(&line, &mut pager);
+        pager.finish().await;
+        match action {
+            ShellAction::Continue => {}
+            ShellAction::WaitForeground(pid) => loop {
+                match process::info(pid).map(|p| p.state) {
+                    Some(ProcessState::Running) => time::sleep(Duration::from_millis(10)).await,
+                    Some(ProcessState::Exited(_)) => {
+                        let _ = process::reap(pid);
+                        break;
+                    }
+                    None => break,
+                }
+            },
+            ShellAction::Exit => {
+                shell = Shell::new(|| Box::new(ConsoleWriter));
+                console::clear();
+            }
+        }
+    }
+}
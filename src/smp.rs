@@ -0,0 +1,328 @@
+//! Starting the application processors (APs).
+//!
+//! Each AP is woken with the INIT-SIPI-SIPI sequence and starts in real
+//! mode at a trampoline copied to a page below 1 MiB. The trampoline goes
+//! through protected mode into long mode with the page table of the BSP,
+//! switches to the GDT of the BSP and jumps to ap_entry() on a stack of its
+//! own. There the AP installs its PerCpu block, gets its own GDT (with a
+//! TSS) and IDT, and then idles with its Local APIC enabled.
+//!
+//! The APs are started one at a time, as they share the trampoline page.
+
+use crate::apic;
+use crate::arch::read_cr0;
+use crate::arch::read_cr3;
+use crate::arch::read_cr4;
+use crate::arch::read_cs;
+use crate::arch::read_gdtr;
+use crate::arch::read_msr;
+use crate::arch::sti_and_hlt;
+use crate::arch::DescriptorTablePointer;
+use crate::fpu;
+use crate::gdt;
+use crate::interrupt;
+use crate::kexec;
+use crate::memory_map;
+use crate::mutex::Mutex;
+use crate::percpu;
+use crate::percpu::PerCpu;
+use crate::result::Result;
+use crate::stack::Stack;
+use crate::syscall;
+use crate::time;
+use crate::uefi::EfiMemoryType;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::arch::global_asm;
+use core::mem::offset_of;
+use core::mem::size_of;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+const AP_STACK_SIZE: usize = 64 * 1024;
+const PAGE_SIZE: u64 = 4096;
+/// The trampoline must be in a page that a SIPI vector can point to.
+const TRAMPOLINE_LIMIT: u64 = 0xa0000;
+
+const MSR_EFER: u32 = 0xc000_0080;
+const EFER_LME: u64 = 1 << 8;
+const EFER_NXE: u64 = 1 << 11;
+const CR4_PCIDE: u64 = 1 << 17;
+
+// 16ビットのリアルモードで始まり、32ビットを経て64ビットモードに入る。
+// ebx/rbxにはトランポリンの物理アドレスを入れておき、データはそこからの相対位置で読む
+global_asm!(
+    ".global wasabi_ap_trampoline_start",
+    ".global wasabi_ap_trampoline_end",
+    ".global wasabi_ap_trampoline_jump32",
+    ".global wasabi_ap_trampoline_protected",
+    ".global wasabi_ap_trampoline_long",
+    ".code16",
+    "wasabi_ap_trampoline_start:",
+    "cli",
+    "cld",
+    "mov ax, cs",
+    "mov ds, ax",
+    "xor ebx, ebx",
+    "mov bx, ax",
+    "shl ebx, 4",
+    "lgdt [0x200 + 32]",
+    "mov eax, cr0",
+    "or eax, 1",
+    "mov cr0, eax",
+    // jmp far 0x08:(32ビットのコード)。飛び先はRust側で書き込む
+    ".byte 0x66, 0xea",
+    "wasabi_ap_trampoline_jump32:",
+    ".long 0",
+    ".word 0x08",
+    ".code32",
+    "wasabi_ap_trampoline_protected:",
+    "mov ax, 0x10",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "mov eax, [ebx + 0x200 + 64]",
+    "mov cr4, eax",
+    "mov eax, [ebx + 0x200 + 56]",
+    "mov cr3, eax",
+    "mov ecx, 0xc0000080",
+    "mov eax, [ebx + 0x200 + 72]",
+    "xor edx, edx",
+    "wrmsr",
+    "mov eax, [ebx + 0x200 + 80]",
+    "mov cr0, eax",
+    "mov eax, [ebx + 0x200 + 116]",
+    "push 0x18",
+    "push eax",
+    "retf",
+    ".code64",
+    "wasabi_ap_trampoline_long:",
+    "lgdt [rbx + 0x200 + 40]",
+    "movzx eax, word ptr [rbx + 0x200 + 114]",
+    "mov ds, ax",
+    "mov es, ax",
+    "mov ss, ax",
+    "xor eax, eax",
+    "mov fs, ax",
+    "mov gs, ax",
+    "mov rsp, [rbx + 0x200 + 88]",
+    "mov rdi, [rbx + 0x200 + 104]",
+    // ap_entry()がcallされたときと同じアラインメントにするためのダミーの戻りアドレス
+    "push 0",
+    "movzx eax, word ptr [rbx + 0x200 + 112]",
+    "push rax",
+    "push qword ptr [rbx + 0x200 + 96]",
+    "retfq",
+    ".balign 16",
+    "wasabi_ap_trampoline_end:",
+);
+extern "C" {
+    static wasabi_ap_trampoline_start: u8;
+    static wasabi_ap_trampoline_jump32: u8;
+    static wasabi_ap_trampoline_protected: u8;
+    static wasabi_ap_trampoline_long: u8;
+    static wasabi_ap_trampoline_end: u8;
+}
+
+/// The offset of TrampolineData in the trampoline page, used in the asm.
+const DATA_OFFSET: usize = 0x200;
+
+/// Parameters for the trampoline, written after its code. The offsets are
+/// hard-coded in the asm above.
+#[repr(C, packed)]
+struct TrampolineData {
+    gdt: [u64; 4],
+    gdtr_limit: u16,
+    gdtr_base: u32,
+    _pad0: u16,
+    kernel_gdtr: DescriptorTablePointer,
+    _pad1: [u8; 6],
+    cr3: u64,
+    cr4: u64,
+    efer: u64,
+    cr0: u64,
+    stack: u64,
+    entry: u64,
+    arg: u64,
+    cs: u16,
+    ss: u16,
+    /// The address of the 64-bit part of the trampoline.
+    long_mode: u32,
+}
+const _: () = assert!(offset_of!(TrampolineData, gdtr_limit) == 32);
+const _: () = assert!(offset_of!(TrampolineData, kernel_gdtr) == 40);
+const _: () = assert!(offset_of!(TrampolineData, cr3) == 56);
+const _: () = assert!(offset_of!(TrampolineData, cr4) == 64);
+const _: () = assert!(offset_of!(TrampolineData, efer) == 72);
+const _: () = assert!(offset_of!(TrampolineData, cr0) == 80);
+const _: () = assert!(offset_of!(TrampolineData, stack) == 88);
+const _: () = assert!(offset_of!(TrampolineData, entry) == 96);
+const _: () = assert!(offset_of!(TrampolineData, arg) == 104);
+const _: () = assert!(offset_of!(TrampolineData, cs) == 112);
+const _: () = assert!(offset_of!(TrampolineData, ss) == 114);
+const _: () = assert!(offset_of!(TrampolineData, long_mode) == 116);
+const _: () = assert!(DATA_OFFSET + size_of::<TrampolineData>() <= PAGE_SIZE as usize);
+
+/// null, 32-bit code (0x08), data (0x10), 64-bit code (0x18)
+const TRAMPOLINE_GDT: [u64; 4] = [
+    0,
+    0x00cf_9a00_0000_ffff,
+    0x00cf_9200_0000_ffff,
+    0x00af_9a00_0000_ffff,
+];
+
+static CPUS: Mutex<Vec<&'static PerCpu>> = Mutex::new(Vec::new());
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// All the CPUs that have been started, including the BSP.
+pub fn cpus() -> Vec<&'static PerCpu> {
+    CPUS.lock().clone()
+}
+
+pub fn is_online(apic_id: u32) -> bool {
+    CPUS.lock()
+        .iter()
+        .any(|c| c.apic_id == apic_id && c.is_online())
+}
+
+fn read_ss() -> u16 {
+    let ss: u16;
+    // SAFETY: reading a segment register has no side effect
+    unsafe { asm!("mov {0:x}, ss", out(reg) ss) };
+    ss
+}
+
+extern "sysv64" fn ap_entry(cpu: &'static PerCpu) -> ! {
+    percpu::install(cpu);
+    fpu::init();
+    gdt::init();
+    syscall::init();
+    interrupt::init_ap();
+    apic::enable_local_apic();
+    cpu.set_online(true);
+    loop {
+        sti_and_hlt();
+    }
+}
+
+/// Finds a free page for the trampoline in the memory map at boot.
+fn find_trampoline_page() -> Result<u64> {
+    let summary = memory_map::summary().ok_or("The memory map is not available")?;
+    summary
+        .regions
+        .iter()
+        .filter(|r| r.memory_type == EfiMemoryType::CONVENTIONAL_MEMORY)
+        .filter_map(|r| {
+            // 0番地のページはnullポインタの検出に使われていることがあるので避ける
+            let start = r.start.max(PAGE_SIZE);
+            let end = r.end().min(TRAMPOLINE_LIMIT) & !(PAGE_SIZE - 1);
+            (start + PAGE_SIZE <= end).then_some(end - PAGE_SIZE)
+        })
+        .max()
+        .ok_or("No free page below 640 KiB for the AP trampoline")
+}
+
+/// Copies the trampoline to the page and fills in the parameters that are
+/// the same for all the APs.
+///
+/// # Safety
+/// The page must be free, identity mapped and below 1 MiB.
+unsafe fn install_trampoline(page: u64) -> Result<*mut TrampolineData> {
+    let start = &wasabi_ap_trampoline_start as *const u8;
+    let len = &wasabi_ap_trampoline_end as *const u8 as usize - start as usize;
+    if len > DATA_OFFSET {
+        return Err("The AP trampoline is too large");
+    }
+    let cr3 = read_cr3() & !0xfff;
+    if cr3 >= 1 << 32 {
+        return Err("The page table is above 4 GiB");
+    }
+    core::ptr::copy_nonoverlapping(start, page as *mut u8, len);
+    let offset_of_label = |label: &u8| label as *const u8 as u64 - start as u64;
+    let jump32 = (page + offset_of_label(&wasabi_ap_trampoline_jump32)) as *mut u32;
+    jump32.write_unaligned((page + offset_of_label(&wasabi_ap_trampoline_protected)) as u32);
+    let data = (page as usize + DATA_OFFSET) as *mut TrampolineData;
+    data.write(TrampolineData {
+        gdt: TRAMPOLINE_GDT,
+        gdtr_limit: (size_of::<[u64; 4]>() - 1) as u16,
+        gdtr_base: (page as usize + DATA_OFFSET) as u32,
+        _pad0: 0,
+        kernel_gdtr: read_gdtr(),
+        _pad1: [0; 6],
+        cr3,
+        cr4: read_cr4() & !CR4_PCIDE,
+        efer: read_msr(MSR_EFER) & (EFER_LME | EFER_NXE) | EFER_LME,
+        cr0: read_cr0(),
+        stack: 0,
+        entry: ap_entry as usize as u64,
+        arg: 0,
+        cs: read_cs(),
+        ss: read_ss(),
+        long_mode: (page + offset_of_label(&wasabi_ap_trampoline_long)) as u32,
+    });
+    Ok(data)
+}
+
+fn wait_online(cpu: &PerCpu, timeout: Duration) -> bool {
+    let deadline = time::now_ns() + timeout.as_nanos() as u64;
+    while time::now_ns() < deadline {
+        if cpu.is_online() {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    cpu.is_online()
+}
+
+/// Starts all the APs listed in the MADT. Returns the number of APs that
+/// came online. Call after apic::init() and time::init().
+pub fn init() -> Result<usize> {
+    if STARTED.swap(true, Ordering::SeqCst) {
+        return Err("The APs are already started");
+    }
+    let info = apic::info().ok_or("The MADT is not available")?;
+    interrupt::set_handler(apic::SPURIOUS_VECTOR, apic::spurious_handler);
+    apic::enable_local_apic();
+    CPUS.lock().push(percpu::this());
+    let aps: Vec<u32> = info.application_processors().map(|c| c.apic_id).collect();
+    if aps.is_empty() {
+        return Ok(0);
+    }
+    let page = find_trampoline_page()?;
+    // SAFETY: the page is conventional memory below 640 KiB, which the
+    // kernel does not use otherwise
+    let data = unsafe { install_trampoline(page)? };
+    let sipi = apic::ICR_STARTUP | (page / PAGE_SIZE) as u32;
+    let mut started = 0;
+    for (i, apic_id) in aps.into_iter().enumerate() {
+        let cpu = PerCpu::new(i + 1, apic_id);
+        let stack = Box::leak(Box::new(Stack::new(AP_STACK_SIZE)));
+        // SAFETY: data points to the trampoline installed above
+        unsafe {
+            (*data).stack = stack.top();
+            (*data).arg = cpu as *const PerCpu as u64;
+        }
+        apic::send_ipi(apic_id, apic::ICR_INIT);
+        time::spin_wait(Duration::from_millis(10));
+        apic::send_ipi(apic_id, sipi);
+        if !wait_online(cpu, Duration::from_micros(200)) {
+            apic::send_ipi(apic_id, sipi);
+        }
+        CPUS.lock().push(cpu);
+        if wait_online(cpu, Duration::from_millis(100)) {
+            started += 1;
+        } else {
+            // 応答しなかったAPが後からトランポリンを実行しないように止めておく
+            apic::send_ipi(apic_id, apic::ICR_INIT);
+        }
+    }
+    kexec::register_shutdown_hook("smp", || {
+        for cpu in cpus().iter().filter(|c| c.index != 0) {
+            apic::send_ipi(cpu.apic_id, apic::ICR_INIT);
+            cpu.set_online(false);
+        }
+    });
+    Ok(started)
+}
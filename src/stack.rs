@@ -0,0 +1,114 @@
+//! Kernel stacks with a guard page.
+//!
+//! The page below each stack is unmapped, so that running off the end of
+//! it (e.g. by deep recursion) faults instead of silently overwriting the
+//! memory below. The CPU then faults again while pushing the exception
+//! frame, which turns it into a double fault; its handler runs on its own
+//! stack (see gdt.rs) and tells that a guard page was hit.
+
+use crate::mutex::Mutex;
+use crate::paging;
+use crate::paging::PAGE_SIZE;
+use alloc::alloc::alloc;
+use alloc::alloc::dealloc;
+use alloc::alloc::handle_alloc_error;
+use alloc::alloc::Layout;
+use alloc::vec::Vec;
+use core::arch::asm;
+
+/// The stack that efi_main() moves to from the one of the firmware.
+pub const BOOT_STACK_SIZE: usize = 256 * 1024;
+
+/// The guard pages of the live stacks.
+static GUARD_PAGES: Mutex<Vec<u64>> = Mutex::new(Vec::new());
+
+pub struct Stack {
+    /// The guard page, followed by the stack.
+    base: *mut u8,
+    size: usize,
+    guarded: bool,
+}
+// SAFETY: the stack is only used by the task that owns it
+unsafe impl Send for Stack {}
+impl Stack {
+    /// Allocates a stack of size bytes (rounded up to pages) from the heap.
+    /// It has no guard page if paging::init() could not take over the page
+    /// table.
+    pub fn new(size: usize) -> Self {
+        let size = size.div_ceil(PAGE_SIZE as usize) * PAGE_SIZE as usize;
+        let layout = Self::layout(size);
+        // SAFETY: the layout has a non-zero size
+        let base = unsafe { alloc(layout) };
+        if base.is_null() {
+            handle_alloc_error(layout);
+        }
+        let guarded = paging::unmap_kernel_page(base as u64).is_ok();
+        if guarded {
+            GUARD_PAGES.lock().push(base as u64);
+        }
+        Self {
+            base,
+            size,
+            guarded,
+        }
+    }
+    fn layout(size: usize) -> Layout {
+        Layout::from_size_align(size + PAGE_SIZE as usize, PAGE_SIZE as usize).unwrap()
+    }
+    /// The initial rsp, which is 16-byte aligned.
+    pub fn top(&self) -> u64 {
+        self.base as u64 + PAGE_SIZE + self.size as u64
+    }
+    pub fn size(&self) -> usize {
+        self.size
+    }
+    pub fn guard_page(&self) -> Option<u64> {
+        self.guarded.then_some(self.base as u64)
+    }
+}
+impl Drop for Stack {
+    fn drop(&mut self) {
+        if self.guarded {
+            GUARD_PAGES.lock().retain(|p| *p != self.base as u64);
+            // ヒープに返す前に元どおり読み書きできるようにする
+            paging::remap_kernel_page(self.base as u64).expect("Failed to remap a guard page");
+        }
+        // SAFETY: base was allocated in new() with the same layout
+        unsafe { dealloc(self.base, Self::layout(self.size)) }
+    }
+}
+
+/// Whether addr is in the guard page of a stack. Does not wait for the
+/// lock, as it is called from the double fault handler.
+pub fn is_guard_page(addr: u64) -> bool {
+    let page = addr & !(PAGE_SIZE - 1);
+    GUARD_PAGES
+        .try_lock()
+        .is_some_and(|pages| pages.contains(&page))
+}
+
+/// Moves to stack and calls f there, which must not return. The old stack
+/// is left as it is, so whatever f borrows from it stays valid.
+pub fn run_on<F: FnOnce()>(stack: &'static Stack, f: F) -> ! {
+    extern "sysv64" fn trampoline<F: FnOnce()>(f: *mut Option<F>) -> ! {
+        // SAFETY: f points to the Option on the old stack, which is never
+        // popped
+        let f = unsafe { (*f).take() }.expect("run_on() entered twice");
+        f();
+        panic!("Returned from the function run on a new stack");
+    }
+    let mut f = Some(f);
+    // SAFETY: the stack is alive forever, and trampoline never returns
+    unsafe {
+        asm!(
+            "mov rsp, {top}",
+            "xor ebp, ebp",
+            "call {trampoline}",
+            "ud2",
+            top = in(reg) stack.top(),
+            trampoline = sym trampoline::<F>,
+            in("rdi") &mut f as *mut Option<F>,
+            options(noreturn)
+        )
+    }
+}
@@ -0,0 +1,302 @@
+//! The syscall interface of user programs.
+//!
+//! A program puts the syscall number in rax and the arguments in rdi, rsi,
+//! rdx, r10, r8 and r9 (as on Linux), executes `syscall`, and gets the
+//! result in rax. u64::MAX (-1) means an error. rcx and r11 are clobbered
+//! by the instruction itself; the other registers, including the x87 and
+//! SSE state, are preserved.
+
+use crate::arch::cli;
+use crate::arch::read_msr;
+use crate::arch::sti;
+use crate::arch::write_msr;
+use crate::clipboard;
+use crate::gdt;
+use crate::graphics::fill_rect;
+use crate::mutex::Mutex;
+use crate::paging;
+use crate::percpu;
+use crate::result::Result;
+use crate::uefi;
+use crate::uefi::VramBefferInfo;
+use crate::user;
+use crate::user::file::Fd;
+use crate::user::file::File;
+use crate::user::file::STDIN;
+use crate::user::message_queue;
+use crate::user::pipe;
+use crate::user::process;
+use crate::user::process::Process;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+
+pub const SYS_EXIT: u64 = 0;
+pub const SYS_WRITE: u64 = 1;
+pub const SYS_GETCHAR: u64 = 2;
+pub const SYS_DRAW_RECT: u64 = 3;
+pub const SYS_READ: u64 = 4;
+pub const SYS_SPAWN: u64 = 5;
+pub const SYS_WAIT: u64 = 6;
+pub const SYS_CLOSE: u64 = 7;
+pub const SYS_PIPE: u64 = 8;
+pub const SYS_DUP2: u64 = 9;
+pub const SYS_MQ_OPEN: u64 = 10;
+pub const SYS_CLIPBOARD_COPY: u64 = 11;
+pub const SYS_CLIPBOARD_PASTE: u64 = 12;
+
+const ERROR: u64 = u64::MAX;
+/// Longer reads and writes are cut short, and the program is told how much
+/// is done.
+const MAX_IO: usize = 64 * 1024;
+const MAX_PATH: usize = 4096;
+
+const MSR_EFER: u32 = 0xc000_0080;
+const MSR_STAR: u32 = 0xc000_0081;
+const MSR_LSTAR: u32 = 0xc000_0082;
+const MSR_FMASK: u32 = 0xc000_0084;
+const EFER_SCE: u64 = 1 << 0;
+/// TF, IF, DF and AC are cleared on entering the kernel.
+const FMASK: u64 = 1 << 8 | 1 << 9 | 1 << 10 | 1 << 18;
+
+// The offsets of PerCpu::user_rsp and PerCpu::kernel_stack are hard-coded
+// below, as global_asm! cannot take constants.
+const _: () = assert!(percpu::OFFSET_USER_RSP == 8);
+const _: () = assert!(percpu::OFFSET_KERNEL_STACK == 16);
+
+// ユーザーのGSはあてにならないので、swapgsでPerCpuを取り戻してから
+// IA32_KERNEL_GS_BASEもPerCpuに戻しておく (sysretの前にswapgsはしない)。
+// カーネルもSSEを使うので、ユーザーのx87/SSEの状態はフレームの下に退避する。
+// フレームは80バイトなので、rspは16バイト境界のまま
+global_asm!(
+    ".global wasabi_syscall_entry",
+    "wasabi_syscall_entry:",
+    "swapgs",
+    "mov gs:[8], rsp",
+    "mov rsp, gs:[16]",
+    "push qword ptr gs:[8]",
+    "push rcx",
+    "push r11",
+    "push r9",
+    "push r8",
+    "push r10",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rax",
+    "sub rsp, 512",
+    "fxsave64 [rsp]",
+    "mov ecx, 0xc0000102",
+    "mov rax, gs:[0]",
+    "mov rdx, rax",
+    "shr rdx, 32",
+    "wrmsr",
+    "lea rdi, [rsp + 512]",
+    "call wasabi_syscall_handler",
+    // ユーザーのスタックに戻ってから割り込まれないように
+    "cli",
+    "fxrstor64 [rsp]",
+    "add rsp, 512",
+    "pop rax",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop r10",
+    "pop r8",
+    "pop r9",
+    "pop r11",
+    "pop rcx",
+    "pop rsp",
+    "sysretq",
+);
+extern "sysv64" {
+    fn wasabi_syscall_entry();
+}
+
+/// The registers pushed by wasabi_syscall_entry.
+#[repr(C)]
+struct SyscallFrame {
+    /// The syscall number, and the result on return.
+    rax: u64,
+    args: [u64; 6],
+    /// r11 (rflags), rcx (rip) and rsp of the user, restored by sysretq.
+    _user: [u64; 3],
+}
+
+/// Enables syscall on the running CPU. Call after gdt::init().
+pub fn init() {
+    // SAFETY: the selectors are the ones set up by gdt::init(), and the
+    // entry point switches to a kernel stack before using the stack
+    unsafe {
+        write_msr(MSR_EFER, read_msr(MSR_EFER) | EFER_SCE);
+        write_msr(
+            MSR_STAR,
+            (gdt::SYSRET_BASE as u64) << 48 | (gdt::KERNEL_CS as u64) << 32,
+        );
+        write_msr(MSR_LSTAR, wasabi_syscall_entry as usize as u64);
+        write_msr(MSR_FMASK, FMASK);
+    }
+}
+
+#[no_mangle]
+extern "sysv64" fn wasabi_syscall_handler(frame: &mut SyscallFrame) {
+    sti();
+    let result = match frame.rax {
+        SYS_EXIT => user::exit(frame.args[0] as i64),
+        SYS_WRITE => sys_write(frame.args[0], frame.args[1], frame.args[2] as usize),
+        SYS_GETCHAR => sys_getchar(),
+        SYS_DRAW_RECT => sys_draw_rect(&frame.args),
+        SYS_READ => sys_read(frame.args[0], frame.args[1], frame.args[2] as usize),
+        SYS_SPAWN => sys_spawn(&frame.args),
+        SYS_WAIT => sys_wait(frame.args[0]),
+        SYS_CLOSE => sys_close(frame.args[0]),
+        SYS_PIPE => sys_pipe(frame.args[0]),
+        SYS_DUP2 => sys_dup2(frame.args[0], frame.args[1]),
+        SYS_MQ_OPEN => sys_mq_open(frame.args[0], frame.args[1]),
+        SYS_CLIPBOARD_COPY => sys_clipboard_copy(frame.args[0], frame.args[1] as usize),
+        SYS_CLIPBOARD_PASTE => sys_clipboard_paste(frame.args[0], frame.args[1] as usize),
+        _ => Err("Unknown syscall"),
+    };
+    frame.rax = result.unwrap_or(ERROR);
+    cli();
+}
+
+fn current_process() -> Result<Arc<Process>> {
+    process::current().ok_or("Not a user process")
+}
+
+fn current_file(fd: u64) -> Result<Arc<dyn File>> {
+    let file = current_process()?.files().lock().get(fd as Fd)?;
+    Ok(file)
+}
+
+/// write(fd, buf, len) -> written
+fn sys_write(fd: u64, buf: u64, len: usize) -> Result<u64> {
+    let data = paging::copy_from_user(buf, len.min(MAX_IO))?;
+    Ok(current_file(fd)?.write(&data)? as u64)
+}
+
+/// read(fd, buf, len) -> read, 0 at the end of the file
+fn sys_read(fd: u64, buf: u64, len: usize) -> Result<u64> {
+    let file = current_file(fd)?;
+    let mut data = vec![0u8; len.min(MAX_IO)];
+    let n = file.read(&mut data)?;
+    paging::copy_to_user(buf, &data[..n])?;
+    Ok(n as u64)
+}
+
+/// getchar() -> the next byte of stdin, or -1 at the end
+fn sys_getchar() -> Result<u64> {
+    let mut c = [0u8];
+    match current_file(STDIN as u64)?.read(&mut c)? {
+        0 => Err("End of file"),
+        _ => Ok(c[0] as u64),
+    }
+}
+
+fn user_str(ptr: u64, len: u64) -> Result<String> {
+    let bytes = paging::copy_from_user(ptr, (len as usize).min(MAX_PATH))?;
+    String::from_utf8(bytes).or(Err("Invalid UTF-8"))
+}
+
+/// spawn(path, path_len, args, args_len) -> pid. args are separated by
+/// spaces, and the path is passed as argv[0].
+fn sys_spawn(args: &[u64; 6]) -> Result<u64> {
+    let path = user_str(args[0], args[1])?;
+    let rest = user_str(args[2], args[3])?;
+    let argv: Vec<&str> = core::iter::once(path.as_str())
+        .chain(rest.split_whitespace())
+        .collect();
+    process::spawn(&path, &argv)
+}
+
+/// wait(pid) -> exit code
+fn sys_wait(pid: u64) -> Result<u64> {
+    Ok(process::wait(pid)? as u64)
+}
+
+/// close(fd) -> 0
+fn sys_close(fd: u64) -> Result<u64> {
+    current_process()?.files().lock().close(fd as Fd)?;
+    Ok(0)
+}
+
+/// pipe(fds) -> 0, with the read end in fds[0] and the write end in fds[1]
+/// (as u64)
+fn sys_pipe(fds: u64) -> Result<u64> {
+    let process = current_process()?;
+    let (reader, writer) = pipe::pipe();
+    let mut files = process.files().lock();
+    let read_fd = files.open(reader);
+    let write_fd = files.open(writer);
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&(read_fd as u64).to_le_bytes());
+    bytes[8..].copy_from_slice(&(write_fd as u64).to_le_bytes());
+    if let Err(e) = paging::copy_to_user(fds, &bytes) {
+        let _ = files.close(read_fd);
+        let _ = files.close(write_fd);
+        return Err(e);
+    }
+    Ok(0)
+}
+
+/// dup2(fd, new_fd) -> new_fd, making new_fd refer to the file of fd
+fn sys_dup2(fd: u64, new_fd: u64) -> Result<u64> {
+    const MAX_FDS: u64 = 1024;
+    if new_fd >= MAX_FDS {
+        return Err("Bad file descriptor");
+    }
+    let process = current_process()?;
+    let mut files = process.files().lock();
+    let file = files.get(fd as Fd)?;
+    files.set(new_fd as Fd, file);
+    Ok(new_fd)
+}
+
+/// mq_open(name, name_len) -> fd of the message queue
+fn sys_mq_open(name: u64, len: u64) -> Result<u64> {
+    let name = user_str(name, len)?;
+    let queue = message_queue::open(&name);
+    Ok(current_process()?.files().lock().open(queue) as u64)
+}
+
+/// clipboard_copy(text, len) -> 0
+fn sys_clipboard_copy(text: u64, len: usize) -> Result<u64> {
+    if len > clipboard::MAX_LEN {
+        return Err("Too long for the clipboard");
+    }
+    let bytes = paging::copy_from_user(text, len)?;
+    let text = String::from_utf8(bytes).or(Err("Invalid UTF-8"))?;
+    clipboard::copy(&text)?;
+    Ok(0)
+}
+
+/// clipboard_paste(buf, len) -> the length of the text on the clipboard,
+/// of which as much as fits is copied to buf
+fn sys_clipboard_paste(buf: u64, len: usize) -> Result<u64> {
+    let text = clipboard::paste();
+    let n = text.len().min(len);
+    paging::copy_to_user(buf, &text.as_bytes()[..n])?;
+    Ok(text.len() as u64)
+}
+
+struct FrameBuffer(Option<VramBefferInfo>);
+// SAFETY: the VRAM pointer is only accessed with the lock held
+unsafe impl Send for FrameBuffer {}
+
+static FRAME_BUFFER: Mutex<FrameBuffer> = Mutex::new(FrameBuffer(None));
+
+/// draw_rect(x, y, width, height, color)
+fn sys_draw_rect(args: &[u64; 6]) -> Result<u64> {
+    let mut fb = FRAME_BUFFER.lock();
+    if fb.0.is_none() {
+        let efi_system_table = uefi::system_table().ok_or("EFI context is not initialized")?;
+        fb.0 = Some(uefi::init_vram(efi_system_table)?);
+    }
+    let vram = fb.0.as_mut().ok_or("No frame buffer")?;
+    let [x, y, w, h, color, _] = args.map(|a| a as i64);
+    fill_rect(vram, color as u32, x, y, w, h)?;
+    Ok(0)
+}
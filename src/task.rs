@@ -0,0 +1,335 @@
+//! Cooperative kernel tasks.
+//!
+//! Each task has its own stack (with a guard page, see stack.rs) and runs until it calls yield_now() (or
+//! returns), then the ready task picked by the fair scheduler (see
+//! scheduler.rs) is resumed.
+//! Tasks are registered in the process table, so their pid can be used with
+//! process::send_signal(); a task with a pending signal is ended when it
+//! calls yield_now(), or dropped instead of resumed. A task joins the
+//! process group of the task that spawned it.
+//!
+//! Each CPU has its own run queue in its PerCpu block, and the functions
+//! here work on the queue of the CPU that calls them.
+//!
+//! A task running a user program has its own address space and kernel
+//! stack for entering the kernel (see user.rs), which are switched along
+//! with the task.
+
+use crate::arch::read_cr3;
+use crate::arch::write_cr3;
+use crate::fpu::FpuState;
+use crate::mutex::Mutex;
+use crate::paging;
+use crate::percpu;
+use crate::process;
+use crate::process::Pid;
+use crate::result::Result;
+use crate::scheduler;
+use crate::scheduler::Scheduler;
+use crate::scheduler::SchedulerConfig;
+use crate::stack::Stack;
+use crate::time;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::fmt;
+
+const TASK_STACK_SIZE: usize = 128 * 1024;
+
+// 呼び出し先保存レジスタ(sysv64)だけを積んでスタックを切り替える。
+// その他のレジスタは呼び出し元のRustコードが保存する。x87/SSEの状態は
+// ユーザープログラムのものを含めてタスクごとに退避する
+global_asm!(
+    ".global wasabi_switch_context",
+    "wasabi_switch_context:",
+    "fxsave64 [rdx]",
+    "fxrstor64 [rcx]",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdi], rsp",
+    "mov rsp, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+);
+extern "sysv64" {
+    /// Saves the current context to *prev_rsp and *prev_fpu, and resumes the
+    /// one at next_rsp and next_fpu.
+    fn wasabi_switch_context(
+        prev_rsp: *mut u64,
+        next_rsp: u64,
+        prev_fpu: *mut FpuState,
+        next_fpu: *const FpuState,
+    );
+}
+
+struct Task {
+    pid: Pid,
+    rsp: u64,
+    /// None for the task that was running at init().
+    _stack: Option<Stack>,
+    entry: Option<Box<dyn FnOnce()>>,
+    /// The page table, or 0 for the kernel's.
+    cr3: u64,
+    /// The stack for syscalls and interrupts from user mode.
+    kernel_stack: u64,
+    fpu: FpuState,
+}
+impl Task {
+    fn new(pid: Pid, entry: Box<dyn FnOnce()>) -> Box<Self> {
+        let stack = Stack::new(TASK_STACK_SIZE);
+        let top = stack.top();
+        // wasabi_switch_context()のretでtask_entry()に飛ぶように積んでおく。
+        // 先頭のダミーは呼び出されたときと同じアラインメントにするための戻りアドレス
+        let frame: [u64; 8] = [0, 0, 0, 0, 0, 0, task_entry as usize as u64, 0];
+        let rsp = top - core::mem::size_of_val(&frame) as u64;
+        unsafe {
+            core::ptr::copy_nonoverlapping(frame.as_ptr(), rsp as *mut u64, frame.len());
+        }
+        Box::new(Self {
+            pid,
+            rsp,
+            _stack: Some(stack),
+            entry: Some(entry),
+            cr3: 0,
+            kernel_stack: 0,
+            fpu: FpuState::initial(),
+        })
+    }
+}
+
+pub(crate) struct TaskManager {
+    current: Option<Box<Task>>,
+    ready: VecDeque<Box<Task>>,
+    /// Exited tasks whose stacks are freed once another task is running.
+    // 切り替え中にrspの保存先が動かないよう、Boxのまま持つ
+    #[allow(clippy::vec_box)]
+    dead: Vec<Box<Task>>,
+    scheduler: Scheduler,
+}
+impl TaskManager {
+    pub(crate) const fn new() -> Self {
+        Self {
+            current: None,
+            ready: VecDeque::new(),
+            dead: Vec::new(),
+            scheduler: Scheduler::new(SchedulerConfig::DEFAULT),
+        }
+    }
+    /// Pops the next task to run, dropping the ones ended by a signal, and
+    /// charges the time of the current one.
+    fn pop_next(&mut self) -> Option<Box<Task>> {
+        let mut i = 0;
+        while i < self.ready.len() {
+            let pid = self.ready[i].pid;
+            if process::deliver_signals(pid).is_none() && process::is_runnable(pid) {
+                i += 1;
+                continue;
+            }
+            if let Some(task) = self.ready.remove(i) {
+                self.scheduler.remove(pid);
+                self.dead.push(task);
+            }
+        }
+        let now = time::now_ns();
+        self.scheduler.account(now);
+        let i = self.scheduler.pick(self.ready.iter().map(|t| t.pid), now)?;
+        let task = self.ready.remove(i)?;
+        self.scheduler.switch_to(task.pid, now);
+        Some(task)
+    }
+}
+
+// SAFETY: tasks run on the CPU that owns the queue and are never touched
+// from interrupt handlers
+unsafe impl Send for TaskManager {}
+
+fn tasks() -> &'static Mutex<TaskManager> {
+    &percpu::this().tasks
+}
+
+/// Turns the running context into a task named name.
+pub fn init(name: &str) {
+    let pid = process::register(name);
+    percpu::this().set_current_task(Some(pid));
+    let mut tasks = tasks().lock();
+    tasks.scheduler.set_current(pid, time::now_ns());
+    tasks.current = Some(Box::new(Task {
+        pid,
+        rsp: 0,
+        _stack: None,
+        entry: None,
+        cr3: 0,
+        kernel_stack: 0,
+        fpu: FpuState::initial(),
+    }));
+}
+
+/// Creates a task that runs f, and returns its pid.
+///
+/// The task starts at the next yield_now() of the others.
+pub fn spawn(name: &str, f: impl FnOnce() + 'static) -> Pid {
+    let pid = process::register(name);
+    if let Some(parent) = current().and_then(process::info) {
+        let _ = process::set_group(pid, parent.group);
+        let _ = process::set_nice(pid, parent.nice);
+    }
+    let mut tasks = tasks().lock();
+    tasks.scheduler.add(pid, time::now_ns());
+    tasks.ready.push_back(Task::new(pid, Box::new(f)));
+    pid
+}
+
+pub fn current() -> Option<Pid> {
+    percpu::this().current_task()
+}
+
+fn switch_address_space(cr3: u64, kernel_stack: u64) {
+    let cr3 = if cr3 == 0 { paging::kernel_cr3() } else { cr3 };
+    percpu::this().set_kernel_stack(kernel_stack);
+    if cr3 != 0 && read_cr3() != cr3 {
+        // SAFETY: every address space maps the kernel as the kernel's one
+        unsafe { write_cr3(cr3) }
+    }
+}
+
+/// Sets the address space (0 for the kernel's) and the kernel stack for
+/// user mode of the current task, and switches to them.
+pub fn set_user_context(cr3: u64, kernel_stack: u64) {
+    if let Some(task) = tasks().lock().current.as_mut() {
+        task.cr3 = cr3;
+        task.kernel_stack = kernel_stack;
+    }
+    switch_address_space(cr3, kernel_stack);
+}
+
+/// Where to save the context of the task switched from. The task is boxed,
+/// so the pointers stay valid while it moves between the queues.
+struct Prev {
+    rsp: *mut u64,
+    fpu: *mut FpuState,
+}
+impl Prev {
+    fn of(task: &mut Task) -> Self {
+        Self {
+            rsp: &mut task.rsp,
+            fpu: &mut task.fpu,
+        }
+    }
+}
+
+/// The context of the task switched to.
+struct Next {
+    rsp: u64,
+    fpu: *const FpuState,
+    cr3: u64,
+    kernel_stack: u64,
+}
+impl Next {
+    fn of(task: &Task) -> Self {
+        Self {
+            rsp: task.rsp,
+            fpu: &task.fpu,
+            cr3: task.cr3,
+            kernel_stack: task.kernel_stack,
+        }
+    }
+}
+
+/// # Safety
+/// The task of prev must be alive until another task runs, and next.rsp
+/// must be saved by wasabi_switch_context() or built by Task::new().
+unsafe fn switch(prev: Prev, next: Next) {
+    switch_address_space(next.cr3, next.kernel_stack);
+    wasabi_switch_context(prev.rsp, next.rsp, prev.fpu, next.fpu)
+}
+
+/// Switches to the next ready task, if any. Returns when this task is
+/// resumed, or ends it if it got a signal.
+pub fn yield_now() {
+    if let Some(code) = current().and_then(process::deliver_signals) {
+        exit(code);
+    }
+    let (prev, next) = {
+        let mut tasks = tasks().lock();
+        tasks.dead.clear();
+        let Some(next) = tasks.pop_next() else {
+            return;
+        };
+        let next_context = Next::of(&next);
+        percpu::this().set_current_task(Some(next.pid));
+        let mut prev = tasks
+            .current
+            .replace(next)
+            .expect("task::init() is not called yet");
+        let prev_context = Prev::of(&mut prev);
+        tasks.ready.push_back(prev);
+        (prev_context, next_context)
+    };
+    // SAFETY: prev is kept alive in the ready queue, and next.rsp was saved by
+    // wasabi_switch_context() or built by Task::new()
+    unsafe { switch(prev, next) }
+}
+
+/// Ends the current task with the exit code.
+pub fn exit(code: i64) -> ! {
+    let (prev, next) = {
+        let mut tasks = tasks().lock();
+        let mut prev = tasks.current.take().expect("No current task");
+        let _ = process::exit(prev.pid, code);
+        let next = tasks.pop_next().expect("The last task exited");
+        let next_context = Next::of(&next);
+        percpu::this().set_current_task(Some(next.pid));
+        tasks.current = Some(next);
+        tasks.scheduler.remove(prev.pid);
+        let prev_context = Prev::of(&mut prev);
+        // スタックはまだ使用中なので、次に切り替わった先で解放する
+        tasks.dead.push(prev);
+        (prev_context, next_context)
+    };
+    // SAFETY: prev is kept alive until another task runs, and next.rsp was
+    // saved by wasabi_switch_context() or built by Task::new()
+    unsafe { switch(prev, next) }
+    unreachable!("An exited task was resumed");
+}
+
+/// Whether the current task has run for its time slice or got a signal, and
+/// should call yield_now() even if it has more to do.
+pub fn should_yield() -> bool {
+    if current().is_some_and(|pid| !process::is_runnable(pid)) {
+        return true;
+    }
+    let tasks = tasks().lock();
+    !tasks.ready.is_empty() && tasks.scheduler.slice_expired(time::now_ns())
+}
+
+/// The `sched` command: the statistics of the scheduler of this CPU.
+pub fn cmd_sched(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    if !args.is_empty() {
+        return Err("usage: sched");
+    }
+    // 出力中にロックを持たないよう、写してから書く
+    let stats = tasks().lock().scheduler.all_stats();
+    let _ = scheduler::write_stats(&stats, out);
+    Ok(())
+}
+
+extern "sysv64" fn task_entry() -> ! {
+    let entry = tasks()
+        .lock()
+        .current
+        .as_mut()
+        .and_then(|t| t.entry.take())
+        .expect("Task has no entry");
+    entry();
+    exit(0)
+}
@@ -0,0 +1,233 @@
+//! The taskbar along the bottom of the screen: a button for each window,
+//! the CPU usage and the free memory as gauges, and the clock.
+//!
+//! It is redrawn once a second. Clicking a window button raises and
+//! focuses the window. Built with the `gui` feature.
+
+use crate::allocator::ALLOCATOR;
+use crate::cursor;
+use crate::executor;
+use crate::graphics::blit;
+use crate::graphics::draw_str_fg;
+use crate::graphics::fill_rect;
+use crate::graphics::Bitmap;
+use crate::graphics::OwnedBitmap;
+use crate::graphics::Rect;
+use crate::memory_map::Size;
+use crate::mouse::MouseButtons;
+use crate::mutex::Mutex;
+use crate::time;
+use crate::time::DateTime;
+use crate::wm;
+use crate::wm::WindowId;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+pub const HEIGHT: i64 = 24;
+
+const BACKGROUND: u32 = 0x202830;
+const TOP_LINE: u32 = 0x606060;
+const TEXT: u32 = 0xffffff;
+const BUTTON: u32 = 0x404850;
+const BUTTON_FOCUSED: u32 = 0x3060c0;
+const BUTTON_FRAME: u32 = 0x808080;
+const GAUGE: u32 = 0x40c040;
+const GAUGE_WIDTH: i64 = 48;
+const MAX_BUTTON_WIDTH: i64 = 120;
+const MIN_BUTTON_WIDTH: i64 = 40;
+const SPACING: i64 = 8;
+
+/// What the taskbar shows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    /// The windows in the order of creation, and whether each is focused.
+    pub windows: Vec<(WindowId, String, bool)>,
+    pub cpu_percent: u32,
+    pub free_bytes: u64,
+    pub total_bytes: u64,
+    pub clock: String,
+}
+impl Status {
+    /// The current status, with the CPU usage given by the caller.
+    pub fn now(cpu_percent: u32) -> Self {
+        let mut windows: Vec<(WindowId, String, bool)> = wm::with(|wm| {
+            wm.windows()
+                .iter()
+                .map(|w| (w.id(), w.title().to_string(), wm.focused() == Some(w.id())))
+                .collect()
+        })
+        .unwrap_or_default();
+        windows.sort_by_key(|(id, _, _)| *id);
+        // 時刻が合わせられるまでは起動からの時間を出す
+        let clock = match DateTime::now() {
+            Some(now) => format!("{now}"),
+            None => format!("up {}s", time::now_ns() / 1_000_000_000),
+        };
+        Self {
+            windows,
+            cpu_percent,
+            free_bytes: ALLOCATOR.free_bytes() as u64,
+            total_bytes: ALLOCATOR.total_bytes() as u64,
+            clock,
+        }
+    }
+}
+
+/// The CPU usage from the idle time between two samples.
+#[derive(Debug, Default)]
+pub struct CpuMeter {
+    last_ns: u64,
+    last_idle_ns: u64,
+}
+impl CpuMeter {
+    pub const fn new() -> Self {
+        Self {
+            last_ns: 0,
+            last_idle_ns: 0,
+        }
+    }
+    /// The percentage of the time not idle since the last sample.
+    pub fn sample(&mut self, now_ns: u64, idle_ns: u64) -> u32 {
+        let elapsed = now_ns.saturating_sub(self.last_ns);
+        let idle = idle_ns.saturating_sub(self.last_idle_ns);
+        self.last_ns = now_ns;
+        self.last_idle_ns = idle_ns;
+        if elapsed == 0 {
+            return 0;
+        }
+        (100 - idle.min(elapsed) * 100 / elapsed) as u32
+    }
+}
+
+/// Draws "LABEL [gauge] text" with its right end at right, and returns
+/// where it starts.
+fn draw_gauge<T: Bitmap>(
+    bitmap: &mut T,
+    right: i64,
+    label: &str,
+    ratio: (u64, u64),
+    text: &str,
+) -> i64 {
+    let text_x = right - text.len() as i64 * 8;
+    let gauge_x = text_x - 4 - GAUGE_WIDTH;
+    let label_x = gauge_x - 4 - label.len() as i64 * 8;
+    let y = (HEIGHT - 16) / 2;
+    draw_str_fg(bitmap, label_x, y, TEXT, label);
+    let _ = fill_rect(bitmap, BUTTON_FRAME, gauge_x, y + 3, GAUGE_WIDTH, 10);
+    let _ = fill_rect(bitmap, BACKGROUND, gauge_x + 1, y + 4, GAUGE_WIDTH - 2, 8);
+    let (used, total) = ratio;
+    if total > 0 {
+        let w = ((GAUGE_WIDTH - 2) as u64 * used.min(total) / total) as i64;
+        let _ = fill_rect(bitmap, GAUGE, gauge_x + 1, y + 4, w, 8);
+    }
+    draw_str_fg(bitmap, text_x, y, TEXT, text);
+    label_x
+}
+
+/// Draws the taskbar filling bitmap, and returns where the window buttons
+/// are. The buttons that do not fit are left out.
+pub fn draw<T: Bitmap>(bitmap: &mut T, status: &Status) -> Vec<(WindowId, Rect)> {
+    let (w, h) = (bitmap.width(), bitmap.height());
+    let _ = fill_rect(bitmap, BACKGROUND, 0, 0, w, h);
+    let _ = fill_rect(bitmap, TOP_LINE, 0, 0, w, 1);
+    // 右から時計、メモリ、CPUの順に並べる
+    let clock_x = w - SPACING - status.clock.len() as i64 * 8;
+    draw_str_fg(bitmap, clock_x, (HEIGHT - 16) / 2, TEXT, &status.clock);
+    let used = status.total_bytes.saturating_sub(status.free_bytes);
+    let free = format!("{}", Size(status.free_bytes));
+    let mem_x = draw_gauge(
+        bitmap,
+        clock_x - SPACING * 2,
+        "MEM",
+        (used, status.total_bytes),
+        &free,
+    );
+    let cpu = format!("{}%", status.cpu_percent.min(100));
+    let cpu_x = draw_gauge(
+        bitmap,
+        mem_x - SPACING * 2,
+        "CPU",
+        (status.cpu_percent as u64, 100),
+        &cpu,
+    );
+    let area = cpu_x - SPACING * 2 - SPACING;
+    let n = status.windows.len() as i64;
+    if n == 0 {
+        return Vec::new();
+    }
+    let button_w = ((area - SPACING) / n - 4).clamp(MIN_BUTTON_WIDTH, MAX_BUTTON_WIDTH);
+    let mut buttons = Vec::new();
+    let mut x = SPACING;
+    for (id, title, focused) in &status.windows {
+        if x + button_w > area {
+            break;
+        }
+        let rect = Rect::new(x, 3, button_w, HEIGHT - 5);
+        let fill = if *focused { BUTTON_FOCUSED } else { BUTTON };
+        let _ = fill_rect(bitmap, BUTTON_FRAME, rect.x, rect.y, rect.w, rect.h);
+        let _ = fill_rect(bitmap, fill, rect.x + 1, rect.y + 1, rect.w - 2, rect.h - 2);
+        let max_chars = ((button_w - 8) / 8) as usize;
+        let title: String = title.chars().take(max_chars).collect();
+        draw_str_fg(bitmap, rect.x + 4, rect.y + (rect.h - 16) / 2, TEXT, &title);
+        buttons.push((*id, rect));
+        x += button_w + 4;
+    }
+    buttons
+}
+
+/// The window buttons on the screen.
+static BUTTONS: Mutex<Vec<(WindowId, Rect)>> = Mutex::new(Vec::new());
+static LEFT_PRESSED: AtomicBool = AtomicBool::new(false);
+
+/// Starts redrawing the taskbar at rect of the screen every second.
+pub fn start<T: Bitmap + 'static>(mut screen: T, rect: Rect) {
+    executor::spawn(async move {
+        let mut meter = CpuMeter::new();
+        meter.sample(time::now_ns(), time::idle_ns());
+        loop {
+            let cpu_percent = meter.sample(time::now_ns(), time::idle_ns());
+            let status = Status::now(cpu_percent);
+            let mut bar = OwnedBitmap::new(rect.w, rect.h, BACKGROUND);
+            let buttons = draw(&mut bar, &status);
+            *BUTTONS.lock() = buttons
+                .into_iter()
+                .map(|(id, r)| (id, Rect::new(r.x + rect.x, r.y + rect.y, r.w, r.h)))
+                .collect();
+            cursor::draw_around(&mut screen, rect, |screen| {
+                let _ = blit(
+                    &mut bar,
+                    Rect::new(0, 0, rect.w, rect.h),
+                    screen,
+                    rect.x,
+                    rect.y,
+                );
+            });
+            time::sleep(Duration::from_secs(1)).await;
+        }
+    });
+}
+
+/// Raises and focuses the window whose button is clicked at (x, y) on the
+/// screen.
+pub fn handle_mouse(x: i64, y: i64, buttons: MouseButtons) {
+    let was_pressed = LEFT_PRESSED.swap(buttons.left(), Ordering::Relaxed);
+    if !buttons.left() || was_pressed {
+        return;
+    }
+    let hit = BUTTONS
+        .lock()
+        .iter()
+        .find(|(_, r)| r.contains(x, y))
+        .map(|(id, _)| *id);
+    if let Some(id) = hit {
+        let _ = wm::with(|wm| {
+            let _ = wm.raise(id);
+            wm.focus(Some(id))
+        });
+    }
+}
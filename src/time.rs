@@ -0,0 +1,407 @@
+use crate::arch::rdtsc;
+use crate::arch::read_io_port_u8;
+use crate::arch::without_interrupts;
+use crate::arch::write_io_port_u8;
+use crate::hpet;
+use crate::hpet::Hpet;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::rtc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+use core::pin::Pin;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+use core::task::Context;
+use core::task::Poll;
+use core::task::Waker;
+use core::time::Duration;
+
+const PIT_FREQ_HZ: u64 = 1_193_182;
+const PIT_CH2_DATA: u16 = 0x42;
+const PIT_CMD: u16 = 0x43;
+/// Channel 2, lobyte/hibyte, mode 0 (interrupt on terminal count), binary
+const PIT_CMD_CH2_ONESHOT: u8 = 0xb0;
+/// Bit 0 gates channel 2, bit 1 drives the speaker and bit 5 reads its output.
+const PORT_B: u16 = 0x61;
+const PORT_B_GATE: u8 = 0x01;
+const PORT_B_SPEAKER: u8 = 0x02;
+const PORT_B_OUT2: u8 = 0x20;
+
+const CALIBRATION_MS: u64 = 10;
+const CALIBRATION_ROUNDS: usize = 3;
+
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+static BOOT_TSC: AtomicU64 = AtomicU64::new(0);
+/// The Unix time at init() in nanoseconds, or 0 while the wall clock is not set.
+static BOOT_UNIX_NS: AtomicU64 = AtomicU64::new(0);
+/// The time the CPU has been halted with nothing to do.
+static IDLE_NS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts TSC ticks during `ms` milliseconds measured by PIT channel 2.
+fn measure_tsc_ticks(ms: u64) -> Result<u64> {
+    let count = PIT_FREQ_HZ * ms / 1000;
+    if count > u16::MAX as u64 {
+        return Err("Calibration period is too long for the PIT");
+    }
+    let port_b = read_io_port_u8(PORT_B) & !PORT_B_SPEAKER;
+    write_io_port_u8(PORT_B, port_b & !PORT_B_GATE);
+    write_io_port_u8(PIT_CMD, PIT_CMD_CH2_ONESHOT);
+    write_io_port_u8(PIT_CH2_DATA, count as u8);
+    write_io_port_u8(PIT_CH2_DATA, (count >> 8) as u8);
+    // ゲートを立ち上げるとカウントが始まる
+    write_io_port_u8(PORT_B, port_b | PORT_B_GATE);
+    let start = rdtsc();
+    let mut spins: u64 = 0;
+    while read_io_port_u8(PORT_B) & PORT_B_OUT2 == 0 {
+        spins += 1;
+        if spins > 1 << 32 {
+            return Err("PIT channel 2 did not fire");
+        }
+    }
+    let end = rdtsc();
+    write_io_port_u8(PORT_B, port_b & !PORT_B_GATE);
+    Ok(end - start)
+}
+
+/// Counts TSC ticks during `ms` milliseconds measured by the HPET main counter.
+fn measure_tsc_ticks_with_hpet(hpet: &Hpet, ms: u64) -> u64 {
+    let ticks = hpet.frequency_hz() * ms / 1000;
+    let counter_start = hpet.main_counter();
+    let start = rdtsc();
+    while hpet.main_counter().wrapping_sub(counter_start) < ticks {
+        core::hint::spin_loop();
+    }
+    rdtsc() - start
+}
+
+/// Calibrates the TSC frequency against the HPET if hpet::init() has
+/// succeeded, or against the PIT otherwise. Call once at boot.
+///
+/// Returns the name of the reference timer. The shortest of a few rounds is
+/// used since SMIs and the like only make a round longer.
+pub fn init() -> Result<&'static str> {
+    let hpet = hpet::get();
+    let mut ticks = u64::MAX;
+    for _ in 0..CALIBRATION_ROUNDS {
+        let t = match &hpet {
+            Some(hpet) => measure_tsc_ticks_with_hpet(hpet, CALIBRATION_MS),
+            None => measure_tsc_ticks(CALIBRATION_MS)?,
+        };
+        ticks = ticks.min(t);
+    }
+    let hz = ticks * 1000 / CALIBRATION_MS;
+    if hz == 0 {
+        return Err("TSC is not running");
+    }
+    BOOT_TSC.store(rdtsc(), Ordering::Relaxed);
+    TSC_HZ.store(hz, Ordering::Relaxed);
+    Ok(if hpet.is_some() { "HPET" } else { "PIT" })
+}
+
+/// Calibrated TSC frequency, or 0 before init().
+pub fn tsc_hz() -> u64 {
+    TSC_HZ.load(Ordering::Relaxed)
+}
+
+fn ticks_to_ns(ticks: u64) -> u64 {
+    let hz = tsc_hz();
+    if hz == 0 {
+        return 0;
+    }
+    (ticks as u128 * 1_000_000_000 / hz as u128) as u64
+}
+
+/// Nanoseconds since init(). Always 0 before calibration.
+pub fn now_ns() -> u64 {
+    ticks_to_ns(rdtsc().saturating_sub(BOOT_TSC.load(Ordering::Relaxed)))
+}
+
+/// Sets the wall clock to unix_time, the time since 1970-01-01 00:00:00 UTC.
+pub fn set_unix_time(unix_time: Duration) {
+    let boot = (unix_time.as_nanos() as u64).saturating_sub(now_ns());
+    BOOT_UNIX_NS.store(boot.max(1), Ordering::Relaxed);
+}
+
+/// The current Unix time, or None until the wall clock is set.
+pub fn unix_time() -> Option<Duration> {
+    match BOOT_UNIX_NS.load(Ordering::Relaxed) {
+        0 => None,
+        boot => Some(Duration::from_nanos(boot + now_ns())),
+    }
+}
+
+/// Counts ns as idle, for the time the idle loop has been halted.
+pub fn add_idle_time(ns: u64) {
+    IDLE_NS.fetch_add(ns, Ordering::Relaxed);
+}
+
+/// Nanoseconds spent idle since boot.
+pub fn idle_ns() -> u64 {
+    IDLE_NS.load(Ordering::Relaxed)
+}
+
+/// A date and time in UTC, in the proleptic Gregorian calendar.
+///
+/// Displayed as `2024-01-01 12:34:56`, or as RFC 3339
+/// (`2024-01-01T12:34:56Z`) with the alternate flag `{:#}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+impl DateTime {
+    const WEEKDAYS: [&'static str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+    const MONTHS: [&'static str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    /// The current date and time, or None until the wall clock is set.
+    pub fn now() -> Option<Self> {
+        unix_time().map(Self::from_unix_time)
+    }
+    pub fn from_unix_time(unix_time: Duration) -> Self {
+        let secs = unix_time.as_secs();
+        let (year, month, day) = civil_from_days(secs / 86400);
+        let secs = secs % 86400;
+        Self {
+            year: year as u16,
+            month,
+            day,
+            hour: (secs / 3600) as u8,
+            minute: (secs / 60 % 60) as u8,
+            second: (secs % 60) as u8,
+        }
+    }
+    /// The time since the Unix epoch, or None if self is invalid or before it.
+    pub fn to_unix_time(&self) -> Option<Duration> {
+        if !self.is_valid() || self.year < 1970 {
+            return None;
+        }
+        let days = days_from_civil(self.year as u64, self.month, self.day);
+        let secs = self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64;
+        Some(Duration::from_secs(days * 86400 + secs))
+    }
+    pub fn is_valid(&self) -> bool {
+        (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+    /// The day of the week, 0 for Sunday. self must be valid.
+    pub fn weekday(&self) -> u8 {
+        // 0000-03-01 は水曜日。400年 (146097日) はちょうど20871週なので、
+        // 1-2月の年を繰り下げても負にならないよう400年ずらして数える
+        ((days_from_march_0(self.year as u64 + 400, self.month, self.day) + 3) % 7) as u8
+    }
+    /// The abbreviated English name of the weekday, e.g. "Mon".
+    pub fn weekday_name(&self) -> &'static str {
+        Self::WEEKDAYS[self.weekday() as usize]
+    }
+    /// The abbreviated English name of the month, e.g. "Jan".
+    pub fn month_name(&self) -> &'static str {
+        Self::MONTHS[(self.month as usize).clamp(1, 12) - 1]
+    }
+}
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sep = if f.alternate() { 'T' } else { ' ' };
+        write!(
+            f,
+            "{:04}-{:02}-{:02}{sep}{:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )?;
+        if f.alternate() {
+            write!(f, "Z")?;
+        }
+        Ok(())
+    }
+}
+
+fn is_leap_year(year: u16) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 0000-03-01 to the date, which must be valid. The years are
+/// counted from March so that the leap day comes last (H. Hinnant,
+/// "chrono-Compatible Low-Level Date Algorithms").
+fn days_from_march_0(year: u64, month: u8, day: u8) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * ((month as u64 + 9) % 12) + 2) / 5 + day as u64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era
+}
+
+/// Days from 0000-03-01 to 1970-01-01.
+const UNIX_EPOCH_DAYS: u64 = 719468;
+
+/// Days from 1970-01-01 to the date, which must be valid and not before it.
+fn days_from_civil(year: u64, month: u8, day: u8) -> u64 {
+    days_from_march_0(year, month, day) - UNIX_EPOCH_DAYS
+}
+
+/// The inverse of days_from_civil(): (year, month, day).
+fn civil_from_days(days: u64) -> (u64, u8, u8) {
+    let days = days + UNIX_EPOCH_DAYS;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = era * 400 + year_of_era + (month <= 2) as u64;
+    (year, month, day)
+}
+
+/// A point in time measured with the TSC, like std::time::Instant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Instant {
+    tsc: u64,
+}
+impl Instant {
+    pub fn now() -> Self {
+        Self { tsc: rdtsc() }
+    }
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(ticks_to_ns(self.tsc.saturating_sub(earlier.tsc)))
+    }
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+}
+
+/// Busy-waits for the given duration.
+pub fn spin_wait(duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        core::hint::spin_loop();
+    }
+}
+
+struct Timer {
+    id: u64,
+    deadline_ns: u64,
+    waker: Waker,
+}
+
+static TIMERS: Mutex<Vec<Timer>> = Mutex::new(Vec::new());
+static NEXT_TIMER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Wakes the sleeps whose deadline has passed. Called from the periodic
+/// timer interrupt and by the executor. Neither waking nor dropping the
+/// wakers of the executor allocates or frees memory (see executor.rs).
+pub fn wake_expired_timers() {
+    let now = now_ns();
+    // 割り込みハンドラからも呼ばれるので、ロックが取れなければ次の機会に回す
+    let Some(mut timers) = TIMERS.try_lock() else {
+        return;
+    };
+    timers.retain(|t| {
+        if t.deadline_ns <= now {
+            t.waker.wake_by_ref();
+            false
+        } else {
+            true
+        }
+    });
+}
+
+/// A future that completes after a duration, e.g. `time::sleep(d).await`.
+///
+/// It has a single entry in TIMERS while it is pending, whose waker is
+/// replaced each time it is polled, and which is removed when it is dropped.
+pub struct Sleep {
+    deadline_ns: u64,
+    timer: Option<u64>,
+}
+impl Future for Sleep {
+    type Output = ();
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if now_ns() >= self.deadline_ns {
+            return Poll::Ready(());
+        }
+        let this = self.get_mut();
+        without_interrupts(|| {
+            let mut timers = TIMERS.lock();
+            if let Some(t) = timers.iter_mut().find(|t| Some(t.id) == this.timer) {
+                if !t.waker.will_wake(cx.waker()) {
+                    t.waker = cx.waker().clone();
+                }
+                return;
+            }
+            let id = NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+            timers.push(Timer {
+                id,
+                deadline_ns: this.deadline_ns,
+                waker: cx.waker().clone(),
+            });
+            this.timer = Some(id);
+        });
+        Poll::Pending
+    }
+}
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.timer {
+            without_interrupts(|| TIMERS.lock().retain(|t| t.id != id));
+        }
+    }
+}
+
+pub fn sleep(duration: Duration) -> Sleep {
+    Sleep {
+        deadline_ns: now_ns().saturating_add(duration.as_nanos() as u64),
+        timer: None,
+    }
+}
+
+/// The `uptime` command.
+pub fn cmd_uptime(_args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let secs = now_ns() / 1_000_000_000;
+    let _ = writeln!(
+        out,
+        "up {}:{:02}:{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    );
+    Ok(())
+}
+
+/// The `date` command: shows the wall clock, or the RTC with `date rtc` and
+/// the firmware clock with `date efi`. `date efi set` sets the firmware
+/// clock to the wall clock.
+pub fn cmd_date(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let now = match args {
+        [] => DateTime::now().ok_or("date: the clock is not set")?,
+        ["rtc"] => rtc::read()?,
+        ["efi"] => rtc::read_firmware()?,
+        ["efi", "set"] => {
+            let now = DateTime::now().ok_or("date: the clock is not set")?;
+            rtc::write_firmware(&now)?;
+            now
+        }
+        _ => return Err("usage: date [rtc | efi | efi set]"),
+    };
+    let _ = writeln!(out, "{} {now} UTC", now.weekday_name());
+    Ok(())
+}
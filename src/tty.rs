@@ -0,0 +1,223 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use core::fmt;
+
+pub const CTRL_C: char = '\x03';
+pub const CTRL_D: char = '\x04';
+pub const BACKSPACE: char = '\x08';
+pub const CTRL_U: char = '\x15';
+pub const CTRL_V: char = '\x16';
+pub const CTRL_W: char = '\x17';
+pub const CTRL_X: char = '\x18';
+pub const DELETE: char = '\x7f';
+
+const MAX_LINE_LEN: usize = 256;
+const MAX_PENDING_INPUT: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TtyMode {
+    /// Buffers input until Enter is pressed and handles line editing keys.
+    pub canonical: bool,
+    /// Writes received characters back to the terminal.
+    pub echo: bool,
+    /// Turns Ctrl+C into TtySignal::Interrupt instead of passing it through.
+    pub signals: bool,
+}
+impl TtyMode {
+    pub const fn cooked() -> Self {
+        Self {
+            canonical: true,
+            echo: true,
+            signals: true,
+        }
+    }
+    pub const fn raw() -> Self {
+        Self {
+            canonical: false,
+            echo: false,
+            signals: false,
+        }
+    }
+}
+impl Default for TtyMode {
+    fn default() -> Self {
+        Self::cooked()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtySignal {
+    Interrupt,
+}
+
+/// Line discipline that sits between an input device and its reader.
+///
+/// Characters are fed by the driver with `input()`, and become readable
+/// immediately in raw mode or line by line in canonical mode.
+pub struct Tty {
+    mode: TtyMode,
+    line: String,
+    pending: VecDeque<char>,
+    /// Where the lines flushed by Ctrl+D, which have no '\n', end in pending.
+    flushed: VecDeque<usize>,
+    eof: bool,
+}
+impl Tty {
+    pub const fn new() -> Self {
+        Self {
+            mode: TtyMode::cooked(),
+            line: String::new(),
+            pending: VecDeque::new(),
+            flushed: VecDeque::new(),
+            eof: false,
+        }
+    }
+    pub fn mode(&self) -> TtyMode {
+        self.mode
+    }
+    pub fn set_mode(&mut self, mode: TtyMode) {
+        if self.mode.canonical && !mode.canonical {
+            // 編集途中の行はそのまま読めるようにする
+            self.push_line();
+        }
+        self.mode = mode;
+    }
+    /// Processes one character from the input device, echoing to out if enabled.
+    pub fn input(&mut self, c: char, out: &mut dyn fmt::Write) -> Option<TtySignal> {
+        if self.mode.signals && c == CTRL_C {
+            self.line.clear();
+            if self.mode.echo {
+                let _ = out.write_str("^C\n");
+            }
+            return Some(TtySignal::Interrupt);
+        }
+        if !self.mode.canonical {
+            self.push_pending(c);
+            if self.mode.echo {
+                let _ = out.write_char(c);
+            }
+            return None;
+        }
+        match c {
+            '\r' | '\n' => {
+                self.push_line();
+                self.push_pending('\n');
+                if self.mode.echo {
+                    let _ = out.write_char('\n');
+                }
+            }
+            BACKSPACE | DELETE => {
+                if self.line.pop().is_some() {
+                    self.echo_erase(out);
+                }
+            }
+            CTRL_U => self.erase_while(out, |_| true),
+            CTRL_W => {
+                self.erase_while(out, |c| c == ' ');
+                self.erase_while(out, |c| c != ' ');
+            }
+            CTRL_D => {
+                if self.line.is_empty() {
+                    self.eof = true;
+                } else {
+                    self.push_line();
+                    self.flushed.push_back(self.pending.len());
+                }
+            }
+            c if c.is_control() => {}
+            c => {
+                if self.line.len() < MAX_LINE_LEN {
+                    self.line.push(c);
+                    if self.mode.echo {
+                        let _ = out.write_char(c);
+                    }
+                }
+            }
+        }
+        None
+    }
+    fn erase_while(&mut self, out: &mut dyn fmt::Write, f: impl Fn(char) -> bool) {
+        while let Some(c) = self.line.pop() {
+            if !f(c) {
+                self.line.push(c);
+                break;
+            }
+            self.echo_erase(out);
+        }
+    }
+    /// Erases the last character on the terminal, which may only move the
+    /// cursor back on BACKSPACE.
+    fn echo_erase(&self, out: &mut dyn fmt::Write) {
+        if self.mode.echo {
+            let _ = out.write_str("\x08 \x08");
+        }
+    }
+    fn push_pending(&mut self, c: char) {
+        if self.pending.len() < MAX_PENDING_INPUT {
+            self.pending.push_back(c);
+        }
+    }
+    /// Makes the line being edited readable.
+    fn push_line(&mut self) {
+        for c in core::mem::take(&mut self.line).chars() {
+            self.push_pending(c);
+        }
+    }
+    /// Returns the next readable character, if any.
+    pub fn read_char(&mut self) -> Option<char> {
+        let c = self.pending.pop_front()?;
+        self.consume(1);
+        Some(c)
+    }
+    /// Moves the ends of the flushed lines after n characters are read.
+    fn consume(&mut self, n: usize) {
+        for end in self.flushed.iter_mut() {
+            *end = end.saturating_sub(n);
+        }
+        while self.flushed.front() == Some(&0) {
+            self.flushed.pop_front();
+        }
+    }
+    /// Returns a completed line without the trailing newline.
+    ///
+    /// In raw mode, this returns whatever has been received so far.
+    pub fn read_line(&mut self) -> Option<String> {
+        if self.mode.canonical {
+            let newline = self.pending.iter().position(|c| *c == '\n');
+            // Ctrl+Dで押し出された行は、改行がなくてもそこで区切る
+            let (n, terminated) = match (self.flushed.front(), newline) {
+                (Some(&end), Some(n)) if end <= n => (end, false),
+                (Some(&end), None) => (end, false),
+                (_, Some(n)) => (n, true),
+                (None, None) => return None,
+            };
+            let line = self.pending.drain(..n).collect();
+            if terminated {
+                self.pending.pop_front();
+            }
+            self.consume(n + terminated as usize);
+            Some(line)
+        } else if self.pending.is_empty() {
+            None
+        } else {
+            self.flushed.clear();
+            Some(self.pending.drain(..).collect())
+        }
+    }
+    /// Returns true once after Ctrl+D is pressed on an empty line.
+    pub fn take_eof(&mut self) -> bool {
+        core::mem::take(&mut self.eof)
+    }
+    /// The line being edited in canonical mode.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+    pub fn has_input(&self) -> bool {
+        !self.pending.is_empty()
+    }
+}
+impl Default for Tty {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,1296 @@
+use crate::graphics::draw_font_fg;
+use crate::graphics::fill_rect;
+use crate::graphics::Bitmap;
+use crate::keymap::Key;
+use crate::keymap::NamedKey;
+use crate::result::Result;
+use crate::time::DateTime;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem::offset_of;
+use core::mem::size_of;
+use core::ptr::null;
+use core::ptr::null_mut;
+use core::sync::atomic::AtomicPtr;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+pub type EfiVoid = u8;
+pub type EfiHandle = u64;
+pub type EfiEvent = u64;
+
+#[repr(C)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct EfiGuid {
+    pub data0: u32,
+    pub data1: u16,
+    pub data2: u16,
+    pub data3: [u8; 8],
+}
+impl EfiGuid {
+    /// Reads a GUID in the on-disk layout, where the first three fields
+    /// are little endian.
+    pub fn from_bytes(b: &[u8; 16]) -> Self {
+        Self {
+            data0: u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            data1: u16::from_le_bytes([b[4], b[5]]),
+            data2: u16::from_le_bytes([b[6], b[7]]),
+            data3: [b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]],
+        }
+    }
+}
+impl fmt::Display for EfiGuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let d = &self.data3;
+        write!(
+            f,
+            "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{:02X}{:02X}{:02X}{:02X}{:02X}{:02X}",
+            self.data0, self.data1, self.data2, d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7]
+        )
+    }
+}
+
+const EFI_GRAPHICS_OUTPUT_PROTOCOL_GUID: EfiGuid = EfiGuid {
+    data0: 0x9042a9de,
+    data1: 0x23dc,
+    data2: 0x4a38,
+    data3: [0x96, 0xfb, 0x7a, 0xde, 0xd0, 0x80, 0x51, 0x6a],
+};
+
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[must_use]
+#[repr(u64)]
+pub enum EfiStatus {
+    Success = 0,
+    LoadError = 0x8000_0000_0000_0001,
+    InvalidParameter = 0x8000_0000_0000_0002,
+    Unsupported = 0x8000_0000_0000_0003,
+    BadBufferSize = 0x8000_0000_0000_0004,
+    BufferTooSmall = 0x8000_0000_0000_0005,
+    NotReady = 0x8000_0000_0000_0006,
+    DeviceError = 0x8000_0000_0000_0007,
+    WriteProtected = 0x8000_0000_0000_0008,
+    OutOfResources = 0x8000_0000_0000_0009,
+    NotFound = 0x8000_0000_0000_000e,
+    AccessDenied = 0x8000_0000_0000_000f,
+    Aborted = 0x8000_0000_0000_0015,
+    SecurityViolation = 0x8000_0000_0000_001a,
+}
+
+#[repr(i64)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(non_camel_case_types)]
+pub enum EfiMemoryType {
+    RESERVED = 0,
+    LOADER_CODE,
+    LOADER_DATA,
+    BOOT_SERVICES_CODE,
+    BOOT_SERVICES_DATA,
+    RUNTIME_SERVICES_CODE,
+    RUNTIME_SERVICES_DATA,
+    CONVENTIONAL_MEMORY,
+    UNUSABLE_MEMORY,
+    ACPI_RECLAIM_MEMORY,
+    ACPI_MEMORY_NVS,
+    MEMORY_MAPPED_IO,
+    MEMORY_MAPPED_IO_PORT_SPACE,
+    PAL_CODE,
+    PERSISTENT_MEMORY,
+}
+impl EfiMemoryType {
+    pub fn name(self) -> &'static str {
+        match self {
+            EfiMemoryType::RESERVED => "Reserved",
+            EfiMemoryType::LOADER_CODE => "LoaderCode",
+            EfiMemoryType::LOADER_DATA => "LoaderData",
+            EfiMemoryType::BOOT_SERVICES_CODE => "BootServicesCode",
+            EfiMemoryType::BOOT_SERVICES_DATA => "BootServicesData",
+            EfiMemoryType::RUNTIME_SERVICES_CODE => "RuntimeServicesCode",
+            EfiMemoryType::RUNTIME_SERVICES_DATA => "RuntimeServicesData",
+            EfiMemoryType::CONVENTIONAL_MEMORY => "Conventional",
+            EfiMemoryType::UNUSABLE_MEMORY => "Unusable",
+            EfiMemoryType::ACPI_RECLAIM_MEMORY => "AcpiReclaim",
+            EfiMemoryType::ACPI_MEMORY_NVS => "AcpiNvs",
+            EfiMemoryType::MEMORY_MAPPED_IO => "Mmio",
+            EfiMemoryType::MEMORY_MAPPED_IO_PORT_SPACE => "MmioPortSpace",
+            EfiMemoryType::PAL_CODE => "PalCode",
+            EfiMemoryType::PERSISTENT_MEMORY => "Persistent",
+        }
+    }
+    /// True if the memory is backed by RAM, i.e. it counts toward the total.
+    pub fn is_ram(self) -> bool {
+        !matches!(
+            self,
+            EfiMemoryType::RESERVED
+                | EfiMemoryType::UNUSABLE_MEMORY
+                | EfiMemoryType::MEMORY_MAPPED_IO
+                | EfiMemoryType::MEMORY_MAPPED_IO_PORT_SPACE
+        )
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct EfiMemoryDescriptor {
+    pub memory_type: EfiMemoryType,
+    pub physical_start: u64,
+    pub virtual_start: u64,
+    pub number_of_pages: u64,
+    pub attribute: u64,
+}
+
+const MEMORY_MAP_BUFFER_SIZE: usize = 0x8000;
+/// The version of EfiMemoryDescriptor above. Later versions only append
+/// fields, which descriptor_size skips.
+pub const EFI_MEMORY_DESCRIPTOR_VERSION: u32 = 1;
+
+pub struct MemoryMapHolder {
+    memory_map_buffer: [u8; MEMORY_MAP_BUFFER_SIZE],
+    /// A buffer from AllocatePool, used instead of memory_map_buffer once
+    /// the map does not fit in it. Never freed, as the map is kept.
+    pool: *mut u8,
+    pool_size: usize,
+    memory_map_size: usize,
+    map_key: usize,
+    descriptor_size: usize,
+    descriptor_version: u32,
+}
+pub struct MemoryMapIterator<'a> {
+    map: &'a MemoryMapHolder,
+    ofs: usize,
+}
+impl<'a> Iterator for MemoryMapIterator<'a> {
+    type Item = &'a EfiMemoryDescriptor;
+    fn next(&mut self) -> Option<&'a EfiMemoryDescriptor> {
+        if self.ofs >= self.map.memory_map_size {
+            None
+        } else {
+            let e: &EfiMemoryDescriptor =
+                unsafe { &*(self.map.buffer().add(self.ofs) as *const EfiMemoryDescriptor) };
+            self.ofs += self.map.descriptor_size;
+            Some(e)
+        }
+    }
+}
+
+impl MemoryMapHolder {
+    pub const fn new() -> MemoryMapHolder {
+        MemoryMapHolder {
+            memory_map_buffer: [0; MEMORY_MAP_BUFFER_SIZE],
+            pool: null_mut(),
+            pool_size: 0,
+            memory_map_size: MEMORY_MAP_BUFFER_SIZE,
+            map_key: 0,
+            descriptor_size: 0,
+            descriptor_version: 0,
+        }
+    }
+    pub fn iter(&self) -> MemoryMapIterator {
+        MemoryMapIterator { map: self, ofs: 0 }
+    }
+    pub fn descriptor_version(&self) -> u32 {
+        self.descriptor_version
+    }
+    /// The key of the map for ExitBootServices().
+    pub fn map_key(&self) -> usize {
+        self.map_key
+    }
+    fn buffer(&self) -> *const u8 {
+        if self.pool.is_null() {
+            self.memory_map_buffer.as_ptr()
+        } else {
+            self.pool
+        }
+    }
+    fn buffer_mut(&mut self) -> (*mut u8, usize) {
+        if self.pool.is_null() {
+            (self.memory_map_buffer.as_mut_ptr(), MEMORY_MAP_BUFFER_SIZE)
+        } else {
+            (self.pool, self.pool_size)
+        }
+    }
+}
+impl Default for MemoryMapHolder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[repr(C)]
+pub struct EfiBootServicesTable {
+    _reserved0: [u64; 5],
+    allocate_pages: extern "win64" fn(
+        allocate_type: u32,
+        memory_type: EfiMemoryType,
+        pages: usize,
+        memory: *mut u64,
+    ) -> EfiStatus,
+    free_pages: extern "win64" fn(memory: u64, pages: usize) -> EfiStatus,
+    get_memory_map: extern "win64" fn(
+        memory_map_size: *mut usize,
+        memory_map: *mut u8,
+        map_key: *mut usize,
+        descriptor_size: *mut usize,
+        descriptor_version: *mut u32,
+    ) -> EfiStatus,
+    allocate_pool: extern "win64" fn(
+        pool_type: EfiMemoryType,
+        size: usize,
+        buffer: *mut *mut EfiVoid,
+    ) -> EfiStatus,
+    free_pool: extern "win64" fn(buffer: *mut EfiVoid) -> EfiStatus,
+    _reserved1: [u64; 2],
+    wait_for_event: extern "win64" fn(
+        number_of_events: usize,
+        event: *const EfiEvent,
+        index: *mut usize,
+    ) -> EfiStatus,
+    _reserved2: [u64; 6],
+    handle_protocol: extern "win64" fn(
+        handle: EfiHandle,
+        protocol: *const EfiGuid,
+        interface: *mut *mut EfiVoid,
+    ) -> EfiStatus,
+    _reserved3: [u64; 5],
+    load_image: extern "win64" fn(
+        boot_policy: bool,
+        parent_image_handle: EfiHandle,
+        device_path: *const u8,
+        source_buffer: *const u8,
+        source_size: usize,
+        image_handle: *mut EfiHandle,
+    ) -> EfiStatus,
+    // 起動したアプリケーションが返す値はEfiStatusに無いものもあり得るので生の値で受け取る
+    start_image: extern "win64" fn(
+        image_handle: EfiHandle,
+        exit_data_size: *mut usize,
+        exit_data: *mut *mut u16,
+    ) -> u64,
+    _reserved4: [u64; 1],
+    unload_image: extern "win64" fn(image_handle: EfiHandle) -> EfiStatus,
+    exit_boot_services: extern "win64" fn(image_handle: EfiHandle, map_key: usize) -> EfiStatus,
+    _reserved5: [u64; 1],
+    stall: extern "win64" fn(microseconds: usize) -> EfiStatus,
+    set_watchdog_timer: extern "win64" fn(
+        timeout: usize,
+        watchdog_code: u64,
+        data_size: usize,
+        watchdog_data: *const u16,
+    ) -> EfiStatus,
+    _reserved6: [u64; 7],
+    locate_protocol: extern "win64" fn(
+        protocol: *const EfiGuid,
+        registration: *mut EfiVoid,
+        interface: *mut *mut EfiVoid,
+    ) -> EfiStatus,
+}
+impl EfiBootServicesTable {
+    pub fn get_memory_map(&self, map: &mut MemoryMapHolder) -> EfiStatus {
+        // 呼ぶたびにバッファの大きさを渡し直す (前回の結果で上書きされている)
+        let (buffer, size) = map.buffer_mut();
+        map.memory_map_size = size;
+        (self.get_memory_map)(
+            &mut map.memory_map_size,
+            buffer,
+            &mut map.map_key,
+            &mut map.descriptor_size,
+            &mut map.descriptor_version,
+        )
+    }
+    /// Gets the memory map, moving it to a larger buffer from AllocatePool
+    /// while it does not fit.
+    pub fn fetch_memory_map(&self, map: &mut MemoryMapHolder) -> Result<()> {
+        const MAX_TRIES: usize = 4;
+        for _ in 0..MAX_TRIES {
+            match self.get_memory_map(map) {
+                EfiStatus::Success => {
+                    if map.descriptor_size < size_of::<EfiMemoryDescriptor>() {
+                        return Err("GetMemoryMap: descriptors are too small");
+                    }
+                    return Ok(());
+                }
+                EfiStatus::BufferTooSmall => {
+                    // 確保したプール自体で記述子が増えるので、少し余分に取る
+                    let slack = 8 * map.descriptor_size.max(size_of::<EfiMemoryDescriptor>());
+                    let size = map.memory_map_size + slack;
+                    let pool = self.allocate_pool(EfiMemoryType::LOADER_DATA, size)?;
+                    if !map.pool.is_null() {
+                        // SAFETY: the old buffer is not referenced anymore
+                        let _ = unsafe { self.free_pool(map.pool) };
+                    }
+                    map.pool = pool;
+                    map.pool_size = size;
+                }
+                _ => return Err("GetMemoryMap failed"),
+            }
+        }
+        Err("GetMemoryMap: the map kept growing")
+    }
+}
+/// Where AllocatePages places the pages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocateType {
+    AnyPages,
+    /// Anywhere below the address.
+    MaxAddress(u64),
+    /// At the address.
+    Address(u64),
+}
+impl EfiBootServicesTable {
+    /// Allocates pages of 4 KiB and returns the physical address of the
+    /// first one. The firmware leaves them alone and reports them as
+    /// memory_type in the memory map.
+    pub fn allocate_pages(
+        &self,
+        allocate_type: AllocateType,
+        memory_type: EfiMemoryType,
+        pages: usize,
+    ) -> Result<u64> {
+        let (allocate_type, mut memory) = match allocate_type {
+            AllocateType::AnyPages => (0, 0),
+            AllocateType::MaxAddress(addr) => (1, addr),
+            AllocateType::Address(addr) => (2, addr),
+        };
+        match (self.allocate_pages)(allocate_type, memory_type, pages, &mut memory) {
+            EfiStatus::Success => Ok(memory),
+            EfiStatus::OutOfResources => Err("AllocatePages: out of memory"),
+            EfiStatus::NotFound => Err("AllocatePages: the pages are not available"),
+            _ => Err("AllocatePages failed"),
+        }
+    }
+    /// Frees pages from allocate_pages().
+    ///
+    /// # Safety
+    /// The pages must not be used anymore.
+    pub unsafe fn free_pages(&self, addr: u64, pages: usize) -> Result<()> {
+        match (self.free_pages)(addr, pages) {
+            EfiStatus::Success => Ok(()),
+            _ => Err("FreePages failed"),
+        }
+    }
+    /// Allocates size bytes aligned to 8 bytes.
+    pub fn allocate_pool(&self, memory_type: EfiMemoryType, size: usize) -> Result<*mut u8> {
+        let mut buffer = null_mut();
+        match (self.allocate_pool)(memory_type, size, &mut buffer) {
+            EfiStatus::Success => Ok(buffer),
+            EfiStatus::OutOfResources => Err("AllocatePool: out of memory"),
+            _ => Err("AllocatePool failed"),
+        }
+    }
+    /// Frees a buffer from allocate_pool().
+    ///
+    /// # Safety
+    /// The buffer must not be used anymore.
+    pub unsafe fn free_pool(&self, buffer: *mut u8) -> Result<()> {
+        match (self.free_pool)(buffer) {
+            EfiStatus::Success => Ok(()),
+            _ => Err("FreePool failed"),
+        }
+    }
+}
+impl EfiBootServicesTable {
+    /// Returns the interface of the protocol that the handle supports.
+    pub fn handle_protocol(&self, handle: EfiHandle, protocol: &EfiGuid) -> Result<*mut EfiVoid> {
+        let mut interface = null_mut::<EfiVoid>();
+        let status = (self.handle_protocol)(handle, protocol, &mut interface);
+        if status != EfiStatus::Success {
+            return Err("HandleProtocol failed");
+        }
+        Ok(interface)
+    }
+    /// Loads an EFI application specified by a device path.
+    pub fn load_image(&self, parent: EfiHandle, device_path: &[u8]) -> Result<EfiHandle> {
+        let mut image = 0;
+        match (self.load_image)(
+            false,
+            parent,
+            device_path.as_ptr(),
+            core::ptr::null(),
+            0,
+            &mut image,
+        ) {
+            EfiStatus::Success => Ok(image),
+            EfiStatus::NotFound => Err("LoadImage: file not found"),
+            EfiStatus::SecurityViolation => Err("LoadImage: rejected by Secure Boot"),
+            EfiStatus::Unsupported | EfiStatus::LoadError => Err("LoadImage: not an EFI image"),
+            _ => Err("LoadImage failed"),
+        }
+    }
+    /// Loads an EFI application from an image in memory.
+    pub fn load_image_from_buffer(&self, parent: EfiHandle, image: &[u8]) -> Result<EfiHandle> {
+        let mut handle = 0;
+        match (self.load_image)(
+            false,
+            parent,
+            core::ptr::null(),
+            image.as_ptr(),
+            image.len(),
+            &mut handle,
+        ) {
+            EfiStatus::Success => Ok(handle),
+            EfiStatus::SecurityViolation => Err("LoadImage: rejected by Secure Boot"),
+            EfiStatus::Unsupported | EfiStatus::LoadError => Err("LoadImage: not an EFI image"),
+            _ => Err("LoadImage failed"),
+        }
+    }
+    /// Transfers control to a loaded image and returns its exit status.
+    pub fn start_image(&self, image: EfiHandle) -> u64 {
+        let mut exit_data_size = 0;
+        let mut exit_data = null_mut();
+        (self.start_image)(image, &mut exit_data_size, &mut exit_data)
+    }
+    pub fn unload_image(&self, image: EfiHandle) -> Result<()> {
+        match (self.unload_image)(image) {
+            EfiStatus::Success => Ok(()),
+            _ => Err("UnloadImage failed"),
+        }
+    }
+    /// Resets the machine after timeout_secs seconds unless called again,
+    /// or never if timeout_secs is 0. The firmware arms it for 5 minutes
+    /// before starting an OS loader.
+    pub fn set_watchdog_timer(&self, timeout_secs: usize) -> Result<()> {
+        match (self.set_watchdog_timer)(timeout_secs, 0, 0, null()) {
+            EfiStatus::Success => Ok(()),
+            EfiStatus::Unsupported => Err("SetWatchdogTimer: no watchdog"),
+            _ => Err("SetWatchdogTimer failed"),
+        }
+    }
+    /// Takes over the machine from the firmware. map_key must be from the
+    /// last GetMemoryMap(), and no boot service can be called afterwards.
+    pub fn exit_boot_services(&self, image_handle: EfiHandle, map_key: usize) -> Result<()> {
+        match (self.exit_boot_services)(image_handle, map_key) {
+            EfiStatus::Success => Ok(()),
+            EfiStatus::InvalidParameter => Err("ExitBootServices: the memory map is stale"),
+            _ => Err("ExitBootServices failed"),
+        }
+    }
+    /// Busy-waits with the timer of the firmware, before ours is calibrated.
+    pub fn stall(&self, duration: Duration) -> Result<()> {
+        match (self.stall)(duration.as_micros() as usize) {
+            EfiStatus::Success => Ok(()),
+            _ => Err("Stall failed"),
+        }
+    }
+}
+const _: () = assert!(offset_of!(EfiBootServicesTable, allocate_pages) == 40);
+const _: () = assert!(offset_of!(EfiBootServicesTable, free_pages) == 48);
+const _: () = assert!(offset_of!(EfiBootServicesTable, get_memory_map) == 56);
+const _: () = assert!(offset_of!(EfiBootServicesTable, allocate_pool) == 64);
+const _: () = assert!(offset_of!(EfiBootServicesTable, free_pool) == 72);
+const _: () = assert!(offset_of!(EfiBootServicesTable, wait_for_event) == 96);
+const _: () = assert!(offset_of!(EfiBootServicesTable, handle_protocol) == 152);
+const _: () = assert!(offset_of!(EfiBootServicesTable, load_image) == 200);
+const _: () = assert!(offset_of!(EfiBootServicesTable, start_image) == 208);
+const _: () = assert!(offset_of!(EfiBootServicesTable, unload_image) == 224);
+const _: () = assert!(offset_of!(EfiBootServicesTable, exit_boot_services) == 232);
+const _: () = assert!(offset_of!(EfiBootServicesTable, stall) == 248);
+const _: () = assert!(offset_of!(EfiBootServicesTable, set_watchdog_timer) == 256);
+const _: () = assert!(offset_of!(EfiBootServicesTable, locate_protocol) == 320);
+
+pub const EFI_ACPI_20_TABLE_GUID: EfiGuid = EfiGuid {
+    data0: 0x8868e871,
+    data1: 0xe4f1,
+    data2: 0x11d3,
+    data3: [0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81],
+};
+
+#[repr(C)]
+pub struct EfiConfigurationTable {
+    pub vendor_guid: EfiGuid,
+    pub vendor_table: *const EfiVoid,
+}
+
+pub const EFI_VARIABLE_NON_VOLATILE: u32 = 0x1;
+pub const EFI_VARIABLE_BOOTSERVICE_ACCESS: u32 = 0x2;
+pub const EFI_VARIABLE_RUNTIME_ACCESS: u32 = 0x4;
+
+/// The vendor GUID for the variables owned by WasabiOS.
+pub const WASABI_VENDOR_GUID: EfiGuid = EfiGuid {
+    data0: 0x8f2a6c3e,
+    data1: 0x1b7d,
+    data2: 0x4f5a,
+    data3: [0x9c, 0x21, 0x57, 0x61, 0x73, 0x61, 0x62, 0x69],
+};
+
+#[repr(C)]
+pub struct EfiRuntimeServicesTable {
+    _reserved0: [u64; 3],
+    get_time: extern "win64" fn(time: *mut EfiTime, capabilities: *mut u8) -> EfiStatus,
+    set_time: extern "win64" fn(time: *const EfiTime) -> EfiStatus,
+    _reserved1: [u64; 4],
+    get_variable: extern "win64" fn(
+        variable_name: *const u16,
+        vendor_guid: *const EfiGuid,
+        attributes: *mut u32,
+        data_size: *mut usize,
+        data: *mut u8,
+    ) -> EfiStatus,
+    _reserved2: [u64; 1],
+    set_variable: extern "win64" fn(
+        variable_name: *const u16,
+        vendor_guid: *const EfiGuid,
+        attributes: u32,
+        data_size: usize,
+        data: *const u8,
+    ) -> EfiStatus,
+    _reserved3: [u64; 1],
+    reset_system: extern "win64" fn(
+        reset_type: EfiResetType,
+        status: EfiStatus,
+        data_size: usize,
+        data: *const u8,
+    ),
+}
+const _: () = assert!(offset_of!(EfiRuntimeServicesTable, get_time) == 24);
+const _: () = assert!(offset_of!(EfiRuntimeServicesTable, set_time) == 32);
+const _: () = assert!(offset_of!(EfiRuntimeServicesTable, get_variable) == 72);
+const _: () = assert!(offset_of!(EfiRuntimeServicesTable, set_variable) == 88);
+const _: () = assert!(offset_of!(EfiRuntimeServicesTable, reset_system) == 104);
+
+/// EFI_TIME: the time of the firmware clock.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EfiTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    _pad1: u8,
+    pub nanosecond: u32,
+    /// The offset from UTC in minutes, or EFI_UNSPECIFIED_TIMEZONE.
+    pub time_zone: i16,
+    pub daylight: u8,
+    _pad2: u8,
+}
+const _: () = assert!(size_of::<EfiTime>() == 16);
+pub const EFI_UNSPECIFIED_TIMEZONE: i16 = 0x07ff;
+impl EfiTime {
+    /// The time in UTC, or None if it is not valid. A time without a time
+    /// zone is taken as UTC, as the RTC is.
+    pub fn to_date_time(&self) -> Option<DateTime> {
+        let local = DateTime {
+            year: self.year,
+            month: self.month,
+            day: self.day,
+            hour: self.hour,
+            minute: self.minute,
+            second: self.second,
+        };
+        let unix_time = local.to_unix_time()?;
+        match self.time_zone {
+            EFI_UNSPECIFIED_TIMEZONE => Some(local),
+            // UTC = ローカル時刻 - TimeZone
+            tz @ -1440..=1440 => {
+                let secs = unix_time.as_secs() as i64 - tz as i64 * 60;
+                Some(DateTime::from_unix_time(Duration::from_secs(
+                    u64::try_from(secs).ok()?,
+                )))
+            }
+            _ => None,
+        }
+    }
+    /// The time in UTC, with the time zone unspecified.
+    pub fn from_date_time(t: &DateTime) -> Self {
+        Self {
+            year: t.year,
+            month: t.month,
+            day: t.day,
+            hour: t.hour,
+            minute: t.minute,
+            second: t.second,
+            time_zone: EFI_UNSPECIFIED_TIMEZONE,
+            ..Default::default()
+        }
+    }
+}
+
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EfiResetType {
+    Cold = 0,
+    Warm = 1,
+    Shutdown = 2,
+}
+
+fn to_utf16z(s: &str) -> Vec<u16> {
+    s.encode_utf16().chain(core::iter::once(0)).collect()
+}
+
+impl EfiRuntimeServicesTable {
+    /// Reads a variable into buf and returns its size. Ok(None) if it does not exist.
+    pub fn get_variable(
+        &self,
+        name: &str,
+        guid: &EfiGuid,
+        buf: &mut [u8],
+    ) -> Result<Option<usize>> {
+        let name = to_utf16z(name);
+        let mut attributes = 0;
+        let mut size = buf.len();
+        match (self.get_variable)(
+            name.as_ptr(),
+            guid,
+            &mut attributes,
+            &mut size,
+            buf.as_mut_ptr(),
+        ) {
+            EfiStatus::Success => Ok(Some(size)),
+            EfiStatus::NotFound => Ok(None),
+            EfiStatus::BufferTooSmall => Err("GetVariable: buffer too small"),
+            _ => Err("GetVariable failed"),
+        }
+    }
+    /// Writes a variable. Empty data deletes it.
+    pub fn set_variable(
+        &self,
+        name: &str,
+        guid: &EfiGuid,
+        attributes: u32,
+        data: &[u8],
+    ) -> Result<()> {
+        let name = to_utf16z(name);
+        match (self.set_variable)(name.as_ptr(), guid, attributes, data.len(), data.as_ptr()) {
+            EfiStatus::Success => Ok(()),
+            EfiStatus::NotFound if data.is_empty() => Ok(()),
+            EfiStatus::WriteProtected => Err("SetVariable: write protected"),
+            EfiStatus::OutOfResources => Err("SetVariable: no space for variables"),
+            _ => Err("SetVariable failed"),
+        }
+    }
+    /// Reads the firmware clock.
+    pub fn get_time(&self) -> Result<EfiTime> {
+        let mut time = EfiTime::default();
+        match (self.get_time)(&mut time, null_mut()) {
+            EfiStatus::Success => Ok(time),
+            EfiStatus::DeviceError => Err("GetTime: device error"),
+            EfiStatus::Unsupported => Err("GetTime: no clock"),
+            _ => Err("GetTime failed"),
+        }
+    }
+    /// Sets the firmware clock.
+    pub fn set_time(&self, time: &EfiTime) -> Result<()> {
+        match (self.set_time)(time) {
+            EfiStatus::Success => Ok(()),
+            EfiStatus::InvalidParameter => Err("SetTime: invalid time"),
+            EfiStatus::Unsupported => Err("SetTime: not supported"),
+            _ => Err("SetTime failed"),
+        }
+    }
+    /// Resets or turns off the machine with the help of the firmware.
+    /// Returns only if that did not work.
+    pub fn reset_system(&self, reset_type: EfiResetType) {
+        (self.reset_system)(reset_type, EfiStatus::Success, 0, null());
+    }
+}
+
+#[repr(C)]
+pub struct EfiSystemTable {
+    _reserved0: [u64; 6],
+    con_in: *mut EfiSimpleTextInputProtocol,
+    _reserved1: [u64; 4],
+    pub runtime_services: &'static EfiRuntimeServicesTable,
+    pub boot_services: &'static EfiBootServicesTable,
+    number_of_table_entries: usize,
+    configuration_table: *const EfiConfigurationTable,
+}
+const _: () = assert!(offset_of!(EfiSystemTable, con_in) == 48);
+const _: () = assert!(offset_of!(EfiSystemTable, runtime_services) == 88);
+const _: () = assert!(offset_of!(EfiSystemTable, boot_services) == 96);
+const _: () = assert!(offset_of!(EfiSystemTable, configuration_table) == 112);
+impl EfiSystemTable {
+    /// Returns the next key pressed on the console of the firmware, or None
+    /// if there is none. The firmware must still be handling the
+    /// interrupts (see chainload::with_firmware_interrupts()).
+    pub fn read_key_stroke(&self) -> Result<Option<EfiInputKey>> {
+        if self.con_in.is_null() {
+            return Err("No console input");
+        }
+        let mut key = EfiInputKey::default();
+        // SAFETY: con_in is the protocol installed by the firmware
+        match unsafe { ((*self.con_in).read_key_stroke)(self.con_in, &mut key) } {
+            EfiStatus::Success => Ok(Some(key)),
+            EfiStatus::NotReady => Ok(None),
+            EfiStatus::DeviceError => Err("ReadKeyStroke: device error"),
+            _ => Err("ReadKeyStroke failed"),
+        }
+    }
+    /// Waits for a key on the console of the firmware, with the same
+    /// restriction as read_key_stroke().
+    pub fn wait_for_key(&self) -> Result<EfiInputKey> {
+        loop {
+            if let Some(key) = self.read_key_stroke()? {
+                return Ok(key);
+            }
+            // SAFETY: con_in is not null, as read_key_stroke() succeeded
+            let event = unsafe { (*self.con_in).wait_for_key };
+            let mut index = 0;
+            if (self.boot_services.wait_for_event)(1, &event, &mut index) != EfiStatus::Success {
+                return Err("WaitForEvent failed");
+            }
+        }
+    }
+    pub fn configuration_tables(&self) -> &[EfiConfigurationTable] {
+        unsafe {
+            core::slice::from_raw_parts(self.configuration_table, self.number_of_table_entries)
+        }
+    }
+    pub fn lookup_configuration_table(&self, guid: &EfiGuid) -> Option<*const EfiVoid> {
+        self.configuration_tables()
+            .iter()
+            .find(|t| t.vendor_guid == *guid)
+            .map(|t| t.vendor_table)
+    }
+}
+
+static IMAGE_HANDLE: AtomicU64 = AtomicU64::new(0);
+static SYSTEM_TABLE: AtomicPtr<EfiSystemTable> = AtomicPtr::new(null_mut());
+
+/// Remembers the arguments of efi_main so that code without them (e.g. shell
+/// commands) can call boot services later.
+pub fn init_efi_context(image_handle: EfiHandle, efi_system_table: &'static EfiSystemTable) {
+    IMAGE_HANDLE.store(image_handle, Ordering::SeqCst);
+    SYSTEM_TABLE.store(
+        efi_system_table as *const EfiSystemTable as *mut EfiSystemTable,
+        Ordering::SeqCst,
+    );
+}
+
+pub fn image_handle() -> Option<EfiHandle> {
+    Some(IMAGE_HANDLE.load(Ordering::SeqCst)).filter(|h| *h != 0)
+}
+
+pub fn system_table() -> Option<&'static EfiSystemTable> {
+    // SAFETY: only a &'static EfiSystemTable is stored
+    unsafe { SYSTEM_TABLE.load(Ordering::SeqCst).as_ref() }
+}
+
+const EFI_LOADED_IMAGE_PROTOCOL_GUID: EfiGuid = EfiGuid {
+    data0: 0x5b1b31a1,
+    data1: 0x9562,
+    data2: 0x11d2,
+    data3: [0x8e, 0x3f, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+};
+const EFI_DEVICE_PATH_PROTOCOL_GUID: EfiGuid = EfiGuid {
+    data0: 0x09576e91,
+    data1: 0x6d3f,
+    data2: 0x11d2,
+    data3: [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+};
+
+#[repr(C)]
+pub struct EfiLoadedImageProtocol {
+    revision: u32,
+    parent_handle: EfiHandle,
+    system_table: *const EfiSystemTable,
+    pub device_handle: EfiHandle,
+    file_path: *const u8,
+    _reserved: u64,
+    load_options_size: u32,
+    load_options: *const EfiVoid,
+    pub image_base: *const EfiVoid,
+    pub image_size: u64,
+}
+const _: () = assert!(offset_of!(EfiLoadedImageProtocol, device_handle) == 24);
+const _: () = assert!(offset_of!(EfiLoadedImageProtocol, image_size) == 72);
+
+pub fn loaded_image(
+    efi_system_table: &EfiSystemTable,
+    image: EfiHandle,
+) -> Result<&'static EfiLoadedImageProtocol> {
+    let p = efi_system_table
+        .boot_services
+        .handle_protocol(image, &EFI_LOADED_IMAGE_PROTOCOL_GUID)?;
+    Ok(unsafe { &*(p as *const EfiLoadedImageProtocol) })
+}
+
+/// Sets the options (command line) that a loaded but not yet started image
+/// will find in its EFI_LOADED_IMAGE_PROTOCOL.
+pub fn set_load_options(
+    efi_system_table: &EfiSystemTable,
+    image: EfiHandle,
+    options: &str,
+) -> Result<()> {
+    let p = efi_system_table
+        .boot_services
+        .handle_protocol(image, &EFI_LOADED_IMAGE_PROTOCOL_GUID)?
+        as *mut EfiLoadedImageProtocol;
+    // 起動したイメージが参照し続けるので解放しない
+    let options = to_utf16z(options).leak();
+    unsafe {
+        (*p).load_options_size = core::mem::size_of_val(options) as u32;
+        (*p).load_options = options.as_ptr() as *const EfiVoid;
+    }
+    Ok(())
+}
+
+/// Returns the load options of the image, assuming they are a UTF-16 string.
+pub fn load_options(efi_system_table: &EfiSystemTable, image: EfiHandle) -> Result<String> {
+    let li = loaded_image(efi_system_table, image)?;
+    if li.load_options.is_null() {
+        return Ok(String::new());
+    }
+    let units = unsafe {
+        core::slice::from_raw_parts(
+            li.load_options as *const u16,
+            li.load_options_size as usize / 2,
+        )
+    };
+    let units = units.split(|c| *c == 0).next().unwrap_or(&[]);
+    Ok(char::decode_utf16(units.iter().copied())
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect())
+}
+
+const DEVICE_PATH_TYPE_MEDIA: u8 = 4;
+const DEVICE_PATH_SUBTYPE_FILE_PATH: u8 = 4;
+const DEVICE_PATH_TYPE_END: u8 = 0x7f;
+const DEVICE_PATH_SUBTYPE_END_ENTIRE: u8 = 0xff;
+
+/// Builds the device path of a file on the same device (usually the ESP) as
+/// the running image, e.g. `\EFI\Shell.efi`.
+pub fn file_device_path(efi_system_table: &EfiSystemTable, path: &str) -> Result<Vec<u8>> {
+    let image = image_handle().ok_or("EFI context is not initialized")?;
+    let device = loaded_image(efi_system_table, image)?.device_handle;
+    let device_path = efi_system_table
+        .boot_services
+        .handle_protocol(device, &EFI_DEVICE_PATH_PROTOCOL_GUID)?
+        as *const u8;
+    let mut result = Vec::new();
+    // デバイスのパスを終端ノードの手前までコピーし、ファイルパスのノードを継ぎ足す
+    let mut node = device_path;
+    loop {
+        let (node_type, len) = unsafe { (*node, u16::from_le_bytes([*node.add(2), *node.add(3)])) };
+        if node_type == DEVICE_PATH_TYPE_END || len < 4 {
+            break;
+        }
+        result.extend_from_slice(unsafe { core::slice::from_raw_parts(node, len as usize) });
+        node = unsafe { node.add(len as usize) };
+    }
+    let name = to_utf16z(&path.replace('/', "\\"));
+    let len = u16::try_from(4 + name.len() * 2).or(Err("Path is too long"))?;
+    result.push(DEVICE_PATH_TYPE_MEDIA);
+    result.push(DEVICE_PATH_SUBTYPE_FILE_PATH);
+    result.extend_from_slice(&len.to_le_bytes());
+    for c in name {
+        result.extend_from_slice(&c.to_le_bytes());
+    }
+    result.extend_from_slice(&[DEVICE_PATH_TYPE_END, DEVICE_PATH_SUBTYPE_END_ENTIRE, 4, 0]);
+    Ok(result)
+}
+
+const EFI_SIMPLE_FILE_SYSTEM_PROTOCOL_GUID: EfiGuid = EfiGuid {
+    data0: 0x964e5b22,
+    data1: 0x6459,
+    data2: 0x11d2,
+    data3: [0x8e, 0x39, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+};
+const EFI_FILE_MODE_READ: u64 = 1;
+const EFI_FILE_MODE_WRITE: u64 = 2;
+const EFI_FILE_MODE_CREATE: u64 = 0x8000_0000_0000_0000;
+const FILE_READ_CHUNK: usize = 64 * 1024;
+
+#[repr(C)]
+struct EfiSimpleFileSystemProtocol {
+    revision: u64,
+    open_volume: extern "win64" fn(
+        this: *mut EfiSimpleFileSystemProtocol,
+        root: *mut *mut EfiFileProtocol,
+    ) -> EfiStatus,
+}
+
+#[repr(C)]
+struct EfiFileProtocol {
+    revision: u64,
+    open: extern "win64" fn(
+        this: *mut EfiFileProtocol,
+        new_handle: *mut *mut EfiFileProtocol,
+        file_name: *const u16,
+        open_mode: u64,
+        attributes: u64,
+    ) -> EfiStatus,
+    close: extern "win64" fn(this: *mut EfiFileProtocol) -> EfiStatus,
+    // 成功・失敗にかかわらずハンドルは閉じられる
+    delete: extern "win64" fn(this: *mut EfiFileProtocol) -> EfiStatus,
+    read: extern "win64" fn(
+        this: *mut EfiFileProtocol,
+        buffer_size: *mut usize,
+        buffer: *mut u8,
+    ) -> EfiStatus,
+    write: extern "win64" fn(
+        this: *mut EfiFileProtocol,
+        buffer_size: *mut usize,
+        buffer: *const u8,
+    ) -> EfiStatus,
+}
+const _: () = assert!(offset_of!(EfiFileProtocol, read) == 32);
+const _: () = assert!(offset_of!(EfiFileProtocol, write) == 40);
+
+/// Opens a file on the same device as the running image.
+fn open_file(
+    efi_system_table: &EfiSystemTable,
+    path: &str,
+    mode: u64,
+) -> Result<*mut EfiFileProtocol> {
+    let image = image_handle().ok_or("EFI context is not initialized")?;
+    let device = loaded_image(efi_system_table, image)?.device_handle;
+    let fs = efi_system_table
+        .boot_services
+        .handle_protocol(device, &EFI_SIMPLE_FILE_SYSTEM_PROTOCOL_GUID)?
+        as *mut EfiSimpleFileSystemProtocol;
+    let mut root = null_mut::<EfiFileProtocol>();
+    // SAFETY: fs is the protocol interface returned by the firmware
+    if unsafe { ((*fs).open_volume)(fs, &mut root) } != EfiStatus::Success {
+        return Err("Failed to open the volume");
+    }
+    let name = to_utf16z(&path.replace('/', "\\"));
+    let mut file = null_mut::<EfiFileProtocol>();
+    // SAFETY: root is a valid file handle until it is closed below
+    let status = unsafe { ((*root).open)(root, &mut file, name.as_ptr(), mode, 0) };
+    let _ = unsafe { ((*root).close)(root) };
+    match status {
+        EfiStatus::Success => Ok(file),
+        EfiStatus::NotFound => Err("File not found"),
+        EfiStatus::WriteProtected => Err("The volume is write-protected"),
+        _ => Err("Failed to open the file"),
+    }
+}
+
+/// Reads a whole file on the same device (usually the ESP) as the running
+/// image, e.g. `\EFI\wasabi\font.psf`.
+pub fn read_file(efi_system_table: &EfiSystemTable, path: &str) -> Result<Vec<u8>> {
+    let file = open_file(efi_system_table, path, EFI_FILE_MODE_READ)?;
+    let mut data = Vec::new();
+    let result = loop {
+        let ofs = data.len();
+        data.resize(ofs + FILE_READ_CHUNK, 0);
+        let mut size = FILE_READ_CHUNK;
+        // SAFETY: file is a valid file handle and data has room for size bytes
+        let status = unsafe { ((*file).read)(file, &mut size, data.as_mut_ptr().add(ofs)) };
+        data.truncate(ofs + size);
+        if status != EfiStatus::Success {
+            break Err("Failed to read the file");
+        }
+        if size == 0 {
+            break Ok(data);
+        }
+    };
+    let _ = unsafe { ((*file).close)(file) };
+    result
+}
+
+/// Creates or replaces a file on the same device as the running image.
+pub fn write_file(efi_system_table: &EfiSystemTable, path: &str, data: &[u8]) -> Result<()> {
+    // 既存のファイルは切り詰められないので、一度消してから作り直す
+    if let Ok(file) = open_file(
+        efi_system_table,
+        path,
+        EFI_FILE_MODE_READ | EFI_FILE_MODE_WRITE,
+    ) {
+        // SAFETY: file is a valid file handle, which Delete() closes
+        if unsafe { ((*file).delete)(file) } != EfiStatus::Success {
+            return Err("Failed to replace the file");
+        }
+    }
+    let file = open_file(
+        efi_system_table,
+        path,
+        EFI_FILE_MODE_READ | EFI_FILE_MODE_WRITE | EFI_FILE_MODE_CREATE,
+    )?;
+    let mut size = data.len();
+    // SAFETY: file is a valid file handle and data has size bytes
+    let status = unsafe { ((*file).write)(file, &mut size, data.as_ptr()) };
+    let _ = unsafe { ((*file).close)(file) };
+    if status != EfiStatus::Success || size != data.len() {
+        return Err("Failed to write the file");
+    }
+    Ok(())
+}
+
+#[repr(C)]
+#[derive(Debug)]
+struct EfiGraphicsOutputProtocolPixelInfo {
+    pub version: u32,
+    pub horizontal_resolution: u32,
+    pub vertical_resolution: u32,
+    pub pixel_format: u32,
+    _pixel_information: [u32; 4],
+    pub pixels_per_scan_line: u32,
+}
+const _: () = assert!(size_of::<EfiGraphicsOutputProtocolPixelInfo>() == 36);
+
+/// PixelRedGreenBlueReserved8BitPerColor and PixelBlueGreenRedReserved8BitPerColor,
+/// the formats with a linear 32bpp framebuffer.
+const EFI_GRAPHICS_PIXEL_FORMATS_32BPP: [u32; 2] = [0, 1];
+
+#[repr(C)]
+#[derive(Debug)]
+struct EfiGraphicsOutputProtocolMode<'a> {
+    pub max_mode: u32,
+    pub mode: u32,
+    pub info: &'a EfiGraphicsOutputProtocolPixelInfo,
+    pub size_of_info: u32,
+    pub frame_buffer_base: usize,
+    pub frame_buffer_size: usize,
+}
+
+#[repr(C)]
+#[derive(Debug)]
+struct EfiGraphicsOutputProtocol<'a> {
+    query_mode: extern "win64" fn(
+        this: *const EfiGraphicsOutputProtocol,
+        mode_number: u32,
+        size_of_info: *mut usize,
+        info: *mut *mut EfiGraphicsOutputProtocolPixelInfo,
+    ) -> EfiStatus,
+    set_mode:
+        extern "win64" fn(this: *const EfiGraphicsOutputProtocol, mode_number: u32) -> EfiStatus,
+    _blt: u64,
+    pub mode: &'a EfiGraphicsOutputProtocolMode<'a>,
+}
+fn locate_graphic_protocol(
+    efi_system_table: &EfiSystemTable,
+) -> Result<&EfiGraphicsOutputProtocol<'_>> {
+    let mut efi_graphics_output_protocol = null_mut::<EfiGraphicsOutputProtocol>();
+    let status = (efi_system_table.boot_services.locate_protocol)(
+        &EFI_GRAPHICS_OUTPUT_PROTOCOL_GUID,
+        null_mut::<EfiVoid>(),
+        &mut efi_graphics_output_protocol as *mut *mut EfiGraphicsOutputProtocol
+            as *mut *mut EfiVoid,
+    );
+    if status != EfiStatus::Success {
+        return Err("Failed to locate graphics outptut protocol");
+    }
+    Ok(unsafe { &*efi_graphics_output_protocol })
+}
+
+/// A mode of the GOP that has a 32bpp framebuffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoMode {
+    pub number: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Lists the modes that the screen can be switched to.
+pub fn video_modes(efi_system_table: &EfiSystemTable) -> Result<Vec<VideoMode>> {
+    let gp = locate_graphic_protocol(efi_system_table)?;
+    let mut modes = Vec::new();
+    for number in 0..gp.mode.max_mode {
+        let mut size = 0;
+        let mut info = null_mut::<EfiGraphicsOutputProtocolPixelInfo>();
+        if (gp.query_mode)(gp, number, &mut size, &mut info) != EfiStatus::Success {
+            continue;
+        }
+        // SAFETY: QueryMode() succeeded, so info points to a PixelInfo in a
+        // buffer from the pool, which is ours to free
+        unsafe {
+            if EFI_GRAPHICS_PIXEL_FORMATS_32BPP.contains(&(*info).pixel_format) {
+                modes.push(VideoMode {
+                    number,
+                    width: (*info).horizontal_resolution,
+                    height: (*info).vertical_resolution,
+                });
+            }
+            let _ = efi_system_table.boot_services.free_pool(info as *mut u8);
+        }
+    }
+    Ok(modes)
+}
+
+/// Switches the screen to a mode of width x height. The VRAM from
+/// init_vram() must be taken again afterwards.
+pub fn set_resolution(efi_system_table: &EfiSystemTable, width: u32, height: u32) -> Result<()> {
+    let gp = locate_graphic_protocol(efi_system_table)?;
+    let info = gp.mode.info;
+    if info.horizontal_resolution == width && info.vertical_resolution == height {
+        return Ok(());
+    }
+    let mode = video_modes(efi_system_table)?
+        .into_iter()
+        .find(|m| m.width == width && m.height == height)
+        .ok_or("No such video mode")?;
+    match (gp.set_mode)(gp, mode.number) {
+        EfiStatus::Success => Ok(()),
+        EfiStatus::Unsupported => Err("SetMode: unsupported mode"),
+        _ => Err("SetMode failed"),
+    }
+}
+
+/// A key from EFI_SIMPLE_TEXT_INPUT_PROTOCOL: a scan code for the keys
+/// without a character, or else a UCS-2 character.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EfiInputKey {
+    pub scan_code: u16,
+    pub unicode_char: u16,
+}
+impl EfiInputKey {
+    /// The key as the keymap translates it, or None for unknown keys.
+    pub fn to_key(&self) -> Option<Key> {
+        let named = |k| Some(Key::Named(k));
+        match self.scan_code {
+            0 => match char::from_u32(self.unicode_char as u32)? {
+                '\0' => None,
+                '\r' => Some(Key::Char('\n')),
+                c => Some(Key::Char(c)),
+            },
+            0x01 => named(NamedKey::Up),
+            0x02 => named(NamedKey::Down),
+            0x03 => named(NamedKey::Right),
+            0x04 => named(NamedKey::Left),
+            0x05 => named(NamedKey::Home),
+            0x06 => named(NamedKey::End),
+            0x07 => named(NamedKey::Insert),
+            0x08 => named(NamedKey::Delete),
+            0x09 => named(NamedKey::PageUp),
+            0x0a => named(NamedKey::PageDown),
+            n @ 0x0b..=0x16 => named(NamedKey::F((n - 0x0a) as u8)),
+            0x17 => Some(Key::Char('\x1b')),
+            _ => None,
+        }
+    }
+}
+
+#[repr(C)]
+struct EfiSimpleTextInputProtocol {
+    _reset: u64,
+    read_key_stroke: extern "win64" fn(
+        this: *mut EfiSimpleTextInputProtocol,
+        key: *mut EfiInputKey,
+    ) -> EfiStatus,
+    wait_for_key: EfiEvent,
+}
+
+const EFI_RNG_PROTOCOL_GUID: EfiGuid = EfiGuid {
+    data0: 0x3152bca5,
+    data1: 0xeade,
+    data2: 0x433d,
+    data3: [0x86, 0x2e, 0xc0, 0x1c, 0xdc, 0x29, 0x1f, 0x44],
+};
+
+#[repr(C)]
+struct EfiRngProtocol {
+    _get_info: u64,
+    get_rng: extern "win64" fn(
+        this: *mut EfiRngProtocol,
+        algorithm: *const EfiGuid,
+        value_length: usize,
+        value: *mut u8,
+    ) -> EfiStatus,
+}
+
+/// Fills buf with random bytes from EFI_RNG_PROTOCOL, with the default
+/// algorithm of the firmware.
+pub fn get_rng(efi_system_table: &EfiSystemTable, buf: &mut [u8]) -> Result<()> {
+    let mut rng = null_mut::<EfiRngProtocol>();
+    let status = (efi_system_table.boot_services.locate_protocol)(
+        &EFI_RNG_PROTOCOL_GUID,
+        null_mut::<EfiVoid>(),
+        &mut rng as *mut *mut EfiRngProtocol as *mut *mut EfiVoid,
+    );
+    if status != EfiStatus::Success {
+        return Err("No EFI_RNG_PROTOCOL");
+    }
+    // SAFETY: the firmware returned a valid protocol, and buf has room for
+    // buf.len() bytes
+    match unsafe { ((*rng).get_rng)(rng, null(), buf.len(), buf.as_mut_ptr()) } {
+        EfiStatus::Success => Ok(()),
+        EfiStatus::NotReady => Err("GetRNG: not ready"),
+        _ => Err("GetRNG failed"),
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct VramBefferInfo {
+    buf: *mut u8,
+    pub width: i64,
+    pub height: i64,
+    pixels_per_line: i64,
+}
+
+impl Bitmap for VramBefferInfo {
+    fn bytes_per_pixel(&self) -> i64 {
+        4
+    }
+    fn pixels_per_scan_line(&self) -> i64 {
+        self.pixels_per_line
+    }
+    fn width(&self) -> i64 {
+        self.width
+    }
+    fn height(&self) -> i64 {
+        self.height
+    }
+    fn buf_mut(&mut self) -> *mut u8 {
+        self.buf
+    }
+}
+
+impl VramBefferInfo {
+    /// Wraps a linear 32bpp framebuffer that was set up by someone else
+    /// (e.g. a bootloader).
+    ///
+    /// # Safety
+    /// buf must point to a mapped framebuffer of at least
+    /// pixels_per_line * height * 4 bytes.
+    pub unsafe fn from_raw(buf: *mut u8, width: i64, height: i64, pixels_per_line: i64) -> Self {
+        Self {
+            buf,
+            width,
+            height,
+            pixels_per_line,
+        }
+    }
+}
+
+pub fn init_vram(efi_system_table: &EfiSystemTable) -> Result<VramBefferInfo> {
+    let gp = locate_graphic_protocol(efi_system_table)?;
+
+    Ok(VramBefferInfo {
+        buf: gp.mode.frame_buffer_base as *mut u8,
+        width: gp.mode.info.horizontal_resolution as i64,
+        height: gp.mode.info.vertical_resolution as i64,
+        pixels_per_line: gp.mode.info.pixels_per_scan_line as i64,
+    })
+}
+
+pub struct VramTextWriter<'a> {
+    vram: &'a mut VramBefferInfo,
+    cursor_x: i64,
+    cursor_y: i64,
+}
+impl<'a> VramTextWriter<'a> {
+    pub fn new(vram: &'a mut VramBefferInfo) -> Self {
+        Self {
+            vram,
+            cursor_x: 0,
+            cursor_y: 0,
+        }
+    }
+}
+
+impl fmt::Write for VramTextWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            if c == '\n' {
+                self.cursor_x = 0;
+                self.cursor_y += 16;
+                continue;
+            }
+            if c == '\x08' {
+                if self.cursor_x >= 8 {
+                    self.cursor_x -= 8;
+                    let _ = fill_rect(self.vram, 0x000000, self.cursor_x, self.cursor_y, 8, 16);
+                }
+                continue;
+            }
+            draw_font_fg(self.vram, self.cursor_x, self.cursor_y, 0xffffff, c);
+            self.cursor_x += 8;
+        }
+        Ok(())
+    }
+}
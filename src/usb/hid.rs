@@ -0,0 +1,90 @@
+//! USB HID boot protocol mouse.
+//!
+//! The mouse is switched to the boot protocol, so the reports have a fixed
+//! layout and no report descriptor is needed. Most mice still send the
+//! wheel in the 4th byte in the boot protocol.
+
+use super::xhci;
+use super::xhci::EndpointConfig;
+use super::xhci::Xhci;
+use super::EndpointDescriptor;
+use super::InterfaceDescriptor;
+use super::SetupPacket;
+use super::UsbDevice;
+use crate::input;
+use crate::input::InputEvent;
+use crate::mouse::MouseButtons;
+use crate::mouse::MouseEvent;
+use crate::result::Result;
+
+const CLASS_HID: u8 = 3;
+const SUBCLASS_BOOT: u8 = 1;
+const PROTOCOL_MOUSE: u8 = 2;
+
+const REQUEST_SET_IDLE: u8 = 0x0a;
+const REQUEST_SET_PROTOCOL: u8 = 0x0b;
+const PROTOCOL_BOOT: u16 = 0;
+
+pub fn is_boot_mouse(iface: &InterfaceDescriptor) -> bool {
+    iface.class == CLASS_HID && iface.subclass == SUBCLASS_BOOT && iface.protocol == PROTOCOL_MOUSE
+}
+
+/// Converts a boot protocol report to an event: buttons, X, Y and
+/// optionally the wheel. The wheel of HID is positive when scrolled up.
+pub fn parse_boot_mouse_report(report: &[u8]) -> Option<MouseEvent> {
+    let [buttons, x, y, rest @ ..] = report else {
+        return None;
+    };
+    let wheel = rest.first().map(|&z| -(z as i8 as i32)).unwrap_or(0);
+    Some(MouseEvent {
+        dx: *x as i8 as i32,
+        dy: *y as i8 as i32,
+        wheel,
+        buttons: MouseButtons::from_bits(*buttons),
+    })
+}
+
+fn on_report(_slot: u8, report: &[u8]) {
+    if let Some(event) = parse_boot_mouse_report(report) {
+        input::push(InputEvent::Mouse(event));
+    }
+}
+
+fn class_request(xhci: &mut Xhci, slot: u8, iface: u8, request: u8, value: u16) -> Result<()> {
+    let setup = SetupPacket {
+        request_type: SetupPacket::CLASS | SetupPacket::TO_INTERFACE,
+        request,
+        value,
+        index: iface as u16,
+        length: 0,
+    };
+    xhci.control(slot, setup, &mut [])
+}
+
+/// Starts receiving reports from the mouse.
+pub fn attach_mouse(xhci: &mut Xhci, dev: &UsbDevice, iface: &InterfaceDescriptor) -> Result<()> {
+    let ep = iface
+        .endpoints
+        .iter()
+        .find(|ep| ep.is_in() && ep.transfer_type() == EndpointDescriptor::TRANSFER_INTERRUPT)
+        .ok_or("HID mouse has no interrupt IN endpoint")?;
+    class_request(
+        xhci,
+        dev.slot,
+        iface.number,
+        REQUEST_SET_PROTOCOL,
+        PROTOCOL_BOOT,
+    )?;
+    // 動いたときだけ報告させる。対応していないマウスもあるので失敗は無視する
+    let _ = class_request(xhci, dev.slot, iface.number, REQUEST_SET_IDLE, 0);
+    xhci.configure_endpoints(
+        dev.slot,
+        &[EndpointConfig {
+            dci: ep.dci(),
+            ep_type: xhci::EP_TYPE_INTERRUPT_IN,
+            max_packet_size: ep.max_packet_size,
+            interval: ep.xhci_interval(dev.speed),
+        }],
+    )?;
+    xhci.start_interrupt_in(dev.slot, ep.dci(), ep.max_packet_size as usize, on_report)
+}
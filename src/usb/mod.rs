@@ -0,0 +1,403 @@
+//! USB support on xHCI controllers.
+//!
+//! init() resets the first xHCI controller, addresses the devices on its
+//! root hub ports, and hands their interfaces to the class drivers. After
+//! that, the events of the controller are processed by an executor task,
+//! woken by the interrupt of the controller if it has one, or periodically.
+//!
+//! The firmware's USB driver stops working once the kernel takes over the
+//! controller, so booting from a USB disk is not supported.
+
+pub mod hid;
+pub mod msc;
+pub mod xhci;
+
+use crate::executor;
+use crate::executor::IrqQueue;
+use crate::kexec;
+use crate::mutex::Mutex;
+use crate::pci;
+use crate::pic;
+use crate::result::Result;
+use crate::time;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::AtomicU8;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+use xhci::Xhci;
+
+const DESCRIPTOR_DEVICE: u8 = 1;
+const DESCRIPTOR_CONFIGURATION: u8 = 2;
+const DESCRIPTOR_INTERFACE: u8 = 4;
+const DESCRIPTOR_ENDPOINT: u8 = 5;
+
+const REQUEST_GET_DESCRIPTOR: u8 = 6;
+const REQUEST_SET_CONFIGURATION: u8 = 9;
+
+/// The setup stage of a control transfer.
+#[derive(Debug, Clone, Copy)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+impl SetupPacket {
+    pub const DEVICE_TO_HOST: u8 = 0x80;
+    pub const CLASS: u8 = 0x20;
+    pub const TO_INTERFACE: u8 = 0x01;
+    pub const TO_ENDPOINT: u8 = 0x02;
+
+    fn to_u64(self) -> u64 {
+        self.request_type as u64
+            | (self.request as u64) << 8
+            | (self.value as u64) << 16
+            | (self.index as u64) << 32
+            | (self.length as u64) << 48
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeviceDescriptor {
+    pub usb_version: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub max_packet_size0: u8,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub num_configurations: u8,
+}
+impl DeviceDescriptor {
+    pub const SIZE: usize = 18;
+
+    /// Parses a device descriptor. The first 8 bytes are enough for
+    /// max_packet_size0.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 8 || bytes[1] != DESCRIPTOR_DEVICE {
+            return Err("Invalid device descriptor");
+        }
+        let u16_at = |i: usize| match bytes.get(i..i + 2) {
+            Some(b) => u16::from_le_bytes([b[0], b[1]]),
+            None => 0,
+        };
+        Ok(Self {
+            usb_version: u16_at(2),
+            class: bytes[4],
+            subclass: bytes[5],
+            protocol: bytes[6],
+            max_packet_size0: bytes[7],
+            vendor_id: u16_at(8),
+            product_id: u16_at(10),
+            num_configurations: bytes.get(17).copied().unwrap_or(0),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndpointDescriptor {
+    pub address: u8,
+    pub attributes: u8,
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+impl EndpointDescriptor {
+    pub const TRANSFER_BULK: u8 = 2;
+    pub const TRANSFER_INTERRUPT: u8 = 3;
+
+    pub fn number(&self) -> u8 {
+        self.address & 0x0f
+    }
+    pub fn is_in(&self) -> bool {
+        self.address & 0x80 != 0
+    }
+    pub fn transfer_type(&self) -> u8 {
+        self.attributes & 0b11
+    }
+    /// The device context index of the endpoint in xHCI.
+    pub fn dci(&self) -> u8 {
+        self.number() * 2 + self.is_in() as u8
+    }
+    /// The interval of an interrupt endpoint in the xHCI encoding
+    /// (2^n * 125 us). bInterval is in frames (1 ms) for low and full speed
+    /// devices, and an exponent for faster ones.
+    pub fn xhci_interval(&self, speed: u8) -> u8 {
+        match speed {
+            xhci::SPEED_FULL | xhci::SPEED_LOW => {
+                let frames = self.interval.max(1) as u32 * 8;
+                (31 - frames.leading_zeros()) as u8
+            }
+            _ => self.interval.clamp(1, 16) - 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceDescriptor {
+    pub number: u8,
+    pub alternate_setting: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub endpoints: Vec<EndpointDescriptor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigurationDescriptor {
+    pub value: u8,
+    pub interfaces: Vec<InterfaceDescriptor>,
+}
+impl ConfigurationDescriptor {
+    /// Parses a configuration descriptor with the interface and endpoint
+    /// descriptors that follow it. Other descriptors are skipped.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < 9 || bytes[1] != DESCRIPTOR_CONFIGURATION {
+            return Err("Invalid configuration descriptor");
+        }
+        let mut config = Self {
+            value: bytes[5],
+            interfaces: Vec::new(),
+        };
+        let mut rest = bytes;
+        while rest.len() >= 2 {
+            let len = rest[0] as usize;
+            if len < 2 || len > rest.len() {
+                return Err("Invalid descriptor length");
+            }
+            let d = &rest[..len];
+            match d[1] {
+                DESCRIPTOR_INTERFACE if len >= 9 => config.interfaces.push(InterfaceDescriptor {
+                    number: d[2],
+                    alternate_setting: d[3],
+                    class: d[5],
+                    subclass: d[6],
+                    protocol: d[7],
+                    endpoints: Vec::new(),
+                }),
+                DESCRIPTOR_ENDPOINT if len >= 7 => {
+                    if let Some(iface) = config.interfaces.last_mut() {
+                        iface.endpoints.push(EndpointDescriptor {
+                            address: d[2],
+                            attributes: d[3],
+                            max_packet_size: u16::from_le_bytes([d[4], d[5]]) & 0x7ff,
+                            interval: d[6],
+                        });
+                    }
+                }
+                _ => {}
+            }
+            rest = &rest[len..];
+        }
+        Ok(config)
+    }
+    /// The alternate setting 0 of each interface.
+    pub fn default_interfaces(&self) -> impl Iterator<Item = &InterfaceDescriptor> {
+        self.interfaces.iter().filter(|i| i.alternate_setting == 0)
+    }
+}
+
+/// A device on a root hub port.
+#[derive(Debug, Clone)]
+pub struct UsbDevice {
+    pub slot: u8,
+    pub port: u8,
+    pub speed: u8,
+    pub descriptor: DeviceDescriptor,
+    pub config: ConfigurationDescriptor,
+    /// The class drivers bound to the interfaces.
+    pub drivers: Vec<&'static str>,
+}
+
+static CONTROLLER: Mutex<Option<Xhci>> = Mutex::new(None);
+static DEVICES: Mutex<Vec<UsbDevice>> = Mutex::new(Vec::new());
+
+/// Runs f with the controller.
+pub fn with_controller<T>(f: impl FnOnce(&mut Xhci) -> Result<T>) -> Result<T> {
+    f(CONTROLLER.lock().as_mut().ok_or("USB is not initialized")?)
+}
+
+pub fn devices() -> Vec<UsbDevice> {
+    DEVICES.lock().clone()
+}
+
+pub fn get_descriptor(
+    xhci: &mut Xhci,
+    slot: u8,
+    descriptor_type: u8,
+    buf: &mut [u8],
+) -> Result<()> {
+    let setup = SetupPacket {
+        request_type: SetupPacket::DEVICE_TO_HOST,
+        request: REQUEST_GET_DESCRIPTOR,
+        value: (descriptor_type as u16) << 8,
+        index: 0,
+        length: buf.len() as u16,
+    };
+    xhci.control(slot, setup, buf)
+}
+
+fn enumerate(xhci: &mut Xhci, port: u8) -> Result<UsbDevice> {
+    let speed = xhci.reset_port(port)?;
+    let slot = xhci.address_device(port, speed)?;
+    let mut buf = [0u8; DeviceDescriptor::SIZE];
+    get_descriptor(xhci, slot, DESCRIPTOR_DEVICE, &mut buf[..8])?;
+    let max_packet_size0 = DeviceDescriptor::parse(&buf[..8])?.max_packet_size0;
+    if speed == xhci::SPEED_FULL && max_packet_size0 != 8 {
+        xhci.set_ep0_max_packet_size(slot, max_packet_size0 as u16)?;
+    }
+    get_descriptor(xhci, slot, DESCRIPTOR_DEVICE, &mut buf)?;
+    let descriptor = DeviceDescriptor::parse(&buf)?;
+    let mut header = [0u8; 9];
+    get_descriptor(xhci, slot, DESCRIPTOR_CONFIGURATION, &mut header)?;
+    let total_length = u16::from_le_bytes([header[2], header[3]]) as usize;
+    let mut bytes = vec![0u8; total_length.max(header.len())];
+    get_descriptor(xhci, slot, DESCRIPTOR_CONFIGURATION, &mut bytes)?;
+    let config = ConfigurationDescriptor::parse(&bytes)?;
+    let setup = SetupPacket {
+        request_type: 0,
+        request: REQUEST_SET_CONFIGURATION,
+        value: config.value as u16,
+        index: 0,
+        length: 0,
+    };
+    xhci.control(slot, setup, &mut [])?;
+    Ok(UsbDevice {
+        slot,
+        port,
+        speed,
+        descriptor,
+        config,
+        drivers: Vec::new(),
+    })
+}
+
+/// Binds the class drivers to the interfaces of the device.
+fn attach_drivers(xhci: &mut Xhci, dev: &mut UsbDevice) {
+    for iface in dev.config.default_interfaces() {
+        let (name, result) = if hid::is_boot_mouse(iface) {
+            ("hid-mouse", hid::attach_mouse(xhci, dev, iface))
+        } else if msc::is_bulk_only(iface) {
+            ("msc", msc::attach(xhci, dev, iface))
+        } else {
+            continue;
+        };
+        match result {
+            Ok(()) => dev.drivers.push(name),
+            Err(e) => crate::warn!("port {}: {name}: {e}", dev.port),
+        }
+    }
+}
+
+static IRQ_REGISTERS: [AtomicUsize; 2] = [AtomicUsize::new(0), AtomicUsize::new(0)];
+static IRQ_EVENTS: IrqQueue<()> = IrqQueue::new(1);
+/// The IRQ of the controller, or NO_IRQ if events are polled.
+static IRQ: AtomicU8 = AtomicU8::new(NO_IRQ);
+const NO_IRQ: u8 = 0xff;
+
+fn irq_handler(_irq: u8) {
+    let op = IRQ_REGISTERS[0].load(Ordering::Relaxed);
+    let rt = IRQ_REGISTERS[1].load(Ordering::Relaxed);
+    if op != 0 && Xhci::ack_interrupt(op, rt) {
+        IRQ_EVENTS.push(());
+    }
+}
+
+fn poll() {
+    // 同期的な要求の完了を待っている間は、そちらがイベントを処理する
+    if let Some(mut xhci) = CONTROLLER.try_lock() {
+        if let Some(xhci) = xhci.as_mut() {
+            xhci.poll_events();
+        }
+    }
+}
+
+/// Starts the first xHCI controller and its devices. Returns the number of
+/// devices found.
+pub fn init() -> Result<usize> {
+    let dev = pci::find_by_class(0x0c, 0x03, Some(0x30))
+        .into_iter()
+        .next()
+        .ok_or("No xHCI controller")?;
+    let mut xhci = Xhci::new(&dev)?;
+    let mut devices = Vec::new();
+    for port in xhci.connected_ports() {
+        match enumerate(&mut xhci, port) {
+            Ok(mut dev) => {
+                attach_drivers(&mut xhci, &mut dev);
+                devices.push(dev);
+            }
+            Err(e) => crate::warn!("port {port}: {e}"),
+        }
+    }
+    let (op, rt) = xhci.interrupt_registers();
+    IRQ_REGISTERS[0].store(op, Ordering::Relaxed);
+    IRQ_REGISTERS[1].store(rt, Ordering::Relaxed);
+    let n = devices.len();
+    *DEVICES.lock() = devices;
+    *CONTROLLER.lock() = Some(xhci);
+    #[cfg(feature = "storage")]
+    msc::register_block_devices();
+    let irq = dev.interrupt_line;
+    let has_irq =
+        (1..=4).contains(&dev.interrupt_pin) && pic::register_irq_handler(irq, irq_handler).is_ok();
+    if has_irq {
+        IRQ.store(irq, Ordering::Relaxed);
+    }
+    executor::spawn(async move {
+        loop {
+            if has_irq {
+                IRQ_EVENTS.pop().await;
+            } else {
+                time::sleep(Duration::from_millis(10)).await;
+            }
+            poll();
+        }
+    });
+    kexec::register_shutdown_hook("usb", || {
+        let irq = IRQ.swap(NO_IRQ, Ordering::Relaxed);
+        if irq != NO_IRQ {
+            pic::unregister_irq_handler(irq);
+        }
+        if let Some(xhci) = CONTROLLER.lock().as_ref() {
+            xhci.stop();
+        }
+    });
+    Ok(n)
+}
+
+/// The `lsusb` command.
+pub fn cmd_lsusb(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    if !args.is_empty() {
+        return Err("usage: lsusb");
+    }
+    for d in DEVICES.lock().iter() {
+        let _ = writeln!(
+            out,
+            "Port {:2} Slot {:2}: ID {:04x}:{:04x} {} speed, USB {:x}.{:02x} {}",
+            d.port,
+            d.slot,
+            d.descriptor.vendor_id,
+            d.descriptor.product_id,
+            xhci::speed_name(d.speed),
+            d.descriptor.usb_version >> 8,
+            d.descriptor.usb_version & 0xff,
+            d.drivers.join(" ")
+        );
+        for i in d.config.default_interfaces() {
+            let _ = writeln!(
+                out,
+                "    Interface {}: class {:02x}:{:02x}:{:02x}, {} endpoints",
+                i.number,
+                i.class,
+                i.subclass,
+                i.protocol,
+                i.endpoints.len()
+            );
+        }
+    }
+    Ok(())
+}
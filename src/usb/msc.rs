@@ -0,0 +1,395 @@
+//! USB mass storage with the Bulk-Only Transport (BOT) and SCSI commands.
+//!
+//! Each command is a Command Block Wrapper (CBW) sent to the bulk OUT
+//! endpoint, an optional data stage, and a Command Status Wrapper (CSW)
+//! received from the bulk IN endpoint. Only LUN 0 is used, and READ(10) /
+//! WRITE(10) limit the disks to 2^32 blocks.
+
+use super::xhci;
+use super::xhci::EndpointConfig;
+use super::xhci::Xhci;
+use super::EndpointDescriptor;
+use super::InterfaceDescriptor;
+use super::SetupPacket;
+use super::UsbDevice;
+#[cfg(feature = "storage")]
+use crate::block;
+#[cfg(feature = "storage")]
+use crate::block::BlockDevice;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::time;
+use alloc::string::String;
+#[cfg(feature = "storage")]
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+const CLASS_MASS_STORAGE: u8 = 8;
+const SUBCLASS_SCSI: u8 = 6;
+const PROTOCOL_BULK_ONLY: u8 = 0x50;
+
+const REQUEST_BULK_ONLY_RESET: u8 = 0xff;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CBW_SIZE: usize = 31;
+const CBW_FLAG_IN: u8 = 0x80;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CSW_SIZE: usize = 13;
+const CSW_PASSED: u8 = 0;
+const CSW_FAILED: u8 = 1;
+
+const SCSI_TEST_UNIT_READY: u8 = 0x00;
+const SCSI_REQUEST_SENSE: u8 = 0x03;
+const SCSI_INQUIRY: u8 = 0x12;
+const SCSI_READ_CAPACITY_10: u8 = 0x25;
+const SCSI_READ_10: u8 = 0x28;
+const SCSI_WRITE_10: u8 = 0x2a;
+
+/// The data of a command is split into transfers of this size at most.
+const MAX_TRANSFER: usize = 0x10000;
+
+pub fn is_bulk_only(iface: &InterfaceDescriptor) -> bool {
+    iface.class == CLASS_MASS_STORAGE
+        && iface.subclass == SUBCLASS_SCSI
+        && iface.protocol == PROTOCOL_BULK_ONLY
+}
+
+enum Data<'a> {
+    None,
+    In(&'a mut [u8]),
+    Out(&'a [u8]),
+}
+impl Data<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Data::None => 0,
+            Data::In(buf) => buf.len(),
+            Data::Out(buf) => buf.len(),
+        }
+    }
+}
+
+/// The command block of READ(10) or WRITE(10).
+fn rw_10(opcode: u8, lba: u64, blocks: usize) -> [u8; 10] {
+    let lba = (lba as u32).to_be_bytes();
+    let blocks = (blocks as u16).to_be_bytes();
+    [
+        opcode, 0, lba[0], lba[1], lba[2], lba[3], 0, blocks[0], blocks[1], 0,
+    ]
+}
+
+static TAG: AtomicU32 = AtomicU32::new(1);
+
+/// A USB mass storage device (LUN 0).
+#[derive(Debug, Clone)]
+pub struct MassStorage {
+    /// sda, sdb, ... in the order the disks were found.
+    pub name: String,
+    pub slot: u8,
+    pub interface: u8,
+    bulk_in: u8,
+    bulk_out: u8,
+    pub vendor: String,
+    pub product: String,
+    pub block_size: usize,
+    pub block_count: u64,
+}
+impl MassStorage {
+    /// Sends a SCSI command and transfers its data.
+    fn command(&self, xhci: &mut Xhci, cb: &[u8], mut data: Data) -> Result<()> {
+        let tag = TAG.fetch_add(1, Ordering::Relaxed);
+        let mut cbw = [0u8; CBW_SIZE];
+        cbw[0..4].copy_from_slice(&CBW_SIGNATURE.to_le_bytes());
+        cbw[4..8].copy_from_slice(&tag.to_le_bytes());
+        cbw[8..12].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        cbw[12] = if matches!(data, Data::In(_)) {
+            CBW_FLAG_IN
+        } else {
+            0
+        };
+        cbw[14] = cb.len() as u8;
+        cbw[15..15 + cb.len()].copy_from_slice(cb);
+        if let Err(e) = xhci.bulk_out(self.slot, self.bulk_out, &cbw) {
+            self.reset_recovery(xhci)?;
+            return Err(e);
+        }
+        // データステージでSTALLされてもCSWは受け取れる
+        let result = match &mut data {
+            Data::None => Ok(0),
+            Data::In(buf) => xhci.bulk_in(self.slot, self.bulk_in, buf),
+            Data::Out(buf) => xhci.bulk_out(self.slot, self.bulk_out, buf),
+        };
+        if result.is_err() {
+            let dci = match data {
+                Data::Out(_) => self.bulk_out,
+                _ => self.bulk_in,
+            };
+            xhci.clear_halt(self.slot, dci)?;
+        }
+        let mut csw = [0u8; CSW_SIZE];
+        let received = match xhci.bulk_in(self.slot, self.bulk_in, &mut csw) {
+            Ok(n) => n,
+            Err(_) => {
+                xhci.clear_halt(self.slot, self.bulk_in)?;
+                xhci.bulk_in(self.slot, self.bulk_in, &mut csw)?
+            }
+        };
+        let signature = u32::from_le_bytes([csw[0], csw[1], csw[2], csw[3]]);
+        let csw_tag = u32::from_le_bytes([csw[4], csw[5], csw[6], csw[7]]);
+        if received != CSW_SIZE || signature != CSW_SIGNATURE || csw_tag != tag {
+            self.reset_recovery(xhci)?;
+            return Err("Invalid CSW");
+        }
+        match csw[12] {
+            CSW_PASSED => result.map(|_| ()),
+            CSW_FAILED => Err("SCSI command failed"),
+            _ => {
+                self.reset_recovery(xhci)?;
+                Err("BOT phase error")
+            }
+        }
+    }
+    /// Brings the device back to the state that accepts a CBW.
+    fn reset_recovery(&self, xhci: &mut Xhci) -> Result<()> {
+        let setup = SetupPacket {
+            request_type: SetupPacket::CLASS | SetupPacket::TO_INTERFACE,
+            request: REQUEST_BULK_ONLY_RESET,
+            value: 0,
+            index: self.interface as u16,
+            length: 0,
+        };
+        xhci.control(self.slot, setup, &mut [])?;
+        xhci.clear_halt(self.slot, self.bulk_in)?;
+        xhci.clear_halt(self.slot, self.bulk_out)
+    }
+    fn inquiry(&mut self, xhci: &mut Xhci) -> Result<()> {
+        let mut buf = [0u8; 36];
+        self.command(
+            xhci,
+            &[SCSI_INQUIRY, 0, 0, 0, buf.len() as u8, 0],
+            Data::In(&mut buf),
+        )?;
+        let text = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|&c| if c.is_ascii_graphic() { c as char } else { ' ' })
+                .collect::<String>()
+                .trim()
+                .into()
+        };
+        self.vendor = text(&buf[8..16]);
+        self.product = text(&buf[16..32]);
+        Ok(())
+    }
+    /// Waits until the medium is ready. The first commands after a reset
+    /// usually fail with UNIT ATTENTION, which REQUEST SENSE clears.
+    fn wait_ready(&self, xhci: &mut Xhci) -> Result<()> {
+        for _ in 0..10 {
+            if self
+                .command(xhci, &[SCSI_TEST_UNIT_READY, 0, 0, 0, 0, 0], Data::None)
+                .is_ok()
+            {
+                return Ok(());
+            }
+            let mut sense = [0u8; 18];
+            self.command(
+                xhci,
+                &[SCSI_REQUEST_SENSE, 0, 0, 0, sense.len() as u8, 0],
+                Data::In(&mut sense),
+            )?;
+            time::spin_wait(Duration::from_millis(100));
+        }
+        Err("USB disk is not ready")
+    }
+    fn read_capacity(&mut self, xhci: &mut Xhci) -> Result<()> {
+        let mut buf = [0u8; 8];
+        let mut cb = [0u8; 10];
+        cb[0] = SCSI_READ_CAPACITY_10;
+        self.command(xhci, &cb, Data::In(&mut buf))?;
+        let last_lba = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        if last_lba == u32::MAX {
+            return Err("USB disk is too large");
+        }
+        self.block_count = last_lba as u64 + 1;
+        self.block_size = u32::from_be_bytes([buf[4], buf[5], buf[6], buf[7]]) as usize;
+        if self.block_size == 0 || self.block_size > MAX_TRANSFER {
+            return Err("Unsupported block size");
+        }
+        Ok(())
+    }
+    fn check_range(&self, lba: u64, len: usize) -> Result<()> {
+        if len % self.block_size != 0 {
+            return Err("Buffer is not a multiple of the block size");
+        }
+        let end = lba.checked_add((len / self.block_size) as u64);
+        if end.map_or(true, |end| end > self.block_count) {
+            return Err("Block is out of range");
+        }
+        Ok(())
+    }
+    fn read_with(&self, xhci: &mut Xhci, lba: u64, buf: &mut [u8]) -> Result<()> {
+        self.check_range(lba, buf.len())?;
+        let mut lba = lba;
+        for chunk in buf.chunks_mut(MAX_TRANSFER / self.block_size * self.block_size) {
+            let blocks = chunk.len() / self.block_size;
+            let cb = rw_10(SCSI_READ_10, lba, blocks);
+            self.command(xhci, &cb, Data::In(chunk))?;
+            lba += blocks as u64;
+        }
+        Ok(())
+    }
+    fn write_with(&self, xhci: &mut Xhci, lba: u64, buf: &[u8]) -> Result<()> {
+        self.check_range(lba, buf.len())?;
+        let mut lba = lba;
+        for chunk in buf.chunks(MAX_TRANSFER / self.block_size * self.block_size) {
+            let blocks = chunk.len() / self.block_size;
+            let cb = rw_10(SCSI_WRITE_10, lba, blocks);
+            self.command(xhci, &cb, Data::Out(chunk))?;
+            lba += blocks as u64;
+        }
+        Ok(())
+    }
+    /// Reads the blocks from lba. buf.len() must be a multiple of
+    /// block_size.
+    pub fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        super::with_controller(|xhci| self.read_with(xhci, lba, buf))
+    }
+    /// Writes the blocks from lba. buf.len() must be a multiple of
+    /// block_size.
+    pub fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        super::with_controller(|xhci| self.write_with(xhci, lba, buf))
+    }
+}
+
+#[cfg(feature = "storage")]
+impl BlockDevice for MassStorage {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        MassStorage::read_blocks(self, lba, buf)
+    }
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        MassStorage::write_blocks(self, lba, buf)
+    }
+}
+
+static DISKS: Mutex<Vec<MassStorage>> = Mutex::new(Vec::new());
+
+/// The mass storage devices found, in the order of the ports.
+pub fn disks() -> Vec<MassStorage> {
+    DISKS.lock().clone()
+}
+
+/// Configures the bulk endpoints and reads the capacity of the device.
+pub fn attach(xhci: &mut Xhci, dev: &UsbDevice, iface: &InterfaceDescriptor) -> Result<()> {
+    let find = |is_in: bool| {
+        iface
+            .endpoints
+            .iter()
+            .find(|ep| {
+                ep.is_in() == is_in && ep.transfer_type() == EndpointDescriptor::TRANSFER_BULK
+            })
+            .ok_or("Mass storage has no bulk endpoints")
+    };
+    let (ep_in, ep_out) = (find(true)?, find(false)?);
+    xhci.configure_endpoints(
+        dev.slot,
+        &[
+            EndpointConfig {
+                dci: ep_in.dci(),
+                ep_type: xhci::EP_TYPE_BULK_IN,
+                max_packet_size: ep_in.max_packet_size,
+                interval: 0,
+            },
+            EndpointConfig {
+                dci: ep_out.dci(),
+                ep_type: xhci::EP_TYPE_BULK_OUT,
+                max_packet_size: ep_out.max_packet_size,
+                interval: 0,
+            },
+        ],
+    )?;
+    let index = DISKS.lock().len() as u8;
+    let mut disk = MassStorage {
+        name: alloc::format!("sd{}", (b'a' + index) as char),
+        slot: dev.slot,
+        interface: iface.number,
+        bulk_in: ep_in.dci(),
+        bulk_out: ep_out.dci(),
+        vendor: String::new(),
+        product: String::new(),
+        block_size: 0,
+        block_count: 0,
+    };
+    disk.inquiry(xhci)?;
+    disk.wait_ready(xhci)?;
+    disk.read_capacity(xhci)?;
+    DISKS.lock().push(disk);
+    Ok(())
+}
+
+/// Registers the disks as block devices. The controller must be ready.
+#[cfg(feature = "storage")]
+pub(super) fn register_block_devices() {
+    for disk in disks() {
+        block::register(Arc::new(disk));
+    }
+}
+
+fn hexdump(out: &mut dyn fmt::Write, data: &[u8]) {
+    for (i, line) in data.chunks(16).enumerate() {
+        let _ = write!(out, "{:04x}:", i * 16);
+        for b in line {
+            let _ = write!(out, " {b:02x}");
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&c| if c.is_ascii_graphic() { c as char } else { '.' })
+            .collect();
+        let _ = writeln!(out, "  {ascii}");
+    }
+}
+
+/// The `usbdisk` command.
+pub fn cmd_usbdisk(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    match args {
+        [] => {
+            for d in DISKS.lock().iter() {
+                let _ = writeln!(
+                    out,
+                    "{}: {} {}, {} blocks of {} bytes ({} MiB)",
+                    d.name,
+                    d.vendor,
+                    d.product,
+                    d.block_count,
+                    d.block_size,
+                    (d.block_count * d.block_size as u64) >> 20
+                );
+            }
+            Ok(())
+        }
+        ["read", name, lba] => {
+            let disk = disks()
+                .into_iter()
+                .find(|d| d.name == *name)
+                .ok_or("No such USB disk")?;
+            let lba = lba.parse().map_err(|_| "Invalid LBA")?;
+            let mut buf = alloc::vec![0u8; disk.block_size];
+            disk.read_blocks(lba, &mut buf)?;
+            hexdump(out, &buf);
+            Ok(())
+        }
+        _ => Err("usage: usbdisk [read <disk> <lba>]"),
+    }
+}
@@ -0,0 +1,860 @@
+//! xHCI host controller driver.
+//!
+//! Only what the class drivers need is implemented: devices on the root hub
+//! ports (no hubs), control transfers on the default endpoint, and interrupt
+//! IN endpoints. The memory given to the controller is identity
+//! mapped, so virtual addresses are used as physical addresses.
+//!
+//! Events are taken from the event ring by poll_events(). Synchronous
+//! requests (commands and control transfers) call it until their
+//! completion arrives, and the completions of the interrupt IN endpoints are
+//! handed to the callbacks of the class drivers, which run in the poll.
+
+use crate::dma;
+use crate::pci;
+use crate::pci::PciDevice;
+use crate::result::Result;
+use crate::time;
+use crate::usb::SetupPacket;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::ptr::read_volatile;
+use core::ptr::write_volatile;
+use core::sync::atomic::fence;
+use core::sync::atomic::Ordering;
+
+const PAGE_SIZE: usize = 4096;
+const RING_SIZE: usize = 256;
+const EVENT_RING_SIZE: usize = 256;
+const TIMEOUT_NS: u64 = 1_000_000_000;
+/// The bounce buffer for bulk transfers. A TRB must not cross a 64 KiB
+/// boundary, so it is 64 KiB aligned.
+const BULK_BUFFER_SIZE: usize = 0x10000;
+
+// Capability registers
+const CAP_CAPLENGTH: usize = 0x00;
+const CAP_HCSPARAMS1: usize = 0x04;
+const CAP_HCSPARAMS2: usize = 0x08;
+const CAP_HCCPARAMS1: usize = 0x10;
+const CAP_DBOFF: usize = 0x14;
+const CAP_RTSOFF: usize = 0x18;
+
+// Operational registers
+const OP_USBCMD: usize = 0x00;
+const OP_USBSTS: usize = 0x04;
+const OP_CRCR: usize = 0x18;
+const OP_DCBAAP: usize = 0x30;
+const OP_CONFIG: usize = 0x38;
+const OP_PORTSC: usize = 0x400;
+
+const USBCMD_RUN: u32 = 1 << 0;
+const USBCMD_RESET: u32 = 1 << 1;
+const USBCMD_INTE: u32 = 1 << 2;
+const USBSTS_HALTED: u32 = 1 << 0;
+const USBSTS_EINT: u32 = 1 << 3;
+const USBSTS_NOT_READY: u32 = 1 << 11;
+
+const PORTSC_CCS: u32 = 1 << 0;
+const PORTSC_PED: u32 = 1 << 1;
+const PORTSC_PR: u32 = 1 << 4;
+const PORTSC_PP: u32 = 1 << 9;
+const PORTSC_PRC: u32 = 1 << 21;
+/// The change bits, which are cleared by writing 1.
+const PORTSC_CHANGES: u32 = 0x7f << 17;
+
+// Interrupter 0 in the runtime registers
+const IR0_IMAN: usize = 0x20;
+const IR0_IMOD: usize = 0x24;
+const IR0_ERSTSZ: usize = 0x28;
+const IR0_ERSTBA: usize = 0x30;
+const IR0_ERDP: usize = 0x38;
+const IMAN_IP: u32 = 1 << 0;
+const IMAN_IE: u32 = 1 << 1;
+const ERDP_EHB: u64 = 1 << 3;
+
+// Extended capabilities
+const XECP_LEGACY: u32 = 1;
+const LEGACY_BIOS_OWNED: u32 = 1 << 16;
+const LEGACY_OS_OWNED: u32 = 1 << 24;
+
+// TRB types
+const TRB_NORMAL: u32 = 1;
+const TRB_SETUP: u32 = 2;
+const TRB_DATA: u32 = 3;
+const TRB_STATUS: u32 = 4;
+const TRB_LINK: u32 = 6;
+const TRB_ENABLE_SLOT: u32 = 9;
+const TRB_ADDRESS_DEVICE: u32 = 11;
+const TRB_CONFIGURE_ENDPOINT: u32 = 12;
+const TRB_EVALUATE_CONTEXT: u32 = 13;
+const TRB_RESET_ENDPOINT: u32 = 14;
+const TRB_SET_TR_DEQUEUE: u32 = 16;
+const TRB_TRANSFER_EVENT: u32 = 32;
+const TRB_COMMAND_COMPLETION: u32 = 33;
+
+const TRB_CYCLE: u32 = 1 << 0;
+const TRB_TOGGLE_CYCLE: u32 = 1 << 1;
+const TRB_ISP: u32 = 1 << 2;
+const TRB_IOC: u32 = 1 << 5;
+const TRB_IDT: u32 = 1 << 6;
+const TRB_DIR_IN: u32 = 1 << 16;
+
+const COMPLETION_SUCCESS: u8 = 1;
+const COMPLETION_STALL: u8 = 6;
+const COMPLETION_SHORT_PACKET: u8 = 13;
+
+const REQUEST_CLEAR_FEATURE: u8 = 1;
+const FEATURE_ENDPOINT_HALT: u16 = 0;
+
+/// Endpoint types in the endpoint context.
+pub const EP_TYPE_BULK_OUT: u32 = 2;
+pub const EP_TYPE_INTERRUPT_OUT: u32 = 3;
+pub const EP_TYPE_CONTROL: u32 = 4;
+pub const EP_TYPE_BULK_IN: u32 = 6;
+pub const EP_TYPE_INTERRUPT_IN: u32 = 7;
+
+/// Port speeds in PORTSC and the slot context.
+pub const SPEED_FULL: u8 = 1;
+pub const SPEED_LOW: u8 = 2;
+pub const SPEED_HIGH: u8 = 3;
+pub const SPEED_SUPER: u8 = 4;
+
+pub fn speed_name(speed: u8) -> &'static str {
+    match speed {
+        SPEED_FULL => "full",
+        SPEED_LOW => "low",
+        SPEED_HIGH => "high",
+        SPEED_SUPER => "super",
+        5 => "super+",
+        _ => "unknown",
+    }
+}
+
+/// Allocates zeroed memory for the controller that is never freed, below
+/// 4 GiB in case it can only take 32-bit addresses (no HCCPARAMS1.AC64).
+fn alloc_dma(size: usize, align: usize) -> Result<*mut u8> {
+    Ok(dma::alloc_dma_below(size, align, dma::LIMIT_32BIT)?.leak())
+}
+
+#[derive(Clone, Copy)]
+struct Mmio(usize);
+impl Mmio {
+    fn read32(&self, offset: usize) -> u32 {
+        // SAFETY: the registers of the controller are identity mapped
+        unsafe { read_volatile((self.0 + offset) as *const u32) }
+    }
+    fn write32(&self, offset: usize, value: u32) {
+        // SAFETY: the registers of the controller are identity mapped
+        unsafe { write_volatile((self.0 + offset) as *mut u32, value) }
+    }
+    fn write64(&self, offset: usize, value: u64) {
+        // 64ビットアクセスに対応していないコントローラもあるので2回に分ける
+        self.write32(offset, value as u32);
+        self.write32(offset + 4, (value >> 32) as u32);
+    }
+}
+
+fn ring_doorbell(db: Mmio, slot: u8, target: u8) {
+    fence(Ordering::SeqCst);
+    db.write32(slot as usize * 4, target as u32);
+}
+
+#[repr(C, align(16))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Trb {
+    pub param: u64,
+    pub status: u32,
+    pub control: u32,
+}
+impl Trb {
+    fn new(trb_type: u32, param: u64, status: u32, flags: u32) -> Self {
+        Self {
+            param,
+            status,
+            control: trb_type << 10 | flags,
+        }
+    }
+    fn trb_type(&self) -> u32 {
+        (self.control >> 10) & 0x3f
+    }
+    fn completion_code(&self) -> u8 {
+        (self.status >> 24) as u8
+    }
+    fn slot_id(&self) -> u8 {
+        (self.control >> 24) as u8
+    }
+    fn endpoint_id(&self) -> u8 {
+        ((self.control >> 16) & 0x1f) as u8
+    }
+    /// The number of bytes that were not transferred.
+    fn residue(&self) -> usize {
+        (self.status & 0xff_ffff) as usize
+    }
+}
+
+/// A command or transfer ring with a link TRB at the end.
+struct Ring {
+    trbs: *mut Trb,
+    enqueue: usize,
+    cycle: bool,
+}
+// SAFETY: the ring is only touched with the controller locked
+unsafe impl Send for Ring {}
+impl Ring {
+    fn new() -> Result<Self> {
+        let trbs = alloc_dma(RING_SIZE * core::mem::size_of::<Trb>(), PAGE_SIZE)? as *mut Trb;
+        Ok(Self {
+            trbs,
+            enqueue: 0,
+            cycle: true,
+        })
+    }
+    fn address(&self) -> u64 {
+        self.trbs as u64
+    }
+    /// The address of the next TRB with the current cycle bit in bit 0, as
+    /// Set TR Dequeue Pointer takes it.
+    fn enqueue_pointer(&self) -> u64 {
+        self.trbs.wrapping_add(self.enqueue) as u64 | self.cycle as u64
+    }
+    fn write(&mut self, mut trb: Trb) -> u64 {
+        let p = self.trbs.wrapping_add(self.enqueue);
+        trb.control = (trb.control & !TRB_CYCLE) | self.cycle as u32;
+        // サイクルビットを含むcontrolは最後に書いて、書きかけのTRBを読ませない
+        // SAFETY: p is within the ring
+        unsafe {
+            write_volatile(&mut (*p).param, trb.param);
+            write_volatile(&mut (*p).status, trb.status);
+            fence(Ordering::SeqCst);
+            write_volatile(&mut (*p).control, trb.control);
+        }
+        p as u64
+    }
+    /// Queues a TRB and returns its address.
+    fn push(&mut self, trb: Trb) -> u64 {
+        let address = self.write(trb);
+        self.enqueue += 1;
+        if self.enqueue == RING_SIZE - 1 {
+            self.write(Trb::new(TRB_LINK, self.address(), 0, TRB_TOGGLE_CYCLE));
+            self.enqueue = 0;
+            self.cycle = !self.cycle;
+        }
+        address
+    }
+}
+
+#[repr(C)]
+struct ErstEntry {
+    base: u64,
+    size: u32,
+    _reserved: u32,
+}
+
+struct EventRing {
+    trbs: *mut Trb,
+    dequeue: usize,
+    cycle: bool,
+}
+// SAFETY: the ring is only touched with the controller locked
+unsafe impl Send for EventRing {}
+impl EventRing {
+    fn pop(&mut self) -> Option<Trb> {
+        // SAFETY: dequeue is within the ring
+        let trb = unsafe { read_volatile(self.trbs.add(self.dequeue)) };
+        if (trb.control & TRB_CYCLE != 0) != self.cycle {
+            return None;
+        }
+        self.dequeue += 1;
+        if self.dequeue == EVENT_RING_SIZE {
+            self.dequeue = 0;
+            self.cycle = !self.cycle;
+        }
+        Some(trb)
+    }
+    fn dequeue_address(&self) -> u64 {
+        self.trbs.wrapping_add(self.dequeue) as u64
+    }
+}
+
+/// Called with the data received on an interrupt IN endpoint.
+pub type InterruptCallback = fn(slot: u8, data: &[u8]);
+
+struct InterruptPipe {
+    buf: *mut u8,
+    len: usize,
+    callback: InterruptCallback,
+}
+// SAFETY: the buffer is only touched with the controller locked
+unsafe impl Send for InterruptPipe {}
+
+struct Slot {
+    /// The output device context, owned by the controller.
+    device_context: *mut u8,
+    input_context: *mut u8,
+    /// Transfer rings by DCI (device context index).
+    rings: BTreeMap<u8, Ring>,
+    /// A buffer for control transfers.
+    buf: *mut u8,
+    /// A buffer for bulk transfers, allocated on the first use.
+    bulk_buf: *mut u8,
+    interrupt_pipes: BTreeMap<u8, InterruptPipe>,
+}
+// SAFETY: the contexts are only touched with the controller locked
+unsafe impl Send for Slot {}
+
+/// An endpoint to be configured with configure_endpoints().
+#[derive(Debug, Clone, Copy)]
+pub struct EndpointConfig {
+    pub dci: u8,
+    pub ep_type: u32,
+    pub max_packet_size: u16,
+    /// The interval in the xHCI encoding (2^interval * 125 us).
+    pub interval: u8,
+}
+
+pub struct Xhci {
+    pub bdf: pci::BusDeviceFunction,
+    cap: Mmio,
+    op: Mmio,
+    rt: Mmio,
+    db: Mmio,
+    pub max_slots: u8,
+    pub max_ports: u8,
+    context_size: usize,
+    dcbaa: *mut u64,
+    command_ring: Ring,
+    event_ring: EventRing,
+    slots: BTreeMap<u8, Slot>,
+    /// Completion events of commands and synchronous transfers that have
+    /// not been taken yet.
+    completions: Vec<Trb>,
+}
+// SAFETY: the DMA memory is only touched with the controller locked
+unsafe impl Send for Xhci {}
+
+impl Xhci {
+    /// Resets and starts the controller.
+    pub fn new(dev: &PciDevice) -> Result<Self> {
+        let base = dev
+            .bar(0)
+            .and_then(|bar| bar.memory_addr())
+            .ok_or("xHCI BAR0 is not memory mapped")?;
+        pci::enable_bus_master(dev.bdf);
+        let cap = Mmio(base as usize);
+        let caplength = cap.read32(CAP_CAPLENGTH) & 0xff;
+        let hcsparams1 = cap.read32(CAP_HCSPARAMS1);
+        let hcsparams2 = cap.read32(CAP_HCSPARAMS2);
+        let hccparams1 = cap.read32(CAP_HCCPARAMS1);
+        let mut xhci = Self {
+            bdf: dev.bdf,
+            cap,
+            op: Mmio(base as usize + caplength as usize),
+            rt: Mmio(base as usize + (cap.read32(CAP_RTSOFF) & !0x1f) as usize),
+            db: Mmio(base as usize + (cap.read32(CAP_DBOFF) & !0x3) as usize),
+            max_slots: hcsparams1 as u8,
+            max_ports: (hcsparams1 >> 24) as u8,
+            context_size: if hccparams1 & (1 << 2) != 0 { 64 } else { 32 },
+            dcbaa: core::ptr::null_mut(),
+            command_ring: Ring::new()?,
+            event_ring: EventRing {
+                trbs: alloc_dma(EVENT_RING_SIZE * core::mem::size_of::<Trb>(), PAGE_SIZE)?
+                    as *mut Trb,
+                dequeue: 0,
+                cycle: true,
+            },
+            slots: BTreeMap::new(),
+            completions: Vec::new(),
+        };
+        xhci.take_ownership(hccparams1);
+        xhci.reset()?;
+        xhci.op.write32(OP_CONFIG, xhci.max_slots as u32);
+        xhci.dcbaa = alloc_dma((xhci.max_slots as usize + 1) * 8, 64)? as *mut u64;
+        let scratchpads = ((hcsparams2 >> 21) & 0x1f) << 5 | (hcsparams2 >> 27) & 0x1f;
+        if scratchpads > 0 {
+            let array = alloc_dma(scratchpads as usize * 8, 64)? as *mut u64;
+            for i in 0..scratchpads as usize {
+                // SAFETY: array has scratchpads entries
+                unsafe { *array.add(i) = alloc_dma(PAGE_SIZE, PAGE_SIZE)? as u64 };
+            }
+            // SAFETY: dcbaa has max_slots + 1 entries
+            unsafe { *xhci.dcbaa = array as u64 };
+        }
+        xhci.op.write64(OP_DCBAAP, xhci.dcbaa as u64);
+        xhci.op.write64(OP_CRCR, xhci.command_ring.address() | 1);
+        let erst = alloc_dma(core::mem::size_of::<ErstEntry>(), 64)? as *mut ErstEntry;
+        // SAFETY: erst was just allocated
+        unsafe {
+            *erst = ErstEntry {
+                base: xhci.event_ring.trbs as u64,
+                size: EVENT_RING_SIZE as u32,
+                _reserved: 0,
+            };
+        }
+        xhci.rt.write32(IR0_ERSTSZ, 1);
+        xhci.rt.write64(IR0_ERDP, xhci.event_ring.dequeue_address());
+        xhci.rt.write64(IR0_ERSTBA, erst as u64);
+        // 割り込みは1msに1回まで
+        xhci.rt.write32(IR0_IMOD, 4000);
+        xhci.rt.write32(IR0_IMAN, IMAN_IE | IMAN_IP);
+        xhci.op.write32(OP_USBCMD, USBCMD_RUN | USBCMD_INTE);
+        xhci.wait_status(USBSTS_HALTED, false)?;
+        Ok(xhci)
+    }
+    /// Asks the firmware (SMM) to hand the controller over.
+    fn take_ownership(&self, hccparams1: u32) {
+        let mut offset = ((hccparams1 >> 16) as usize) << 2;
+        while offset != 0 {
+            let cap = self.cap.read32(offset);
+            if cap & 0xff == XECP_LEGACY {
+                self.cap.write32(offset, cap | LEGACY_OS_OWNED);
+                let deadline = time::now_ns() + TIMEOUT_NS;
+                while self.cap.read32(offset) & LEGACY_BIOS_OWNED != 0 && time::now_ns() < deadline
+                {
+                    core::hint::spin_loop();
+                }
+                // SMIを止める
+                self.cap.write32(offset + 4, 0);
+                return;
+            }
+            offset += (((cap >> 8) & 0xff) as usize) << 2;
+            if (cap >> 8) & 0xff == 0 {
+                break;
+            }
+        }
+    }
+    fn wait_status(&self, bit: u32, set: bool) -> Result<()> {
+        let deadline = time::now_ns() + TIMEOUT_NS;
+        while (self.op.read32(OP_USBSTS) & bit != 0) != set {
+            if time::now_ns() > deadline {
+                return Err("xHCI timed out");
+            }
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+    fn reset(&self) -> Result<()> {
+        self.op
+            .write32(OP_USBCMD, self.op.read32(OP_USBCMD) & !USBCMD_RUN);
+        self.wait_status(USBSTS_HALTED, true)?;
+        self.op.write32(OP_USBCMD, USBCMD_RESET);
+        let deadline = time::now_ns() + TIMEOUT_NS;
+        while self.op.read32(OP_USBCMD) & USBCMD_RESET != 0 {
+            if time::now_ns() > deadline {
+                return Err("xHCI reset timed out");
+            }
+            core::hint::spin_loop();
+        }
+        self.wait_status(USBSTS_NOT_READY, false)
+    }
+    /// Stops the controller so that it does no more DMA.
+    pub fn stop(&self) {
+        self.op.write32(OP_USBCMD, 0);
+        let _ = self.wait_status(USBSTS_HALTED, true);
+    }
+    /// Acknowledges the interrupt. Called from the interrupt handler.
+    pub(crate) fn ack_interrupt(op: usize, rt: usize) -> bool {
+        let (op, rt) = (Mmio(op), Mmio(rt));
+        if op.read32(OP_USBSTS) & USBSTS_EINT == 0 {
+            return false;
+        }
+        op.write32(OP_USBSTS, USBSTS_EINT);
+        rt.write32(IR0_IMAN, IMAN_IE | IMAN_IP);
+        true
+    }
+    /// The addresses of the registers that ack_interrupt() needs.
+    pub(crate) fn interrupt_registers(&self) -> (usize, usize) {
+        (self.op.0, self.rt.0)
+    }
+
+    fn portsc(&self, port: u8) -> usize {
+        OP_PORTSC + 0x10 * (port as usize - 1)
+    }
+    /// The ports (1-based) that have a device connected.
+    pub fn connected_ports(&self) -> Vec<u8> {
+        (1..=self.max_ports)
+            .filter(|&p| self.op.read32(self.portsc(p)) & PORTSC_CCS != 0)
+            .collect()
+    }
+    /// Resets the port if needed and returns its speed.
+    pub fn reset_port(&self, port: u8) -> Result<u8> {
+        let reg = self.portsc(port);
+        let portsc = self.op.read32(reg);
+        // USB3のポートはリンクが確立すると自動で有効になる
+        if portsc & PORTSC_PED == 0 {
+            self.op
+                .write32(reg, (portsc & PORTSC_PP) | PORTSC_CHANGES | PORTSC_PR);
+            let deadline = time::now_ns() + TIMEOUT_NS;
+            while self.op.read32(reg) & PORTSC_PRC == 0 {
+                if time::now_ns() > deadline {
+                    return Err("USB port reset timed out");
+                }
+                core::hint::spin_loop();
+            }
+        }
+        let portsc = self.op.read32(reg);
+        self.op.write32(reg, (portsc & PORTSC_PP) | PORTSC_CHANGES);
+        if portsc & PORTSC_PED == 0 {
+            return Err("USB port is not enabled");
+        }
+        Ok(((portsc >> 10) & 0xf) as u8)
+    }
+
+    fn ring_doorbell(&self, slot: u8, target: u8) {
+        ring_doorbell(self.db, slot, target);
+    }
+
+    /// Takes the events out of the event ring and dispatches them.
+    pub fn poll_events(&mut self) {
+        let mut any = false;
+        while let Some(trb) = self.event_ring.pop() {
+            any = true;
+            match trb.trb_type() {
+                TRB_TRANSFER_EVENT => self.on_transfer_event(trb),
+                TRB_COMMAND_COMPLETION => self.completions.push(trb),
+                // ホットプラグには対応していないので、ポートの状態変化は無視する
+                _ => {}
+            }
+        }
+        if any {
+            self.rt
+                .write64(IR0_ERDP, self.event_ring.dequeue_address() | ERDP_EHB);
+        }
+    }
+    fn on_transfer_event(&mut self, trb: Trb) {
+        let (slot_id, dci) = (trb.slot_id(), trb.endpoint_id());
+        let Some(slot) = self.slots.get_mut(&slot_id) else {
+            return;
+        };
+        let Some(pipe) = slot.interrupt_pipes.get(&dci) else {
+            self.completions.push(trb);
+            return;
+        };
+        // エラーで止まったエンドポイントは再開しない
+        let code = trb.completion_code();
+        if code != COMPLETION_SUCCESS && code != COMPLETION_SHORT_PACKET {
+            return;
+        }
+        let len = pipe.len.saturating_sub(trb.residue());
+        // SAFETY: buf has pipe.len bytes and the controller is done with it
+        let data = unsafe { core::slice::from_raw_parts(pipe.buf, len) };
+        (pipe.callback)(slot_id, data);
+        let (buf, len) = (pipe.buf as u64, pipe.len as u32);
+        if let Some(ring) = slot.rings.get_mut(&dci) {
+            ring.push(Trb::new(TRB_NORMAL, buf, len, TRB_IOC | TRB_ISP));
+            ring_doorbell(self.db, slot_id, dci);
+        }
+    }
+    /// Waits for the completion event of the TRB at address.
+    fn wait_completion(&mut self, address: u64) -> Result<Trb> {
+        let deadline = time::now_ns() + TIMEOUT_NS;
+        loop {
+            self.poll_events();
+            if let Some(i) = self.completions.iter().position(|t| t.param == address) {
+                let trb = self.completions.remove(i);
+                return match trb.completion_code() {
+                    COMPLETION_SUCCESS | COMPLETION_SHORT_PACKET => Ok(trb),
+                    COMPLETION_STALL => Err("USB endpoint stalled"),
+                    _ => Err("USB request failed"),
+                };
+            }
+            if time::now_ns() > deadline {
+                return Err("USB request timed out");
+            }
+            core::hint::spin_loop();
+        }
+    }
+    fn command(&mut self, trb: Trb) -> Result<Trb> {
+        let address = self.command_ring.push(trb);
+        self.ring_doorbell(0, 0);
+        self.wait_completion(address)
+    }
+
+    fn slot(&self, slot: u8) -> Result<&Slot> {
+        self.slots.get(&slot).ok_or("Invalid USB slot")
+    }
+    /// A dword in the input context. index 0 is the input control context,
+    /// 1 the slot context, and 2.. the endpoint contexts by DCI.
+    fn input_context(&self, slot: u8, index: usize, dword: usize) -> Result<*mut u32> {
+        let base = self.slot(slot)?.input_context;
+        Ok(base.wrapping_add(index * self.context_size + dword * 4) as *mut u32)
+    }
+    fn set_input(&self, slot: u8, index: usize, dword: usize, value: u32) -> Result<()> {
+        // SAFETY: the input context has 33 contexts
+        unsafe { write_volatile(self.input_context(slot, index, dword)?, value) };
+        Ok(())
+    }
+    fn clear_input_context(&self, slot: u8) -> Result<()> {
+        let base = self.slot(slot)?.input_context;
+        // SAFETY: the input context has 33 contexts
+        unsafe { core::ptr::write_bytes(base, 0, 33 * self.context_size) };
+        Ok(())
+    }
+
+    /// Enables a slot for the device on the port and addresses it.
+    pub fn address_device(&mut self, port: u8, speed: u8) -> Result<u8> {
+        let event = self.command(Trb::new(TRB_ENABLE_SLOT, 0, 0, 0))?;
+        let slot_id = event.slot_id();
+        if slot_id == 0 || slot_id > self.max_slots {
+            return Err("xHCI returned an invalid slot");
+        }
+        let device_context = alloc_dma(32 * self.context_size, 64)?;
+        let input_context = alloc_dma(33 * self.context_size, 64)?;
+        let ep0 = Ring::new()?;
+        let ep0_address = ep0.address();
+        let mut rings = BTreeMap::new();
+        rings.insert(1, ep0);
+        self.slots.insert(
+            slot_id,
+            Slot {
+                device_context,
+                input_context,
+                rings,
+                buf: alloc_dma(PAGE_SIZE, PAGE_SIZE)?,
+                bulk_buf: core::ptr::null_mut(),
+                interrupt_pipes: BTreeMap::new(),
+            },
+        );
+        // SAFETY: dcbaa has max_slots + 1 entries
+        unsafe { write_volatile(self.dcbaa.add(slot_id as usize), device_context as u64) };
+        let max_packet_size = match speed {
+            SPEED_SUPER.. => 512,
+            SPEED_HIGH => 64,
+            _ => 8,
+        };
+        // A0 (スロット) と A1 (EP0) を追加する
+        self.set_input(slot_id, 0, 1, 0b11)?;
+        self.set_input(slot_id, 1, 0, 1 << 27 | (speed as u32) << 20)?;
+        self.set_input(slot_id, 1, 1, (port as u32) << 16)?;
+        self.set_input(
+            slot_id,
+            2,
+            1,
+            max_packet_size << 16 | EP_TYPE_CONTROL << 3 | 3 << 1,
+        )?;
+        self.set_input(slot_id, 2, 2, ep0_address as u32 | 1)?;
+        self.set_input(slot_id, 2, 3, (ep0_address >> 32) as u32)?;
+        self.set_input(slot_id, 2, 4, 8)?;
+        self.command(Trb::new(
+            TRB_ADDRESS_DEVICE,
+            input_context as u64,
+            0,
+            (slot_id as u32) << 24,
+        ))?;
+        Ok(slot_id)
+    }
+    /// Updates the max packet size of the default endpoint.
+    pub fn set_ep0_max_packet_size(&mut self, slot: u8, max_packet_size: u16) -> Result<()> {
+        self.clear_input_context(slot)?;
+        self.set_input(slot, 0, 1, 1 << 1)?;
+        self.set_input(
+            slot,
+            2,
+            1,
+            (max_packet_size as u32) << 16 | EP_TYPE_CONTROL << 3 | 3 << 1,
+        )?;
+        let input = self.slot(slot)?.input_context as u64;
+        self.command(Trb::new(
+            TRB_EVALUATE_CONTEXT,
+            input,
+            0,
+            (slot as u32) << 24,
+        ))?;
+        Ok(())
+    }
+    /// Adds the endpoints to the slot.
+    pub fn configure_endpoints(&mut self, slot: u8, endpoints: &[EndpointConfig]) -> Result<()> {
+        self.clear_input_context(slot)?;
+        let max_dci = endpoints.iter().map(|e| e.dci).max().unwrap_or(1);
+        let add_flags = endpoints.iter().fold(1, |flags, e| flags | 1 << e.dci);
+        self.set_input(slot, 0, 1, add_flags)?;
+        // スロットコンテキストは出力側からコピーしてContext Entriesだけ変える
+        let device_context = self.slot(slot)?.device_context as *const u32;
+        for dword in 0..4 {
+            // SAFETY: the device context has 32 contexts
+            let value = unsafe { read_volatile(device_context.add(dword)) };
+            self.set_input(slot, 1, dword, value)?;
+        }
+        // SAFETY: the input context has 33 contexts
+        let dw0 = unsafe { read_volatile(self.input_context(slot, 1, 0)?) };
+        self.set_input(slot, 1, 0, (dw0 & !(0x1f << 27)) | (max_dci as u32) << 27)?;
+        self.set_input(slot, 1, 3, 0)?;
+        for e in endpoints {
+            let ring = Ring::new()?;
+            let address = ring.address();
+            let index = e.dci as usize + 1;
+            self.set_input(slot, index, 0, (e.interval as u32) << 16)?;
+            self.set_input(
+                slot,
+                index,
+                1,
+                (e.max_packet_size as u32) << 16 | e.ep_type << 3 | 3 << 1,
+            )?;
+            self.set_input(slot, index, 2, address as u32 | 1)?;
+            self.set_input(slot, index, 3, (address >> 32) as u32)?;
+            let average = match e.ep_type {
+                EP_TYPE_INTERRUPT_IN | EP_TYPE_INTERRUPT_OUT => e.max_packet_size as u32,
+                _ => 3072,
+            };
+            let esit = match e.ep_type {
+                EP_TYPE_INTERRUPT_IN | EP_TYPE_INTERRUPT_OUT => e.max_packet_size as u32,
+                _ => 0,
+            };
+            self.set_input(slot, index, 4, esit << 16 | average)?;
+            self.slots
+                .get_mut(&slot)
+                .ok_or("Invalid USB slot")?
+                .rings
+                .insert(e.dci, ring);
+        }
+        let input = self.slot(slot)?.input_context as u64;
+        self.command(Trb::new(
+            TRB_CONFIGURE_ENDPOINT,
+            input,
+            0,
+            (slot as u32) << 24,
+        ))?;
+        Ok(())
+    }
+
+    fn ring(&mut self, slot: u8, dci: u8) -> Result<&mut Ring> {
+        self.slots
+            .get_mut(&slot)
+            .and_then(|s| s.rings.get_mut(&dci))
+            .ok_or("Endpoint is not configured")
+    }
+    /// Does a control transfer on the default endpoint. IN data is copied
+    /// to data, and OUT data is taken from it.
+    pub fn control(&mut self, slot: u8, setup: SetupPacket, data: &mut [u8]) -> Result<()> {
+        let len = data.len().min(setup.length as usize);
+        if len > PAGE_SIZE {
+            return Err("Control transfer is too large");
+        }
+        let buf = self.slot(slot)?.buf;
+        let is_in = setup.request_type & 0x80 != 0;
+        if !is_in {
+            // SAFETY: buf is a page
+            unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), buf, len) };
+        }
+        let transfer_type = match (len, is_in) {
+            (0, _) => 0,
+            (_, false) => 2,
+            (_, true) => 3,
+        };
+        let ring = self.ring(slot, 1)?;
+        ring.push(Trb::new(
+            TRB_SETUP,
+            setup.to_u64(),
+            8,
+            TRB_IDT | transfer_type << 16,
+        ));
+        if len > 0 {
+            let dir = if is_in { TRB_DIR_IN } else { 0 };
+            ring.push(Trb::new(TRB_DATA, buf as u64, len as u32, dir));
+        }
+        // ステータスステージはデータと逆向き
+        let dir = if is_in && len > 0 { 0 } else { TRB_DIR_IN };
+        let address = ring.push(Trb::new(TRB_STATUS, 0, 0, TRB_IOC | dir));
+        self.ring_doorbell(slot, 1);
+        self.wait_completion(address)?;
+        if is_in {
+            // SAFETY: buf is a page
+            unsafe { core::ptr::copy_nonoverlapping(buf, data.as_mut_ptr(), len) };
+        }
+        Ok(())
+    }
+    /// Receives data from a bulk IN endpoint. Returns the number of bytes
+    /// received, which is less than data.len() if the device ended the
+    /// transfer with a short packet.
+    pub fn bulk_in(&mut self, slot: u8, dci: u8, data: &mut [u8]) -> Result<usize> {
+        if dci & 1 == 0 {
+            return Err("Not an IN endpoint");
+        }
+        self.bulk(slot, dci, data.as_mut_ptr(), data.len())
+    }
+    /// Sends data to a bulk OUT endpoint.
+    pub fn bulk_out(&mut self, slot: u8, dci: u8, data: &[u8]) -> Result<usize> {
+        if dci & 1 != 0 {
+            return Err("Not an OUT endpoint");
+        }
+        self.bulk(slot, dci, data.as_ptr() as *mut u8, data.len())
+    }
+    /// data is written only if dci is an IN endpoint.
+    fn bulk(&mut self, slot: u8, dci: u8, data: *mut u8, len: usize) -> Result<usize> {
+        let is_in = dci & 1 != 0;
+        let buf = {
+            let slot = self.slots.get_mut(&slot).ok_or("Invalid USB slot")?;
+            if slot.bulk_buf.is_null() {
+                slot.bulk_buf = alloc_dma(BULK_BUFFER_SIZE, BULK_BUFFER_SIZE)?;
+            }
+            slot.bulk_buf
+        };
+        let mut done = 0;
+        while done < len {
+            let chunk = (len - done).min(BULK_BUFFER_SIZE);
+            let p = data.wrapping_add(done);
+            if !is_in {
+                // SAFETY: p has chunk bytes, and buf has BULK_BUFFER_SIZE bytes
+                unsafe { core::ptr::copy_nonoverlapping(p, buf, chunk) };
+            }
+            let address = self.ring(slot, dci)?.push(Trb::new(
+                TRB_NORMAL,
+                buf as u64,
+                chunk as u32,
+                TRB_IOC | TRB_ISP,
+            ));
+            self.ring_doorbell(slot, dci);
+            let event = self.wait_completion(address)?;
+            let transferred = chunk.saturating_sub(event.residue());
+            if is_in {
+                // SAFETY: p has chunk bytes, and buf has BULK_BUFFER_SIZE bytes
+                unsafe { core::ptr::copy_nonoverlapping(buf, p, transferred) };
+            }
+            done += transferred;
+            if transferred < chunk {
+                break;
+            }
+        }
+        Ok(done)
+    }
+    /// Recovers a stalled endpoint: resets it in the controller, skips the
+    /// TRBs left in its ring, and clears the halt in the device.
+    pub fn clear_halt(&mut self, slot: u8, dci: u8) -> Result<()> {
+        let target = (slot as u32) << 24 | (dci as u32) << 16;
+        self.command(Trb::new(TRB_RESET_ENDPOINT, 0, 0, target))?;
+        let dequeue = self.ring(slot, dci)?.enqueue_pointer();
+        self.command(Trb::new(TRB_SET_TR_DEQUEUE, dequeue, 0, target))?;
+        let endpoint_address = (dci / 2) | (dci & 1) << 7;
+        let setup = SetupPacket {
+            request_type: SetupPacket::TO_ENDPOINT,
+            request: REQUEST_CLEAR_FEATURE,
+            value: FEATURE_ENDPOINT_HALT,
+            index: endpoint_address as u16,
+            length: 0,
+        };
+        self.control(slot, setup, &mut [])
+    }
+    /// Starts polling an interrupt IN endpoint. callback is called with
+    /// each packet received.
+    pub fn start_interrupt_in(
+        &mut self,
+        slot: u8,
+        dci: u8,
+        len: usize,
+        callback: InterruptCallback,
+    ) -> Result<()> {
+        let buf = alloc_dma(len.max(1), 64)?;
+        self.slots
+            .get_mut(&slot)
+            .ok_or("Invalid USB slot")?
+            .interrupt_pipes
+            .insert(dci, InterruptPipe { buf, len, callback });
+        self.ring(slot, dci)?.push(Trb::new(
+            TRB_NORMAL,
+            buf as u64,
+            len as u32,
+            TRB_IOC | TRB_ISP,
+        ));
+        self.ring_doorbell(slot, dci);
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+//! File handles of user processes.
+//!
+//! There is no file system for user programs yet. Processes get the
+//! console as stdin, stdout and stderr, and can open pipes and message
+//! queues.
+
+use crate::input;
+use crate::input::InputEvent;
+use crate::keymap::Key;
+use crate::keymap::KeyMapper;
+use crate::mutex::Mutex;
+use crate::print;
+use crate::result::Result;
+use crate::serial::SerialPort;
+use crate::task;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+
+pub type Fd = usize;
+
+pub const STDIN: Fd = 0;
+pub const STDOUT: Fd = 1;
+pub const STDERR: Fd = 2;
+
+pub trait File: Send + Sync {
+    /// Reads at least one byte, waiting for it if needed. Returns 0 at the
+    /// end of the file.
+    fn read(&self, buf: &mut [u8]) -> Result<usize>;
+    fn write(&self, buf: &[u8]) -> Result<usize>;
+}
+
+/// The serial port and the keyboard for input, and the serial port and the
+/// screen console for output.
+pub struct Console {
+    /// Bytes of a character that did not fit in the buffer of read().
+    pending: Mutex<VecDeque<u8>>,
+    keys: Mutex<KeyMapper>,
+}
+impl Console {
+    pub const fn new() -> Self {
+        Self {
+            pending: Mutex::new(VecDeque::new()),
+            keys: Mutex::new(KeyMapper::new()),
+        }
+    }
+    fn try_read_char(&self) -> Option<char> {
+        if let Some(c) = SerialPort::default().try_read() {
+            return Some(if c == b'\r' { '\n' } else { c as char });
+        }
+        while let Some(event) = input::try_next_event() {
+            let InputEvent::Key(e) = event else {
+                continue;
+            };
+            if let Some(stroke) = self.keys.lock().process(&e) {
+                match stroke.key {
+                    Key::Char('\r') => return Some('\n'),
+                    Key::Char(c) => return Some(c),
+                    Key::Named(_) => {}
+                }
+            }
+        }
+        None
+    }
+}
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl File for Console {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            {
+                let mut pending = self.pending.lock();
+                if !pending.is_empty() {
+                    let n = buf.len().min(pending.len());
+                    for (dst, src) in buf.iter_mut().zip(pending.drain(..n)) {
+                        *dst = src;
+                    }
+                    return Ok(n);
+                }
+            }
+            match self.try_read_char() {
+                Some(c) => {
+                    let mut bytes = [0u8; 4];
+                    self.pending
+                        .lock()
+                        .extend(c.encode_utf8(&mut bytes).bytes());
+                }
+                None => task::yield_now(),
+            }
+        }
+    }
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        print!("{text}");
+        #[cfg(feature = "gui")]
+        {
+            use core::fmt::Write;
+            let _ = crate::console::ConsoleWriter.write_str(&text);
+        }
+        Ok(buf.len())
+    }
+}
+
+/// The console shared by all the processes, so that characters read ahead
+/// by one are not lost.
+pub fn console() -> Arc<dyn File> {
+    static CONSOLE: Mutex<Option<Arc<Console>>> = Mutex::new(None);
+    CONSOLE
+        .lock()
+        .get_or_insert_with(|| Arc::new(Console::new()))
+        .clone()
+}
+
+/// The open files of a process, indexed by Fd.
+#[derive(Default, Clone)]
+pub struct FileTable {
+    files: Vec<Option<Arc<dyn File>>>,
+}
+impl FileTable {
+    /// stdin, stdout and stderr on the console.
+    pub fn with_console() -> Self {
+        Self {
+            files: vec![Some(console()), Some(console()), Some(console())],
+        }
+    }
+    pub fn get(&self, fd: Fd) -> Result<Arc<dyn File>> {
+        self.files
+            .get(fd)
+            .cloned()
+            .flatten()
+            .ok_or("Bad file descriptor")
+    }
+    /// Adds the file at the lowest free Fd.
+    pub fn open(&mut self, file: Arc<dyn File>) -> Fd {
+        match self.files.iter().position(|f| f.is_none()) {
+            Some(fd) => {
+                self.files[fd] = Some(file);
+                fd
+            }
+            None => {
+                self.files.push(Some(file));
+                self.files.len() - 1
+            }
+        }
+    }
+    /// Puts the file at fd, closing the one that was there.
+    pub fn set(&mut self, fd: Fd, file: Arc<dyn File>) {
+        if self.files.len() <= fd {
+            self.files.resize(fd + 1, None);
+        }
+        self.files[fd] = Some(file);
+    }
+    pub fn close(&mut self, fd: Fd) -> Result<()> {
+        self.files
+            .get_mut(fd)
+            .and_then(|f| f.take())
+            .map(|_| ())
+            .ok_or("Bad file descriptor")
+    }
+    pub fn len(&self) -> usize {
+        self.files.iter().filter(|f| f.is_some()).count()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,98 @@
+//! Named message queues.
+//!
+//! Unlike a pipe, a queue keeps the boundaries of the messages: each
+//! write() sends one message and each read() receives one. Any process (or
+//! the kernel, e.g. the window manager) that opens the same name gets the
+//! same queue, which lives until the kernel restarts.
+
+use super::file::File;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::task;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+const MAX_MESSAGES: usize = 64;
+pub const MAX_MESSAGE_SIZE: usize = 4096;
+
+pub struct MessageQueue {
+    name: String,
+    messages: Mutex<VecDeque<Vec<u8>>>,
+}
+impl MessageQueue {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn len(&self) -> usize {
+        self.messages.lock().len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    pub fn try_send(&self, message: &[u8]) -> Result<()> {
+        if message.len() > MAX_MESSAGE_SIZE {
+            return Err("Message is too long");
+        }
+        let mut messages = self.messages.lock();
+        if messages.len() >= MAX_MESSAGES {
+            return Err("Message queue is full");
+        }
+        messages.push_back(message.to_vec());
+        Ok(())
+    }
+    pub fn try_receive(&self) -> Option<Vec<u8>> {
+        self.messages.lock().pop_front()
+    }
+}
+impl File for MessageQueue {
+    /// Waits for a message. The part that does not fit in buf is dropped.
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            if let Some(message) = self.try_receive() {
+                let n = buf.len().min(message.len());
+                buf[..n].copy_from_slice(&message[..n]);
+                return Ok(n);
+            }
+            task::yield_now();
+        }
+    }
+    /// Sends buf as a message, waiting while the queue is full.
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        if buf.len() > MAX_MESSAGE_SIZE {
+            return Err("Message is too long");
+        }
+        loop {
+            {
+                let mut messages = self.messages.lock();
+                if messages.len() < MAX_MESSAGES {
+                    messages.push_back(buf.to_vec());
+                    return Ok(buf.len());
+                }
+            }
+            task::yield_now();
+        }
+    }
+}
+
+static QUEUES: Mutex<Vec<Arc<MessageQueue>>> = Mutex::new(Vec::new());
+
+/// Returns the queue named name, creating it if there is none.
+pub fn open(name: &str) -> Arc<MessageQueue> {
+    let mut queues = QUEUES.lock();
+    if let Some(queue) = queues.iter().find(|q| q.name == name) {
+        return queue.clone();
+    }
+    let queue = Arc::new(MessageQueue {
+        name: name.to_string(),
+        messages: Mutex::new(VecDeque::new()),
+    });
+    queues.push(queue.clone());
+    queue
+}
+
+pub fn list() -> Vec<Arc<MessageQueue>> {
+    QUEUES.lock().clone()
+}
@@ -0,0 +1,248 @@
+//! Running programs in user mode (ring 3).
+//!
+//! A program runs in a task, in its own address space (see paging.rs).
+//! The task enters user mode with iretq, and comes back to the kernel on
+//! syscalls (see syscall.rs) and interrupts, on a kernel stack for the
+//! program. When the program exits (or faults), the stack pointer saved on
+//! entering user mode is restored, so Program::run() returns as if the
+//! program were a function call.
+//!
+//! The stack at the entry is laid out as in the System V ABI: rsp points
+//! to argc, followed by argv, a null, an empty envp and an empty auxv.
+
+use crate::arch::sti;
+use crate::elf;
+use crate::fpu::FpuState;
+use crate::gdt;
+use crate::interrupt::InterruptStackFrame;
+use crate::paging;
+use crate::paging::AddressSpace;
+use crate::paging::PAGE_SIZE;
+use crate::percpu;
+use crate::println;
+use crate::result::Result;
+use crate::task;
+use crate::user::file::File;
+use crate::user::file::FileTable;
+use crate::user::file::STDIN;
+use crate::user::file::STDOUT;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::global_asm;
+use core::fmt;
+
+pub mod file;
+pub mod message_queue;
+pub mod pipe;
+pub mod process;
+
+const USER_STACK_SIZE: u64 = 64 * 1024;
+/// The page above the stack is left unmapped to catch underflows.
+const USER_STACK_TOP: u64 = paging::USER_END - PAGE_SIZE;
+const KERNEL_STACK_SIZE: usize = 64 * 1024;
+
+// The selectors are hard-coded below, as global_asm! cannot take constants.
+const _: () = assert!(gdt::USER_DS == 0x9b);
+const _: () = assert!(gdt::USER_CS == 0xa3);
+
+// wasabi_switch_context() と同じく呼び出し先保存レジスタを積み、そのrspを
+// 保存してからiretqでユーザーモードに入る。wasabi_exit_user()がそのrspに戻ると、
+// wasabi_enter_user()から終了コードを返したことになる
+global_asm!(
+    ".global wasabi_enter_user",
+    "wasabi_enter_user:",
+    "push rbp",
+    "push rbx",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+    "mov [rdx], rsp",
+    "fxrstor64 [rcx]",
+    "push 0x9b",
+    "push rsi",
+    "push 0x202",
+    "push 0xa3",
+    "push rdi",
+    "xor eax, eax",
+    "xor ebx, ebx",
+    "xor ecx, ecx",
+    "xor edx, edx",
+    "xor esi, esi",
+    "xor edi, edi",
+    "xor ebp, ebp",
+    "xor r8d, r8d",
+    "xor r9d, r9d",
+    "xor r10d, r10d",
+    "xor r11d, r11d",
+    "xor r12d, r12d",
+    "xor r13d, r13d",
+    "xor r14d, r14d",
+    "xor r15d, r15d",
+    "iretq",
+    ".global wasabi_exit_user",
+    "wasabi_exit_user:",
+    "mov rsp, rdi",
+    "mov rax, rsi",
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop rbx",
+    "pop rbp",
+    "ret",
+);
+extern "sysv64" {
+    /// Enters user mode at entry with the stack at rsp and the x87/SSE
+    /// state fpu, after saving the kernel's stack pointer to *saved_rsp.
+    /// Returns the exit code.
+    fn wasabi_enter_user(entry: u64, rsp: u64, saved_rsp: *mut u64, fpu: *const FpuState) -> i64;
+    /// Returns code from the wasabi_enter_user() that saved saved_rsp.
+    fn wasabi_exit_user(saved_rsp: u64, code: i64) -> !;
+}
+
+/// A program loaded into its own address space.
+pub struct Program {
+    space: AddressSpace,
+    entry: u64,
+    /// The stack pointer at the entry, pointing to argc.
+    rsp: u64,
+    kernel_stack: Box<[u8]>,
+}
+impl Program {
+    /// Loads an ELF executable linked at paging::USER_START or above.
+    pub fn load(bytes: &[u8], args: &[&str]) -> Result<Self> {
+        let elf = elf::parse(bytes)?;
+        let mut space = AddressSpace::new()?;
+        for segment in &elf.segments {
+            space.map(segment.vaddr, segment.mem_size, segment.writable)?;
+            space.write(segment.vaddr, segment.data)?;
+        }
+        space.map(USER_STACK_TOP - USER_STACK_SIZE, USER_STACK_SIZE, true)?;
+        let rsp = push_args(&mut space, args)?;
+        Ok(Self {
+            space,
+            entry: elf.entry,
+            rsp,
+            kernel_stack: vec![0u8; KERNEL_STACK_SIZE].into_boxed_slice(),
+        })
+    }
+    /// The memory used by the address space, in bytes.
+    pub fn memory_size(&self) -> u64 {
+        self.space.size()
+    }
+    /// Runs the program in the current task until it exits, and returns the
+    /// exit code.
+    pub fn run(&self) -> i64 {
+        let top = (self.kernel_stack.as_ptr() as u64 + KERNEL_STACK_SIZE as u64) & !0xf;
+        // 最上部の16バイトには、終了時に戻るためのrspを置く
+        let kernel_stack_top = top - 16;
+        task::set_user_context(self.space.cr3(), kernel_stack_top);
+        // カーネルが使ったSSEレジスタの値をプログラムに見せない
+        let fpu = FpuState::initial();
+        // SAFETY: the address space maps the kernel, and the kernel stack is
+        // alive while self is
+        let code =
+            unsafe { wasabi_enter_user(self.entry, self.rsp, kernel_stack_top as *mut u64, &fpu) };
+        task::set_user_context(0, 0);
+        code
+    }
+}
+
+/// Puts argc, argv and the strings at the top of the stack, and returns the
+/// stack pointer pointing to argc.
+fn push_args(space: &mut AddressSpace, args: &[&str]) -> Result<u64> {
+    let mut strings = Vec::new();
+    let mut offsets = Vec::new();
+    for arg in args {
+        offsets.push(strings.len() as u64);
+        strings.extend_from_slice(arg.as_bytes());
+        strings.push(0);
+    }
+    if strings.len() as u64 + (args.len() as u64 + 5) * 8 > USER_STACK_SIZE / 2 {
+        return Err("Arguments are too long");
+    }
+    let strings_va = (USER_STACK_TOP - strings.len() as u64) & !0xf;
+    let mut words = vec![args.len() as u64];
+    words.extend(offsets.iter().map(|o| strings_va + o));
+    // argvの終端、空のenvp、空のauxv (AT_NULL)
+    words.extend_from_slice(&[0, 0, 0, 0]);
+    let rsp = (strings_va - words.len() as u64 * 8) & !0xf;
+    let words: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+    space.write(rsp, &words)?;
+    space.write(strings_va, &strings)?;
+    Ok(rsp)
+}
+
+/// Ends the program running in the current task with the exit code.
+pub fn exit(code: i64) -> ! {
+    let kernel_stack_top = percpu::this().kernel_stack();
+    assert_ne!(kernel_stack_top, 0, "No user program is running");
+    // SAFETY: Program::run() has saved the stack pointer of the kernel there
+    unsafe { wasabi_exit_user(*(kernel_stack_top as *const u64), code) }
+}
+
+/// Called by the exception handlers on a fault in user mode, which is not
+/// the kernel's fault: kills the program instead of stopping the system.
+pub fn kill_on_fault(name: &str, frame: &InterruptStackFrame) -> ! {
+    percpu::restore_from_kernel_gs_base();
+    println!("user: {name} at {:#x}, killed", frame.rip);
+    sti();
+    exit(-1)
+}
+
+/// Called by the IRQ handlers after the EOI: lets the other tasks run if the
+/// IRQ came from a user program that has used up its time slice or got a
+/// signal, so that a program that never makes a syscall can still be
+/// stopped. The kernel itself is cooperative and never preempted.
+pub fn preempt(frame: &InterruptStackFrame) {
+    if !is_user_frame(frame) {
+        return;
+    }
+    percpu::restore_from_kernel_gs_base();
+    if task::should_yield() {
+        // ユーザーモードからの割り込みなので、カーネルのロックは何も持っていない
+        sti();
+        task::yield_now();
+    }
+}
+
+/// Whether the exception happened in user mode.
+pub fn is_user_frame(frame: &InterruptStackFrame) -> bool {
+    frame.cs & 3 == 3
+}
+
+/// The `run` command. Programs separated by `|` are connected with pipes.
+pub fn cmd_run(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let stages: Vec<&[&str]> = args.split(|a| *a == "|").collect();
+    if stages.iter().any(|s| s.is_empty()) {
+        return Err("usage: run <path of an ELF executable on the ESP> [args...] [| ...]");
+    }
+    let mut pids = Vec::new();
+    let mut stdin: Option<Arc<dyn File>> = None;
+    for (i, stage) in stages.iter().enumerate() {
+        let mut files = FileTable::with_console();
+        if let Some(reader) = stdin.take() {
+            files.set(STDIN, reader);
+        }
+        if i + 1 < stages.len() {
+            let (reader, writer) = pipe::pipe();
+            files.set(STDOUT, writer);
+            stdin = Some(reader);
+        }
+        // 起動に失敗しても、起動済みのものはパイプが閉じられて終わる
+        pids.push(process::spawn_with_files(stage[0], stage, files)?);
+    }
+    let mut code = 0;
+    for pid in pids {
+        code = process::wait(pid)?;
+    }
+    let _ = writeln!(
+        out,
+        "{} exited with code {code}",
+        stages[stages.len() - 1][0]
+    );
+    Ok(())
+}
@@ -0,0 +1,105 @@
+//! Pipes: byte streams from one process to another.
+//!
+//! read() waits for data, and returns 0 once the write end is closed and
+//! the buffer is empty. write() waits for room, and fails once the read
+//! end is closed. Both ends are closed when the last file handle to them
+//! is dropped.
+
+use super::file::File;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::task;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+const CAPACITY: usize = 4096;
+
+struct Buffer {
+    data: VecDeque<u8>,
+    reader_closed: bool,
+    writer_closed: bool,
+}
+
+pub struct PipeReader {
+    buffer: Arc<Mutex<Buffer>>,
+}
+pub struct PipeWriter {
+    buffer: Arc<Mutex<Buffer>>,
+}
+
+/// Creates a pipe and returns its read end and write end.
+pub fn pipe() -> (Arc<PipeReader>, Arc<PipeWriter>) {
+    let buffer = Arc::new(Mutex::new(Buffer {
+        data: VecDeque::with_capacity(CAPACITY),
+        reader_closed: false,
+        writer_closed: false,
+    }));
+    (
+        Arc::new(PipeReader {
+            buffer: buffer.clone(),
+        }),
+        Arc::new(PipeWriter { buffer }),
+    )
+}
+
+impl File for PipeReader {
+    fn read(&self, buf: &mut [u8]) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            {
+                let mut buffer = self.buffer.lock();
+                if !buffer.data.is_empty() {
+                    let n = buf.len().min(buffer.data.len());
+                    for (dst, src) in buf.iter_mut().zip(buffer.data.drain(..n)) {
+                        *dst = src;
+                    }
+                    return Ok(n);
+                }
+                if buffer.writer_closed {
+                    return Ok(0);
+                }
+            }
+            task::yield_now();
+        }
+    }
+    fn write(&self, _buf: &[u8]) -> Result<usize> {
+        Err("Not open for writing")
+    }
+}
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.buffer.lock().reader_closed = true;
+    }
+}
+
+impl File for PipeWriter {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize> {
+        Err("Not open for reading")
+    }
+    /// Writes all of buf, waiting for the reader to make room as needed.
+    fn write(&self, buf: &[u8]) -> Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            {
+                let mut buffer = self.buffer.lock();
+                if buffer.reader_closed {
+                    return Err("Broken pipe");
+                }
+                let n = (CAPACITY - buffer.data.len()).min(buf.len() - written);
+                buffer.data.extend(&buf[written..written + n]);
+                written += n;
+            }
+            if written < buf.len() {
+                task::yield_now();
+            }
+        }
+        Ok(written)
+    }
+}
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.buffer.lock().writer_closed = true;
+    }
+}
@@ -0,0 +1,137 @@
+//! User processes: a program with its address space, open files and tasks.
+//!
+//! The pid of a process is the one of its main task, so process::info()
+//! and the signals work on it as on any task. The process is dropped,
+//! freeing its memory, when the main task ends.
+
+use super::file::FileTable;
+use super::Program;
+use crate::chainload::with_firmware_interrupts;
+use crate::mutex::Mutex;
+use crate::process;
+use crate::process::Pid;
+use crate::process::ProcessState;
+use crate::result::Result;
+use crate::task;
+use crate::uefi;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+pub struct Process {
+    pid: Pid,
+    path: String,
+    program: Program,
+    files: Mutex<FileTable>,
+    tasks: Mutex<Vec<Pid>>,
+}
+impl Process {
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    pub fn memory_size(&self) -> u64 {
+        self.program.memory_size()
+    }
+    pub fn files(&self) -> &Mutex<FileTable> {
+        &self.files
+    }
+    pub fn tasks(&self) -> Vec<Pid> {
+        self.tasks.lock().clone()
+    }
+}
+
+static PROCESSES: Mutex<Vec<Arc<Process>>> = Mutex::new(Vec::new());
+
+pub fn find(pid: Pid) -> Option<Arc<Process>> {
+    PROCESSES.lock().iter().find(|p| p.pid == pid).cloned()
+}
+
+pub fn list() -> Vec<Arc<Process>> {
+    PROCESSES.lock().clone()
+}
+
+/// The process whose task is running, if it is a user process.
+pub fn current() -> Option<Arc<Process>> {
+    let pid = task::current()?;
+    PROCESSES
+        .lock()
+        .iter()
+        .find(|p| p.tasks.lock().contains(&pid))
+        .cloned()
+}
+
+/// The open files of the calling process, or the console if called from
+/// the kernel.
+pub fn inherited_files() -> FileTable {
+    match current() {
+        Some(parent) => parent.files.lock().clone(),
+        None => FileTable::with_console(),
+    }
+}
+
+/// Starts the program at path on the ESP with args (args[0] is the name of
+/// the program by convention), and returns its pid.
+///
+/// The process inherits the open files of the calling process.
+pub fn spawn(path: &str, args: &[&str]) -> Result<Pid> {
+    spawn_with_files(path, args, inherited_files())
+}
+
+/// Starts the program at path with the open files, e.g. with stdout
+/// redirected to a pipe. See spawn().
+pub fn spawn_with_files(path: &str, args: &[&str], files: FileTable) -> Result<Pid> {
+    let efi_system_table = uefi::system_table().ok_or("EFI context is not initialized")?;
+    let bytes = with_firmware_interrupts(|| uefi::read_file(efi_system_table, path))?;
+    spawn_image(path, &bytes, args, files)
+}
+
+/// Starts a program from an ELF image in memory. See spawn().
+pub fn spawn_image(path: &str, image: &[u8], args: &[&str], files: FileTable) -> Result<Pid> {
+    let program = Program::load(image, args)?;
+    // タスクは次のyield_now()まで動かないので、その前に登録すれば間に合う
+    let pid = task::spawn(path, run_main_task);
+    PROCESSES.lock().push(Arc::new(Process {
+        pid,
+        path: path.to_string(),
+        program,
+        files: Mutex::new(files),
+        tasks: Mutex::new(alloc::vec![pid]),
+    }));
+    Ok(pid)
+}
+
+fn run_main_task() {
+    let pid = task::current().expect("No current task");
+    let process = find(pid).expect("The process is not registered");
+    let code = process.program.run();
+    remove(pid);
+    drop(process);
+    task::exit(code)
+}
+
+/// Forgets the process, which frees its memory once nobody refers to it.
+fn remove(pid: Pid) {
+    PROCESSES.lock().retain(|p| p.pid != pid);
+}
+
+/// Waits for the process to exit, and returns its exit code.
+pub fn wait(pid: Pid) -> Result<i64> {
+    if task::current() == Some(pid) {
+        return Err("A process cannot wait for itself");
+    }
+    loop {
+        match process::info(pid).ok_or("No such process")?.state {
+            ProcessState::Exited(code) => {
+                let _ = process::reap(pid);
+                // Killされたタスクは実行されずに捨てられるので、ここで片付ける
+                remove(pid);
+                return Ok(code);
+            }
+            ProcessState::Running => task::yield_now(),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use crate::result::Result;
+use core::fmt;
+
+/// Information about this kernel build, embedded by build.rs.
+pub struct VersionInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    /// Short commit hash, with "-dirty" if there were uncommitted changes.
+    pub git_hash: &'static str,
+    /// UTC date in YYYY-MM-DD.
+    pub build_date: &'static str,
+    pub rustc_version: &'static str,
+    /// Enabled cargo features, comma separated.
+    pub features: &'static str,
+    /// "debug" or "release".
+    pub profile: &'static str,
+    pub arch: &'static str,
+}
+
+static VERSION: VersionInfo = VersionInfo {
+    name: "WasabiOS",
+    version: env!("CARGO_PKG_VERSION"),
+    git_hash: env!("WASABI_GIT_HASH"),
+    build_date: env!("WASABI_BUILD_DATE"),
+    rustc_version: env!("WASABI_RUSTC_VERSION"),
+    features: env!("WASABI_FEATURES"),
+    profile: env!("WASABI_PROFILE"),
+    arch: "x86_64",
+};
+
+pub fn version() -> &'static VersionInfo {
+    &VERSION
+}
+
+impl fmt::Display for VersionInfo {
+    /// The one-line banner, e.g. "WasabiOS 0.1.0 (abcdef012345, 2024-01-01, debug)".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} ({}, {}, {})",
+            self.name, self.version, self.git_hash, self.build_date, self.profile
+        )
+    }
+}
+
+/// The `uname` command.
+pub fn cmd_uname(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let v = version();
+    match args {
+        [] | ["-s"] => {
+            let _ = writeln!(out, "{}", v.name);
+        }
+        ["-r"] => {
+            let _ = writeln!(out, "{}", v.version);
+        }
+        ["-m"] => {
+            let _ = writeln!(out, "{}", v.arch);
+        }
+        ["-a"] => {
+            let _ = writeln!(out, "{v} {}", v.arch);
+            let _ = writeln!(out, "built with {}", v.rustc_version);
+            if !v.features.is_empty() {
+                let _ = writeln!(out, "features: {}", v.features);
+            }
+        }
+        _ => return Err("usage: uname [-a|-s|-r|-m]"),
+    }
+    Ok(())
+}
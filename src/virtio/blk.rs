@@ -0,0 +1,178 @@
+//! virtio-blk, the disks of QEMU with `-drive if=virtio`.
+
+use super::Buffer;
+use super::VirtioPci;
+use super::Virtqueue;
+use crate::block;
+use crate::block::BlockDevice;
+use crate::kexec;
+use crate::mutex::Mutex;
+use crate::pci;
+use crate::result::Result;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// The transitional and the modern device IDs.
+const DEVICE_IDS: [u16; 2] = [0x1001, 0x1042];
+
+const F_RO: u64 = 1 << 5;
+const F_BLK_SIZE: u64 = 1 << 6;
+const F_FLUSH: u64 = 1 << 9;
+
+const CONFIG_CAPACITY: usize = 0;
+const CONFIG_BLK_SIZE: usize = 20;
+
+const REQ_IN: u32 = 0;
+const REQ_OUT: u32 = 1;
+const REQ_FLUSH: u32 = 4;
+const STATUS_OK: u8 = 0;
+const STATUS_UNSUPPORTED: u8 = 2;
+
+/// The unit of the sector numbers in the requests, regardless of the block
+/// size of the disk.
+const SECTOR_SIZE: usize = 512;
+/// Larger requests are split.
+const MAX_TRANSFER: usize = 0x10000;
+
+#[repr(C)]
+struct RequestHeader {
+    req_type: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+pub struct VirtioBlk {
+    name: String,
+    virtio: VirtioPci,
+    queue: Mutex<Virtqueue>,
+    block_size: usize,
+    block_count: u64,
+    features: u64,
+}
+impl VirtioBlk {
+    fn new(dev: &pci::PciDevice, name: String) -> Result<Self> {
+        let virtio = VirtioPci::new(dev)?;
+        let features = virtio.negotiate(F_RO | F_BLK_SIZE | F_FLUSH)?;
+        let queue = virtio.setup_queue(0)?;
+        virtio.driver_ok();
+        let block_size = if features & F_BLK_SIZE != 0 {
+            virtio.config::<u32>(CONFIG_BLK_SIZE) as usize
+        } else {
+            SECTOR_SIZE
+        };
+        if block_size < SECTOR_SIZE || block_size % SECTOR_SIZE != 0 || block_size > MAX_TRANSFER {
+            return Err("Unsupported block size");
+        }
+        let sectors = virtio.config::<u64>(CONFIG_CAPACITY);
+        Ok(Self {
+            name,
+            virtio,
+            queue: Mutex::new(queue),
+            block_size,
+            block_count: sectors / (block_size / SECTOR_SIZE) as u64,
+            features,
+        })
+    }
+    fn request(&self, req_type: u32, lba: u64, data: Option<Buffer>) -> Result<()> {
+        let header = RequestHeader {
+            req_type,
+            reserved: 0,
+            sector: lba * (self.block_size / SECTOR_SIZE) as u64,
+        };
+        let mut status = 0xffu8;
+        let header = Buffer::Out(
+            &header as *const RequestHeader as *const u8,
+            core::mem::size_of::<RequestHeader>(),
+        );
+        let status_buf = Buffer::In(&mut status, 1);
+        // メモリはアイデンティティマップなので、呼び出し元のバッファを直接渡せる
+        let mut queue = self.queue.lock();
+        match data {
+            Some(data) => queue.submit(&[header, data, status_buf])?,
+            None => queue.submit(&[header, status_buf])?,
+        };
+        // SAFETY: the device has written the status
+        match unsafe { core::ptr::read_volatile(&status) } {
+            STATUS_OK => Ok(()),
+            STATUS_UNSUPPORTED => Err("virtio-blk request is not supported"),
+            _ => Err("virtio-blk I/O error"),
+        }
+    }
+    pub fn flush(&self) -> Result<()> {
+        if self.features & F_FLUSH == 0 {
+            return Ok(());
+        }
+        self.request(REQ_FLUSH, 0, None)
+    }
+}
+impl BlockDevice for VirtioBlk {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+    fn block_count(&self) -> u64 {
+        self.block_count
+    }
+    fn is_read_only(&self) -> bool {
+        self.features & F_RO != 0
+    }
+    fn read_blocks(&self, lba: u64, buf: &mut [u8]) -> Result<()> {
+        block::check_range(self, lba, buf.len())?;
+        let mut lba = lba;
+        for chunk in buf.chunks_mut(MAX_TRANSFER / self.block_size * self.block_size) {
+            let data = Buffer::In(chunk.as_mut_ptr(), chunk.len());
+            self.request(REQ_IN, lba, Some(data))?;
+            lba += (chunk.len() / self.block_size) as u64;
+        }
+        Ok(())
+    }
+    fn write_blocks(&self, lba: u64, buf: &[u8]) -> Result<()> {
+        if self.is_read_only() {
+            return Err("Block device is read-only");
+        }
+        block::check_range(self, lba, buf.len())?;
+        let mut lba = lba;
+        for chunk in buf.chunks(MAX_TRANSFER / self.block_size * self.block_size) {
+            let data = Buffer::Out(chunk.as_ptr(), chunk.len());
+            self.request(REQ_OUT, lba, Some(data))?;
+            lba += (chunk.len() / self.block_size) as u64;
+        }
+        Ok(())
+    }
+}
+
+static DISKS: Mutex<Vec<Arc<VirtioBlk>>> = Mutex::new(Vec::new());
+
+/// Starts the virtio-blk devices and registers them as vda, vdb, ...
+/// Returns the number of disks.
+pub fn init() -> Result<usize> {
+    let mut disks = Vec::new();
+    for dev in pci::devices() {
+        if dev.vendor_id != super::VENDOR_ID || !DEVICE_IDS.contains(&dev.device_id) {
+            continue;
+        }
+        match VirtioBlk::new(&dev, block::next_name("vd")) {
+            Ok(disk) => {
+                let disk = Arc::new(disk);
+                block::register(disk.clone());
+                disks.push(disk);
+            }
+            Err(e) => crate::warn!("{}: {e}", dev.bdf),
+        }
+    }
+    if disks.is_empty() {
+        return Err("No virtio-blk device");
+    }
+    let n = disks.len();
+    *DISKS.lock() = disks;
+    kexec::register_shutdown_hook("virtio-blk", || {
+        for disk in DISKS.lock().iter() {
+            let _ = disk.flush();
+            let _ = disk.virtio.reset();
+        }
+    });
+    Ok(n)
+}
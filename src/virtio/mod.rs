@@ -0,0 +1,363 @@
+//! The virtio PCI transport (virtio 1.0 "modern" devices).
+//!
+//! The registers are found through the vendor specific PCI capabilities.
+//! Virtqueues use the split layout, and the requests are completed
+//! synchronously by polling the used ring, so no interrupts are needed.
+//! Queues the device fills at its own pace (e.g. the receive queue of a
+//! NIC) are kept stocked with post() and checked with poll() instead.
+
+#[cfg(feature = "storage")]
+pub mod blk;
+#[cfg(feature = "net")]
+pub mod net;
+
+use crate::dma;
+use crate::pci;
+use crate::pci::PciDevice;
+use crate::result::Result;
+use crate::time;
+use core::ptr::read_volatile;
+use core::ptr::write_volatile;
+use core::sync::atomic::fence;
+use core::sync::atomic::Ordering;
+
+pub const VENDOR_ID: u16 = 0x1af4;
+
+const PCI_CAP_VENDOR: u8 = 0x09;
+const CFG_TYPE_COMMON: u8 = 1;
+const CFG_TYPE_NOTIFY: u8 = 2;
+const CFG_TYPE_ISR: u8 = 3;
+const CFG_TYPE_DEVICE: u8 = 4;
+
+// Common configuration
+const COMMON_DEVICE_FEATURE_SELECT: usize = 0x00;
+const COMMON_DEVICE_FEATURE: usize = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: usize = 0x08;
+const COMMON_DRIVER_FEATURE: usize = 0x0c;
+const COMMON_DEVICE_STATUS: usize = 0x14;
+const COMMON_QUEUE_SELECT: usize = 0x16;
+const COMMON_QUEUE_SIZE: usize = 0x18;
+const COMMON_QUEUE_MSIX_VECTOR: usize = 0x1a;
+const COMMON_QUEUE_ENABLE: usize = 0x1c;
+const COMMON_QUEUE_NOTIFY_OFF: usize = 0x1e;
+const COMMON_QUEUE_DESC: usize = 0x20;
+const COMMON_QUEUE_DRIVER: usize = 0x28;
+const COMMON_QUEUE_DEVICE: usize = 0x30;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FEATURES_OK: u8 = 8;
+const STATUS_FAILED: u8 = 0x80;
+
+pub const F_VERSION_1: u64 = 1 << 32;
+
+const NO_VECTOR: u16 = 0xffff;
+const MAX_QUEUE_SIZE: u16 = 128;
+const TIMEOUT_NS: u64 = 5_000_000_000;
+
+/// Allocates zeroed memory for a queue that is never freed. VIRTIO 1.0
+/// devices take 64-bit addresses.
+fn alloc_dma(size: usize, align: usize) -> Result<*mut u8> {
+    Ok(dma::alloc_dma(size, align)?.leak())
+}
+
+/// A register window in a memory BAR.
+#[derive(Debug, Clone, Copy)]
+struct Mmio(usize);
+impl Mmio {
+    fn read<T: Copy>(&self, offset: usize) -> T {
+        // SAFETY: the BARs are identity mapped
+        unsafe { read_volatile((self.0 + offset) as *const T) }
+    }
+    fn write<T: Copy>(&self, offset: usize, value: T) {
+        // SAFETY: the BARs are identity mapped
+        unsafe { write_volatile((self.0 + offset) as *mut T, value) }
+    }
+    fn write64(&self, offset: usize, value: u64) {
+        self.write(offset, value as u32);
+        self.write(offset + 4, (value >> 32) as u32);
+    }
+}
+
+/// The registers of a virtio device.
+#[derive(Debug)]
+pub struct VirtioPci {
+    pub bdf: pci::BusDeviceFunction,
+    common: Mmio,
+    notify: Mmio,
+    notify_off_multiplier: u32,
+    isr: Mmio,
+    device: Mmio,
+}
+impl VirtioPci {
+    /// Finds the registers and resets the device.
+    pub fn new(dev: &PciDevice) -> Result<Self> {
+        let bdf = dev.bdf;
+        let (mut common, mut notify, mut isr, mut device) = (None, None, None, None);
+        let mut notify_off_multiplier = 0;
+        for (id, cap) in pci::capabilities(bdf) {
+            if id != PCI_CAP_VENDOR {
+                continue;
+            }
+            let cfg_type = pci::read_config_u8(bdf, cap + 3);
+            let bar = pci::read_config_u8(bdf, cap + 4) as usize;
+            let offset = pci::read_config_u32(bdf, cap + 8) as usize;
+            let Some(base) = dev.bar(bar).and_then(|b| b.memory_addr()) else {
+                continue;
+            };
+            let window = Some(Mmio(base as usize + offset));
+            // 同じ種類が複数あるときは最初のものを使う (仕様の推奨)
+            match cfg_type {
+                CFG_TYPE_COMMON if common.is_none() => common = window,
+                CFG_TYPE_NOTIFY if notify.is_none() => {
+                    notify = window;
+                    notify_off_multiplier = pci::read_config_u32(bdf, cap + 16);
+                }
+                CFG_TYPE_ISR if isr.is_none() => isr = window,
+                CFG_TYPE_DEVICE if device.is_none() => device = window,
+                _ => {}
+            }
+        }
+        pci::enable_bus_master(bdf);
+        let virtio = Self {
+            bdf,
+            common: common.ok_or("virtio common config not found")?,
+            notify: notify.ok_or("virtio notify config not found")?,
+            notify_off_multiplier,
+            isr: isr.ok_or("virtio ISR not found")?,
+            device: device.ok_or("virtio device config not found")?,
+        };
+        virtio.reset()?;
+        Ok(virtio)
+    }
+    pub fn reset(&self) -> Result<()> {
+        self.common.write(COMMON_DEVICE_STATUS, 0u8);
+        let deadline = time::now_ns() + TIMEOUT_NS;
+        while self.common.read::<u8>(COMMON_DEVICE_STATUS) != 0 {
+            if time::now_ns() > deadline {
+                return Err("virtio reset timed out");
+            }
+            core::hint::spin_loop();
+        }
+        Ok(())
+    }
+    fn add_status(&self, status: u8) {
+        let old = self.common.read::<u8>(COMMON_DEVICE_STATUS);
+        self.common.write(COMMON_DEVICE_STATUS, old | status);
+    }
+    /// Acknowledges the device and accepts the features in wanted that the
+    /// device offers. VERSION_1 is required. Returns the accepted features.
+    pub fn negotiate(&self, wanted: u64) -> Result<u64> {
+        self.add_status(STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+        let mut offered = 0;
+        for i in 0..2 {
+            self.common.write(COMMON_DEVICE_FEATURE_SELECT, i as u32);
+            offered |= (self.common.read::<u32>(COMMON_DEVICE_FEATURE) as u64) << (i * 32);
+        }
+        if offered & F_VERSION_1 == 0 {
+            self.add_status(STATUS_FAILED);
+            return Err("Legacy virtio devices are not supported");
+        }
+        let accepted = offered & (wanted | F_VERSION_1);
+        for i in 0..2 {
+            self.common.write(COMMON_DRIVER_FEATURE_SELECT, i as u32);
+            self.common
+                .write(COMMON_DRIVER_FEATURE, (accepted >> (i * 32)) as u32);
+        }
+        self.add_status(STATUS_FEATURES_OK);
+        if self.common.read::<u8>(COMMON_DEVICE_STATUS) & STATUS_FEATURES_OK == 0 {
+            self.add_status(STATUS_FAILED);
+            return Err("virtio device rejected the features");
+        }
+        Ok(accepted)
+    }
+    /// Sets up the virtqueue. Call after negotiate() and before driver_ok().
+    pub fn setup_queue(&self, index: u16) -> Result<Virtqueue> {
+        self.common.write(COMMON_QUEUE_SELECT, index);
+        let size = self
+            .common
+            .read::<u16>(COMMON_QUEUE_SIZE)
+            .min(MAX_QUEUE_SIZE);
+        if size == 0 {
+            return Err("virtqueue is not available");
+        }
+        let queue = Virtqueue::new(index, size)?;
+        self.common.write(COMMON_QUEUE_SIZE, size);
+        self.common.write(COMMON_QUEUE_MSIX_VECTOR, NO_VECTOR);
+        self.common.write64(COMMON_QUEUE_DESC, queue.desc as u64);
+        self.common.write64(COMMON_QUEUE_DRIVER, queue.avail as u64);
+        self.common.write64(COMMON_QUEUE_DEVICE, queue.used as u64);
+        let notify_off = self.common.read::<u16>(COMMON_QUEUE_NOTIFY_OFF) as usize;
+        self.common.write(COMMON_QUEUE_ENABLE, 1u16);
+        Ok(Virtqueue {
+            notify: Mmio(self.notify.0 + notify_off * self.notify_off_multiplier as usize),
+            ..queue
+        })
+    }
+    pub fn driver_ok(&self) {
+        self.add_status(STATUS_DRIVER_OK);
+    }
+    /// Reads the device specific configuration at offset.
+    pub fn config<T: Copy>(&self, offset: usize) -> T {
+        self.device.read(offset)
+    }
+    /// Reads and clears the interrupt status.
+    pub fn isr(&self) -> u8 {
+        self.isr.read(0)
+    }
+}
+
+const DESC_F_NEXT: u16 = 1;
+const DESC_F_WRITE: u16 = 2;
+const AVAIL_F_NO_INTERRUPT: u16 = 1;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// A buffer of a request.
+#[derive(Debug, Clone, Copy)]
+pub enum Buffer {
+    /// Read by the device.
+    Out(*const u8, usize),
+    /// Written by the device.
+    In(*mut u8, usize),
+}
+
+/// A split virtqueue. submit() has one request in flight at a time, while
+/// post() keeps up to size single buffers in flight.
+#[derive(Debug)]
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    desc: *mut Descriptor,
+    /// flags, idx, ring[size], used_event
+    avail: *mut u16,
+    /// flags, idx, ring[size] of (id: u32, len: u32), avail_event
+    used: *mut u16,
+    notify: Mmio,
+    avail_idx: u16,
+    used_idx: u16,
+}
+// SAFETY: the rings are only touched by the owner of the queue
+unsafe impl Send for Virtqueue {}
+impl Virtqueue {
+    fn new(index: u16, size: u16) -> Result<Self> {
+        let n = size as usize;
+        let desc = alloc_dma(16 * n, 16)? as *mut Descriptor;
+        let avail = alloc_dma(6 + 2 * n, 2)? as *mut u16;
+        let used = alloc_dma(6 + 8 * n, 4)? as *mut u16;
+        // 完了はポーリングで待つので割り込みは要らない
+        // SAFETY: avail has the flags at the start
+        unsafe { write_volatile(avail, AVAIL_F_NO_INTERRUPT) };
+        Ok(Self {
+            index,
+            size,
+            desc,
+            avail,
+            used,
+            notify: Mmio(0),
+            avail_idx: 0,
+            used_idx: 0,
+        })
+    }
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+    /// Passes the buffers to the device as a chain and waits until the
+    /// device is done with them. Returns the number of bytes written by the
+    /// device.
+    pub fn submit(&mut self, buffers: &[Buffer]) -> Result<u32> {
+        if buffers.is_empty() || buffers.len() > self.size as usize {
+            return Err("Invalid number of virtio buffers");
+        }
+        for (i, b) in buffers.iter().enumerate() {
+            let (addr, len, flags) = match *b {
+                Buffer::Out(p, len) => (p as u64, len, 0),
+                Buffer::In(p, len) => (p as u64, len, DESC_F_WRITE),
+            };
+            let next = if i + 1 < buffers.len() {
+                DESC_F_NEXT
+            } else {
+                0
+            };
+            let desc = Descriptor {
+                addr,
+                len: len as u32,
+                flags: flags | next,
+                next: i as u16 + 1,
+            };
+            // SAFETY: i < size
+            unsafe { write_volatile(self.desc.add(i), desc) };
+        }
+        self.make_available(0);
+        let deadline = time::now_ns() + TIMEOUT_NS;
+        loop {
+            if let Some((_, len)) = self.poll() {
+                return Ok(len);
+            }
+            if time::now_ns() > deadline {
+                return Err("virtio request timed out");
+            }
+            core::hint::spin_loop();
+        }
+    }
+    /// Passes a single buffer to the device using the descriptor id, without
+    /// waiting. Do not mix with submit() on the same queue.
+    pub fn post(&mut self, id: u16, buffer: Buffer) -> Result<()> {
+        if id >= self.size {
+            return Err("Invalid virtio descriptor id");
+        }
+        let (addr, len, flags) = match buffer {
+            Buffer::Out(p, len) => (p as u64, len, 0),
+            Buffer::In(p, len) => (p as u64, len, DESC_F_WRITE),
+        };
+        let desc = Descriptor {
+            addr,
+            len: len as u32,
+            flags,
+            next: 0,
+        };
+        // SAFETY: id < size
+        unsafe { write_volatile(self.desc.add(id as usize), desc) };
+        self.make_available(id);
+        Ok(())
+    }
+    /// Returns the descriptor id of a chain the device is done with and the
+    /// number of bytes it has written, if any.
+    pub fn poll(&mut self) -> Option<(u16, u32)> {
+        // SAFETY: idx is the second u16 of the used ring
+        if unsafe { read_volatile(self.used.add(1)) } == self.used_idx {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let elem = 4 + 8 * (self.used_idx % self.size) as usize;
+        self.used_idx = self.used_idx.wrapping_add(1);
+        // SAFETY: the element is within the used ring
+        let (id, len) = unsafe {
+            (
+                read_volatile(self.used.byte_add(elem) as *const u32),
+                read_volatile(self.used.byte_add(elem + 4) as *const u32),
+            )
+        };
+        Some((id as u16, len))
+    }
+    fn make_available(&mut self, head: u16) {
+        let slot = 2 + (self.avail_idx % self.size) as usize;
+        self.avail_idx = self.avail_idx.wrapping_add(1);
+        // SAFETY: the ring has size entries after flags and idx
+        unsafe {
+            write_volatile(self.avail.add(slot), head);
+            fence(Ordering::SeqCst);
+            write_volatile(self.avail.add(1), self.avail_idx);
+        }
+        fence(Ordering::SeqCst);
+        self.notify.write(0, self.index);
+    }
+}
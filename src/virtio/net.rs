@@ -0,0 +1,162 @@
+//! virtio-net, the NICs of QEMU with `-device virtio-net-pci`.
+//!
+//! The receive queue is kept full of buffers, one descriptor each, and
+//! polled by receive(). Frames are sent synchronously.
+
+use super::Buffer;
+use super::VirtioPci;
+use super::Virtqueue;
+use crate::arch::rdtsc;
+use crate::kexec;
+use crate::mutex::Mutex;
+use crate::net;
+use crate::net::ethernet;
+use crate::net::ethernet::MacAddr;
+use crate::net::NetworkDevice;
+use crate::pci;
+use crate::result::Result;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+/// The transitional and the modern device IDs.
+const DEVICE_IDS: [u16; 2] = [0x1000, 0x1041];
+
+const F_MAC: u64 = 1 << 5;
+
+const CONFIG_MAC: usize = 0;
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+const MAX_RX_BUFFERS: u16 = 32;
+
+/// The header in front of every frame. Without offloads it is all zero.
+#[repr(C)]
+#[derive(Debug, Default)]
+struct NetHeader {
+    flags: u8,
+    gso_type: u8,
+    hdr_len: u16,
+    gso_size: u16,
+    csum_start: u16,
+    csum_offset: u16,
+    num_buffers: u16,
+}
+
+const HEADER_SIZE: usize = size_of::<NetHeader>();
+/// Large enough for the header and a frame of the MTU.
+const RX_BUFFER_SIZE: usize = HEADER_SIZE + ethernet::HEADER_SIZE + ethernet::MTU;
+
+struct Rx {
+    queue: Virtqueue,
+    /// The buffer of each descriptor.
+    buffers: Vec<Box<[u8]>>,
+}
+
+pub struct VirtioNet {
+    name: String,
+    virtio: VirtioPci,
+    mac: MacAddr,
+    rx: Mutex<Rx>,
+    tx: Mutex<Virtqueue>,
+}
+impl VirtioNet {
+    fn new(dev: &pci::PciDevice, name: String) -> Result<Self> {
+        let virtio = VirtioPci::new(dev)?;
+        let features = virtio.negotiate(F_MAC)?;
+        let mut rx_queue = virtio.setup_queue(RX_QUEUE)?;
+        let tx = virtio.setup_queue(TX_QUEUE)?;
+        virtio.driver_ok();
+        let mac = if features & F_MAC != 0 {
+            MacAddr::new(core::array::from_fn(|i| {
+                virtio.config::<u8>(CONFIG_MAC + i)
+            }))
+        } else {
+            // 仕様ではドライバがランダムに決めることになっている
+            let [a, b, c, d, ..] = rdtsc().to_le_bytes();
+            MacAddr::new([0x02, 0x00, a, b, c, d])
+        };
+        let mut buffers = Vec::new();
+        for id in 0..rx_queue.size().min(MAX_RX_BUFFERS) {
+            let mut buf = vec![0u8; RX_BUFFER_SIZE].into_boxed_slice();
+            rx_queue.post(id, Buffer::In(buf.as_mut_ptr(), buf.len()))?;
+            buffers.push(buf);
+        }
+        Ok(Self {
+            name,
+            virtio,
+            mac,
+            rx: Mutex::new(Rx {
+                queue: rx_queue,
+                buffers,
+            }),
+            tx: Mutex::new(tx),
+        })
+    }
+}
+impl NetworkDevice for VirtioNet {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn mac_addr(&self) -> MacAddr {
+        self.mac
+    }
+    fn send(&self, frame: &[u8]) -> Result<()> {
+        if frame.len() > ethernet::HEADER_SIZE + ethernet::MTU {
+            return Err("Frame is too large");
+        }
+        let header = NetHeader::default();
+        let header = Buffer::Out(&header as *const NetHeader as *const u8, HEADER_SIZE);
+        self.tx
+            .lock()
+            .submit(&[header, Buffer::Out(frame.as_ptr(), frame.len())])?;
+        Ok(())
+    }
+    fn receive(&self) -> Option<Vec<u8>> {
+        let mut rx = self.rx.lock();
+        let Rx { queue, buffers } = &mut *rx;
+        let (id, len) = queue.poll()?;
+        let buf = buffers.get_mut(id as usize)?;
+        let len = (len as usize).clamp(HEADER_SIZE, buf.len());
+        let frame = buf[HEADER_SIZE..len].to_vec();
+        // 同じバッファをすぐに受信キューへ戻す
+        let _ = queue.post(id, Buffer::In(buf.as_mut_ptr(), buf.len()));
+        Some(frame)
+    }
+}
+
+static NICS: Mutex<Vec<Arc<VirtioNet>>> = Mutex::new(Vec::new());
+
+/// Starts the virtio-net devices and registers them as interfaces.
+/// Returns the number of devices.
+pub fn init() -> Result<usize> {
+    let mut nics = Vec::new();
+    for dev in pci::devices() {
+        if dev.vendor_id != super::VENDOR_ID || !DEVICE_IDS.contains(&dev.device_id) {
+            continue;
+        }
+        match VirtioNet::new(&dev, net::next_name()) {
+            Ok(nic) => {
+                let nic = Arc::new(nic);
+                net::register(nic.clone());
+                nics.push(nic);
+            }
+            Err(e) => crate::warn!("{}: {e}", dev.bdf),
+        }
+    }
+    if nics.is_empty() {
+        return Err("No virtio-net device");
+    }
+    let n = nics.len();
+    *NICS.lock() = nics;
+    // 次のカーネルに渡ったメモリへ受信データを書き込まないよう止めておく
+    kexec::register_shutdown_hook("virtio-net", || {
+        for nic in NICS.lock().iter() {
+            let _ = nic.virtio.reset();
+        }
+    });
+    Ok(n)
+}
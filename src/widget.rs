@@ -0,0 +1,542 @@
+//! A small retained-mode widget toolkit on top of the windows.
+//!
+//! A Ui is a tree of widgets (Label, Button, TextBox, stacked with VStack)
+//! which lays them out in the contents of a window, hit-tests the pointer,
+//! keeps the keyboard focus and turns the keys into characters, so that an
+//! app only builds the tree and reacts in the callbacks. Texts that the
+//! callbacks change are shared as `Rc<RefCell<String>>`. Built with the
+//! `gui` feature.
+
+use crate::clipboard;
+use crate::executor;
+use crate::graphics::draw_str_fg;
+use crate::graphics::fill_rect;
+use crate::graphics::Bitmap;
+use crate::graphics::OwnedBitmap;
+use crate::graphics::Rect;
+use crate::keymap::Key;
+use crate::keymap::KeyMapper;
+use crate::keymap::Keystroke;
+use crate::keymap::NamedKey;
+use crate::mouse::MouseButtons;
+use crate::result::Result;
+use crate::tty::CTRL_C;
+use crate::tty::CTRL_V;
+use crate::tty::CTRL_X;
+use crate::wm;
+use crate::wm::WindowEvent;
+use crate::wm::WindowId;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+const CHAR_WIDTH: i64 = 8;
+const CHAR_HEIGHT: i64 = 16;
+
+const BACKGROUND: u32 = 0xd0d0d0;
+const TEXT: u32 = 0x000000;
+const FRAME: u32 = 0x404040;
+const FOCUS_FRAME: u32 = 0x3060c0;
+const BUTTON: u32 = 0xe8e8e8;
+const BUTTON_PRESSED: u32 = 0xa0a0a0;
+const TEXT_BOX: u32 = 0xffffff;
+
+/// The space between the frame and the text of buttons and text boxes.
+const PADDING: i64 = 4;
+
+/// An input to a widget. The positions are relative to the contents of the
+/// window, like the rects of the widgets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// A key, to the focused widget only.
+    Key(Keystroke),
+    /// The left button was pressed.
+    MouseDown { x: i64, y: i64 },
+    /// The left button was released.
+    MouseUp { x: i64, y: i64 },
+    MouseMove {
+        x: i64,
+        y: i64,
+        buttons: MouseButtons,
+    },
+}
+
+pub trait Widget {
+    /// The size the widget wants.
+    fn preferred_size(&self) -> (i64, i64);
+    /// Places the widget at rect.
+    fn layout(&mut self, rect: Rect);
+    fn rect(&self) -> Rect;
+    fn draw(&self, bitmap: &mut OwnedBitmap);
+    /// Handles an event, and returns true if the widget needs to be redrawn.
+    fn handle_event(&mut self, _event: &Event) -> bool {
+        false
+    }
+    /// Whether clicking the widget gives it the keyboard focus.
+    fn accepts_focus(&self) -> bool {
+        false
+    }
+    fn set_focus(&mut self, _focused: bool) {}
+}
+
+/// Draws s at (x, y), cut at max_width.
+fn draw_text(bitmap: &mut OwnedBitmap, x: i64, y: i64, max_width: i64, color: u32, s: &str) {
+    let max_chars = (max_width / CHAR_WIDTH).max(0) as usize;
+    let s: String = s.chars().take(max_chars).collect();
+    draw_str_fg(bitmap, x, y, color, &s);
+}
+
+/// Draws a frame of width 1 filled with fill.
+fn draw_box(bitmap: &mut OwnedBitmap, rect: Rect, frame: u32, fill: u32) {
+    let _ = fill_rect(bitmap, frame, rect.x, rect.y, rect.w, rect.h);
+    let _ = fill_rect(bitmap, fill, rect.x + 1, rect.y + 1, rect.w - 2, rect.h - 2);
+}
+
+/// A line of text.
+pub struct Label {
+    text: Rc<RefCell<String>>,
+    color: u32,
+    rect: Rect,
+}
+impl Label {
+    pub fn new(text: &str) -> Self {
+        Self {
+            text: Rc::new(RefCell::new(text.to_string())),
+            color: TEXT,
+            rect: Rect::new(0, 0, 0, 0),
+        }
+    }
+    pub fn with_color(mut self, color: u32) -> Self {
+        self.color = color;
+        self
+    }
+    /// The text, shared to be changed later.
+    pub fn text(&self) -> Rc<RefCell<String>> {
+        self.text.clone()
+    }
+}
+impl Widget for Label {
+    fn preferred_size(&self) -> (i64, i64) {
+        (
+            self.text.borrow().chars().count() as i64 * CHAR_WIDTH,
+            CHAR_HEIGHT,
+        )
+    }
+    fn layout(&mut self, rect: Rect) {
+        self.rect = rect;
+    }
+    fn rect(&self) -> Rect {
+        self.rect
+    }
+    fn draw(&self, bitmap: &mut OwnedBitmap) {
+        let r = self.rect;
+        draw_text(bitmap, r.x, r.y, r.w, self.color, &self.text.borrow());
+    }
+}
+
+/// A push button, which calls on_click when it is pressed and released
+/// with the pointer on it.
+pub struct Button {
+    label: String,
+    on_click: Box<dyn FnMut()>,
+    rect: Rect,
+    pressed: bool,
+}
+impl Button {
+    pub fn new(label: &str, on_click: impl FnMut() + 'static) -> Self {
+        Self {
+            label: label.to_string(),
+            on_click: Box::new(on_click),
+            rect: Rect::new(0, 0, 0, 0),
+            pressed: false,
+        }
+    }
+}
+impl Widget for Button {
+    fn preferred_size(&self) -> (i64, i64) {
+        let w = self.label.chars().count() as i64 * CHAR_WIDTH;
+        (w + PADDING * 4, CHAR_HEIGHT + PADDING * 2)
+    }
+    fn layout(&mut self, rect: Rect) {
+        self.rect = rect;
+    }
+    fn rect(&self) -> Rect {
+        self.rect
+    }
+    fn draw(&self, bitmap: &mut OwnedBitmap) {
+        let r = self.rect;
+        let fill = if self.pressed { BUTTON_PRESSED } else { BUTTON };
+        draw_box(bitmap, r, FRAME, fill);
+        let w = self.label.chars().count() as i64 * CHAR_WIDTH;
+        let x = r.x + ((r.w - w) / 2).max(PADDING);
+        let y = r.y + (r.h - CHAR_HEIGHT) / 2;
+        draw_text(bitmap, x, y, r.right() - PADDING - x, TEXT, &self.label);
+    }
+    fn handle_event(&mut self, event: &Event) -> bool {
+        match *event {
+            Event::MouseDown { x, y } if self.rect.contains(x, y) => {
+                self.pressed = true;
+                true
+            }
+            Event::MouseUp { x, y } if self.pressed => {
+                self.pressed = false;
+                if self.rect.contains(x, y) {
+                    (self.on_click)();
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The byte offset of the i-th character of s.
+fn byte_offset(s: &str, i: usize) -> usize {
+    s.char_indices()
+        .nth(i)
+        .map_or(s.len(), |(offset, _)| offset)
+}
+
+/// A single-line text field. Left, Right, Home, End, Backspace and Delete
+/// move the cursor and edit the text. Ctrl+C and Ctrl+X copy and cut the
+/// whole text, and Ctrl+V pastes at the cursor.
+pub struct TextBox {
+    text: Rc<RefCell<String>>,
+    /// The position of the cursor in characters.
+    cursor: usize,
+    /// The width in characters.
+    columns: usize,
+    focused: bool,
+    rect: Rect,
+}
+impl TextBox {
+    pub fn new(columns: usize) -> Self {
+        Self {
+            text: Rc::new(RefCell::new(String::new())),
+            cursor: 0,
+            columns,
+            focused: false,
+            rect: Rect::new(0, 0, 0, 0),
+        }
+    }
+    /// The text, shared to be read or changed later.
+    pub fn text(&self) -> Rc<RefCell<String>> {
+        self.text.clone()
+    }
+    pub fn cursor(&self) -> usize {
+        self.cursor.min(self.text.borrow().chars().count())
+    }
+    /// The first character shown, so that the cursor stays visible.
+    fn scroll(&self) -> usize {
+        let visible = ((self.rect.w - PADDING * 2) / CHAR_WIDTH - 1).max(0) as usize;
+        self.cursor().saturating_sub(visible)
+    }
+    fn handle_key(&mut self, key: Key) -> bool {
+        let mut text = self.text.borrow_mut();
+        let len = text.chars().count();
+        let cursor = self.cursor.min(len);
+        self.cursor = match key {
+            Key::Char('\x08') if cursor > 0 => {
+                let offset = byte_offset(&text, cursor - 1);
+                text.remove(offset);
+                cursor - 1
+            }
+            Key::Named(NamedKey::Delete) if cursor < len => {
+                let offset = byte_offset(&text, cursor);
+                text.remove(offset);
+                cursor
+            }
+            Key::Char(c) if !c.is_control() => {
+                let offset = byte_offset(&text, cursor);
+                text.insert(offset, c);
+                cursor + 1
+            }
+            Key::Char(CTRL_C) => {
+                let _ = clipboard::copy(&text);
+                cursor
+            }
+            Key::Char(CTRL_X) => {
+                let _ = clipboard::copy(&text);
+                text.clear();
+                0
+            }
+            Key::Char(CTRL_V) => {
+                // 1行なので改行などの制御文字は入れない
+                let pasted: String = clipboard::paste()
+                    .chars()
+                    .filter(|c| !c.is_control())
+                    .collect();
+                let offset = byte_offset(&text, cursor);
+                text.insert_str(offset, &pasted);
+                cursor + pasted.chars().count()
+            }
+            Key::Named(NamedKey::Left) => cursor.saturating_sub(1),
+            Key::Named(NamedKey::Right) => (cursor + 1).min(len),
+            Key::Named(NamedKey::Home) => 0,
+            Key::Named(NamedKey::End) => len,
+            _ => return false,
+        };
+        true
+    }
+}
+impl Widget for TextBox {
+    fn preferred_size(&self) -> (i64, i64) {
+        let w = self.columns as i64 * CHAR_WIDTH;
+        (w + PADDING * 2, CHAR_HEIGHT + PADDING * 2)
+    }
+    fn layout(&mut self, rect: Rect) {
+        self.rect = rect;
+    }
+    fn rect(&self) -> Rect {
+        self.rect
+    }
+    fn draw(&self, bitmap: &mut OwnedBitmap) {
+        let r = self.rect;
+        let frame = if self.focused { FOCUS_FRAME } else { FRAME };
+        draw_box(bitmap, r, frame, TEXT_BOX);
+        let scroll = self.scroll();
+        let text = self.text.borrow();
+        let shown: String = text.chars().skip(scroll).collect();
+        let (x, y) = (r.x + PADDING, r.y + PADDING);
+        draw_text(bitmap, x, y, r.w - PADDING * 2, TEXT, &shown);
+        if self.focused {
+            let cursor_x = x + (self.cursor() - scroll) as i64 * CHAR_WIDTH;
+            let _ = fill_rect(bitmap, TEXT, cursor_x, y, 1, CHAR_HEIGHT);
+        }
+    }
+    fn handle_event(&mut self, event: &Event) -> bool {
+        match *event {
+            Event::Key(stroke) => self.handle_key(stroke.key),
+            Event::MouseDown { x, y } if self.rect.contains(x, y) => {
+                // クリックした文字の前へカーソルを動かす
+                let column = (x - self.rect.x - PADDING + CHAR_WIDTH / 2) / CHAR_WIDTH;
+                let len = self.text.borrow().chars().count();
+                self.cursor = (self.scroll() + column.max(0) as usize).min(len);
+                true
+            }
+            _ => false,
+        }
+    }
+    fn accepts_focus(&self) -> bool {
+        true
+    }
+    fn set_focus(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+}
+
+/// Stacks the children from the top to the bottom, each in its preferred
+/// height and the full width.
+pub struct VStack {
+    children: Vec<Box<dyn Widget>>,
+    /// The child with the keyboard focus.
+    focus: Option<usize>,
+    rect: Rect,
+}
+impl VStack {
+    /// The space around and between the children.
+    pub const SPACING: i64 = 8;
+
+    pub fn new() -> Self {
+        Self {
+            children: Vec::new(),
+            focus: None,
+            rect: Rect::new(0, 0, 0, 0),
+        }
+    }
+    pub fn with(mut self, child: impl Widget + 'static) -> Self {
+        self.push(child);
+        self
+    }
+    pub fn push(&mut self, child: impl Widget + 'static) {
+        self.children.push(Box::new(child));
+    }
+    /// Moves the focus to the child i, or to none.
+    fn focus(&mut self, i: Option<usize>) -> bool {
+        if self.focus == i {
+            return false;
+        }
+        if let Some(old) = self.focus {
+            self.children[old].set_focus(false);
+        }
+        if let Some(new) = i {
+            self.children[new].set_focus(true);
+        }
+        self.focus = i;
+        true
+    }
+}
+impl Default for VStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl Widget for VStack {
+    fn preferred_size(&self) -> (i64, i64) {
+        let (w, h) = self
+            .children
+            .iter()
+            .map(|c| c.preferred_size())
+            .fold((0, 0), |(w, h), (cw, ch)| {
+                (w.max(cw), h + ch + Self::SPACING)
+            });
+        (w + Self::SPACING * 2, h + Self::SPACING)
+    }
+    fn layout(&mut self, rect: Rect) {
+        self.rect = rect;
+        let mut y = rect.y + Self::SPACING;
+        for child in self.children.iter_mut() {
+            let (_, h) = child.preferred_size();
+            let w = (rect.w - Self::SPACING * 2).max(0);
+            child.layout(Rect::new(rect.x + Self::SPACING, y, w, h));
+            y += h + Self::SPACING;
+        }
+    }
+    fn rect(&self) -> Rect {
+        self.rect
+    }
+    fn draw(&self, bitmap: &mut OwnedBitmap) {
+        for child in self.children.iter() {
+            child.draw(bitmap);
+        }
+    }
+    fn handle_event(&mut self, event: &Event) -> bool {
+        match *event {
+            Event::Key(_) => match self.focus {
+                Some(i) => self.children[i].handle_event(event),
+                None => false,
+            },
+            Event::MouseDown { x, y } => {
+                let hit = self.children.iter().position(|c| c.rect().contains(x, y));
+                // ボタンなどを押してもフォーカスは動かさず、何もない所で外す
+                let refocused = match hit {
+                    Some(i) if !self.children[i].accepts_focus() => false,
+                    _ => self.focus(hit),
+                };
+                let handled = hit.is_some_and(|i| self.children[i].handle_event(event));
+                refocused || handled
+            }
+            // 押したまま外で離されることもあるので、全員に配る
+            Event::MouseUp { .. } | Event::MouseMove { .. } => self
+                .children
+                .iter_mut()
+                .fold(false, |redraw, c| c.handle_event(event) || redraw),
+        }
+    }
+    fn accepts_focus(&self) -> bool {
+        self.children.iter().any(|c| c.accepts_focus())
+    }
+    fn set_focus(&mut self, focused: bool) {
+        if !focused {
+            self.focus(None);
+        }
+    }
+}
+
+/// The widgets of a window: turns the WindowEvents into Events for them.
+pub struct Ui {
+    root: Box<dyn Widget>,
+    mapper: KeyMapper,
+    left: bool,
+}
+impl Ui {
+    pub fn new(root: impl Widget + 'static) -> Self {
+        Self {
+            root: Box::new(root),
+            mapper: KeyMapper::new(),
+            left: false,
+        }
+    }
+    /// The size of the contents the widgets want.
+    pub fn preferred_size(&self) -> (i64, i64) {
+        self.root.preferred_size()
+    }
+    /// Lays out the widgets in contents of w x h.
+    pub fn layout(&mut self, w: i64, h: i64) {
+        self.root.layout(Rect::new(0, 0, w, h));
+    }
+    pub fn draw(&self, bitmap: &mut OwnedBitmap) {
+        let (w, h) = (bitmap.width(), bitmap.height());
+        let _ = fill_rect(bitmap, BACKGROUND, 0, 0, w, h);
+        self.root.draw(bitmap);
+    }
+    /// Handles an event of the window, and returns true if the widgets need
+    /// to be redrawn.
+    pub fn handle(&mut self, event: &WindowEvent) -> bool {
+        let event = match *event {
+            WindowEvent::Key(key) => match self.mapper.process(&key) {
+                Some(stroke) => Event::Key(stroke),
+                None => return false,
+            },
+            WindowEvent::MouseDown { x, y, buttons } if buttons.left() && !self.left => {
+                self.left = true;
+                Event::MouseDown { x, y }
+            }
+            WindowEvent::MouseUp { x, y, buttons } if !buttons.left() && self.left => {
+                self.left = false;
+                Event::MouseUp { x, y }
+            }
+            WindowEvent::MouseMove { x, y, buttons } => Event::MouseMove { x, y, buttons },
+            WindowEvent::Resize { width, height } => {
+                self.layout(width, height);
+                return true;
+            }
+            _ => return false,
+        };
+        self.root.handle_event(&event)
+    }
+}
+
+/// Opens a window with the widgets, sized to fit them.
+pub fn open(title: &str, x: i64, y: i64, mut ui: Ui) -> Result<WindowId> {
+    let (w, h) = ui.preferred_size();
+    let rect = Rect::new(
+        x,
+        y,
+        w + wm::BORDER * 2,
+        h + wm::BORDER * 2 + wm::TITLE_HEIGHT,
+    );
+    let (id, events) = wm::with(|wm| -> Result<_> {
+        let id = wm.create_window(title, rect)?;
+        ui.layout(w, h);
+        wm.draw(id, |bitmap| ui.draw(bitmap))?;
+        Ok((id, wm.events(id)?))
+    })??;
+    executor::spawn(async move {
+        while let Some(event) = events.next().await {
+            if event == WindowEvent::Close {
+                let _ = wm::with(|wm| wm.close_window(id));
+                break;
+            }
+            if ui.handle(&event) {
+                let _ = wm::with(|wm| wm.draw(id, |bitmap| ui.draw(bitmap)));
+            }
+        }
+    });
+    Ok(id)
+}
+
+/// The widgets of the demo: a greeting to the name typed in.
+pub fn demo() -> Ui {
+    let name = TextBox::new(16);
+    let greeting = Label::new("").with_color(0x3060c0);
+    let (name_text, greeting_text) = (name.text(), greeting.text());
+    let (clear_name, clear_greeting) = (name.text(), greeting.text());
+    Ui::new(
+        VStack::new()
+            .with(Label::new("Your name:"))
+            .with(name)
+            .with(Button::new("Greet", move || {
+                *greeting_text.borrow_mut() = format!("Hello, {}!", name_text.borrow());
+            }))
+            .with(Button::new("Clear", move || {
+                clear_name.borrow_mut().clear();
+                clear_greeting.borrow_mut().clear();
+            }))
+            .with(greeting),
+    )
+}
@@ -0,0 +1,661 @@
+//! The protocol between the window server and its client apps.
+//!
+//! A client asks for a surface, draws into the pixel buffer shared with the
+//! server, and commits the damaged rects; the server sends input, focus and
+//! resize events back. Messages are encoded into bytes so that they can be
+//! carried by any IPC transport: `[opcode: u8][payload length: u16 LE][payload]`,
+//! with all integers in little endian. Channel is the in-kernel transport
+//! until user space processes have one of their own.
+//!
+//! Server is the server side on a WindowManager: each surface is a window,
+//! whose id is that of the surface. serve() runs it on the window manager of
+//! the screen; the apps of `win open` are its clients.
+
+use crate::graphics::blit;
+use crate::graphics::Bitmap;
+use crate::graphics::Rect;
+use crate::input::KeyEvent;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::time;
+use crate::wm;
+use crate::wm::EventQueue;
+use crate::wm::WindowEvent;
+use crate::wm::WindowId;
+use crate::wm::WindowManager;
+use crate::wm::BORDER;
+use crate::wm::TITLE_HEIGHT;
+use alloc::collections::BTreeMap;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::cell::UnsafeCell;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering;
+use core::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SurfaceId(pub u32);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ShmId(pub u32);
+
+const MAX_DAMAGE_RECTS: usize = 64;
+const MAX_TITLE_LEN: usize = 256;
+const MAX_SURFACE_SIZE: u32 = 8192;
+
+/// Messages from a client to the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    CreateSurface {
+        width: u32,
+        height: u32,
+    },
+    /// The areas of the shared buffer that have been redrawn.
+    Commit {
+        surface: SurfaceId,
+        damage: Vec<Rect>,
+    },
+    SetTitle {
+        surface: SurfaceId,
+        title: String,
+    },
+    DestroySurface {
+        surface: SurfaceId,
+    },
+}
+
+/// Messages from the server to a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The reply to CreateSurface. Draw into `shm`.
+    SurfaceCreated {
+        surface: SurfaceId,
+        shm: ShmId,
+        width: u32,
+        height: u32,
+    },
+    Key {
+        surface: SurfaceId,
+        key: KeyEvent,
+    },
+    /// The pointer position is relative to the surface.
+    Pointer {
+        surface: SurfaceId,
+        x: i32,
+        y: i32,
+        buttons: u8,
+        wheel: i32,
+    },
+    Focus {
+        surface: SurfaceId,
+        focused: bool,
+    },
+    /// The surface has a new buffer. Redraw everything into `shm`.
+    Resize {
+        surface: SurfaceId,
+        shm: ShmId,
+        width: u32,
+        height: u32,
+    },
+    /// The user asked to close the surface.
+    Close {
+        surface: SurfaceId,
+    },
+}
+
+mod opcode {
+    pub const CREATE_SURFACE: u8 = 0x01;
+    pub const COMMIT: u8 = 0x02;
+    pub const SET_TITLE: u8 = 0x03;
+    pub const DESTROY_SURFACE: u8 = 0x04;
+    pub const SURFACE_CREATED: u8 = 0x81;
+    pub const KEY: u8 = 0x82;
+    pub const POINTER: u8 = 0x83;
+    pub const FOCUS: u8 = 0x84;
+    pub const RESIZE: u8 = 0x85;
+    pub const CLOSE: u8 = 0x86;
+}
+
+struct Writer {
+    buf: Vec<u8>,
+}
+impl Writer {
+    fn new(opcode: u8) -> Self {
+        Self {
+            buf: vec![opcode, 0, 0],
+        }
+    }
+    fn u8(&mut self, v: u8) -> &mut Self {
+        self.buf.push(v);
+        self
+    }
+    fn u16(&mut self, v: u16) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+    fn u32(&mut self, v: u32) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+    fn i32(&mut self, v: i32) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+    fn bytes(&mut self, v: &[u8]) -> &mut Self {
+        self.buf.extend_from_slice(v);
+        self
+    }
+    fn finish(&mut self) -> Result<Vec<u8>> {
+        let len: u16 = (self.buf.len() - 3)
+            .try_into()
+            .or(Err("Message is too large"))?;
+        self.buf[1..3].copy_from_slice(&len.to_le_bytes());
+        Ok(core::mem::take(&mut self.buf))
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+}
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.data.len() < n {
+            return Err("Message is truncated");
+        }
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(head)
+    }
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    fn surface(&mut self) -> Result<SurfaceId> {
+        self.u32().map(SurfaceId)
+    }
+    fn shm(&mut self) -> Result<ShmId> {
+        self.u32().map(ShmId)
+    }
+    fn size(&mut self) -> Result<(u32, u32)> {
+        let (w, h) = (self.u32()?, self.u32()?);
+        if w == 0 || h == 0 || w > MAX_SURFACE_SIZE || h > MAX_SURFACE_SIZE {
+            return Err("Invalid surface size");
+        }
+        Ok((w, h))
+    }
+    fn end(&self) -> Result<()> {
+        if self.data.is_empty() {
+            Ok(())
+        } else {
+            Err("Trailing bytes in a message")
+        }
+    }
+}
+
+/// Splits the first message off `data`. Returns the opcode, the payload and
+/// the number of bytes consumed.
+fn split_message(data: &[u8]) -> Result<(u8, &[u8], usize)> {
+    let mut r = Reader { data };
+    let opcode = r.u8()?;
+    let len = r.u16()? as usize;
+    let payload = r.take(len)?;
+    Ok((opcode, payload, 3 + len))
+}
+
+impl Request {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        match self {
+            Request::CreateSurface { width, height } => Writer::new(opcode::CREATE_SURFACE)
+                .u32(*width)
+                .u32(*height)
+                .finish(),
+            Request::Commit { surface, damage } => {
+                if damage.len() > MAX_DAMAGE_RECTS {
+                    return Err("Too many damage rects");
+                }
+                let mut w = Writer::new(opcode::COMMIT);
+                w.u32(surface.0).u16(damage.len() as u16);
+                for r in damage {
+                    w.i32(r.x as i32)
+                        .i32(r.y as i32)
+                        .i32(r.w as i32)
+                        .i32(r.h as i32);
+                }
+                w.finish()
+            }
+            Request::SetTitle { surface, title } => {
+                if title.len() > MAX_TITLE_LEN {
+                    return Err("Title is too long");
+                }
+                Writer::new(opcode::SET_TITLE)
+                    .u32(surface.0)
+                    .bytes(title.as_bytes())
+                    .finish()
+            }
+            Request::DestroySurface { surface } => {
+                Writer::new(opcode::DESTROY_SURFACE).u32(surface.0).finish()
+            }
+        }
+    }
+    /// Decodes the first message in data and returns it with its size.
+    pub fn decode(data: &[u8]) -> Result<(Self, usize)> {
+        let (op, payload, consumed) = split_message(data)?;
+        let mut r = Reader { data: payload };
+        let req = match op {
+            opcode::CREATE_SURFACE => {
+                let (width, height) = r.size()?;
+                Request::CreateSurface { width, height }
+            }
+            opcode::COMMIT => {
+                let surface = r.surface()?;
+                let n = r.u16()? as usize;
+                if n > MAX_DAMAGE_RECTS {
+                    return Err("Too many damage rects");
+                }
+                let mut damage = Vec::with_capacity(n);
+                for _ in 0..n {
+                    let (x, y, w, h) = (r.i32()?, r.i32()?, r.i32()?, r.i32()?);
+                    damage.push(Rect::new(x as i64, y as i64, w as i64, h as i64));
+                }
+                Request::Commit { surface, damage }
+            }
+            opcode::SET_TITLE => {
+                let surface = r.surface()?;
+                let title = r.take(r.data.len())?;
+                if title.len() > MAX_TITLE_LEN {
+                    return Err("Title is too long");
+                }
+                let title = core::str::from_utf8(title).or(Err("Title is not UTF-8"))?;
+                Request::SetTitle {
+                    surface,
+                    title: String::from(title),
+                }
+            }
+            opcode::DESTROY_SURFACE => Request::DestroySurface {
+                surface: r.surface()?,
+            },
+            _ => return Err("Unknown request"),
+        };
+        r.end()?;
+        Ok((req, consumed))
+    }
+}
+
+impl Event {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        match self {
+            Event::SurfaceCreated {
+                surface,
+                shm,
+                width,
+                height,
+            } => Writer::new(opcode::SURFACE_CREATED)
+                .u32(surface.0)
+                .u32(shm.0)
+                .u32(*width)
+                .u32(*height)
+                .finish(),
+            Event::Key { surface, key } => Writer::new(opcode::KEY)
+                .u32(surface.0)
+                .u8(key.usage)
+                .u8(key.pressed as u8)
+                .finish(),
+            Event::Pointer {
+                surface,
+                x,
+                y,
+                buttons,
+                wheel,
+            } => Writer::new(opcode::POINTER)
+                .u32(surface.0)
+                .i32(*x)
+                .i32(*y)
+                .u8(*buttons)
+                .i32(*wheel)
+                .finish(),
+            Event::Focus { surface, focused } => Writer::new(opcode::FOCUS)
+                .u32(surface.0)
+                .u8(*focused as u8)
+                .finish(),
+            Event::Resize {
+                surface,
+                shm,
+                width,
+                height,
+            } => Writer::new(opcode::RESIZE)
+                .u32(surface.0)
+                .u32(shm.0)
+                .u32(*width)
+                .u32(*height)
+                .finish(),
+            Event::Close { surface } => Writer::new(opcode::CLOSE).u32(surface.0).finish(),
+        }
+    }
+    /// Decodes the first message in data and returns it with its size.
+    pub fn decode(data: &[u8]) -> Result<(Self, usize)> {
+        let (op, payload, consumed) = split_message(data)?;
+        let mut r = Reader { data: payload };
+        let event = match op {
+            opcode::SURFACE_CREATED => {
+                let (surface, shm) = (r.surface()?, r.shm()?);
+                let (width, height) = r.size()?;
+                Event::SurfaceCreated {
+                    surface,
+                    shm,
+                    width,
+                    height,
+                }
+            }
+            opcode::KEY => Event::Key {
+                surface: r.surface()?,
+                key: KeyEvent {
+                    usage: r.u8()?,
+                    pressed: r.u8()? != 0,
+                },
+            },
+            opcode::POINTER => Event::Pointer {
+                surface: r.surface()?,
+                x: r.i32()?,
+                y: r.i32()?,
+                buttons: r.u8()?,
+                wheel: r.i32()?,
+            },
+            opcode::FOCUS => Event::Focus {
+                surface: r.surface()?,
+                focused: r.u8()? != 0,
+            },
+            opcode::RESIZE => {
+                let (surface, shm) = (r.surface()?, r.shm()?);
+                let (width, height) = r.size()?;
+                Event::Resize {
+                    surface,
+                    shm,
+                    width,
+                    height,
+                }
+            }
+            opcode::CLOSE => Event::Close {
+                surface: r.surface()?,
+            },
+            _ => return Err("Unknown event"),
+        };
+        r.end()?;
+        Ok((event, consumed))
+    }
+}
+
+/// A pixel buffer shared by a client and the server.
+pub struct SharedPixels {
+    pixels: UnsafeCell<Vec<u32>>,
+    width: i64,
+    height: i64,
+}
+// SAFETY: the client only draws and the server only reads the committed
+// areas, in the same way as a framebuffer shared with the display hardware.
+unsafe impl Sync for SharedPixels {}
+unsafe impl Send for SharedPixels {}
+impl SharedPixels {
+    fn new(width: u32, height: u32) -> Self {
+        Self {
+            pixels: UnsafeCell::new(vec![0; width as usize * height as usize]),
+            width: width as i64,
+            height: height as i64,
+        }
+    }
+    /// A view to draw on or to copy from.
+    pub fn bitmap(&self) -> SharedBitmap {
+        SharedBitmap {
+            // SAFETY: the Vec is never resized, so the pointer stays valid
+            buf: unsafe { (*self.pixels.get()).as_mut_ptr() as *mut u8 },
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+/// A Bitmap backed by SharedPixels. Valid while the SharedPixels is alive.
+#[derive(Clone, Copy)]
+pub struct SharedBitmap {
+    buf: *mut u8,
+    width: i64,
+    height: i64,
+}
+impl Bitmap for SharedBitmap {
+    fn bytes_per_pixel(&self) -> i64 {
+        4
+    }
+    fn pixels_per_scan_line(&self) -> i64 {
+        self.width
+    }
+    fn width(&self) -> i64 {
+        self.width
+    }
+    fn height(&self) -> i64 {
+        self.height
+    }
+    fn buf_mut(&mut self) -> *mut u8 {
+        self.buf
+    }
+}
+
+static SHM: Mutex<BTreeMap<ShmId, Arc<SharedPixels>>> = Mutex::new(BTreeMap::new());
+static NEXT_SHM_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Allocates a shared buffer. The server calls this for CreateSurface and Resize.
+pub fn create_shm(width: u32, height: u32) -> (ShmId, Arc<SharedPixels>) {
+    let id = ShmId(NEXT_SHM_ID.fetch_add(1, Ordering::SeqCst));
+    let pixels = Arc::new(SharedPixels::new(width, height));
+    SHM.lock().insert(id, pixels.clone());
+    (id, pixels)
+}
+
+/// Looks up a shared buffer by the id received in an event.
+pub fn map_shm(id: ShmId) -> Option<Arc<SharedPixels>> {
+    SHM.lock().get(&id).cloned()
+}
+
+/// Frees a shared buffer. It stays alive until all the mappings are dropped.
+pub fn release_shm(id: ShmId) {
+    SHM.lock().remove(&id);
+}
+
+type Queue = Arc<Mutex<VecDeque<Vec<u8>>>>;
+
+/// One end of a connection between a client and the server.
+pub struct Channel {
+    tx: Queue,
+    rx: Queue,
+}
+impl Channel {
+    /// Creates a connected pair of (client, server) ends.
+    pub fn pair() -> (Channel, Channel) {
+        let a: Queue = Arc::new(Mutex::new(VecDeque::new()));
+        let b: Queue = Arc::new(Mutex::new(VecDeque::new()));
+        (
+            Channel {
+                tx: a.clone(),
+                rx: b.clone(),
+            },
+            Channel { tx: b, rx: a },
+        )
+    }
+    fn send(&self, msg: Vec<u8>) {
+        self.tx.lock().push_back(msg);
+    }
+    fn recv(&self) -> Option<Vec<u8>> {
+        self.rx.lock().pop_front()
+    }
+    pub fn send_request(&self, req: &Request) -> Result<()> {
+        self.send(req.encode()?);
+        Ok(())
+    }
+    pub fn send_event(&self, event: &Event) -> Result<()> {
+        self.send(event.encode()?);
+        Ok(())
+    }
+    /// Returns None if nothing has arrived yet.
+    pub fn recv_request(&self) -> Option<Result<Request>> {
+        self.recv()
+            .map(|msg| Request::decode(&msg).map(|(req, _)| req))
+    }
+    /// Returns None if nothing has arrived yet.
+    pub fn recv_event(&self) -> Option<Result<Event>> {
+        self.recv().map(|msg| Event::decode(&msg).map(|(e, _)| e))
+    }
+    /// Whether the other end has been dropped.
+    pub fn is_closed(&self) -> bool {
+        Arc::strong_count(&self.rx) == 1
+    }
+}
+
+struct Surface {
+    id: SurfaceId,
+    window: WindowId,
+    shm: ShmId,
+    pixels: Arc<SharedPixels>,
+    events: Arc<EventQueue>,
+}
+
+/// The server side of a connection, which carries out the requests of the
+/// client on a window manager and sends it the events of its windows.
+pub struct Server {
+    channel: Channel,
+    surfaces: Vec<Surface>,
+}
+impl Server {
+    pub fn new(channel: Channel) -> Self {
+        Self {
+            channel,
+            surfaces: Vec::new(),
+        }
+    }
+    fn surface(&self, id: SurfaceId) -> Result<&Surface> {
+        self.surfaces
+            .iter()
+            .find(|s| s.id == id)
+            .ok_or("No such surface")
+    }
+    /// Handles the requests and the events that have arrived so far.
+    /// Returns false once the client has hung up and its windows are closed.
+    pub fn handle(&mut self, wm: &mut WindowManager) -> bool {
+        while let Some(request) = self.channel.recv_request() {
+            // 壊れた要求や実行できない要求は捨て、接続は続ける
+            if let Ok(request) = request {
+                let _ = self.handle_request(wm, request);
+            }
+        }
+        for i in 0..self.surfaces.len() {
+            while let Some(event) = self.surfaces[i].events.try_next() {
+                let _ = self.forward_event(i, event);
+            }
+        }
+        if !self.channel.is_closed() {
+            return true;
+        }
+        for s in self.surfaces.drain(..) {
+            release_shm(s.shm);
+            let _ = wm.close_window(s.window);
+        }
+        false
+    }
+    fn handle_request(&mut self, wm: &mut WindowManager, request: Request) -> Result<()> {
+        match request {
+            Request::CreateSurface { width, height } => {
+                // 重ならないよう、開いている数だけずらして置く
+                let n = wm.windows().len() as i64;
+                let rect = Rect::new(
+                    32 + n * 24,
+                    16 + n * 24,
+                    width as i64 + BORDER * 2,
+                    height as i64 + BORDER * 2 + TITLE_HEIGHT,
+                );
+                let window = wm.create_window("", rect)?;
+                let (shm, pixels) = create_shm(width, height);
+                let id = SurfaceId(window.0);
+                self.surfaces.push(Surface {
+                    id,
+                    window,
+                    shm,
+                    pixels,
+                    events: wm.events(window)?,
+                });
+                self.channel.send_event(&Event::SurfaceCreated {
+                    surface: id,
+                    shm,
+                    width,
+                    height,
+                })
+            }
+            Request::Commit { surface, damage } => {
+                let s = self.surface(surface)?;
+                let mut src = s.pixels.bitmap();
+                wm.draw(s.window, |contents| {
+                    for r in damage {
+                        let _ = blit(&mut src, r, contents, r.x, r.y);
+                    }
+                })
+            }
+            Request::SetTitle { surface, title } => {
+                wm.set_title(self.surface(surface)?.window, &title)
+            }
+            Request::DestroySurface { surface } => {
+                let i = self
+                    .surfaces
+                    .iter()
+                    .position(|s| s.id == surface)
+                    .ok_or("No such surface")?;
+                let s = self.surfaces.remove(i);
+                release_shm(s.shm);
+                wm.close_window(s.window)
+            }
+        }
+    }
+    fn forward_event(&mut self, i: usize, event: WindowEvent) -> Result<()> {
+        let s = &mut self.surfaces[i];
+        let surface = s.id;
+        let event = match event {
+            WindowEvent::Key(key) => Event::Key { surface, key },
+            WindowEvent::MouseMove { x, y, buttons }
+            | WindowEvent::MouseDown { x, y, buttons }
+            | WindowEvent::MouseUp { x, y, buttons } => Event::Pointer {
+                surface,
+                x: x as i32,
+                y: y as i32,
+                buttons: buttons.bits(),
+                wheel: 0,
+            },
+            WindowEvent::Resize { width, height } => {
+                let (width, height) = (width as u32, height as u32);
+                let (shm, pixels) = create_shm(width, height);
+                release_shm(core::mem::replace(&mut s.shm, shm));
+                s.pixels = pixels;
+                Event::Resize {
+                    surface,
+                    shm,
+                    width,
+                    height,
+                }
+            }
+            WindowEvent::Close => Event::Close { surface },
+        };
+        self.channel.send_event(&event)
+    }
+}
+
+/// Serves the client on the window manager of the screen until it hangs up.
+pub async fn serve(mut server: Server) {
+    while wm::with(|wm| server.handle(wm)).unwrap_or(false) {
+        time::sleep(Duration::from_millis(10)).await;
+    }
+}
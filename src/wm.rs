@@ -0,0 +1,845 @@
+//! The window manager: overlapping windows on the desktop.
+//!
+//! Each window draws into its own OwnedBitmap. The windows are kept in the
+//! z-order, and the areas that have changed are recomposited from the
+//! bottom to the top into a BackBuffer, which is then copied to the screen.
+//! Windows have a border and a title bar with a close button, and can be
+//! dragged by the title bar. A left click focuses and raises the window
+//! under the pointer.
+//!
+//! Input is delivered to the windows as WindowEvents through their own
+//! queues: the keys to the focused window, and the pointer to the window
+//! under it, so that an app is a loop over `events.next().await`. Built
+//! with the `gui` feature.
+
+use crate::bmp;
+use crate::chainload;
+use crate::cursor;
+use crate::executor;
+use crate::graphics::blit;
+use crate::graphics::draw_font_fg;
+use crate::graphics::draw_str_fg;
+use crate::graphics::fill_rect;
+use crate::graphics::BackBuffer;
+use crate::graphics::Bitmap;
+use crate::graphics::OwnedBitmap;
+use crate::graphics::Rect;
+use crate::input::KeyEvent;
+use crate::mouse::MouseButtons;
+use crate::mutex::Mutex;
+use crate::result::Result;
+use crate::time;
+use crate::uefi;
+use crate::widget;
+use crate::window_protocol;
+use crate::window_protocol::Channel;
+use crate::window_protocol::Event;
+use crate::window_protocol::Request;
+use crate::window_protocol::Server;
+use crate::window_protocol::ShmId;
+use crate::window_protocol::SurfaceId;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::poll_fn;
+use core::task::Poll;
+use core::task::Waker;
+use core::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WindowId(pub u32);
+impl fmt::Display for WindowId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// The width of the border around a window.
+pub const BORDER: i64 = 2;
+/// The height of the title bar, below the top border.
+pub const TITLE_HEIGHT: i64 = 20;
+const CLOSE_BUTTON_SIZE: i64 = 16;
+/// How much of the title bar is kept on the desktop while dragging.
+const DRAG_MARGIN: i64 = 32;
+
+struct Theme {
+    border: u32,
+    title_bar: u32,
+    title: u32,
+}
+const FOCUSED: Theme = Theme {
+    border: 0x80a0ff,
+    title_bar: 0x3060c0,
+    title: 0xffffff,
+};
+const UNFOCUSED: Theme = Theme {
+    border: 0x404040,
+    title_bar: 0x606060,
+    title: 0xc0c0c0,
+};
+const CLOSE_BUTTON: u32 = 0xc04040;
+
+/// An input to a window. The positions are relative to its contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowEvent {
+    Key(KeyEvent),
+    /// The pointer moved over the contents, or anywhere while a button
+    /// pressed on them is held.
+    MouseMove {
+        x: i64,
+        y: i64,
+        buttons: MouseButtons,
+    },
+    /// Buttons were pressed. buttons is the new state.
+    MouseDown {
+        x: i64,
+        y: i64,
+        buttons: MouseButtons,
+    },
+    /// Buttons were released. buttons is the new state.
+    MouseUp {
+        x: i64,
+        y: i64,
+        buttons: MouseButtons,
+    },
+    /// The contents have been resized, and need to be redrawn.
+    Resize {
+        width: i64,
+        height: i64,
+    },
+    /// The close button was clicked. The window stays open until the app
+    /// closes it.
+    Close,
+}
+
+/// Events are dropped while this many are waiting.
+const MAX_QUEUED_EVENTS: usize = 256;
+
+struct EventQueueInner {
+    events: VecDeque<WindowEvent>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+/// The events of a window, shared by the window manager and the app.
+pub struct EventQueue {
+    inner: Mutex<EventQueueInner>,
+}
+impl EventQueue {
+    fn new() -> Self {
+        Self {
+            inner: Mutex::new(EventQueueInner {
+                events: VecDeque::new(),
+                waker: None,
+                closed: false,
+            }),
+        }
+    }
+    fn push(&self, event: WindowEvent) {
+        let mut inner = self.inner.lock();
+        if inner.closed || inner.events.len() >= MAX_QUEUED_EVENTS {
+            return;
+        }
+        inner.events.push_back(event);
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+    }
+    fn close(&self) {
+        let mut inner = self.inner.lock();
+        inner.closed = true;
+        if let Some(waker) = inner.waker.take() {
+            waker.wake();
+        }
+    }
+    pub fn try_next(&self) -> Option<WindowEvent> {
+        self.inner.lock().events.pop_front()
+    }
+    /// Waits for the next event. Returns None once the window is closed and
+    /// the remaining events have been taken.
+    pub async fn next(&self) -> Option<WindowEvent> {
+        poll_fn(|cx| {
+            let mut inner = self.inner.lock();
+            if let Some(event) = inner.events.pop_front() {
+                Poll::Ready(Some(event))
+            } else if inner.closed {
+                Poll::Ready(None)
+            } else {
+                inner.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+/// The size of the contents of a window whose frame is w x h.
+fn content_size(w: i64, h: i64) -> Result<(i64, i64)> {
+    let content_w = w - BORDER * 2;
+    let content_h = h - BORDER * 2 - TITLE_HEIGHT;
+    if content_w < CLOSE_BUTTON_SIZE * 2 || content_h <= 0 {
+        return Err("Window is too small");
+    }
+    Ok((content_w, content_h))
+}
+
+pub struct Window {
+    id: WindowId,
+    title: String,
+    /// The position and the size on the desktop, including the decorations.
+    rect: Rect,
+    /// The border and the title bar, of the size of rect.
+    decoration: OwnedBitmap,
+    contents: OwnedBitmap,
+    events: Arc<EventQueue>,
+}
+impl Window {
+    pub fn id(&self) -> WindowId {
+        self.id
+    }
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+    /// The area of the contents on the desktop.
+    pub fn content_rect(&self) -> Rect {
+        Rect::new(
+            self.rect.x + BORDER,
+            self.rect.y + BORDER + TITLE_HEIGHT,
+            self.contents.width(),
+            self.contents.height(),
+        )
+    }
+    pub fn contents(&self) -> &OwnedBitmap {
+        &self.contents
+    }
+    /// The title bar on the desktop, where the window can be dragged.
+    fn title_bar_rect(&self) -> Rect {
+        Rect::new(
+            self.rect.x + BORDER,
+            self.rect.y + BORDER,
+            self.rect.w - BORDER * 2,
+            TITLE_HEIGHT,
+        )
+    }
+    /// The close button on the desktop, at the right end of the title bar.
+    fn close_button_rect(&self) -> Rect {
+        let bar = self.title_bar_rect();
+        let margin = (TITLE_HEIGHT - CLOSE_BUTTON_SIZE) / 2;
+        Rect::new(
+            bar.right() - margin - CLOSE_BUTTON_SIZE,
+            bar.y + margin,
+            CLOSE_BUTTON_SIZE,
+            CLOSE_BUTTON_SIZE,
+        )
+    }
+    fn draw_decoration(&mut self, focused: bool) {
+        let theme = if focused { &FOCUSED } else { &UNFOCUSED };
+        let (w, h) = (self.rect.w, self.rect.h);
+        let button = self.close_button_rect();
+        let (bx, by) = (button.x - self.rect.x, button.y - self.rect.y);
+        let d = &mut self.decoration;
+        let _ = fill_rect(d, theme.border, 0, 0, w, h);
+        let _ = fill_rect(
+            d,
+            theme.title_bar,
+            BORDER,
+            BORDER,
+            w - BORDER * 2,
+            TITLE_HEIGHT,
+        );
+        let _ = fill_rect(d, CLOSE_BUTTON, bx, by, button.w, button.h);
+        draw_font_fg(
+            d,
+            bx + (button.w - 8) / 2,
+            by + (button.h - 16) / 2,
+            0xffffff,
+            'x',
+        );
+        // 閉じるボタンにかからない文字数だけ書く
+        let text_y = BORDER + (TITLE_HEIGHT - 16) / 2;
+        let max_chars = ((bx - BORDER - 8) / 8).max(0) as usize;
+        let title: String = self.title.chars().take(max_chars).collect();
+        draw_str_fg(d, BORDER + 4, text_y, theme.title, &title);
+    }
+}
+
+/// What is drawn beneath the windows.
+pub enum Wallpaper {
+    Solid(u32),
+    /// From the color at the top to the one at the bottom.
+    Gradient(u32, u32),
+    /// Centered on black, and cut if it is larger than the desktop.
+    Image(OwnedBitmap),
+}
+impl Wallpaper {
+    /// Renders the wallpaper for a desktop of w x h.
+    pub fn render(&mut self, w: i64, h: i64) -> OwnedBitmap {
+        match self {
+            Wallpaper::Solid(color) => OwnedBitmap::new(w, h, *color),
+            Wallpaper::Gradient(top, bottom) => {
+                let mut bitmap = OwnedBitmap::new(w, h, *top);
+                for y in 0..h {
+                    let color = mix(*top, *bottom, y, (h - 1).max(1));
+                    let _ = fill_rect(&mut bitmap, color, 0, y, w, 1);
+                }
+                bitmap
+            }
+            Wallpaper::Image(image) => {
+                let mut bitmap = OwnedBitmap::new(w, h, 0x000000);
+                let rect = image.rect();
+                let _ = blit(image, rect, &mut bitmap, (w - rect.w) / 2, (h - rect.h) / 2);
+                bitmap
+            }
+        }
+    }
+}
+
+/// The color at n/d of the way from a to b.
+fn mix(a: u32, b: u32, n: i64, d: i64) -> u32 {
+    [16, 8, 0].iter().fold(0, |color, shift| {
+        let (a, b) = (((a >> shift) & 0xff) as i64, ((b >> shift) & 0xff) as i64);
+        color | (((a + (b - a) * n / d) as u32) << shift)
+    })
+}
+
+/// The window being dragged, and where it was grabbed.
+#[derive(Clone, Copy)]
+struct Drag {
+    id: WindowId,
+    dx: i64,
+    dy: i64,
+}
+
+pub struct WindowManager {
+    background: OwnedBitmap,
+    back: BackBuffer,
+    /// From the bottom to the top.
+    windows: Vec<Window>,
+    focused: Option<WindowId>,
+    next_id: u32,
+    buttons: MouseButtons,
+    /// Where the pointer was last.
+    pointer: Option<(i64, i64)>,
+    drag: Option<Drag>,
+    /// The window that gets the pointer until the buttons pressed on its
+    /// contents are released.
+    grab: Option<WindowId>,
+}
+impl WindowManager {
+    /// A desktop of the size of background, with no windows.
+    pub fn new(background: OwnedBitmap) -> Self {
+        let mut back = BackBuffer::new(background.width(), background.height());
+        back.add_damage_all();
+        Self {
+            background,
+            back,
+            windows: Vec::new(),
+            focused: None,
+            next_id: 1,
+            buttons: MouseButtons::default(),
+            pointer: None,
+            drag: None,
+            grab: None,
+        }
+    }
+    pub fn rect(&self) -> Rect {
+        self.back.rect()
+    }
+    /// Replaces what is drawn beneath the windows.
+    pub fn set_wallpaper(&mut self, mut wallpaper: Wallpaper) {
+        let rect = self.rect();
+        self.background = wallpaper.render(rect.w, rect.h);
+        self.back.add_damage_all();
+    }
+    /// The windows from the bottom to the top.
+    pub fn windows(&self) -> &[Window] {
+        &self.windows
+    }
+    pub fn window(&self, id: WindowId) -> Option<&Window> {
+        self.windows.iter().find(|w| w.id == id)
+    }
+    fn index_of(&self, id: WindowId) -> Result<usize> {
+        self.windows
+            .iter()
+            .position(|w| w.id == id)
+            .ok_or("No such window")
+    }
+    pub fn focused(&self) -> Option<WindowId> {
+        self.focused
+    }
+    /// Opens a window on the top, focused. rect includes the decorations,
+    /// and the contents, which are black, get the rest of it.
+    pub fn create_window(&mut self, title: &str, rect: Rect) -> Result<WindowId> {
+        let (content_w, content_h) = content_size(rect.w, rect.h)?;
+        let id = WindowId(self.next_id);
+        self.next_id += 1;
+        self.windows.push(Window {
+            id,
+            title: title.to_string(),
+            rect,
+            decoration: OwnedBitmap::new(rect.w, rect.h, 0x000000),
+            contents: OwnedBitmap::new(content_w, content_h, 0x000000),
+            events: Arc::new(EventQueue::new()),
+        });
+        self.back.add_damage(rect);
+        self.set_focus(Some(id));
+        Ok(id)
+    }
+    pub fn close_window(&mut self, id: WindowId) -> Result<()> {
+        let window = self.windows.remove(self.index_of(id)?);
+        self.back.add_damage(window.rect);
+        window.events.close();
+        if self.drag.is_some_and(|d| d.id == id) {
+            self.drag = None;
+        }
+        if self.grab == Some(id) {
+            self.grab = None;
+        }
+        if self.focused == Some(id) {
+            self.focused = None;
+            self.set_focus(self.windows.last().map(|w| w.id));
+        }
+        Ok(())
+    }
+    /// Asks the window to close with a Close event. A window whose events
+    /// nobody has taken is closed right away.
+    pub fn request_close(&mut self, id: WindowId) -> Result<()> {
+        let i = self.index_of(id)?;
+        let events = &self.windows[i].events;
+        if Arc::strong_count(events) > 1 {
+            events.push(WindowEvent::Close);
+            Ok(())
+        } else {
+            self.close_window(id)
+        }
+    }
+    pub fn set_title(&mut self, id: WindowId, title: &str) -> Result<()> {
+        let i = self.index_of(id)?;
+        let focused = self.focused == Some(id);
+        let window = &mut self.windows[i];
+        window.title = title.to_string();
+        window.draw_decoration(focused);
+        self.back.add_damage(window.rect);
+        Ok(())
+    }
+    /// The event queue of the window, for the app that runs it.
+    pub fn events(&self, id: WindowId) -> Result<Arc<EventQueue>> {
+        Ok(self.windows[self.index_of(id)?].events.clone())
+    }
+    /// Runs f, which draws the contents of the window.
+    pub fn draw<R>(&mut self, id: WindowId, f: impl FnOnce(&mut OwnedBitmap) -> R) -> Result<R> {
+        let i = self.index_of(id)?;
+        let window = &mut self.windows[i];
+        let result = f(&mut window.contents);
+        self.back.add_damage(window.content_rect());
+        Ok(result)
+    }
+    /// Moves the top-left corner of the window to (x, y).
+    pub fn move_window(&mut self, id: WindowId, x: i64, y: i64) -> Result<()> {
+        let i = self.index_of(id)?;
+        let window = &mut self.windows[i];
+        let old = window.rect;
+        window.rect.x = x;
+        window.rect.y = y;
+        let new = window.rect;
+        self.back.add_damage(old);
+        self.back.add_damage(new);
+        Ok(())
+    }
+    /// Resizes the window to w x h including the decorations. The contents
+    /// keep their top-left part, and the window gets a Resize event.
+    pub fn resize_window(&mut self, id: WindowId, w: i64, h: i64) -> Result<()> {
+        let (content_w, content_h) = content_size(w, h)?;
+        let i = self.index_of(id)?;
+        let focused = self.focused == Some(id);
+        let window = &mut self.windows[i];
+        let old = window.rect;
+        let mut contents = OwnedBitmap::new(content_w, content_h, 0x000000);
+        let src = window.contents.rect();
+        let _ = blit(&mut window.contents, src, &mut contents, 0, 0);
+        window.contents = contents;
+        window.rect.w = w;
+        window.rect.h = h;
+        window.decoration = OwnedBitmap::new(w, h, 0x000000);
+        window.draw_decoration(focused);
+        window.events.push(WindowEvent::Resize {
+            width: content_w,
+            height: content_h,
+        });
+        let new = window.rect;
+        self.back.add_damage(old);
+        self.back.add_damage(new);
+        Ok(())
+    }
+    /// Brings the window to the top.
+    pub fn raise(&mut self, id: WindowId) -> Result<()> {
+        let window = self.windows.remove(self.index_of(id)?);
+        self.back.add_damage(window.rect);
+        self.windows.push(window);
+        Ok(())
+    }
+    pub fn focus(&mut self, id: Option<WindowId>) -> Result<()> {
+        if let Some(id) = id {
+            self.index_of(id)?;
+        }
+        self.set_focus(id);
+        Ok(())
+    }
+    /// Moves the focus, redrawing the decorations of the windows whose
+    /// focus changes. id must be a window or None.
+    fn set_focus(&mut self, id: Option<WindowId>) {
+        let old = self.focused;
+        self.focused = id;
+        for w in self.windows.iter_mut() {
+            if Some(w.id) == id || (Some(w.id) == old && old != id) {
+                w.draw_decoration(Some(w.id) == id);
+                self.back.add_damage(w.rect);
+            }
+        }
+    }
+    /// The topmost window at (x, y) on the desktop.
+    pub fn window_at(&self, x: i64, y: i64) -> Option<WindowId> {
+        if !self.rect().contains(x, y) {
+            return None;
+        }
+        self.windows
+            .iter()
+            .rev()
+            .find(|w| w.rect.contains(x, y))
+            .map(|w| w.id)
+    }
+    /// Passes the key to the focused window. Returns false if there is none.
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        let Some(window) = self.focused.and_then(|id| self.window(id)) else {
+            return false;
+        };
+        window.events.push(WindowEvent::Key(key));
+        true
+    }
+    /// Handles the pointer at (x, y) on the desktop.
+    ///
+    /// Pressing the left button focuses and raises the window under it (or
+    /// unfocuses all of them if there is none), asks the window to close on
+    /// its close button, and starts dragging it on its title bar. On the
+    /// contents, the pointer is passed to the window instead.
+    pub fn handle_mouse(&mut self, x: i64, y: i64, buttons: MouseButtons) {
+        let old_buttons = self.buttons;
+        let pressed = buttons.left() && !old_buttons.left();
+        let moved = self.pointer != Some((x, y));
+        self.pointer = Some((x, y));
+        self.buttons = buttons;
+        if !buttons.left() {
+            self.drag = None;
+        }
+        if self.drag.is_none() {
+            self.dispatch_mouse(x, y, old_buttons, moved);
+        }
+        if let Some(drag) = self.drag {
+            let desktop = self.rect();
+            let w = self.window(drag.id).map_or(0, |w| w.rect.w);
+            // タイトルバーを掴み直せるよう、デスクトップからはみ出しすぎないようにする
+            let nx = (x - drag.dx).clamp(DRAG_MARGIN - w, desktop.w - DRAG_MARGIN);
+            let ny = (y - drag.dy).clamp(0, desktop.h - BORDER - TITLE_HEIGHT);
+            let _ = self.move_window(drag.id, nx, ny);
+            return;
+        }
+        if !pressed {
+            return;
+        }
+        let Some(id) = self.window_at(x, y) else {
+            self.set_focus(None);
+            return;
+        };
+        let _ = self.raise(id);
+        self.set_focus(Some(id));
+        let Some(w) = self.window(id) else {
+            return;
+        };
+        if w.close_button_rect().contains(x, y) {
+            let _ = self.request_close(id);
+        } else if w.title_bar_rect().contains(x, y) {
+            self.drag = Some(Drag {
+                id,
+                dx: x - w.rect.x,
+                dy: y - w.rect.y,
+            });
+        }
+    }
+    /// Sends the pointer to the window grabbing it, or to the window whose
+    /// contents are under it.
+    fn dispatch_mouse(&mut self, x: i64, y: i64, old_buttons: MouseButtons, moved: bool) {
+        let buttons = self.buttons;
+        let target = self.grab.or_else(|| {
+            let w = self.window(self.window_at(x, y)?)?;
+            w.content_rect().contains(x, y).then_some(w.id)
+        });
+        let Some(w) = target.and_then(|id| self.window(id)) else {
+            return;
+        };
+        let content = w.content_rect();
+        let (x, y) = (x - content.x, y - content.y);
+        if moved {
+            w.events.push(WindowEvent::MouseMove { x, y, buttons });
+        }
+        if buttons.bits() & !old_buttons.bits() != 0 {
+            w.events.push(WindowEvent::MouseDown { x, y, buttons });
+        }
+        if old_buttons.bits() & !buttons.bits() != 0 {
+            w.events.push(WindowEvent::MouseUp { x, y, buttons });
+        }
+        self.grab = (buttons.bits() != 0).then_some(w.id);
+    }
+    /// Redraws the damaged areas of the back buffer.
+    fn compose(&mut self) {
+        let damage: Vec<Rect> = self.back.damage().rects().to_vec();
+        for r in damage {
+            let _ = blit(&mut self.background, r, &mut self.back, r.x, r.y);
+            for w in self.windows.iter_mut() {
+                let content_rect = w.content_rect();
+                let layers = [(w.rect, &mut w.decoration), (content_rect, &mut w.contents)];
+                for (rect, bitmap) in layers {
+                    let Some(visible) = rect.intersection(&r) else {
+                        continue;
+                    };
+                    let src =
+                        Rect::new(visible.x - rect.x, visible.y - rect.y, visible.w, visible.h);
+                    let _ = blit(bitmap, src, &mut self.back, visible.x, visible.y);
+                }
+            }
+        }
+    }
+    /// The bounding box of the areas to be redrawn, if any.
+    pub fn damage_bounds(&self) -> Option<Rect> {
+        self.back
+            .damage()
+            .rects()
+            .iter()
+            .copied()
+            .reduce(|a, b| a.union(&b))
+    }
+    /// Composites the changes and copies them to dst, with the desktop
+    /// placed at (x, y).
+    pub fn present<T: Bitmap>(&mut self, dst: &mut T, x: i64, y: i64) -> Result<()> {
+        if self.back.damage().is_empty() {
+            return Ok(());
+        }
+        self.compose();
+        self.back.flush_at(dst, x, y)
+    }
+}
+
+/// The window manager of the screen, and where its desktop is on it.
+static WM: Mutex<Option<(WindowManager, Rect)>> = Mutex::new(None);
+
+/// Starts managing the area of the screen, whose current contents become
+/// the background.
+pub fn init<T: Bitmap>(screen: &mut T, area: Rect) {
+    let mut background = OwnedBitmap::new(area.w, area.h, 0x000000);
+    let _ = blit(screen, area, &mut background, 0, 0);
+    *WM.lock() = Some((WindowManager::new(background), area));
+}
+
+/// Runs f with the window manager of the screen.
+pub fn with<R>(f: impl FnOnce(&mut WindowManager) -> R) -> Result<R> {
+    let mut wm = WM.lock();
+    let (wm, _) = wm.as_mut().ok_or("Window manager is not running")?;
+    Ok(f(wm))
+}
+
+/// Copies the changes to the screen, keeping the mouse cursor on the top.
+pub fn present<T: Bitmap>(screen: &mut T) -> Result<()> {
+    let mut wm = WM.lock();
+    let (wm, area) = wm.as_mut().ok_or("Window manager is not running")?;
+    let Some(bounds) = wm.damage_bounds() else {
+        return Ok(());
+    };
+    let bounds = Rect::new(bounds.x + area.x, bounds.y + area.y, bounds.w, bounds.h);
+    cursor::draw_around(screen, bounds, |screen| wm.present(screen, area.x, area.y))
+}
+
+/// Passes a mouse event at (x, y) on the screen to the window manager.
+pub fn handle_mouse(x: i64, y: i64, buttons: MouseButtons) {
+    let mut wm = WM.lock();
+    if let Some((wm, area)) = wm.as_mut() {
+        wm.handle_mouse(x - area.x, y - area.y, buttons);
+    }
+}
+
+/// Passes a key to the focused window. Returns false if there is none, and
+/// the key should go to the console instead.
+pub fn handle_key(key: &KeyEvent) -> bool {
+    WM.lock()
+        .as_mut()
+        .is_some_and(|(wm, _)| wm.handle_key(*key))
+}
+
+/// Parses a color written as RRGGBB, optionally after # or 0x.
+fn parse_color(s: &str) -> Result<u32> {
+    let hex = s.trim_start_matches('#').trim_start_matches("0x");
+    match u32::from_str_radix(hex, 16) {
+        Ok(color) if hex.len() == 6 => Ok(color),
+        _ => Err("wallpaper: colors are written as RRGGBB"),
+    }
+}
+
+/// The `wallpaper` command: changes what is drawn beneath the windows.
+pub fn cmd_wallpaper(args: &[&str], _out: &mut dyn fmt::Write) -> Result<()> {
+    let wallpaper = match args {
+        ["solid", color] => Wallpaper::Solid(parse_color(color)?),
+        ["gradient", top, bottom] => Wallpaper::Gradient(parse_color(top)?, parse_color(bottom)?),
+        ["bmp", path] => {
+            let efi_system_table = uefi::system_table().ok_or("EFI context is not initialized")?;
+            let data =
+                chainload::with_firmware_interrupts(|| uefi::read_file(efi_system_table, path))?;
+            Wallpaper::Image(bmp::decode(&data)?)
+        }
+        _ => return Err("usage: wallpaper [solid RRGGBB | gradient TOP BOTTOM | bmp PATH]"),
+    };
+    with(|wm| wm.set_wallpaper(wallpaper))
+}
+
+/// Draws the sample contents of a window: its title, a pattern and the last
+/// event.
+fn draw_sample<T: Bitmap>(bitmap: &mut T, id: WindowId, title: &str, event: &str) {
+    let colors = [0x3060a0, 0x30a060, 0xa06030, 0x8040a0];
+    let color = colors[id.0 as usize % colors.len()];
+    let (w, h) = (bitmap.width(), bitmap.height());
+    let _ = fill_rect(bitmap, color, 0, 0, w, h);
+    for x in (0..w).step_by(16) {
+        let _ = fill_rect(bitmap, color ^ 0x202020, x, 0, 8, h);
+    }
+    draw_str_fg(bitmap, 8, 8, 0xffffff, title);
+    let max_chars = ((w - 16) / 8).max(0) as usize;
+    let event: String = event.chars().take(max_chars).collect();
+    draw_str_fg(bitmap, 8, 32, 0xffffff, &event);
+}
+
+/// The app of the windows opened by `win open`, a client of the window
+/// protocol: shows the events it gets, and closes the window when asked to.
+async fn run_sample(channel: Channel, surface: SurfaceId, shm: ShmId, title: String) {
+    let Some(mut pixels) = window_protocol::map_shm(shm) else {
+        return;
+    };
+    let id = WindowId(surface.0);
+    let mut text = String::new();
+    loop {
+        let mut bitmap = pixels.bitmap();
+        draw_sample(&mut bitmap, id, &title, &text);
+        let damage = vec![Rect::new(0, 0, bitmap.width(), bitmap.height())];
+        if channel
+            .send_request(&Request::Commit { surface, damage })
+            .is_err()
+        {
+            return;
+        }
+        let event = loop {
+            if let Some(event) = channel.recv_event() {
+                break event;
+            }
+            if channel.is_closed() {
+                return;
+            }
+            time::sleep(Duration::from_millis(10)).await;
+        };
+        match event {
+            Ok(Event::Close { .. }) => {
+                let _ = channel.send_request(&Request::DestroySurface { surface });
+                return;
+            }
+            Ok(Event::Resize { shm, .. }) => match window_protocol::map_shm(shm) {
+                Some(new_pixels) => pixels = new_pixels,
+                None => return,
+            },
+            Ok(event) => text = format!("{event:?}"),
+            Err(_) => {}
+        }
+    }
+}
+
+/// Opens a window of the sample app through the window protocol, and
+/// returns its id.
+fn open_sample(title: String) -> Result<WindowId> {
+    let (client, server) = Channel::pair();
+    let mut server = Server::new(server);
+    client.send_request(&Request::CreateSurface {
+        width: 236,
+        height: 136,
+    })?;
+    with(|wm| server.handle(wm))?;
+    let Some(Ok(Event::SurfaceCreated { surface, shm, .. })) = client.recv_event() else {
+        return Err("win: failed to open a window");
+    };
+    client.send_request(&Request::SetTitle {
+        surface,
+        title: title.clone(),
+    })?;
+    executor::spawn(window_protocol::serve(server));
+    executor::spawn(run_sample(client, surface, shm, title));
+    Ok(WindowId(surface.0))
+}
+
+/// The `win` command: lists, opens, moves, resizes, raises and closes the
+/// windows. `win widgets` opens the demo of the widgets.
+pub fn cmd_win(args: &[&str], out: &mut dyn fmt::Write) -> Result<()> {
+    let parse_id = |s: &str| s.parse().map(WindowId).or(Err("win: invalid window id"));
+    let parse_pos = |s: &str| s.parse::<i64>().or(Err("win: invalid position"));
+    match args {
+        [] => with(|wm| {
+            for w in wm.windows().iter().rev() {
+                let r = w.rect();
+                let mark = if wm.focused() == Some(w.id()) {
+                    '*'
+                } else {
+                    ' '
+                };
+                let _ = writeln!(
+                    out,
+                    "{mark}{:>3} {}x{}+{}+{} {}",
+                    w.id(),
+                    r.w,
+                    r.h,
+                    r.x,
+                    r.y,
+                    w.title()
+                );
+            }
+        }),
+        ["open", title @ ..] => {
+            let title = if title.is_empty() {
+                "window".to_string()
+            } else {
+                title.join(" ")
+            };
+            let id = open_sample(title)?;
+            let _ = writeln!(out, "opened window {id}");
+            Ok(())
+        }
+        ["widgets"] => {
+            let n = with(|wm| wm.windows().len() as i64)?;
+            let id = widget::open("widgets", 32 + n * 24, 16 + n * 24, widget::demo())?;
+            let _ = writeln!(out, "opened window {id}");
+            Ok(())
+        }
+        ["close", id] => with(|wm| wm.request_close(parse_id(id)?))?,
+        ["raise", id] => with(|wm| {
+            let id = parse_id(id)?;
+            wm.raise(id)?;
+            wm.focus(Some(id))
+        })?,
+        ["move", id, x, y] => {
+            with(|wm| wm.move_window(parse_id(id)?, parse_pos(x)?, parse_pos(y)?))?
+        }
+        ["resize", id, w, h] => {
+            with(|wm| wm.resize_window(parse_id(id)?, parse_pos(w)?, parse_pos(h)?))?
+        }
+        _ => Err("usage: win [open [TITLE] | widgets | close ID | raise ID | move ID X Y | resize ID W H]"),
+    }
+}
@@ -0,0 +1,200 @@
+//! W^X for the kernel: no page of it is both writable and executable.
+//!
+//! Once paging::init() has given the kernel its own page tables, the
+//! sections of the loaded PE image are mapped by their characteristics:
+//! .text read+execute, .rdata read-only and .data (with .bss) read+write,
+//! all but .text with the NX bit. The LoaderData memory of the firmware,
+//! which is the heap, the stacks and the page tables, is made
+//! non-executable too. CR0.WP is set so that the kernel itself can not
+//! write to read-only pages either, and self_test() checks the result at
+//! boot by walking the page tables.
+
+use crate::arch::read_cr0;
+use crate::arch::write_cr0;
+use crate::info;
+use crate::paging;
+use crate::paging::Protection;
+use crate::paging::PAGE_SIZE;
+use crate::result::Result;
+use crate::uefi;
+use crate::uefi::EfiMemoryType;
+use crate::uefi::EfiSystemTable;
+use crate::uefi::MemoryMapHolder;
+use crate::warn;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU64;
+
+const CR0_WP: u64 = 1 << 16;
+
+const DOS_MAGIC: &[u8; 2] = b"MZ";
+const PE_MAGIC: &[u8; 4] = b"PE\0\0";
+const PE_OFFSET: usize = 0x3c;
+const COFF_HEADER_SIZE: usize = 20;
+const SECTION_HEADER_SIZE: usize = 40;
+const SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+const SCN_MEM_WRITE: u32 = 0x8000_0000;
+
+/// A section of a PE image as loaded in memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub name: String,
+    /// The offset from the base of the image.
+    pub rva: u64,
+    pub size: u64,
+    pub protection: Protection,
+}
+
+/// The len bytes at offset, if they are in the image.
+fn range(image: &[u8], offset: usize, len: usize) -> Result<&[u8]> {
+    offset
+        .checked_add(len)
+        .and_then(|end| image.get(offset..end))
+        .ok_or("PE image is truncated")
+}
+
+fn u16_at(b: &[u8], i: usize) -> u16 {
+    u16::from_le_bytes([b[i], b[i + 1]])
+}
+fn u32_at(b: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([b[i], b[i + 1], b[i + 2], b[i + 3]])
+}
+
+/// Reads the section headers of a PE image, which is laid out as loaded
+/// (i.e. the section at rva is at image[rva..]).
+pub fn sections(image: &[u8]) -> Result<Vec<Section>> {
+    if range(image, 0, PE_OFFSET + 4).ok().map(|b| &b[0..2]) != Some(DOS_MAGIC) {
+        return Err("Not a PE image");
+    }
+    let pe = u32_at(image, PE_OFFSET) as usize;
+    if range(image, pe, PE_MAGIC.len())? != PE_MAGIC {
+        return Err("Not a PE image");
+    }
+    let coff = range(image, pe + PE_MAGIC.len(), COFF_HEADER_SIZE)?;
+    let count = u16_at(coff, 2) as usize;
+    let optional_header_size = u16_at(coff, 16) as usize;
+    let headers = range(
+        image,
+        pe + PE_MAGIC.len() + COFF_HEADER_SIZE + optional_header_size,
+        count * SECTION_HEADER_SIZE,
+    )?;
+    Ok(headers
+        .chunks_exact(SECTION_HEADER_SIZE)
+        .map(|h| {
+            let name = h[0..8].split(|c| *c == 0).next().unwrap_or_default();
+            // VirtualSizeが0ならSizeOfRawDataを使う
+            let size = match u32_at(h, 8) {
+                0 => u32_at(h, 16),
+                size => size,
+            };
+            let characteristics = u32_at(h, 36);
+            Section {
+                name: String::from_utf8_lossy(name).into_owned(),
+                rva: u32_at(h, 12) as u64,
+                size: size as u64,
+                protection: Protection {
+                    writable: characteristics & SCN_MEM_WRITE != 0,
+                    executable: characteristics & SCN_MEM_EXECUTE != 0,
+                },
+            }
+        })
+        .collect())
+}
+
+/// The running image, as [base, base + size).
+fn image(efi_system_table: &EfiSystemTable) -> Result<(u64, u64)> {
+    let handle = uefi::image_handle().ok_or("EFI context is not initialized")?;
+    let image = uefi::loaded_image(efi_system_table, handle)?;
+    Ok((image.image_base as u64, image.image_size))
+}
+
+/// Maps the kernel W^X. Call after paging::init(), while the boot services
+/// are available.
+pub fn init(efi_system_table: &EfiSystemTable) -> Result<()> {
+    paging::enable_no_execute()?;
+    let (base, size) = image(efi_system_table)?;
+    // SAFETY: the firmware loaded the image there, and maps it 1:1
+    let bytes = unsafe { core::slice::from_raw_parts(base as *const u8, size as usize) };
+    let sections = sections(bytes)?;
+    // 実行中のコードを一瞬でも実行不可にしないよう、セクションごとに設定する
+    let headers_end = sections.iter().map(|s| s.rva).min().unwrap_or(size);
+    paging::protect_kernel_range(base, base + headers_end, Protection::READ_ONLY)?;
+    for s in &sections {
+        if s.protection.writable && s.protection.executable {
+            warn!("W^X: {} is writable and executable, left as is", s.name);
+            continue;
+        }
+        let start = base + s.rva;
+        paging::protect_kernel_range(start, start + s.size, s.protection)?;
+    }
+    let mut map = Box::new(MemoryMapHolder::new());
+    efi_system_table.boot_services.fetch_memory_map(&mut map)?;
+    let mut data_pages = 0;
+    for e in map.iter() {
+        let start = e.physical_start;
+        let end = start + e.number_of_pages * PAGE_SIZE;
+        // イメージ自身はLoaderCodeのはずだが、念のため避ける
+        if e.memory_type != EfiMemoryType::LOADER_DATA || (start < base + size && base < end) {
+            continue;
+        }
+        paging::protect_kernel_range(start, end, Protection::READ_WRITE)?;
+        data_pages += e.number_of_pages;
+    }
+    // SAFETY: WP only makes the kernel fault on writes to read-only pages,
+    // which it has none of now
+    unsafe { write_cr0(read_cr0() | CR0_WP) };
+    info!(
+        "W^X: {} sections of the kernel, {} KiB of data",
+        sections.len(),
+        data_pages * PAGE_SIZE / 1024
+    );
+    Ok(())
+}
+
+static SELF_TEST_RODATA: [u8; 8] = *b"wasabi\0\0";
+static SELF_TEST_DATA: AtomicU64 = AtomicU64::new(0);
+
+/// Checks that init() did its job: the code, constants, statics and the
+/// heap are mapped as they should be, and no page of the image is both
+/// writable and executable.
+pub fn self_test(efi_system_table: &EfiSystemTable) -> Result<()> {
+    if read_cr0() & CR0_WP == 0 {
+        return Err("CR0.WP is not set");
+    }
+    let heap = Box::new(0u64);
+    let checks = [
+        (
+            self_test as usize as u64,
+            Protection::READ_EXECUTE,
+            "The code is not read+execute",
+        ),
+        (
+            SELF_TEST_RODATA.as_ptr() as u64,
+            Protection::READ_ONLY,
+            "The constants are not read-only",
+        ),
+        (
+            &SELF_TEST_DATA as *const AtomicU64 as u64,
+            Protection::READ_WRITE,
+            "The statics are not read+write",
+        ),
+        (
+            &*heap as *const u64 as u64,
+            Protection::READ_WRITE,
+            "The heap is not read+write",
+        ),
+    ];
+    for (va, expected, error) in checks {
+        if paging::kernel_page_protection(va) != Some(expected) {
+            return Err(error);
+        }
+    }
+    let (base, size) = image(efi_system_table)?;
+    for page in (base..base + size).step_by(PAGE_SIZE as usize) {
+        if paging::kernel_page_protection(page).is_some_and(|p| p.writable && p.executable) {
+            return Err("A page of the kernel is writable and executable");
+        }
+    }
+    Ok(())
+}